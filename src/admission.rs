@@ -0,0 +1,103 @@
+//! Concurrency limit for query handling
+//!
+//! Each query being answered may start mDNS browses and hold a task, so a burst
+//! of queries must not run unbounded in parallel. Up to
+//! `server.max_concurrent_queries` are answered at once; up to
+//! `server.query_queue_length` more wait, each for at most
+//! `server.query_queue_timeout_ms`, for one of them to finish. A query that
+//! finds the queue full or waits too long is answered with
+//! `server.overload_response` instead.
+
+use crate::config::ServerConfig;
+use crate::metrics;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Admits queries up to the configured concurrency, queueing a bounded number
+#[derive(Debug)]
+pub struct Admission {
+    max_concurrent: usize,
+    running: Arc<Semaphore>,
+    queue: Semaphore,
+    queue_timeout: Duration,
+}
+
+impl Admission {
+    /// Limits of `[server]`; None when `max_concurrent_queries` is 0 (unlimited)
+    pub fn from_config(server: &ServerConfig) -> Option<Self> {
+        (server.max_concurrent_queries > 0).then(|| {
+            Self::new(
+                server.max_concurrent_queries,
+                server.query_queue_length,
+                Duration::from_millis(server.query_queue_timeout_ms),
+            )
+        })
+    }
+
+    /// Answer `max_concurrent` queries at once, with `queue_length` more waiting up to `queue_timeout`
+    pub fn new(max_concurrent: usize, queue_length: usize, queue_timeout: Duration) -> Self {
+        Self {
+            max_concurrent,
+            running: Arc::new(Semaphore::new(max_concurrent)),
+            queue: Semaphore::new(queue_length),
+            queue_timeout,
+        }
+    }
+
+    /// Permission to answer one query, held until it is answered; None when
+    /// the proxy is overloaded and the query should be turned away
+    pub async fn admit(&self) -> Option<OwnedSemaphorePermit> {
+        if let Ok(permit) = self.running.clone().try_acquire_owned() {
+            return Some(permit);
+        }
+        // Hold a place in the queue while waiting
+        let _queued = self.queue.try_acquire().ok()?;
+        metrics::inc(&metrics::metrics().queries_queued);
+        tokio::time::timeout(self.queue_timeout, self.running.clone().acquire_owned())
+            .await
+            .ok()?
+            .ok()
+    }
+
+    /// Queries being answered now
+    pub fn running(&self) -> usize {
+        self.max_concurrent - self.running.available_permits()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_queue_then_turn_away() {
+        let admission = Arc::new(Admission::new(1, 1, Duration::from_millis(200)));
+        let first = admission.admit().await.unwrap();
+        assert_eq!(admission.running(), 1);
+
+        // The second waits in the queue for the first to finish
+        let queued = tokio::spawn({
+            let admission = admission.clone();
+            async move { admission.admit().await.is_some() }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        // The queue is full, so the third is turned away at once
+        assert!(admission.admit().await.is_none());
+        drop(first);
+        assert!(queued.await.unwrap());
+
+        // A queued query gives up after the queue timeout
+        let _busy = admission.admit().await.unwrap();
+        let started = std::time::Instant::now();
+        assert!(admission.admit().await.is_none());
+        assert!(started.elapsed() >= Duration::from_millis(200));
+
+        let config = ServerConfig {
+            max_concurrent_queries: 0,
+            ..ServerConfig::default()
+        };
+        assert!(Admission::from_config(&config).is_none());
+        assert!(Admission::from_config(&ServerConfig::default()).is_some());
+    }
+}
@@ -0,0 +1,329 @@
+//! Query audit log
+//!
+//! Records every answered query for abuse forensics without storing client
+//! addresses: each client's identity (its TSIG key name, DNS cookie or IP, see
+//! [`crate::client`]) is replaced by a salted SHA-256 hash, so the same client
+//! can be followed through the log but not identified from it. Lines go
+//! to daily files (`audit-YYYY-MM-DD.log`) that are deleted after the configured
+//! retention period, or to the `audit` tracing target when no directory is set.
+
+use crate::client::{ClientIdentity, ClientKey};
+use crate::config::AuditConfig;
+use crate::metrics;
+use crate::random::random_u64;
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::RecordType;
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+/// Lines buffered between request handlers and the writer before new ones are dropped
+const QUEUE_DEPTH: usize = 1024;
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// One answered query
+#[derive(Debug, Clone)]
+pub struct AuditEvent {
+    pub client: ClientIdentity,
+    pub protocol: String,
+    pub name: String,
+    pub query_type: RecordType,
+    pub response_code: ResponseCode,
+    pub answers: usize,
+}
+
+/// Handle used by request handlers to append to the audit log
+pub struct AuditLog {
+    salt: Vec<u8>,
+    tx: mpsc::Sender<String>,
+}
+
+impl AuditLog {
+    /// Start the background writer. Must be called from within a tokio runtime.
+    pub fn start(config: &AuditConfig) -> io::Result<Arc<Self>> {
+        if let Some(dir) = &config.directory {
+            std::fs::create_dir_all(dir)?;
+        }
+        let salt = match &config.salt {
+            Some(salt) => salt.as_bytes().to_vec(),
+            None => random_salt(),
+        };
+
+        let (tx, rx) = mpsc::channel(QUEUE_DEPTH);
+        let writer = AuditWriter {
+            directory: config.directory.clone(),
+            retention_days: config.retention_days,
+            current_day: None,
+            file: None,
+        };
+        tokio::spawn(writer.run(rx));
+
+        Ok(Arc::new(Self { salt, tx }))
+    }
+
+    /// Salted hash identifying a client without revealing its address
+    pub fn client_hash(&self, client: &ClientKey) -> String {
+        client_hash(&self.salt, client)
+    }
+
+    /// Queue an event; dropped (and counted) if the writer cannot keep up
+    pub fn record(&self, event: &AuditEvent) {
+        let line = format_line(unix_now(), &self.client_hash(&event.client.key()), event);
+        if self.tx.try_send(line).is_err() {
+            metrics::inc(&metrics::metrics().audit_dropped);
+        }
+    }
+}
+
+struct AuditWriter {
+    directory: Option<PathBuf>,
+    retention_days: u64,
+    current_day: Option<u64>,
+    file: Option<File>,
+}
+
+impl AuditWriter {
+    async fn run(mut self, mut rx: mpsc::Receiver<String>) {
+        let mut lines = Vec::new();
+        while rx.recv_many(&mut lines, QUEUE_DEPTH).await > 0 {
+            let batch = std::mem::take(&mut lines);
+            // Opening, writing and deleting files blocks, so it happens off the async workers
+            let written = tokio::task::spawn_blocking(move || {
+                for line in &batch {
+                    if let Err(e) = self.write(line) {
+                        warn!("Failed to write audit log: {}", e);
+                    }
+                }
+                self
+            })
+            .await;
+            match written {
+                Ok(writer) => self = writer,
+                Err(e) => {
+                    warn!("Audit log writer stopped: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        let Some(dir) = &self.directory else {
+            info!(target: "audit", "{}", line);
+            return Ok(());
+        };
+
+        let today = unix_now() / SECONDS_PER_DAY;
+        if self.current_day != Some(today) || self.file.is_none() {
+            let path = dir.join(file_name(today));
+            self.file = Some(OpenOptions::new().create(true).append(true).open(path)?);
+            self.current_day = Some(today);
+            remove_expired(dir, today, self.retention_days)?;
+        }
+
+        if let Some(file) = &mut self.file {
+            writeln!(file, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+fn client_hash(salt: &[u8], client: &ClientKey) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    match client {
+        ClientKey::Address(IpAddr::V4(v4)) => hasher.update(v4.octets()),
+        ClientKey::Address(IpAddr::V6(v6)) => hasher.update(v6.octets()),
+        ClientKey::Tsig(name) => {
+            hasher.update(b"tsig:");
+            hasher.update(name.to_ascii().as_bytes());
+        }
+        ClientKey::Cookie(cookie) => {
+            hasher.update(b"cookie:");
+            hasher.update(cookie);
+        }
+    }
+    // 64 bits is plenty to tell clients apart in a log
+    hasher.finalize()[..8].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Per-process salt when none is configured: hashes are then only comparable within one run
+fn random_salt() -> Vec<u8> {
+    let mut salt = Vec::with_capacity(16);
+    for _ in 0..2 {
+        salt.extend_from_slice(&random_u64().to_le_bytes());
+    }
+    salt
+}
+
+fn format_line(timestamp: u64, client: &str, event: &AuditEvent) -> String {
+    format!(
+        "{} client={} id={} proto={} name={} type={:?} rcode={:?} answers={}",
+        timestamp,
+        client,
+        event.client.key().kind(),
+        event.protocol, event.name, event.query_type, event.response_code, event.answers
+    )
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+fn file_name(day: u64) -> String {
+    let (y, m, d) = civil_from_days(day as i64);
+    format!("audit-{:04}-{:02}-{:02}.log", y, m, d)
+}
+
+/// Day number (days since 1970-01-01) encoded in an audit file name
+fn day_from_file_name(name: &str) -> Option<u64> {
+    let date = name.strip_prefix("audit-")?.strip_suffix(".log")?;
+    let mut parts = date.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let (y, m, d) = (parts.next()??, parts.next()??, parts.next()??);
+    u64::try_from(days_from_civil(y, m, d)).ok()
+}
+
+/// Delete audit files older than `retention_days` (0 keeps everything)
+fn remove_expired(dir: &Path, today: u64, retention_days: u64) -> io::Result<()> {
+    if retention_days == 0 {
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(dir)?.flatten() {
+        let name = entry.file_name();
+        let Some(day) = name.to_str().and_then(day_from_file_name) else {
+            continue;
+        };
+        if day + retention_days <= today {
+            std::fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+// Gregorian calendar conversions (Howard Hinnant's days_from_civil / civil_from_days)
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(z: i64) -> (i64, i64, i64) {
+    let z = z + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + if m <= 2 { 1 } else { 0 }, m, d)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+    fn client(ip: IpAddr) -> ClientIdentity {
+        ClientIdentity::from_addr(SocketAddr::new(ip, 5353))
+    }
+
+    #[test]
+    fn test_client_hash_is_salted_and_stable() {
+        let ip = ClientKey::Address(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20)));
+        let a = client_hash(b"salt-a", &ip);
+        assert_eq!(a, client_hash(b"salt-a", &ip));
+        assert_ne!(a, client_hash(b"salt-b", &ip));
+        assert_ne!(a, client_hash(b"salt-a", &ClientKey::Address(IpAddr::V6(Ipv6Addr::LOCALHOST))));
+        assert_ne!(a, client_hash(b"salt-a", &ClientKey::Cookie([0; 8])));
+        assert_eq!(a.len(), 16);
+        assert!(!a.contains("192"));
+    }
+
+    #[test]
+    fn test_format_line_has_no_address() {
+        let event = AuditEvent {
+            client: client(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 7))),
+            protocol: "udp".to_string(),
+            name: "printer.mdns.home.arpa.".to_string(),
+            query_type: RecordType::A,
+            response_code: ResponseCode::NoError,
+            answers: 1,
+        };
+        let line = format_line(1_700_000_000, &client_hash(b"s", &event.client.key()), &event);
+        assert!(line.starts_with("1700000000 client="));
+        assert!(line.contains(" id=addr "));
+        assert!(line.contains("name=printer.mdns.home.arpa. type=A rcode=NoError answers=1"));
+        assert!(!line.contains("10.0.0.7"));
+    }
+
+    #[test]
+    fn test_file_name_dates() {
+        assert_eq!(file_name(0), "audit-1970-01-01.log");
+        assert_eq!(file_name(19_723), "audit-2024-01-01.log");
+        assert_eq!(day_from_file_name("audit-2024-02-29.log"), Some(19_782));
+        assert_eq!(day_from_file_name("audit-garbage.log"), None);
+        assert_eq!(day_from_file_name("other.log"), None);
+    }
+
+    #[test]
+    fn test_remove_expired_respects_retention() {
+        let dir = tempfile::tempdir().unwrap();
+        let today = 19_782;
+        for day in [today, today - 6, today - 7, today - 30] {
+            std::fs::write(dir.path().join(file_name(day)), "x").unwrap();
+        }
+        std::fs::write(dir.path().join("unrelated.txt"), "x").unwrap();
+
+        remove_expired(dir.path(), today, 7).unwrap();
+
+        let mut left: Vec<String> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        assert_eq!(left, vec![file_name(today - 6), file_name(today), "unrelated.txt".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_audit_log_writes_daily_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = AuditConfig {
+            enabled: true,
+            directory: Some(dir.path().to_path_buf()),
+            salt: Some("test".to_string()),
+            retention_days: 7,
+        };
+        let log = AuditLog::start(&config).unwrap();
+        log.record(&AuditEvent {
+            client: client(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            protocol: "tcp".to_string(),
+            name: "host.mdns.home.arpa.".to_string(),
+            query_type: RecordType::AAAA,
+            response_code: ResponseCode::NoError,
+            answers: 0,
+        });
+
+        let path = dir.path().join(file_name(unix_now() / SECONDS_PER_DAY));
+        let mut contents = String::new();
+        for _ in 0..50 {
+            contents = std::fs::read_to_string(&path).unwrap_or_default();
+            if !contents.is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert!(contents.contains(&format!("client={}", log.client_hash(&ClientKey::Address(IpAddr::V4(Ipv4Addr::LOCALHOST))))));
+        assert!(contents.contains("type=AAAA"));
+    }
+}
@@ -0,0 +1,92 @@
+//! Ordinary authoritative zones served next to the discovery zones
+//!
+//! Small sites can run one daemon for both a static internal zone and the
+//! discovery zone. Zone files are loaded into a hickory [`Catalog`] at startup;
+//! a query goes to the catalog when the most specific zone containing its name
+//! is a static one, so a static zone may hold a discovery zone below it and
+//! the other way round.
+
+use crate::config::AuthoritativeZone;
+use hickory_proto::rr::{LowerName, Name};
+use hickory_server::authority::{AuthorityObject, Catalog, ZoneType};
+use hickory_server::store::file::{FileAuthority, FileConfig};
+use std::sync::Arc;
+use tracing::info;
+
+type LoadResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Static zones and the catalog answering for them
+pub struct AuthoritativeZones {
+    catalog: Catalog,
+    origins: Vec<Name>,
+}
+
+impl AuthoritativeZones {
+    /// Load every configured zone file; any unreadable or invalid file is an error
+    pub fn load(zones: &[AuthoritativeZone]) -> LoadResult<Self> {
+        let mut catalog = Catalog::new();
+        let mut origins = Vec::new();
+        for zone in zones {
+            let mut origin = Name::from_utf8(&zone.origin)?.to_lowercase();
+            origin.set_fqdn(true);
+            if origins.contains(&origin) {
+                return Err(format!("zone {} configured twice", origin).into());
+            }
+            let config = FileConfig {
+                zone_file_path: zone.file.clone(),
+            };
+            let authority = FileAuthority::try_from_config(origin.clone(), ZoneType::Primary, false, None, &config)
+                .map_err(|e| format!("zone {}: {}", origin, e))?;
+            info!("Serving authoritative zone {} from {}", origin, zone.file.display());
+            let authority: Arc<dyn AuthorityObject> = Arc::new(authority);
+            catalog.upsert(LowerName::new(&origin), vec![authority]);
+            origins.push(origin);
+        }
+        Ok(Self { catalog, origins })
+    }
+
+    pub fn catalog(&self) -> &Catalog {
+        &self.catalog
+    }
+
+    pub fn origins(&self) -> &[Name] {
+        &self.origins
+    }
+
+    /// Whether `name` belongs to a static zone rather than to `discovery_zone`,
+    /// the discovery zone containing it (if any): the more specific apex wins
+    pub fn serves(&self, name: &Name, discovery_zone: Option<&Name>) -> bool {
+        let Some(origin) = self
+            .origins
+            .iter()
+            .filter(|origin| origin.zone_of(name))
+            .max_by_key(|origin| origin.num_labels())
+        else {
+            return false;
+        };
+        discovery_zone.is_none_or(|apex| origin.num_labels() > apex.num_labels())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(s: &str) -> Name {
+        Name::from_utf8(s).unwrap()
+    }
+
+    #[test]
+    fn test_most_specific_zone_wins() {
+        let zones = AuthoritativeZones {
+            catalog: Catalog::new(),
+            origins: vec![name("home.arpa."), name("static.mdns.home.arpa.")],
+        };
+        let discovery = name("mdns.home.arpa.");
+
+        assert!(zones.serves(&name("router.home.arpa."), None));
+        assert!(!zones.serves(&name("printer.mdns.home.arpa."), Some(&discovery)));
+        assert!(zones.serves(&name("nas.static.mdns.home.arpa."), Some(&discovery)));
+        assert!(!zones.serves(&name("example.com."), None));
+    }
+}
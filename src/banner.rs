@@ -0,0 +1,288 @@
+//! Startup summary
+//!
+//! At startup the proxy logs one line per subsystem and one per RFC 8766
+//! requirement it implements, all derived from the configuration, so a log
+//! excerpt attached to a support ticket says how the proxy was set up without
+//! the configuration file. Each line carries its subsystem or RFC section as a
+//! structured field.
+
+use crate::config::{Config, ResolutionStep};
+use crate::mdns_resolver::capabilities;
+use hickory_proto::rr::{Name, RecordType};
+use std::str::FromStr;
+use tracing::info;
+
+/// One configured subsystem and how it is set up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subsystem {
+    pub name: &'static str,
+    pub enabled: bool,
+    pub detail: String,
+}
+
+/// Requirement level of an RFC 8766 item
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Must,
+    Should,
+}
+
+impl Level {
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Must => "MUST",
+            Level::Should => "SHOULD",
+        }
+    }
+}
+
+/// One RFC 8766 item and whether this configuration satisfies it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Requirement {
+    /// Section of RFC 8766, or the RFC the item comes from
+    pub reference: &'static str,
+    pub level: Level,
+    pub summary: &'static str,
+    pub active: bool,
+    pub detail: String,
+}
+
+/// Log the subsystem and compliance summary for `config`
+pub fn log(config: &Config) {
+    for subsystem in subsystems(config) {
+        info!(
+            subsystem = subsystem.name,
+            enabled = subsystem.enabled,
+            "{}: {}",
+            subsystem.name,
+            subsystem.detail
+        );
+    }
+    for requirement in requirements(config) {
+        info!(
+            rfc8766 = requirement.reference,
+            level = requirement.level.name(),
+            active = requirement.active,
+            "{} {} {}: {}{}",
+            requirement.reference,
+            requirement.level.name(),
+            requirement.summary,
+            if requirement.active { "active" } else { "inactive" },
+            if requirement.detail.is_empty() { String::new() } else { format!(" ({})", requirement.detail) }
+        );
+    }
+}
+
+/// Subsystems in the order they are logged
+pub fn subsystems(config: &Config) -> Vec<Subsystem> {
+    let subsystem = |name, enabled, detail: String| Subsystem { name, enabled, detail };
+    let on_off = |enabled: bool| if enabled { "on" } else { "off" };
+
+    let mut zones = vec![config.discovery_domain().to_string()];
+    zones.extend(config.zones.keys().filter(|zone| *zone != config.discovery_domain()).cloned());
+    let authoritative: Vec<&str> = config.authoritative.zones.iter().map(|zone| zone.origin.as_str()).collect();
+    let zones_detail = if authoritative.is_empty() {
+        format!("discovery {}", zones.join(", "))
+    } else {
+        format!("discovery {}; static {}", zones.join(", "), authoritative.join(", "))
+    };
+
+    let server = &config.server;
+    let addrs = |port| crate::listener::describe_addrs(&server.bind_address, port);
+    let mut listeners = vec![format!("udp {} ({} socket(s))", addrs(server.port), server.udp_sockets)];
+    listeners.push(format!("tcp {}", addrs(server.port)));
+    if let Some(tls) = &server.tls {
+        listeners.push(format!("tls {}", addrs(tls.port)));
+    }
+    if let Some(path) = &server.unix_socket_path {
+        listeners.push(format!("unix {}", path.display()));
+    }
+    if let Some(path) = &config.admin.control_socket {
+        listeners.push(format!(
+            "control {} ({} api key(s))",
+            path.display(),
+            config.admin.api_keys.len()
+        ));
+    }
+
+    let chain = |record_type| {
+        let steps: Vec<&str> = config.resolution_chain(record_type).into_iter().map(ResolutionStep::name).collect();
+        steps.join(">")
+    };
+    let backends = format!(
+        "address {}, service {}, other {}; cache {} ({}s), {} known instance(s), read-only {}, daemon {}; {}",
+        chain(RecordType::A),
+        chain(RecordType::PTR),
+        chain(RecordType::NULL),
+        on_off(config.cache.enabled),
+        config.cache.ttl_seconds,
+        config.known_services.services.len(),
+        on_off(config.mdns.read_only),
+        if config.mdns.lazy_start { "on demand" } else { "at startup" },
+        capabilities::MDNS_SD
+    );
+
+    let peers = &config.peers;
+    let forwarding = peers.forward_on_failure || config.resolution.mentions(ResolutionStep::Peers);
+    let forwarding_detail = format!(
+        "forward to peers {}, {} configured peer(s), discovery {}",
+        on_off(forwarding),
+        peers.addresses.len(),
+        on_off(peers.discover)
+    );
+
+    let cluster_detail = format!("{} member(s), listening on {}", config.cluster.members.len(), config.cluster.listen);
+    let push = &config.push;
+    let push_detail = if push.enabled {
+        format!(
+            "DSO on tcp{}, {} subscription(s) per session, refresh every {}s",
+            if server.unix_socket_path.is_some() { " and unix" } else { "" },
+            push.max_subscriptions,
+            push.refresh_secs
+        )
+    } else {
+        "off".to_string()
+    };
+    let tsig = &config.tsig;
+    let tsig_enabled = !tsig.keys.is_empty() || !tsig.require_from.is_empty();
+    let tsig_detail = if tsig_enabled {
+        let required = if tsig.require_from.is_empty() {
+            "none".to_string()
+        } else {
+            tsig.require_from.join(", ")
+        };
+        format!("{} key(s), required from {}, fudge {}s", tsig.keys.len(), required, tsig.fudge_secs)
+    } else {
+        "off".to_string()
+    };
+    let axfr_detail = if config.axfr.allow_from.is_empty() {
+        "tcp, any client".to_string()
+    } else {
+        format!("tcp, from {}", config.axfr.allow_from.join(", "))
+    };
+    let notify_detail = if config.notify.targets.is_empty() {
+        "off".to_string()
+    } else {
+        let targets: Vec<String> = config.notify.targets.iter().map(|target| target.to_string()).collect();
+        format!("{}, checked every {}s", targets.join(", "), config.notify.check_interval_secs)
+    };
+    let policy_detail = match &config.policy.rpz_file {
+        Some(path) => path.display().to_string(),
+        None => "no rpz_file".to_string(),
+    };
+
+    vec![
+        subsystem("zones", true, zones_detail),
+        subsystem("listeners", true, listeners.join(", ")),
+        subsystem("backends", true, backends),
+        subsystem("push", push.enabled, push_detail),
+        subsystem("signing", false, "responses are not DNSSEC-signed".to_string()),
+        subsystem("tsig", tsig_enabled, tsig_detail),
+        subsystem("axfr", config.axfr.enabled, axfr_detail),
+        subsystem("notify", !config.notify.targets.is_empty(), notify_detail),
+        subsystem("forwarding", forwarding, forwarding_detail),
+        subsystem("cluster", config.cluster.enabled, cluster_detail),
+        subsystem("policy", config.policy.rpz_file.is_some(), policy_detail),
+        subsystem("audit", config.audit.enabled, on_off(config.audit.enabled).to_string()),
+        subsystem("liveness", config.liveness.enabled, format!("every {}s", config.liveness.interval_secs)),
+        subsystem("wake", config.wake.enabled, format!("{} device(s)", config.wake.devices.len())),
+    ]
+}
+
+/// RFC 8766 items in section order
+pub fn requirements(config: &Config) -> Vec<Requirement> {
+    let requirement = |reference, level, summary, active, detail: String| Requirement {
+        reference,
+        level,
+        summary,
+        active,
+        detail,
+    };
+    let apex = Name::from_str(config.discovery_domain()).unwrap_or_else(|_| Name::root());
+    let server = &config.server;
+    let off_link = format!("{:?}", server.off_link_local_queries).to_lowercase();
+    let on_link = if config.network.watch {
+        "on-link networks followed"
+    } else {
+        "on-link networks not watched ([network] watch off)"
+    };
+    let ns_detail = format!(
+        "{} peer proxies, own address records {}",
+        server.peer_proxies.len(),
+        if server.own_address_records { "on" } else { "off" }
+    );
+
+    vec![
+        requirement("5.1", Level::Should, "off-link .local queries answered without mDNS", true, off_link),
+        requirement("5.2.1", Level::Must, "domain enumeration PTR records", true, String::new()),
+        requirement("5.5.1", Level::Should, "TTLs capped at 10 seconds", true, String::new()),
+        requirement("5.5.2", Level::Should, "unusable address records suppressed", true, on_link.to_string()),
+        requirement(
+            "6.1",
+            Level::Must,
+            "SOA record at the zone apex",
+            true,
+            format!("MINIMUM {}s", config.soa_minimum(&apex)),
+        ),
+        requirement("6.2", Level::Must, "NS records at the zone apex", true, ns_detail),
+        requirement("6.3", Level::Must, "no delegation below the zone apex", true, String::new()),
+        requirement(
+            "6.4",
+            Level::Should,
+            "negative answers for DNS Update, LLQ and Push SRV queries",
+            true,
+            String::new(),
+        ),
+        requirement(
+            "RFC 8765",
+            Level::Should,
+            "DNS Push Notifications for long-lived queries",
+            config.push.enabled,
+            if config.push.enabled { "over TCP, not TLS" } else { "[push] enabled off" }.to_string(),
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subsystems_follow_config() {
+        let config = Config::default();
+        let subsystems = subsystems(&config);
+        let find = |name| subsystems.iter().find(|s| s.name == name).unwrap().clone();
+        assert_eq!(find("zones").detail, "discovery mdns.home.arpa.");
+        assert_eq!(find("listeners").detail, "udp 127.0.0.1:5335 [::1]:5335 (1 socket(s)), tcp 127.0.0.1:5335 [::1]:5335");
+        assert!(find("backends").detail.starts_with("address cache>known>mdns,"));
+        assert!(!find("forwarding").enabled);
+        assert!(!find("push").enabled && !find("signing").enabled);
+        assert_eq!(find("push").detail, "off");
+        assert_eq!(find("tsig").detail, "off");
+
+        let mut config = Config::default();
+        config.peers.forward_on_failure = true;
+        let subsystems = super::subsystems(&config);
+        let forwarding = subsystems.iter().find(|s| s.name == "forwarding").unwrap();
+        assert!(forwarding.enabled);
+        assert!(subsystems.iter().find(|s| s.name == "backends").unwrap().detail.contains("cache>known>mdns>peers"));
+    }
+
+    #[test]
+    fn test_requirements_follow_config() {
+        let mut config = Config::default();
+        let requirements = requirements(&config);
+        assert!(requirements.iter().filter(|r| r.level == Level::Must).all(|r| r.active));
+        let off_link = requirements.iter().find(|r| r.reference == "5.1").unwrap();
+        assert_eq!(off_link.detail, "nxdomain");
+
+        config.network.watch = false;
+        config.push.enabled = true;
+        let requirements = super::requirements(&config);
+        let suppression = requirements.iter().find(|r| r.reference == "5.5.2").unwrap();
+        assert!(suppression.detail.contains("not watched"));
+        assert!(requirements.iter().find(|r| r.reference == "RFC 8765").unwrap().active);
+        let push = subsystems(&config).into_iter().find(|s| s.name == "push").unwrap();
+        assert!(push.enabled && push.detail.starts_with("DSO on tcp,"));
+    }
+}
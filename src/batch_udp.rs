@@ -0,0 +1,382 @@
+//! Batched UDP listener (Linux, `batch-udp` feature)
+//!
+//! `ServerFuture` reads and writes one datagram per syscall and spawns a task for
+//! every query, which dominates the cost of a cache hit at high query rates. This
+//! listener drains up to [`BATCH`] datagrams with one `recvmmsg(2)`, polls the
+//! handler once for each, and sends every answer that was ready straight away
+//! (cache hits, administrative records, policy answers) with one `sendmmsg(2)`.
+//! Queries that have to wait for mDNS carry on in their own task and are answered
+//! with a plain `send_to`. TCP is served by [`crate::tcp`].
+
+use crate::metrics;
+use futures_util::FutureExt;
+use hickory_proto::op::{Header, ResponseCode};
+use hickory_proto::rr::Record;
+use hickory_proto::serialize::binary::{BinDecodable, BinEncodable, BinEncoder};
+use hickory_proto::xfer::Protocol;
+use hickory_server::authority::{MessageRequest, MessageResponse};
+use hickory_server::server::{Request, RequestHandler, ResponseHandler, ResponseInfo};
+use std::io;
+use std::mem;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::os::fd::{AsRawFd, RawFd};
+use std::sync::{Arc, Mutex};
+use tokio::io::Interest;
+use tokio::net::UdpSocket;
+use tracing::{debug, error};
+
+/// Datagrams read per `recvmmsg` call
+pub const BATCH: usize = 32;
+
+/// Largest query accepted, as for `ServerFuture`
+const MAX_DATAGRAM: usize = hickory_proto::udp::MAX_RECEIVE_BUFFER_SIZE;
+
+/// Serve DNS over `socket` until it fails
+pub async fn serve<H: RequestHandler>(socket: UdpSocket, handler: Arc<H>) -> io::Result<()> {
+    let socket = Arc::new(socket);
+    let mut batch = RecvBatch::new();
+    loop {
+        let received = match socket
+            .async_io(Interest::READABLE, || batch.recv(socket.as_raw_fd()))
+            .await
+        {
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        let mut ready = Vec::with_capacity(received);
+        for (bytes, src) in batch.datagrams(received) {
+            let message = match MessageRequest::from_bytes(bytes) {
+                Ok(message) => message,
+                Err(e) => {
+                    debug!("Dropping undecodable query from {}: {}", src, e);
+                    continue;
+                }
+            };
+            let request = Request::new(message, src, Protocol::Udp);
+            let responder = BufferedResponse::default();
+            let answer = responder.answer.clone();
+            let handler = handler.clone();
+            let mut task = Box::pin(async move { handler.handle_request(&request, responder).await });
+
+            if (&mut task).now_or_never().is_some() {
+                if let Some(bytes) = take(&answer) {
+                    metrics::inc(&metrics::metrics().udp_fast_path_responses);
+                    ready.push((src, bytes));
+                }
+                continue;
+            }
+            let socket = socket.clone();
+            tokio::spawn(async move {
+                task.await;
+                if let Some(bytes) = take(&answer)
+                    && let Err(e) = socket.send_to(&bytes, src).await
+                {
+                    debug!("Failed to send response to {}: {}", src, e);
+                }
+            });
+        }
+        send_batch(&socket, &ready).await;
+    }
+}
+
+fn take(answer: &Mutex<Option<Vec<u8>>>) -> Option<Vec<u8>> {
+    answer.lock().unwrap_or_else(|e| e.into_inner()).take()
+}
+
+/// Response handle that encodes the answer into a buffer instead of sending it
+#[derive(Clone, Default)]
+struct BufferedResponse {
+    answer: Arc<Mutex<Option<Vec<u8>>>>,
+}
+
+#[async_trait::async_trait]
+impl ResponseHandler for BufferedResponse {
+    async fn send_response<'a>(
+        &mut self,
+        response: MessageResponse<
+            '_,
+            'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+        >,
+    ) -> io::Result<ResponseInfo> {
+        let id = response.header().id();
+        // Same limit as ServerFuture: the client's EDNS payload size, else the RFC 6891 default
+        let max_size = response
+            .get_edns()
+            .as_ref()
+            .map_or(MAX_DATAGRAM as u16, |edns| edns.max_payload());
+        let mut buffer = Vec::with_capacity(512);
+        let mut encoder = BinEncoder::new(&mut buffer);
+        encoder.set_max_size(max_size);
+        let info = match response.destructive_emit(&mut encoder) {
+            Ok(info) => info,
+            Err(e) => {
+                error!("Error encoding response: {}", e);
+                let mut header = Header::new();
+                header.set_id(id);
+                header.set_response_code(ResponseCode::ServFail);
+                buffer = header.to_bytes()?;
+                ResponseInfo::from(header)
+            }
+        };
+        *self.answer.lock().unwrap_or_else(|e| e.into_inner()) = Some(buffer);
+        Ok(info)
+    }
+}
+
+/// Receive buffers and source addresses for one `recvmmsg` call
+struct RecvBatch {
+    buffers: Vec<[u8; MAX_DATAGRAM]>,
+    addrs: Vec<libc::sockaddr_storage>,
+    lens: Vec<usize>,
+}
+
+impl RecvBatch {
+    fn new() -> Self {
+        Self {
+            buffers: vec![[0; MAX_DATAGRAM]; BATCH],
+            // SAFETY: sockaddr_storage is plain data; all zeroes is a valid (unspecified) address
+            addrs: vec![unsafe { mem::zeroed() }; BATCH],
+            lens: vec![0; BATCH],
+        }
+    }
+
+    /// Read up to [`BATCH`] datagrams without blocking, returning how many arrived
+    fn recv(&mut self, fd: RawFd) -> io::Result<usize> {
+        let mut iovecs: Vec<libc::iovec> = self
+            .buffers
+            .iter_mut()
+            .map(|buffer| libc::iovec {
+                iov_base: buffer.as_mut_ptr().cast(),
+                iov_len: buffer.len(),
+            })
+            .collect();
+        let mut headers: Vec<libc::mmsghdr> = iovecs
+            .iter_mut()
+            .zip(self.addrs.iter_mut())
+            .map(|(iovec, addr)| {
+                // SAFETY: mmsghdr is plain data; the fields that matter are set below
+                let mut header: libc::mmsghdr = unsafe { mem::zeroed() };
+                header.msg_hdr.msg_name = (addr as *mut libc::sockaddr_storage).cast();
+                header.msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+                header.msg_hdr.msg_iov = iovec;
+                header.msg_hdr.msg_iovlen = 1;
+                header
+            })
+            .collect();
+
+        // SAFETY: every header points at a live buffer and address owned by self or iovecs
+        let n = unsafe {
+            libc::recvmmsg(
+                fd,
+                headers.as_mut_ptr(),
+                BATCH as _,
+                libc::MSG_DONTWAIT as _,
+                std::ptr::null_mut(),
+            )
+        };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let n = n as usize;
+        for (len, header) in self.lens.iter_mut().zip(&headers[..n]) {
+            // Truncated datagrams were larger than any query we accept
+            *len = if header.msg_hdr.msg_flags & libc::MSG_TRUNC != 0 {
+                0
+            } else {
+                header.msg_len as usize
+            };
+        }
+        Ok(n)
+    }
+
+    /// The first `n` datagrams with their senders, skipping truncated ones
+    fn datagrams(&self, n: usize) -> impl Iterator<Item = (&[u8], SocketAddr)> {
+        self.buffers[..n]
+            .iter()
+            .zip(&self.lens)
+            .zip(&self.addrs)
+            .filter(|((_, len), _)| **len > 0)
+            .filter_map(|((buffer, len), addr)| Some((&buffer[..*len], from_sockaddr(addr)?)))
+    }
+}
+
+/// Send every `(destination, bytes)` pair, as many per `sendmmsg` as the kernel takes
+async fn send_batch(socket: &UdpSocket, ready: &[(SocketAddr, Vec<u8>)]) {
+    let mut sent = 0;
+    while sent < ready.len() {
+        match socket
+            .async_io(Interest::WRITABLE, || send_mmsg(socket.as_raw_fd(), &ready[sent..]))
+            .await
+        {
+            Ok(n) => sent += n.max(1),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => {
+                // The error belongs to the first message; the rest may still go through
+                debug!("Failed to send response to {}: {}", ready[sent].0, e);
+                sent += 1;
+            }
+        }
+    }
+}
+
+fn send_mmsg(fd: RawFd, messages: &[(SocketAddr, Vec<u8>)]) -> io::Result<usize> {
+    let messages = &messages[..messages.len().min(BATCH)];
+    let mut addrs: Vec<(libc::sockaddr_storage, libc::socklen_t)> =
+        messages.iter().map(|(dst, _)| to_sockaddr(*dst)).collect();
+    let mut iovecs: Vec<libc::iovec> = messages
+        .iter()
+        .map(|(_, bytes)| libc::iovec {
+            iov_base: bytes.as_ptr().cast_mut().cast(),
+            iov_len: bytes.len(),
+        })
+        .collect();
+    let mut headers: Vec<libc::mmsghdr> = iovecs
+        .iter_mut()
+        .zip(addrs.iter_mut())
+        .map(|(iovec, (addr, len))| {
+            // SAFETY: mmsghdr is plain data; the fields that matter are set below
+            let mut header: libc::mmsghdr = unsafe { mem::zeroed() };
+            header.msg_hdr.msg_name = (addr as *mut libc::sockaddr_storage).cast();
+            header.msg_hdr.msg_namelen = *len;
+            header.msg_hdr.msg_iov = iovec;
+            header.msg_hdr.msg_iovlen = 1;
+            header
+        })
+        .collect();
+
+    // SAFETY: every header points at a live address and payload; sendmmsg only reads them
+    let n = unsafe { libc::sendmmsg(fd, headers.as_mut_ptr(), headers.len() as _, libc::MSG_DONTWAIT as _) };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(n as usize)
+}
+
+fn from_sockaddr(storage: &libc::sockaddr_storage) -> Option<SocketAddr> {
+    match storage.ss_family as libc::c_int {
+        libc::AF_INET => {
+            // SAFETY: the family says the storage holds a sockaddr_in
+            let addr = unsafe { &*(storage as *const libc::sockaddr_storage).cast::<libc::sockaddr_in>() };
+            Some(SocketAddr::V4(SocketAddrV4::new(
+                Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+                u16::from_be(addr.sin_port),
+            )))
+        }
+        libc::AF_INET6 => {
+            // SAFETY: the family says the storage holds a sockaddr_in6
+            let addr = unsafe { &*(storage as *const libc::sockaddr_storage).cast::<libc::sockaddr_in6>() };
+            Some(SocketAddr::V6(SocketAddrV6::new(
+                Ipv6Addr::from(addr.sin6_addr.s6_addr),
+                u16::from_be(addr.sin6_port),
+                addr.sin6_flowinfo,
+                addr.sin6_scope_id,
+            )))
+        }
+        _ => None,
+    }
+}
+
+fn to_sockaddr(addr: SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    // SAFETY: sockaddr_storage is plain data; all zeroes is a valid (unspecified) address
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = match addr {
+        SocketAddr::V4(v4) => {
+            // SAFETY: sockaddr_storage is large and aligned enough for any address family
+            let sin = unsafe { &mut *(&mut storage as *mut libc::sockaddr_storage).cast::<libc::sockaddr_in>() };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = v4.port().to_be();
+            sin.sin_addr.s_addr = u32::from(*v4.ip()).to_be();
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(v6) => {
+            // SAFETY: as above
+            let sin6 = unsafe { &mut *(&mut storage as *mut libc::sockaddr_storage).cast::<libc::sockaddr_in6>() };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = v6.port().to_be();
+            sin6.sin6_flowinfo = v6.flowinfo();
+            sin6.sin6_addr.s6_addr = v6.ip().octets();
+            sin6.sin6_scope_id = v6.scope_id();
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    (storage, len as libc::socklen_t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::{Message, MessageType, Query};
+    use hickory_proto::rr::{Name, RecordType};
+    use hickory_server::authority::MessageResponseBuilder;
+    use std::time::Duration;
+
+    #[test]
+    fn test_sockaddr_round_trip() {
+        for addr in ["192.168.1.40:5353", "[fe80::1%2]:53", "[2001:db8::7]:40000"] {
+            let addr: SocketAddr = addr.parse().unwrap();
+            let (storage, _) = to_sockaddr(addr);
+            assert_eq!(from_sockaddr(&storage), Some(addr));
+        }
+    }
+
+    /// Answers every query at once, or after a delay when it asks for AAAA
+    struct Echo;
+
+    #[async_trait::async_trait]
+    impl RequestHandler for Echo {
+        async fn handle_request<R: ResponseHandler>(&self, request: &Request, mut response: R) -> ResponseInfo {
+            if request.queries()[0].query_type() == RecordType::AAAA {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            let builder = MessageResponseBuilder::from_message_request(request);
+            let mut header = Header::response_from_request(request.header());
+            header.set_response_code(ResponseCode::NXDomain);
+            response
+                .send_response(builder.build_no_records(header))
+                .await
+                .unwrap()
+        }
+    }
+
+    fn query(id: u16, record_type: RecordType) -> Vec<u8> {
+        let mut message = Message::new();
+        message.set_id(id);
+        message.add_query(Query::query(Name::from_utf8("printer.mdns.home.arpa.").unwrap(), record_type));
+        message.to_bytes().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_answers_ready_and_waiting_queries() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(serve(server, Arc::new(Echo)));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        for id in 1..=5 {
+            let record_type = if id == 3 { RecordType::AAAA } else { RecordType::A };
+            client.send_to(&query(id, record_type), addr).await.unwrap();
+        }
+        client.send_to(b"not dns", addr).await.unwrap();
+
+        let mut ids = Vec::new();
+        let mut buffer = [0; 512];
+        for _ in 1..=5 {
+            let (len, _) = tokio::time::timeout(Duration::from_secs(5), client.recv_from(&mut buffer))
+                .await
+                .unwrap()
+                .unwrap();
+            let message = Message::from_bytes(&buffer[..len]).unwrap();
+            assert_eq!(message.message_type(), MessageType::Response);
+            assert_eq!(message.response_code(), ResponseCode::NXDomain);
+            ids.push(message.id());
+        }
+        ids.sort_unstable();
+        assert_eq!(ids, vec![1, 2, 3, 4, 5]);
+    }
+}
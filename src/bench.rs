@@ -0,0 +1,299 @@
+//! `bench` subcommand
+//!
+//! Open-loop load generator for sizing hardware and checking performance
+//! work: queries go out over UDP at a fixed rate whether or not earlier ones
+//! were answered, so a slow target shows up as latency and timeouts rather
+//! than as a lower send rate. Listed names are queried once before the timed
+//! run so they are answered from the cache; a share of the queries asks for
+//! names nobody advertises, which the target has to look up via mDNS.
+
+use crate::conformance::build_query;
+use crate::random::random_u64;
+use hickory_proto::op::{Message, ResponseCode};
+use hickory_proto::rr::{Name, RecordType};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+
+/// Load to generate
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    pub target: SocketAddr,
+    /// Discovery domain the target serves; miss names are made up below it
+    pub zone: Name,
+    /// Queries sent per second
+    pub qps: u32,
+    pub duration: Duration,
+    /// Percentage of queries for names that are not cached
+    pub miss_percent: u8,
+    /// Time after which an unanswered query counts as timed out
+    pub timeout: Duration,
+    /// Names queried for cache hits
+    pub names: Vec<(Name, RecordType)>,
+}
+
+/// What the timed run measured
+#[derive(Debug, Default)]
+pub struct Report {
+    pub sent: usize,
+    /// Latencies of answered queries for listed names
+    pub hits: Vec<Duration>,
+    /// Latencies of answered queries for made-up names
+    pub misses: Vec<Duration>,
+    /// Answers with a response code other than NOERROR
+    pub errors: usize,
+    pub timeouts: usize,
+    /// Listed names the warm-up query got no answer for in time
+    pub cold: usize,
+    /// Time taken to send every query
+    pub elapsed: Duration,
+}
+
+#[derive(Default)]
+struct InFlight {
+    /// Send time and whether it was a miss, by message id
+    pending: HashMap<u16, (Instant, bool)>,
+    report: Report,
+}
+
+impl InFlight {
+    fn answered(&mut self, response: &Message, timeout: Duration) {
+        let Some((sent, miss)) = self.pending.remove(&response.id()) else {
+            return;
+        };
+        let latency = sent.elapsed();
+        if latency > timeout {
+            self.report.timeouts += 1;
+            return;
+        }
+        if response.response_code() != ResponseCode::NoError {
+            self.report.errors += 1;
+        }
+        if miss {
+            self.report.misses.push(latency);
+        } else {
+            self.report.hits.push(latency);
+        }
+    }
+}
+
+/// Default names when no list is given: records the target answers itself
+pub fn default_names(zone: &Name) -> Vec<(Name, RecordType)> {
+    let services = Name::from_ascii("_services._dns-sd._udp").and_then(|n| n.append_domain(zone));
+    let mut names = vec![(zone.clone(), RecordType::SOA), (zone.clone(), RecordType::NS)];
+    if let Ok(services) = services {
+        names.push((services, RecordType::PTR));
+    }
+    names
+}
+
+/// Parse a name list: one name per line, optionally followed by a record
+/// type (default A). Names without a trailing dot are relative to `zone`.
+/// Blank lines and lines starting with `#` are skipped.
+pub fn parse_names(text: &str, zone: &Name) -> Result<Vec<(Name, RecordType)>, String> {
+    let mut names = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let name = fields.next().unwrap_or_default();
+        let record_type = match fields.next() {
+            Some(t) => t
+                .to_ascii_uppercase()
+                .parse()
+                .map_err(|_| format!("line {}: unknown record type '{}'", number + 1, t))?,
+            None => RecordType::A,
+        };
+        let mut parsed = Name::from_utf8(name).map_err(|e| format!("line {}: {}", number + 1, e))?;
+        if !parsed.is_fqdn() {
+            parsed = parsed.append_domain(zone).map_err(|e| format!("line {}: {}", number + 1, e))?;
+        }
+        names.push((parsed, record_type));
+    }
+    if names.is_empty() {
+        return Err("no names listed".to_string());
+    }
+    Ok(names)
+}
+
+/// Warm the cache, generate the load and collect the results
+pub async fn run(config: &BenchConfig) -> Result<Report, String> {
+    let bind: SocketAddr = if config.target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }
+        .parse()
+        .map_err(|e: std::net::AddrParseError| e.to_string())?;
+    let socket = Arc::new(UdpSocket::bind(bind).await.map_err(|e| e.to_string())?);
+    socket.connect(config.target).await.map_err(|e| e.to_string())?;
+
+    let cold = warm_up(&socket, config).await?;
+
+    let state = Arc::new(Mutex::new(InFlight::default()));
+    let receiver = tokio::spawn(receive(socket.clone(), state.clone(), config.timeout));
+
+    let total = (u64::from(config.qps) * config.duration.as_secs()).max(1) as usize;
+    let mut interval = tokio::time::interval(Duration::from_secs_f64(1.0 / f64::from(config.qps.max(1))));
+    // Catch up after a stall instead of lowering the offered load
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Burst);
+    let started = Instant::now();
+    for i in 0..total {
+        interval.tick().await;
+        let miss = random_u64() % 100 < u64::from(config.miss_percent);
+        let mut query = if miss {
+            let label = format!("bench-miss-{:x}", random_u64() as u32);
+            let name = Name::from_ascii(&label)
+                .and_then(|n| n.append_domain(&config.zone))
+                .map_err(|e| e.to_string())?;
+            build_query(&name, RecordType::A)
+        } else {
+            let (name, record_type) = &config.names[i % config.names.len()];
+            build_query(name, *record_type)
+        };
+        let id = i as u16;
+        query.set_id(id);
+        let bytes = query.to_vec().map_err(|e| e.to_string())?;
+        {
+            let mut state = state.lock().unwrap();
+            // Ids wrap after 65536 queries; one still unanswered by then has timed out
+            if state.pending.insert(id, (Instant::now(), miss)).is_some() {
+                state.report.timeouts += 1;
+            }
+            state.report.sent += 1;
+        }
+        socket.send(&bytes).await.map_err(|e| e.to_string())?;
+    }
+    let elapsed = started.elapsed();
+
+    tokio::time::sleep(config.timeout).await;
+    receiver.abort();
+    let mut state = state.lock().unwrap();
+    let outstanding = state.pending.len();
+    let mut report = std::mem::take(&mut state.report);
+    report.timeouts += outstanding;
+    report.elapsed = elapsed;
+    report.cold = cold;
+    Ok(report)
+}
+
+/// Query every listed name once, one at a time, so the timed run finds them
+/// cached. Returns how many got no answer in time.
+async fn warm_up(socket: &UdpSocket, config: &BenchConfig) -> Result<usize, String> {
+    let mut buf = vec![0u8; 65_535];
+    let mut cold = 0;
+    for (name, record_type) in &config.names {
+        let query = build_query(name, *record_type);
+        socket.send(&query.to_vec().map_err(|e| e.to_string())?).await.map_err(|e| e.to_string())?;
+        let answered = tokio::time::timeout(config.timeout, async {
+            loop {
+                let len = socket.recv(&mut buf).await?;
+                if Message::from_vec(&buf[..len]).is_ok_and(|r| r.id() == query.id()) {
+                    return Ok::<_, std::io::Error>(());
+                }
+            }
+        })
+        .await;
+        match answered {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => return Err(e.to_string()),
+            Err(_) => cold += 1,
+        }
+    }
+    Ok(cold)
+}
+
+async fn receive(socket: Arc<UdpSocket>, state: Arc<Mutex<InFlight>>, timeout: Duration) {
+    let mut buf = vec![0u8; 65_535];
+    while let Ok(len) = socket.recv(&mut buf).await {
+        if let Ok(response) = Message::from_vec(&buf[..len]) {
+            state.lock().unwrap().answered(&response, timeout);
+        }
+    }
+}
+
+/// Latency at percentile `p` (0-100) of sorted `latencies`
+pub fn percentile(latencies: &[Duration], p: f64) -> Duration {
+    if latencies.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = ((p / 100.0) * latencies.len() as f64).ceil() as usize;
+    latencies[rank.clamp(1, latencies.len()) - 1]
+}
+
+/// Human-readable summary
+pub fn render(config: &BenchConfig, report: &mut Report) -> String {
+    let mut out = String::new();
+    let answered = report.hits.len() + report.misses.len();
+    let _ = writeln!(
+        out,
+        "Benchmark: {} at {} qps for {:?} ({}% misses)",
+        config.target, config.qps, config.duration, config.miss_percent
+    );
+    let _ = writeln!(
+        out,
+        "sent {} ({:.1}/s), answered {}, errors {}, timeouts {}",
+        report.sent,
+        report.sent as f64 / report.elapsed.as_secs_f64().max(f64::EPSILON),
+        answered,
+        report.errors,
+        report.timeouts
+    );
+    if report.cold > 0 {
+        let _ = writeln!(out, "{} listed names were not answered before the run and may count as misses", report.cold);
+    }
+    let _ = writeln!(out, "{:<8} {:>8} {:>10} {:>10} {:>10} {:>10} {:>10}", "", "count", "p50", "p90", "p99", "p99.9", "max");
+    let mut all: Vec<Duration> = report.hits.iter().chain(&report.misses).copied().collect();
+    for (label, latencies) in [("hits", &mut report.hits), ("misses", &mut report.misses), ("all", &mut all)] {
+        latencies.sort();
+        let _ = writeln!(
+            out,
+            "{:<8} {:>8} {:>10} {:>10} {:>10} {:>10} {:>10}",
+            label,
+            latencies.len(),
+            format_latency(percentile(latencies, 50.0)),
+            format_latency(percentile(latencies, 90.0)),
+            format_latency(percentile(latencies, 99.0)),
+            format_latency(percentile(latencies, 99.9)),
+            format_latency(latencies.last().copied().unwrap_or_default()),
+        );
+    }
+    out
+}
+
+fn format_latency(latency: Duration) -> String {
+    format!("{:.2}ms", latency.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zone() -> Name {
+        Name::from_utf8("mdns.home.arpa.").unwrap()
+    }
+
+    #[test]
+    fn test_parse_names() {
+        let text = "# printers\nprinter\n\nOffice._ipp._tcp SRV\nother.example. aaaa\n";
+        let names = parse_names(text, &zone()).unwrap();
+        assert_eq!(names.len(), 3);
+        assert_eq!(names[0], (Name::from_utf8("printer.mdns.home.arpa.").unwrap(), RecordType::A));
+        assert_eq!(names[1].1, RecordType::SRV);
+        assert_eq!(names[2], (Name::from_utf8("other.example.").unwrap(), RecordType::AAAA));
+
+        assert!(parse_names("printer BOGUS\n", &zone()).unwrap_err().contains("line 1"));
+        assert!(parse_names("# nothing\n", &zone()).is_err());
+    }
+
+    #[test]
+    fn test_percentile() {
+        let latencies: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&latencies, 50.0), Duration::from_millis(50));
+        assert_eq!(percentile(&latencies, 99.0), Duration::from_millis(99));
+        assert_eq!(percentile(&latencies, 100.0), Duration::from_millis(100));
+        assert_eq!(percentile(&latencies, 0.0), Duration::from_millis(1));
+        assert_eq!(percentile(&[], 50.0), Duration::ZERO);
+    }
+}
@@ -0,0 +1,161 @@
+//! Client identification
+//!
+//! One notion of "who is asking" for every per-client feature, so they agree on
+//! what a client is. A query is identified by its source address, plus the TSIG
+//! key name and the DNS Cookie (RFC 7873) client cookie when the query carries
+//! them. The most specific of the three is the client's [`ClientKey`].
+//!
+//! TSIG signatures are not verified: the key name is taken as presented, so it
+//! tells cooperating devices apart but must not be used to grant access.
+
+use hickory_proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+use hickory_proto::rr::{Name, RecordType};
+use hickory_server::server::Request;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+/// Who sent a query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientIdentity {
+    pub addr: SocketAddr,
+    /// Owner name of a TSIG record in the additional section (unverified)
+    pub tsig_key: Option<Name>,
+    /// Client cookie of an EDNS COOKIE option
+    pub cookie: Option<[u8; 8]>,
+}
+
+/// The most specific identifier of a client
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ClientKey {
+    Tsig(Name),
+    Cookie([u8; 8]),
+    Address(IpAddr),
+}
+
+impl ClientIdentity {
+    /// A client known only by its address
+    pub fn from_addr(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            tsig_key: None,
+            cookie: None,
+        }
+    }
+
+    pub fn from_request(request: &Request) -> Self {
+        let tsig_key = request
+            .sig0()
+            .iter()
+            .chain(request.additionals())
+            .find(|record| record.record_type() == RecordType::TSIG)
+            .map(|record| record.name().to_lowercase());
+        let cookie = request.edns().and_then(|edns| match edns.option(EdnsCode::Cookie) {
+            // The client cookie is the first 8 bytes; a server cookie may follow
+            Some(EdnsOption::Unknown(_, data)) if data.len() >= 8 => data[..8].try_into().ok(),
+            _ => None,
+        });
+        Self {
+            addr: request.src(),
+            tsig_key,
+            cookie,
+        }
+    }
+
+    pub fn ip(&self) -> IpAddr {
+        self.addr.ip()
+    }
+
+    /// TSIG key name if present, else the client cookie, else the source address
+    pub fn key(&self) -> ClientKey {
+        if let Some(name) = &self.tsig_key {
+            ClientKey::Tsig(name.clone())
+        } else if let Some(cookie) = self.cookie {
+            ClientKey::Cookie(cookie)
+        } else {
+            ClientKey::Address(self.ip())
+        }
+    }
+}
+
+impl ClientKey {
+    /// "tsig", "cookie" or "addr"
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ClientKey::Tsig(_) => "tsig",
+            ClientKey::Cookie(_) => "cookie",
+            ClientKey::Address(_) => "addr",
+        }
+    }
+}
+
+impl fmt::Display for ClientIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.addr)?;
+        if let Some(name) = &self.tsig_key {
+            write!(f, " key={}", name)?;
+        }
+        if self.cookie.is_some() {
+            write!(f, " cookie")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::{Edns, Message, Query};
+    use hickory_proto::rr::{RData, Record};
+    use hickory_proto::rr::rdata::NULL;
+    use hickory_proto::serialize::binary::{BinDecodable, BinEncodable};
+    use hickory_proto::xfer::Protocol;
+    use hickory_server::authority::MessageRequest;
+
+    fn request(message: Message) -> Request {
+        let bytes = message.to_bytes().unwrap();
+        Request::new(
+            MessageRequest::from_bytes(&bytes).unwrap(),
+            "192.168.1.40:5353".parse().unwrap(),
+            Protocol::Udp,
+        )
+    }
+
+    fn query() -> Message {
+        let mut message = Message::new();
+        message.add_query(Query::query(Name::from_utf8("printer.mdns.home.arpa.").unwrap(), RecordType::A));
+        message
+    }
+
+    #[test]
+    fn test_address_only() {
+        let identity = ClientIdentity::from_request(&request(query()));
+        assert_eq!(identity, ClientIdentity::from_addr("192.168.1.40:5353".parse().unwrap()));
+        assert_eq!(identity.key(), ClientKey::Address("192.168.1.40".parse().unwrap()));
+        assert_eq!(identity.to_string(), "192.168.1.40:5353");
+    }
+
+    #[test]
+    fn test_cookie_and_tsig_key() {
+        let mut message = query();
+        let mut edns = Edns::new();
+        edns.options_mut()
+            .insert(EdnsOption::Unknown(u16::from(EdnsCode::Cookie), vec![1, 2, 3, 4, 5, 6, 7, 8]));
+        message.set_edns(edns);
+        let identity = ClientIdentity::from_request(&request(message.clone()));
+        assert_eq!(identity.key(), ClientKey::Cookie([1, 2, 3, 4, 5, 6, 7, 8]));
+
+        let tsig = Record::from_rdata(
+            Name::from_utf8("Living-Room-TV.").unwrap(),
+            0,
+            RData::Unknown {
+                code: RecordType::TSIG,
+                rdata: NULL::with(vec![0; 4]),
+            },
+        );
+        message.add_additional(tsig);
+        let identity = ClientIdentity::from_request(&request(message));
+        assert_eq!(identity.key(), ClientKey::Tsig(Name::from_utf8("living-room-tv.").unwrap()));
+        assert_eq!(identity.key().kind(), "tsig");
+        assert_eq!(identity.to_string(), "192.168.1.40:5353 key=living-room-tv. cookie");
+    }
+}
@@ -1,11 +1,14 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use hickory_proto::rr::{Name, RecordType};
 use serde::{Deserialize, Serialize};
-use std::net::{IpAddr, Ipv4Addr};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
 use tracing::Level;
 
 /// mDNS-DNS Discovery Proxy configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Config {
     /// Server configuration
     #[serde(default)]
@@ -22,36 +25,291 @@ pub struct Config {
     /// mDNS query configuration
     #[serde(default)]
     pub mdns: MdnsConfig,
+
+    /// Per-service-type resolution strategies, keyed by service type (e.g. "_ipp._tcp")
+    #[serde(default)]
+    pub strategies: HashMap<String, ServiceStrategy>,
+
+    /// Per-zone settings, keyed by zone apex (e.g. "mdns.home.arpa.")
+    #[serde(default)]
+    pub zones: HashMap<String, ZoneConfig>,
+
+    /// Virtual service groups: a name below each zone apex (e.g. "printers")
+    /// whose PTR answer is the union of browsing the listed service types
+    #[serde(default)]
+    pub service_groups: HashMap<String, Vec<String>>,
+
+    /// Debugging aids
+    #[serde(default)]
+    pub debug: DebugConfig,
+
+    /// Administrative interfaces
+    #[serde(default)]
+    pub admin: AdminConfig,
+
+    /// Query audit log
+    #[serde(default)]
+    pub audit: AuditConfig,
+
+    /// Time windows with reduced multicast activity
+    #[serde(default)]
+    pub quiet_hours: QuietHoursConfig,
+
+    /// Service instances answered from configuration before mDNS confirms them
+    #[serde(default)]
+    pub known_services: KnownServicesConfig,
+
+    /// Wake-on-LAN for sleeping devices
+    #[serde(default)]
+    pub wake: WakeConfig,
+
+    /// Active probing of cached service instances
+    #[serde(default)]
+    pub liveness: LivenessConfig,
+
+    /// Response policy (RPZ) applied to queries
+    #[serde(default)]
+    pub policy: PolicyConfig,
+
+    /// Discovery of, and fail-over to, other proxies on the link
+    #[serde(default)]
+    pub peers: PeersConfig,
+
+    /// Cache shared with other instances of an anycast or VIP deployment
+    #[serde(default)]
+    pub cluster: ClusterConfig,
+
+    /// Reaction to interface address changes
+    #[serde(default)]
+    pub network: NetworkConfig,
+
+    /// Ordinary zones served from zone files next to the discovery zones
+    #[serde(default)]
+    pub authoritative: AuthoritativeConfig,
+
+    /// Order in which answer sources are tried
+    #[serde(default)]
+    pub resolution: ResolutionConfig,
+
+    /// DNS Push Notifications (RFC 8765) over DNS Stateful Operations
+    #[serde(default)]
+    pub push: PushConfig,
+
+    /// TSIG (RFC 8945) keys for signed queries
+    #[serde(default)]
+    pub tsig: TsigConfig,
+
+    /// Zone transfers (AXFR) of the discovery zones
+    #[serde(default)]
+    pub axfr: AxfrConfig,
+
+    /// DNS NOTIFY (RFC 1996) to downstream servers when a discovery zone changes
+    #[serde(default)]
+    pub notify: NotifyConfig,
+
+    /// Latency objectives for cached and uncached queries
+    #[serde(default)]
+    pub slo: SloConfig,
+
+    /// Links served by listeners of their own, each with its own discovery domain
+    #[serde(default)]
+    pub links: Vec<LinkConfig>,
+
+    /// Record types each group of clients may receive; the first matching entry applies
+    #[serde(default)]
+    pub record_acls: Vec<RecordAclConfig>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerConfig {
-    /// IP address to bind to
-    #[serde(default = "default_bind_address")]
-    pub bind_address: IpAddr,
+    /// IP addresses to bind to, all on `port`; one address or a list
+    #[serde(default = "default_bind_address", deserialize_with = "one_or_many")]
+    pub bind_address: Vec<IpAddr>,
     
     /// Port to bind to
     #[serde(default = "default_port")]
     pub port: u16,
     
     /// TCP connection timeout in seconds
-    #[serde(default = "default_tcp_timeout")]
+    #[serde(default = "default_tcp_timeout", deserialize_with = "crate::duration::secs")]
     pub tcp_timeout: u64,
 
+    /// Largest DNS message accepted over TCP; a longer length prefix closes the connection
+    #[serde(default = "default_tcp_max_message_bytes")]
+    pub tcp_max_message_bytes: u16,
+
+    /// Most questions a TCP message may carry; more is answered with FORMERR
+    #[serde(default = "default_tcp_max_questions")]
+    pub tcp_max_questions: u16,
+
+    /// Milliseconds a TCP message may take to arrive once it has started
+    #[serde(default = "default_tcp_read_timeout_ms", deserialize_with = "crate::duration::millis")]
+    pub tcp_read_timeout_ms: u64,
+
+    /// Seconds given at shutdown to open TCP and Unix socket connections and
+    /// in-flight mDNS lookups to finish before the proxy exits
+    #[serde(default = "default_drain_timeout", deserialize_with = "crate::duration::secs")]
+    pub drain_timeout_secs: u64,
+
+    /// Most queries answered at once; 0 means no limit
+    #[serde(default = "default_max_concurrent_queries")]
+    pub max_concurrent_queries: usize,
+
+    /// Queries that may wait for one of those slots; more are turned away
+    #[serde(default = "default_query_queue_length")]
+    pub query_queue_length: usize,
+
+    /// Milliseconds a query may wait in the queue before it is turned away
+    #[serde(default = "default_query_queue_timeout_ms", deserialize_with = "crate::duration::millis")]
+    pub query_queue_timeout_ms: u64,
+
+    /// Response to queries turned away while overloaded
+    #[serde(default)]
+    pub overload_response: OverloadResponse,
+
+    /// UDP payload size advertised in EDNS responses; answers to EDNS clients
+    /// are sized to the smaller of this and the client's own size
+    #[serde(default = "default_edns_udp_payload")]
+    pub edns_udp_payload: u16,
+
     /// Discovery domain served by this proxy (mapped to .local for mDNS)
     #[serde(default = "default_discovery_domain")]
     pub discovery_domain: String,
+
+    /// Ports tried in order when `port` cannot be bound
+    #[serde(default)]
+    pub fallback_ports: Vec<u16>,
+
+    /// Unix stream socket also serving DNS, framed as over TCP (disabled when unset)
+    #[serde(default)]
+    pub unix_socket_path: Option<PathBuf>,
+
+    /// Tokio worker threads (default: one per CPU core); at least 1
+    #[serde(default)]
+    pub worker_threads: Option<NonZeroUsize>,
+
+    /// Upper bound on tokio's blocking thread pool (default: tokio's 512); at least 1
+    #[serde(default)]
+    pub max_blocking_threads: Option<NonZeroUsize>,
+
+    /// Host names of other Discovery Proxies on the link, listed with this one
+    /// in zone apex NS answers (RFC 8766 Section 6.2)
+    #[serde(default)]
+    pub peer_proxies: Vec<String>,
+
+    /// Character-strings of a TXT record served at each zone apex, for
+    /// monitoring and Discovery Proxy detection; `{zone}` becomes the zone
+    #[serde(default)]
+    pub apex_txt: Vec<String>,
+
+    /// Answer A/AAAA for this proxy's own name (the zone apex NS target,
+    /// `discovery-proxy.<zone>`) from its interface addresses
+    #[serde(default = "default_own_address_records")]
+    pub own_address_records: bool,
+
+    /// Up to this many seconds are taken off each response's TTLs at random,
+    /// so clients do not re-query in step
+    #[serde(default, deserialize_with = "crate::duration::secs")]
+    pub ttl_jitter_secs: u32,
+
+    /// Nonzero TTLs are raised to at least this many seconds, after decay,
+    /// the cap and jitter: some clients treat TTLs of 1 or 2 as zero
+    #[serde(default, deserialize_with = "crate::duration::secs")]
+    pub ttl_floor_secs: TtlFloor,
+
+    /// NODATA answers for names the proxy has records for carry an NSEC record
+    /// listing the types that do exist, as RFC 8766 recommends
+    #[serde(default = "default_nsec_records")]
+    pub nsec_records: bool,
+
+    /// UDP sockets opened on the DNS port with SO_REUSEPORT, each served by its own task
+    #[serde(default = "default_udp_sockets")]
+    pub udp_sockets: usize,
+
+    /// Receive buffer size requested for each UDP socket, in bytes (None for the OS default)
+    #[serde(default)]
+    pub udp_recv_buffer_bytes: Option<usize>,
+
+    /// Interface or VRF the DNS sockets are pinned to with SO_BINDTODEVICE (Linux only)
+    #[serde(default)]
+    pub bind_device: Option<String>,
+
+    /// Answer for `.local` names asked by clients that are not on a local link
+    #[serde(default)]
+    pub off_link_local_queries: OffLinkLocalQueries,
+
+    /// DNS-over-TLS listener, served when the section is present (needs the `tls` feature)
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+}
+
+/// DNS-over-TLS listener on its own port
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// Port on `server.bind_address`
+    #[serde(default = "default_tls_port")]
+    pub port: u16,
+
+    /// PEM certificate chain, leaf first
+    pub cert_file: PathBuf,
+
+    /// PEM private key (PKCS#8, PKCS#1 or SEC1)
+    pub key_file: PathBuf,
+
+    /// PEM bundle of the CAs client certificates must be issued by; unset
+    /// means any client may connect without a certificate
+    #[serde(default)]
+    pub client_ca_file: Option<PathBuf>,
+
+    /// Which EDNS clients get responses padded (RFC 7830)
+    #[serde(default)]
+    pub padding: EdnsPadding,
+
+    /// Padded responses are a multiple of this many bytes (RFC 8467 Section 4.1)
+    #[serde(default = "default_padding_block_bytes")]
+    pub padding_block_bytes: u16,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            port: default_tls_port(),
+            cert_file: PathBuf::new(),
+            key_file: PathBuf::new(),
+            client_ca_file: None,
+            padding: EdnsPadding::default(),
+            padding_block_bytes: default_padding_block_bytes(),
+        }
+    }
+}
+
+/// Responses padded to a block size, so their length does not tell an
+/// observer of encrypted traffic which name was resolved
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EdnsPadding {
+    /// No padding
+    Off,
+    /// Pad responses to queries that were padded themselves
+    #[default]
+    Requested,
+    /// Pad responses to every query with EDNS
+    Always,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CacheConfig {
     /// Cache TTL in seconds
-    #[serde(default = "default_cache_ttl")]
+    #[serde(default = "default_cache_ttl", deserialize_with = "crate::duration::secs")]
     pub ttl_seconds: u64,
     
     /// Enable or disable caching
     #[serde(default = "default_cache_enabled")]
     pub enabled: bool,
+
+    /// Soft memory limit for cached records in KiB; exceeding it evicts the oldest entries
+    #[serde(default)]
+    pub memory_limit_kb: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -64,67 +322,1073 @@ pub struct LoggingConfig {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MdnsConfig {
     /// PTR/SRV/TXT query timeout in milliseconds
-    #[serde(default = "default_service_query_timeout")]
+    #[serde(default = "default_service_query_timeout", deserialize_with = "crate::duration::millis")]
     pub service_query_timeout_ms: u64,
     
     /// Per-event poll timeout in milliseconds during service queries
-    #[serde(default = "default_service_poll_interval")]
+    #[serde(default = "default_service_poll_interval", deserialize_with = "crate::duration::millis")]
     pub service_poll_interval_ms: u64,
     
     /// Hostname resolution timeout in milliseconds
     /// Timeout for A/AAAA queries when resolving hostnames
-    #[serde(default = "default_hostname_resolution_timeout")]
+    #[serde(default = "default_hostname_resolution_timeout", deserialize_with = "crate::duration::millis")]
     pub hostname_resolution_timeout_ms: u64,
+
+    /// Answer from cache only and never send multicast queries (toggleable at runtime)
+    #[serde(default)]
+    pub read_only: bool,
+
+    /// Cache every record a resolved instance yields (SRV, TXT, target A/AAAA) for service
+    /// types without an explicit prefetch strategy
+    #[serde(default = "default_co_resolve")]
+    pub co_resolve: bool,
+
+    /// PTR answers carry each instance's SRV, TXT and address records in the
+    /// additional section, for service types without an explicit additional strategy
+    #[serde(default = "default_answer_bundles")]
+    pub answer_bundles: bool,
+
+    /// After answering A or AAAA for a host, look up the other type in the
+    /// background if it is not cached, for dual-stack clients' second query
+    #[serde(default = "default_address_family_prefetch")]
+    pub address_family_prefetch: bool,
+
+    /// Answer HTTPS queries for hosts, and SVCB queries for instances, of `_https._tcp`
+    /// services with records synthesized from their SRV, TXT and addresses
+    #[serde(default = "default_https_records")]
+    pub https_records: bool,
+
+    /// Answer PTR queries for the reverse names of addresses on the link
+    /// (`dig -x`) with the host's name in the discovery domain
+    #[serde(default = "default_reverse_lookups")]
+    pub reverse_lookups: bool,
+
+    /// IANA service name registry (CSV) whose descriptions are added to the
+    /// built-in ones shown in logs, the inventory and `doctor`
+    #[serde(default)]
+    pub service_types_file: Option<PathBuf>,
+
+    /// Answer for underscore names that are not DNS-SD, e.g. `_dmarc` or `_acme-challenge`
+    #[serde(default)]
+    pub non_dns_sd_names: NonDnsSdNames,
+
+    /// Fresh queries (cache bypassed, answered with TTL 0) let through per minute;
+    /// 0 turns them off
+    #[serde(default = "default_fresh_queries_per_minute")]
+    pub fresh_queries_per_minute: u32,
+
+    /// Changes to one instance's SRV or TXT data are reported at most once per
+    /// this many seconds
+    #[serde(default = "default_change_debounce_secs", deserialize_with = "crate::duration::secs")]
+    pub change_debounce_secs: u64,
+
+    /// A browse the daemon has kept running for this many seconds is torn
+    /// down and started afresh; 0 keeps browses for the life of the process
+    #[serde(default = "default_browse_max_age_secs", deserialize_with = "crate::duration::secs")]
+    pub browse_max_age_secs: u64,
+
+    /// Start the mDNS daemon on the first query that needs it instead of at startup
+    #[serde(default)]
+    pub lazy_start: bool,
+
+    /// Seconds between background retries of a lazy daemon start that failed
+    #[serde(default = "default_start_retry_secs", deserialize_with = "crate::duration::secs")]
+    pub start_retry_secs: u64,
+
+    /// Interfaces (by name) mDNS queries are sent and answered on; all when empty
+    #[serde(default)]
+    pub interfaces: Vec<String>,
+
+    /// What happens to TXT records too large for DNS or for `max_txt_bytes`
+    #[serde(default)]
+    pub oversized_txt: OversizedTxt,
+
+    /// Most bytes of TXT data served for one instance; at least 1
+    #[serde(default = "default_max_txt_bytes")]
+    pub max_txt_bytes: NonZeroUsize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminConfig {
+    /// Path of the Unix control socket (disabled when unset)
+    #[serde(default)]
+    pub control_socket: Option<PathBuf>,
+
+    /// File mode of the control socket; widen it (e.g. 0o666) to share the
+    /// socket with holders of `api_keys`
+    #[serde(default = "default_control_socket_mode")]
+    pub control_socket_mode: u32,
+
+    /// Bearer tokens for the control socket; when any is set, a connection
+    /// must `auth <token>` before other commands and gets that key's scopes
+    #[serde(default)]
+    pub api_keys: Vec<ApiKey>,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            control_socket: None,
+            control_socket_mode: default_control_socket_mode(),
+            api_keys: Vec::new(),
+        }
+    }
+}
+
+/// A control socket token and what it may do
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiKey {
+    /// Label used in logs, never the token itself
+    pub name: String,
+    pub token: String,
+    pub scopes: Vec<ApiScope>,
+}
+
+/// Group of control commands an API key may run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiScope {
+    /// Commands that only report: inventory, metrics, pending, export, ...
+    Read,
+    /// Commands that change what is cached or served: flush, import, zone add/remove, read-only on/off
+    Flush,
+    /// Replacing the response policy
+    Policy,
+}
+
+impl ApiScope {
+    /// Name as written in the configuration
+    pub fn name(self) -> &'static str {
+        match self {
+            ApiScope::Read => "read",
+            ApiScope::Flush => "flush",
+            ApiScope::Policy => "policy",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditConfig {
+    /// Log every answered query
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Directory for daily audit files (logged via tracing when unset)
+    #[serde(default)]
+    pub directory: Option<PathBuf>,
+
+    /// Salt for client IP hashes; a random per-process salt is used when unset
+    #[serde(default)]
+    pub salt: Option<String>,
+
+    /// Days to keep audit files (0 keeps them forever)
+    #[serde(default = "default_audit_retention_days", deserialize_with = "crate::duration::days")]
+    pub retention_days: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuietHoursConfig {
+    /// Daily windows as "HH:MM-HH:MM"; a window may wrap past midnight
+    #[serde(default)]
+    pub windows: Vec<String>,
+
+    /// Offset of the window times from UTC, as "+HH:MM" or "-HH:MM"
+    #[serde(default = "default_quiet_utc_offset")]
+    pub utc_offset: String,
+
+    /// Upper bound on every mDNS query timeout while quiet, in milliseconds
+    #[serde(default = "default_quiet_query_timeout", deserialize_with = "crate::duration::millis")]
+    pub query_timeout_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnownServicesConfig {
+    /// How often configured instances are checked against mDNS, in seconds
+    #[serde(default = "default_known_verify_interval", deserialize_with = "crate::duration::secs")]
+    pub verify_interval_secs: u64,
+
+    /// Instances served immediately for PTR/SRV/TXT queries
+    #[serde(default)]
+    pub services: Vec<KnownService>,
+
+    /// Write-ahead journal of what the background checks learn, replayed and
+    /// compacted at startup (disabled when unset)
+    #[serde(default)]
+    pub journal: Option<PathBuf>,
+}
+
+/// A service instance expected on the link, e.g. a printer that sleeps through browses
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KnownService {
+    /// Service type (e.g. "_ipp._tcp")
+    #[serde(rename = "type")]
+    pub service_type: String,
+
+    /// Instance name (e.g. "Office Printer")
+    pub name: String,
+
+    /// Target host, with or without ".local."
+    pub host: String,
+
+    pub port: u16,
+
+    /// TXT entries as "key=value" (or a bare "key")
+    #[serde(default)]
+    pub txt: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WakeConfig {
+    /// Send a magic packet when a listed device's host name is queried
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Where magic packets are sent
+    #[serde(default = "default_wake_broadcast_address")]
+    pub broadcast_address: SocketAddr,
+
+    /// Minimum seconds between magic packets to the same device
+    #[serde(default = "default_wake_min_interval", deserialize_with = "crate::duration::secs")]
+    pub min_interval_secs: u64,
+
+    /// Devices that can be woken
+    #[serde(default)]
+    pub devices: Vec<WakeDevice>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeersConfig {
+    /// Browse `_dns._udp.local.` for other proxies
+    #[serde(default)]
+    pub discover: bool,
+
+    /// Seconds between browses
+    #[serde(default = "default_peer_discover_interval", deserialize_with = "crate::duration::secs")]
+    pub discover_interval_secs: u64,
+
+    /// Unicast DNS addresses of peer proxies, tried before discovered ones
+    #[serde(default)]
+    pub addresses: Vec<SocketAddr>,
+
+    /// Forward a query to a peer when the local mDNS query fails
+    #[serde(default)]
+    pub forward_on_failure: bool,
+
+    /// How long to wait for each peer in milliseconds
+    #[serde(default = "default_peer_forward_timeout", deserialize_with = "crate::duration::millis")]
+    pub forward_timeout_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClusterConfig {
+    /// Share cache entries with the other cluster members
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// UDP address cache gossip is received on
+    #[serde(default = "default_cluster_listen")]
+    pub listen: SocketAddr,
+
+    /// Gossip addresses of the other cluster members
+    #[serde(default)]
+    pub members: Vec<SocketAddr>,
+
+    /// Base64 secret shared by every member, which signs each datagram; required when enabled
+    #[serde(default)]
+    pub secret: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkConfig {
+    /// Follow interface address changes (netlink on Linux, polling elsewhere)
+    #[serde(default = "default_network_watch")]
+    pub watch: bool,
+
+    /// Seconds between interface polls where netlink is not available
+    #[serde(default = "default_network_poll_interval", deserialize_with = "crate::duration::secs")]
+    pub poll_interval_secs: u64,
+
+    /// Drop cached address records in networks that went away
+    #[serde(default)]
+    pub flush_cache: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResolutionConfig {
+    /// Steps tried for every query; default cache, known, mdns, and peers when
+    /// `[peers] forward_on_failure` is set
+    #[serde(default)]
+    pub chain: Option<Vec<ResolutionStep>>,
+
+    /// Steps for A/AAAA queries, instead of `chain`
+    #[serde(default)]
+    pub address: Option<Vec<ResolutionStep>>,
+
+    /// Steps for PTR/SRV/TXT queries, instead of `chain`
+    #[serde(default)]
+    pub service: Option<Vec<ResolutionStep>>,
+
+    /// Steps for queries of any other type, instead of `chain`
+    #[serde(default)]
+    pub other: Option<Vec<ResolutionStep>>,
+
+    /// Time limit of each step in milliseconds; a step over its limit counts as failed
+    #[serde(default, deserialize_with = "crate::duration::millis_map")]
+    pub timeouts_ms: HashMap<ResolutionStep, u64>,
+}
+
+/// Source of answers in the resolution chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResolutionStep {
+    /// Answers (and recent empty answers) cached from earlier mDNS queries
+    Cache,
+    /// Instances from `[known_services]`
+    Known,
+    /// Multicast query on the link
+    Mdns,
+    /// Forwarding to another proxy on the link (see `[peers]`)
+    Peers,
+}
+
+impl ResolutionStep {
+    /// Name as written in the configuration
+    pub fn name(self) -> &'static str {
+        match self {
+            ResolutionStep::Cache => "cache",
+            ResolutionStep::Known => "known",
+            ResolutionStep::Mdns => "mdns",
+            ResolutionStep::Peers => "peers",
+        }
+    }
+}
+
+impl ResolutionConfig {
+    /// Whether any chain lists `step`
+    pub fn mentions(&self, step: ResolutionStep) -> bool {
+        [&self.chain, &self.address, &self.service, &self.other]
+            .into_iter()
+            .flatten()
+            .any(|steps| steps.contains(&step))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PushConfig {
+    /// Accept DSO sessions and SUBSCRIBE requests on the TCP listener and the Unix socket
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Inactivity timeout offered to clients, in seconds: a session without
+    /// subscriptions that is quiet this long is closed
+    #[serde(default = "default_push_inactivity_timeout", deserialize_with = "crate::duration::secs")]
+    pub inactivity_timeout_secs: u64,
+
+    /// Keepalive interval offered to clients, in seconds; a session with
+    /// subscriptions that is quiet for twice this long is closed
+    #[serde(default = "default_push_keepalive_interval", deserialize_with = "crate::duration::secs")]
+    pub keepalive_interval_secs: u64,
+
+    /// Most subscriptions one session may hold; more are refused
+    #[serde(default = "default_push_max_subscriptions")]
+    pub max_subscriptions: usize,
+
+    /// Seconds between re-resolutions of subscriptions not backed by a browse
+    /// (addresses, subtypes and other types)
+    #[serde(default = "default_push_refresh", deserialize_with = "crate::duration::secs")]
+    pub refresh_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TsigConfig {
+    /// Keys clients may sign queries with; signed queries get signed answers
+    #[serde(default)]
+    pub keys: Vec<TsigKey>,
+
+    /// Interfaces (by name) and networks (e.g. "10.20.0.0/16") whose clients
+    /// must sign their queries; unsigned queries from them are refused
+    #[serde(default)]
+    pub require_from: Vec<String>,
+
+    /// Most seconds a signature's time may differ from the proxy's clock; a
+    /// query signed with a smaller fudge gets that one
+    #[serde(default = "default_tsig_fudge", deserialize_with = "crate::duration::secs")]
+    pub fudge_secs: u16,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AxfrConfig {
+    /// Answer AXFR queries over TCP for the discovery zones with what is cached
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Networks (e.g. "192.168.1.53/32") allowed to transfer; empty allows any client
+    #[serde(default)]
+    pub allow_from: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotifyConfig {
+    /// Servers (e.g. "192.168.1.53:53") sent a NOTIFY when a discovery zone changes;
+    /// none disables the check
+    #[serde(default)]
+    pub targets: Vec<SocketAddr>,
+
+    /// Seconds between comparisons of the discovery zones with what was last notified
+    #[serde(default = "default_notify_check_interval", deserialize_with = "crate::duration::secs")]
+    pub check_interval_secs: u64,
+
+    /// Milliseconds to wait for a target to acknowledge a NOTIFY
+    #[serde(default = "default_notify_timeout", deserialize_with = "crate::duration::millis")]
+    pub timeout_ms: u64,
+
+    /// Times a NOTIFY is resent to a target that does not acknowledge it
+    #[serde(default = "default_notify_retries")]
+    pub retries: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SloConfig {
+    /// Track the objectives below
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Percentage of cached answers that must take at most `cached_target_ms`
+    #[serde(default = "default_slo_cached_percent")]
+    pub cached_percent: f64,
+
+    #[serde(default = "default_slo_cached_target", deserialize_with = "crate::duration::millis")]
+    pub cached_target_ms: u64,
+
+    /// Percentage of answers that needed mDNS or a peer that must take at most `uncached_target_ms`
+    #[serde(default = "default_slo_uncached_percent")]
+    pub uncached_percent: f64,
+
+    #[serde(default = "default_slo_uncached_target", deserialize_with = "crate::duration::millis")]
+    pub uncached_target_ms: u64,
+
+    /// Seconds of queries compliance is computed over
+    #[serde(default = "default_slo_window", deserialize_with = "crate::duration::secs")]
+    pub window_secs: u64,
+
+    /// Fewer queries of a class in the window leave it unjudged
+    #[serde(default = "default_slo_min_samples")]
+    pub min_samples: u64,
+
+    /// Seconds between checks for a breach starting or ending
+    #[serde(default = "default_slo_check_interval", deserialize_with = "crate::duration::secs")]
+    pub check_interval_secs: u64,
+
+    /// `http://` URL sent a JSON POST when a breach starts or ends
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+}
+
+/// A shared TSIG secret
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TsigKey {
+    /// Key name clients sign with (e.g. "resolver1.example.")
+    pub name: String,
+    pub algorithm: TsigAlgorithm,
+    /// Base64 secret, as written by `tsig-keygen`
+    pub secret: String,
+}
+
+/// HMAC algorithm of a TSIG key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TsigAlgorithm {
+    HmacSha256,
+    HmacSha384,
+    HmacSha512,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuthoritativeConfig {
+    /// Zones loaded at startup
+    #[serde(default)]
+    pub zones: Vec<AuthoritativeZone>,
+}
+
+/// A static zone served from an RFC 1035 zone file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuthoritativeZone {
+    /// Zone apex (e.g. "corp.home.arpa.")
+    pub origin: String,
+
+    /// Zone file holding the zone's records, SOA included
+    pub file: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolicyConfig {
+    /// RPZ zone file whose triggers block or rewrite names
+    #[serde(default)]
+    pub rpz_file: Option<PathBuf>,
+
+    /// Seconds between checks of the file for changes
+    #[serde(default = "default_policy_reload_interval", deserialize_with = "crate::duration::secs")]
+    pub reload_interval_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LivenessConfig {
+    /// Probe the targets of cached SRV records with TCP connects
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Seconds between probe rounds
+    #[serde(default = "default_liveness_interval", deserialize_with = "crate::duration::secs")]
+    pub interval_secs: u64,
+
+    /// Connect timeout per probe in milliseconds
+    #[serde(default = "default_liveness_timeout", deserialize_with = "crate::duration::millis")]
+    pub timeout_ms: u64,
+
+    /// Leave instances that failed their last probe out of PTR and SRV answers
+    #[serde(default)]
+    pub filter_dead: bool,
+}
+
+/// A device that can be woken by a Wake-on-LAN magic packet
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WakeDevice {
+    /// Host name, with or without ".local."
+    pub host: String,
+
+    /// MAC address as "aa:bb:cc:dd:ee:ff"
+    pub mac: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DebugConfig {
+    /// Validate outgoing responses against RFC 8766 rules
+    #[serde(default)]
+    pub lint: LintMode,
+
+    /// Log debug detail for single queries that carry the trace EDNS option
+    #[serde(default)]
+    pub query_tracing: bool,
+
+    /// Sort cached records and response sections by name, type and rdata
+    #[serde(default)]
+    pub deterministic_output: bool,
+
+    /// Add a `proxy-debug.<zone>` TXT record describing how the answer was reached
+    #[serde(default)]
+    pub provenance_record: bool,
+}
+
+/// What to do with responses that fail validation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LintMode {
+    /// No validation
+    #[default]
+    Off,
+    /// Log violations and send the response unchanged
+    Log,
+    /// Log violations and drop offending records before sending
+    Drop,
+}
+
+/// Response to queries turned away by the concurrency limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OverloadResponse {
+    /// SERVFAIL, so clients retry elsewhere or later
+    #[default]
+    ServFail,
+    /// REFUSED
+    Refused,
+}
+
+/// How to answer `.local` queries from clients off the local links (RFC 8766 Section 5.1)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OffLinkLocalQueries {
+    /// NXDOMAIN with an SOA in the authority section naming the discovery domain
+    #[default]
+    NxDomain,
+    /// REFUSED
+    Refused,
+    /// No response at all
+    Drop,
+}
+
+/// How to answer names with underscore labels that do not follow the DNS-SD grammar
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NonDnsSdNames {
+    /// Empty NOERROR answer without querying mDNS
+    #[default]
+    NoData,
+    /// NXDOMAIN without querying mDNS
+    NxDomain,
+    /// Query mDNS like any other name
+    Query,
+}
+
+/// Policy for TXT data beyond DNS limits: strings over 255 bytes, or more than
+/// `max_txt_bytes` in all
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OversizedTxt {
+    /// Cut long strings to 255 bytes and leave out strings past the total cap
+    #[default]
+    Truncate,
+    /// Leave out long strings whole, and strings past the total cap
+    Omit,
+    /// Serve no TXT record for the instance
+    Drop,
+}
+
+/// Resolution strategy applied to queries for a single service type
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ServiceStrategy {
+    /// Override for the PTR/SRV/TXT query timeout in milliseconds
+    #[serde(default, deserialize_with = "crate::duration::millis_opt")]
+    pub timeout_ms: Option<u64>,
+
+    /// Records derived from each resolved instance that are cached alongside the answer
+    #[serde(default)]
+    pub prefetch: Vec<ServiceRecordKind>,
+
+    /// Records added to the additional section of PTR/SRV answers for this type
+    #[serde(default)]
+    pub additional: Vec<ServiceRecordKind>,
+
+    /// Always query mDNS afresh for this type, answering with TTL 0
+    #[serde(default)]
+    pub fresh: bool,
+
+    /// Override for `mdns.oversized_txt`
+    #[serde(default)]
+    pub oversized_txt: Option<OversizedTxt>,
+
+    /// Override for `mdns.max_txt_bytes`; at least 1
+    #[serde(default)]
+    pub max_txt_bytes: Option<NonZeroUsize>,
+}
+
+/// A link with a listener and discovery domain of its own, answered only
+/// from the mDNS traffic on its interface
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LinkConfig {
+    /// Interface of the link (e.g. "eth1"); the listener is bound to it
+    pub interface: String,
+
+    /// Discovery domain of the link (e.g. "lan1.mdns.home.arpa.")
+    pub discovery_domain: String,
+
+    /// Address the link's listener binds (default: `server.bind_address`)
+    #[serde(default)]
+    pub bind_address: Option<IpAddr>,
+
+    /// Port the link's listener binds (default: `server.port`)
+    #[serde(default)]
+    pub port: Option<u16>,
+}
+
+/// Record types a group of clients may receive
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordAclConfig {
+    /// Client networks (e.g. "192.168.50.0/24") the entry applies to
+    pub clients: Vec<String>,
+
+    /// Record types these clients receive (e.g. "PTR"); SOA and NS always pass
+    #[serde(default = "default_record_acl_allow")]
+    pub allow: Vec<String>,
+}
+
+/// Settings for a single discovery zone
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ZoneConfig {
+    /// SOA MINIMUM advertised for the zone, which is also how long an empty mDNS
+    /// answer is cached, in seconds
+    #[serde(default = "default_soa_minimum", deserialize_with = "crate::duration::secs")]
+    pub soa_minimum_secs: u32,
+}
+
+/// Lowest nonzero TTL served, in seconds; 0 for none. No higher than the
+/// 10 second TTL cap, so the cap still holds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "u32", into = "u32")]
+pub struct TtlFloor(u32);
+
+impl TtlFloor {
+    pub fn secs(self) -> u32 {
+        self.0
+    }
+}
+
+impl TryFrom<u32> for TtlFloor {
+    type Error = String;
+
+    fn try_from(secs: u32) -> Result<Self, Self::Error> {
+        if secs > crate::mdns_resolver::MAX_UNICAST_TTL {
+            return Err(format!(
+                "ttl_floor_secs {} is above the {}s TTL cap",
+                secs,
+                crate::mdns_resolver::MAX_UNICAST_TTL
+            ));
+        }
+        Ok(Self(secs))
+    }
+}
+
+impl TryFrom<u64> for TtlFloor {
+    type Error = String;
+
+    fn try_from(secs: u64) -> Result<Self, Self::Error> {
+        u32::try_from(secs).map_err(|e| e.to_string()).and_then(Self::try_from)
+    }
+}
+
+impl From<TtlFloor> for u32 {
+    fn from(floor: TtlFloor) -> u32 {
+        floor.0
+    }
+}
+
+/// Largest SOA MINIMUM served: RFC 8766 Section 5.5.1 caps TTLs at 10 seconds
+pub const MAX_SOA_MINIMUM: u32 = 10;
+
+/// Record kinds that can be derived from a resolved service instance
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ServiceRecordKind {
+    #[serde(alias = "srv")]
+    Srv,
+    #[serde(alias = "txt")]
+    Txt,
+    #[serde(alias = "a")]
+    A,
+    #[serde(alias = "aaaa")]
+    Aaaa,
+}
+
+impl ServiceRecordKind {
+    /// Every kind a resolved instance can yield
+    pub const ALL: [ServiceRecordKind; 4] = [
+        ServiceRecordKind::Srv,
+        ServiceRecordKind::Txt,
+        ServiceRecordKind::A,
+        ServiceRecordKind::Aaaa,
+    ];
+
+    /// DNS record type corresponding to this kind
+    pub fn record_type(self) -> RecordType {
+        match self {
+            ServiceRecordKind::Srv => RecordType::SRV,
+            ServiceRecordKind::Txt => RecordType::TXT,
+            ServiceRecordKind::A => RecordType::A,
+            ServiceRecordKind::Aaaa => RecordType::AAAA,
+        }
+    }
+}
+
+// Default value functions
+fn default_bind_address() -> Vec<IpAddr> {
+    vec![IpAddr::V4(Ipv4Addr::LOCALHOST), IpAddr::V6(Ipv6Addr::LOCALHOST)]
+}
+
+/// `deserialize_with` for a setting given as one value or a list of them
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+
+    match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => Ok(vec![value]),
+        OneOrMany::Many(values) if values.is_empty() => Err(serde::de::Error::custom("at least one value is needed")),
+        OneOrMany::Many(values) => Ok(values),
+    }
+}
+
+fn default_record_acl_allow() -> Vec<String> {
+    vec!["PTR".to_string(), "TXT".to_string()]
+}
+
+fn default_port() -> u16 {
+    5335
+}
+
+fn default_padding_block_bytes() -> u16 {
+    468
+}
+
+fn default_tls_port() -> u16 {
+    853
+}
+
+fn default_tcp_timeout() -> u64 {
+    30
+}
+
+fn default_tcp_max_message_bytes() -> u16 {
+    4096
+}
+
+fn default_tcp_max_questions() -> u16 {
+    1
+}
+
+fn default_tcp_read_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_drain_timeout() -> u64 {
+    10
+}
+
+fn default_max_concurrent_queries() -> usize {
+    256
+}
+
+fn default_query_queue_length() -> usize {
+    1024
+}
+
+fn default_query_queue_timeout_ms() -> u64 {
+    1000
+}
+
+fn default_edns_udp_payload() -> u16 {
+    1232
+}
+
+fn default_discovery_domain() -> String {
+    "mdns.home.arpa.".to_string()
+}
+
+fn default_cache_ttl() -> u64 {
+    120
+}
+
+fn default_cache_enabled() -> bool {
+    true
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+fn default_service_query_timeout() -> u64 {
+    option_env!("MDNS_DNS_PROXY_DEFAULT_SERVICE_QUERY_TIMEOUT")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(2000)
+}
+
+fn default_co_resolve() -> bool {
+    true
+}
+
+fn default_answer_bundles() -> bool {
+    true
+}
+
+fn default_address_family_prefetch() -> bool {
+    true
+}
+
+fn default_https_records() -> bool {
+    true
+}
+
+fn default_nsec_records() -> bool {
+    true
+}
+
+fn default_reverse_lookups() -> bool {
+    true
+}
+
+fn default_service_poll_interval() -> u64 {
+    option_env!("MDNS_DNS_PROXY_DEFAULT_SERVICE_POLL_INTERVAL")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(500)
+}
+
+fn default_hostname_resolution_timeout() -> u64 {
+    option_env!("MDNS_DNS_PROXY_DEFAULT_HOSTNAME_RESOLUTION_TIMEOUT")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(1500)
+}
+
+fn default_peer_discover_interval() -> u64 {
+    300
+}
+
+fn default_peer_forward_timeout() -> u64 {
+    1000
+}
+
+fn default_own_address_records() -> bool {
+    true
+}
+
+fn default_udp_sockets() -> usize {
+    1
+}
+
+fn default_fresh_queries_per_minute() -> u32 {
+    30
+}
+
+fn default_change_debounce_secs() -> u64 {
+    10
+}
+
+fn default_browse_max_age_secs() -> u64 {
+    3600
+}
+
+fn default_start_retry_secs() -> u64 {
+    30
+}
+
+fn default_control_socket_mode() -> u32 {
+    0o600
+}
+
+fn default_soa_minimum() -> u32 {
+    MAX_SOA_MINIMUM
+}
+
+fn default_cluster_listen() -> SocketAddr {
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 5390)
+}
+
+fn default_network_watch() -> bool {
+    true
+}
+
+fn default_network_poll_interval() -> u64 {
+    30
+}
+
+fn default_policy_reload_interval() -> u64 {
+    30
+}
+
+fn default_push_inactivity_timeout() -> u64 {
+    15
+}
+
+fn default_push_keepalive_interval() -> u64 {
+    3600
+}
+
+fn default_push_max_subscriptions() -> usize {
+    64
+}
+
+fn default_tsig_fudge() -> u16 {
+    300
+}
+
+fn default_push_refresh() -> u64 {
+    10
+}
+
+fn default_notify_check_interval() -> u64 {
+    10
+}
+
+fn default_notify_timeout() -> u64 {
+    2000
+}
+
+fn default_notify_retries() -> u32 {
+    3
+}
+
+fn default_slo_cached_percent() -> f64 {
+    99.0
+}
+
+fn default_slo_cached_target() -> u64 {
+    5
+}
+
+fn default_slo_uncached_percent() -> f64 {
+    95.0
+}
+
+fn default_slo_uncached_target() -> u64 {
+    1500
+}
+
+fn default_slo_window() -> u64 {
+    300
+}
+
+fn default_slo_min_samples() -> u64 {
+    20
 }
 
-// Default value functions
-fn default_bind_address() -> IpAddr {
-    IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))
+fn default_slo_check_interval() -> u64 {
+    10
 }
 
-fn default_port() -> u16 {
-    5335
+fn default_liveness_interval() -> u64 {
+    600
 }
 
-fn default_tcp_timeout() -> u64 {
-    30
+fn default_liveness_timeout() -> u64 {
+    1000
 }
 
-fn default_discovery_domain() -> String {
-    "mdns.home.arpa.".to_string()
+fn default_wake_broadcast_address() -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::BROADCAST, 9))
 }
 
-fn default_cache_ttl() -> u64 {
-    120
+fn default_wake_min_interval() -> u64 {
+    60
 }
 
-fn default_cache_enabled() -> bool {
-    true
+fn default_known_verify_interval() -> u64 {
+    300
 }
 
-fn default_log_level() -> String {
-    "info".to_string()
+fn default_quiet_utc_offset() -> String {
+    "+00:00".to_string()
 }
 
-fn default_service_query_timeout() -> u64 {
-    option_env!("MDNS_DNS_PROXY_DEFAULT_SERVICE_QUERY_TIMEOUT")
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(2000)
+fn default_quiet_query_timeout() -> u64 {
+    250
 }
 
-fn default_service_poll_interval() -> u64 {
-    option_env!("MDNS_DNS_PROXY_DEFAULT_SERVICE_POLL_INTERVAL")
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(500)
+fn default_audit_retention_days() -> u64 {
+    30
 }
 
-fn default_hostname_resolution_timeout() -> u64 {
-    option_env!("MDNS_DNS_PROXY_DEFAULT_HOSTNAME_RESOLUTION_TIMEOUT")
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(1500)
+fn default_max_txt_bytes() -> NonZeroUsize {
+    // Largest RDATA a record can carry
+    NonZeroUsize::new(u16::MAX as usize).unwrap()
+}
+
+/// Reduce a service type or service name to its `_service._proto` key.
+/// Accepts "_ipp._tcp", "_ipp._tcp.local." and "Printer._ipp._tcp.mdns.home.arpa." alike.
+fn service_type_key(name: &str) -> Option<String> {
+    let lower = name.to_lowercase();
+    let labels: Vec<&str> = lower.trim_end_matches('.').split('.').collect();
+    let proto_idx = labels.iter().rposition(|l| *l == "_tcp" || *l == "_udp")?;
+    if proto_idx == 0 || !labels[proto_idx - 1].starts_with('_') {
+        return None;
+    }
+    Some(format!("{}.{}", labels[proto_idx - 1], labels[proto_idx]))
 }
 
-fn normalize_domain(domain: &str) -> String {
+pub(crate) fn normalize_domain(domain: &str) -> String {
     let mut d = domain.trim().trim_end_matches('.').to_lowercase();
     if d.starts_with('.') {
         d = d.trim_start_matches('.').to_string();
@@ -132,13 +1396,52 @@ fn normalize_domain(domain: &str) -> String {
     format!("{}.", d)
 }
 
+impl Default for ZoneConfig {
+    fn default() -> Self {
+        Self {
+            soa_minimum_secs: default_soa_minimum(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Address a client on this host reaches the server on: the first bind address
+    pub fn local_address(&self) -> IpAddr {
+        self.bind_address.first().copied().unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST))
+    }
+}
+
 impl Default for ServerConfig {
     fn default() -> Self {
         Self {
             bind_address: default_bind_address(),
             port: default_port(),
             tcp_timeout: default_tcp_timeout(),
+            tcp_max_message_bytes: default_tcp_max_message_bytes(),
+            tcp_max_questions: default_tcp_max_questions(),
+            tcp_read_timeout_ms: default_tcp_read_timeout_ms(),
+            drain_timeout_secs: default_drain_timeout(),
+            max_concurrent_queries: default_max_concurrent_queries(),
+            query_queue_length: default_query_queue_length(),
+            query_queue_timeout_ms: default_query_queue_timeout_ms(),
+            overload_response: OverloadResponse::default(),
+            edns_udp_payload: default_edns_udp_payload(),
             discovery_domain: default_discovery_domain(),
+            fallback_ports: Vec::new(),
+            unix_socket_path: None,
+            worker_threads: None,
+            max_blocking_threads: None,
+            peer_proxies: Vec::new(),
+            apex_txt: Vec::new(),
+            own_address_records: default_own_address_records(),
+            ttl_jitter_secs: 0,
+            ttl_floor_secs: TtlFloor::default(),
+            nsec_records: default_nsec_records(),
+            udp_sockets: default_udp_sockets(),
+            udp_recv_buffer_bytes: None,
+            bind_device: None,
+            off_link_local_queries: OffLinkLocalQueries::default(),
+            tls: None,
         }
     }
 }
@@ -148,6 +1451,7 @@ impl Default for CacheConfig {
         Self {
             ttl_seconds: default_cache_ttl(),
             enabled: default_cache_enabled(),
+            memory_limit_kb: None,
         }
     }
 }
@@ -160,38 +1464,187 @@ impl Default for LoggingConfig {
     }
 }
 
-impl Default for MdnsConfig {
+impl Default for QuietHoursConfig {
     fn default() -> Self {
         Self {
-            service_query_timeout_ms: default_service_query_timeout(),
-            service_poll_interval_ms: default_service_poll_interval(),
-            hostname_resolution_timeout_ms: default_hostname_resolution_timeout(),
+            windows: Vec::new(),
+            utc_offset: default_quiet_utc_offset(),
+            query_timeout_ms: default_quiet_query_timeout(),
+        }
+    }
+}
+
+impl Default for KnownServicesConfig {
+    fn default() -> Self {
+        Self {
+            verify_interval_secs: default_known_verify_interval(),
+            journal: None,
+            services: Vec::new(),
+        }
+    }
+}
+
+impl Default for SloConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            cached_percent: default_slo_cached_percent(),
+            cached_target_ms: default_slo_cached_target(),
+            uncached_percent: default_slo_uncached_percent(),
+            uncached_target_ms: default_slo_uncached_target(),
+            window_secs: default_slo_window(),
+            min_samples: default_slo_min_samples(),
+            check_interval_secs: default_slo_check_interval(),
+            webhook_url: None,
+        }
+    }
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        Self {
+            targets: Vec::new(),
+            check_interval_secs: default_notify_check_interval(),
+            timeout_ms: default_notify_timeout(),
+            retries: default_notify_retries(),
+        }
+    }
+}
+
+impl Default for PeersConfig {
+    fn default() -> Self {
+        Self {
+            discover: false,
+            discover_interval_secs: default_peer_discover_interval(),
+            addresses: Vec::new(),
+            forward_on_failure: false,
+            forward_timeout_ms: default_peer_forward_timeout(),
+        }
+    }
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: default_cluster_listen(),
+            members: Vec::new(),
+            secret: String::new(),
+        }
+    }
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            watch: default_network_watch(),
+            poll_interval_secs: default_network_poll_interval(),
+            flush_cache: false,
+        }
+    }
+}
+
+impl Default for PolicyConfig {
+    fn default() -> Self {
+        Self {
+            rpz_file: None,
+            reload_interval_secs: default_policy_reload_interval(),
+        }
+    }
+}
+
+impl Default for PushConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            inactivity_timeout_secs: default_push_inactivity_timeout(),
+            keepalive_interval_secs: default_push_keepalive_interval(),
+            max_subscriptions: default_push_max_subscriptions(),
+            refresh_secs: default_push_refresh(),
+        }
+    }
+}
+
+impl Default for TsigConfig {
+    fn default() -> Self {
+        Self {
+            keys: Vec::new(),
+            require_from: Vec::new(),
+            fudge_secs: default_tsig_fudge(),
+        }
+    }
+}
+
+impl Default for LivenessConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: default_liveness_interval(),
+            timeout_ms: default_liveness_timeout(),
+            filter_dead: false,
+        }
+    }
+}
+
+impl Default for WakeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            broadcast_address: default_wake_broadcast_address(),
+            min_interval_secs: default_wake_min_interval(),
+            devices: Vec::new(),
+        }
+    }
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            directory: None,
+            salt: None,
+            retention_days: default_audit_retention_days(),
         }
     }
 }
 
-impl Default for Config {
+impl Default for MdnsConfig {
     fn default() -> Self {
         Self {
-            server: ServerConfig::default(),
-            cache: CacheConfig::default(),
-            logging: LoggingConfig::default(),
-            mdns: MdnsConfig::default(),
+            service_query_timeout_ms: default_service_query_timeout(),
+            service_poll_interval_ms: default_service_poll_interval(),
+            hostname_resolution_timeout_ms: default_hostname_resolution_timeout(),
+            read_only: false,
+            co_resolve: default_co_resolve(),
+            answer_bundles: default_answer_bundles(),
+            address_family_prefetch: default_address_family_prefetch(),
+            https_records: default_https_records(),
+            reverse_lookups: default_reverse_lookups(),
+            service_types_file: None,
+            non_dns_sd_names: NonDnsSdNames::default(),
+            fresh_queries_per_minute: default_fresh_queries_per_minute(),
+            change_debounce_secs: default_change_debounce_secs(),
+            browse_max_age_secs: default_browse_max_age_secs(),
+            lazy_start: false,
+            start_retry_secs: default_start_retry_secs(),
+            interfaces: Vec::new(),
+            oversized_txt: OversizedTxt::default(),
+            max_txt_bytes: default_max_txt_bytes(),
         }
     }
 }
 
 /// Command-line arguments
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
     /// Path to configuration file (TOML format)
     #[arg(short, long, env = "MDNS_DNS_PROXY_CONFIG")]
     pub config: Option<PathBuf>,
     
-    /// IP address to bind to
-    #[arg(short, long, env = "MDNS_DNS_PROXY_BIND_ADDRESS")]
-    pub bind_address: Option<IpAddr>,
+    /// IP addresses to bind to, comma separated
+    #[arg(short, long, env = "MDNS_DNS_PROXY_BIND_ADDRESS", value_delimiter = ',')]
+    pub bind_address: Vec<IpAddr>,
     
     /// Port to bind to
     #[arg(short, long, env = "MDNS_DNS_PROXY_PORT")]
@@ -224,6 +1677,85 @@ pub struct Args {
     /// Print an example configuration file with defaults and exit
     #[arg(long)]
     pub print_example_config: bool,
+
+    /// Tool to run instead of the proxy
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Subcommands; the `minimal` build has none
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Resolve a name through the proxy pipeline and directly via mDNS, and compare the answers
+    #[cfg(not(feature = "minimal"))]
+    Doctor {
+        /// Name in the discovery domain (e.g. printer.mdns.home.arpa.)
+        name: String,
+
+        /// Record type to query
+        #[arg(default_value = "A", value_parser = parse_record_type)]
+        record_type: RecordType,
+
+        /// Evaluate record suppression as if this client had asked
+        #[arg(long)]
+        client: Option<IpAddr>,
+    },
+
+    /// Run RFC 8766 conformance checks against a running proxy and report pass/fail
+    #[cfg(not(feature = "minimal"))]
+    Conformance {
+        /// Address of the proxy to test (defaults to the configured bind address and port)
+        #[arg(long)]
+        target: Option<SocketAddr>,
+
+        /// Discovery domain served by the target (defaults to the configured one)
+        #[arg(long)]
+        zone: Option<String>,
+
+        /// Time to wait for each response, in milliseconds
+        #[arg(long, default_value_t = 5000)]
+        timeout_ms: u64,
+    },
+
+    /// Generate query load against a running proxy and print latency percentiles
+    #[cfg(not(feature = "minimal"))]
+    Bench {
+        /// Address of the proxy to load (defaults to the configured bind address and port)
+        #[arg(long)]
+        target: Option<SocketAddr>,
+
+        /// Discovery domain served by the target (defaults to the configured one)
+        #[arg(long)]
+        zone: Option<String>,
+
+        /// Queries sent per second
+        #[arg(long, default_value_t = 500)]
+        qps: u32,
+
+        /// File listing names to query, one per line with an optional record type
+        #[arg(long)]
+        names: Option<PathBuf>,
+
+        /// Length of the run, in seconds
+        #[arg(long, default_value_t = 10)]
+        duration_secs: u64,
+
+        /// Percentage of queries for uncached names
+        #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u8).range(0..=100))]
+        miss_percent: u8,
+
+        /// Time after which a query counts as timed out, in milliseconds
+        #[arg(long, default_value_t = 2000)]
+        timeout_ms: u64,
+    },
+}
+
+#[cfg(not(feature = "minimal"))]
+fn parse_record_type(value: &str) -> Result<RecordType, String> {
+    value
+        .to_ascii_uppercase()
+        .parse()
+        .map_err(|_| format!("unknown record type '{}'", value))
 }
 
 impl Config {
@@ -235,65 +1767,604 @@ impl Config {
         println!("# ");
         println!("# This file configures the behavior of the mDNS-DNS proxy server.");
         println!("# All settings have sensible defaults and are optional.");
+        println!("# Timeouts and intervals take a number in the unit their name gives, or a");
+        println!("# duration with units such as \"500ms\", \"2s\", \"5m\" or \"1h30m\".");
         println!();
         println!("[server]");
-        println!("# IP address to bind the DNS server to");
-        println!("# Default: {} (localhost only)", defaults.server.bind_address);
-        println!("# Use 0.0.0.0 to listen on all interfaces");
-        println!("bind_address = \"{}\"", defaults.server.bind_address);
+        println!("# IP addresses to bind the DNS server to, one or a list; all share the port.");
+        println!("# An address whose family the host lacks (e.g. ::1 with IPv6 disabled) is");
+        println!("# skipped with a warning as long as another one binds");
+        println!("# Default: [\"127.0.0.1\", \"::1\"] (localhost only, IPv4 and IPv6)");
+        println!("# Use \"::\" to listen on all interfaces (both families on most systems)");
+        println!("bind_address = [\"127.0.0.1\", \"::1\"]");
         println!();
         println!("# Port to bind the DNS server to");
         println!("# Default: {}", defaults.server.port);
         println!("# Note: Ports below 1024 require root/admin privileges");
         println!("port = {}", defaults.server.port);
         println!();
+        println!("# Alternate ports tried in order when the port above is already in use");
+        println!("# Default: [] (fail if the port is busy)");
+        println!("# fallback_ports = [5336, 5337]");
+        println!();
+        println!("# Unix socket serving DNS to local stub resolvers and tools, framed as over TCP");
+        println!("# Default: unset (disabled)");
+        println!("# unix_socket_path = \"/run/mdns-dns-proxy/dns.sock\"");
+        println!();
+        println!("# Other Discovery Proxies on the link, listed in zone apex NS answers so");
+        println!("# clients can fail over between them");
+        println!("# Default: [] (only this proxy)");
+        println!("# peer_proxies = [\"proxy2.home.arpa.\"]");
+        println!();
+        println!("# TXT record served at each zone apex, one character-string per entry;");
+        println!("# {{zone}} is replaced by the zone");
+        println!("# Default: [] (no apex TXT record)");
+        println!("# apex_txt = [\"proxy=mdns-dns-proxy\", \"zone={{zone}}\"]");
+        println!();
+        println!("# Answer A/AAAA for this proxy's own name (discovery-proxy.<zone>, the NS");
+        println!("# target) from its interface addresses, so delegations resolve without glue");
+        println!("# Default: {}", defaults.server.own_address_records);
+        println!("own_address_records = {}", defaults.server.own_address_records);
+        println!();
+        println!("# Take up to this many seconds off answer TTLs at random so clients");
+        println!("# do not all re-query at the same moment");
+        println!("# Default: {}", defaults.server.ttl_jitter_secs);
+        println!("ttl_jitter_secs = {}", defaults.server.ttl_jitter_secs);
+        println!();
+        println!("# Raise nonzero TTLs to at least this many seconds (at most 10), for");
+        println!("# clients that treat TTLs of 1 or 2 as zero and re-query in a loop");
+        println!("# Default: {} (off)", defaults.server.ttl_floor_secs.secs());
+        println!("ttl_floor_secs = {}", defaults.server.ttl_floor_secs.secs());
+        println!();
+        println!("# Add an NSEC record listing the types a name does have to NODATA answers");
+        println!("# Default: {}", defaults.server.nsec_records);
+        println!("nsec_records = {}", defaults.server.nsec_records);
+        println!();
+        println!("# UDP sockets opened on the DNS port with SO_REUSEPORT; the kernel spreads");
+        println!("# queries over them, so more than one helps on multi-core hosts (Unix only)");
+        println!("# Default: {}", defaults.server.udp_sockets);
+        println!("udp_sockets = {}", defaults.server.udp_sockets);
+        println!();
+        println!("# Receive buffer requested for each UDP socket, in bytes");
+        println!("# Default: unset (OS default)");
+        println!("# udp_recv_buffer_bytes = 1048576");
+        println!();
+        println!("# Only answer queries arriving on this interface or VRF, whatever the bind address (Linux only)");
+        println!("# Default: unset (all interfaces)");
+        println!("# bind_device = \"mgmt\"");
+        println!();
+        println!("# Answer for .local queries from clients that are not on a local link");
+        println!("# Options: nxdomain (with an SOA naming the discovery domain), refused, drop");
+        println!("# Default: nxdomain");
+        println!("off_link_local_queries = \"nxdomain\"");
+        println!();
+        println!("# DNS-over-TLS listener (RFC 7858; needs a build with the tls feature)");
+        println!("# [server.tls]");
+        println!("# port = 853");
+        println!("# cert_file = \"/etc/mdns-dns-proxy/tls/cert.pem\"");
+        println!("# key_file = \"/etc/mdns-dns-proxy/tls/key.pem\"");
+        println!("# Only accept clients with a certificate issued by one of these CAs (mutual TLS)");
+        println!("# client_ca_file = \"/etc/mdns-dns-proxy/tls/clients-ca.pem\"");
+        println!("# EDNS padding (RFC 7830): off, requested (clients that pad their queries), always");
+        println!("# padding = \"requested\"");
+        println!("# padding_block_bytes = 468");
+        println!();
         println!("# TCP connection timeout in seconds");
         println!("# Default: {}", defaults.server.tcp_timeout);
         println!("tcp_timeout = {}", defaults.server.tcp_timeout);
         println!();
+        println!("# Largest DNS message accepted over TCP, in bytes; longer ones close the connection");
+        println!("# Default: {}", defaults.server.tcp_max_message_bytes);
+        println!("tcp_max_message_bytes = {}", defaults.server.tcp_max_message_bytes);
+        println!();
+        println!("# Most questions a TCP message may carry; more is answered with FORMERR");
+        println!("# Default: {}", defaults.server.tcp_max_questions);
+        println!("tcp_max_questions = {}", defaults.server.tcp_max_questions);
+        println!();
+        println!("# Milliseconds a TCP message may take to arrive once it has started");
+        println!("# Default: {}", defaults.server.tcp_read_timeout_ms);
+        println!("tcp_read_timeout_ms = {}", defaults.server.tcp_read_timeout_ms);
+        println!();
+        println!("# Seconds open TCP connections and in-flight mDNS lookups get at shutdown to finish");
+        println!("# Default: {}", defaults.server.drain_timeout_secs);
+        println!("drain_timeout_secs = {}", defaults.server.drain_timeout_secs);
+        println!();
+        println!("# Most queries answered at once (0 for no limit), how many more may wait");
+        println!("# and for how many milliseconds, and the answer to those turned away");
+        println!("# (servfail or refused)");
+        println!("max_concurrent_queries = {}", defaults.server.max_concurrent_queries);
+        println!("query_queue_length = {}", defaults.server.query_queue_length);
+        println!("query_queue_timeout_ms = {}", defaults.server.query_queue_timeout_ms);
+        println!("overload_response = \"servfail\"");
+        println!();
+        println!("# UDP payload size advertised over EDNS, in bytes (512-4096); clients without EDNS get 512");
+        println!("# Default: {}", defaults.server.edns_udp_payload);
+        println!("edns_udp_payload = {}", defaults.server.edns_udp_payload);
+        println!();
         println!("# Discovery domain served by this proxy (mapped to .local for mDNS)");
         println!("# Default: {}", defaults.server.discovery_domain);
         println!("discovery_domain = \"{}\"", defaults.server.discovery_domain);
         println!();
+        println!("# Runtime worker threads; unset uses one per CPU core");
+        println!("# 1-2 is plenty on small routers");
+        println!("# worker_threads = 2");
+        println!();
+        println!("# Maximum threads in the blocking pool; unset uses tokio's default (512)");
+        println!("# max_blocking_threads = 8");
+        println!();
         println!("[cache]");
         println!("# Cache TTL (time-to-live) in seconds");
         println!("# How long to cache mDNS query results");
         println!("# Default: {} ({} minutes)", defaults.cache.ttl_seconds, defaults.cache.ttl_seconds as f64 / 60.0);
         println!("ttl_seconds = {}", defaults.cache.ttl_seconds);
         println!();
-        println!("# Enable or disable result caching");
-        println!("# Default: {}", defaults.cache.enabled);
-        println!("enabled = {}", defaults.cache.enabled);
+        println!("# Enable or disable result caching");
+        println!("# Default: {}", defaults.cache.enabled);
+        println!("enabled = {}", defaults.cache.enabled);
+        println!();
+        println!("# Soft memory limit for cached records in KiB");
+        println!("# When exceeded, the oldest entries are evicted down to 75% of the limit");
+        println!("# Default: unset (unlimited)");
+        println!("# memory_limit_kb = 4096");
+        println!();
+        println!("[logging]");
+        println!("# Log level for the application");
+        println!("# Options: trace, debug, info, warn, error");
+        println!("# Default: {}", defaults.logging.level);
+        println!("level = \"{}\"", defaults.logging.level);
+        println!();
+        println!("[mdns]");
+        println!("# Timeout for service queries (PTR/SRV/TXT) in milliseconds");
+        println!("# Default: {} ({} seconds)", defaults.mdns.service_query_timeout_ms, defaults.mdns.service_query_timeout_ms as f64 / 1000.0);
+        println!("service_query_timeout_ms = {}", defaults.mdns.service_query_timeout_ms);
+        println!();
+        println!("# Per-event poll interval during service queries in milliseconds");
+        println!("# How frequently to check for new mDNS events");
+        println!("# Default: {} ({} ms)", defaults.mdns.service_poll_interval_ms, defaults.mdns.service_poll_interval_ms);
+        println!("service_poll_interval_ms = {}", defaults.mdns.service_poll_interval_ms);
+        println!();
+        println!("# Timeout for hostname resolution (A/AAAA queries) in milliseconds");
+        println!("# How long to wait when resolving hostnames to IP addresses");
+        println!("# Default: {} ({} second)", defaults.mdns.hostname_resolution_timeout_ms, defaults.mdns.hostname_resolution_timeout_ms as f64 / 1000.0);
+        println!("hostname_resolution_timeout_ms = {}", defaults.mdns.hostname_resolution_timeout_ms);
+        println!();
+        println!("# Answer from cache only and never send multicast queries");
+        println!("# (quiet hours, network maintenance); can be toggled via the control socket");
+        println!("# Default: {}", defaults.mdns.read_only);
+        println!("read_only = {}", defaults.mdns.read_only);
+        println!();
+        println!("# Cache SRV, TXT and address records from every resolved instance so that");
+        println!("# follow-up queries are answered instantly (per-type prefetch overrides this)");
+        println!("# Default: {}", defaults.mdns.co_resolve);
+        println!("co_resolve = {}", defaults.mdns.co_resolve);
+        println!();
+        println!("# Send each browsed instance's SRV, TXT and address records along with a PTR");
+        println!("# answer, so clients need no follow-up queries (per-type additional overrides this)");
+        println!("# Default: {}", defaults.mdns.answer_bundles);
+        println!("answer_bundles = {}", defaults.mdns.answer_bundles);
+        println!();
+        println!("# After answering A (or AAAA) for a host, look up AAAA (or A) in the background");
+        println!("# when it is not cached, so a dual-stack client's second query hits the cache");
+        println!("# Default: {}", defaults.mdns.address_family_prefetch);
+        println!("address_family_prefetch = {}", defaults.mdns.address_family_prefetch);
+        println!();
+        println!("# Answer HTTPS queries for hosts of _https._tcp services (and SVCB queries");
+        println!("# for their instances) with records built from the SRV, TXT and addresses");
+        println!("# Default: {}", defaults.mdns.https_records);
+        println!("https_records = {}", defaults.mdns.https_records);
+        println!();
+        println!("# Answer in-addr.arpa and ip6.arpa PTR queries for private and link-local");
+        println!("# addresses with the host's name, from cached addresses or an mDNS query");
+        println!("# Default: {}", defaults.mdns.reverse_lookups);
+        println!("reverse_lookups = {}", defaults.mdns.reverse_lookups);
+        println!();
+        println!("# Copy of the IANA service name registry (service-names-port-numbers.csv) whose");
+        println!("# descriptions name service types in logs, the inventory and doctor output;");
+        println!("# re-read on reload. Common DNS-SD types are described without it");
+        println!("# Default: unset (built-in descriptions only)");
+        println!("# service_types_file = \"/usr/share/mdns-dns-proxy/service-names-port-numbers.csv\"");
+        println!();
+        println!("# Answer for underscore names that are not DNS-SD (e.g. _dmarc, _acme-challenge)");
+        println!("# Options: nodata (empty answer), nxdomain, query (ask mDNS anyway)");
+        println!("# Default: nodata");
+        println!("non_dns_sd_names = \"nodata\"");
+        println!();
+        println!("# Fresh queries (EDNS option 65003 or a strategy with fresh = true) bypass the");
+        println!("# cache and are answered with TTL 0; at most this many go out per minute, the");
+        println!("# rest are answered normally. 0 turns them off");
+        println!("# Default: {}", defaults.mdns.fresh_queries_per_minute);
+        println!("fresh_queries_per_minute = {}", defaults.mdns.fresh_queries_per_minute);
+        println!();
+        println!("# Changed SRV or TXT data of a known instance is logged as an update at most");
+        println!("# once per this many seconds per instance");
+        println!("# Default: {}", defaults.mdns.change_debounce_secs);
+        println!("change_debounce_secs = {}", defaults.mdns.change_debounce_secs);
+        println!();
+        println!("# Browses for a service type are restarted after this many seconds, so one");
+        println!("# the daemon has let go stale does not linger; 0 keeps them forever");
+        println!("# Default: {}", defaults.mdns.browse_max_age_secs);
+        println!("browse_max_age_secs = {}", defaults.mdns.browse_max_age_secs);
+        println!();
+        println!("# Start the mDNS daemon on the first query that needs it rather than at startup,");
+        println!("# for hosts whose interfaces (VPN, bridges) come up after the proxy");
+        println!("# Default: {}", defaults.mdns.lazy_start);
+        println!("lazy_start = {}", defaults.mdns.lazy_start);
+        println!();
+        println!("# Seconds between background retries of a lazy start that failed");
+        println!("# Default: {}", defaults.mdns.start_retry_secs);
+        println!("start_retry_secs = {}", defaults.mdns.start_retry_secs);
+        println!();
+        println!("# TXT data beyond DNS limits (strings over 255 bytes, or more than");
+        println!("# max_txt_bytes in all): \"truncate\" cuts long strings and leaves out");
+        println!("# strings past the cap, \"omit\" leaves out long strings whole, \"drop\"");
+        println!("# serves no TXT record for the instance");
+        println!("# Default: \"truncate\"");
+        println!("oversized_txt = \"truncate\"");
+        println!("# Default: {}", defaults.mdns.max_txt_bytes);
+        println!("max_txt_bytes = {}", defaults.mdns.max_txt_bytes);
+        println!();
+        println!("# Interfaces mDNS is sent and received on, by name; empty uses every interface");
+        println!("# Default: []");
+        println!("# interfaces = [\"eth0\"]");
+        println!();
+        println!("[admin]");
+        println!("# Unix control socket for runtime changes (e.g. \"zone add vlan20.home.arpa.\")");
+        println!("# Default: unset (disabled)");
+        println!("# control_socket = \"/run/mdns-dns-proxy.sock\"");
+        println!();
+        println!("# File mode of the control socket; widen it to share the socket with api_keys holders");
+        println!("# Default: 0o{:o}", defaults.admin.control_socket_mode);
+        println!("control_socket_mode = 0o{:o}", defaults.admin.control_socket_mode);
+        println!();
+        println!("# Tokens required by the control socket (\"auth <token>\"), each limited to");
+        println!("# scopes: read, flush (cache and served zones), policy (response policy)");
+        println!("# Default: none (every connection may run every command)");
+        println!("# [[admin.api_keys]]");
+        println!("# name = \"dashboard\"");
+        println!("# token = \"change-me\"");
+        println!("# scopes = [\"read\"]");
+        println!();
+        println!("[audit]");
+        println!("# Log every answered query with a salted hash in place of the client IP");
+        println!("# Default: {}", defaults.audit.enabled);
+        println!("enabled = {}", defaults.audit.enabled);
+        println!();
+        println!("# Directory for daily audit-YYYY-MM-DD.log files");
+        println!("# Default: unset (written to the regular log under the \"audit\" target)");
+        println!("# directory = \"/var/log/mdns-dns-proxy\"");
+        println!();
+        println!("# Secret salt for client hashes; set it to compare clients across restarts");
+        println!("# Default: unset (random per process)");
+        println!("# salt = \"change-me\"");
+        println!();
+        println!("# Days to keep audit files, 0 keeps them forever");
+        println!("# Default: {}", defaults.audit.retention_days);
+        println!("retention_days = {}", defaults.audit.retention_days);
+        println!();
+        println!("[quiet_hours]");
+        println!("# Daily windows (\"HH:MM-HH:MM\", may wrap past midnight) during which");
+        println!("# prefetching is paused and mDNS query timeouts are shortened");
+        println!("# Default: [] (never quiet)");
+        println!("# windows = [\"23:00-06:00\"]");
+        println!();
+        println!("# Offset of the window times from UTC");
+        println!("# Default: \"{}\"", defaults.quiet_hours.utc_offset);
+        println!("utc_offset = \"{}\"", defaults.quiet_hours.utc_offset);
+        println!();
+        println!("# Upper bound on mDNS query timeouts while quiet, in milliseconds");
+        println!("# Default: {}", defaults.quiet_hours.query_timeout_ms);
+        println!("query_timeout_ms = {}", defaults.quiet_hours.query_timeout_ms);
+        println!();
+        println!("[known_services]");
+        println!("# Instances answered immediately for PTR/SRV/TXT queries, for devices that");
+        println!("# sleep through browses; mDNS is checked in the background to keep them current");
+        println!("# How often the configured instances are checked, in seconds");
+        println!("# Default: {}", defaults.known_services.verify_interval_secs);
+        println!("verify_interval_secs = {}", defaults.known_services.verify_interval_secs);
+        println!();
+        println!("# Journal of the instances the checks find, so a restart or crash does not");
+        println!("# lose them; replayed and compacted at startup");
+        println!("# Default: none");
+        println!("# journal = \"/var/lib/mdns-dns-proxy/known.journal\"");
+        println!();
+        println!("# [[known_services.services]]");
+        println!("# type = \"_ipp._tcp\"");
+        println!("# name = \"Office Printer\"");
+        println!("# host = \"printer\"");
+        println!("# port = 631");
+        println!("# txt = [\"rp=ipp/print\"]");
+        println!();
+        println!("[wake]");
+        println!("# Send a Wake-on-LAN magic packet when a listed device's host name is queried,");
+        println!("# answering with its last known addresses while it wakes");
+        println!("# Default: {}", defaults.wake.enabled);
+        println!("enabled = {}", defaults.wake.enabled);
+        println!();
+        println!("# Destination of magic packets");
+        println!("# Default: \"{}\"", defaults.wake.broadcast_address);
+        println!("broadcast_address = \"{}\"", defaults.wake.broadcast_address);
+        println!();
+        println!("# Minimum seconds between magic packets to the same device");
+        println!("# Default: {}", defaults.wake.min_interval_secs);
+        println!("min_interval_secs = {}", defaults.wake.min_interval_secs);
+        println!();
+        println!("# [[wake.devices]]");
+        println!("# host = \"nas\"");
+        println!("# mac = \"00:11:32:aa:bb:cc\"");
+        println!();
+        println!("[liveness]");
+        println!("# Probe the targets of cached SRV records with a TCP connect to the service port");
+        println!("# Default: {}", defaults.liveness.enabled);
+        println!("enabled = {}", defaults.liveness.enabled);
+        println!();
+        println!("# Seconds between probe rounds");
+        println!("# Default: {}", defaults.liveness.interval_secs);
+        println!("interval_secs = {}", defaults.liveness.interval_secs);
+        println!();
+        println!("# Connect timeout per probe in milliseconds");
+        println!("# Default: {}", defaults.liveness.timeout_ms);
+        println!("timeout_ms = {}", defaults.liveness.timeout_ms);
+        println!();
+        println!("# Leave instances that failed their last probe out of PTR and SRV answers");
+        println!("# Default: {}", defaults.liveness.filter_dead);
+        println!("filter_dead = {}", defaults.liveness.filter_dead);
+        println!();
+        println!("[policy]");
+        println!("# RPZ zone file with QNAME triggers that block or rewrite names, e.g.");
+        println!("#   blocked.mdns.home.arpa CNAME .   (NXDOMAIN)");
+        println!("# rpz_file = \"/etc/mdns-dns-proxy/policy.rpz\"");
+        println!();
+        println!("# Seconds between checks of the file for changes");
+        println!("# Default: {}", defaults.policy.reload_interval_secs);
+        println!("reload_interval_secs = {}", defaults.policy.reload_interval_secs);
+        println!();
+        println!("[peers]");
+        println!("# Browse _dns._udp.local. for other proxies on the link");
+        println!("# Default: {}", defaults.peers.discover);
+        println!("discover = {}", defaults.peers.discover);
+        println!();
+        println!("# Seconds between browses");
+        println!("# Default: {}", defaults.peers.discover_interval_secs);
+        println!("discover_interval_secs = {}", defaults.peers.discover_interval_secs);
+        println!();
+        println!("# Unicast DNS addresses of peer proxies, tried before discovered ones");
+        println!("# Default: []");
+        println!("# addresses = [\"192.168.1.3:53\"]");
+        println!();
+        println!("# Forward a query to a peer when the local mDNS query fails");
+        println!("# Default: {}", defaults.peers.forward_on_failure);
+        println!("forward_on_failure = {}", defaults.peers.forward_on_failure);
+        println!();
+        println!("# How long to wait for each peer in milliseconds");
+        println!("# Default: {}", defaults.peers.forward_timeout_ms);
+        println!("forward_timeout_ms = {}", defaults.peers.forward_timeout_ms);
+        println!();
+        println!("[cluster]");
+        println!("# Share cache entries with other instances behind the same anycast address or VIP");
+        println!("# Default: {}", defaults.cluster.enabled);
+        println!("enabled = {}", defaults.cluster.enabled);
+        println!();
+        println!("# UDP address cache gossip is received on");
+        println!("# Default: \"{}\"", defaults.cluster.listen);
+        println!("listen = \"{}\"", defaults.cluster.listen);
+        println!();
+        println!("# Gossip addresses of the other cluster members");
+        println!("# Default: []");
+        println!("# members = [\"192.168.1.3:5390\"]");
+        println!();
+        println!("# Base64 secret shared by every member; datagrams not signed with it are dropped.");
+        println!("# Required when enabled; generate one with e.g. `openssl rand -base64 32`");
+        println!("# secret = \"...\"");
+        println!();
+        println!("[network]");
+        println!("# Follow interface address changes: refresh this proxy's own address records");
+        println!("# and the networks treated as on-link (netlink on Linux, polling elsewhere)");
+        println!("# Default: {}", defaults.network.watch);
+        println!("watch = {}", defaults.network.watch);
+        println!();
+        println!("# Seconds between interface polls where netlink is not available");
+        println!("# Default: {}", defaults.network.poll_interval_secs);
+        println!("poll_interval_secs = {}", defaults.network.poll_interval_secs);
+        println!();
+        println!("# Drop cached address records in networks that went away");
+        println!("# Default: {}", defaults.network.flush_cache);
+        println!("flush_cache = {}", defaults.network.flush_cache);
+        println!();
+        println!("[resolution]");
+        println!("# Answer sources tried in order until one answers: cache, known (known_services),");
+        println!("# mdns (an empty mDNS answer counts as an answer) and peers (forwarding)");
+        println!("# Default: [\"cache\", \"known\", \"mdns\"], plus \"peers\" with peers.forward_on_failure");
+        println!("# chain = [\"cache\", \"known\", \"mdns\"]");
+        println!();
+        println!("# Chains for A/AAAA (address), PTR/SRV/TXT (service) and other queries, instead of chain");
+        println!("# address = [\"cache\", \"mdns\", \"peers\"]");
+        println!("# service = [\"known\", \"cache\", \"mdns\"]");
+        println!("# other = [\"cache\", \"mdns\"]");
+        println!();
+        println!("# Time limits of single steps in milliseconds; a step over its limit counts as failed");
+        println!("# [resolution.timeouts_ms]");
+        println!("# peers = 500");
+        println!();
+        println!("# Ordinary zones served from zone files next to the discovery zones (optional);");
+        println!("# the most specific zone containing a query name answers it");
+        println!("# [[authoritative.zones]]");
+        println!("# origin = \"corp.home.arpa.\"");
+        println!("# file = \"/etc/mdns-dns-proxy/corp.home.arpa.zone\"");
+        println!();
+        println!("[push]");
+        println!("# DNS Push Notifications (RFC 8765): clients SUBSCRIBE over a DSO session on the");
+        println!("# TCP listener or the Unix socket and are sent changes as mDNS sees them");
+        println!("# Default: {}", defaults.push.enabled);
+        println!("enabled = {}", defaults.push.enabled);
+        println!();
+        println!("# Inactivity timeout offered to clients: sessions without subscriptions quiet");
+        println!("# this long are closed");
+        println!("# Default: {}", defaults.push.inactivity_timeout_secs);
+        println!("inactivity_timeout_secs = {}", defaults.push.inactivity_timeout_secs);
+        println!();
+        println!("# Keepalive interval offered to clients: sessions with subscriptions quiet for");
+        println!("# twice this long are closed");
+        println!("# Default: {}", defaults.push.keepalive_interval_secs);
+        println!("keepalive_interval_secs = {}", defaults.push.keepalive_interval_secs);
+        println!();
+        println!("# Most subscriptions per session");
+        println!("# Default: {}", defaults.push.max_subscriptions);
+        println!("max_subscriptions = {}", defaults.push.max_subscriptions);
+        println!();
+        println!("# Seconds between re-resolutions of subscriptions not backed by an mDNS browse");
+        println!("# (addresses, subtypes and other types)");
+        println!("# Default: {}", defaults.push.refresh_secs);
+        println!("refresh_secs = {}", defaults.push.refresh_secs);
+        println!();
+        println!("[tsig]");
+        println!("# Interfaces and networks whose clients must sign queries with one of the keys");
+        println!("# below; unsigned queries from them are refused");
+        println!("# Default: none");
+        println!("# require_from = [\"eth2\", \"10.20.0.0/16\"]");
+        println!();
+        println!("# Most seconds a signature's time may differ from this host's clock; a query");
+        println!("# signed with a smaller fudge is held to that one");
+        println!("# Default: {}", defaults.tsig.fudge_secs);
+        println!("fudge_secs = {}", defaults.tsig.fudge_secs);
+        println!();
+        println!("# Keys for TSIG-signed queries (RFC 8945); algorithms: hmac-sha256, hmac-sha384,");
+        println!("# hmac-sha512. Signed queries are answered with signed responses.");
+        println!("# Default: none");
+        println!("# [[tsig.keys]]");
+        println!("# name = \"resolver1.example.\"");
+        println!("# algorithm = \"hmac-sha256\"");
+        println!("# secret = \"<base64 from tsig-keygen>\"");
+        println!();
+        println!("[axfr]");
+        println!("# Answer AXFR over TCP for the discovery zones with the records cached now, so a");
+        println!("# downstream authoritative server can mirror them");
+        println!("# Default: {}", defaults.axfr.enabled);
+        println!("enabled = {}", defaults.axfr.enabled);
+        println!("# Networks allowed to transfer (empty allows any client)");
+        println!("# Default: none");
+        println!("# allow_from = [\"192.168.1.53\", \"10.20.0.0/16\"]");
+        println!();
+        println!("[notify]");
+        println!("# Servers sent a DNS NOTIFY when the records of a discovery zone change, so they");
+        println!("# re-query or re-transfer promptly");
+        println!("# Default: none");
+        println!("# targets = [\"192.168.1.53:53\"]");
+        println!();
+        println!("# Seconds between checks of the discovery zones for changes");
+        println!("# Default: {}", defaults.notify.check_interval_secs);
+        println!("check_interval_secs = {}", defaults.notify.check_interval_secs);
+        println!();
+        println!("# Milliseconds to wait for a target to acknowledge, and times to resend");
+        println!("# Default: {} and {}", defaults.notify.timeout_ms, defaults.notify.retries);
+        println!("timeout_ms = {}", defaults.notify.timeout_ms);
+        println!("retries = {}", defaults.notify.retries);
+        println!();
+        println!("[slo]");
+        println!("# Track latency objectives over a rolling window: a share of cached answers and");
+        println!("# of answers that needed mDNS (or a peer) must each come within a target time");
+        println!("# Default: {}", defaults.slo.enabled);
+        println!("enabled = {}", defaults.slo.enabled);
+        println!();
+        println!("# Percentage and target for cached answers");
+        println!("# Default: {}% within {}ms", defaults.slo.cached_percent, defaults.slo.cached_target_ms);
+        println!("cached_percent = {:.1}", defaults.slo.cached_percent);
+        println!("cached_target_ms = {}", defaults.slo.cached_target_ms);
+        println!();
+        println!("# Percentage and target for uncached answers");
+        println!("# Default: {}% within {}ms", defaults.slo.uncached_percent, defaults.slo.uncached_target_ms);
+        println!("uncached_percent = {:.1}", defaults.slo.uncached_percent);
+        println!("uncached_target_ms = {}", defaults.slo.uncached_target_ms);
+        println!();
+        println!("# Seconds of queries compliance is computed over, and the fewest queries of a");
+        println!("# class in that window for it to be judged");
+        println!("# Default: {} and {}", defaults.slo.window_secs, defaults.slo.min_samples);
+        println!("window_secs = {}", defaults.slo.window_secs);
+        println!("min_samples = {}", defaults.slo.min_samples);
+        println!();
+        println!("# Seconds between checks for a breach starting or ending");
+        println!("# Default: {}", defaults.slo.check_interval_secs);
+        println!("check_interval_secs = {}", defaults.slo.check_interval_secs);
+        println!();
+        println!("# http:// URL sent a JSON POST when a breach starts or ends");
+        println!("# Default: unset");
+        println!("# webhook_url = \"http://alerts.home.arpa:9000/hooks/mdns-dns-proxy\"");
+        println!();
+        println!("[debug]");
+        println!("# Validate outgoing responses against RFC 8766 rules (development aid)");
+        println!("# Options: off, log (report violations), drop (report and remove offending records)");
+        println!("# Default: off");
+        println!("lint = \"off\"");
         println!();
-        println!("[logging]");
-        println!("# Log level for the application");
-        println!("# Options: trace, debug, info, warn, error");
-        println!("# Default: {}", defaults.logging.level);
-        println!("level = \"{}\"", defaults.logging.level);
+        println!("# Log debug detail for queries carrying EDNS option 65002, whatever the log level");
+        println!("# Default: {}", defaults.debug.query_tracing);
+        println!("query_tracing = {}", defaults.debug.query_tracing);
         println!();
-        println!("[mdns]");
-        println!("# Timeout for service queries (PTR/SRV/TXT) in milliseconds");
-        println!("# Default: {} ({} seconds)", defaults.mdns.service_query_timeout_ms, defaults.mdns.service_query_timeout_ms as f64 / 1000.0);
-        println!("service_query_timeout_ms = {}", defaults.mdns.service_query_timeout_ms);
+        println!("# Sort records by name, type and rdata so output is stable across runs (for tests and diffs)");
+        println!("# Default: {}", defaults.debug.deterministic_output);
+        println!("deterministic_output = {}", defaults.debug.deterministic_output);
         println!();
-        println!("# Per-event poll interval during service queries in milliseconds");
-        println!("# How frequently to check for new mDNS events");
-        println!("# Default: {} ({} ms)", defaults.mdns.service_poll_interval_ms, defaults.mdns.service_poll_interval_ms);
-        println!("service_poll_interval_ms = {}", defaults.mdns.service_poll_interval_ms);
+        println!("# Add a proxy-debug.<zone> TXT record to the additional section saying which");
+        println!("# source answered, whether it was a cache hit and how long it took");
+        println!("# Default: {}", defaults.debug.provenance_record);
+        println!("provenance_record = {}", defaults.debug.provenance_record);
         println!();
-        println!("# Timeout for hostname resolution (A/AAAA queries) in milliseconds");
-        println!("# How long to wait when resolving hostnames to IP addresses");
-        println!("# Default: {} ({} second)", defaults.mdns.hostname_resolution_timeout_ms, defaults.mdns.hostname_resolution_timeout_ms as f64 / 1000.0);
-        println!("hostname_resolution_timeout_ms = {}", defaults.mdns.hostname_resolution_timeout_ms);
+        println!("# Per-service-type resolution strategies (optional, one table per type)");
+        println!("# timeout_ms: overrides service_query_timeout_ms for this type");
+        println!("# prefetch: records (SRV, TXT, A, AAAA) cached from each resolved instance");
+        println!("# additional: records added to the additional section of PTR/SRV answers");
+        println!("# fresh: always query mDNS afresh and answer with TTL 0 (rate limited)");
+        println!("# oversized_txt, max_txt_bytes: override the [mdns] TXT size policy");
+        println!("# [strategies.\"_ipp._tcp\"]");
+        println!("# timeout_ms = 4000");
+        println!("# prefetch = [\"SRV\", \"TXT\"]");
+        println!("# additional = [\"SRV\", \"TXT\", \"A\", \"AAAA\"]");
+        println!();
+        println!("# Per-zone settings (optional, one table per zone apex)");
+        println!("# soa_minimum_secs: SOA MINIMUM and negative-caching TTL (default and maximum 10)");
+        println!("# [zones.\"mdns.home.arpa.\"]");
+        println!("# soa_minimum_secs = 5");
+        println!();
+        println!("# Virtual service groups (optional): a PTR query for <group>.<zone> is");
+        println!("# answered with the instances of every listed service type");
+        println!("# [service_groups]");
+        println!("# printers = [\"_ipp._tcp\", \"_printer._tcp\", \"_pdl-datastream._tcp\"]");
+        println!();
+        println!("# Per-link proxies (optional, one table per interface): each answers for its");
+        println!("# own discovery domain on a listener bound to the interface, from mDNS on");
+        println!("# that interface only. bind_address and port default");
+        println!("# to those of [server]");
+        println!("# [[links]]");
+        println!("# interface = \"eth1\"");
+        println!("# discovery_domain = \"lan1.mdns.home.arpa.\"");
+        println!();
+        println!("# Record-level ACLs (optional): clients in these networks only receive the");
+        println!("# listed record types (default PTR and TXT), so they see which services");
+        println!("# exist but not where to connect. The first matching entry applies; SOA");
+        println!("# and NS always pass");
+        println!("# [[record_acls]]");
+        println!("# clients = [\"192.168.50.0/24\"]");
+        println!("# allow = [\"PTR\", \"TXT\"]");
     }
     
+    /// Parse a configuration file: TOML, OpenWrt UCI, or flat `section.key=value` lines
+    pub fn parse(contents: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        if crate::uci::looks_like_uci(contents) {
+            return crate::uci::parse_uci(contents);
+        }
+        match toml::from_str(contents) {
+            Ok(config) => Ok(config),
+            // Not TOML; accept a flat key=value file, otherwise report the TOML error
+            Err(toml_err) => crate::uci::parse_flat(contents).map_err(|_| toml_err.into()),
+        }
+    }
+
     /// Load configuration from file, environment variables, and CLI arguments
     pub fn load(args: Args) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
         // Start with default config
         let mut config = if let Some(config_path) = &args.config {
             // Load from file
             let contents = std::fs::read_to_string(config_path)?;
-            toml::from_str(&contents)?
+            Self::parse(&contents)?
         } else {
             Config::default()
         };
@@ -302,8 +2373,8 @@ impl Config {
         config.server.discovery_domain = normalize_domain(&config.server.discovery_domain);
         
         // Override with CLI arguments
-        if let Some(bind_address) = args.bind_address {
-            config.server.bind_address = bind_address;
+        if !args.bind_address.is_empty() {
+            config.server.bind_address = args.bind_address;
         }
         
         if let Some(port) = args.port {
@@ -353,6 +2424,42 @@ impl Config {
         std::time::Duration::from_secs(self.cache.ttl_seconds)
     }
     
+    /// Configuration of the proxy serving `link`: its discovery domain, a
+    /// listener bound to its interface, and mDNS on that interface only
+    pub fn for_link(&self, link: &LinkConfig) -> Config {
+        let mut config = self.clone();
+        config.server.discovery_domain = normalize_domain(&link.discovery_domain);
+        if let Some(bind_address) = link.bind_address {
+            config.server.bind_address = vec![bind_address];
+        }
+        config.server.port = link.port.unwrap_or(self.server.port);
+        config.server.fallback_ports.clear();
+        config.server.bind_device = Some(link.interface.clone());
+        config.server.unix_socket_path = None;
+        config.server.tls = None;
+        config.mdns.interfaces = vec![link.interface.clone()];
+        config.links.clear();
+        config
+    }
+
+    /// Copy of this configuration with every mDNS query timeout capped for quiet hours
+    pub fn with_quiet_timeouts(&self) -> Config {
+        let cap = self.quiet_hours.query_timeout_ms;
+        let mut quiet = self.clone();
+        quiet.mdns.service_query_timeout_ms = quiet.mdns.service_query_timeout_ms.min(cap);
+        quiet.mdns.hostname_resolution_timeout_ms = quiet.mdns.hostname_resolution_timeout_ms.min(cap);
+        quiet.mdns.service_poll_interval_ms = quiet.mdns.service_poll_interval_ms.min(cap);
+        for strategy in quiet.strategies.values_mut() {
+            strategy.timeout_ms = strategy.timeout_ms.map(|t| t.min(cap));
+        }
+        quiet
+    }
+
+    /// Get the cache memory limit in bytes, if any
+    pub fn cache_memory_limit(&self) -> Option<usize> {
+        self.cache.memory_limit_kb.map(|kb| (kb as usize).saturating_mul(1024))
+    }
+
     /// Get service query timeout as Duration
     pub fn service_query_timeout(&self) -> std::time::Duration {
         std::time::Duration::from_millis(self.mdns.service_query_timeout_ms)
@@ -371,6 +2478,82 @@ impl Config {
     pub fn discovery_domain(&self) -> &str {
         &self.server.discovery_domain
     }
+
+    /// SOA MINIMUM of the zone at `apex`, which is also its negative-caching TTL in seconds
+    pub fn soa_minimum(&self, apex: &Name) -> u32 {
+        let apex = normalize_domain(&apex.to_utf8());
+        self.zones
+            .iter()
+            .find(|(k, _)| normalize_domain(k) == apex)
+            .map_or_else(default_soa_minimum, |(_, zone)| zone.soa_minimum_secs)
+            .min(MAX_SOA_MINIMUM)
+    }
+
+    /// Service types whose instances answer a PTR query for `name`, when it is a
+    /// configured service group in zone `apex`; each is a name in the zone
+    pub fn service_group(&self, name: &Name, apex: &Name) -> Option<Vec<Name>> {
+        if self.service_groups.is_empty() || !apex.zone_of(name) || name.num_labels() <= apex.num_labels() {
+            return None;
+        }
+        let below_apex = (name.num_labels() - apex.num_labels()) as usize;
+        let mut relative = Name::from_labels(name.iter().take(below_apex)).ok()?;
+        relative.set_fqdn(false);
+        let key = relative.to_utf8();
+        let (_, members) = self
+            .service_groups
+            .iter()
+            .find(|(k, _)| k.trim_end_matches('.').eq_ignore_ascii_case(&key))?;
+        Some(
+            members
+                .iter()
+                .filter_map(|member| Name::from_utf8(member.trim_end_matches('.')).ok()?.append_domain(apex).ok())
+                .collect(),
+        )
+    }
+
+    /// Steps tried, in order, to answer a query of `record_type`
+    pub fn resolution_chain(&self, record_type: RecordType) -> Vec<ResolutionStep> {
+        let per_class = match record_type {
+            RecordType::A | RecordType::AAAA => &self.resolution.address,
+            RecordType::PTR | RecordType::SRV | RecordType::TXT => &self.resolution.service,
+            _ => &self.resolution.other,
+        };
+        if let Some(steps) = per_class.as_ref().or(self.resolution.chain.as_ref()) {
+            return steps.clone();
+        }
+        let mut steps = vec![ResolutionStep::Cache, ResolutionStep::Known, ResolutionStep::Mdns];
+        if self.peers.forward_on_failure {
+            steps.push(ResolutionStep::Peers);
+        }
+        steps
+    }
+
+    /// Resolution strategy for the service type contained in `name`, if one is configured
+    pub fn strategy_for(&self, name: &str) -> Option<&ServiceStrategy> {
+        let key = service_type_key(name)?;
+        self.strategies
+            .iter()
+            .find(|(k, _)| service_type_key(k).as_deref() == Some(key.as_str()))
+            .map(|(_, strategy)| strategy)
+    }
+
+    /// TXT size policy and total cap for instances named like `name`, honoring any
+    /// per-type strategy override
+    pub fn txt_size_for(&self, name: &str) -> (OversizedTxt, usize) {
+        let strategy = self.strategy_for(name);
+        (
+            strategy.and_then(|s| s.oversized_txt).unwrap_or(self.mdns.oversized_txt),
+            strategy.and_then(|s| s.max_txt_bytes).unwrap_or(self.mdns.max_txt_bytes).get(),
+        )
+    }
+
+    /// Service query timeout for `name`, honoring any per-type strategy override
+    pub fn service_query_timeout_for(&self, name: &str) -> std::time::Duration {
+        self.strategy_for(name)
+            .and_then(|s| s.timeout_ms)
+            .map(std::time::Duration::from_millis)
+            .unwrap_or_else(|| self.service_query_timeout())
+    }
 }
 
 #[cfg(test)]
@@ -437,6 +2620,7 @@ mod tests {
         assert_eq!(mdns.service_query_timeout_ms, default_service_query_timeout());
         assert_eq!(mdns.service_poll_interval_ms, default_service_poll_interval());
         assert_eq!(mdns.hostname_resolution_timeout_ms, default_hostname_resolution_timeout());
+        assert!(mdns.co_resolve);
     }
 
     #[test]
@@ -527,7 +2711,7 @@ mod tests {
         let config: Config = toml::from_str(toml_str).unwrap();
         assert_eq!(config.server.port, 5354);
         // Other values should be defaults
-        assert_eq!(config.server.bind_address, IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)));
+        assert_eq!(config.server.bind_address, default_bind_address());
         assert_eq!(config.cache.ttl_seconds, 120);
     }
 
@@ -550,10 +2734,16 @@ mod tests {
             [mdns]
             service_query_timeout_ms = 1500
             hostname_resolution_timeout_ms = 3000
+            co_resolve = false
+            answer_bundles = false
+            address_family_prefetch = false
+            https_records = false
+            reverse_lookups = false
+            service_types_file = "/var/lib/mdns-dns-proxy/service-names-port-numbers.csv"
         "#;
         
         let config: Config = toml::from_str(toml_str).unwrap();
-        assert_eq!(config.server.bind_address, IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)));
+        assert_eq!(config.server.bind_address, vec![IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0))]);
         assert_eq!(config.server.port, 5354);
         assert_eq!(config.server.tcp_timeout, 60);
         assert_eq!(config.server.discovery_domain, "Example.COM");
@@ -562,6 +2752,428 @@ mod tests {
         assert_eq!(config.logging.level, "trace");
         assert_eq!(config.mdns.service_query_timeout_ms, 1500);
         assert_eq!(config.mdns.hostname_resolution_timeout_ms, 3000);
+        assert!(!config.mdns.co_resolve);
+        assert!(!config.mdns.answer_bundles);
+        assert!(Config::default().mdns.answer_bundles);
+        assert!(!config.mdns.address_family_prefetch);
+        assert!(Config::default().mdns.address_family_prefetch);
+        assert!(!config.mdns.https_records);
+        assert!(Config::default().mdns.https_records);
+        assert!(!config.mdns.reverse_lookups);
+        assert!(Config::default().mdns.reverse_lookups);
+        assert_eq!(
+            config.mdns.service_types_file,
+            Some(PathBuf::from("/var/lib/mdns-dns-proxy/service-names-port-numbers.csv"))
+        );
+        assert_eq!(Config::default().mdns.service_types_file, None);
+    }
+
+    #[test]
+    fn test_toml_known_services() {
+        let config = Config::parse(
+            r#"
+            [known_services]
+            verify_interval_secs = 60
+            journal = "/var/lib/mdns-dns-proxy/known.journal"
+
+            [[known_services.services]]
+            type = "_ipp._tcp"
+            name = "Office Printer"
+            host = "printer"
+            port = 631
+            txt = ["rp=ipp/print", "Color"]
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config.known_services.verify_interval_secs, 60);
+        assert_eq!(config.known_services.journal, Some(PathBuf::from("/var/lib/mdns-dns-proxy/known.journal")));
+        assert_eq!(
+            config.known_services.services,
+            vec![KnownService {
+                service_type: "_ipp._tcp".to_string(),
+                name: "Office Printer".to_string(),
+                host: "printer".to_string(),
+                port: 631,
+                txt: vec!["rp=ipp/print".to_string(), "Color".to_string()],
+            }]
+        );
+        assert!(Config::default().known_services.services.is_empty());
+    }
+
+    #[test]
+    fn test_toml_peers() {
+        let config = Config::parse(
+            r#"
+            [peers]
+            discover = true
+            addresses = ["192.168.1.3:53"]
+            forward_on_failure = true
+        "#,
+        )
+        .unwrap();
+        assert!(config.peers.discover);
+        assert!(config.peers.forward_on_failure);
+        assert_eq!(config.peers.addresses, vec!["192.168.1.3:53".parse::<SocketAddr>().unwrap()]);
+        assert_eq!(config.peers.discover_interval_secs, 300);
+        assert_eq!(config.peers.forward_timeout_ms, 1000);
+    }
+
+    #[test]
+    fn test_toml_cluster() {
+        let config = Config::parse(
+            r#"
+            [cluster]
+            enabled = true
+            members = ["192.168.1.3:5390", "192.168.1.4:5390"]
+            secret = "c2hhcmVkIGNsdXN0ZXIgc2VjcmV0"
+        "#,
+        )
+        .unwrap();
+        assert!(config.cluster.enabled);
+        assert_eq!(config.cluster.members.len(), 2);
+        assert_eq!(config.cluster.secret, "c2hhcmVkIGNsdXN0ZXIgc2VjcmV0");
+        assert_eq!(config.cluster.listen, "0.0.0.0:5390".parse::<SocketAddr>().unwrap());
+        assert!(!Config::default().cluster.enabled);
+    }
+
+    #[test]
+    fn test_toml_peer_proxies() {
+        let config = Config::parse("[server]\npeer_proxies = [\"proxy2.home.arpa.\", \"proxy3.home.arpa\"]").unwrap();
+        assert_eq!(config.server.peer_proxies, vec!["proxy2.home.arpa.", "proxy3.home.arpa"]);
+        assert!(Config::default().server.peer_proxies.is_empty());
+    }
+
+    #[test]
+    fn test_toml_own_address_records() {
+        let config = Config::parse("[server]\nown_address_records = false").unwrap();
+        assert!(!config.server.own_address_records);
+        assert!(Config::default().server.own_address_records);
+    }
+
+    #[test]
+    fn test_toml_udp_tuning() {
+        let config = Config::parse("[server]\nudp_sockets = 4\nudp_recv_buffer_bytes = 1048576").unwrap();
+        assert_eq!(config.server.udp_sockets, 4);
+        assert_eq!(config.server.udp_recv_buffer_bytes, Some(1_048_576));
+        assert_eq!(Config::default().server.udp_sockets, 1);
+        assert_eq!(Config::default().server.udp_recv_buffer_bytes, None);
+    }
+
+    #[test]
+    fn test_toml_tcp_limits() {
+        let config = Config::parse(
+            "[server]\ntcp_max_message_bytes = 1232\ntcp_max_questions = 2\ntcp_read_timeout_ms = 800\ndrain_timeout_secs = 30",
+        )
+        .unwrap();
+        assert_eq!(config.server.tcp_max_message_bytes, 1232);
+        assert_eq!(config.server.tcp_max_questions, 2);
+        assert_eq!(config.server.tcp_read_timeout_ms, 800);
+        assert_eq!(config.server.drain_timeout_secs, 30);
+        let defaults = Config::default().server;
+        assert_eq!((defaults.tcp_max_message_bytes, defaults.tcp_max_questions), (4096, 1));
+        assert_eq!(defaults.tcp_read_timeout_ms, 5000);
+        assert_eq!(defaults.drain_timeout_secs, 10);
+    }
+
+    #[test]
+    fn test_toml_query_limits() {
+        let config = Config::parse(
+            "[server]\nmax_concurrent_queries = 8\nquery_queue_length = 0\nquery_queue_timeout_ms = \"2s\"\n\
+             overload_response = \"refused\"",
+        )
+        .unwrap();
+        assert_eq!(config.server.max_concurrent_queries, 8);
+        assert_eq!(config.server.query_queue_length, 0);
+        assert_eq!(config.server.query_queue_timeout_ms, 2000);
+        assert_eq!(config.server.overload_response, OverloadResponse::Refused);
+        let defaults = Config::default().server;
+        assert_eq!((defaults.max_concurrent_queries, defaults.query_queue_length), (256, 1024));
+        assert_eq!(defaults.query_queue_timeout_ms, 1000);
+        assert_eq!(defaults.overload_response, OverloadResponse::ServFail);
+        assert!(Config::parse("[server]\noverload_response = \"drop\"").is_err());
+    }
+
+    #[test]
+    fn test_toml_unix_socket_path() {
+        let config = Config::parse("[server]\nunix_socket_path = \"/run/proxy/dns.sock\"").unwrap();
+        assert_eq!(config.server.unix_socket_path, Some(PathBuf::from("/run/proxy/dns.sock")));
+        assert!(Config::default().server.unix_socket_path.is_none());
+    }
+
+    #[test]
+    fn test_toml_edns_udp_payload() {
+        let config = Config::parse("[server]\nedns_udp_payload = 4096").unwrap();
+        assert_eq!(config.server.edns_udp_payload, 4096);
+        assert_eq!(Config::default().server.edns_udp_payload, 1232);
+    }
+
+    #[test]
+    fn test_toml_off_link_local_queries() {
+        let config = Config::parse("[server]\noff_link_local_queries = \"refused\"").unwrap();
+        assert_eq!(config.server.off_link_local_queries, OffLinkLocalQueries::Refused);
+        assert_eq!(Config::default().server.off_link_local_queries, OffLinkLocalQueries::NxDomain);
+        assert!(Config::parse("[server]\noff_link_local_queries = \"servfail\"").is_err());
+    }
+
+    #[test]
+    fn test_toml_server_tls() {
+        let config = Config::parse("[server.tls]\ncert_file = \"/etc/dot/cert.pem\"\nkey_file = \"/etc/dot/key.pem\"").unwrap();
+        let tls = config.server.tls.unwrap();
+        assert_eq!(tls.port, 853);
+        assert_eq!(tls.cert_file, PathBuf::from("/etc/dot/cert.pem"));
+        assert_eq!(tls.padding, EdnsPadding::Requested);
+        assert_eq!(tls.padding_block_bytes, 468);
+        assert_eq!(tls.client_ca_file, None);
+        assert_eq!(Config::default().server.tls, None);
+
+        // Both files are required
+        assert!(Config::parse("[server.tls]\ncert_file = \"/etc/dot/cert.pem\"").is_err());
+
+        let config = Config::parse(
+            "[server.tls]\ncert_file = \"c.pem\"\nkey_file = \"k.pem\"\npadding = \"always\"\npadding_block_bytes = 128\n\
+             client_ca_file = \"ca.pem\"",
+        )
+        .unwrap();
+        let tls = config.server.tls.unwrap();
+        assert_eq!(tls.client_ca_file, Some(PathBuf::from("ca.pem")));
+        assert_eq!(tls.padding, EdnsPadding::Always);
+        assert_eq!(tls.padding_block_bytes, 128);
+    }
+
+    #[test]
+    fn test_toml_bind_device() {
+        let config = Config::parse("[server]\nbind_address = \"0.0.0.0\"\nbind_device = \"vrf-mgmt\"").unwrap();
+        assert_eq!(config.server.bind_device.as_deref(), Some("vrf-mgmt"));
+        assert_eq!(Config::default().server.bind_device, None);
+    }
+
+    #[test]
+    fn test_toml_ttl_jitter() {
+        let config = Config::parse("[server]\nttl_jitter_secs = 3").unwrap();
+        assert_eq!(config.server.ttl_jitter_secs, 3);
+        assert_eq!(Config::default().server.ttl_jitter_secs, 0);
+    }
+
+    #[test]
+    fn test_toml_ttl_floor() {
+        let config = Config::parse("[server]\nttl_floor_secs = 5").unwrap();
+        assert_eq!(config.server.ttl_floor_secs.secs(), 5);
+        assert_eq!(Config::default().server.ttl_floor_secs.secs(), 0);
+        let err = Config::parse("[server]\nttl_floor_secs = 11").unwrap_err();
+        assert!(err.to_string().contains("above the 10s TTL cap"));
+        let serialized = toml::to_string(&config).unwrap();
+        assert!(serialized.contains("ttl_floor_secs = 5"));
+    }
+
+    #[test]
+    fn test_toml_policy() {
+        let config = Config::parse("[policy]\nrpz_file = \"/etc/mdns-dns-proxy/policy.rpz\"").unwrap();
+        assert_eq!(config.policy.rpz_file, Some(PathBuf::from("/etc/mdns-dns-proxy/policy.rpz")));
+        assert_eq!(config.policy.reload_interval_secs, 30);
+        assert_eq!(Config::default().policy.rpz_file, None);
+    }
+
+    #[test]
+    fn test_toml_network() {
+        let config = Config::parse("[network]\nflush_cache = true\npoll_interval_secs = 5").unwrap();
+        assert!(config.network.watch);
+        assert!(config.network.flush_cache);
+        assert_eq!(config.network.poll_interval_secs, 5);
+        assert!(!Config::default().network.flush_cache);
+    }
+
+    #[test]
+    fn test_toml_authoritative_zones() {
+        let config = Config::parse(
+            r#"
+            [[authoritative.zones]]
+            origin = "corp.home.arpa."
+            file = "/etc/mdns-dns-proxy/corp.zone"
+        "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.authoritative.zones,
+            vec![AuthoritativeZone {
+                origin: "corp.home.arpa.".to_string(),
+                file: PathBuf::from("/etc/mdns-dns-proxy/corp.zone"),
+            }]
+        );
+        assert!(Config::default().authoritative.zones.is_empty());
+    }
+
+    #[test]
+    fn test_toml_resolution_chain() {
+        use ResolutionStep::*;
+
+        let default = Config::default();
+        assert_eq!(default.resolution_chain(RecordType::A), vec![Cache, Known, Mdns]);
+
+        let config = Config::parse(
+            r#"
+            [peers]
+            forward_on_failure = true
+
+            [resolution]
+            address = ["mdns", "cache"]
+
+            [resolution.timeouts_ms]
+            peers = 500
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config.resolution_chain(RecordType::AAAA), vec![Mdns, Cache]);
+        assert_eq!(config.resolution_chain(RecordType::PTR), vec![Cache, Known, Mdns, Peers]);
+        assert_eq!(config.resolution.timeouts_ms.get(&Peers), Some(&500));
+
+        let config = Config::parse("[resolution]\nchain = [\"cache\", \"peers\"]\nservice = [\"known\"]").unwrap();
+        assert_eq!(config.resolution_chain(RecordType::TXT), vec![Known]);
+        assert_eq!(config.resolution_chain(RecordType::HINFO), vec![Cache, Peers]);
+        assert!(config.resolution.mentions(Peers));
+
+        assert!(Config::parse("[resolution]\nchain = [\"cache\", \"llmnr\"]").is_err());
+    }
+
+    #[test]
+    fn test_toml_push() {
+        let config = Config::parse("[push]\nenabled = true\nmax_subscriptions = 8").unwrap();
+        assert!(config.push.enabled);
+        assert_eq!(config.push.max_subscriptions, 8);
+        assert_eq!(config.push.keepalive_interval_secs, 3600);
+        assert_eq!(config.push.inactivity_timeout_secs, 15);
+        assert!(!Config::default().push.enabled);
+    }
+
+    #[test]
+    fn test_toml_axfr() {
+        let config = Config::parse("[axfr]\nenabled = true\nallow_from = [\"192.168.1.53\"]").unwrap();
+        assert!(config.axfr.enabled);
+        assert_eq!(config.axfr.allow_from, vec!["192.168.1.53"]);
+        assert!(!Config::default().axfr.enabled);
+    }
+
+    #[test]
+    fn test_toml_notify() {
+        let config = Config::parse("[notify]\ntargets = [\"192.168.1.53:53\"]\nretries = 1").unwrap();
+        assert_eq!(config.notify.targets, vec!["192.168.1.53:53".parse::<SocketAddr>().unwrap()]);
+        assert_eq!(config.notify.retries, 1);
+        assert_eq!(config.notify.check_interval_secs, 10);
+        assert!(Config::default().notify.targets.is_empty());
+    }
+
+    #[test]
+    fn test_toml_slo() {
+        let config = Config::parse(
+            "[slo]\nenabled = true\nuncached_target_ms = \"2s\"\nwindow_secs = \"10m\"\n\
+             webhook_url = \"http://alerts.home.arpa:9000/hook\"",
+        )
+        .unwrap();
+        assert!(config.slo.enabled);
+        assert_eq!(config.slo.uncached_target_ms, 2000);
+        assert_eq!(config.slo.window_secs, 600);
+        assert_eq!(config.slo.cached_percent, 99.0);
+        assert_eq!(config.slo.webhook_url.as_deref(), Some("http://alerts.home.arpa:9000/hook"));
+        assert!(!Config::default().slo.enabled);
+    }
+
+    #[test]
+    fn test_toml_durations() {
+        let config = Config::parse(
+            r#"
+            [server]
+            tcp_timeout = "2m"
+            tcp_read_timeout_ms = 750
+            ttl_floor_secs = "5s"
+
+            [mdns]
+            service_query_timeout_ms = "2s"
+            hostname_resolution_timeout_ms = "1s 500ms"
+
+            [audit]
+            retention_days = "2d"
+
+            [resolution.timeouts_ms]
+            mdns = "3s"
+
+            [strategies."_ipp._tcp"]
+            timeout_ms = "5s"
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config.server.tcp_timeout, 120);
+        assert_eq!(config.server.tcp_read_timeout_ms, 750);
+        assert_eq!(config.server.ttl_floor_secs.secs(), 5);
+        assert_eq!(config.mdns.service_query_timeout_ms, 2_000);
+        assert_eq!(config.mdns.hostname_resolution_timeout_ms, 1_500);
+        assert_eq!(config.audit.retention_days, 2);
+        assert_eq!(config.resolution.timeouts_ms[&ResolutionStep::Mdns], 3_000);
+        assert_eq!(config.strategies["_ipp._tcp"].timeout_ms, Some(5_000));
+
+        // Values that could be read more than one way are refused, not guessed at
+        let error = Config::parse("[server]\ndrain_timeout_secs = \"1500ms\"").unwrap_err().to_string();
+        assert!(error.contains("whole number of seconds"), "{}", error);
+        assert!(Config::parse("[server]\ntcp_timeout = \"30\"").is_err());
+        assert!(Config::parse("[mdns]\nchange_debounce_secs = \"5M\"").is_err());
+        assert!(Config::parse("[server]\nttl_floor_secs = \"1m\"").is_err());
+        assert!(Config::parse("[tsig]\nfudge_secs = \"1d\"").is_err());
+        assert!(Config::parse("[server]\ntcp_timeout = -1").is_err());
+    }
+
+    #[test]
+    fn test_toml_tsig() {
+        let config = Config::parse(
+            r#"
+            [tsig]
+            require_from = ["eth2", "10.20.0.0/16"]
+
+            [[tsig.keys]]
+            name = "resolver1.example."
+            algorithm = "hmac-sha256"
+            secret = "c2VjcmV0"
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config.tsig.require_from, vec!["eth2", "10.20.0.0/16"]);
+        assert_eq!(config.tsig.keys[0].algorithm, TsigAlgorithm::HmacSha256);
+        assert_eq!(config.tsig.fudge_secs, 300);
+        assert!(Config::parse("[[tsig.keys]]\nname = \"k.\"\nalgorithm = \"hmac-md5\"\nsecret = \"\"").is_err());
+        assert!(Config::default().tsig.keys.is_empty());
+    }
+
+    #[test]
+    fn test_toml_liveness() {
+        let config = Config::parse(
+            r#"
+            [liveness]
+            enabled = true
+            filter_dead = true
+        "#,
+        )
+        .unwrap();
+        assert!(config.liveness.enabled);
+        assert!(config.liveness.filter_dead);
+        assert_eq!(config.liveness.interval_secs, 600);
+        assert_eq!(config.liveness.timeout_ms, 1000);
+    }
+
+    #[test]
+    fn test_toml_wake() {
+        let config = Config::parse(
+            r#"
+            [wake]
+            enabled = true
+            broadcast_address = "192.168.1.255:9"
+
+            [[wake.devices]]
+            host = "nas"
+            mac = "00:11:32:aa:bb:cc"
+        "#,
+        )
+        .unwrap();
+        assert!(config.wake.enabled);
+        assert_eq!(config.wake.broadcast_address, "192.168.1.255:9".parse::<SocketAddr>().unwrap());
+        assert_eq!(config.wake.min_interval_secs, 60);
+        assert_eq!(config.wake.devices[0].mac, "00:11:32:aa:bb:cc");
     }
 
     #[test]
@@ -573,7 +3185,12 @@ mod tests {
         "#;
         
         let config: Config = toml::from_str(toml_str).unwrap();
-        assert!(matches!(config.server.bind_address, IpAddr::V6(_)));
+        assert!(matches!(config.server.bind_address[..], [IpAddr::V6(_)]));
+
+        let config: Config = toml::from_str("[server]\nbind_address = [\"192.168.1.1\", \"fd00::1\"]").unwrap();
+        assert_eq!(config.server.bind_address, vec![IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)), "fd00::1".parse::<IpAddr>().unwrap()]);
+        assert_eq!(config.server.local_address(), IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)));
+        assert!(toml::from_str::<Config>("[server]\nbind_address = []").is_err());
     }
 
     #[test]
@@ -598,11 +3215,9 @@ mod tests {
 
     #[test]
     fn test_config_load_with_defaults() {
-        use std::net::Ipv4Addr;
-        
         let args = Args {
             config: None,
-            bind_address: None,
+            bind_address: Vec::new(),
             port: None,
             cache_ttl: None,
             no_cache: false,
@@ -611,10 +3226,11 @@ mod tests {
             hostname_resolution_timeout: None,
             discovery_domain: None,
             print_example_config: false,
+            command: None,
         };
         
         let config = Config::load(args).unwrap();
-        assert_eq!(config.server.bind_address, IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)));
+        assert_eq!(config.server.bind_address, default_bind_address());
         assert_eq!(config.server.port, 5335);
         assert_eq!(config.cache.ttl_seconds, 120);
         assert!(config.cache.enabled);
@@ -627,7 +3243,7 @@ mod tests {
         
         let args = Args {
             config: None,
-            bind_address: Some(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0))),
+            bind_address: vec![IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0))],
             port: Some(5354),
             cache_ttl: Some(300),
             no_cache: true,
@@ -636,10 +3252,11 @@ mod tests {
             hostname_resolution_timeout: Some(1500),
             discovery_domain: Some("Custom.Domain".to_string()),
             print_example_config: false,
+            command: None,
         };
         
         let config = Config::load(args).unwrap();
-        assert_eq!(config.server.bind_address, IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)));
+        assert_eq!(config.server.bind_address, vec![IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0))]);
         assert_eq!(config.server.port, 5354);
         assert_eq!(config.cache.ttl_seconds, 300);
         assert!(!config.cache.enabled);
@@ -669,7 +3286,7 @@ mod tests {
         
         let args = Args {
             config: Some(path),
-            bind_address: None,
+            bind_address: Vec::new(),
             port: None,
             cache_ttl: None,
             no_cache: false,
@@ -678,6 +3295,7 @@ mod tests {
             hostname_resolution_timeout: None,
             discovery_domain: None,
             print_example_config: false,
+            command: None,
         };
         
         let config = Config::load(args).unwrap();
@@ -701,7 +3319,7 @@ mod tests {
         
         let args = Args {
             config: Some(path),
-            bind_address: None,
+            bind_address: Vec::new(),
             port: Some(5356), // CLI override
             cache_ttl: None,
             no_cache: false,
@@ -710,6 +3328,7 @@ mod tests {
             hostname_resolution_timeout: None,
             discovery_domain: None,
             print_example_config: false,
+            command: None,
         };
         
         let config = Config::load(args).unwrap();
@@ -723,7 +3342,7 @@ mod tests {
         
         let args = Args {
             config: Some(PathBuf::from("/nonexistent/file.toml")),
-            bind_address: None,
+            bind_address: Vec::new(),
             port: None,
             cache_ttl: None,
             no_cache: false,
@@ -732,6 +3351,7 @@ mod tests {
             hostname_resolution_timeout: None,
             discovery_domain: None,
             print_example_config: false,
+            command: None,
         };
         
         let result = Config::load(args);
@@ -742,7 +3362,7 @@ mod tests {
     fn test_config_load_partial_cli_overrides() {
         let args = Args {
             config: None,
-            bind_address: None,
+            bind_address: Vec::new(),
             port: Some(5354),
             cache_ttl: None,
             no_cache: false,
@@ -751,6 +3371,7 @@ mod tests {
             hostname_resolution_timeout: None,
             discovery_domain: None,
             print_example_config: false,
+            command: None,
         };
         
         let config = Config::load(args).unwrap();
@@ -761,6 +3382,318 @@ mod tests {
         assert!(config.cache.enabled);
     }
 
+    #[test]
+    fn test_toml_service_strategies() {
+        let toml_str = r#"
+            [strategies."_ipp._tcp"]
+            timeout_ms = 4000
+            prefetch = ["SRV", "txt"]
+            additional = ["SRV", "TXT", "A", "AAAA"]
+
+            [strategies."_googlecast._tcp"]
+            prefetch = ["TXT"]
+            fresh = true
+        "#;
+
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let ipp = config.strategy_for("_ipp._tcp.local.").unwrap();
+        assert_eq!(ipp.timeout_ms, Some(4000));
+        assert_eq!(ipp.prefetch, vec![ServiceRecordKind::Srv, ServiceRecordKind::Txt]);
+        assert_eq!(ipp.additional.len(), 4);
+
+        let cast = config.strategy_for("Living Room._googlecast._tcp.mdns.home.arpa.").unwrap();
+        assert_eq!(cast.timeout_ms, None);
+        assert_eq!(cast.prefetch, vec![ServiceRecordKind::Txt]);
+        assert!(cast.fresh && !ipp.fresh);
+
+        assert!(config.strategy_for("_http._tcp.local.").is_none());
+    }
+
+    #[test]
+    fn test_service_query_timeout_for_strategy_override() {
+        let mut config = Config::default();
+        config.mdns.service_query_timeout_ms = 2000;
+        config.strategies.insert(
+            "_IPP._tcp.local.".to_string(),
+            ServiceStrategy {
+                timeout_ms: Some(5000),
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(config.service_query_timeout_for("_ipp._tcp.local."), Duration::from_millis(5000));
+        assert_eq!(config.service_query_timeout_for("Printer._ipp._tcp.local."), Duration::from_millis(5000));
+        assert_eq!(config.service_query_timeout_for("_http._tcp.local."), Duration::from_millis(2000));
+    }
+
+    #[test]
+    fn test_parse_detects_format() {
+        let toml_config = Config::parse("[server]\nport = 53").unwrap();
+        assert_eq!(toml_config.server.port, 53);
+
+        let uci_config = Config::parse("config server 'server'\n\toption port '54'").unwrap();
+        assert_eq!(uci_config.server.port, 54);
+
+        let flat_config = Config::parse("server.bind_address=0.0.0.0\nserver.port=55").unwrap();
+        assert_eq!(flat_config.server.port, 55);
+
+        // Broken TOML that is not a flat file either reports the TOML error
+        let err = Config::parse("[server\nport = 53").unwrap_err();
+        assert!(err.to_string().contains("TOML parse error"));
+    }
+
+    #[test]
+    fn test_cache_memory_limit() {
+        let config: Config = toml::from_str("[cache]\nmemory_limit_kb = 2048").unwrap();
+        assert_eq!(config.cache_memory_limit(), Some(2048 * 1024));
+        assert_eq!(Config::default().cache_memory_limit(), None);
+    }
+
+    #[test]
+    fn test_toml_runtime_sizing() {
+        let config: Config = toml::from_str("[server]\nworker_threads = 2\nmax_blocking_threads = 8").unwrap();
+        assert_eq!(config.server.worker_threads, NonZeroUsize::new(2));
+        assert_eq!(config.server.max_blocking_threads, NonZeroUsize::new(8));
+
+        let config = Config::default();
+        assert_eq!(config.server.worker_threads, None);
+        assert_eq!(config.server.max_blocking_threads, None);
+    }
+
+    #[test]
+    fn test_toml_admin_control_socket() {
+        let config: Config = toml::from_str("[admin]\ncontrol_socket = \"/run/proxy.sock\"").unwrap();
+        assert_eq!(config.admin.control_socket, Some(PathBuf::from("/run/proxy.sock")));
+        assert!(Config::default().admin.control_socket.is_none());
+    }
+
+    #[test]
+    fn test_toml_admin_api_keys() {
+        let config: Config = toml::from_str(
+            "[admin]\ncontrol_socket_mode = 0o666\n\
+             [[admin.api_keys]]\nname = \"dashboard\"\ntoken = \"s3cret\"\nscopes = [\"read\", \"flush\"]",
+        )
+        .unwrap();
+        assert_eq!(config.admin.control_socket_mode, 0o666);
+        assert_eq!(
+            config.admin.api_keys,
+            vec![ApiKey {
+                name: "dashboard".to_string(),
+                token: "s3cret".to_string(),
+                scopes: vec![ApiScope::Read, ApiScope::Flush],
+            }]
+        );
+        assert!(toml::from_str::<Config>("[[admin.api_keys]]\nname = \"x\"\ntoken = \"y\"\nscopes = [\"admin\"]").is_err());
+        assert_eq!(Config::default().admin.control_socket_mode, 0o600);
+        assert!(Config::default().admin.api_keys.is_empty());
+    }
+
+    #[test]
+    fn test_toml_audit() {
+        let config: Config = toml::from_str("[audit]\nenabled = true\nsalt = \"pepper\"").unwrap();
+        assert!(config.audit.enabled);
+        assert_eq!(config.audit.salt.as_deref(), Some("pepper"));
+        assert_eq!(config.audit.retention_days, default_audit_retention_days());
+        assert!(!Config::default().audit.enabled);
+    }
+
+    #[test]
+    fn test_with_quiet_timeouts_caps_every_timeout() {
+        let config: Config = toml::from_str(
+            "[quiet_hours]\nquery_timeout_ms = 100\n[strategies.\"_ipp._tcp\"]\ntimeout_ms = 4000",
+        )
+        .unwrap();
+        let quiet = config.with_quiet_timeouts();
+        assert_eq!(quiet.mdns.service_query_timeout_ms, 100);
+        assert_eq!(quiet.mdns.hostname_resolution_timeout_ms, 100);
+        assert_eq!(quiet.strategies["_ipp._tcp"].timeout_ms, Some(100));
+        assert_eq!(config.strategies["_ipp._tcp"].timeout_ms, Some(4000));
+    }
+
+    #[test]
+    fn test_toml_debug_lint_mode() {
+        let config: Config = toml::from_str("[debug]\nlint = \"drop\"").unwrap();
+        assert_eq!(config.debug.lint, LintMode::Drop);
+
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.debug.lint, LintMode::Off);
+
+        assert!(toml::from_str::<Config>("[debug]\nlint = \"loud\"").is_err());
+    }
+
+    #[test]
+    fn test_toml_debug_query_tracing() {
+        let config: Config = toml::from_str("[debug]\nquery_tracing = true").unwrap();
+        assert!(config.debug.query_tracing);
+        assert_eq!(config.debug.lint, LintMode::Off);
+        assert!(!Config::default().debug.query_tracing);
+    }
+
+    #[test]
+    fn test_toml_nsec_records() {
+        let config: Config = toml::from_str("[server]\nnsec_records = false").unwrap();
+        assert!(!config.server.nsec_records);
+        assert!(Config::default().server.nsec_records);
+    }
+
+    #[test]
+    fn test_toml_debug_deterministic_output() {
+        let config: Config = toml::from_str("[debug]\ndeterministic_output = true").unwrap();
+        assert!(config.debug.deterministic_output);
+        assert!(!Config::default().debug.deterministic_output);
+    }
+
+    #[test]
+    fn test_toml_debug_provenance_record() {
+        let config: Config = toml::from_str("[debug]\nprovenance_record = true").unwrap();
+        assert!(config.debug.provenance_record);
+        assert!(!Config::default().debug.provenance_record);
+    }
+
+    #[test]
+    fn test_toml_fresh_queries_per_minute() {
+        let config: Config = toml::from_str("[mdns]\nfresh_queries_per_minute = 0").unwrap();
+        assert_eq!(config.mdns.fresh_queries_per_minute, 0);
+        assert_eq!(Config::default().mdns.fresh_queries_per_minute, 30);
+    }
+
+    #[test]
+    fn test_toml_browse_max_age_secs() {
+        let config: Config = toml::from_str("[mdns]\nbrowse_max_age_secs = 0").unwrap();
+        assert_eq!(config.mdns.browse_max_age_secs, 0);
+        assert_eq!(Config::default().mdns.browse_max_age_secs, 3600);
+    }
+
+    #[test]
+    fn test_toml_lazy_start() {
+        let config: Config = toml::from_str("[mdns]\nlazy_start = true\nstart_retry_secs = 5").unwrap();
+        assert!(config.mdns.lazy_start);
+        assert_eq!(config.mdns.start_retry_secs, 5);
+        assert!(!Config::default().mdns.lazy_start);
+        assert_eq!(Config::default().mdns.start_retry_secs, 30);
+    }
+
+    #[test]
+    fn test_toml_oversized_txt() {
+        let config = Config::parse(
+            r#"
+            [mdns]
+            oversized_txt = "omit"
+            max_txt_bytes = 1300
+
+            [strategies."_ipp._tcp"]
+            oversized_txt = "drop"
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config.txt_size_for("_http._tcp.local."), (OversizedTxt::Omit, 1300));
+        assert_eq!(
+            config.txt_size_for("Printer._ipp._tcp.mdns.home.arpa."),
+            (OversizedTxt::Drop, 1300)
+        );
+        let defaults = Config::default();
+        assert_eq!(defaults.txt_size_for("_ipp._tcp.local."), (OversizedTxt::Truncate, 65535));
+        assert!(Config::parse("[mdns]\noversized_txt = \"shorten\"").is_err());
+        assert!(Config::parse("[mdns]\nmax_txt_bytes = 0").is_err());
+        assert!(Config::parse("[strategies.\"_ipp._tcp\"]\nmax_txt_bytes = 0").is_err());
+    }
+
+    #[test]
+    fn test_toml_change_debounce_secs() {
+        let config: Config = toml::from_str("[mdns]\nchange_debounce_secs = 60").unwrap();
+        assert_eq!(config.mdns.change_debounce_secs, 60);
+        assert_eq!(Config::default().mdns.change_debounce_secs, 10);
+    }
+
+    #[test]
+    fn test_toml_non_dns_sd_names() {
+        let config: Config = toml::from_str("[mdns]\nnon_dns_sd_names = \"nxdomain\"").unwrap();
+        assert_eq!(config.mdns.non_dns_sd_names, NonDnsSdNames::NxDomain);
+        assert_eq!(Config::default().mdns.non_dns_sd_names, NonDnsSdNames::NoData);
+        assert!(toml::from_str::<Config>("[mdns]\nnon_dns_sd_names = \"ignore\"").is_err());
+    }
+
+    #[test]
+    fn test_toml_zone_soa_minimum() {
+        let config: Config =
+            toml::from_str("[zones.\"Lab.Home.Arpa\"]\nsoa_minimum_secs = 3\n\n[zones.\"big.home.arpa.\"]\nsoa_minimum_secs = 600")
+                .unwrap();
+        let apex = |s: &str| Name::from_utf8(s).unwrap();
+        assert_eq!(config.soa_minimum(&apex("lab.home.arpa.")), 3);
+        assert_eq!(config.soa_minimum(&apex("big.home.arpa.")), MAX_SOA_MINIMUM);
+        assert_eq!(config.soa_minimum(&apex("mdns.home.arpa.")), MAX_SOA_MINIMUM);
+    }
+
+    #[test]
+    fn test_toml_links() {
+        let config: Config = toml::from_str(
+            "[server]\nport = 5335\nfallback_ports = [5336]\n\n\
+             [[links]]\ninterface = \"eth1\"\ndiscovery_domain = \"LAN1.mdns.home.arpa\"\n\n\
+             [[links]]\ninterface = \"eth2\"\ndiscovery_domain = \"lan2.mdns.home.arpa.\"\nport = 53",
+        )
+        .unwrap();
+        assert_eq!(config.links.len(), 2);
+        assert!(Config::default().links.is_empty());
+        assert!(Config::default().mdns.interfaces.is_empty());
+
+        let lan1 = config.for_link(&config.links[0]);
+        assert_eq!(lan1.discovery_domain(), "lan1.mdns.home.arpa.");
+        assert_eq!(lan1.server.port, 5335);
+        assert!(lan1.server.fallback_ports.is_empty());
+        assert_eq!(lan1.server.bind_device.as_deref(), Some("eth1"));
+        assert_eq!(lan1.mdns.interfaces, vec!["eth1"]);
+        assert!(lan1.links.is_empty());
+        assert_eq!(config.for_link(&config.links[1]).server.port, 53);
+    }
+
+    #[test]
+    fn test_toml_apex_txt() {
+        let config = Config::parse("[server]\napex_txt = [\"proxy=mdns-dns-proxy\", \"zone={zone}\"]").unwrap();
+        assert_eq!(config.server.apex_txt, vec!["proxy=mdns-dns-proxy", "zone={zone}"]);
+        assert!(Config::default().server.apex_txt.is_empty());
+    }
+
+    #[test]
+    fn test_toml_service_groups() {
+        let config = Config::parse(
+            "[service_groups]\nprinters = [\"_ipp._tcp\", \"_printer._tcp.\"]\n\"media.lab\" = [\"_airplay._tcp\"]",
+        )
+        .unwrap();
+        assert!(Config::default().service_groups.is_empty());
+        let apex = Name::from_utf8("mdns.home.arpa.").unwrap();
+        let group = |name: &str| config.service_group(&Name::from_utf8(name).unwrap(), &apex);
+
+        let members = group("Printers.mdns.home.arpa.").unwrap();
+        let members: Vec<String> = members.iter().map(Name::to_utf8).collect();
+        assert_eq!(members, vec!["_ipp._tcp.mdns.home.arpa.", "_printer._tcp.mdns.home.arpa."]);
+        assert_eq!(group("media.lab.mdns.home.arpa.").unwrap().len(), 1);
+        assert!(group("lab.mdns.home.arpa.").is_none());
+        assert!(group("mdns.home.arpa.").is_none());
+        assert!(group("printers.example.com.").is_none());
+    }
+
+    #[test]
+    fn test_toml_record_acls() {
+        let config: Config = toml::from_str(
+            "[[record_acls]]\nclients = [\"192.168.50.0/24\"]\n\n\
+             [[record_acls]]\nclients = [\"10.9.0.0/16\", \"10.8.0.1\"]\nallow = [\"PTR\", \"SRV\"]",
+        )
+        .unwrap();
+        assert!(Config::default().record_acls.is_empty());
+        assert_eq!(config.record_acls.len(), 2);
+        assert_eq!(config.record_acls[0].allow, vec!["PTR", "TXT"]);
+        assert_eq!(config.record_acls[1].clients, vec!["10.9.0.0/16", "10.8.0.1"]);
+        assert_eq!(config.record_acls[1].allow, vec!["PTR", "SRV"]);
+    }
+
+    #[test]
+    fn test_service_type_key() {
+        assert_eq!(service_type_key("_ipp._tcp").as_deref(), Some("_ipp._tcp"));
+        assert_eq!(service_type_key("My Printer._IPP._TCP.local.").as_deref(), Some("_ipp._tcp"));
+        assert_eq!(service_type_key("host.local."), None);
+        assert_eq!(service_type_key("_tcp.local."), None);
+    }
+
     #[test]
     fn test_normalize_domain_lowercase_and_trailing_dot() {
         assert_eq!(normalize_domain("Example.COM"), "example.com.");
@@ -0,0 +1,341 @@
+//! `conformance` subcommand
+//!
+//! Black-box RFC 8766 checks run over the wire against any Discovery Proxy,
+//! not just this one: every check sends real DNS queries (UDP unless noted) to
+//! the target and inspects only the responses.
+
+use crate::random::random_u64;
+use hickory_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+/// Maximum TTL a Discovery Proxy should hand out (RFC 8766 Section 5.5.1)
+const MAX_TTL: u32 = 10;
+
+type CheckResult = Result<(), String>;
+
+/// Instance under test
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub addr: SocketAddr,
+    /// Discovery domain the target serves
+    pub zone: Name,
+    /// How long to wait for each response
+    pub timeout: Duration,
+}
+
+/// Result of one check
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    /// RFC 8766 section the check covers
+    pub section: &'static str,
+    pub name: &'static str,
+    pub result: CheckResult,
+}
+
+/// Run every check against `target`, print the report and return the number of failures
+pub async fn run(target: &Target) -> usize {
+    let outcomes = run_checks(target).await;
+    print!("{}", render(target, &outcomes));
+    outcomes.iter().filter(|o| o.result.is_err()).count()
+}
+
+/// Run every check against `target`
+pub async fn run_checks(target: &Target) -> Vec<Outcome> {
+    let mut outcomes = Vec::new();
+    let mut push = |section, name, result| outcomes.push(Outcome { section, name, result });
+
+    push("RFC 1035", "response header matches query", check_header(target).await);
+    push("6.1", "SOA at zone apex", check_apex_soa(target).await);
+    push("6.2", "NS at zone apex", check_apex_ns(target).await);
+    push("6.3", "no delegation below zone apex", check_no_delegation(target).await);
+    push("6.4", "DNS Update SRV is negative", check_update_srv(target).await);
+    push("5.2.1", "domain enumeration", check_domain_enumeration(target).await);
+    push("5.5.1", "service enumeration TTLs capped", check_service_enumeration(target).await);
+    push("5.6", "unknown name is NOERROR, not NXDOMAIN", check_unknown_name(target).await);
+    push("5", "names outside the zone are not answered", check_out_of_zone(target).await);
+    push("5", "queries over TCP", check_tcp(target).await);
+
+    outcomes
+}
+
+/// Human-readable pass/fail report
+pub fn render(target: &Target, outcomes: &[Outcome]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "RFC 8766 conformance: {} (zone {})", target.addr, target.zone);
+    for outcome in outcomes {
+        match &outcome.result {
+            Ok(()) => {
+                let _ = writeln!(out, "PASS  [{}] {}", outcome.section, outcome.name);
+            }
+            Err(reason) => {
+                let _ = writeln!(out, "FAIL  [{}] {}: {}", outcome.section, outcome.name, reason);
+            }
+        }
+    }
+    let failed = outcomes.iter().filter(|o| o.result.is_err()).count();
+    let _ = writeln!(out, "{} passed, {} failed", outcomes.len() - failed, failed);
+    out
+}
+
+async fn check_header(target: &Target) -> CheckResult {
+    let query = build_query(&target.zone, RecordType::SOA);
+    let response = exchange_udp(target, &query).await?;
+    if response.id() != query.id() {
+        return Err(format!("id {} does not match query id {}", response.id(), query.id()));
+    }
+    if response.message_type() != MessageType::Response {
+        return Err("QR bit not set".to_string());
+    }
+    if response.op_code() != OpCode::Query {
+        return Err(format!("opcode {:?}", response.op_code()));
+    }
+    if response.queries() != query.queries() {
+        return Err("question section not echoed".to_string());
+    }
+    Ok(())
+}
+
+async fn check_apex_soa(target: &Target) -> CheckResult {
+    let response = query(target, &target.zone, RecordType::SOA).await?;
+    expect_code(&response, ResponseCode::NoError)?;
+    check_ttls(&response)?;
+    let soa = response
+        .answers()
+        .iter()
+        .find_map(|r| match r.data() {
+            RData::SOA(soa) if r.name() == &target.zone => Some(soa),
+            _ => None,
+        })
+        .ok_or("no SOA record for the zone apex")?;
+    if soa.serial() != 0 {
+        return Err(format!("SOA serial is {}, must be 0", soa.serial()));
+    }
+    if soa.minimum() > MAX_TTL {
+        return Err(format!("SOA minimum is {}, expected at most {}", soa.minimum(), MAX_TTL));
+    }
+    Ok(())
+}
+
+async fn check_apex_ns(target: &Target) -> CheckResult {
+    let response = query(target, &target.zone, RecordType::NS).await?;
+    expect_code(&response, ResponseCode::NoError)?;
+    check_ttls(&response)?;
+    if !response.answers().iter().any(|r| matches!(r.data(), RData::NS(_))) {
+        return Err("no NS record for the zone apex".to_string());
+    }
+    Ok(())
+}
+
+async fn check_no_delegation(target: &Target) -> CheckResult {
+    let name = probe_name(target, "delegation")?;
+    for record_type in [RecordType::SOA, RecordType::NS, RecordType::DS] {
+        let response = query(target, &name, record_type).await?;
+        expect_code(&response, ResponseCode::NoError).map_err(|e| format!("{}: {}", record_type, e))?;
+        if response.answers().iter().any(|r| r.record_type() == record_type) {
+            return Err(format!("{} below the apex was answered", record_type));
+        }
+    }
+    Ok(())
+}
+
+async fn check_update_srv(target: &Target) -> CheckResult {
+    for service in ["_dns-update._udp", "_dns-update._tcp", "_dns-update-tls._tcp"] {
+        let name = Name::from_utf8(service).and_then(|n| n.append_domain(&target.zone)).map_err(|e| e.to_string())?;
+        let response = query(target, &name, RecordType::SRV).await?;
+        expect_code(&response, ResponseCode::NoError).map_err(|e| format!("{}: {}", service, e))?;
+        if response.answers().iter().any(|r| r.record_type() == RecordType::SRV) {
+            return Err(format!("{} advertises an update server", service));
+        }
+    }
+    Ok(())
+}
+
+async fn check_domain_enumeration(target: &Target) -> CheckResult {
+    for label in ["b", "db", "lb"] {
+        let name = Name::from_utf8(format!("{}._dns-sd._udp", label))
+            .and_then(|n| n.append_domain(&target.zone))
+            .map_err(|e| e.to_string())?;
+        let response = query(target, &name, RecordType::PTR).await?;
+        expect_code(&response, ResponseCode::NoError).map_err(|e| format!("{}: {}", name, e))?;
+        check_ttls(&response)?;
+        if !response.answers().iter().any(|r| matches!(r.data(), RData::PTR(_))) {
+            return Err(format!("no PTR answer for {}", name));
+        }
+    }
+    Ok(())
+}
+
+async fn check_service_enumeration(target: &Target) -> CheckResult {
+    let name = Name::from_utf8("_services._dns-sd._udp")
+        .and_then(|n| n.append_domain(&target.zone))
+        .map_err(|e| e.to_string())?;
+    let response = query(target, &name, RecordType::PTR).await?;
+    expect_code(&response, ResponseCode::NoError)?;
+    check_ttls(&response)
+}
+
+async fn check_unknown_name(target: &Target) -> CheckResult {
+    let name = probe_name(target, "absent")?;
+    let response = query(target, &name, RecordType::A).await?;
+    expect_code(&response, ResponseCode::NoError)?;
+    if !response.answers().is_empty() {
+        return Err(format!("{} answers for a name nobody advertises", response.answers().len()));
+    }
+    check_ttls(&response)
+}
+
+async fn check_out_of_zone(target: &Target) -> CheckResult {
+    let name = Name::from_ascii("conformance.example.com.").map_err(|e| e.to_string())?;
+    let response = query(target, &name, RecordType::A).await?;
+    if !response.answers().is_empty() {
+        return Err(format!("answered with {} records", response.answers().len()));
+    }
+    Ok(())
+}
+
+async fn check_tcp(target: &Target) -> CheckResult {
+    let query = build_query(&target.zone, RecordType::SOA);
+    let response = exchange_tcp(target, &query).await?;
+    if response.id() != query.id() {
+        return Err("id mismatch".to_string());
+    }
+    expect_code(&response, ResponseCode::NoError)?;
+    if !response.answers().iter().any(|r| r.record_type() == RecordType::SOA) {
+        return Err("no SOA record over TCP".to_string());
+    }
+    Ok(())
+}
+
+fn expect_code(response: &Message, expected: ResponseCode) -> CheckResult {
+    if response.response_code() != expected {
+        return Err(format!("response code {}, expected {}", response.response_code(), expected));
+    }
+    Ok(())
+}
+
+/// Every record in the response must respect the unicast TTL cap
+fn check_ttls(response: &Message) -> CheckResult {
+    let records: Vec<&Record> = response
+        .answers()
+        .iter()
+        .chain(response.name_servers())
+        .chain(response.additionals())
+        .collect();
+    match records.iter().find(|r| r.ttl() > MAX_TTL) {
+        Some(r) => Err(format!("{} {} has TTL {} (max {})", r.name(), r.record_type(), r.ttl(), MAX_TTL)),
+        None => Ok(()),
+    }
+}
+
+/// A name below the zone apex that no device should ever advertise
+fn probe_name(target: &Target, purpose: &str) -> Result<Name, String> {
+    Name::from_utf8(format!("conformance-{}-{:x}", purpose, random_u64() as u32))
+        .and_then(|n| n.append_domain(&target.zone))
+        .map_err(|e| e.to_string())
+}
+
+pub(crate) fn build_query(name: &Name, record_type: RecordType) -> Message {
+    let mut message = Message::new();
+    message.set_id(random_u64() as u16);
+    message.set_message_type(MessageType::Query);
+    message.set_op_code(OpCode::Query);
+    message.set_recursion_desired(true);
+    message.add_query(Query::query(name.clone(), record_type));
+    message
+}
+
+async fn query(target: &Target, name: &Name, record_type: RecordType) -> Result<Message, String> {
+    exchange_udp(target, &build_query(name, record_type)).await
+}
+
+async fn exchange_udp(target: &Target, query: &Message) -> Result<Message, String> {
+    let bind: SocketAddr = if target.addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }
+        .parse()
+        .map_err(|e: std::net::AddrParseError| e.to_string())?;
+    let socket = UdpSocket::bind(bind).await.map_err(|e| e.to_string())?;
+    socket.connect(target.addr).await.map_err(|e| e.to_string())?;
+    socket.send(&query.to_vec().map_err(|e| e.to_string())?).await.map_err(|e| e.to_string())?;
+
+    let mut buf = vec![0u8; 65_535];
+    loop {
+        let len = tokio::time::timeout(target.timeout, socket.recv(&mut buf))
+            .await
+            .map_err(|_| format!("no response within {:?}", target.timeout))?
+            .map_err(|e| e.to_string())?;
+        let response = Message::from_vec(&buf[..len]).map_err(|e| format!("malformed response: {}", e))?;
+        // Ignore stray datagrams for other queries
+        if response.id() == query.id() {
+            return Ok(response);
+        }
+    }
+}
+
+async fn exchange_tcp(target: &Target, query: &Message) -> Result<Message, String> {
+    let exchange = async {
+        let mut stream = TcpStream::connect(target.addr).await?;
+        let bytes = query.to_vec().map_err(std::io::Error::other)?;
+        let mut framed = (bytes.len() as u16).to_be_bytes().to_vec();
+        framed.extend_from_slice(&bytes);
+        stream.write_all(&framed).await?;
+
+        let mut len = [0u8; 2];
+        stream.read_exact(&mut len).await?;
+        let mut buf = vec![0u8; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut buf).await?;
+        Ok::<_, std::io::Error>(buf)
+    };
+    let buf = tokio::time::timeout(target.timeout, exchange)
+        .await
+        .map_err(|_| format!("no response within {:?}", target.timeout))?
+        .map_err(|e| e.to_string())?;
+    Message::from_vec(&buf).map_err(|e| format!("malformed response: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::rdata::A;
+
+    fn target() -> Target {
+        Target {
+            addr: "127.0.0.1:5335".parse().unwrap(),
+            zone: Name::from_utf8("mdns.home.arpa.").unwrap(),
+            timeout: Duration::from_secs(1),
+        }
+    }
+
+    #[test]
+    fn test_check_ttls_flags_long_ttl() {
+        let mut response = Message::new();
+        response.add_answer(Record::from_rdata(Name::from_utf8("a.mdns.home.arpa.").unwrap(), 10, RData::A(A::new(192, 0, 2, 1))));
+        assert!(check_ttls(&response).is_ok());
+        response.add_additional(Record::from_rdata(Name::from_utf8("b.mdns.home.arpa.").unwrap(), 120, RData::A(A::new(192, 0, 2, 2))));
+        let err = check_ttls(&response).unwrap_err();
+        assert!(err.contains("b.mdns.home.arpa.") && err.contains("120"), "{}", err);
+    }
+
+    #[test]
+    fn test_probe_name_is_inside_zone() {
+        let t = target();
+        let name = probe_name(&t, "absent").unwrap();
+        assert!(t.zone.zone_of(&name));
+        assert_eq!(name.num_labels(), t.zone.num_labels() + 1);
+    }
+
+    #[test]
+    fn test_render_counts_failures() {
+        let outcomes = vec![
+            Outcome { section: "6.1", name: "SOA at zone apex", result: Ok(()) },
+            Outcome { section: "5.6", name: "unknown name", result: Err("response code NXDomain".to_string()) },
+        ];
+        let text = render(&target(), &outcomes);
+        assert!(text.contains("PASS  [6.1] SOA at zone apex"));
+        assert!(text.contains("FAIL  [5.6] unknown name: response code NXDomain"));
+        assert!(text.ends_with("1 passed, 1 failed\n"));
+    }
+}
@@ -0,0 +1,821 @@
+//! Local control socket
+//!
+//! A Unix stream socket accepting one text command per line and answering each
+//! with a single line starting with `ok` or `error:`. Try it with
+//! `socat - UNIX-CONNECT:/run/mdns-dns-proxy.sock`.
+//!
+//! Commands:
+//! - `zone list` — served discovery domains
+//! - `zone add <domain>` — start serving a discovery domain
+//! - `zone remove <domain>` — stop serving a domain and drop its cached records
+//! - `read-only [on|off]` — show or switch cache-only answering
+//! - `toggle [<feature> [on|off]]` — show or switch suppression, forwarding or push (see [`crate::toggles`])
+//! - `quiet` — whether quiet hours are in effect
+//! - `inventory` — cached service instances, their service type descriptions, links and liveness, as a JSON array
+//! - `metrics` — counters, per-phase latency histograms and instances per service type, as a JSON object
+//! - `pending` — queries being answered right now, longest-running first, as a JSON array
+//! - `health` — last browse event, last resolution and error count of the daemon and each link, as a JSON array
+//! - `slo` — compliance of cached and uncached answers with `[slo]` and whether each is in breach, as a JSON array
+//! - `export` — cache and last known addresses as one line of JSON
+//! - `import <json>` — restore the output of `export`
+//! - `policy export` — response policy triggers as a JSON array of rules
+//! - `policy import <json>` — replace the response policy and its file with the given rules
+//! - `prewarm <name> <type> [<name> <type> ...]` — resolve names and cache the answers, e.g. right after a restart
+//! - `flush` — drop every cached record for the served domains
+//! - `auth <token>` — authenticate with one of `[admin] api_keys`
+//!
+//! When API keys are configured, a connection may run nothing but `auth` and
+//! `help` until it authenticates, and then only the commands in its key's
+//! scopes (see [`required_scope`]).
+
+use crate::config::{ApiKey, ApiScope};
+use crate::mdns_resolver::{from_presentation, presentation, MdnsResolver};
+use crate::mdns_resolver::health::LinkStatus;
+use crate::mdns_resolver::liveness::InventoryEntry;
+use crate::mdns_resolver::snapshot::Snapshot;
+use crate::metrics;
+use crate::pending::PendingQuery;
+use crate::policy::{PolicyRule, PolicyStore};
+use crate::slo::{self, SloTracker};
+use crate::toggles::Feature;
+use crate::zones::ZoneRegistry;
+use futures_util::future::join_all;
+use hickory_proto::rr::RecordType;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tracing::{debug, info, warn};
+
+/// State the control commands operate on
+pub struct ControlContext {
+    pub zones: Arc<ZoneRegistry>,
+    pub resolver: Arc<MdnsResolver>,
+    /// Response policy, when `[policy] rpz_file` is set
+    pub policy: Option<Arc<PolicyStore>>,
+    /// Tokens connections must authenticate with; empty lets every connection run everything
+    pub api_keys: Vec<ApiKey>,
+    /// Latency objectives, when `[slo]` is enabled
+    pub slo: Option<Arc<SloTracker>>,
+}
+
+/// Listen on `path`, created with file mode `mode`, and serve control
+/// connections until the task is dropped
+pub async fn serve(path: &Path, mode: u32, ctx: Arc<ControlContext>) -> io::Result<()> {
+    // A socket file left behind by a previous run would make bind fail
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    info!("Control socket listening on {}", path.display());
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, &ctx).await {
+                debug!("Control connection closed: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(stream: UnixStream, ctx: &ControlContext) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let mut session = Session::new(ctx);
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = session.run(ctx, &line).await;
+        writer.write_all(reply.as_bytes()).await?;
+        writer.write_all(b"\n").await?;
+    }
+    Ok(())
+}
+
+/// What one connection may run: every command when no API keys are
+/// configured, otherwise nothing until `auth` and then its key's scopes
+pub struct Session {
+    /// Name of the key authenticated with, if any
+    key: Option<String>,
+    scopes: Vec<ApiScope>,
+}
+
+impl Session {
+    pub fn new(ctx: &ControlContext) -> Self {
+        let scopes = if ctx.api_keys.is_empty() {
+            vec![ApiScope::Read, ApiScope::Flush, ApiScope::Policy]
+        } else {
+            Vec::new()
+        };
+        Self { key: None, scopes }
+    }
+
+    /// Run a single command line if the session's scopes allow it and return the reply line
+    pub fn execute(&mut self, ctx: &ControlContext, line: &str) -> String {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["auth", token] => return self.auth(ctx, token),
+            ["auth", ..] => return "error: usage: auth <token>".to_string(),
+            ["help"] => return execute(ctx, line),
+            _ => {}
+        }
+        match self.check_scope(line) {
+            Ok(()) => match words.as_slice() {
+                ["toggle", feature, state @ ("on" | "off")] => set_toggle(ctx, self.key.as_deref(), feature, *state == "on"),
+                _ => execute(ctx, line),
+            },
+            Err(denied) => denied,
+        }
+    }
+
+    /// Like [`execute`](Self::execute), including the commands that wait on resolution
+    pub async fn run(&mut self, ctx: &ControlContext, line: &str) -> String {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["prewarm", questions @ ..] => match self.check_scope(line) {
+                Ok(()) => prewarm(ctx, questions).await,
+                Err(denied) => denied,
+            },
+            _ => self.execute(ctx, line),
+        }
+    }
+
+    /// The error reply for a command the session may not run
+    fn check_scope(&self, line: &str) -> Result<(), String> {
+        let scope = required_scope(line);
+        if self.scopes.contains(&scope) {
+            return Ok(());
+        }
+        match &self.key {
+            None => Err("error: authentication required (auth <token>)".to_string()),
+            Some(key) => {
+                let command = line.split_whitespace().next();
+                warn!("Control: key {} denied {:?}, which needs the {} scope", key, command, scope.name());
+                Err(format!("error: key {} lacks the {} scope", key, scope.name()))
+            }
+        }
+    }
+
+    fn auth(&mut self, ctx: &ControlContext, token: &str) -> String {
+        if ctx.api_keys.is_empty() {
+            return "error: no api keys are configured ([admin] api_keys)".to_string();
+        }
+        match ctx.api_keys.iter().find(|key| token_matches(&key.token, token)) {
+            Some(key) => {
+                debug!("Control: authenticated as {}", key.name);
+                self.key = Some(key.name.clone());
+                self.scopes = key.scopes.clone();
+                let scopes: Vec<&str> = key.scopes.iter().map(|scope| scope.name()).collect();
+                format!("ok {} {}", key.name, scopes.join(" "))
+            }
+            None => {
+                warn!("Control: authentication with an unknown token");
+                "error: unknown token".to_string()
+            }
+        }
+    }
+}
+
+/// Compare without returning early, so timing does not reveal how much of a token matched
+fn token_matches(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected.bytes().zip(given.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Scope an API key needs to run `line`. Only the commands listed as
+/// reporting need `read`; anything else, unknown commands included, needs the
+/// strictest scope, so a command added later is never open by mistake.
+pub fn required_scope(line: &str) -> ApiScope {
+    let line = line.trim_start();
+    if line.starts_with("import ") {
+        return ApiScope::Flush;
+    }
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        ["zone", "list"]
+        | ["read-only"]
+        | ["toggle"]
+        | ["toggle", _]
+        | ["quiet"]
+        | ["inventory"]
+        | ["metrics"]
+        | ["pending"]
+        | ["health"]
+        | ["slo"]
+        | ["export"]
+        | ["policy", "export"]
+        | ["help"] => ApiScope::Read,
+        ["flush"] | ["prewarm", ..] | ["zone", "add" | "remove", _] | ["read-only", "on" | "off"] | ["toggle", _, "on" | "off"] => {
+            ApiScope::Flush
+        }
+        _ => ApiScope::Policy,
+    }
+}
+
+/// Run a single command line and return the reply line
+pub fn execute(ctx: &ControlContext, line: &str) -> String {
+    // The JSON argument may contain spaces, so it is taken whole
+    if let Some(json) = line.trim_start().strip_prefix("import ") {
+        return import(ctx, json);
+    }
+    if let Some(json) = line.trim_start().strip_prefix("policy import ") {
+        return policy_import(ctx, json);
+    }
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        ["zone", "list"] => {
+            let zones: Vec<String> = ctx.zones.list().iter().map(|z| z.to_utf8()).collect();
+            format!("ok {}", zones.join(" "))
+        }
+        ["zone", "add", domain] => match ctx.zones.add(domain) {
+            Ok(true) => {
+                info!("Control: now serving discovery domain {}", domain);
+                format!("ok added {}", domain)
+            }
+            Ok(false) => format!("ok {} already served", domain),
+            Err(e) => format!("error: {}", e),
+        },
+        ["zone", "remove", domain] => match ctx.zones.remove(domain) {
+            Ok(Some(apex)) => {
+                let purged = ctx.resolver.purge_zone(&apex);
+                info!("Control: stopped serving {} ({} cache entries dropped)", apex, purged);
+                format!("ok removed {}", apex)
+            }
+            Ok(None) => format!("error: {} is not served", domain),
+            Err(e) => format!("error: {}", e),
+        },
+        ["read-only"] => format!("ok read-only {}", on_off(ctx.resolver.is_read_only())),
+        ["read-only", state @ ("on" | "off")] => {
+            ctx.resolver.set_read_only(*state == "on");
+            info!("Control: read-only mode {}", state);
+            format!("ok read-only {}", state)
+        }
+        ["toggle"] => {
+            let states: Vec<String> = Feature::ALL
+                .into_iter()
+                .map(|feature| format!("{} {}", feature, toggle_state(ctx, feature)))
+                .collect();
+            format!("ok {}", states.join(" "))
+        }
+        ["toggle", feature] => match feature.parse::<Feature>() {
+            Ok(feature) => format!("ok {} {}", feature, toggle_state(ctx, feature)),
+            Err(e) => format!("error: {}", e),
+        },
+        ["toggle", feature, state @ ("on" | "off")] => set_toggle(ctx, None, feature, *state == "on"),
+        ["quiet"] => format!("ok quiet {}", on_off(ctx.resolver.is_quiet())),
+        ["inventory"] => format!("ok {}", inventory_json(&ctx.resolver.inventory())),
+        ["metrics"] => format!("ok {}", metrics_json(ctx.slo.as_deref())),
+        ["pending"] => format!("ok {}", pending_json(&ctx.resolver.pending().list())),
+        ["health"] => format!("ok {}", health_json(&ctx.resolver.health())),
+        ["slo"] => match &ctx.slo {
+            Some(slo) => format!("ok {}", slo::status_json(&slo.status())),
+            None => "error: latency objectives are not enabled ([slo] enabled)".to_string(),
+        },
+        ["export"] => format!("ok {}", ctx.resolver.export_snapshot().to_json()),
+        ["flush"] => {
+            let flushed: usize = ctx.zones.list().iter().map(|apex| ctx.resolver.purge_zone(apex)).sum();
+            info!("Control: flushed {} cache entries", flushed);
+            format!("ok flushed {}", flushed)
+        }
+        ["policy", "export"] => match &ctx.policy {
+            Some(store) => format!("ok {}", serde_json::to_string(&store.current().to_rules()).expect("rules serialize")),
+            None => NO_POLICY.to_string(),
+        },
+        ["help"] => {
+            "ok commands: zone list | zone add <domain> | zone remove <domain> | read-only [on|off] | toggle [<feature> [on|off]] | quiet | inventory \
+             | metrics | pending | health | slo | export | import <json> | policy export | policy import <json> | prewarm <name> <type> ... | flush | auth <token>"
+                .to_string()
+        }
+        _ => {
+            warn!("Control: unknown command {:?}", line);
+            "error: unknown command (try help)".to_string()
+        }
+    }
+}
+
+/// "on", "off", or "unavailable" when the configuration did not set `feature` up
+fn toggle_state(ctx: &ControlContext, feature: Feature) -> &'static str {
+    let toggles = ctx.resolver.toggles();
+    if !toggles.is_available(feature) {
+        "unavailable"
+    } else {
+        on_off(toggles.is_enabled(feature))
+    }
+}
+
+/// Switch `feature`, logging the change with the API key that made it
+fn set_toggle(ctx: &ControlContext, key: Option<&str>, feature: &str, enabled: bool) -> String {
+    let feature = match feature.parse::<Feature>() {
+        Ok(feature) => feature,
+        Err(e) => return format!("error: {}", e),
+    };
+    match ctx.resolver.toggles().set(feature, enabled) {
+        Ok(was) => {
+            let by = key.map_or(String::new(), |key| format!(" by key {}", key));
+            warn!("Control: {} switched {}{} (was {})", feature, on_off(enabled), by, on_off(was));
+            format!("ok {} {}", feature, on_off(enabled))
+        }
+        Err(e) => format!("error: {}", e),
+    }
+}
+
+fn import(ctx: &ControlContext, json: &str) -> String {
+    match Snapshot::from_json(json).and_then(|snapshot| ctx.resolver.import_snapshot(&snapshot)) {
+        Ok(restored) => {
+            info!("Control: imported {} cache entries", restored);
+            format!("ok imported {}", restored)
+        }
+        Err(e) => format!("error: {}", e),
+    }
+}
+
+/// Most questions one `prewarm` line may ask
+const MAX_PREWARM: usize = 64;
+
+/// Resolve each name and type pair as a query would, which caches the answers,
+/// and reply with a JSON array of record counts, or errors, per question
+async fn prewarm(ctx: &ControlContext, words: &[&str]) -> String {
+    if words.is_empty() || !words.len().is_multiple_of(2) {
+        return "error: usage: prewarm <name> <type> [<name> <type> ...]".to_string();
+    }
+    if words.len() / 2 > MAX_PREWARM {
+        return format!("error: at most {} names per prewarm", MAX_PREWARM);
+    }
+    let mut questions = Vec::new();
+    for pair in words.chunks(2) {
+        let name = match from_presentation(pair[0]) {
+            Ok(name) => name,
+            Err(e) => return format!("error: bad name {}: {}", pair[0], e),
+        };
+        let Ok(record_type) = pair[1].to_ascii_uppercase().parse::<RecordType>() else {
+            return format!("error: unknown record type {}", pair[1]);
+        };
+        let Some(zone) = ctx.zones.zone_for(&name) else {
+            return format!("error: {} is not in a served zone", pair[0]);
+        };
+        questions.push((name, zone, record_type));
+    }
+
+    let answers = join_all(
+        questions
+            .iter()
+            .map(|(name, zone, record_type)| ctx.resolver.query_in_zone(name, zone, *record_type)),
+    )
+    .await;
+    let mut warmed = 0;
+    let items: Vec<String> = questions
+        .iter()
+        .zip(answers)
+        .map(|((name, _, record_type), answer)| {
+            let result = match answer {
+                Ok(records) => {
+                    warmed += usize::from(!records.is_empty());
+                    format!("\"records\":{}", records.len())
+                }
+                Err(e) => format!("\"error\":{}", json_string(&e.to_string())),
+            };
+            format!(
+                "{{\"name\":{},\"type\":{},{}}}",
+                json_string(&presentation(name)),
+                json_string(&record_type.to_string()),
+                result
+            )
+        })
+        .collect();
+    info!("Control: prewarmed {} of {} name(s)", warmed, questions.len());
+    format!("ok [{}]", items.join(","))
+}
+
+const NO_POLICY: &str = "error: no response policy is configured ([policy] rpz_file)";
+
+fn policy_import(ctx: &ControlContext, json: &str) -> String {
+    let Some(store) = &ctx.policy else {
+        return NO_POLICY.to_string();
+    };
+    let rules: Vec<PolicyRule> = match serde_json::from_str(json) {
+        Ok(rules) => rules,
+        Err(e) => return format!("error: {}", e),
+    };
+    match store.import(&rules) {
+        Ok(triggers) => {
+            info!("Control: imported response policy ({} triggers)", triggers);
+            format!("ok imported {}", triggers)
+        }
+        Err(e) => format!("error: {}", e),
+    }
+}
+
+/// Counters and histograms, with the histogram bucket bounds and the
+/// per-service-type instance counts alongside
+fn metrics_json(slo: Option<&SloTracker>) -> String {
+    let mut value = serde_json::to_value(metrics::metrics().snapshot()).expect("metrics serialize");
+    value["bucket_bounds_us"] = serde_json::json!(metrics::BUCKET_BOUNDS_US);
+    value["service_types"] = serde_json::to_value(metrics::service_types()).expect("metrics serialize");
+    if let Some(slo) = slo {
+        value["slo"] = serde_json::to_value(slo.status()).expect("metrics serialize");
+    }
+    value.to_string()
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value { "on" } else { "off" }
+}
+
+/// One-line JSON array; `description` is null for service types the registry
+/// does not describe, `alive` and `checked_secs_ago` until an instance is probed;
+/// `links` lists the interfaces a browse saw the instance on
+fn inventory_json(entries: &[InventoryEntry]) -> String {
+    let items: Vec<String> = entries
+        .iter()
+        .map(|entry| {
+            let (alive, checked) = match entry.liveness {
+                Some(l) => (l.alive.to_string(), l.checked.elapsed().as_secs().to_string()),
+                None => ("null".to_string(), "null".to_string()),
+            };
+            let links: Vec<String> = entry.links.iter().map(|link| json_string(link)).collect();
+            format!(
+                "{{\"instance\":{},\"target\":{},\"port\":{},\"description\":{},\"links\":[{}],\"alive\":{},\"checked_secs_ago\":{}}}",
+                json_string(&presentation(&entry.instance)),
+                json_string(&presentation(&entry.target)),
+                entry.port,
+                entry.description.as_deref().map_or("null".to_string(), json_string),
+                links.join(","),
+                alive,
+                checked
+            )
+        })
+        .collect();
+    format!("[{}]", items.join(","))
+}
+
+/// One-line JSON array; `phase` is the resolution step running (e.g. "mdns"),
+/// "started" before the first and "answering" after the last
+fn pending_json(queries: &[PendingQuery]) -> String {
+    let items: Vec<String> = queries
+        .iter()
+        .map(|query| {
+            format!(
+                "{{\"name\":{},\"type\":{},\"client\":{},\"elapsed_ms\":{},\"phase\":{}}}",
+                json_string(&presentation(&query.name)),
+                json_string(&query.record_type.to_string()),
+                json_string(&query.client.to_string()),
+                query.elapsed.as_millis(),
+                json_string(query.phase)
+            )
+        })
+        .collect();
+    format!("[{}]", items.join(","))
+}
+
+/// One-line JSON array, the daemon first; the `_secs_ago` fields are null
+/// until the first event or resolution
+fn health_json(statuses: &[LinkStatus]) -> String {
+    let secs_ago = |at: Option<std::time::Instant>| at.map_or("null".to_string(), |at| at.elapsed().as_secs().to_string());
+    let items: Vec<String> = statuses
+        .iter()
+        .map(|status| {
+            let networks: Vec<String> = status
+                .networks
+                .iter()
+                .map(|network| json_string(&format!("{}/{}", network.addr, network.prefix_len)))
+                .collect();
+            format!(
+                "{{\"link\":{},\"networks\":[{}],\"last_event_secs_ago\":{},\"last_resolution_secs_ago\":{},\"errors\":{}}}",
+                json_string(&status.name),
+                networks.join(","),
+                secs_ago(status.last_event),
+                secs_ago(status.last_resolution),
+                status.errors
+            )
+        })
+        .collect();
+    format!("[{}]", items.join(","))
+}
+
+fn json_string(value: &str) -> String {
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn context() -> ControlContext {
+        let resolver = MdnsResolver::new(Arc::new(Config::default())).unwrap();
+        ControlContext {
+            zones: Arc::new(ZoneRegistry::new(&["mdns.home.arpa."]).unwrap()),
+            resolver: Arc::new(resolver),
+            policy: None,
+            api_keys: Vec::new(),
+            slo: None,
+        }
+    }
+
+    #[test]
+    fn test_zone_commands() {
+        let ctx = context();
+        assert_eq!(execute(&ctx, "zone list"), "ok mdns.home.arpa.");
+        assert_eq!(execute(&ctx, "zone add vlan20.home.arpa."), "ok added vlan20.home.arpa.");
+        assert_eq!(execute(&ctx, "zone add vlan20.home.arpa."), "ok vlan20.home.arpa. already served");
+        assert_eq!(execute(&ctx, "zone list"), "ok mdns.home.arpa. vlan20.home.arpa.");
+        assert_eq!(execute(&ctx, "zone remove vlan20.home.arpa"), "ok removed vlan20.home.arpa.");
+        assert!(execute(&ctx, "zone remove vlan20.home.arpa.").starts_with("error:"));
+        assert!(execute(&ctx, "zone add local.").starts_with("error:"));
+        assert!(execute(&ctx, "frobnicate").starts_with("error:"));
+    }
+
+    #[test]
+    fn test_read_only_commands() {
+        let ctx = context();
+        assert_eq!(execute(&ctx, "read-only"), "ok read-only off");
+        assert_eq!(execute(&ctx, "read-only on"), "ok read-only on");
+        assert!(ctx.resolver.is_read_only());
+        assert_eq!(execute(&ctx, "read-only off"), "ok read-only off");
+        assert!(!ctx.resolver.is_read_only());
+        assert!(execute(&ctx, "read-only maybe").starts_with("error:"));
+    }
+
+    #[test]
+    fn test_toggle_commands() {
+        let ctx = context();
+        assert_eq!(execute(&ctx, "toggle"), "ok suppression on forwarding unavailable push unavailable");
+        assert_eq!(execute(&ctx, "toggle suppression off"), "ok suppression off");
+        assert!(!ctx.resolver.toggles().is_enabled(Feature::Suppression));
+        assert_eq!(execute(&ctx, "toggle suppression"), "ok suppression off");
+        assert_eq!(execute(&ctx, "toggle push on"), "error: push is not configured");
+        assert!(execute(&ctx, "toggle serve-stale off").starts_with("error: unknown feature"));
+        assert!(execute(&ctx, "toggle suppression maybe").starts_with("error:"));
+        assert_eq!(required_scope("toggle suppression on"), ApiScope::Flush);
+        assert_eq!(required_scope("toggle"), ApiScope::Read);
+    }
+
+    #[test]
+    fn test_flush() {
+        use hickory_proto::rr::rdata::A;
+        use hickory_proto::rr::{Name, RData, Record, RecordType};
+
+        let ctx = context();
+        let name = Name::from_utf8("printer.mdns.home.arpa.").unwrap();
+        let a = Record::from_rdata(name, 10, RData::A(A::new(192, 168, 1, 20)));
+        ctx.resolver.cache.insert("printer.mdns.home.arpa.", RecordType::A, vec![a]);
+        assert_eq!(execute(&ctx, "flush"), "ok flushed 1");
+        assert!(ctx.resolver.cache.get("printer.mdns.home.arpa.", RecordType::A).is_none());
+        assert_eq!(execute(&ctx, "flush"), "ok flushed 0");
+    }
+
+    #[test]
+    fn test_api_key_scopes() {
+        let mut ctx = context();
+        let key = |name: &str, token: &str, scopes: &[ApiScope]| ApiKey {
+            name: name.to_string(),
+            token: token.to_string(),
+            scopes: scopes.to_vec(),
+        };
+        ctx.api_keys = vec![
+            key("dashboard", "read-token", &[ApiScope::Read]),
+            key("ops", "ops-token", &[ApiScope::Read, ApiScope::Flush]),
+        ];
+
+        let mut session = Session::new(&ctx);
+        assert_eq!(session.execute(&ctx, "inventory"), "error: authentication required (auth <token>)");
+        assert!(session.execute(&ctx, "help").starts_with("ok commands:"));
+        assert_eq!(session.execute(&ctx, "auth read-tokeN"), "error: unknown token");
+        assert_eq!(session.execute(&ctx, "auth read-token"), "ok dashboard read");
+        assert_eq!(session.execute(&ctx, "inventory"), "ok []");
+        assert_eq!(session.execute(&ctx, "read-only"), "ok read-only off");
+        assert_eq!(session.execute(&ctx, "read-only on"), "error: key dashboard lacks the flush scope");
+        assert_eq!(session.execute(&ctx, "flush"), "error: key dashboard lacks the flush scope");
+        assert!(!ctx.resolver.is_read_only());
+
+        assert_eq!(session.execute(&ctx, "auth ops-token"), "ok ops read flush");
+        assert_eq!(session.execute(&ctx, "read-only on"), "ok read-only on");
+        assert_eq!(session.execute(&ctx, "policy import []"), "error: key ops lacks the policy scope");
+
+        // Without keys every command is open and auth has nothing to check
+        ctx.api_keys.clear();
+        let mut session = Session::new(&ctx);
+        assert_eq!(session.execute(&ctx, "read-only off"), "ok read-only off");
+        assert!(session.execute(&ctx, "auth ops-token").starts_with("error: no api keys"));
+    }
+
+    #[test]
+    fn test_required_scope() {
+        assert_eq!(required_scope("metrics"), ApiScope::Read);
+        assert_eq!(required_scope("policy export"), ApiScope::Read);
+        assert_eq!(required_scope("zone list"), ApiScope::Read);
+        assert_eq!(required_scope("zone add vlan20.home.arpa."), ApiScope::Flush);
+        assert_eq!(required_scope("import {\"version\":1}"), ApiScope::Flush);
+        assert_eq!(required_scope("prewarm printer.mdns.home.arpa. A"), ApiScope::Flush);
+        assert_eq!(required_scope("  policy import []"), ApiScope::Policy);
+        assert_eq!(required_scope("frobnicate"), ApiScope::Policy);
+        assert_eq!(required_scope("zone"), ApiScope::Policy);
+        assert_eq!(required_scope("read-only maybe"), ApiScope::Policy);
+        assert_eq!(required_scope("read-only"), ApiScope::Read);
+        assert_eq!(required_scope("read-only on"), ApiScope::Flush);
+    }
+
+    #[test]
+    fn test_quiet_status() {
+        let ctx = context();
+        assert_eq!(execute(&ctx, "quiet"), "ok quiet off");
+        ctx.resolver.set_quiet(true);
+        assert_eq!(execute(&ctx, "quiet"), "ok quiet on");
+    }
+
+    #[test]
+    fn test_inventory() {
+        use hickory_proto::rr::rdata::SRV;
+        use hickory_proto::rr::{Name, RData, Record, RecordType};
+
+        let ctx = context();
+        assert_eq!(execute(&ctx, "inventory"), "ok []");
+
+        let instance = Name::from_utf8("printer._ipp._tcp.mdns.home.arpa.").unwrap();
+        let target = Name::from_utf8("printer.mdns.home.arpa.").unwrap();
+        let srv = Record::from_rdata(instance.clone(), 10, RData::SRV(SRV::new(0, 0, 631, target)));
+        ctx.resolver.cache.insert("printer._ipp._tcp.mdns.home.arpa.", RecordType::SRV, vec![srv]);
+        assert_eq!(
+            execute(&ctx, "inventory"),
+            "ok [{\"instance\":\"printer._ipp._tcp.mdns.home.arpa.\",\"target\":\"printer.mdns.home.arpa.\",\
+             \"port\":631,\"description\":\"Internet Printing Protocol\",\"links\":[],\"alive\":null,\"checked_secs_ago\":null}]"
+        );
+        assert_eq!(json_string("a\"b\\c\n"), "\"a\\\"b\\\\c\\u000a\"");
+    }
+
+    #[test]
+    fn test_health() {
+        let ctx = context();
+        let reply = execute(&ctx, "health");
+        assert!(reply.starts_with(
+            "ok [{\"link\":\"daemon\",\"networks\":[],\"last_event_secs_ago\":null,\"last_resolution_secs_ago\":null,\"errors\":0}"
+        ));
+        let statuses: serde_json::Value = serde_json::from_str(reply.strip_prefix("ok ").unwrap()).unwrap();
+        assert_eq!(statuses.as_array().unwrap().len(), ctx.resolver.health().len());
+        assert_eq!(required_scope("health"), ApiScope::Read);
+    }
+
+    #[test]
+    fn test_slo_status() {
+        let mut ctx = context();
+        assert!(execute(&ctx, "slo").starts_with("error: "));
+        assert!(!execute(&ctx, "metrics").contains("\"slo\""));
+
+        let config = crate::config::SloConfig {
+            enabled: true,
+            min_samples: 1,
+            ..Default::default()
+        };
+        let slo = Arc::new(SloTracker::from_config(&config).unwrap());
+        slo.record(slo::Class::Uncached, std::time::Duration::from_secs(2));
+        ctx.slo = Some(slo);
+        let reply = execute(&ctx, "slo");
+        let statuses: serde_json::Value = serde_json::from_str(reply.strip_prefix("ok ").unwrap()).unwrap();
+        assert_eq!(statuses[0]["class"], "cached");
+        assert_eq!(statuses[0]["compliance"], serde_json::Value::Null);
+        assert_eq!(statuses[1]["samples"], 1);
+        assert_eq!(statuses[1]["breached"], true);
+        assert!(execute(&ctx, "metrics").contains("\"slo\":[{"));
+    }
+
+    #[tokio::test]
+    async fn test_prewarm() {
+        use hickory_proto::rr::{Name, RData, Record};
+
+        let ctx = context();
+        // Cache only, so the unknown name comes back empty instead of waiting on mDNS
+        ctx.resolver.set_read_only(true);
+        let name = Name::from_utf8("printer.mdns.home.arpa.").unwrap();
+        let a = Record::from_rdata(name, 10, RData::A("192.168.1.40".parse().unwrap()));
+        ctx.resolver.cache.insert("printer.mdns.home.arpa.", RecordType::A, vec![a]);
+
+        let mut session = Session::new(&ctx);
+        assert_eq!(
+            session.run(&ctx, "prewarm printer.mdns.home.arpa. a nas.mdns.home.arpa. AAAA").await,
+            "ok [{\"name\":\"printer.mdns.home.arpa.\",\"type\":\"A\",\"records\":1},\
+             {\"name\":\"nas.mdns.home.arpa.\",\"type\":\"AAAA\",\"records\":0}]"
+        );
+        assert!(session.run(&ctx, "prewarm printer.mdns.home.arpa.").await.starts_with("error: usage"));
+        assert!(session.run(&ctx, "prewarm printer.mdns.home.arpa. BOGUS").await.contains("unknown record type"));
+        assert!(session.run(&ctx, "prewarm example.com. A").await.contains("not in a served zone"));
+        assert_eq!(session.run(&ctx, "zone list").await, "ok mdns.home.arpa.");
+    }
+
+    #[test]
+    fn test_pending() {
+        use hickory_proto::rr::{Name, RecordType};
+
+        let ctx = context();
+        assert_eq!(execute(&ctx, "pending"), "ok []");
+
+        let name = Name::from_utf8("printer.mdns.home.arpa.").unwrap();
+        let guard = ctx.resolver.pending().start(&name, RecordType::A, "192.168.1.40:5353".parse().unwrap());
+        guard.set_phase("mdns");
+        let reply = execute(&ctx, "pending");
+        let value: serde_json::Value = serde_json::from_str(reply.strip_prefix("ok ").unwrap()).unwrap();
+        assert_eq!(value[0]["name"], "printer.mdns.home.arpa.");
+        assert_eq!(value[0]["type"], "A");
+        assert_eq!(value[0]["client"], "192.168.1.40:5353");
+        assert_eq!(value[0]["phase"], "mdns");
+        assert!(value[0]["elapsed_ms"].is_u64());
+
+        drop(guard);
+        assert_eq!(execute(&ctx, "pending"), "ok []");
+    }
+
+    #[test]
+    fn test_policy_commands() {
+        let mut ctx = context();
+        assert!(execute(&ctx, "policy export").starts_with("error: no response policy"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("policy.rpz");
+        std::fs::write(&path, "$ORIGIN rpz.example.\nblocked.mdns.home.arpa 60 CNAME .\n").unwrap();
+        ctx.policy = Some(Arc::new(PolicyStore::open(&path).unwrap()));
+        assert_eq!(
+            execute(&ctx, "policy export"),
+            r#"ok [{"trigger":"blocked.mdns.home.arpa.","action":"nxdomain"}]"#
+        );
+
+        let rules = r#"[{"trigger":"*.guest.mdns.home.arpa.","action":"nodata"},{"trigger":"tv.mdns.home.arpa.","action":"drop"}]"#;
+        assert_eq!(execute(&ctx, &format!("policy import {}", rules)), "ok imported 2");
+        assert_eq!(execute(&ctx, "policy export"), format!("ok {}", rules));
+        assert!(execute(&ctx, "policy import [{\"trigger\": 1}]").starts_with("error:"));
+    }
+
+    #[test]
+    fn test_metrics() {
+        let ctx = context();
+        let reply = execute(&ctx, "metrics");
+        let value: serde_json::Value = serde_json::from_str(reply.strip_prefix("ok ").unwrap()).unwrap();
+        assert!(value["requests"].is_u64());
+        assert_eq!(value["mdns_wait_time"]["buckets"].as_array().unwrap().len(), metrics::BUCKET_BOUNDS_US.len() + 1);
+        assert_eq!(value["bucket_bounds_us"][0], 50);
+        assert!(value["service_types"].is_object());
+    }
+
+    #[test]
+    fn test_export_import() {
+        use hickory_proto::rr::rdata::A;
+        use hickory_proto::rr::{Name, RData, Record, RecordType};
+
+        let ctx = context();
+        let name = Name::from_utf8("printer.local.").unwrap();
+        let a = Record::from_rdata(name, 120, RData::A(A::new(192, 168, 1, 20)));
+        ctx.resolver.cache.insert("printer.local.", RecordType::A, vec![a.clone()]);
+        let export = execute(&ctx, "export");
+        let json = export.strip_prefix("ok ").unwrap();
+        assert!(json.contains("\"rdata\":\"\\\\# 4 c0a80114\""));
+
+        let restored = context();
+        assert_eq!(execute(&restored, &format!("import {}", json)), "ok imported 1");
+        assert_eq!(restored.resolver.cache.get("printer.local.", RecordType::A), Some(vec![a]));
+        let again = Snapshot::from_json(execute(&restored, "export").strip_prefix("ok ").unwrap()).unwrap();
+        assert_eq!(again.cache[0].records, Snapshot::from_json(json).unwrap().cache[0].records);
+
+        assert!(execute(&restored, "import {\"version\":9}").contains("newer than this build supports"));
+        assert!(execute(&restored, "import [").starts_with("error:"));
+    }
+
+    #[tokio::test]
+    async fn test_control_socket_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("control.sock");
+        let ctx = Arc::new(context());
+
+        let server_path = path.clone();
+        let server_ctx = ctx.clone();
+        let server = tokio::spawn(async move { serve(&server_path, 0o660, server_ctx).await });
+
+        let mut stream = loop {
+            match UnixStream::connect(&path).await {
+                Ok(s) => break s,
+                Err(_) => tokio::time::sleep(std::time::Duration::from_millis(10)).await,
+            }
+        };
+        stream.write_all(b"zone add lab.home.arpa.\nzone list\n").await.unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "ok added lab.home.arpa.");
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "ok mdns.home.arpa. lab.home.arpa.");
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o660);
+        server.abort();
+    }
+}
@@ -3,9 +3,11 @@
 //! This module handles administrative DNS queries that should be answered
 //! directly by the Discovery Proxy without forwarding to Multicast DNS.
 
+use crate::netwatch::NetworkState;
 use hickory_proto::rr::{Name, RData, Record, RecordType};
-use hickory_proto::rr::rdata::{SOA, NS};
+use hickory_proto::rr::rdata::{NULL, SOA, NS, TXT};
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
 use tracing::debug;
 
 /// Maximum TTL for administrative records per RFC 8766 Section 5.5.1
@@ -18,6 +20,8 @@ pub struct RecordSuppressionConfig {
     pub enabled: bool,
     /// Client IP address for determining if link-local addresses should be suppressed
     pub client_ip: Option<IpAddr>,
+    /// Networks of this host's interfaces; clients inside them are on-link
+    pub local_networks: Option<Arc<NetworkState>>,
 }
 
 impl Default for RecordSuppressionConfig {
@@ -25,6 +29,7 @@ impl Default for RecordSuppressionConfig {
         Self {
             enabled: true,
             client_ip: None,
+            local_networks: None,
         }
     }
 }
@@ -86,19 +91,20 @@ pub fn is_zone_apex_query(name: &Name, zone_apex: &Name) -> bool {
     name == zone_apex
 }
 
-/// Generate SOA record for zone apex per RFC 8766 Section 6.1
-pub fn generate_soa_record(name: &Name, zone_apex: &Name) -> Record {
+/// Generate SOA record for zone apex per RFC 8766 Section 6.1, advertising `minimum`
+/// (the zone's negative-caching TTL, see [`crate::Config::soa_minimum`])
+pub fn generate_soa_record(name: &Name, zone_apex: &Name, minimum: u32) -> Record {
     // Per RFC 8766 Section 6.1:
     // - MNAME: host name of the Discovery Proxy device
     // - RNAME: mailbox of the person responsible
     // - SERIAL: MUST be zero
     // - REFRESH: 7200, RETRY: 3600, EXPIRE: 86400 (recommended)
-    // - MINIMUM: 10 (negative caching TTL per Section 5.5.1)
+    // - MINIMUM: negative caching TTL, at most 10 per Section 5.5.1
     
     let zone = zone_apex.to_utf8();
     let zone_trimmed = zone.trim_end_matches('.');
-    let mname = Name::from_utf8(&format!("discovery-proxy.{}.", zone_trimmed)).unwrap();
-    let rname = Name::from_utf8(&format!("hostmaster.{}.", zone_trimmed)).unwrap();
+    let mname = Name::from_utf8(format!("discovery-proxy.{}.", zone_trimmed)).unwrap();
+    let rname = Name::from_utf8(format!("hostmaster.{}.", zone_trimmed)).unwrap();
     
     let soa = SOA::new(
         mname,
@@ -107,7 +113,7 @@ pub fn generate_soa_record(name: &Name, zone_apex: &Name) -> Record {
         7200,   // REFRESH
         3600,   // RETRY
         86400,  // EXPIRE
-        10,     // MINIMUM: 10 seconds per RFC 8766 Section 5.5.1
+        minimum,
     );
     
     Record::from_rdata(
@@ -117,16 +123,60 @@ pub fn generate_soa_record(name: &Name, zone_apex: &Name) -> Record {
     )
 }
 
+/// NSEC record for `name` whose type bitmap lists `types`, sent with a NODATA
+/// answer to say which types the name does have (RFC 4034 Section 4). As in
+/// mDNS (RFC 6762 Section 6.1), the next domain name is `name` itself: the
+/// proxy cannot enumerate the zone, so the record denies nothing beyond it.
+/// Encoded by hand, as hickory only models NSEC with its DNSSEC feature.
+pub fn generate_nsec_record(name: &Name, types: &[RecordType], ttl: u32) -> Record {
+    let mut rdata = Vec::new();
+    // Next domain name, uncompressed and in canonical (lowercase) form
+    for label in name.to_lowercase().iter() {
+        rdata.push(label.len() as u8);
+        rdata.extend_from_slice(label);
+    }
+    rdata.push(0);
+
+    // Type bitmap: one block per window of 256 types, trimmed after its last set bit
+    let mut codes: Vec<u16> = types.iter().map(|&t| u16::from(t)).collect();
+    codes.sort_unstable();
+    codes.dedup();
+    for window in codes.chunk_by(|a, b| a >> 8 == b >> 8) {
+        let mut bitmap = [0u8; 32];
+        for code in window {
+            let low = (code & 0xff) as usize;
+            bitmap[low / 8] |= 0x80 >> (low % 8);
+        }
+        let length = (window[window.len() - 1] & 0xff) as usize / 8 + 1;
+        rdata.push((window[0] >> 8) as u8);
+        rdata.push(length as u8);
+        rdata.extend_from_slice(&bitmap[..length]);
+    }
+
+    Record::from_rdata(
+        name.clone(),
+        ttl.min(MAX_ADMIN_TTL),
+        RData::Unknown {
+            code: RecordType::NSEC,
+            rdata: NULL::with(rdata),
+        },
+    )
+}
+
+/// This proxy's own host name in `zone_apex`: the NS target and SOA MNAME
+pub fn proxy_host_name(zone_apex: &Name) -> Name {
+    let zone = zone_apex.to_utf8();
+    let zone_trimmed = zone.trim_end_matches('.');
+    Name::from_utf8(format!("discovery-proxy.{}.", zone_trimmed)).unwrap()
+}
+
 /// Generate NS record for zone apex per RFC 8766 Section 6.2
 pub fn generate_ns_record(name: &Name, zone_apex: &Name) -> Record {
     // Per RFC 8766 Section 6.2:
     // Each Discovery Proxy returns its own NS record
     // NS target host MUST NOT fall within delegated zone (except zone apex)
     
-    let zone = zone_apex.to_utf8();
-    let zone_trimmed = zone.trim_end_matches('.');
-    let ns_name = Name::from_utf8(&format!("discovery-proxy.{}.", zone_trimmed)).unwrap();
-    let ns = NS(ns_name);
+    let ns = NS(proxy_host_name(zone_apex));
     
     Record::from_rdata(
         name.clone(),
@@ -135,6 +185,39 @@ pub fn generate_ns_record(name: &Name, zone_apex: &Name) -> Record {
     )
 }
 
+/// NS records for the zone apex: this proxy first, then each configured peer proxy
+/// (RFC 8766 Section 6.2). Peers whose names do not parse are skipped.
+pub fn generate_ns_records(name: &Name, zone_apex: &Name, peers: &[String]) -> Vec<Record> {
+    let mut records = vec![generate_ns_record(name, zone_apex)];
+    for peer in peers {
+        let mut target = match Name::from_utf8(peer) {
+            Ok(target) => target,
+            Err(e) => {
+                debug!("Ignoring peer proxy {:?}: {}", peer, e);
+                continue;
+            }
+        };
+        target.set_fqdn(true);
+        let record = Record::from_rdata(name.clone(), MAX_ADMIN_TTL, RData::NS(NS(target)));
+        if !records.contains(&record) {
+            records.push(record);
+        }
+    }
+    records
+}
+
+/// Zone apex TXT record of `strings` (`server.apex_txt`), each one character-string
+/// with `{zone}` replaced by the zone apex without its trailing dot; None when there are none
+pub fn generate_apex_txt_record(name: &Name, zone_apex: &Name, strings: &[String]) -> Option<Record> {
+    if strings.is_empty() {
+        return None;
+    }
+    let zone = zone_apex.to_utf8();
+    let zone_trimmed = zone.trim_end_matches('.');
+    let txt = TXT::new(strings.iter().map(|s| s.replace("{zone}", zone_trimmed)).collect());
+    Some(Record::from_rdata(name.clone(), MAX_ADMIN_TTL, RData::TXT(txt)))
+}
+
 /// Generate domain enumeration PTR records per RFC 8766 Section 5.2.1 and 6.5
 pub fn generate_domain_enumeration_records(name: &Name, zone_apex: &Name) -> Vec<Record> {
     // Return PTR record pointing to the configured zone
@@ -184,21 +267,36 @@ pub fn is_ipv6_link_local(addr: &Ipv6Addr) -> bool {
     octets[0] == 0xfe && (octets[1] & 0xc0) == 0x80
 }
 
-/// Check if client is on the same local link as the address
-/// This is a simplified check - in production, you'd check actual network interfaces
-fn is_same_link(client_ip: &IpAddr, target_addr: &IpAddr) -> bool {
+/// Whether `client_ip` is on one of this host's links: inside an interface
+/// network when those are known, otherwise only loopback and link-local clients
+pub fn is_client_on_link(client_ip: &IpAddr, local_networks: Option<&NetworkState>) -> bool {
+    if client_ip.is_loopback() {
+        return true;
+    }
+    if let Some(networks) = local_networks {
+        return networks.is_on_link(client_ip);
+    }
+    match client_ip {
+        IpAddr::V4(addr) => is_ipv4_link_local(addr),
+        IpAddr::V6(addr) => is_ipv6_link_local(addr),
+    }
+}
+
+/// Check if client is on the same local link as the address: inside one of the
+/// interface networks when those are known, otherwise by address heuristics
+fn is_same_link(client_ip: &IpAddr, target_addr: &IpAddr, local_networks: Option<&NetworkState>) -> bool {
+    // If client is on loopback, they're local
+    if client_ip.is_loopback() {
+        return true;
+    }
+    if let Some(networks) = local_networks {
+        return networks.is_on_link(client_ip);
+    }
     match (client_ip, target_addr) {
-        // If client is on loopback, they're local
-        (IpAddr::V4(c), _) if c.is_loopback() => true,
-        (IpAddr::V6(c), _) if c.is_loopback() => true,
         // Same address family private ranges suggest same network
-        (IpAddr::V4(c), IpAddr::V4(t)) => {
-            // Check if both are in the same /24 for private ranges
-            if c.is_private() && t.is_private() {
-                c.octets()[0..3] == t.octets()[0..3]
-            } else {
-                false
-            }
+        // Check if both are in the same /24 for private ranges
+        (IpAddr::V4(c), IpAddr::V4(t)) if c.is_private() && t.is_private() => {
+            c.octets()[0..3] == t.octets()[0..3]
         }
         (IpAddr::V6(c), IpAddr::V6(t)) => {
             // Check if both are link-local on same interface (simplified)
@@ -225,7 +323,7 @@ pub fn should_suppress_address_record(record: &Record, config: &RecordSuppressio
             let addr = a.0;
             // Suppress IPv4 link-local for non-local clients
             if is_ipv4_link_local(&addr) {
-                let same_link = is_same_link(client_ip, &IpAddr::V4(addr));
+                let same_link = is_same_link(client_ip, &IpAddr::V4(addr), config.local_networks.as_deref());
                 if !same_link {
                     debug!("Suppressing IPv4 link-local address {} for non-local client", addr);
                     return true;
@@ -237,7 +335,7 @@ pub fn should_suppress_address_record(record: &Record, config: &RecordSuppressio
             let addr = aaaa.0;
             // Suppress IPv6 link-local for non-local clients
             if is_ipv6_link_local(&addr) {
-                let same_link = is_same_link(client_ip, &IpAddr::V6(addr));
+                let same_link = is_same_link(client_ip, &IpAddr::V6(addr), config.local_networks.as_deref());
                 if !same_link {
                     debug!("Suppressing IPv6 link-local address {} for non-local client", addr);
                     return true;
@@ -245,7 +343,7 @@ pub fn should_suppress_address_record(record: &Record, config: &RecordSuppressio
             }
             // Suppress ULA for non-local clients  
             if is_ipv6_ula(&addr) {
-                let same_link = is_same_link(client_ip, &IpAddr::V6(addr));
+                let same_link = is_same_link(client_ip, &IpAddr::V6(addr), config.local_networks.as_deref());
                 if !same_link {
                     debug!("Suppressing IPv6 ULA address {} for non-local client", addr);
                     return true;
@@ -269,11 +367,9 @@ pub fn should_suppress_srv_record(record: &Record, address_records: &[Record], c
         
         // Check if any address record for this target should be suppressed
         for addr_record in address_records {
-            if addr_record.name() == target_name {
-                if should_suppress_address_record(addr_record, config) {
-                    debug!("Suppressing SRV record referencing link-local target {}", target_name);
-                    return true;
-                }
+            if addr_record.name() == target_name && should_suppress_address_record(addr_record, config) {
+                debug!("Suppressing SRV record referencing link-local target {}", target_name);
+                return true;
             }
         }
     }
@@ -412,7 +508,7 @@ mod tests {
     #[test]
     fn test_generate_soa_record() {
         let name = Name::from_utf8("mdns.home.arpa.").unwrap();
-        let record = generate_soa_record(&name, &name);
+        let record = generate_soa_record(&name, &name, 10);
         
         assert_eq!(record.name(), &name);
         assert_eq!(record.ttl(), MAX_ADMIN_TTL);
@@ -426,6 +522,24 @@ mod tests {
         } else {
             panic!("Expected SOA record");
         }
+
+        let record = generate_soa_record(&name, &name, 3);
+        assert!(matches!(record.data(), RData::SOA(soa) if soa.minimum() == 3));
+    }
+
+    #[test]
+    fn test_generate_nsec_record() {
+        let name = Name::from_utf8("Printer.mdns.home.arpa.").unwrap();
+        let record = generate_nsec_record(&name, &[RecordType::AAAA, RecordType::A, RecordType::CAA], 30);
+        assert_eq!(record.record_type(), RecordType::NSEC);
+        assert_eq!(record.ttl(), MAX_ADMIN_TTL);
+        let RData::Unknown { rdata, .. } = record.data() else {
+            panic!("Expected NSEC record");
+        };
+        let mut expected = b"\x07printer\x04mdns\x04home\x04arpa\x00".to_vec();
+        // Window 0: A (1) and AAAA (28); window 1: CAA (257)
+        expected.extend_from_slice(&[0, 4, 0x40, 0, 0, 0x08, 1, 1, 0x40]);
+        assert_eq!(rdata.anything(), &expected[..]);
     }
 
     #[test]
@@ -438,6 +552,42 @@ mod tests {
         assert!(matches!(record.data(), RData::NS(_)));
     }
 
+    #[test]
+    fn test_generate_ns_records_with_peers() {
+        let name = Name::from_utf8("mdns.home.arpa.").unwrap();
+        let peers = vec![
+            "proxy2.home.arpa".to_string(),
+            "proxy2.home.arpa.".to_string(),
+            "bad..name".to_string(),
+        ];
+        let records = generate_ns_records(&name, &name, &peers);
+
+        let targets: Vec<String> = records
+            .iter()
+            .map(|r| match r.data() {
+                RData::NS(ns) => ns.0.to_utf8(),
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+        assert_eq!(targets, vec!["discovery-proxy.mdns.home.arpa.", "proxy2.home.arpa."]);
+        assert_eq!(generate_ns_records(&name, &name, &[]).len(), 1);
+    }
+
+    #[test]
+    fn test_generate_apex_txt_record() {
+        let name = Name::from_utf8("mdns.home.arpa.").unwrap();
+        assert!(generate_apex_txt_record(&name, &name, &[]).is_none());
+
+        let strings = vec!["proxy=mdns-dns-proxy".to_string(), "zone={zone}".to_string()];
+        let record = generate_apex_txt_record(&name, &name, &strings).unwrap();
+        assert_eq!(record.name(), &name);
+        assert_eq!(record.ttl(), MAX_ADMIN_TTL);
+        match record.data() {
+            RData::TXT(txt) => assert_eq!(txt.to_string(), "proxy=mdns-dns-proxyzone=mdns.home.arpa"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
     #[test]
     fn test_is_ipv4_link_local() {
         assert!(is_ipv4_link_local(&Ipv4Addr::new(169, 254, 0, 1)));
@@ -467,6 +617,7 @@ mod tests {
         let config = RecordSuppressionConfig {
             enabled: false,
             client_ip: Some(IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8))),
+            ..Default::default()
         };
         
         let name = Name::from_utf8("test.local.").unwrap();
@@ -485,6 +636,7 @@ mod tests {
         let config = RecordSuppressionConfig {
             enabled: true,
             client_ip: Some(IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8))), // Remote client
+            ..Default::default()
         };
         
         let name = Name::from_utf8("test.local.").unwrap();
@@ -506,6 +658,32 @@ mod tests {
         assert!(!should_suppress_address_record(&record, &config));
     }
 
+    #[test]
+    fn test_same_link_follows_interface_networks() {
+        use crate::netwatch::{LocalNetwork, NetworkState};
+
+        let lan = LocalNetwork {
+            addr: "10.20.0.1".parse().unwrap(),
+            prefix_len: 16,
+        };
+        let record = Record::from_rdata(
+            Name::from_utf8("test.local.").unwrap(),
+            10,
+            RData::A(hickory_proto::rr::rdata::A::from(Ipv4Addr::new(169, 254, 1, 1))),
+        );
+        let config = |client: [u8; 4]| RecordSuppressionConfig {
+            client_ip: Some(IpAddr::V4(Ipv4Addr::from(client))),
+            local_networks: Some(Arc::new(NetworkState::with_networks(vec![lan]))),
+            ..Default::default()
+        };
+
+        // Inside an interface network: on-link, even outside the /24 heuristic
+        assert!(!should_suppress_address_record(&record, &config([10, 20, 7, 7])));
+        // Private, but not on any interface network
+        assert!(should_suppress_address_record(&record, &config([192, 168, 1, 5])));
+        assert!(!should_suppress_address_record(&record, &config([127, 0, 0, 1])));
+    }
+
     #[test]
     fn test_generate_domain_enumeration_records() {
         let name = Name::from_utf8("b._dns-sd._udp.local.").unwrap();
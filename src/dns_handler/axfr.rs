@@ -0,0 +1,98 @@
+//! Zone transfers (AXFR, RFC 5936) of the discovery zones
+//!
+//! Opt-in with `[axfr] enabled`, so a downstream authoritative server can
+//! mirror a discovery zone. A transfer holds the zone as the proxy would answer
+//! it now, from [`QueryEngine::zone_transfer`]: mDNS cannot list everything on
+//! the link, so the records are those cached from browses and queries. Transfers
+//! are refused over UDP and to clients outside `allow_from`. IXFR queries get
+//! the whole zone, as RFC 1995 Section 4 allows.
+//!
+//! [`QueryEngine::zone_transfer`]: super::QueryEngine::zone_transfer
+
+use crate::config::AxfrConfig;
+use crate::netwatch::LocalNetwork;
+use hickory_proto::rr::Record;
+use hickory_proto::serialize::binary::BinEncodable;
+use std::net::IpAddr;
+
+/// Bytes of records per transfer message, leaving room for the header and
+/// question within the 64 KiB a TCP message can hold
+pub const MESSAGE_BYTES: usize = 60_000;
+
+/// Which clients may transfer the discovery zones
+#[derive(Debug, Clone)]
+pub struct ZoneTransfers {
+    allow_from: Vec<LocalNetwork>,
+}
+
+impl ZoneTransfers {
+    /// Access for `[axfr]`; fails on an entry of `allow_from` that is not a network
+    pub fn from_config(config: &AxfrConfig) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let allow_from = config
+            .allow_from
+            .iter()
+            .map(|entry| entry.parse().map_err(|e| format!("[axfr] allow_from: {}", e)))
+            .collect::<Result<Vec<LocalNetwork>, String>>()?;
+        Ok(Self { allow_from })
+    }
+
+    /// Whether `client` may transfer; any client when `allow_from` is empty
+    pub fn allows(&self, client: IpAddr) -> bool {
+        self.allow_from.is_empty() || self.allow_from.iter().any(|network| network.contains(&client))
+    }
+}
+
+/// `records` split into runs of at most `limit` encoded bytes, one per message;
+/// a record larger than `limit` goes in a message of its own
+pub fn messages(records: &[Record], limit: usize) -> Vec<&[Record]> {
+    let mut messages = Vec::new();
+    let (mut start, mut size) = (0, 0);
+    for (i, record) in records.iter().enumerate() {
+        // Encoded on its own, so without compression: never less than in the message
+        let len = record.to_bytes().map_or(0, |bytes| bytes.len());
+        if size + len > limit && i > start {
+            messages.push(&records[start..i]);
+            (start, size) = (i, 0);
+        }
+        size += len;
+    }
+    if start < records.len() {
+        messages.push(&records[start..]);
+    }
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::rdata::A;
+    use hickory_proto::rr::{Name, RData};
+
+    #[test]
+    fn test_access_and_message_split() {
+        let transfers = ZoneTransfers::from_config(&AxfrConfig {
+            enabled: true,
+            allow_from: vec!["192.168.1.53".to_string(), "10.20.0.0/16".to_string()],
+        })
+        .unwrap();
+        assert!(transfers.allows("192.168.1.53".parse().unwrap()));
+        assert!(transfers.allows("10.20.9.9".parse().unwrap()));
+        assert!(!transfers.allows("192.168.1.54".parse().unwrap()));
+        assert!(ZoneTransfers::from_config(&AxfrConfig::default()).unwrap().allows("8.8.8.8".parse().unwrap()));
+        let bad = AxfrConfig {
+            enabled: true,
+            allow_from: vec!["eth0".to_string()],
+        };
+        assert!(ZoneTransfers::from_config(&bad).is_err());
+
+        let name = Name::from_utf8("printer.mdns.home.arpa.").unwrap();
+        let records: Vec<Record> = (0..5)
+            .map(|i| Record::from_rdata(name.clone(), 10, RData::A(A::new(192, 168, 1, i))))
+            .collect();
+        let len = records[0].to_bytes().unwrap().len();
+        let sizes: Vec<usize> = messages(&records, 2 * len).iter().map(|m| m.len()).collect();
+        assert_eq!(sizes, [2, 2, 1]);
+        assert_eq!(messages(&records, 1).len(), 5);
+        assert!(messages(&[], 100).is_empty());
+    }
+}
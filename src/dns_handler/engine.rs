@@ -0,0 +1,718 @@
+//! Transport-agnostic query decisions
+//!
+//! [`QueryEngine`] decides how to answer one question: zone membership, response
+//! policy, RFC 8766 administrative records, names that are not DNS-SD, the
+//! configured resolution chain (cache, known services, mDNS, peers), record
+//! suppression, response linting and TTL jitter. It takes a name, a type and [`ClientMeta`] and returns an [`Answer`],
+//! and knows nothing about DNS messages or sockets. Transports are adapters around it: [`MdnsDnsHandler`]
+//! parses hickory-server requests and encodes the answer, `doctor` calls it
+//! directly, and any further listener would do the same.
+//!
+//! [`MdnsDnsHandler`]: super::MdnsDnsHandler
+
+use crate::config::{LintMode, NonDnsSdNames, OffLinkLocalQueries, ResolutionStep};
+use crate::mdns_resolver::{classify, mark_fresh, reverse, sort_canonical, MdnsResolver, NameKind};
+use crate::own_addresses::OwnAddresses;
+#[cfg(not(feature = "minimal"))]
+use crate::peers::{self, PeerSet};
+use crate::pending::PendingGuard;
+use crate::policy::{PolicyAction, PolicyStore};
+use crate::toggles::Feature;
+use crate::zones::ZoneRegistry;
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::rdata::opt::EdnsCode;
+use hickory_proto::rr::rdata::TXT;
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use hickory_proto::xfer::Protocol;
+use hickory_server::server::Request;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use super::admin_records::{
+    filter_suppressed_records, generate_apex_txt_record, generate_domain_enumeration_records, generate_ns_records, generate_nsec_record, generate_soa_record,
+    is_admin_srv_query, is_client_on_link, is_delegation_query_below_apex, is_domain_enumeration_query, is_negative_admin_srv_query,
+    is_zone_apex_query, proxy_host_name, RecordSuppressionConfig,
+};
+use super::lint::{drop_violating_records, lint_response};
+use super::record_acl::RecordAcls;
+use super::utils::{apply_ttl_floor, apply_ttl_jitter, build_response_from_records, ttl_jitter_offset, ExtendedError};
+
+/// EDNS option (private use range, RFC 6891 Section 9) asking for a fresh answer:
+/// the cache is bypassed and records come back with TTL 0
+pub const FRESH_OPTION: u16 = 65_003;
+
+/// What the engine needs to know about who asked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientMeta {
+    pub addr: SocketAddr,
+    pub protocol: Protocol,
+    /// Sent by a peer proxy, so must not be forwarded to a peer again
+    pub forwarded: bool,
+    /// Asked for a fresh answer with [`FRESH_OPTION`]
+    pub fresh: bool,
+}
+
+impl ClientMeta {
+    pub fn new(addr: SocketAddr, protocol: Protocol) -> Self {
+        Self {
+            addr,
+            protocol,
+            forwarded: false,
+            fresh: false,
+        }
+    }
+
+    pub fn from_request(request: &Request) -> Self {
+        Self {
+            addr: request.src(),
+            protocol: request.protocol(),
+            #[cfg(not(feature = "minimal"))]
+            forwarded: peers::is_forwarded(request),
+            #[cfg(feature = "minimal")]
+            forwarded: false,
+            fresh: request
+                .edns()
+                .is_some_and(|edns| edns.option(EdnsCode::Unknown(FRESH_OPTION)).is_some()),
+        }
+    }
+}
+
+/// Decided answer to one question
+#[derive(Debug, Clone, Default)]
+pub struct Answer {
+    pub response_code: ResponseCode,
+    pub answers: Vec<Record>,
+    pub authority: Vec<Record>,
+    pub additionals: Vec<Record>,
+    pub extended_error: Option<ExtendedError>,
+    /// Send no response at all
+    pub drop: bool,
+    /// What produced the answer, e.g. "cache", "mdns" or "policy"; empty for errors
+    pub source: &'static str,
+}
+
+impl Answer {
+    /// Empty answer with `response_code`
+    pub fn error(response_code: ResponseCode) -> Self {
+        Self {
+            response_code,
+            ..Default::default()
+        }
+    }
+}
+
+/// Query decision logic shared by every transport
+#[derive(Clone)]
+pub struct QueryEngine {
+    resolver: Arc<MdnsResolver>,
+    /// Discovery domains served; each is a zone apex mapped to .local
+    zones: Arc<ZoneRegistry>,
+    /// Configuration for suppressing unusable records
+    suppression_config: RecordSuppressionConfig,
+    /// Response policy (RPZ), when configured
+    policy: Option<Arc<PolicyStore>>,
+    /// Peer proxies to forward to when the local mDNS query fails
+    #[cfg(not(feature = "minimal"))]
+    peers: Option<Arc<PeerSet>>,
+    /// Addresses served for this proxy's own name, when enabled
+    own_addresses: Option<Arc<OwnAddresses>>,
+    /// Record types each group of clients may receive, when configured
+    record_acls: Option<Arc<RecordAcls>>,
+}
+
+impl QueryEngine {
+    /// Engine serving a (runtime-modifiable) set of zones
+    pub fn new(resolver: Arc<MdnsResolver>, zones: Arc<ZoneRegistry>) -> Self {
+        Self {
+            resolver,
+            zones,
+            suppression_config: RecordSuppressionConfig::default(),
+            policy: None,
+            #[cfg(not(feature = "minimal"))]
+            peers: None,
+            own_addresses: None,
+            record_acls: None,
+        }
+    }
+
+    /// Answer from `resolver` for `zones` instead, keeping everything else
+    pub fn with_resolver(mut self, resolver: Arc<MdnsResolver>, zones: Arc<ZoneRegistry>) -> Self {
+        self.resolver = resolver;
+        self.zones = zones;
+        self
+    }
+
+    /// Apply the response policy in `policy` before answering
+    pub fn with_policy(mut self, policy: Arc<PolicyStore>) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Forward queries to `peers` when the local mDNS query fails
+    #[cfg(not(feature = "minimal"))]
+    pub fn with_peers(mut self, peers: Arc<PeerSet>) -> Self {
+        self.peers = Some(peers);
+        self
+    }
+
+    /// Answer A/AAAA for this proxy's own name (the NS target) from `own_addresses`
+    pub fn with_own_addresses(mut self, own_addresses: Arc<OwnAddresses>) -> Self {
+        self.own_addresses = Some(own_addresses);
+        self
+    }
+
+    /// Withhold record types from the clients `record_acls` restricts
+    pub fn with_record_acls(mut self, record_acls: Arc<RecordAcls>) -> Self {
+        self.record_acls = Some(record_acls);
+        self
+    }
+
+    /// Suppress unusable records as configured (e.g. for a specific client address)
+    pub fn with_suppression(mut self, suppression_config: RecordSuppressionConfig) -> Self {
+        self.suppression_config = suppression_config;
+        self
+    }
+
+    pub fn resolver(&self) -> &Arc<MdnsResolver> {
+        &self.resolver
+    }
+
+    /// Discovery zones served
+    pub fn zones(&self) -> &Arc<ZoneRegistry> {
+        &self.zones
+    }
+
+    /// Try the configured answer sources in order until one answers, returning
+    /// its records and the step that answered (None if none did). A failed step
+    /// is reported only if no later step answers instead.
+    async fn run_chain(
+        &self,
+        name: &Name,
+        zone_apex: &Name,
+        record_type: RecordType,
+        client: &ClientMeta,
+        pending: &PendingGuard<'_>,
+    ) -> Result<(Vec<Record>, Option<ResolutionStep>), Box<dyn std::error::Error + Send + Sync>> {
+        let config = self.resolver.config();
+        let fresh = self.resolver.take_fresh(name, client.fresh);
+        let mut failure: Option<Box<dyn std::error::Error + Send + Sync>> = None;
+        for step in config.resolution_chain(record_type) {
+            if step == ResolutionStep::Peers
+                && let Some(e) = &failure
+            {
+                warn!("Query for {} failed ({}), trying peer proxies", name, e);
+            }
+            pending.set_phase(step.name());
+            let attempt = self.run_step(step, name, zone_apex, record_type, client, fresh);
+            let outcome = match config.resolution.timeouts_ms.get(&step) {
+                Some(&ms) => tokio::time::timeout(Duration::from_millis(ms), attempt)
+                    .await
+                    .unwrap_or_else(|_| Some(Err(format!("{:?} step timed out after {} ms", step, ms).into()))),
+                None => attempt.await,
+            };
+            match outcome {
+                Some(Ok(mut records)) => {
+                    if fresh {
+                        mark_fresh(&mut records);
+                    }
+                    if !records.is_empty() {
+                        self.resolver.prefetch_other_family(name, zone_apex, record_type);
+                    }
+                    return Ok((records, Some(step)));
+                }
+                Some(Err(e)) => {
+                    debug!("{:?} step failed for {}: {}", step, name, e);
+                    failure = Some(e);
+                }
+                None => {}
+            }
+        }
+        failure.map_or(Ok((Vec::new(), None)), Err)
+    }
+
+    /// PTR records for service group `group_name`: each member service type is
+    /// answered through the chain, and the instances found are listed under the
+    /// group's name. Fails only if every member fails.
+    async fn run_group(
+        &self,
+        group_name: &Name,
+        members: &[Name],
+        client: &ClientMeta,
+        pending: &PendingGuard<'_>,
+    ) -> Result<(Vec<Record>, Option<ResolutionStep>), Box<dyn std::error::Error + Send + Sync>> {
+        debug!("{} is a service group of {} type(s)", group_name, members.len());
+        let answers = futures_util::future::join_all(members.iter().map(|member| async move {
+            let zone_apex = self.zones.zone_for(member).ok_or_else(|| format!("{} is outside the served zones", member))?;
+            self.run_chain(member, &zone_apex, RecordType::PTR, client, pending).await
+        }))
+        .await;
+
+        let mut records: Vec<Record> = Vec::new();
+        let (mut step, mut failure) = (None, None);
+        let mut answered = false;
+        for answer in answers {
+            match answer {
+                Ok((member_records, member_step)) => {
+                    answered = true;
+                    step = step.or(member_step);
+                    for mut record in member_records {
+                        record.set_name(group_name.clone());
+                        if !records.iter().any(|r| r.data() == record.data()) {
+                            records.push(record);
+                        }
+                    }
+                }
+                Err(e) => failure = Some(e),
+            }
+        }
+        match failure {
+            Some(e) if !answered => Err(e),
+            _ => Ok((records, step)),
+        }
+    }
+
+    /// One step of the chain: None when the step has no answer and the next
+    /// should be tried, an empty answer when it knows there are no records
+    #[cfg_attr(feature = "minimal", allow(unused_variables))]
+    async fn run_step(
+        &self,
+        step: ResolutionStep,
+        name: &Name,
+        zone_apex: &Name,
+        record_type: RecordType,
+        client: &ClientMeta,
+        fresh: bool,
+    ) -> Option<Result<Vec<Record>, Box<dyn std::error::Error + Send + Sync>>> {
+        match step {
+            // A fresh query must not be answered from the cache
+            ResolutionStep::Cache if fresh => None,
+            ResolutionStep::Cache => self.resolver.cached_in_zone(name, record_type).map(Ok),
+            ResolutionStep::Known => self.resolver.known_in_zone(name, zone_apex, record_type).transpose(),
+            ResolutionStep::Mdns => Some(self.resolver.mdns_in_zone(name, zone_apex, record_type).await),
+            #[cfg(not(feature = "minimal"))]
+            ResolutionStep::Peers => {
+                // A query a peer sent is never forwarded again, which prevents loops
+                let peer_set = self
+                    .peers
+                    .as_ref()
+                    .filter(|_| !client.forwarded && self.resolver.toggles().is_enabled(Feature::Forwarding))?;
+                peer_set.forward(name, record_type).await.map(Ok)
+            }
+            // Peer forwarding is compiled out
+            #[cfg(feature = "minimal")]
+            ResolutionStep::Peers => None,
+        }
+    }
+
+    /// Check if the query should be handled by this proxy
+    pub fn should_handle(&self, name: &Name) -> bool {
+        self.zones.zone_for(name).is_some()
+    }
+
+    /// Apex of the most specific discovery domain containing `name`
+    pub fn zone_for(&self, name: &Name) -> Option<Name> {
+        self.zones.zone_for(name)
+    }
+
+    /// Decide the answer to `name`/`record_type` asked by `client`
+    pub async fn resolve(&self, name: &Name, record_type: RecordType, client: &ClientMeta) -> Answer {
+        let started = std::time::Instant::now();
+        let pending = self.resolver.pending().start(name, record_type, client.addr);
+        let mut answer = self.decide(name, record_type, client, &pending).await.unwrap_or_else(Answer::error);
+        if let Some(acls) = &self.record_acls {
+            let withheld = acls.apply(client.addr.ip(), &mut answer);
+            if withheld > 0 {
+                debug!("Withheld {} record(s) for {} from {}", withheld, name, client.addr);
+            }
+        }
+        if let Some(zone_apex) = self.zones.zone_for(name)
+            && !answer.drop
+        {
+            self.finish(&zone_apex, &mut answer);
+            if self.resolver.config().debug.provenance_record {
+                answer.additionals.push(provenance_record(&zone_apex, &answer, started.elapsed()));
+            }
+            // Last, so the check sees the response exactly as it is sent
+            self.lint(name, record_type, &zone_apex, &mut answer);
+        }
+        answer
+    }
+
+    async fn decide(
+        &self,
+        query_name: &Name,
+        query_type: RecordType,
+        client: &ClientMeta,
+        pending: &PendingGuard<'_>,
+    ) -> Result<Answer, ResponseCode> {
+        // Check if we should handle this query
+        let Some(zone_apex) = self.zones.zone_for(query_name) else {
+            if let Some(answer) = self.off_link_local_answer(query_name, client) {
+                return answer;
+            }
+            if let Some(answer) = self.reverse_answer(query_name, query_type).await {
+                return answer;
+            }
+            debug!("Query {} not in any served discovery domain, returning NXDOMAIN", query_name);
+            return Err(ResponseCode::NXDomain);
+        };
+
+        // Response policy triggers take precedence over everything below
+        if let Some(store) = &self.policy {
+            let policy = store.current();
+            match policy.lookup(query_name) {
+                Some(PolicyAction::NxDomain) => {
+                    debug!("Response policy: NXDOMAIN for {}", query_name);
+                    return Err(ResponseCode::NXDomain);
+                }
+                Some(PolicyAction::NoData) => {
+                    debug!("Response policy: NODATA for {}", query_name);
+                    return Ok(Answer {
+                        source: "policy",
+                        ..Default::default()
+                    });
+                }
+                Some(PolicyAction::Drop) => {
+                    debug!("Response policy: dropping query for {}", query_name);
+                    return Ok(Answer {
+                        drop: true,
+                        ..Default::default()
+                    });
+                }
+                Some(PolicyAction::Rewrite(records)) => {
+                    debug!("Response policy: local data for {}", query_name);
+                    return Ok(Answer {
+                        answers: PolicyAction::rewrite_answers(records, query_name, query_type),
+                        source: "policy",
+                        ..Default::default()
+                    });
+                }
+                Some(PolicyAction::Passthru) | None => {}
+            }
+        }
+
+        // A virtual service group is browsed as the union of its member types
+        let group = (query_type == RecordType::PTR)
+            .then(|| self.resolver.config().service_group(query_name, &zone_apex))
+            .flatten();
+
+        // RFC 8766 Section 6: Check for administrative queries that don't need mDNS
+        let answer = if let Some(admin_records) = self.handle_admin_query(query_name, query_type, &zone_apex) {
+            Answer {
+                answers: admin_records,
+                source: "admin",
+                ..Default::default()
+            }
+        } else if classify(query_name, &zone_apex) == NameKind::Other
+            && self.resolver.config().mdns.non_dns_sd_names != NonDnsSdNames::Query
+            && group.is_none()
+        {
+            // Names such as _dmarc or _acme-challenge cannot be answered by mDNS
+            debug!("{} is not a DNS-SD name, not querying mDNS", query_name);
+            if self.resolver.config().mdns.non_dns_sd_names == NonDnsSdNames::NxDomain {
+                return Err(ResponseCode::NXDomain);
+            }
+            Answer {
+                source: "not-dns-sd",
+                ..Default::default()
+            }
+        } else {
+            let outcome = match &group {
+                Some(members) => self.run_group(query_name, members, client, pending).await,
+                None => self.run_chain(query_name, &zone_apex, query_type, client, pending).await,
+            };
+            pending.set_phase("answering");
+            let source = match &outcome {
+                Ok((_, Some(step))) => step.name(),
+                _ => "none",
+            };
+            let records = outcome.map(|(records, _)| records);
+
+            // Build response from mDNS records
+            let answer = match build_response_from_records(records) {
+                (ResponseCode::NoError, Some(records)) => {
+                    // Leave out instances that stopped answering liveness probes
+                    let records = self.resolver.filter_dead(records);
+
+                    // Apply RFC 8766 Section 5.5.2: Suppress records unusable by the client that
+                    // asked, unless a client address was configured (e.g. by `doctor`)
+                    let mut suppression = self.suppression_config.clone();
+                    let client_ip = *suppression.client_ip.get_or_insert(client.addr.ip());
+                    suppression.enabled &= self.resolver.toggles().is_enabled(Feature::Suppression);
+                    // Instances only reachable link-locally on another link are of no use to it
+                    let records = if suppression.enabled {
+                        self.resolver.filter_other_links(records, client_ip)
+                    } else {
+                        records
+                    };
+                    let answers = filter_suppressed_records(records, &suppression);
+
+                    // Per-type strategies may ask for related records in the additional section
+                    let additionals = filter_suppressed_records(
+                        self.resolver.additional_records(&answers),
+                        &suppression,
+                    );
+
+                    Answer {
+                        answers,
+                        additionals,
+                        ..Default::default()
+                    }
+                }
+                // Nothing cached and no multicast allowed: say why the answer is empty
+                (ResponseCode::NoError, None) if self.resolver.is_read_only() => Answer {
+                    extended_error: Some(ExtendedError::new(
+                        ExtendedError::NOT_READY,
+                        "read-only mode: answered from cache only",
+                    )),
+                    ..Default::default()
+                },
+                (ResponseCode::NoError, None) => Answer::default(),
+                (response_code, _) => return Err(response_code),
+            };
+            let mut answer = Answer { source, ..answer };
+            // For a name that has other types: say which (RFC 8766)
+            let config = self.resolver.config();
+            if answer.answers.is_empty() && config.server.nsec_records {
+                let types = self.resolver.types_in_zone(query_name, &zone_apex);
+                if !types.is_empty() {
+                    let minimum = config.soa_minimum(&zone_apex);
+                    answer.authority.push(generate_nsec_record(query_name, &types, minimum));
+                }
+            }
+            answer
+        };
+
+        Ok(answer)
+    }
+
+    /// Rules applied to every answer inside discovery zone `zone_apex`, whatever decided it
+    fn finish(&self, zone_apex: &Name, answer: &mut Answer) {
+        // Negative answers carry the zone's SOA, whose MINIMUM resolvers cache them for (RFC 2308)
+        let negative = answer.response_code == ResponseCode::NXDomain
+            || (answer.response_code == ResponseCode::NoError && answer.answers.is_empty());
+        if negative && !answer.authority.iter().any(|r| r.record_type() == RecordType::SOA) {
+            let minimum = self.resolver.config().soa_minimum(zone_apex);
+            answer.authority.insert(0, generate_soa_record(zone_apex, zone_apex, minimum));
+        }
+
+        // Spread re-queries from many clients over time instead of synchronized waves
+        let offset = ttl_jitter_offset(self.resolver.config().server.ttl_jitter_secs);
+        if offset > 0 {
+            apply_ttl_jitter(
+                answer
+                    .answers
+                    .iter_mut()
+                    .chain(answer.authority.iter_mut())
+                    .chain(answer.additionals.iter_mut()),
+                offset,
+            );
+        }
+        // Last, so no earlier step takes a TTL back under the floor
+        let floor = self.resolver.config().server.ttl_floor_secs.secs();
+        if floor > 0 {
+            apply_ttl_floor(
+                answer
+                    .answers
+                    .iter_mut()
+                    .chain(answer.authority.iter_mut())
+                    .chain(answer.additionals.iter_mut()),
+                floor,
+            );
+        }
+
+        if self.resolver.config().debug.deterministic_output {
+            sort_canonical(&mut answer.answers);
+            sort_canonical(&mut answer.authority);
+            sort_canonical(&mut answer.additionals);
+        }
+    }
+
+    /// Optional development check of the finished answer against RFC 8766 rules
+    fn lint(&self, query_name: &Name, query_type: RecordType, zone_apex: &Name, answer: &mut Answer) {
+        let lint_mode = self.resolver.config().debug.lint;
+        if lint_mode == LintMode::Off {
+            return;
+        }
+        let violations = lint_response(
+            query_name,
+            query_type,
+            answer.response_code,
+            zone_apex,
+            &answer.answers,
+            &answer.authority,
+            &answer.additionals,
+        );
+        for violation in &violations {
+            warn!("Response lint for {} {:?}: {}", query_name, query_type, violation);
+        }
+        if lint_mode == LintMode::Drop {
+            drop_violating_records(&violations, &mut answer.answers, &mut answer.additionals);
+        }
+    }
+
+    /// Contents of discovery zone `zone_apex` for a zone transfer (RFC 5936): the
+    /// SOA, the apex NS records, this proxy's own addresses and every cached record
+    /// in the zone the response policy lets through, then the SOA again
+    pub fn zone_transfer(&self, zone_apex: &Name) -> Vec<Record> {
+        let config = self.resolver.config();
+        let soa = generate_soa_record(zone_apex, zone_apex, config.soa_minimum(zone_apex));
+        let mut records = vec![soa.clone()];
+        records.extend(generate_ns_records(zone_apex, zone_apex, &config.server.peer_proxies));
+        records.extend(generate_apex_txt_record(zone_apex, zone_apex, &config.server.apex_txt));
+        if let Some(own_addresses) = &self.own_addresses {
+            let host = proxy_host_name(zone_apex);
+            records.extend(own_addresses.records(&host, RecordType::A));
+            records.extend(own_addresses.records(&host, RecordType::AAAA));
+        }
+
+        let policy = self.policy.as_ref().map(|store| store.current());
+        let mut cached: Vec<Record> = self
+            .resolver
+            .zone_records(zone_apex)
+            .into_iter()
+            .filter(|record| {
+                policy
+                    .as_ref()
+                    .is_none_or(|policy| matches!(policy.lookup(record.name()), None | Some(PolicyAction::Passthru)))
+            })
+            .collect();
+        sort_canonical(&mut cached);
+        cached.dedup();
+        records.extend(cached);
+        records.push(soa);
+        records
+    }
+
+    /// RFC 8766 Section 5.1: a client off the local links asking for a `.local`
+    /// name cannot use the answer; point it at the discovery domain instead.
+    /// None for other names and for on-link clients.
+    fn off_link_local_answer(&self, name: &Name, client: &ClientMeta) -> Option<Result<Answer, ResponseCode>> {
+        let local = Name::from_ascii("local.").ok()?;
+        if !local.zone_of(name)
+            || is_client_on_link(&client.addr.ip(), self.suppression_config.local_networks.as_deref())
+        {
+            return None;
+        }
+        let config = self.resolver.config();
+        debug!("Off-link client {} asked for {}", client.addr, name);
+        Some(match config.server.off_link_local_queries {
+            OffLinkLocalQueries::NxDomain => {
+                let zone_apex = self.zones.list().into_iter().next()?;
+                Ok(Answer {
+                    response_code: ResponseCode::NXDomain,
+                    authority: vec![generate_soa_record(&local, &zone_apex, config.soa_minimum(&zone_apex))],
+                    ..Default::default()
+                })
+            }
+            OffLinkLocalQueries::Refused => Err(ResponseCode::Refused),
+            OffLinkLocalQueries::Drop => Ok(Answer {
+                drop: true,
+                ..Default::default()
+            }),
+        })
+    }
+
+    /// PTR query for the reverse name of an address on the link (`dig -x`):
+    /// the host's name in the first discovery zone, or NXDOMAIN
+    async fn reverse_answer(&self, name: &Name, record_type: RecordType) -> Option<Result<Answer, ResponseCode>> {
+        if record_type != RecordType::PTR
+            || !self.resolver.config().mdns.reverse_lookups
+            || !reverse::address_of(name).is_some_and(|addr| reverse::is_local_address(&addr))
+        {
+            return None;
+        }
+        let zone_apex = self.zones.list().into_iter().next()?;
+        Some(match self.resolver.reverse_in_zone(name, &zone_apex).await {
+            Ok(records) if records.is_empty() => Err(ResponseCode::NXDomain),
+            Ok(records) => Ok(Answer {
+                answers: records,
+                source: "reverse",
+                ..Default::default()
+            }),
+            Err(e) => {
+                warn!("Reverse lookup for {} failed: {}", name, e);
+                Err(ResponseCode::ServFail)
+            }
+        })
+    }
+
+    /// Handle administrative queries that don't need mDNS forwarding
+    /// Returns Some(records) if this is an administrative query, None otherwise
+    fn handle_admin_query(&self, name: &Name, record_type: RecordType, zone_apex: &Name) -> Option<Vec<Record>> {
+        // REQ-6.5.1/6.5.2: Domain enumeration queries (PTR for b/db/lb._dns-sd._udp)
+        if is_domain_enumeration_query(name, record_type) {
+            info!("Handling domain enumeration query for {}", name);
+            return Some(generate_domain_enumeration_records(name, zone_apex));
+        }
+
+        // REQ-6.4.1-6.4.8: Administrative SRV queries
+        if is_admin_srv_query(name, record_type) {
+            info!("Handling administrative SRV query for {}", name);
+            if is_negative_admin_srv_query(name) {
+                // Return empty for unsupported services (DNS Update, LLQ, DNS Push)
+                return Some(Vec::new());
+            }
+            // If we supported LLQ/DNS Push, we'd return positive records here
+            return Some(Vec::new());
+        }
+
+        // REQ-6.3.1: Zone apex SOA query
+        if record_type == RecordType::SOA && is_zone_apex_query(name, zone_apex) {
+            info!("Handling zone apex SOA query");
+            let minimum = self.resolver.config().soa_minimum(zone_apex);
+            return Some(vec![generate_soa_record(name, zone_apex, minimum)]);
+        }
+
+        // REQ-6.2.1: Zone apex NS query
+        if record_type == RecordType::NS && is_zone_apex_query(name, zone_apex) {
+            info!("Handling zone apex NS query");
+            return Some(generate_ns_records(name, zone_apex, &self.resolver.config().server.peer_proxies));
+        }
+
+        // Zone apex TXT metadata, when configured
+        if record_type == RecordType::TXT
+            && is_zone_apex_query(name, zone_apex)
+            && let Some(txt) = generate_apex_txt_record(name, zone_apex, &self.resolver.config().server.apex_txt)
+        {
+            debug!("Handling zone apex TXT query");
+            return Some(vec![txt]);
+        }
+
+        // Addresses of the NS target, so delegations resolve without glue
+        if matches!(record_type, RecordType::A | RecordType::AAAA)
+            && let Some(own_addresses) = &self.own_addresses
+            && *name == proxy_host_name(zone_apex)
+        {
+            debug!("Answering {:?} for this proxy's own name", record_type);
+            return Some(own_addresses.records(name, record_type));
+        }
+
+        // REQ-6.3.2-4: NS/DS/SOA query below zone apex - immediate negative answer
+        if is_delegation_query_below_apex(name, record_type, zone_apex) {
+            debug!("NS/DS/SOA query below zone apex, returning empty");
+            return Some(Vec::new());
+        }
+
+        None
+    }
+}
+
+/// `proxy-debug.<zone>` TXT record saying how `answer` was reached, so `dig`
+/// shows the decision path: source (the answering resolution step, "none" if
+/// no step answered, or "admin", "policy", "not-dns-sd"; "error" for error
+/// responses), whether the cache answered, and the time taken
+fn provenance_record(zone_apex: &Name, answer: &Answer, elapsed: Duration) -> Record {
+    let source = if answer.source.is_empty() { "error" } else { answer.source };
+    let mut strings = vec![format!("source={}", source)];
+    if matches!(source, "cache" | "known" | "mdns" | "peers" | "none") {
+        strings.push(format!("cache={}", if source == "cache" { "hit" } else { "miss" }));
+    }
+    strings.push(format!("elapsed_ms={}", elapsed.as_millis()));
+    let name = Name::from_ascii("proxy-debug")
+        .and_then(|label| label.append_domain(zone_apex))
+        .unwrap_or_else(|_| zone_apex.clone());
+    Record::from_rdata(name, 0, RData::TXT(TXT::new(strings)))
+}
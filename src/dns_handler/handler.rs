@@ -1,205 +1,537 @@
+use crate::admission::Admission;
+use crate::slo::{self, SloTracker};
+#[cfg(not(feature = "minimal"))]
+use crate::audit::{AuditEvent, AuditLog};
+use crate::authoritative::AuthoritativeZones;
+#[cfg(not(feature = "minimal"))]
+use crate::client::ClientIdentity;
+use crate::config::{EdnsPadding, OverloadResponse};
+#[cfg(not(feature = "minimal"))]
+use crate::mdns_resolver::presentation;
 use crate::mdns_resolver::MdnsResolver;
+use crate::metrics;
+use crate::own_addresses::OwnAddresses;
+#[cfg(not(feature = "minimal"))]
+use crate::peers::PeerSet;
+use crate::policy::PolicyStore;
+use crate::query_trace;
+use crate::tsig::{SigningResponder, TsigKeyring, Verification};
+use crate::zones::ZoneRegistry;
+use futures_util::FutureExt;
+use hickory_server::authority::MessageResponseBuilder;
 use hickory_server::server::{Request, RequestHandler, ResponseHandler, ResponseInfo};
-use hickory_proto::op::{Header, ResponseCode};
-use hickory_proto::rr::{Name, RecordType};
+use hickory_proto::op::{Edns, Header, ResponseCode};
+use hickory_proto::rr::rdata::opt::EdnsCode;
+use hickory_proto::rr::{Name, Record, RecordType};
+use hickory_proto::xfer::Protocol;
+use std::any::Any;
+use std::net::SocketAddr;
+use std::panic::AssertUnwindSafe;
 use std::sync::Arc;
-use tracing::{debug, error, info};
-
-use super::utils::{build_response_from_records, parse_dns_request, should_handle_domain};
-use super::admin_records::{
-    is_admin_srv_query, is_delegation_query_below_apex, 
-    is_domain_enumeration_query, is_negative_admin_srv_query,
-    is_zone_apex_query, generate_soa_record, generate_ns_record,
-    generate_domain_enumeration_records, filter_suppressed_records,
-    RecordSuppressionConfig,
-};
-
-/// DNS request handler that forwards queries to mDNS
+use tracing::{debug, error, info, Instrument};
+
+use super::axfr::{self, ZoneTransfers};
+use super::utils::{encoded_len, pad_response, parse_dns_request, response_edns, response_header, CLASSIC_UDP_PAYLOAD, EDNS_VERSION};
+use super::admin_records::RecordSuppressionConfig;
+use super::record_acl::RecordAcls;
+use super::engine::{Answer, ClientMeta, QueryEngine};
+
+/// DNS request handler that forwards queries to mDNS: a hickory-server adapter
+/// around [`QueryEngine`]
+#[derive(Clone)]
 pub struct MdnsDnsHandler {
-    resolver: Arc<MdnsResolver>,
-    /// Zone apex for the Discovery Proxy (default: local.)
-    zone_apex: Name,
-    discovery_domain: String,
-    /// Configuration for suppressing unusable records
-    suppression_config: RecordSuppressionConfig,
+    engine: QueryEngine,
+    /// Query audit log, when enabled
+    #[cfg(not(feature = "minimal"))]
+    audit: Option<Arc<AuditLog>>,
+    /// Static zones answered from zone files, when configured
+    authoritative: Option<Arc<AuthoritativeZones>>,
+    /// TSIG keys and the clients that must sign, when configured
+    tsig: Option<Arc<TsigKeyring>>,
+    /// Who may transfer the discovery zones, when AXFR is enabled
+    axfr: Option<Arc<ZoneTransfers>>,
+    /// Concurrency limit, and the response to queries it turns away
+    admission: Option<(Arc<Admission>, OverloadResponse)>,
+    /// Latency objectives answers are timed against, when `[slo]` is enabled
+    slo: Option<Arc<SloTracker>>,
 }
 
 impl MdnsDnsHandler {
     /// Create a new DNS handler with mDNS resolver
     pub fn new(resolver: Arc<MdnsResolver>, discovery_domain: String) -> Self {
-        let zone_apex = Name::from_utf8(&discovery_domain).unwrap();
-        Self { 
-            resolver,
-            zone_apex,
-            discovery_domain,
-            suppression_config: RecordSuppressionConfig::default(),
-        }
+        let zones = ZoneRegistry::new(&[discovery_domain]).unwrap();
+        Self::with_zones(resolver, Arc::new(zones))
     }
 
     /// Create a new DNS handler with custom zone apex
     pub fn with_zone_apex(resolver: Arc<MdnsResolver>, zone_apex: Name) -> Self {
+        let zones = ZoneRegistry::new(&[zone_apex.to_utf8()]).unwrap();
+        Self::with_zones(resolver, Arc::new(zones))
+    }
+
+    /// Create a new DNS handler serving a (runtime-modifiable) set of zones
+    pub fn with_zones(resolver: Arc<MdnsResolver>, zones: Arc<ZoneRegistry>) -> Self {
+        Self::with_engine(QueryEngine::new(resolver, zones))
+    }
+
+    /// Create a new DNS handler answering with `engine`
+    pub fn with_engine(engine: QueryEngine) -> Self {
         Self {
-            resolver,
-            discovery_domain: zone_apex.to_utf8(),
-            zone_apex,
-            suppression_config: RecordSuppressionConfig::default(),
+            engine,
+            #[cfg(not(feature = "minimal"))]
+            audit: None,
+            authoritative: None,
+            tsig: None,
+            axfr: None,
+            admission: None,
+            slo: None,
         }
     }
 
+    /// Answer from `resolver` for `zones` instead, keeping the audit log,
+    /// policy, TSIG keys and other settings
+    pub fn with_resolver(mut self, resolver: Arc<MdnsResolver>, zones: Arc<ZoneRegistry>) -> Self {
+        self.engine = self.engine.with_resolver(resolver, zones);
+        self
+    }
+
+    /// Record every answered query in `audit`
+    #[cfg(not(feature = "minimal"))]
+    pub fn with_audit(mut self, audit: Arc<AuditLog>) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Answer names in the static zones of `authoritative` from their zone files
+    pub fn with_authoritative(mut self, authoritative: Arc<AuthoritativeZones>) -> Self {
+        self.authoritative = Some(authoritative);
+        self
+    }
+
+    /// Verify TSIG-signed queries against `tsig` and sign their answers
+    pub fn with_tsig(mut self, tsig: Arc<TsigKeyring>) -> Self {
+        self.tsig = Some(tsig);
+        self
+    }
+
+    /// Answer AXFR and IXFR for the discovery zones to the clients `axfr` allows
+    pub fn with_axfr(mut self, axfr: Arc<ZoneTransfers>) -> Self {
+        self.axfr = Some(axfr);
+        self
+    }
+
+    /// Apply the response policy in `policy` before answering
+    pub fn with_policy(mut self, policy: Arc<PolicyStore>) -> Self {
+        self.engine = self.engine.with_policy(policy);
+        self
+    }
+
+    /// Forward queries to `peers` when the local mDNS query fails
+    #[cfg(not(feature = "minimal"))]
+    pub fn with_peers(mut self, peers: Arc<PeerSet>) -> Self {
+        self.engine = self.engine.with_peers(peers);
+        self
+    }
+
+    /// Answer A/AAAA for this proxy's own name (the NS target) from `own_addresses`
+    pub fn with_own_addresses(mut self, own_addresses: Arc<OwnAddresses>) -> Self {
+        self.engine = self.engine.with_own_addresses(own_addresses);
+        self
+    }
+
+    /// Withhold record types from the clients `record_acls` restricts
+    pub fn with_record_acls(mut self, record_acls: Arc<RecordAcls>) -> Self {
+        self.engine = self.engine.with_record_acls(record_acls);
+        self
+    }
+
+    /// Answer no more queries at once than `admission` admits, turning the rest
+    /// away with `overload_response`
+    pub fn with_admission(mut self, admission: Arc<Admission>, overload_response: OverloadResponse) -> Self {
+        self.admission = Some((admission, overload_response));
+        self
+    }
+
+    /// Time every answer against the latency objectives of `slo`
+    pub fn with_slo(mut self, slo: Arc<SloTracker>) -> Self {
+        self.slo = Some(slo);
+        self
+    }
+
+    /// Suppress unusable records as configured (e.g. for a specific client address)
+    pub fn with_suppression(mut self, suppression_config: RecordSuppressionConfig) -> Self {
+        self.engine = self.engine.with_suppression(suppression_config);
+        self
+    }
+
+    pub fn engine(&self) -> &QueryEngine {
+        &self.engine
+    }
+
     /// Check if the query should be handled by this proxy
     pub fn should_handle(&self, name: &Name) -> bool {
-        should_handle_domain(&name.to_utf8(), &self.discovery_domain)
+        self.engine.should_handle(name)
     }
 
-    /// Handle administrative queries that don't need mDNS forwarding
-    /// Returns Some(records) if this is an administrative query, None otherwise
-    fn handle_admin_query(&self, name: &Name, record_type: RecordType) -> Option<Vec<hickory_proto::rr::Record>> {
-        // REQ-6.5.1/6.5.2: Domain enumeration queries (PTR for b/db/lb._dns-sd._udp)
-        if is_domain_enumeration_query(name, record_type) {
-            info!("Handling domain enumeration query for {}", name);
-            return Some(generate_domain_enumeration_records(name, &self.zone_apex));
+    /// Run one question through the answer pipeline without a socket, returning the
+    /// answer and additional sections. Used by `doctor`.
+    pub async fn lookup(
+        &self,
+        name: &Name,
+        record_type: RecordType,
+        client: SocketAddr,
+    ) -> Result<(Vec<Record>, Vec<Record>), ResponseCode> {
+        let answer = self
+            .engine
+            .resolve(name, record_type, &ClientMeta::new(client, Protocol::Udp))
+            .await;
+        if answer.response_code != ResponseCode::NoError {
+            return Err(answer.response_code);
         }
+        Ok((answer.answers, answer.additionals))
+    }
 
-        // REQ-6.4.1-6.4.8: Administrative SRV queries
-        if is_admin_srv_query(name, record_type) {
-            info!("Handling administrative SRV query for {}", name);
-            if is_negative_admin_srv_query(name) {
-                // Return empty for unsupported services (DNS Update, LLQ, DNS Push)
-                return Some(Vec::new());
+    /// Answer a single request. Every failure is reported as a response code,
+    /// never a panic, so a bad request cannot take down the serving task.
+    async fn answer(&self, request: &Request) -> Answer {
+        match parse_dns_request(request) {
+            Ok(info) => {
+                self.engine
+                    .resolve(info.query.name(), info.query.query_type(), &ClientMeta::from_request(request))
+                    .await
             }
-            // If we supported LLQ/DNS Push, we'd return positive records here
-            return Some(Vec::new());
+            Err(response_code) => Answer::error(response_code),
         }
+    }
+}
 
-        // REQ-6.3.1: Zone apex SOA query
-        if record_type == RecordType::SOA && is_zone_apex_query(name, &self.zone_apex) {
-            info!("Handling zone apex SOA query");
-            return Some(vec![generate_soa_record(name, &self.zone_apex)]);
-        }
+#[async_trait::async_trait]
+impl RequestHandler for MdnsDnsHandler {
+    async fn handle_request<R: ResponseHandler>(
+        &self,
+        request: &Request,
+        response_handle: R,
+    ) -> ResponseInfo {
+        metrics::inc(&metrics::metrics().requests);
+        let received = std::time::Instant::now();
 
-        // REQ-6.2.1: Zone apex NS query
-        if record_type == RecordType::NS && is_zone_apex_query(name, &self.zone_apex) {
-            info!("Handling zone apex NS query");
-            return Some(vec![generate_ns_record(name, &self.zone_apex)]);
-        }
+        // Held until the query is answered
+        let _permit = match &self.admission {
+            Some((admission, overload_response)) => match admission.admit().await {
+                Some(permit) => Some(permit),
+                None => {
+                    metrics::inc(&metrics::metrics().queries_overloaded);
+                    debug!("Overloaded, turning away the query from {}", request.src());
+                    let response_code = match overload_response {
+                        OverloadResponse::ServFail => ResponseCode::ServFail,
+                        OverloadResponse::Refused => ResponseCode::Refused,
+                    };
+                    return self.reply(request, response_handle, response_code).await;
+                }
+            },
+            None => None,
+        };
 
-        // REQ-6.3.2-4: NS/DS/SOA query below zone apex - immediate negative answer
-        if is_delegation_query_below_apex(name, record_type, &self.zone_apex) {
-            debug!("NS/DS/SOA query below zone apex, returning empty");
-            return Some(Vec::new());
+        let Some(tsig) = &self.tsig else {
+            return self.handle(request, response_handle, 0, received).await;
+        };
+        match tsig.verify(request) {
+            Verification::Unsigned if tsig.requires_signature(request.src().ip()) => {
+                metrics::inc(&metrics::metrics().tsig_unsigned_refused);
+                debug!("Refusing unsigned query from {}", request.src());
+                self.reply(request, response_handle, ResponseCode::Refused).await
+            }
+            Verification::Unsigned => self.handle(request, response_handle, 0, received).await,
+            Verification::Verified(signer) => {
+                metrics::inc(&metrics::metrics().tsig_verified);
+                let reserve = signer.record_len();
+                self.handle(request, SigningResponder::new(response_handle, signer), reserve, received).await
+            }
+            Verification::Rejected(signer) => {
+                metrics::inc(&metrics::metrics().tsig_rejected);
+                debug!("TSIG error {} for the query from {}", signer.error(), request.src());
+                let response_handle = SigningResponder::new(response_handle, signer);
+                self.reply(request, response_handle, ResponseCode::NotAuth).await
+            }
+            Verification::FormErr => {
+                metrics::inc(&metrics::metrics().tsig_rejected);
+                self.reply(request, response_handle, ResponseCode::FormErr).await
+            }
         }
-
-        None
     }
 }
 
-#[async_trait::async_trait]
-impl RequestHandler for MdnsDnsHandler {
-    async fn handle_request<R: ResponseHandler>(
+impl MdnsDnsHandler {
+    /// Answer `request`, received at `received`, keeping `reserve` bytes free
+    /// under the UDP size limit for a TSIG record added on the way out
+    async fn handle<R: ResponseHandler>(
         &self,
         request: &Request,
         mut response_handle: R,
+        reserve: usize,
+        received: std::time::Instant,
     ) -> ResponseInfo {
-        // Parse request and build initial response components
-        let (mut header, builder) = match parse_dns_request(request) {
-            Some((h, b)) => (h, b),
-            None => {
-                let mut header = Header::new();
-                header.set_response_code(ResponseCode::FormErr);
-                return ResponseInfo::from(header);
+        // Names in a static zone are answered by hickory's catalog from the zone file
+        if let Some(authoritative) = &self.authoritative
+            && let Some(query) = request.queries().first()
+        {
+            let name = Name::from(query.name());
+            if authoritative.serves(&name, self.engine.zone_for(&name).as_ref()) {
+                return authoritative.catalog().handle_request(request, response_handle).await;
+            }
+        }
+
+        let mut header = response_header(request);
+        let mut builder = MessageResponseBuilder::from_message_request(request);
+        let edns_udp_payload = self.engine.resolver().config().server.edns_udp_payload;
+
+        // Only EDNS version 0 is implemented (RFC 6891 Section 6.1.3)
+        if let Some(request_edns) = request.edns()
+            && request_edns.version() > EDNS_VERSION
+        {
+            debug!("EDNS version {} from {}, answering BADVERS", request_edns.version(), request.src());
+            header.set_response_code(ResponseCode::BADVERS);
+            builder.edns(response_edns(request_edns, edns_udp_payload, None));
+            return response_handle
+                .send_response(builder.build_no_records(header))
+                .await
+                .unwrap_or_else(|e| {
+                    error!("Error sending response: {}", e);
+                    ResponseInfo::from(header)
+                });
+        }
+
+        if let Some(query) = request.queries().first()
+            && matches!(query.query_type(), RecordType::AXFR | RecordType::IXFR)
+        {
+            return self.transfer(request, response_handle, reserve).await;
+        }
+
+        // Queries asking for a trace are answered inside a span that lifts the log level
+        let span = query_trace::span(request, self.engine.resolver().config().debug.query_tracing);
+        span.in_scope(|| debug!("Query from {}: {:?}", request.src(), request.queries()));
+
+        // A panic while answering one query must not take the server down with it:
+        // catch it here, count it and answer SERVFAIL
+        let outcome = AssertUnwindSafe(self.answer(request).instrument(span.clone()))
+            .catch_unwind()
+            .await;
+        let mut answer = match outcome {
+            Ok(answer) => answer,
+            Err(panic) => {
+                metrics::inc(&metrics::metrics().handler_panics);
+                error!("Panic while handling request {}: {}", request.id(), panic_message(&panic));
+                Answer::error(ResponseCode::ServFail)
             }
         };
+        header.set_response_code(answer.response_code);
 
-        // Check if request was malformed (FormErr code set by parse_dns_request)
-        if header.response_code() == ResponseCode::FormErr {
-            let response = builder.build_no_records(header);
-            return response_handle.send_response(response).await.unwrap_or_else(|e| {
-                error!("Error sending response: {}", e);
-                ResponseInfo::from(header)
+        // Only answer with EDNS (and so with an EDE) when the client used it
+        let mut edns = request
+            .edns()
+            .map(|request_edns| response_edns(request_edns, edns_udp_payload, answer.extended_error.as_ref()));
+        if request.protocol() == Protocol::Udp {
+            let limit = edns.as_ref().map_or(CLASSIC_UDP_PAYLOAD, |edns| edns.max_payload());
+            fit_udp(request, &mut header, edns.as_ref(), &mut answer, usize::from(limit).saturating_sub(reserve));
+        }
+        if let Some(edns) = edns.as_mut()
+            && let Some(block) = self.padding_block(request)
+        {
+            let len = encoded_len(request, header, Some(edns), &answer.answers, &answer.authority, &answer.additionals);
+            pad_response(edns, len, block);
+        }
+        if let Some(edns) = edns {
+            builder.edns(edns);
+        }
+
+        #[cfg(not(feature = "minimal"))]
+        if let Some(audit) = &self.audit
+            && let Some(query) = request.queries().first()
+        {
+            audit.record(&AuditEvent {
+                client: ClientIdentity::from_request(request),
+                protocol: request.protocol().to_string(),
+                name: presentation(query.name()),
+                query_type: query.query_type(),
+                response_code: header.response_code(),
+                answers: answer.answers.len(),
             });
         }
 
-        // Get request info for querying
-        let request_message = request.request_info().unwrap();
+        span.in_scope(|| {
+            debug!(
+                "Response {:?}: answers {:?}, authority {:?}, additionals {:?}{}",
+                header.response_code(),
+                answer.answers,
+                answer.authority,
+                answer.additionals,
+                if answer.drop { " (dropped)" } else { "" }
+            )
+        });
 
-        // Check if we should handle this query
-        if !self.should_handle(request_message.query.name()) {
-            debug!("Query not for discovery domain {}, returning NXDOMAIN", self.discovery_domain);
-            header.set_response_code(ResponseCode::NXDomain);
-            let response = builder.build_no_records(header);
-            return response_handle.send_response(response).await.unwrap_or_else(|e| {
-                error!("Error sending response: {}", e);
-                ResponseInfo::from(header)
-            });
+        if answer.drop {
+            return ResponseInfo::from(header);
         }
 
-        let query_name = request_message.query.name();
-        let query_type = request_message.query.query_type();
+        let response = builder.build(
+            header,
+            answer.answers.iter(),
+            answer.authority.iter(),
+            std::iter::empty(),
+            answer.additionals.iter(),
+        );
+        // Encoding happens as the response is sent
+        let started = std::time::Instant::now();
+        let info = response_handle.send_response(response).await.unwrap_or_else(|e| {
+            error!("Error sending response: {}", e);
+            ResponseInfo::from(header)
+        });
+        metrics::observe(&metrics::metrics().serialize_time, started.elapsed());
+        if let Some(slo) = &self.slo
+            && let Some(class) = slo::Class::of(answer.source, answer.response_code)
+        {
+            slo.record(class, received.elapsed());
+        }
+        info
+    }
 
-        // RFC 8766 Section 6: Check for administrative queries that don't need mDNS
-        if let Some(admin_records) = self.handle_admin_query(query_name, query_type) {
-            header.set_response_code(ResponseCode::NoError);
-            
-            if admin_records.is_empty() {
-                let response = builder.build_no_records(header);
-                return response_handle.send_response(response).await.unwrap_or_else(|e| {
-                    error!("Error sending response: {}", e);
-                    ResponseInfo::from(header)
-                });
-            } else {
-                let response = builder.build(
-                    header,
-                    admin_records.iter(),
-                    std::iter::empty(),
-                    std::iter::empty(),
-                    std::iter::empty(),
-                );
-                return response_handle.send_response(response).await.unwrap_or_else(|e| {
-                    error!("Error sending response: {}", e);
-                    ResponseInfo::from(header)
-                });
-            }
+    /// Block size to pad the response to `request` to: set for queries over
+    /// an encrypted transport whose listener pads them (`server.tls.padding`)
+    fn padding_block(&self, request: &Request) -> Option<usize> {
+        if !request.protocol().is_encrypted() {
+            return None;
+        }
+        let config = self.engine.resolver().config();
+        let tls = config.server.tls.as_ref()?;
+        let padded_query = request.edns().is_some_and(|edns| edns.option(EdnsCode::Padding).is_some());
+        match tls.padding {
+            EdnsPadding::Off => None,
+            EdnsPadding::Requested if !padded_query => None,
+            EdnsPadding::Requested | EdnsPadding::Always => Some(usize::from(tls.padding_block_bytes)),
         }
+    }
 
-        // Query mDNS for the records
-        let records = self
-            .resolver
-            .query(query_name, query_type)
-            .await;
+    /// Send the discovery zone asked for by an AXFR or IXFR query, in as many
+    /// messages as it takes, or refuse
+    async fn transfer<R: ResponseHandler>(&self, request: &Request, mut response_handle: R, reserve: usize) -> ResponseInfo {
+        let name = request.queries().first().map(|query| Name::from(query.name())).unwrap_or_default();
+        let allowed = self
+            .axfr
+            .as_ref()
+            .is_some_and(|axfr| request.protocol() != Protocol::Udp && axfr.allows(request.src().ip()));
+        if !allowed {
+            metrics::inc(&metrics::metrics().axfr_refused);
+            debug!("Refusing transfer of {} to {} over {}", name, request.src(), request.protocol());
+            return self.reply(request, response_handle, ResponseCode::Refused).await;
+        }
+        let Some(zone_apex) = self.engine.zone_for(&name).filter(|apex| *apex == name) else {
+            return self.reply(request, response_handle, ResponseCode::NotAuth).await;
+        };
 
-        // Build response from mDNS records
-        let (response_code, records_opt) = build_response_from_records(records);
-        header.set_response_code(response_code);
-        
-        if let Some(records) = records_opt {
-            // Apply RFC 8766 Section 5.5.2: Suppress unusable records
-            let filtered_records = filter_suppressed_records(records, &self.suppression_config);
-            
-            if filtered_records.is_empty() {
-                let response = builder.build_no_records(header);
-                response_handle.send_response(response).await.unwrap_or_else(|e| {
-                    error!("Error sending response: {}", e);
-                    ResponseInfo::from(header)
-                })
-            } else {
-                let response = builder.build(
-                    header,
-                    filtered_records.iter(),
-                    std::iter::empty(),
-                    std::iter::empty(),
-                    std::iter::empty(),
-                );
-                response_handle.send_response(response).await.unwrap_or_else(|e| {
-                    error!("Error sending response: {}", e);
-                    ResponseInfo::from(header)
-                })
+        let records = self.engine.zone_transfer(&zone_apex);
+        metrics::inc(&metrics::metrics().axfr_transfers);
+        info!("Transferring {} ({} records) to {}", zone_apex, records.len(), request.src());
+        let mut header = response_header(request);
+        header.set_authoritative(true);
+        let edns = request.edns().map(|request_edns| {
+            response_edns(request_edns, self.engine.resolver().config().server.edns_udp_payload, None)
+        });
+        let mut info = ResponseInfo::from(header);
+        for message in axfr::messages(&records, axfr::MESSAGE_BYTES.saturating_sub(reserve)) {
+            let mut builder = MessageResponseBuilder::from_message_request(request);
+            if let Some(edns) = &edns {
+                builder.edns(edns.clone());
             }
-        } else {
-            let response = builder.build_no_records(header);
-            response_handle.send_response(response).await.unwrap_or_else(|e| {
+            let response = builder.build(header, message.iter(), std::iter::empty(), std::iter::empty(), std::iter::empty());
+            match response_handle.send_response(response).await {
+                Ok(sent) => info = sent,
+                Err(e) => {
+                    error!("Error sending transfer of {}: {}", zone_apex, e);
+                    break;
+                }
+            }
+        }
+        info
+    }
+
+    /// Answer `request` with `response_code` and no records
+    async fn reply<R: ResponseHandler>(&self, request: &Request, mut response_handle: R, response_code: ResponseCode) -> ResponseInfo {
+        let mut header = response_header(request);
+        header.set_response_code(response_code);
+        let mut builder = MessageResponseBuilder::from_message_request(request);
+        if let Some(request_edns) = request.edns() {
+            let edns_udp_payload = self.engine.resolver().config().server.edns_udp_payload;
+            builder.edns(response_edns(request_edns, edns_udp_payload, None));
+        }
+        response_handle
+            .send_response(builder.build_no_records(header))
+            .await
+            .unwrap_or_else(|e| {
                 error!("Error sending response: {}", e);
                 ResponseInfo::from(header)
             })
+    }
+}
+
+/// Fit a UDP answer into `limit` bytes, the client's EDNS payload size or 512 without EDNS
+/// (hickory would allow 4096). Additionals go first, as they are optional (RFC 2181
+/// Section 9): the most that fit are kept, in order, so leading instances of a browse
+/// keep their whole bundle. If the rest still does not fit, authority records are
+/// dropped and, if that is not enough, answers trimmed to the most that fit, with TC
+/// set so the client retries over TCP.
+pub(super) fn fit_udp(request: &Request, header: &mut Header, edns: Option<&Edns>, answer: &mut Answer, limit: usize) {
+    let fits = |header: Header, answers: &[Record], authority: &[Record], additionals: &[Record]| {
+        encoded_len(request, header, edns, answers, authority, additionals) <= limit
+    };
+    if fits(*header, &answer.answers, &answer.authority, &answer.additionals) {
+        return;
+    }
+    if fits(*header, &answer.answers, &answer.authority, &[]) {
+        let (mut fitting, mut too_many) = (0, answer.additionals.len());
+        while too_many - fitting > 1 {
+            let mid = (fitting + too_many) / 2;
+            if fits(*header, &answer.answers, &answer.authority, &answer.additionals[..mid]) {
+                fitting = mid;
+            } else {
+                too_many = mid;
+            }
         }
+        let total = answer.additionals.len();
+        debug!("Leaving out {} of {} additional records for {} bytes", total - fitting, total, limit);
+        answer.additionals.truncate(fitting);
+        return;
+    }
+    answer.additionals.clear();
+    let dropped_authority = !answer.authority.is_empty();
+    answer.authority.clear();
+    let total = answer.answers.len();
+    if !fits(*header, &answer.answers, &[], &[]) {
+        // Most answers that fit: the header and question alone always do
+        let (mut fitting, mut too_many) = (0, total);
+        while too_many - fitting > 1 {
+            let mid = (fitting + too_many) / 2;
+            if fits(*header, &answer.answers[..mid], &[], &[]) {
+                fitting = mid;
+            } else {
+                too_many = mid;
+            }
+        }
+        answer.answers.truncate(fitting);
+    }
+    if dropped_authority || answer.answers.len() < total {
+        debug!("Truncating UDP response to {} of {} answers, without authority, for {} bytes", answer.answers.len(), total, limit);
+        metrics::inc(&metrics::metrics().truncated_responses);
+        header.set_truncated(true);
+    }
+}
+
+/// Best-effort text of a caught panic payload
+fn panic_message(panic: &Box<dyn Any + Send>) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
     }
 }
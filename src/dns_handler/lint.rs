@@ -0,0 +1,219 @@
+//! Response validation ("lint mode")
+//!
+//! Development aid that checks each outgoing response against the rules the
+//! proxy is supposed to follow (RFC 8766 Section 5.5.1 TTL cap, answers staying
+//! inside the discovery zone, SOA on negative answers, answer types matching the
+//! question). Violations are logged and, in drop mode, offending records removed.
+
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::{Name, Record, RecordType};
+use std::fmt;
+
+/// Maximum TTL for unicast answers per RFC 8766 Section 5.5.1
+const MAX_TTL: u32 = 10;
+
+/// Response section a violating record was found in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Answer,
+    Authority,
+    Additional,
+}
+
+/// A single rule violation found in an outgoing response
+#[derive(Debug, Clone, PartialEq)]
+pub enum LintViolation {
+    /// Record TTL exceeds the RFC 8766 cap
+    TtlAboveCap { section: Section, record: Record },
+    /// Record owner name lies outside the discovery zone
+    OutsideZone { section: Section, record: Record },
+    /// Answer record type does not match the question type
+    UnexpectedType { record: Record },
+    /// Negative answer carries no SOA in the authority section
+    MissingSoa,
+}
+
+impl LintViolation {
+    /// Offending record and its section, for violations tied to a single record
+    fn record(&self) -> Option<(Section, &Record)> {
+        match self {
+            LintViolation::TtlAboveCap { section, record } => Some((*section, record)),
+            LintViolation::OutsideZone { section, record } => Some((*section, record)),
+            LintViolation::UnexpectedType { record } => Some((Section::Answer, record)),
+            LintViolation::MissingSoa => None,
+        }
+    }
+}
+
+impl fmt::Display for LintViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LintViolation::TtlAboveCap { section, record } => write!(
+                f,
+                "{:?} record {} {:?} has TTL {} above cap of {}",
+                section, record.name(), record.record_type(), record.ttl(), MAX_TTL
+            ),
+            LintViolation::OutsideZone { section, record } => write!(
+                f,
+                "{:?} record {} {:?} is outside the discovery zone",
+                section, record.name(), record.record_type()
+            ),
+            LintViolation::UnexpectedType { record } => write!(
+                f,
+                "answer record {} has type {:?} not matching the question",
+                record.name(), record.record_type()
+            ),
+            LintViolation::MissingSoa => write!(f, "negative answer without SOA in authority section"),
+        }
+    }
+}
+
+/// Check a response about to be sent and return every violation found
+pub fn lint_response(
+    query_name: &Name,
+    query_type: RecordType,
+    response_code: ResponseCode,
+    zone_apex: &Name,
+    answers: &[Record],
+    authority: &[Record],
+    additionals: &[Record],
+) -> Vec<LintViolation> {
+    let mut violations = Vec::new();
+
+    let sections = [
+        (Section::Answer, answers),
+        (Section::Authority, authority),
+        (Section::Additional, additionals),
+    ];
+    for (section, records) in sections {
+        for record in records {
+            if record.ttl() > MAX_TTL {
+                violations.push(LintViolation::TtlAboveCap { section, record: record.clone() });
+            }
+            if !zone_apex.zone_of(record.name()) {
+                violations.push(LintViolation::OutsideZone { section, record: record.clone() });
+            }
+        }
+    }
+
+    if query_type != RecordType::ANY {
+        for record in answers {
+            let rtype = record.record_type();
+            if rtype != query_type && rtype != RecordType::CNAME {
+                violations.push(LintViolation::UnexpectedType { record: record.clone() });
+            }
+        }
+    }
+
+    let negative = response_code == ResponseCode::NXDomain
+        || (response_code == ResponseCode::NoError && answers.is_empty());
+    if negative
+        && zone_apex.zone_of(query_name)
+        && !authority.iter().any(|r| r.record_type() == RecordType::SOA)
+    {
+        violations.push(LintViolation::MissingSoa);
+    }
+
+    violations
+}
+
+/// Remove the records named by `violations` from the answer and additional sections
+pub fn drop_violating_records(
+    violations: &[LintViolation],
+    answers: &mut Vec<Record>,
+    additionals: &mut Vec<Record>,
+) {
+    // Record equality ignores TTL, so compare it explicitly
+    let same = |a: &Record, b: &Record| a == b && a.ttl() == b.ttl();
+    for violation in violations {
+        match violation.record() {
+            Some((Section::Answer, record)) => answers.retain(|r| !same(r, record)),
+            Some((Section::Additional, record)) => additionals.retain(|r| !same(r, record)),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::RData;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn zone() -> Name {
+        Name::from_utf8("mdns.home.arpa.").unwrap()
+    }
+
+    fn a_record(name: &str, ttl: u32) -> Record {
+        Record::from_rdata(
+            Name::from_utf8(name).unwrap(),
+            ttl,
+            RData::A(hickory_proto::rr::rdata::A::from(Ipv4Addr::new(192, 168, 1, 10))),
+        )
+    }
+
+    #[test]
+    fn test_clean_response_has_no_violations() {
+        let name = Name::from_utf8("host.mdns.home.arpa.").unwrap();
+        let answers = vec![a_record("host.mdns.home.arpa.", 10)];
+        let violations = lint_response(&name, RecordType::A, ResponseCode::NoError, &zone(), &answers, &[], &[]);
+        assert!(violations.is_empty());
+    }
+
+    #[test]
+    fn test_ttl_above_cap() {
+        let name = Name::from_utf8("host.mdns.home.arpa.").unwrap();
+        let answers = vec![a_record("host.mdns.home.arpa.", 120)];
+        let violations = lint_response(&name, RecordType::A, ResponseCode::NoError, &zone(), &answers, &[], &[]);
+        assert_eq!(violations.len(), 1);
+        assert!(matches!(violations[0], LintViolation::TtlAboveCap { section: Section::Answer, .. }));
+    }
+
+    #[test]
+    fn test_record_outside_zone() {
+        let name = Name::from_utf8("host.mdns.home.arpa.").unwrap();
+        let additionals = vec![a_record("host.local.", 10)];
+        let answers = vec![a_record("host.mdns.home.arpa.", 10)];
+        let violations = lint_response(&name, RecordType::A, ResponseCode::NoError, &zone(), &answers, &[], &additionals);
+        assert_eq!(violations.len(), 1);
+        assert!(matches!(violations[0], LintViolation::OutsideZone { section: Section::Additional, .. }));
+    }
+
+    #[test]
+    fn test_mixed_types_in_a_answer() {
+        let name = Name::from_utf8("host.mdns.home.arpa.").unwrap();
+        let aaaa = Record::from_rdata(
+            name.clone(),
+            10,
+            RData::AAAA(hickory_proto::rr::rdata::AAAA::from(Ipv6Addr::LOCALHOST)),
+        );
+        let answers = vec![a_record("host.mdns.home.arpa.", 10), aaaa];
+        let violations = lint_response(&name, RecordType::A, ResponseCode::NoError, &zone(), &answers, &[], &[]);
+        assert_eq!(violations.len(), 1);
+        assert!(matches!(violations[0], LintViolation::UnexpectedType { .. }));
+    }
+
+    #[test]
+    fn test_negative_answer_without_soa() {
+        let name = Name::from_utf8("missing.mdns.home.arpa.").unwrap();
+        let violations = lint_response(&name, RecordType::A, ResponseCode::NoError, &zone(), &[], &[], &[]);
+        assert_eq!(violations, vec![LintViolation::MissingSoa]);
+
+        let soa = crate::dns_handler::admin_records::generate_soa_record(&zone(), &zone(), 10);
+        let violations = lint_response(&name, RecordType::A, ResponseCode::NoError, &zone(), &[], &[soa], &[]);
+        assert!(violations.is_empty());
+    }
+
+    #[test]
+    fn test_drop_violating_records() {
+        let name = Name::from_utf8("host.mdns.home.arpa.").unwrap();
+        let mut answers = vec![a_record("host.mdns.home.arpa.", 120), a_record("host.mdns.home.arpa.", 10)];
+        let mut additionals = vec![a_record("other.local.", 10)];
+        let violations = lint_response(&name, RecordType::A, ResponseCode::NoError, &zone(), &answers, &[], &additionals);
+
+        drop_violating_records(&violations, &mut answers, &mut additionals);
+        assert_eq!(answers.len(), 1);
+        assert_eq!(answers[0].ttl(), 10);
+        assert!(additionals.is_empty());
+    }
+}
@@ -1,7 +1,14 @@
+pub mod axfr; // Zone transfers of the discovery zones
 mod handler;
+pub mod engine; // Transport-agnostic query decisions
 pub mod utils; // Make public for testing
 pub mod admin_records; // RFC 8766 Section 6 administrative records
+pub mod lint;
+pub mod record_acl; // Record types each group of clients may receive
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
 
+pub use engine::{Answer, ClientMeta, QueryEngine, FRESH_OPTION};
 pub use handler::MdnsDnsHandler;
 pub use utils::should_handle_domain;
 
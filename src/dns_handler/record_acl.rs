@@ -0,0 +1,141 @@
+//! Record-level access control
+//!
+//! Each `[[record_acls]]` entry names client networks and the record types
+//! they may receive. A guest network limited to PTR and TXT learns that a
+//! printer exists (e.g. for a portal to show) but gets no SRV, A or AAAA to
+//! connect to it directly. The first entry matching the client applies;
+//! clients no entry matches are unrestricted. Records of other types are
+//! removed from the answer and additional sections once the rest of the
+//! engine has decided the answer. SOA and NS always pass, so negative answers
+//! and delegations stay valid.
+//! Zone transfers are governed by `[axfr] allow_from` instead.
+
+use crate::config::RecordAclConfig;
+use crate::netwatch::LocalNetwork;
+use hickory_proto::rr::{Record, RecordType};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use super::engine::Answer;
+
+/// One entry: the record types clients in `clients` receive
+#[derive(Debug, Clone)]
+struct RecordAcl {
+    clients: Vec<LocalNetwork>,
+    allow: Vec<RecordType>,
+}
+
+/// Record types each group of clients may receive
+#[derive(Debug, Clone, Default)]
+pub struct RecordAcls {
+    entries: Vec<RecordAcl>,
+}
+
+impl RecordAcls {
+    /// Entries of `[[record_acls]]`; fails on a client that is not a network or an unknown record type
+    pub fn from_config(config: &[RecordAclConfig]) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let mut entries = Vec::with_capacity(config.len());
+        for entry in config {
+            let clients = entry
+                .clients
+                .iter()
+                .map(|client| client.parse().map_err(|e| format!("[[record_acls]] clients: {}", e)))
+                .collect::<Result<Vec<LocalNetwork>, String>>()?;
+            if clients.is_empty() {
+                return Err("[[record_acls]] clients must not be empty".into());
+            }
+            let allow = entry
+                .allow
+                .iter()
+                .map(|name| {
+                    RecordType::from_str(&name.to_ascii_uppercase())
+                        .map_err(|_| format!("[[record_acls]] allow: unknown record type {}", name))
+                })
+                .collect::<Result<Vec<RecordType>, String>>()?;
+            entries.push(RecordAcl { clients, allow });
+        }
+        Ok(Self { entries })
+    }
+
+    /// Record types `client` may receive; None when no entry restricts it
+    pub fn allowed(&self, client: IpAddr) -> Option<&[RecordType]> {
+        self.entries
+            .iter()
+            .find(|entry| entry.clients.iter().any(|network| network.contains(&client)))
+            .map(|entry| entry.allow.as_slice())
+    }
+
+    /// Remove the records `client` may not receive from `answer`; returns how many were removed
+    pub fn apply(&self, client: IpAddr, answer: &mut Answer) -> usize {
+        let Some(allow) = self.allowed(client) else {
+            return 0;
+        };
+        let permitted = |record: &Record| {
+            matches!(record.record_type(), RecordType::SOA | RecordType::NS) || allow.contains(&record.record_type())
+        };
+        let before = answer.answers.len() + answer.additionals.len();
+        answer.answers.retain(permitted);
+        answer.additionals.retain(permitted);
+        before - answer.answers.len() - answer.additionals.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::rdata::{A, PTR, SRV, TXT};
+    use hickory_proto::rr::{Name, RData};
+
+    fn record(name: &str, rdata: RData) -> Record {
+        Record::from_rdata(Name::from_utf8(name).unwrap(), 120, rdata)
+    }
+
+    #[test]
+    fn test_guests_see_services_but_not_addresses() {
+        let acls = RecordAcls::from_config(&[
+            RecordAclConfig {
+                clients: vec!["192.168.50.0/24".to_string()],
+                allow: vec!["ptr".to_string(), "TXT".to_string()],
+            },
+            RecordAclConfig {
+                clients: vec!["192.168.0.0/16".to_string()],
+                allow: vec!["PTR".to_string(), "TXT".to_string(), "SRV".to_string()],
+            },
+        ])
+        .unwrap();
+        let guest: IpAddr = "192.168.50.7".parse().unwrap();
+        assert_eq!(acls.allowed(guest), Some(&[RecordType::PTR, RecordType::TXT][..]));
+        assert_eq!(acls.allowed("192.168.1.7".parse().unwrap()).map(<[_]>::len), Some(3));
+        assert_eq!(acls.allowed("10.0.0.7".parse().unwrap()), None);
+
+        let printer = Name::from_utf8("Printer._ipp._tcp.mdns.home.arpa.").unwrap();
+        let full = Answer {
+            answers: vec![record("_ipp._tcp.mdns.home.arpa.", RData::PTR(PTR(printer.clone())))],
+            additionals: vec![
+                record("Printer._ipp._tcp.mdns.home.arpa.", RData::TXT(TXT::new(vec!["rp=ipp".to_string()]))),
+                record(
+                    "Printer._ipp._tcp.mdns.home.arpa.",
+                    RData::SRV(SRV::new(0, 0, 631, Name::from_utf8("printer.mdns.home.arpa.").unwrap())),
+                ),
+                record("printer.mdns.home.arpa.", RData::A(A::new(192, 168, 1, 20))),
+            ],
+            ..Answer::default()
+        };
+
+        let mut answer = full.clone();
+        assert_eq!(acls.apply(guest, &mut answer), 2);
+        assert_eq!(answer.answers.len(), 1);
+        let types: Vec<_> = answer.additionals.iter().map(Record::record_type).collect();
+        assert_eq!(types, vec![RecordType::TXT]);
+
+        let mut answer = full.clone();
+        assert_eq!(acls.apply("10.0.0.7".parse().unwrap(), &mut answer), 0);
+        assert_eq!(answer.additionals.len(), 3);
+
+        assert!(RecordAcls::from_config(&[RecordAclConfig {
+            clients: vec!["192.168.50.0/24".to_string()],
+            allow: vec!["NOPE".to_string()],
+        }])
+        .is_err());
+    }
+}
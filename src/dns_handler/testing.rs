@@ -0,0 +1,88 @@
+//! Test seam for [`MdnsDnsHandler`](super::MdnsDnsHandler) requests
+//!
+//! [`CapturingResponseHandler`] stands in for hickory's response handle: it
+//! encodes the response under the same size limit the real handle applies for
+//! the protocol, so truncation happens as it would on the wire, and keeps the
+//! bytes instead of sending them. Available to this crate's tests and, with the
+//! `test-util` feature, to library users testing their own setups.
+
+use hickory_proto::op::Message;
+use hickory_proto::rr::Record;
+use hickory_proto::serialize::binary::{BinDecodable, BinEncodable, BinEncoder};
+use hickory_proto::xfer::Protocol;
+use hickory_server::authority::{MessageRequest, MessageResponse};
+use hickory_server::server::{Request, ResponseHandler, ResponseInfo};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+/// Response handler that records the encoded response. Clones share the
+/// recording, so keep one clone and hand the other to `handle_request`.
+#[derive(Clone)]
+pub struct CapturingResponseHandler {
+    protocol: Protocol,
+    sent: Arc<Mutex<Option<Vec<u8>>>>,
+}
+
+impl Default for CapturingResponseHandler {
+    fn default() -> Self {
+        Self::new(Protocol::Udp)
+    }
+}
+
+impl CapturingResponseHandler {
+    /// Capture as if answering over `protocol`: UDP responses are limited to
+    /// the EDNS payload size (4096 bytes without EDNS), others are not
+    pub fn new(protocol: Protocol) -> Self {
+        Self {
+            protocol,
+            sent: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Encoded response, if one was sent; taking it clears the recording
+    pub fn take_bytes(&self) -> Option<Vec<u8>> {
+        self.sent.lock().unwrap().take()
+    }
+
+    /// Decoded response, if one was sent
+    pub fn take_response(&self) -> Option<Message> {
+        Message::from_vec(&self.take_bytes()?).ok()
+    }
+}
+
+#[async_trait::async_trait]
+impl ResponseHandler for CapturingResponseHandler {
+    async fn send_response<'a>(
+        &mut self,
+        response: MessageResponse<
+            '_,
+            'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+        >,
+    ) -> std::io::Result<ResponseInfo> {
+        // Same limits as hickory's ResponseHandle
+        let max_size = match self.protocol {
+            Protocol::Udp => response
+                .get_edns()
+                .as_ref()
+                .map_or(hickory_proto::udp::MAX_RECEIVE_BUFFER_SIZE as u16, |edns| edns.max_payload()),
+            _ => u16::MAX,
+        };
+        let mut bytes = Vec::new();
+        let mut encoder = BinEncoder::new(&mut bytes);
+        encoder.set_max_size(max_size);
+        let info = response.destructive_emit(&mut encoder)?;
+        *self.sent.lock().unwrap() = Some(bytes);
+        Ok(info)
+    }
+}
+
+/// Server-side request for `query`, as if received from `src` over `protocol`
+pub fn request(query: &Message, src: SocketAddr, protocol: Protocol) -> Request {
+    let bytes = query.to_bytes().expect("query encodes");
+    let message = MessageRequest::from_bytes(&bytes).expect("query decodes");
+    Request::new(message, src, protocol)
+}
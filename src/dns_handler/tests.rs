@@ -1,4 +1,5 @@
 use super::*;
+use crate::dns_handler::testing::{self, CapturingResponseHandler};
 use crate::dns_handler::utils::build_response_from_records;
 use crate::mdns_resolver::MdnsResolver;
 use hickory_proto::op::ResponseCode;
@@ -126,7 +127,7 @@ fn test_dns_handler_creation() {
     let handler = MdnsDnsHandler::new(Arc::new(resolver), discovery_domain.clone());
     
     // Test that handler correctly identifies domains
-    let name = hickory_proto::rr::Name::from_utf8(&format!("test.{}", discovery_domain)).unwrap();
+    let name = hickory_proto::rr::Name::from_utf8(format!("test.{}", discovery_domain)).unwrap();
     assert!(handler.should_handle(&name));
     
     let name = hickory_proto::rr::Name::from_utf8("test.com").unwrap();
@@ -199,7 +200,7 @@ fn test_build_response_from_records_error() {
     use std::io;
 
     let error: Box<dyn std::error::Error + Send + Sync> = 
-        Box::new(io::Error::new(io::ErrorKind::Other, "test error"));
+        Box::new(io::Error::other("test error"));
     let result: Result<Vec<hickory_proto::rr::Record>, _> = Err(error);
 
     let (response_code, records_opt) = build_response_from_records(result);
@@ -208,6 +209,47 @@ fn test_build_response_from_records_error() {
     assert!(records_opt.is_none());
 }
 
+#[test]
+fn test_ttl_jitter_stays_within_bounds() {
+    use crate::dns_handler::utils::{apply_ttl_jitter, ttl_jitter_offset};
+    use hickory_proto::rr::rdata::A;
+    use hickory_proto::rr::{Name, RData, Record};
+
+    assert_eq!(ttl_jitter_offset(0), 0);
+    for _ in 0..100 {
+        assert!(ttl_jitter_offset(3) <= 3);
+    }
+
+    let name = Name::from_utf8("printer.mdns.home.arpa.").unwrap();
+    let mut records = [
+        Record::from_rdata(name.clone(), 10, RData::A(A::new(192, 168, 1, 20))),
+        Record::from_rdata(name.clone(), 2, RData::A(A::new(192, 168, 1, 21))),
+        Record::from_rdata(name, 0, RData::A(A::new(192, 168, 1, 22))),
+    ];
+    apply_ttl_jitter(records.iter_mut(), 3);
+    assert_eq!(records[0].ttl(), 7);
+    assert_eq!(records[1].ttl(), 1);
+    assert_eq!(records[2].ttl(), 0);
+}
+
+#[test]
+fn test_ttl_floor_raises_short_ttls() {
+    use crate::dns_handler::utils::apply_ttl_floor;
+    use hickory_proto::rr::rdata::A;
+    use hickory_proto::rr::{Name, RData, Record};
+
+    let name = Name::from_utf8("printer.mdns.home.arpa.").unwrap();
+    let mut records = [
+        Record::from_rdata(name.clone(), 10, RData::A(A::new(192, 168, 1, 20))),
+        Record::from_rdata(name.clone(), 2, RData::A(A::new(192, 168, 1, 21))),
+        Record::from_rdata(name, 0, RData::A(A::new(192, 168, 1, 22))),
+    ];
+    apply_ttl_floor(records.iter_mut(), 5);
+    assert_eq!(records[0].ttl(), 10);
+    assert_eq!(records[1].ttl(), 5);
+    assert_eq!(records[2].ttl(), 0);
+}
+
 #[test]
 fn test_build_response_from_records_multiple_records() {
     use hickory_proto::rr::{Name, RData, Record};
@@ -230,3 +272,1169 @@ fn test_build_response_from_records_multiple_records() {
     assert!(records_opt.is_some());
     assert_eq!(records_opt.unwrap().len(), 2);
 }
+
+/// Feed a raw packet through the handler and decode what it sent back
+async fn handle_raw_packet(packet: &[u8]) -> hickory_proto::op::Message {
+    use hickory_proto::serialize::binary::BinDecodable;
+    use hickory_server::server::RequestHandler;
+
+    let message = hickory_server::authority::MessageRequest::from_bytes(packet).unwrap();
+    let request = hickory_server::server::Request::new(
+        message,
+        "127.0.0.1:53000".parse().unwrap(),
+        hickory_proto::xfer::Protocol::Udp,
+    );
+
+    let resolver = MdnsResolver::new(Arc::new(crate::config::Config::default())).unwrap();
+    let handler = MdnsDnsHandler::new(Arc::new(resolver), "mdns.home.arpa.".to_string());
+    let response_handle = CapturingResponseHandler::default();
+    handler.handle_request(&request, response_handle.clone()).await;
+
+    let bytes = response_handle.take_bytes().expect("no response sent");
+    hickory_proto::op::Message::from_vec(&bytes).unwrap()
+}
+
+/// DNS header with the given flags word and question count, no other sections
+fn raw_header(id: u16, flags: u16, qdcount: u16) -> Vec<u8> {
+    let mut packet = Vec::new();
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&flags.to_be_bytes());
+    packet.extend_from_slice(&qdcount.to_be_bytes());
+    packet.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+    packet
+}
+
+/// Question for `printer.mdns.home.arpa. A IN`
+fn raw_question() -> Vec<u8> {
+    let mut question = Vec::new();
+    for label in ["printer", "mdns", "home", "arpa"] {
+        question.push(label.len() as u8);
+        question.extend_from_slice(label.as_bytes());
+    }
+    question.extend_from_slice(&[0, 0, 1, 0, 1]);
+    question
+}
+
+#[tokio::test]
+async fn test_malformed_request_without_question_returns_formerr() {
+    let before = crate::metrics::metrics().snapshot();
+    let response = handle_raw_packet(&raw_header(0x1234, 0x0100, 0)).await;
+    assert_eq!(response.id(), 0x1234);
+    assert_eq!(response.response_code(), ResponseCode::FormErr);
+    assert!(response.answers().is_empty());
+
+    // Counted as a request, not as a panic
+    let after = crate::metrics::metrics().snapshot();
+    if crate::metrics::ENABLED {
+        assert!(after.requests > before.requests);
+    }
+}
+
+#[tokio::test]
+async fn test_malformed_request_with_two_questions_returns_formerr() {
+    let mut packet = raw_header(0x4321, 0x0100, 2);
+    packet.extend(raw_question());
+    packet.extend(raw_question());
+
+    let response = handle_raw_packet(&packet).await;
+    assert_eq!(response.id(), 0x4321);
+    assert_eq!(response.response_code(), ResponseCode::FormErr);
+}
+
+#[tokio::test]
+async fn test_response_message_as_request_returns_formerr() {
+    // QR bit set: a response sent to us as if it were a query
+    let mut packet = raw_header(7, 0x8100, 1);
+    packet.extend(raw_question());
+
+    let response = handle_raw_packet(&packet).await;
+    assert_eq!(response.response_code(), ResponseCode::FormErr);
+}
+
+#[tokio::test]
+async fn test_unsupported_opcode_returns_notimp() {
+    // Opcode 2 (STATUS)
+    let mut packet = raw_header(8, 0x1100, 1);
+    packet.extend(raw_question());
+
+    let response = handle_raw_packet(&packet).await;
+    assert_eq!(response.response_code(), ResponseCode::NotImp);
+}
+
+#[tokio::test]
+async fn test_out_of_zone_request_returns_nxdomain() {
+    let mut packet = raw_header(9, 0x0100, 1);
+    packet.extend_from_slice(&[7]);
+    packet.extend_from_slice(b"example");
+    packet.extend_from_slice(&[3]);
+    packet.extend_from_slice(b"com");
+    packet.extend_from_slice(&[0, 0, 1, 0, 1]);
+
+    let response = handle_raw_packet(&packet).await;
+    assert_eq!(response.response_code(), ResponseCode::NXDomain);
+}
+
+#[test]
+fn test_truncated_packet_is_rejected_before_handler() {
+    use hickory_proto::serialize::binary::BinDecodable;
+
+    let mut packet = raw_header(1, 0x0100, 1);
+    packet.extend_from_slice(&raw_question()[..6]);
+    assert!(hickory_server::authority::MessageRequest::from_bytes(&packet).is_err());
+}
+
+#[test]
+fn test_handler_follows_runtime_zone_changes() {
+    use hickory_proto::rr::Name;
+
+    let resolver = MdnsResolver::new(Arc::new(crate::config::Config::default())).unwrap();
+    let zones = Arc::new(crate::zones::ZoneRegistry::new(&["mdns.home.arpa."]).unwrap());
+    let handler = MdnsDnsHandler::with_zones(Arc::new(resolver), zones.clone());
+
+    let name = Name::from_utf8("printer.vlan20.home.arpa.").unwrap();
+    assert!(!handler.should_handle(&name));
+
+    zones.add("vlan20.home.arpa.").unwrap();
+    assert!(handler.should_handle(&name));
+
+    zones.remove("vlan20.home.arpa.").unwrap();
+    assert!(!handler.should_handle(&name));
+}
+
+#[tokio::test]
+async fn test_read_only_miss_carries_extended_error() {
+    use hickory_proto::op::{Edns, Message, Query};
+    use hickory_proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+    use hickory_proto::rr::{Name, RecordType};
+    use hickory_proto::serialize::binary::{BinDecodable, BinEncodable};
+    use hickory_server::server::RequestHandler;
+
+    let mut query = Message::new();
+    query.set_id(77);
+    query.add_query(Query::query(Name::from_utf8("nothing.mdns.home.arpa.").unwrap(), RecordType::A));
+    query.set_edns(Edns::new());
+    let packet = query.to_bytes().unwrap();
+
+    let message = hickory_server::authority::MessageRequest::from_bytes(&packet).unwrap();
+    let request = hickory_server::server::Request::new(
+        message,
+        "127.0.0.1:53000".parse().unwrap(),
+        hickory_proto::xfer::Protocol::Udp,
+    );
+
+    let resolver = MdnsResolver::new(Arc::new(crate::config::Config::default())).unwrap();
+    resolver.set_read_only(true);
+    let handler = MdnsDnsHandler::new(Arc::new(resolver), "mdns.home.arpa.".to_string());
+    let response_handle = CapturingResponseHandler::default();
+    handler.handle_request(&request, response_handle.clone()).await;
+
+    let bytes = response_handle.take_bytes().unwrap();
+    let response = Message::from_vec(&bytes).unwrap();
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert!(response.answers().is_empty());
+
+    let edns = response.extensions().as_ref().expect("response has EDNS");
+    match edns.option(EdnsCode::Unknown(15)) {
+        Some(EdnsOption::Unknown(15, data)) => {
+            assert_eq!(u16::from_be_bytes([data[0], data[1]]), 14);
+            assert!(String::from_utf8_lossy(&data[2..]).contains("read-only"));
+        }
+        other => panic!("expected EDE option, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_pad_response_to_block_length() {
+    use crate::dns_handler::utils::pad_response;
+    use hickory_proto::op::{Edns, Message, Query};
+    use hickory_proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+    use hickory_proto::rr::{Name, RecordType};
+    use hickory_proto::serialize::binary::BinEncodable;
+
+    for host in ["tv", "a-much-longer-host-name-for-the-living-room"] {
+        let mut message = Message::new();
+        message.add_query(Query::query(Name::from_utf8(format!("{}.mdns.home.arpa.", host)).unwrap(), RecordType::A));
+        let mut edns = Edns::new();
+        message.set_edns(edns.clone());
+        let len = message.to_bytes().unwrap().len();
+
+        pad_response(&mut edns, len, 468);
+        message.set_edns(edns);
+        let padded = message.to_bytes().unwrap();
+        assert_eq!(padded.len(), 468);
+        let option = message.extensions().as_ref().unwrap().option(EdnsCode::Padding);
+        assert!(matches!(option, Some(EdnsOption::Unknown(12, data)) if data.iter().all(|&b| b == 0)));
+    }
+}
+
+#[cfg(feature = "tls")]
+#[tokio::test]
+async fn test_tls_responses_padded_per_policy() {
+    use crate::config::{Config, EdnsPadding, TlsConfig};
+    use hickory_proto::op::{Edns, Message, Query};
+    use hickory_proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+    use hickory_proto::rr::{Name, RecordType};
+    use hickory_proto::serialize::binary::{BinDecodable, BinEncodable};
+    use hickory_proto::xfer::Protocol;
+    use hickory_server::server::RequestHandler;
+
+    let respond = |padding: EdnsPadding, protocol: Protocol, pad_query: bool| async move {
+        let mut config = Config::default();
+        config.server.tls = Some(TlsConfig {
+            padding,
+            ..Default::default()
+        });
+        let resolver = MdnsResolver::new(Arc::new(config)).unwrap();
+        resolver.set_read_only(true);
+        let handler = MdnsDnsHandler::new(Arc::new(resolver), "mdns.home.arpa.".to_string());
+
+        let mut query = Message::new();
+        query.add_query(Query::query(Name::from_utf8("tv.mdns.home.arpa.").unwrap(), RecordType::A));
+        let mut edns = Edns::new();
+        if pad_query {
+            edns.options_mut().insert(EdnsOption::Unknown(12, vec![0; 8]));
+        }
+        query.set_edns(edns);
+        let message = hickory_server::authority::MessageRequest::from_bytes(&query.to_bytes().unwrap()).unwrap();
+        let request = hickory_server::server::Request::new(message, "127.0.0.1:53000".parse().unwrap(), protocol);
+        let response_handle = CapturingResponseHandler::default();
+        handler.handle_request(&request, response_handle.clone()).await;
+        let bytes = response_handle.take_bytes().unwrap();
+        let padded = Message::from_vec(&bytes).unwrap().extensions().as_ref().unwrap().option(EdnsCode::Padding).is_some();
+        (bytes.len(), padded)
+    };
+
+    assert_eq!(respond(EdnsPadding::Requested, Protocol::Tls, true).await, (468, true));
+    assert!(!respond(EdnsPadding::Requested, Protocol::Tls, false).await.1);
+    assert_eq!(respond(EdnsPadding::Always, Protocol::Tls, false).await, (468, true));
+    assert!(!respond(EdnsPadding::Off, Protocol::Tls, true).await.1);
+    // Cleartext transports are never padded
+    assert!(!respond(EdnsPadding::Always, Protocol::Tcp, true).await.1);
+}
+
+#[tokio::test]
+async fn test_response_policy_applied_before_mdns() {
+    use crate::policy::PolicyStore;
+    use hickory_proto::rr::{Name, RData, RecordType};
+    use hickory_proto::serialize::binary::{BinDecodable, BinEncodable};
+    use hickory_server::server::RequestHandler;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("policy.rpz");
+    std::fs::write(
+        &path,
+        "$ORIGIN rpz.example.\n\
+         blocked.mdns.home.arpa 60 CNAME .\n\
+         silent.mdns.home.arpa 60 CNAME rpz-drop.\n\
+         nas.mdns.home.arpa 60 A 192.168.1.50\n",
+    )
+    .unwrap();
+
+    let resolver = MdnsResolver::new(Arc::new(crate::config::Config::default())).unwrap();
+    resolver.set_read_only(true);
+    let handler = MdnsDnsHandler::new(Arc::new(resolver), "mdns.home.arpa.".to_string())
+        .with_policy(Arc::new(PolicyStore::open(&path).unwrap()));
+    let client = "127.0.0.1:53000".parse().unwrap();
+
+    let blocked = Name::from_utf8("blocked.mdns.home.arpa.").unwrap();
+    assert_eq!(handler.lookup(&blocked, RecordType::A, client).await, Err(ResponseCode::NXDomain));
+
+    let nas = Name::from_utf8("nas.mdns.home.arpa.").unwrap();
+    let (answers, _) = handler.lookup(&nas, RecordType::A, client).await.unwrap();
+    assert!(matches!(answers[0].data(), RData::A(a) if a.0 == std::net::Ipv4Addr::new(192, 168, 1, 50)));
+
+    // A dropped query gets no response at all
+    let mut query = hickory_proto::op::Message::new();
+    query.add_query(hickory_proto::op::Query::query(
+        Name::from_utf8("silent.mdns.home.arpa.").unwrap(),
+        RecordType::A,
+    ));
+    let packet = query.to_bytes().unwrap();
+    let response_handle = CapturingResponseHandler::default();
+    let message = hickory_server::authority::MessageRequest::from_bytes(&packet).unwrap();
+    let request = hickory_server::server::Request::new(message, client, hickory_proto::xfer::Protocol::Udp);
+    handler.handle_request(&request, response_handle.clone()).await;
+    assert!(response_handle.take_bytes().is_none());
+}
+
+#[tokio::test]
+async fn test_authoritative_zone_answered_next_to_discovery_zone() {
+    use crate::authoritative::AuthoritativeZones;
+    use crate::config::AuthoritativeZone;
+    use hickory_proto::rr::{Name, RData, RecordType};
+    use hickory_proto::serialize::binary::{BinDecodable, BinEncodable};
+    use hickory_server::server::RequestHandler;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("home.arpa.zone");
+    std::fs::write(
+        &path,
+        "$ORIGIN home.arpa.\n\
+         @ 3600 IN SOA ns.home.arpa. admin.home.arpa. 42 3600 600 86400 300\n\
+         @ 3600 IN NS ns.home.arpa.\n\
+         router 3600 IN A 192.168.1.1\n",
+    )
+    .unwrap();
+    let authoritative = AuthoritativeZones::load(&[AuthoritativeZone {
+        origin: "home.arpa.".to_string(),
+        file: path,
+    }])
+    .unwrap();
+
+    let resolver = MdnsResolver::new(Arc::new(crate::config::Config::default())).unwrap();
+    resolver.set_read_only(true);
+    let handler = MdnsDnsHandler::new(Arc::new(resolver), "mdns.home.arpa.".to_string())
+        .with_authoritative(Arc::new(authoritative));
+
+    let ask = |name: &str, record_type| {
+        let mut query = hickory_proto::op::Message::new();
+        query.add_query(hickory_proto::op::Query::query(Name::from_utf8(name).unwrap(), record_type));
+        let message = hickory_server::authority::MessageRequest::from_bytes(&query.to_bytes().unwrap()).unwrap();
+        hickory_server::server::Request::new(message, "127.0.0.1:53000".parse().unwrap(), hickory_proto::xfer::Protocol::Udp)
+    };
+
+    let response_handle = CapturingResponseHandler::default();
+    handler.handle_request(&ask("router.home.arpa.", RecordType::A), response_handle.clone()).await;
+    let bytes = response_handle.take_bytes().unwrap();
+    let response = hickory_proto::op::Message::from_vec(&bytes).unwrap();
+    assert!(response.authoritative());
+    assert!(matches!(response.answers()[0].data(), RData::A(a) if a.0 == std::net::Ipv4Addr::new(192, 168, 1, 1)));
+
+    // The discovery domain below the static zone still answers for itself
+    let response_handle = CapturingResponseHandler::default();
+    handler.handle_request(&ask("mdns.home.arpa.", RecordType::SOA), response_handle.clone()).await;
+    let bytes = response_handle.take_bytes().unwrap();
+    let response = hickory_proto::op::Message::from_vec(&bytes).unwrap();
+    assert!(matches!(response.answers()[0].data(), RData::SOA(soa) if soa.serial() == 0));
+}
+
+#[tokio::test]
+async fn test_resolution_chain_order_and_timeouts() {
+    use crate::config::{KnownService, ResolutionStep};
+    use crate::dns_handler::{ClientMeta, QueryEngine};
+    use hickory_proto::rr::{Name, RecordType};
+
+    let mut config = crate::config::Config::default();
+    config.known_services.services.push(KnownService {
+        service_type: "_ipp._tcp".to_string(),
+        name: "Office".to_string(),
+        host: "printer".to_string(),
+        port: 631,
+        txt: Vec::new(),
+    });
+    config.resolution.service = Some(vec![ResolutionStep::Cache]);
+    config.resolution.address = Some(vec![ResolutionStep::Mdns]);
+    config.resolution.timeouts_ms.insert(ResolutionStep::Mdns, 50);
+    config.mdns.hostname_resolution_timeout_ms = 2000;
+    let resolver = Arc::new(MdnsResolver::new(Arc::new(config)).unwrap());
+    let zones = Arc::new(crate::zones::ZoneRegistry::new(&["mdns.home.arpa."]).unwrap());
+    let engine = QueryEngine::new(resolver, zones);
+    let client = ClientMeta::new("127.0.0.1:53000".parse().unwrap(), hickory_proto::xfer::Protocol::Udp);
+
+    // Known services are not in the service chain, so the instance is not answered
+    let srv = Name::from_utf8("Office._ipp._tcp.mdns.home.arpa.").unwrap();
+    let answer = engine.resolve(&srv, RecordType::SRV, &client).await;
+    assert_eq!(answer.response_code, ResponseCode::NoError);
+    assert!(answer.answers.is_empty());
+
+    // The mDNS step gives up at its own limit, long before the query timeout
+    let started = std::time::Instant::now();
+    let host = Name::from_utf8("nobody-here.mdns.home.arpa.").unwrap();
+    let answer = engine.resolve(&host, RecordType::A, &client).await;
+    assert_eq!(answer.response_code, ResponseCode::ServFail);
+    assert!(started.elapsed() < std::time::Duration::from_millis(1000));
+}
+
+#[tokio::test]
+async fn test_engine_decision_matrix() {
+    use crate::dns_handler::{ClientMeta, QueryEngine};
+    use hickory_proto::rr::{Name, RecordType};
+
+    let resolver = MdnsResolver::new(Arc::new(crate::config::Config::default())).unwrap();
+    resolver.set_read_only(true);
+    let zones = Arc::new(crate::zones::ZoneRegistry::new(&["mdns.home.arpa."]).unwrap());
+    let engine = QueryEngine::new(Arc::new(resolver), zones);
+    let client = ClientMeta::new("127.0.0.1:53000".parse().unwrap(), hickory_proto::xfer::Protocol::Udp);
+
+    // (name, type, response code, whether records are expected)
+    let cases = [
+        ("printer.example.com.", RecordType::A, ResponseCode::NXDomain, false),
+        ("mdns.home.arpa.", RecordType::SOA, ResponseCode::NoError, true),
+        ("mdns.home.arpa.", RecordType::NS, ResponseCode::NoError, true),
+        ("printer.mdns.home.arpa.", RecordType::NS, ResponseCode::NoError, false),
+        ("b._dns-sd._udp.mdns.home.arpa.", RecordType::PTR, ResponseCode::NoError, true),
+        ("_dns-update._udp.mdns.home.arpa.", RecordType::SRV, ResponseCode::NoError, false),
+        ("nothing.mdns.home.arpa.", RecordType::A, ResponseCode::NoError, false),
+        ("_dmarc.mdns.home.arpa.", RecordType::TXT, ResponseCode::NoError, false),
+    ];
+    for (name, record_type, response_code, has_records) in cases {
+        let answer = engine.resolve(&Name::from_utf8(name).unwrap(), record_type, &client).await;
+        assert_eq!(answer.response_code, response_code, "{} {:?}", name, record_type);
+        assert_eq!(!answer.answers.is_empty(), has_records, "{} {:?}", name, record_type);
+        assert!(!answer.drop);
+    }
+
+    // Only the mDNS miss explains itself, since only it depends on read-only mode
+    let miss = engine
+        .resolve(&Name::from_utf8("nothing.mdns.home.arpa.").unwrap(), RecordType::A, &client)
+        .await;
+    assert!(miss.extended_error.is_some());
+    let apex = engine
+        .resolve(&Name::from_utf8("mdns.home.arpa.").unwrap(), RecordType::SOA, &client)
+        .await;
+    assert!(apex.extended_error.is_none());
+    // Not DNS-SD: answered without asking mDNS, so no read-only explanation either
+    let dmarc = engine
+        .resolve(&Name::from_utf8("_dmarc.mdns.home.arpa.").unwrap(), RecordType::TXT, &client)
+        .await;
+    assert!(dmarc.extended_error.is_none());
+}
+
+#[tokio::test]
+async fn test_non_dns_sd_names_nxdomain() {
+    use crate::config::{Config, NonDnsSdNames};
+    use crate::dns_handler::{ClientMeta, QueryEngine};
+    use hickory_proto::rr::{Name, RecordType};
+
+    let mut config = Config::default();
+    config.mdns.non_dns_sd_names = NonDnsSdNames::NxDomain;
+    let resolver = MdnsResolver::new(Arc::new(config)).unwrap();
+    resolver.set_read_only(true);
+    let zones = Arc::new(crate::zones::ZoneRegistry::new(&["mdns.home.arpa."]).unwrap());
+    let engine = QueryEngine::new(Arc::new(resolver), zones);
+    let client = ClientMeta::new("127.0.0.1:53000".parse().unwrap(), hickory_proto::xfer::Protocol::Udp);
+
+    let name = Name::from_utf8("_acme-challenge.printer.mdns.home.arpa.").unwrap();
+    assert_eq!(engine.resolve(&name, RecordType::TXT, &client).await.response_code, ResponseCode::NXDomain);
+    let name = Name::from_utf8("_ipp._tcp.mdns.home.arpa.").unwrap();
+    assert_eq!(engine.resolve(&name, RecordType::PTR, &client).await.response_code, ResponseCode::NoError);
+}
+
+#[tokio::test]
+async fn test_local_query_from_off_link_client() {
+    use crate::config::{Config, OffLinkLocalQueries};
+    use crate::dns_handler::{ClientMeta, QueryEngine};
+    use hickory_proto::rr::{Name, RData, RecordType};
+
+    let engine = |mode| {
+        let mut config = Config::default();
+        config.server.off_link_local_queries = mode;
+        let resolver = MdnsResolver::new(Arc::new(config)).unwrap();
+        let zones = Arc::new(crate::zones::ZoneRegistry::new(&["mdns.home.arpa."]).unwrap());
+        QueryEngine::new(Arc::new(resolver), zones)
+    };
+    let on_link = ClientMeta::new("127.0.0.1:53000".parse().unwrap(), hickory_proto::xfer::Protocol::Udp);
+    let off_link = ClientMeta::new("203.0.113.7:53000".parse().unwrap(), hickory_proto::xfer::Protocol::Udp);
+    let name = Name::from_utf8("printer.local.").unwrap();
+
+    let nxdomain = engine(OffLinkLocalQueries::NxDomain);
+    let answer = nxdomain.resolve(&name, RecordType::A, &off_link).await;
+    assert_eq!(answer.response_code, ResponseCode::NXDomain);
+    assert_eq!(answer.authority.len(), 1);
+    assert_eq!(answer.authority[0].name(), &Name::from_utf8("local.").unwrap());
+    let RData::SOA(soa) = answer.authority[0].data() else {
+        panic!("authority is not an SOA");
+    };
+    assert_eq!(soa.mname(), &Name::from_utf8("discovery-proxy.mdns.home.arpa.").unwrap());
+
+    // On-link clients and names outside .local get the plain name error
+    let answer = nxdomain.resolve(&name, RecordType::A, &on_link).await;
+    assert_eq!(answer.response_code, ResponseCode::NXDomain);
+    assert!(answer.authority.is_empty());
+    let other = Name::from_utf8("printer.example.com.").unwrap();
+    assert!(nxdomain.resolve(&other, RecordType::A, &off_link).await.authority.is_empty());
+
+    let refused = engine(OffLinkLocalQueries::Refused).resolve(&name, RecordType::A, &off_link).await;
+    assert_eq!(refused.response_code, ResponseCode::Refused);
+    assert!(engine(OffLinkLocalQueries::Drop).resolve(&name, RecordType::A, &off_link).await.drop);
+}
+
+#[tokio::test]
+async fn test_own_name_answered_from_own_addresses() {
+    use crate::dns_handler::{ClientMeta, QueryEngine};
+    use crate::own_addresses::OwnAddresses;
+    use hickory_proto::rr::{Name, RData, RecordType};
+
+    let resolver = MdnsResolver::new(Arc::new(crate::config::Config::default())).unwrap();
+    resolver.set_read_only(true);
+    let zones = Arc::new(crate::zones::ZoneRegistry::new(&["mdns.home.arpa."]).unwrap());
+    let own = OwnAddresses::with_addresses(vec!["0.0.0.0".parse().unwrap()], vec!["192.168.1.5".parse().unwrap()]);
+    let engine = QueryEngine::new(Arc::new(resolver), zones).with_own_addresses(Arc::new(own));
+    let client = ClientMeta::new("127.0.0.1:53000".parse().unwrap(), hickory_proto::xfer::Protocol::Udp);
+
+    // The NS target resolves, whatever the case of the question
+    let name = Name::from_utf8("Discovery-Proxy.mdns.home.arpa.").unwrap();
+    let answer = engine.resolve(&name, RecordType::A, &client).await;
+    assert_eq!(answer.answers.len(), 1);
+    assert_eq!(answer.answers[0].data(), &RData::A("192.168.1.5".parse::<std::net::Ipv4Addr>().unwrap().into()));
+    assert!(answer.extended_error.is_none());
+
+    let answer = engine.resolve(&name, RecordType::AAAA, &client).await;
+    assert_eq!(answer.response_code, ResponseCode::NoError);
+    assert!(answer.answers.is_empty() && answer.extended_error.is_none());
+}
+
+/// Handler for mdns.home.arpa. answering from cache only
+fn cache_only_handler() -> (Arc<MdnsResolver>, MdnsDnsHandler) {
+    let resolver = Arc::new(MdnsResolver::new(Arc::new(crate::config::Config::default())).unwrap());
+    resolver.set_read_only(true);
+    let handler = MdnsDnsHandler::new(resolver.clone(), "mdns.home.arpa.".to_string());
+    (resolver, handler)
+}
+
+fn query(name: &str, record_type: hickory_proto::rr::RecordType) -> hickory_proto::op::Message {
+    let mut query = hickory_proto::op::Message::new();
+    query.add_query(hickory_proto::op::Query::query(
+        hickory_proto::rr::Name::from_utf8(name).unwrap(),
+        record_type,
+    ));
+    query
+}
+
+#[tokio::test]
+async fn test_handle_request_admin_queries() {
+    use hickory_proto::rr::{RData, RecordType};
+    use hickory_proto::xfer::Protocol;
+    use hickory_server::server::RequestHandler;
+
+    let (_, handler) = cache_only_handler();
+    let client = "127.0.0.1:53000".parse().unwrap();
+
+    let cases = [
+        ("mdns.home.arpa.", RecordType::SOA),
+        ("mdns.home.arpa.", RecordType::NS),
+        ("b._dns-sd._udp.mdns.home.arpa.", RecordType::PTR),
+    ];
+    for (name, record_type) in cases {
+        let response_handle = CapturingResponseHandler::default();
+        let request = testing::request(&query(name, record_type), client, Protocol::Udp);
+        handler.handle_request(&request, response_handle.clone()).await;
+        let response = response_handle.take_response().unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NoError, "{} {:?}", name, record_type);
+        assert_eq!(response.answers()[0].record_type(), record_type, "{} {:?}", name, record_type);
+    }
+
+    let response_handle = CapturingResponseHandler::default();
+    let request = testing::request(&query("mdns.home.arpa.", RecordType::SOA), client, Protocol::Udp);
+    handler.handle_request(&request, response_handle.clone()).await;
+    let response = response_handle.take_response().unwrap();
+    assert!(matches!(response.answers()[0].data(), RData::SOA(soa) if soa.serial() == 0));
+}
+
+#[tokio::test]
+async fn test_handle_request_suppresses_link_local_for_off_link_client() {
+    use hickory_proto::rr::rdata::A;
+    use hickory_proto::rr::{Name, RData, Record, RecordType};
+    use hickory_proto::xfer::Protocol;
+    use hickory_server::server::RequestHandler;
+    use std::net::Ipv4Addr;
+
+    let (resolver, handler) = cache_only_handler();
+    let name = Name::from_utf8("printer.mdns.home.arpa.").unwrap();
+    let a = |addr| Record::from_rdata(name.clone(), 10, RData::A(A(addr)));
+    resolver.cache.insert(
+        "printer.mdns.home.arpa.",
+        RecordType::A,
+        vec![a(Ipv4Addr::new(169, 254, 7, 7)), a(Ipv4Addr::new(192, 168, 1, 20))],
+    );
+
+    for (client, expected) in [("127.0.0.1:53000", 2), ("203.0.113.7:53000", 1)] {
+        let request = testing::request(&query("printer.mdns.home.arpa.", RecordType::A), client.parse().unwrap(), Protocol::Udp);
+        let response_handle = CapturingResponseHandler::default();
+        handler.handle_request(&request, response_handle.clone()).await;
+        assert_eq!(response_handle.take_response().unwrap().answers().len(), expected, "{}", client);
+    }
+}
+
+#[tokio::test]
+async fn test_handle_request_truncates_large_udp_answers() {
+    use hickory_proto::op::Edns;
+    use hickory_proto::rr::rdata::TXT;
+    use hickory_proto::rr::{Name, RData, Record, RecordType};
+    use hickory_proto::xfer::Protocol;
+    use hickory_server::server::RequestHandler;
+
+    let (resolver, handler) = cache_only_handler();
+    let name = Name::from_utf8("Office._ipp._tcp.mdns.home.arpa.").unwrap();
+    let records: Vec<Record> = (0..8)
+        .map(|i| Record::from_rdata(name.clone(), 10, RData::TXT(TXT::new(vec![format!("{}={}", i, "x".repeat(200))]))))
+        .collect();
+    resolver.cache.insert("office._ipp._tcp.mdns.home.arpa.", RecordType::TXT, records);
+
+    let mut small = query("Office._ipp._tcp.mdns.home.arpa.", RecordType::TXT);
+    let mut edns = Edns::new();
+    edns.set_max_payload(512);
+    small.set_edns(edns);
+    let client = "127.0.0.1:53000".parse().unwrap();
+
+    let response_handle = CapturingResponseHandler::new(Protocol::Udp);
+    handler.handle_request(&testing::request(&small, client, Protocol::Udp), response_handle.clone()).await;
+    let bytes = response_handle.take_bytes().unwrap();
+    assert!(bytes.len() <= 512);
+    assert!(hickory_proto::op::Message::from_vec(&bytes).unwrap().truncated());
+
+    // The same question over TCP gets everything
+    let response_handle = CapturingResponseHandler::new(Protocol::Tcp);
+    handler.handle_request(&testing::request(&small, client, Protocol::Tcp), response_handle.clone()).await;
+    let response = response_handle.take_response().unwrap();
+    assert!(!response.truncated());
+    assert_eq!(response.answers().len(), 8);
+}
+
+#[tokio::test]
+async fn test_udp_answers_are_sized_by_edns_payload() {
+    use hickory_proto::op::{Edns, Message, ResponseCode};
+    use hickory_proto::rr::rdata::TXT;
+    use hickory_proto::rr::{Name, RData, Record, RecordType};
+    use hickory_proto::xfer::Protocol;
+    use hickory_server::server::RequestHandler;
+
+    let (resolver, handler) = cache_only_handler();
+    let name = Name::from_utf8("Office._ipp._tcp.mdns.home.arpa.").unwrap();
+    let records: Vec<Record> = (0..8)
+        .map(|i| Record::from_rdata(name.clone(), 10, RData::TXT(TXT::new(vec![format!("{}={}", i, "x".repeat(200))]))))
+        .collect();
+    resolver.cache.insert("office._ipp._tcp.mdns.home.arpa.", RecordType::TXT, records);
+    let client = "127.0.0.1:53000".parse().unwrap();
+    let ask = |edns: Option<Edns>| {
+        let mut message = query("Office._ipp._tcp.mdns.home.arpa.", RecordType::TXT);
+        if let Some(edns) = edns {
+            message.set_edns(edns);
+        }
+        testing::request(&message, client, Protocol::Udp)
+    };
+
+    // Without EDNS the classic 512-byte limit applies, not hickory's 4096
+    let response_handle = CapturingResponseHandler::new(Protocol::Udp);
+    handler.handle_request(&ask(None), response_handle.clone()).await;
+    let bytes = response_handle.take_bytes().unwrap();
+    assert!(bytes.len() <= 512);
+    let response = Message::from_vec(&bytes).unwrap();
+    assert!(response.truncated());
+    assert!(response.extensions().is_none());
+
+    // A client advertising 4096 gets the server's 1232
+    let mut large = Edns::new();
+    large.set_max_payload(4096);
+    let response_handle = CapturingResponseHandler::new(Protocol::Udp);
+    handler.handle_request(&ask(Some(large.clone())), response_handle.clone()).await;
+    let bytes = response_handle.take_bytes().unwrap();
+    assert!(bytes.len() <= 1232);
+    let response = Message::from_vec(&bytes).unwrap();
+    assert!(response.truncated());
+    assert_eq!(response.extensions().as_ref().unwrap().max_payload(), 1232);
+
+    // An unknown EDNS version gets BADVERS and no answers
+    large.set_version(1);
+    let response_handle = CapturingResponseHandler::new(Protocol::Udp);
+    handler.handle_request(&ask(Some(large)), response_handle.clone()).await;
+    let response = response_handle.take_response().unwrap();
+    // BADVERS shares code 16 with BADSIG, which is what hickory decodes it as
+    assert_eq!(u16::from(response.response_code()), u16::from(ResponseCode::BADVERS));
+    assert!(response.answers().is_empty());
+    assert_eq!(response.extensions().as_ref().unwrap().version(), 0);
+}
+
+#[tokio::test]
+async fn test_large_browse_is_trimmed_with_tc_over_udp() {
+    use hickory_proto::op::Message;
+    use hickory_proto::rr::rdata::PTR;
+    use hickory_proto::rr::{Name, RData, Record, RecordType};
+    use hickory_proto::xfer::Protocol;
+    use hickory_server::server::RequestHandler;
+
+    let (resolver, handler) = cache_only_handler();
+    let service = Name::from_utf8("_ipp._tcp.mdns.home.arpa.").unwrap();
+    let records: Vec<Record> = (0..60)
+        .map(|i| {
+            let instance = Name::from_utf8(format!("printer-{:02}._ipp._tcp.mdns.home.arpa.", i)).unwrap();
+            Record::from_rdata(service.clone(), 10, RData::PTR(PTR(instance)))
+        })
+        .collect();
+    resolver.cache.insert("_ipp._tcp.mdns.home.arpa.", RecordType::PTR, records);
+    let request = |protocol| testing::request(&query("_ipp._tcp.mdns.home.arpa.", RecordType::PTR), "127.0.0.1:53000".parse().unwrap(), protocol);
+
+    // Answers are trimmed to whole records that fit, not dropped altogether
+    let response_handle = CapturingResponseHandler::new(Protocol::Udp);
+    handler.handle_request(&request(Protocol::Udp), response_handle.clone()).await;
+    let bytes = response_handle.take_bytes().unwrap();
+    assert!(bytes.len() <= 512);
+    let response = Message::from_vec(&bytes).unwrap();
+    assert!(response.truncated());
+    assert!(!response.answers().is_empty() && response.answers().len() < 60);
+    assert!(response.answers().iter().all(|record| record.record_type() == RecordType::PTR));
+
+    let response_handle = CapturingResponseHandler::new(Protocol::Tcp);
+    handler.handle_request(&request(Protocol::Tcp), response_handle.clone()).await;
+    let response = response_handle.take_response().unwrap();
+    assert!(!response.truncated());
+    assert_eq!(response.answers().len(), 60);
+}
+
+#[test]
+fn test_udp_fit_drops_authority_before_answers() {
+    use super::handler::fit_udp;
+    use super::utils::encoded_len;
+    use crate::dns_handler::Answer;
+    use hickory_proto::op::Header;
+    use hickory_proto::rr::rdata::{A, TXT};
+    use hickory_proto::rr::{Name, RData, Record, RecordType};
+    use hickory_proto::xfer::Protocol;
+
+    let request = testing::request(&query("printer.mdns.home.arpa.", RecordType::A), "127.0.0.1:53000".parse().unwrap(), Protocol::Udp);
+    let name = Name::from_utf8("printer.mdns.home.arpa.").unwrap();
+    let answers: Vec<Record> = (1..=3).map(|i| Record::from_rdata(name.clone(), 10, RData::A(A::new(192, 168, 1, i)))).collect();
+    let authority: Vec<Record> = (0..4)
+        .map(|i| Record::from_rdata(name.clone(), 10, RData::TXT(TXT::new(vec![format!("{}={}", i, "x".repeat(150))]))))
+        .collect();
+    let limit = encoded_len(&request, Header::new(), None, &answers, &[], &[]) + 10;
+
+    // Every answer fits once the authority section is gone: none is dropped
+    let mut header = Header::new();
+    let mut answer = Answer { answers: answers.clone(), authority, ..Default::default() };
+    fit_udp(&request, &mut header, None, &mut answer, limit);
+    assert_eq!(answer.answers, answers);
+    assert!(answer.authority.is_empty());
+    assert!(header.truncated());
+
+    // Answers that fit on their own are left alone
+    let mut header = Header::new();
+    let mut answer = Answer { answers: answers.clone(), ..Default::default() };
+    fit_udp(&request, &mut header, None, &mut answer, limit);
+    assert_eq!(answer.answers, answers);
+    assert!(!header.truncated());
+}
+
+#[tokio::test]
+async fn test_provenance_record_names_the_source() {
+    use crate::config::Config;
+    use crate::dns_handler::{ClientMeta, QueryEngine};
+    use hickory_proto::rr::rdata::A;
+    use hickory_proto::rr::{Name, RData, Record, RecordType};
+
+    let mut config = Config::default();
+    config.debug.provenance_record = true;
+    let resolver = Arc::new(MdnsResolver::new(Arc::new(config)).unwrap());
+    resolver.set_read_only(true);
+    let printer = Name::from_utf8("printer.mdns.home.arpa.").unwrap();
+    resolver.cache.insert(
+        "printer.mdns.home.arpa.",
+        RecordType::A,
+        vec![Record::from_rdata(printer.clone(), 10, RData::A(A::new(192, 168, 1, 20)))],
+    );
+    let zones = Arc::new(crate::zones::ZoneRegistry::new(&["mdns.home.arpa."]).unwrap());
+    let engine = QueryEngine::new(resolver, zones);
+    let client = ClientMeta::new("127.0.0.1:53000".parse().unwrap(), hickory_proto::xfer::Protocol::Udp);
+
+    let provenance = |answer: crate::dns_handler::Answer| {
+        let record = answer.additionals.last().cloned().expect("no provenance record");
+        assert_eq!(record.name(), &Name::from_utf8("proxy-debug.mdns.home.arpa.").unwrap());
+        assert_eq!(record.ttl(), 0);
+        let RData::TXT(txt) = record.data() else {
+            panic!("provenance record is not TXT");
+        };
+        txt.iter()
+            .map(|s| String::from_utf8_lossy(s).into_owned())
+            .filter(|s| !s.starts_with("elapsed_ms="))
+            .collect::<Vec<_>>()
+    };
+
+    let hit = engine.resolve(&printer, RecordType::A, &client).await;
+    assert_eq!(hit.answers.len(), 1);
+    assert_eq!(provenance(hit), ["source=cache", "cache=hit"]);
+    let miss = engine.resolve(&Name::from_utf8("nas.mdns.home.arpa.").unwrap(), RecordType::A, &client).await;
+    assert_eq!(provenance(miss), ["source=mdns", "cache=miss"]);
+    let apex = engine.resolve(&Name::from_utf8("mdns.home.arpa.").unwrap(), RecordType::SOA, &client).await;
+    assert_eq!(provenance(apex), ["source=admin"]);
+
+    // Names outside the discovery domains get none
+    let other = engine.resolve(&Name::from_utf8("example.com.").unwrap(), RecordType::A, &client).await;
+    assert!(other.additionals.is_empty());
+}
+
+#[tokio::test]
+async fn test_axfr_transfers_cached_zone_contents() {
+    use crate::config::AxfrConfig;
+    use crate::dns_handler::axfr::ZoneTransfers;
+    use hickory_proto::rr::rdata::A;
+    use hickory_proto::rr::{Name, RData, Record, RecordType};
+    use hickory_proto::xfer::Protocol;
+    use hickory_server::server::RequestHandler;
+    use std::net::Ipv4Addr;
+
+    let (resolver, handler) = cache_only_handler();
+    let printer = Name::from_utf8("printer.mdns.home.arpa.").unwrap();
+    let a = Record::from_rdata(printer, 10, RData::A(A(Ipv4Addr::new(192, 168, 1, 20))));
+    resolver.cache.insert("printer.mdns.home.arpa.", RecordType::A, vec![a.clone()]);
+    let local = Record::from_rdata(Name::from_utf8("printer.local.").unwrap(), 120, RData::A(A(Ipv4Addr::new(192, 168, 1, 20))));
+    resolver.cache.insert("printer.local.", RecordType::A, vec![local]);
+
+    let transfer = |handler: &MdnsDnsHandler, name: &str, client: &str, protocol| {
+        let request = testing::request(&query(name, RecordType::AXFR), client.parse().unwrap(), protocol);
+        let handler = handler.clone();
+        async move {
+            let response_handle = CapturingResponseHandler::new(protocol);
+            handler.handle_request(&request, response_handle.clone()).await;
+            response_handle.take_response().unwrap()
+        }
+    };
+
+    // Off unless enabled
+    let refused = transfer(&handler, "mdns.home.arpa.", "127.0.0.1:53000", Protocol::Tcp).await;
+    assert_eq!(refused.response_code(), ResponseCode::Refused);
+
+    let axfr = AxfrConfig {
+        enabled: true,
+        allow_from: vec!["127.0.0.0/8".to_string()],
+    };
+    let handler = handler.with_axfr(Arc::new(ZoneTransfers::from_config(&axfr).unwrap()));
+    let response = transfer(&handler, "mdns.home.arpa.", "127.0.0.1:53000", Protocol::Tcp).await;
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert!(response.authoritative());
+    let types: Vec<RecordType> = response.answers().iter().map(Record::record_type).collect();
+    assert_eq!(types.first(), Some(&RecordType::SOA));
+    assert_eq!(types.last(), Some(&RecordType::SOA));
+    assert!(types.contains(&RecordType::NS));
+    assert!(response.answers().contains(&a));
+    assert!(response.answers().iter().all(|r| r.name().to_utf8().ends_with("mdns.home.arpa.")));
+
+    for (name, client, protocol, expected) in [
+        ("mdns.home.arpa.", "127.0.0.1:53000", Protocol::Udp, ResponseCode::Refused),
+        ("mdns.home.arpa.", "192.0.2.1:53000", Protocol::Tcp, ResponseCode::Refused),
+        ("printer.mdns.home.arpa.", "127.0.0.1:53000", Protocol::Tcp, ResponseCode::NotAuth),
+    ] {
+        let response = transfer(&handler, name, client, protocol).await;
+        assert_eq!(response.response_code(), expected, "{} from {} over {}", name, client, protocol);
+    }
+}
+
+#[tokio::test]
+async fn test_service_group_answers_union_of_members() {
+    use crate::config::Config;
+    use crate::dns_handler::{ClientMeta, QueryEngine};
+    use hickory_proto::rr::rdata::PTR;
+    use hickory_proto::rr::{Name, RData, Record, RecordType};
+
+    let mut config = Config::default();
+    config.service_groups.insert(
+        "Printers".to_string(),
+        vec!["_ipp._tcp".to_string(), "_printer._tcp".to_string(), "_pdl-datastream._tcp".to_string()],
+    );
+    let resolver = Arc::new(MdnsResolver::new(Arc::new(config)).unwrap());
+    resolver.set_read_only(true);
+    let pointer = |service_type: &str, instance: &str| {
+        let owner = Name::from_utf8(format!("{}.mdns.home.arpa.", service_type)).unwrap();
+        let target = Name::from_utf8(format!("{}.{}.mdns.home.arpa.", instance, service_type)).unwrap();
+        Record::from_rdata(owner, 10, RData::PTR(PTR(target)))
+    };
+    resolver.cache.insert(
+        "_ipp._tcp.mdns.home.arpa.",
+        RecordType::PTR,
+        vec![pointer("_ipp._tcp", "office"), pointer("_ipp._tcp", "lab")],
+    );
+    resolver.cache.insert("_printer._tcp.mdns.home.arpa.", RecordType::PTR, vec![pointer("_printer._tcp", "office")]);
+    let zones = Arc::new(crate::zones::ZoneRegistry::new(&["mdns.home.arpa."]).unwrap());
+    let engine = QueryEngine::new(resolver, zones);
+    let client = ClientMeta::new("127.0.0.1:53000".parse().unwrap(), hickory_proto::xfer::Protocol::Udp);
+
+    let group = Name::from_utf8("printers.mdns.home.arpa.").unwrap();
+    let answer = engine.resolve(&group, RecordType::PTR, &client).await;
+    assert_eq!(answer.response_code, ResponseCode::NoError);
+    assert_eq!(answer.answers.len(), 3);
+    assert!(answer.answers.iter().all(|record| record.name() == &group));
+
+    // Only PTR queries are answered for the group
+    let answer = engine.resolve(&group, RecordType::A, &client).await;
+    assert!(answer.answers.is_empty());
+}
+
+#[tokio::test]
+async fn test_reverse_lookup_from_cached_address() {
+    use crate::config::Config;
+    use crate::dns_handler::{ClientMeta, QueryEngine};
+    use hickory_proto::rr::rdata::{A, AAAA};
+    use hickory_proto::rr::{Name, RData, Record, RecordType};
+
+    let resolver = Arc::new(MdnsResolver::new(Arc::new(Config::default())).unwrap());
+    resolver.set_read_only(true);
+    let host = Name::from_utf8("printer.mdns.home.arpa.").unwrap();
+    resolver.cache.insert(
+        "printer.mdns.home.arpa.",
+        RecordType::A,
+        vec![Record::from_rdata(host.clone(), 120, RData::A(A::new(192, 168, 1, 20)))],
+    );
+    let link_local = AAAA::new(0xfe80, 0, 0, 0, 0x0211, 0x32ff, 0xfe12, 0x3456);
+    resolver.cache.insert(
+        "printer.mdns.home.arpa.",
+        RecordType::AAAA,
+        vec![Record::from_rdata(host.clone(), 120, RData::AAAA(link_local))],
+    );
+    let zones = Arc::new(crate::zones::ZoneRegistry::new(&["mdns.home.arpa."]).unwrap());
+    let engine = QueryEngine::new(resolver, zones);
+    let client = ClientMeta::new("127.0.0.1:53000".parse().unwrap(), hickory_proto::xfer::Protocol::Udp);
+
+    let reverse = Name::from_utf8("20.1.168.192.in-addr.arpa.").unwrap();
+    let answer = engine.resolve(&reverse, RecordType::PTR, &client).await;
+    assert_eq!(answer.response_code, ResponseCode::NoError);
+    assert_eq!(answer.answers.len(), 1);
+    assert_eq!(answer.answers[0].name(), &reverse);
+    assert_eq!(answer.answers[0].data(), &RData::PTR(hickory_proto::rr::rdata::PTR(host.clone())));
+
+    let reverse = Name::from_utf8("6.5.4.3.2.1.e.f.f.f.2.3.1.1.2.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.e.f.ip6.arpa.").unwrap();
+    let answer = engine.resolve(&reverse, RecordType::PTR, &client).await;
+    assert_eq!(answer.response_code, ResponseCode::NoError);
+    assert_eq!(answer.answers[0].data(), &RData::PTR(hickory_proto::rr::rdata::PTR(host)));
+
+    // Nothing cached holds the address, and a read-only resolver does not ask the link
+    let unknown = Name::from_utf8("21.1.168.192.in-addr.arpa.").unwrap();
+    let answer = engine.resolve(&unknown, RecordType::PTR, &client).await;
+    assert_eq!(answer.response_code, ResponseCode::NXDomain);
+
+    // Public addresses cannot be on the link
+    let public = Name::from_utf8("8.8.8.8.in-addr.arpa.").unwrap();
+    let answer = engine.resolve(&public, RecordType::PTR, &client).await;
+    assert_eq!(answer.response_code, ResponseCode::NXDomain);
+    let public = Name::from_utf8("1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa.").unwrap();
+    let answer = engine.resolve(&public, RecordType::PTR, &client).await;
+    assert_eq!(answer.response_code, ResponseCode::NXDomain);
+}
+
+#[tokio::test]
+async fn test_nodata_answer_carries_nsec_of_existing_types() {
+    use crate::config::Config;
+    use crate::dns_handler::{ClientMeta, QueryEngine};
+    use hickory_proto::rr::rdata::A;
+    use hickory_proto::rr::{Name, RData, Record, RecordType};
+
+    let resolver = Arc::new(MdnsResolver::new(Arc::new(Config::default())).unwrap());
+    resolver.set_read_only(true);
+    let host = Name::from_utf8("printer.mdns.home.arpa.").unwrap();
+    resolver.cache.insert(
+        "printer.mdns.home.arpa.",
+        RecordType::A,
+        vec![Record::from_rdata(host.clone(), 120, RData::A(A::new(192, 168, 1, 20)))],
+    );
+    let zones = Arc::new(crate::zones::ZoneRegistry::new(&["mdns.home.arpa."]).unwrap());
+    let engine = QueryEngine::new(resolver.clone(), zones);
+    let client = ClientMeta::new("127.0.0.1:53000".parse().unwrap(), hickory_proto::xfer::Protocol::Udp);
+
+    let answer = engine.resolve(&host, RecordType::AAAA, &client).await;
+    assert_eq!(answer.response_code, ResponseCode::NoError);
+    assert!(answer.answers.is_empty());
+    assert_eq!(answer.authority.len(), 2);
+    assert_eq!(answer.authority[0].record_type(), RecordType::SOA);
+    let nsec = &answer.authority[1];
+    assert_eq!(nsec.record_type(), RecordType::NSEC);
+    assert_eq!(nsec.name(), &host);
+    let RData::Unknown { rdata, .. } = nsec.data() else {
+        panic!("authority is not an NSEC");
+    };
+    // Next name printer.mdns.home.arpa., then window 0 with only A set
+    assert_eq!(&rdata.anything()[24..], &[0, 1, 0x40]);
+
+    // A name with nothing cached has no types to list
+    let unknown = Name::from_utf8("scanner.mdns.home.arpa.").unwrap();
+    let answer = engine.resolve(&unknown, RecordType::AAAA, &client).await;
+    assert!(answer.authority.iter().all(|r| r.record_type() == RecordType::SOA));
+
+    let mut config = Config::default();
+    config.server.nsec_records = false;
+    resolver.reload(Arc::new(config));
+    let answer = engine.resolve(&host, RecordType::AAAA, &client).await;
+    assert!(answer.authority.iter().all(|r| r.record_type() == RecordType::SOA));
+}
+
+#[tokio::test]
+async fn test_nodata_answer_carries_zone_soa() {
+    use crate::config::{Config, ZoneConfig};
+    use crate::dns_handler::lint::lint_response;
+    use crate::dns_handler::{ClientMeta, QueryEngine};
+    use hickory_proto::rr::{Name, RData, RecordType};
+
+    let mut config = Config::default();
+    config.zones.insert("mdns.home.arpa.".to_string(), ZoneConfig { soa_minimum_secs: 4 });
+    let resolver = MdnsResolver::new(Arc::new(config)).unwrap();
+    resolver.set_read_only(true);
+    let zones = Arc::new(crate::zones::ZoneRegistry::new(&["mdns.home.arpa."]).unwrap());
+    let engine = QueryEngine::new(Arc::new(resolver), zones);
+    let client = ClientMeta::new("127.0.0.1:53000".parse().unwrap(), hickory_proto::xfer::Protocol::Udp);
+
+    let apex = Name::from_utf8("mdns.home.arpa.").unwrap();
+    let name = Name::from_utf8("scanner.mdns.home.arpa.").unwrap();
+    let answer = engine.resolve(&name, RecordType::A, &client).await;
+    assert_eq!(answer.response_code, ResponseCode::NoError);
+    assert!(answer.answers.is_empty());
+    assert_eq!(answer.authority.len(), 1);
+    assert_eq!(answer.authority[0].name(), &apex);
+    let RData::SOA(soa) = answer.authority[0].data() else {
+        panic!("authority is not an SOA");
+    };
+    // The zone's own negative-caching TTL, as the proxy's cache uses
+    assert_eq!(soa.minimum(), 4);
+    let violations = lint_response(&name, RecordType::A, answer.response_code, &apex, &answer.answers, &answer.authority, &answer.additionals);
+    assert!(violations.is_empty(), "{:?}", violations);
+}
+
+#[tokio::test]
+async fn test_lint_checks_finished_answer() {
+    use crate::config::{Config, LintMode};
+    use crate::dns_handler::{ClientMeta, QueryEngine};
+    use crate::policy::PolicyStore;
+    use hickory_proto::rr::{Name, RecordType};
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("policy.rpz");
+    std::fs::write(&path, "$ORIGIN rpz.example.\nnas.mdns.home.arpa 60 A 192.168.1.50\n").unwrap();
+    let policy = Arc::new(PolicyStore::open(&path).unwrap());
+    let engine = |lint| {
+        let mut config = Config::default();
+        config.debug.lint = lint;
+        config.debug.provenance_record = true;
+        let resolver = MdnsResolver::new(Arc::new(config)).unwrap();
+        resolver.set_read_only(true);
+        let zones = Arc::new(crate::zones::ZoneRegistry::new(&["mdns.home.arpa."]).unwrap());
+        QueryEngine::new(Arc::new(resolver), zones).with_policy(policy.clone())
+    };
+    let client = ClientMeta::new("127.0.0.1:53000".parse().unwrap(), hickory_proto::xfer::Protocol::Udp);
+    let nas = Name::from_utf8("nas.mdns.home.arpa.").unwrap();
+
+    // Log mode leaves the answer alone
+    let answer = engine(LintMode::Log).resolve(&nas, RecordType::A, &client).await;
+    assert_eq!(answer.answers.len(), 1);
+
+    // The policy record's TTL is above the cap and goes; the provenance record,
+    // added after the other rewrites, is checked too and passes
+    let answer = engine(LintMode::Drop).resolve(&nas, RecordType::A, &client).await;
+    assert!(answer.answers.is_empty());
+    assert_eq!(answer.additionals.len(), 1);
+    assert_eq!(answer.additionals[0].record_type(), RecordType::TXT);
+}
+
+#[tokio::test]
+async fn test_policy_and_non_dns_sd_negative_answers_carry_zone_soa() {
+    use crate::config::{Config, NonDnsSdNames};
+    use crate::dns_handler::{ClientMeta, QueryEngine};
+    use crate::policy::PolicyStore;
+    use hickory_proto::rr::{Name, RecordType};
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("policy.rpz");
+    std::fs::write(
+        &path,
+        "$ORIGIN rpz.example.\n\
+         blocked.mdns.home.arpa 60 CNAME .\n\
+         hidden.mdns.home.arpa 60 CNAME *.\n",
+    )
+    .unwrap();
+    let policy = Arc::new(PolicyStore::open(&path).unwrap());
+    let engine = |non_dns_sd_names| {
+        let mut config = Config::default();
+        config.mdns.non_dns_sd_names = non_dns_sd_names;
+        let resolver = MdnsResolver::new(Arc::new(config)).unwrap();
+        resolver.set_read_only(true);
+        let zones = Arc::new(crate::zones::ZoneRegistry::new(&["mdns.home.arpa."]).unwrap());
+        QueryEngine::new(Arc::new(resolver), zones).with_policy(policy.clone())
+    };
+    let client = ClientMeta::new("127.0.0.1:53000".parse().unwrap(), hickory_proto::xfer::Protocol::Udp);
+    let apex = Name::from_utf8("mdns.home.arpa.").unwrap();
+
+    // RFC 2308 Sections 2.1 and 2.2: NXDOMAIN and NODATA both carry the zone SOA
+    let cases = [
+        (NonDnsSdNames::NoData, "blocked.mdns.home.arpa.", ResponseCode::NXDomain),
+        (NonDnsSdNames::NoData, "hidden.mdns.home.arpa.", ResponseCode::NoError),
+        (NonDnsSdNames::NxDomain, "_dmarc.mdns.home.arpa.", ResponseCode::NXDomain),
+        (NonDnsSdNames::NoData, "_dmarc.mdns.home.arpa.", ResponseCode::NoError),
+    ];
+    for (non_dns_sd_names, name, response_code) in cases {
+        let name = Name::from_utf8(name).unwrap();
+        let answer = engine(non_dns_sd_names).resolve(&name, RecordType::TXT, &client).await;
+        assert_eq!(answer.response_code, response_code, "{}", name);
+        assert!(answer.answers.is_empty(), "{}", name);
+        assert_eq!(answer.authority.len(), 1, "{}", name);
+        assert_eq!(answer.authority[0].record_type(), RecordType::SOA, "{}", name);
+        assert_eq!(answer.authority[0].name(), &apex, "{}", name);
+    }
+
+    // Outside every served zone there is no SOA to give
+    let outside = Name::from_utf8("printer.example.com.").unwrap();
+    let answer = engine(NonDnsSdNames::NoData).resolve(&outside, RecordType::A, &client).await;
+    assert_eq!(answer.response_code, ResponseCode::NXDomain);
+    assert!(answer.authority.is_empty());
+}
+
+#[tokio::test]
+async fn test_browse_bundle_trimmed_without_tc_over_udp() {
+    use hickory_proto::op::Message;
+    use hickory_proto::rr::rdata::{PTR, SRV, TXT};
+    use hickory_proto::rr::{Name, RData, Record, RecordType};
+    use hickory_proto::xfer::Protocol;
+    use hickory_server::server::RequestHandler;
+
+    let (resolver, handler) = cache_only_handler();
+    let service = Name::from_utf8("_ipp._tcp.mdns.home.arpa.").unwrap();
+    let host = Name::from_utf8("printer.mdns.home.arpa.").unwrap();
+    let mut pointers = Vec::new();
+    for i in 0..4 {
+        let instance = Name::from_utf8(format!("printer-{}._ipp._tcp.mdns.home.arpa.", i)).unwrap();
+        pointers.push(Record::from_rdata(service.clone(), 10, RData::PTR(PTR(instance.clone()))));
+        let srv = Record::from_rdata(instance.clone(), 10, RData::SRV(SRV::new(0, 0, 631, host.clone())));
+        let txt = Record::from_rdata(instance.clone(), 10, RData::TXT(TXT::new(vec!["x".repeat(150)])));
+        let key = instance.to_ascii().to_lowercase();
+        resolver.cache.insert(&key, RecordType::SRV, vec![srv]);
+        resolver.cache.insert(&key, RecordType::TXT, vec![txt]);
+    }
+    resolver.cache.insert("_ipp._tcp.mdns.home.arpa.", RecordType::PTR, pointers);
+    let request = |protocol| testing::request(&query("_ipp._tcp.mdns.home.arpa.", RecordType::PTR), "127.0.0.1:53000".parse().unwrap(), protocol);
+
+    // Every instance is answered; the bundles that do not fit are left out without TC
+    let response_handle = CapturingResponseHandler::new(Protocol::Udp);
+    handler.handle_request(&request(Protocol::Udp), response_handle.clone()).await;
+    let bytes = response_handle.take_bytes().unwrap();
+    assert!(bytes.len() <= 512);
+    let response = Message::from_vec(&bytes).unwrap();
+    assert!(!response.truncated());
+    assert_eq!(response.answers().len(), 4);
+    assert!(!response.additionals().is_empty() && response.additionals().len() < 8);
+    assert_eq!(response.additionals()[0].record_type(), RecordType::SRV);
+
+    let response_handle = CapturingResponseHandler::new(Protocol::Tcp);
+    handler.handle_request(&request(Protocol::Tcp), response_handle.clone()).await;
+    let response = response_handle.take_response().unwrap();
+    assert_eq!(response.additionals().len(), 8);
+}
+
+#[tokio::test]
+async fn test_panic_while_answering_is_isolated() {
+    use crate::mdns_resolver::faults::Faults;
+    use hickory_proto::rr::{Name, RData, RecordType};
+    use hickory_server::server::RequestHandler;
+
+    let faults = Arc::new(Faults::new(5).with_browse_panics(1.0));
+    let resolver = MdnsResolver::new(Arc::new(crate::config::Config::default()))
+        .unwrap()
+        .with_faults(faults.clone());
+    let handler = MdnsDnsHandler::new(Arc::new(resolver), "mdns.home.arpa.".to_string());
+    let client = "127.0.0.1:53000".parse().unwrap();
+    let ask = |name: &str, record_type| {
+        let mut query = hickory_proto::op::Message::new();
+        query.add_query(hickory_proto::op::Query::query(Name::from_utf8(name).unwrap(), record_type));
+        testing::request(&query, client, hickory_proto::xfer::Protocol::Udp)
+    };
+    let panics = || crate::metrics::metrics().handler_panics.load(std::sync::atomic::Ordering::Relaxed);
+    let before = panics();
+
+    // Browsing for the PTR answer panics; the client still gets an answer
+    let response_handle = CapturingResponseHandler::default();
+    handler.handle_request(&ask("_ipp._tcp.mdns.home.arpa.", RecordType::PTR), response_handle.clone()).await;
+    let response = response_handle.take_response().expect("no response sent");
+    assert_eq!(response.response_code(), ResponseCode::ServFail);
+    assert_eq!(faults.injected().browse_panics, 1);
+    assert!(panics() > before || !crate::metrics::ENABLED);
+
+    // and the handler goes on answering
+    let response_handle = CapturingResponseHandler::default();
+    handler.handle_request(&ask("mdns.home.arpa.", RecordType::SOA), response_handle.clone()).await;
+    let response = response_handle.take_response().expect("no response sent");
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert!(matches!(response.answers()[0].data(), RData::SOA(_)));
+}
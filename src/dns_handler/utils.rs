@@ -1,6 +1,9 @@
+use hickory_proto::serialize::binary::BinEncoder;
 use hickory_server::authority::MessageResponseBuilder;
-use hickory_server::server::Request;
-use hickory_proto::op::{Header, ResponseCode};
+use hickory_server::server::{Request, RequestInfo};
+use hickory_proto::op::{Edns, Header, MessageType, OpCode, ResponseCode};
+use hickory_proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+use hickory_proto::rr::Record;
 use tracing::{debug, error, info};
 
 /// Check if a domain name should be handled by the mDNS proxy
@@ -16,46 +19,129 @@ pub fn should_handle_domain(name: &str, discovery_domain: &str) -> bool {
     let matches_discovery = normalized_name == normalized_discovery
         || normalized_name.ends_with(&discovery_suffix);
 
-    // Handle configured discovery domain queries (mapped to .local for mDNS)
-    if matches_discovery {
-        return true;
+    // Every name in the discovery domain is handled (mapped to .local for mDNS);
+    // whether it is a DNS-SD name is decided by `names::classify`
+    matches_discovery
+}
+
+/// EDNS option code for Extended DNS Errors (RFC 8914)
+const EDE_OPTION_CODE: u16 = 15;
+
+/// Extended DNS Error to attach to a response (RFC 8914)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtendedError {
+    pub info_code: u16,
+    pub extra_text: String,
+}
+
+impl ExtendedError {
+    /// INFO-CODE 0: Other Error
+    pub const OTHER: u16 = 0;
+    /// INFO-CODE 14: Not Ready
+    pub const NOT_READY: u16 = 14;
+
+    pub fn new(info_code: u16, extra_text: impl Into<String>) -> Self {
+        Self {
+            info_code,
+            extra_text: extra_text.into(),
+        }
+    }
+}
+
+/// Largest UDP response to a client that did not use EDNS (RFC 1035 Section 4.2.1)
+pub const CLASSIC_UDP_PAYLOAD: u16 = 512;
+
+/// Largest EDNS UDP payload size this proxy will advertise
+const MAX_EDNS_UDP_PAYLOAD: u16 = 4096;
+
+/// EDNS version this proxy implements; requests with a higher one get BADVERS
+pub const EDNS_VERSION: u8 = 0;
+
+/// EDNS for the response to a request that carried `request_edns`, optionally with an EDE option.
+/// The payload size is the smaller of the client's and `server_payload`, so hickory sizes
+/// UDP answers to what both ends can take (RFC 6891 Section 6.2.5).
+pub fn response_edns(request_edns: &Edns, server_payload: u16, error: Option<&ExtendedError>) -> Edns {
+    let server_payload = server_payload.clamp(CLASSIC_UDP_PAYLOAD, MAX_EDNS_UDP_PAYLOAD);
+    let mut edns = Edns::new();
+    edns.set_max_payload(request_edns.max_payload().clamp(CLASSIC_UDP_PAYLOAD, server_payload));
+    edns.set_version(EDNS_VERSION);
+    edns.set_dnssec_ok(false);
+    if let Some(error) = error {
+        let mut data = error.info_code.to_be_bytes().to_vec();
+        data.extend_from_slice(error.extra_text.as_bytes());
+        edns.options_mut().insert(EdnsOption::Unknown(EDE_OPTION_CODE, data));
     }
-    
-    // Handle mDNS service discovery queries only within the discovery domain
-    if (normalized_name.contains("._tcp.") || normalized_name.contains("._udp.")) && matches_discovery {
-        return true;
+    edns
+}
+
+/// Add an EDNS Padding option (RFC 7830) to `edns`, sized so a response of
+/// `len` bytes without it grows to the next multiple of `block` bytes. A
+/// response that would then exceed the largest DNS message is left unpadded.
+pub fn pad_response(edns: &mut Edns, len: usize, block: usize) {
+    // The option adds its code and length before any padding octets
+    let unpadded = len + 4;
+    let padded = unpadded.next_multiple_of(block.max(1));
+    if padded > usize::from(u16::MAX) {
+        return;
     }
-    
-    false
+    edns.options_mut()
+        .insert(EdnsOption::Unknown(u16::from(EdnsCode::Padding), vec![0; padded - unpadded]));
 }
 
-/// Parse DNS request and create initial response components
-/// Returns a tuple of (header, builder) or None if request is malformed
-pub fn parse_dns_request(request: &Request) -> Option<(Header, MessageResponseBuilder<'_>)> {
-    let request_message = match request.request_info() {
-        Ok(info) => info,
-        Err(e) => {
-            error!("Error getting request info: {}", e);
-            let mut header = Header::new();
-            header.set_response_code(ResponseCode::FormErr);
-            let builder = MessageResponseBuilder::from_message_request(request);
-            return Some((header, builder));
-        }
-    };
-    
+/// Encoded size of a response to `request` with `header`, these sections and `edns`,
+/// encoded the way hickory will send it
+pub fn encoded_len(
+    request: &Request,
+    header: Header,
+    edns: Option<&Edns>,
+    answers: &[Record],
+    authority: &[Record],
+    additionals: &[Record],
+) -> usize {
+    let mut builder = MessageResponseBuilder::from_message_request(request);
+    if let Some(edns) = edns {
+        builder.edns(edns.clone());
+    }
+    let response = builder.build(header, answers.iter(), authority.iter(), std::iter::empty(), additionals.iter());
+    let mut bytes = Vec::new();
+    let mut encoder = BinEncoder::new(&mut bytes);
+    match response.destructive_emit(&mut encoder) {
+        Ok(_) => bytes.len(),
+        Err(_) => usize::MAX,
+    }
+}
+
+/// Response header for a request: copies the id, opcode and flags, marked non-authoritative
+pub fn response_header(request: &Request) -> Header {
+    let mut header = Header::response_from_request(request.header());
+    header.set_authoritative(false);
+    header
+}
+
+/// Extract the single question from a request
+/// Returns the response code to send instead when the request cannot be answered
+pub fn parse_dns_request(request: &Request) -> Result<RequestInfo<'_>, ResponseCode> {
+    if request.message_type() != MessageType::Query {
+        debug!("Ignoring DNS message that is not a query");
+        return Err(ResponseCode::FormErr);
+    }
+    if request.op_code() != OpCode::Query {
+        debug!("Unsupported opcode {:?}", request.op_code());
+        return Err(ResponseCode::NotImp);
+    }
+
+    let request_message = request.request_info().map_err(|e| {
+        error!("Error getting request info: {}", e);
+        ResponseCode::FormErr
+    })?;
+
     info!(
         "Received DNS query: {} {:?}",
         request_message.query.name(),
         request_message.query.query_type()
     );
 
-    // Build response
-    let mut header = Header::response_from_request(request.header());
-    header.set_authoritative(false);
-    
-    let builder = MessageResponseBuilder::from_message_request(request);
-
-    Some((header, builder))
+    Ok(request_message)
 }
 
 /// Build a DNS response based on mDNS query results
@@ -81,3 +167,31 @@ pub fn build_response_from_records(
         }
     }
 }
+
+/// Random offset in `0..=max` taken off the TTLs of one response
+pub fn ttl_jitter_offset(max: u32) -> u32 {
+    if max == 0 {
+        return 0;
+    }
+    (crate::random::random_u64() % (u64::from(max) + 1)) as u32
+}
+
+/// Lower every nonzero TTL by `offset`, keeping at least 1 second; one offset per response
+/// keeps the TTLs within each RRset equal (RFC 2181 Section 5.2)
+pub fn apply_ttl_jitter<'a>(records: impl IntoIterator<Item = &'a mut hickory_proto::rr::Record>, offset: u32) {
+    for record in records {
+        // TTL 0 (fresh answers) means "do not cache" and stays that way
+        if record.ttl() > 0 {
+            record.set_ttl(record.ttl().saturating_sub(offset).max(1));
+        }
+    }
+}
+
+/// Raise every nonzero TTL below `floor` to it; TTL 0 stays 0
+pub fn apply_ttl_floor<'a>(records: impl IntoIterator<Item = &'a mut hickory_proto::rr::Record>, floor: u32) {
+    for record in records {
+        if record.ttl() > 0 && record.ttl() < floor {
+            record.set_ttl(floor);
+        }
+    }
+}
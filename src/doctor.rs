@@ -0,0 +1,289 @@
+//! `doctor` subcommand
+//!
+//! Resolves one question twice: through the same answer pipeline the DNS server
+//! uses, and straight from mDNS with no cache, name mapping or TTL capping. Both
+//! record sets are printed with a diff, followed by the discrepancies found:
+//! names that could not be mapped, suppressed or missing records, extra records
+//! and TTLs that differ from the capped mDNS TTL.
+
+use crate::config::Config;
+use crate::dns_handler::admin_records::{
+    should_suppress_address_record, should_suppress_srv_record, RecordSuppressionConfig,
+};
+use crate::dns_handler::MdnsDnsHandler;
+use crate::mdns_resolver::{
+    from_presentation, presentation, rdata_presentation, rewrite_records_to_discovery_domain, MdnsResolver,
+    MAX_UNICAST_TTL,
+};
+use crate::zones::ZoneRegistry;
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::{Name, Record, RecordType};
+use std::fmt;
+use std::fmt::Write;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+
+type DoctorResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// A difference between the proxy answer and the direct mDNS answer
+#[derive(Debug, Clone, PartialEq)]
+pub enum Discrepancy {
+    /// Record whose owner name is not where the zone mapping should have put it
+    Unmapped(Record),
+    /// Direct record the proxy dropped as unusable for the client (RFC 8766 Section 5.5.2)
+    Suppressed(Record),
+    /// Direct record missing from the proxy answer
+    Missing(Record),
+    /// Proxy record with no direct counterpart
+    Extra(Record),
+    /// Record present in both answers whose proxy TTL is not the capped mDNS TTL
+    Ttl { record: Record, proxy: u32, expected: u32 },
+}
+
+impl fmt::Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Discrepancy::Unmapped(r) => write!(f, "mapping: {} is outside the expected zone", brief(r)),
+            Discrepancy::Suppressed(r) => write!(f, "suppression: {} was withheld from the client", brief(r)),
+            Discrepancy::Missing(r) => write!(f, "missing: {} is only in the direct answer", brief(r)),
+            Discrepancy::Extra(r) => write!(f, "extra: {} is only in the proxy answer", brief(r)),
+            Discrepancy::Ttl { record, proxy, expected } => {
+                write!(f, "ttl: {} has TTL {}, expected {}", brief(record), proxy, expected)
+            }
+        }
+    }
+}
+
+/// Both answers to one question
+#[derive(Debug)]
+pub struct Report {
+    pub name: Name,
+    pub record_type: RecordType,
+    pub zone: Name,
+    pub proxy_code: ResponseCode,
+    pub proxy_answers: Vec<Record>,
+    pub proxy_additionals: Vec<Record>,
+    /// Direct answer with its original `.local.` names
+    pub direct: Vec<Record>,
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+/// Resolve `name` both ways, print the report and return the number of discrepancies
+pub async fn run(config: Arc<Config>, name: &str, record_type: RecordType, client: Option<IpAddr>) -> DoctorResult<usize> {
+    // Decimal escapes as dig prints them; raw spaces, as instance names usually contain, are kept too
+    let name = from_presentation(name)?.to_lowercase();
+    let zones = Arc::new(ZoneRegistry::new(&[config.discovery_domain()])?);
+    let zone = zones
+        .zone_for(&name)
+        .ok_or_else(|| format!("{} is not in discovery domain {}", presentation(&name), config.discovery_domain()))?;
+
+    let resolver = Arc::new(MdnsResolver::new(config)?);
+    let suppression = RecordSuppressionConfig {
+        enabled: true,
+        client_ip: client,
+        ..Default::default()
+    };
+    let handler = MdnsDnsHandler::with_zones(resolver.clone(), zones).with_suppression(suppression.clone());
+
+    let client_addr = SocketAddr::new(client.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST)), 0);
+    let (proxy_code, proxy_answers, proxy_additionals) = match handler.lookup(&name, record_type, client_addr).await {
+        Ok((answers, additionals)) => (ResponseCode::NoError, answers, additionals),
+        Err(code) => (code, Vec::new(), Vec::new()),
+    };
+    let direct = resolver.query_direct(&name, &zone, record_type).await?;
+    let discrepancies = compare(&proxy_answers, &direct, &zone, &suppression)?;
+
+    let report = Report {
+        name,
+        record_type,
+        zone,
+        proxy_code,
+        proxy_answers,
+        proxy_additionals,
+        direct,
+        discrepancies,
+    };
+    print!("{}", render(&report));
+    Ok(report.discrepancies.len())
+}
+
+/// Find the discrepancies between a proxy answer and the raw mDNS answer it came from
+pub fn compare(
+    proxy: &[Record],
+    direct: &[Record],
+    zone: &Name,
+    suppression: &RecordSuppressionConfig,
+) -> DoctorResult<Vec<Discrepancy>> {
+    let local = Name::from_ascii("local.")?;
+    let mapped = rewrite_records_to_discovery_domain(direct.to_vec(), zone)?;
+    let mut matched = vec![false; proxy.len()];
+    let mut discrepancies = Vec::new();
+
+    for (raw, record) in direct.iter().zip(&mapped) {
+        if !local.zone_of(raw.name()) {
+            discrepancies.push(Discrepancy::Unmapped(raw.clone()));
+            continue;
+        }
+        // Record equality ignores TTLs, which are compared separately
+        match (0..proxy.len()).find(|&i| !matched[i] && proxy[i] == *record) {
+            Some(i) => {
+                matched[i] = true;
+                let expected = record.ttl().min(MAX_UNICAST_TTL);
+                if proxy[i].ttl() != expected {
+                    discrepancies.push(Discrepancy::Ttl {
+                        record: proxy[i].clone(),
+                        proxy: proxy[i].ttl(),
+                        expected,
+                    });
+                }
+            }
+            None if should_suppress_address_record(record, suppression)
+                || should_suppress_srv_record(record, &mapped, suppression) =>
+            {
+                discrepancies.push(Discrepancy::Suppressed(record.clone()));
+            }
+            None => discrepancies.push(Discrepancy::Missing(record.clone())),
+        }
+    }
+
+    for (record, _) in proxy.iter().zip(&matched).filter(|(_, matched)| !**matched) {
+        if zone.zone_of(record.name()) {
+            discrepancies.push(Discrepancy::Extra(record.clone()));
+        } else {
+            discrepancies.push(Discrepancy::Unmapped(record.clone()));
+        }
+    }
+
+    Ok(discrepancies)
+}
+
+/// Human-readable report
+pub fn render(report: &Report) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "Question: {} {}", presentation(&report.name), report.record_type);
+    let _ = writeln!(out, "Zone: {} (mapped to local.)", report.zone);
+    if let Some(service_type) = crate::service_types::registry().label(&presentation(&report.name)) {
+        let _ = writeln!(out, "Service type: {}", service_type);
+    }
+
+    let _ = writeln!(out, "\nProxy answer ({:?}, {} records):", report.proxy_code, report.proxy_answers.len());
+    for record in &report.proxy_answers {
+        let _ = writeln!(out, "  {}", record);
+    }
+    if !report.proxy_additionals.is_empty() {
+        let _ = writeln!(out, "Proxy additional ({} records):", report.proxy_additionals.len());
+        for record in &report.proxy_additionals {
+            let _ = writeln!(out, "  {}", record);
+        }
+    }
+
+    let _ = writeln!(out, "\nDirect mDNS answer ({} records):", report.direct.len());
+    for record in &report.direct {
+        let _ = writeln!(out, "  {}", record);
+    }
+
+    // Diff after mapping the direct answer into the zone; "-" is direct only, "+" proxy only
+    let mapped = rewrite_records_to_discovery_domain(report.direct.clone(), &report.zone).unwrap_or_default();
+    let _ = writeln!(out, "\nDiff:");
+    for record in &mapped {
+        let marker = if report.proxy_answers.contains(record) { ' ' } else { '-' };
+        let _ = writeln!(out, "{} {}", marker, brief(record));
+    }
+    for record in report.proxy_answers.iter().filter(|r| !mapped.contains(r)) {
+        let _ = writeln!(out, "+ {}", brief(record));
+    }
+
+    if report.discrepancies.is_empty() {
+        let _ = writeln!(out, "\nNo discrepancies");
+    } else {
+        let _ = writeln!(out, "\nDiscrepancies:");
+        for discrepancy in &report.discrepancies {
+            let _ = writeln!(out, "  {}", discrepancy);
+        }
+    }
+    out
+}
+
+/// Record without TTL or class, as compared in the diff
+fn brief(record: &Record) -> String {
+    format!("{} {} {}", presentation(record.name()), record.record_type(), rdata_presentation(record.data()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::rdata::{A, SRV};
+    use hickory_proto::rr::RData;
+
+    fn a(name: &str, ttl: u32, addr: [u8; 4]) -> Record {
+        Record::from_rdata(Name::from_utf8(name).unwrap(), ttl, RData::A(A::new(addr[0], addr[1], addr[2], addr[3])))
+    }
+
+    fn zone() -> Name {
+        Name::from_utf8("mdns.home.arpa.").unwrap()
+    }
+
+    #[test]
+    fn test_matching_answers_have_no_discrepancies() {
+        let direct = vec![a("printer.local.", 120, [192, 168, 1, 20])];
+        let proxy = vec![a("printer.mdns.home.arpa.", 10, [192, 168, 1, 20])];
+        let found = compare(&proxy, &direct, &zone(), &RecordSuppressionConfig::default()).unwrap();
+        assert!(found.is_empty(), "{:?}", found);
+    }
+
+    #[test]
+    fn test_ttl_missing_and_extra_flagged() {
+        let direct = vec![
+            a("printer.local.", 120, [192, 168, 1, 20]),
+            a("printer.local.", 120, [192, 168, 1, 21]),
+        ];
+        let proxy = vec![
+            a("printer.mdns.home.arpa.", 60, [192, 168, 1, 20]),
+            a("printer.mdns.home.arpa.", 10, [192, 168, 1, 99]),
+        ];
+        let found = compare(&proxy, &direct, &zone(), &RecordSuppressionConfig::default()).unwrap();
+        assert_eq!(found.len(), 3);
+        assert!(matches!(found[0], Discrepancy::Ttl { proxy: 60, expected: 10, .. }));
+        assert!(matches!(&found[1], Discrepancy::Missing(r) if r.name().to_utf8() == "printer.mdns.home.arpa."));
+        assert!(matches!(found[2], Discrepancy::Extra(_)));
+    }
+
+    #[test]
+    fn test_suppressed_and_unmapped_flagged() {
+        let link_local = a("printer.local.", 120, [169, 254, 3, 4]);
+        let srv = Record::from_rdata(
+            Name::from_utf8("Printer._ipp._tcp.local.").unwrap(),
+            120,
+            RData::SRV(SRV::new(0, 0, 631, Name::from_utf8("printer.local.").unwrap())),
+        );
+        let outside = a("printer.example.com.", 120, [192, 0, 2, 1]);
+        let suppression = RecordSuppressionConfig {
+            enabled: true,
+            client_ip: Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 5))),
+            ..Default::default()
+        };
+        let found = compare(&[], &[link_local, srv, outside], &zone(), &suppression).unwrap();
+        assert_eq!(found.len(), 3);
+        assert!(matches!(found[0], Discrepancy::Suppressed(_)));
+        assert!(matches!(found[1], Discrepancy::Suppressed(_)));
+        assert!(matches!(found[2], Discrepancy::Unmapped(_)));
+    }
+
+    #[test]
+    fn test_render_marks_diff_lines() {
+        let report = Report {
+            name: Name::from_utf8("printer.mdns.home.arpa.").unwrap(),
+            record_type: RecordType::A,
+            zone: zone(),
+            proxy_code: ResponseCode::NoError,
+            proxy_answers: vec![a("printer.mdns.home.arpa.", 10, [192, 168, 1, 99])],
+            proxy_additionals: Vec::new(),
+            direct: vec![a("printer.local.", 120, [192, 168, 1, 20])],
+            discrepancies: vec![Discrepancy::Extra(a("printer.mdns.home.arpa.", 10, [192, 168, 1, 99]))],
+        };
+        let text = render(&report);
+        assert!(text.contains("- printer.mdns.home.arpa. A 192.168.1.20"));
+        assert!(text.contains("+ printer.mdns.home.arpa. A 192.168.1.99"));
+        assert!(text.contains("extra: printer.mdns.home.arpa. A 192.168.1.99"));
+    }
+}
@@ -0,0 +1,198 @@
+//! Durations in the configuration
+//!
+//! Every timeout and interval setting names its unit (`tcp_timeout` in
+//! seconds, `service_query_timeout_ms` in milliseconds, ...) and still accepts
+//! a plain integer in that unit. It also accepts a string with units, such as
+//! "500ms", "2s", "5m" or "1h30m" (`ms`, `s`, `m`, `h` and `d`, with the usual
+//! long spellings), which is converted to the setting's unit. A string that
+//! would be read differently depending on who reads it is refused rather than
+//! guessed at: one without a unit, "M" (minutes or months), or one that is
+//! not a whole number of the setting's unit, like "1500ms" for seconds.
+
+use serde::de::{self, Deserializer, Visitor};
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::time::Duration;
+
+/// Unit a setting is counted in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Unit {
+    name: &'static str,
+    millis: u64,
+}
+
+pub const MILLIS: Unit = Unit { name: "milliseconds", millis: 1 };
+pub const SECS: Unit = Unit { name: "seconds", millis: 1_000 };
+pub const DAYS: Unit = Unit { name: "days", millis: 86_400_000 };
+
+/// Milliseconds in each suffix accepted after a number
+fn suffix_millis(suffix: &str) -> Result<u64, String> {
+    match suffix {
+        "ms" | "msec" | "msecs" | "millisecond" | "milliseconds" => Ok(1),
+        "s" | "sec" | "secs" | "second" | "seconds" => Ok(1_000),
+        "m" | "min" | "mins" | "minute" | "minutes" => Ok(60_000),
+        "h" | "hr" | "hrs" | "hour" | "hours" => Ok(3_600_000),
+        "d" | "day" | "days" => Ok(86_400_000),
+        "" => Err("a unit is needed (ms, s, m, h or d)".to_string()),
+        "M" => Err("\"M\" is ambiguous; use \"m\" for minutes".to_string()),
+        other => Err(format!("unknown unit \"{}\" (ms, s, m, h or d)", other)),
+    }
+}
+
+/// Parse a duration such as "500ms", "2s", "5m" or "1h30m"
+pub fn parse(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err("empty duration".to_string());
+    }
+    let mut rest = text;
+    let mut millis: u64 = 0;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        if digits == 0 {
+            return Err(format!("\"{}\" is not a duration", text));
+        }
+        let number: u64 = rest[..digits]
+            .parse()
+            .map_err(|_| format!("\"{}\" is too long a duration", text))?;
+        rest = rest[digits..].trim_start();
+        let end = rest.find(|c: char| c.is_ascii_digit() || c.is_whitespace()).unwrap_or(rest.len());
+        let unit = suffix_millis(&rest[..end]).map_err(|e| format!("\"{}\": {}", text, e))?;
+        rest = rest[end..].trim_start();
+        millis = number
+            .checked_mul(unit)
+            .and_then(|part| millis.checked_add(part))
+            .ok_or_else(|| format!("\"{}\" is too long a duration", text))?;
+    }
+    Ok(Duration::from_millis(millis))
+}
+
+/// `text` as a whole number of `unit`
+pub fn parse_in(text: &str, unit: Unit) -> Result<u64, String> {
+    let millis = parse(text)?.as_millis() as u64;
+    if !millis.is_multiple_of(unit.millis) {
+        return Err(format!("\"{}\" is not a whole number of {}", text.trim(), unit.name));
+    }
+    Ok(millis / unit.millis)
+}
+
+struct InUnit(Unit);
+
+impl Visitor<'_> for InUnit {
+    type Value = u64;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a number of {} or a duration such as \"500ms\", \"2s\" or \"5m\"", self.0.name)
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<u64, E> {
+        Ok(value)
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<u64, E> {
+        u64::try_from(value).map_err(|_| E::custom(format!("duration {} is negative", value)))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<u64, E> {
+        parse_in(value, self.0).map_err(E::custom)
+    }
+}
+
+fn deserialize_in<'de, D, T>(deserializer: D, unit: Unit) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: TryFrom<u64>,
+    T::Error: fmt::Display,
+{
+    let value = deserializer.deserialize_any(InUnit(unit))?;
+    T::try_from(value).map_err(|e| de::Error::custom(format!("{} {}: {}", value, unit.name, e)))
+}
+
+/// `deserialize_with` for a setting counted in milliseconds
+pub fn millis<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: TryFrom<u64>,
+    T::Error: fmt::Display,
+{
+    deserialize_in(deserializer, MILLIS)
+}
+
+/// `deserialize_with` for a setting counted in seconds
+pub fn secs<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: TryFrom<u64>,
+    T::Error: fmt::Display,
+{
+    deserialize_in(deserializer, SECS)
+}
+
+/// `deserialize_with` for a setting counted in days
+pub fn days<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: TryFrom<u64>,
+    T::Error: fmt::Display,
+{
+    deserialize_in(deserializer, DAYS)
+}
+
+/// `deserialize_with` for an optional setting counted in milliseconds
+pub fn millis_opt<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(serde::Deserialize)]
+    struct Millis(#[serde(deserialize_with = "millis")] u64);
+
+    let value: Option<Millis> = serde::Deserialize::deserialize(deserializer)?;
+    Ok(value.map(|Millis(ms)| ms))
+}
+
+/// `deserialize_with` for a table of settings counted in milliseconds
+pub fn millis_map<'de, D, K>(deserializer: D) -> Result<HashMap<K, u64>, D::Error>
+where
+    D: Deserializer<'de>,
+    K: serde::Deserialize<'de> + Eq + Hash,
+{
+    #[derive(serde::Deserialize)]
+    struct Millis(#[serde(deserialize_with = "millis")] u64);
+
+    let map: HashMap<K, Millis> = serde::Deserialize::deserialize(deserializer)?;
+    Ok(map.into_iter().map(|(key, Millis(ms))| (key, ms)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_durations() {
+        assert_eq!(parse("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse("2s"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse("1h30m"), Ok(Duration::from_secs(5_400)));
+        assert_eq!(parse(" 1 min 30 secs "), Ok(Duration::from_secs(90)));
+        assert_eq!(parse("7d"), Ok(Duration::from_secs(7 * 86_400)));
+
+        assert!(parse("").is_err());
+        assert!(parse("500").is_err());
+        assert!(parse("5M").unwrap_err().contains("ambiguous"));
+        assert!(parse("2 fortnights").is_err());
+        assert!(parse("1.5s").is_err());
+        assert!(parse("-2s").is_err());
+        assert!(parse("99999999999999999999s").is_err());
+    }
+
+    #[test]
+    fn test_parse_in_unit() {
+        assert_eq!(parse_in("2s", MILLIS), Ok(2_000));
+        assert_eq!(parse_in("2m", SECS), Ok(120));
+        assert_eq!(parse_in("2000ms", SECS), Ok(2));
+        assert!(parse_in("1500ms", SECS).unwrap_err().contains("whole number of seconds"));
+        assert_eq!(parse_in("48h", DAYS), Ok(2));
+        assert!(parse_in("36h", DAYS).is_err());
+    }
+}
@@ -1,8 +1,56 @@
+// The minimal build has no room for a TLS stack
+#[cfg(all(feature = "minimal", feature = "tls"))]
+compile_error!("the `minimal` feature cannot be combined with `tls`");
+
+pub mod admission;
+#[cfg(not(feature = "minimal"))]
+pub mod audit;
+pub mod authoritative;
+pub mod banner;
+#[cfg(all(feature = "batch-udp", target_os = "linux"))]
+pub mod batch_udp;
+#[cfg(not(feature = "minimal"))]
+pub mod bench;
+pub mod client;
+#[cfg(not(feature = "minimal"))]
+pub mod conformance;
 pub mod config;
+#[cfg(all(unix, not(feature = "minimal")))]
+pub mod control;
 pub mod dns_handler;
+#[cfg(not(feature = "minimal"))]
+pub mod doctor;
+pub mod duration;
+pub mod links;
+pub mod listener;
 pub mod mdns_resolver;
+pub mod metrics;
+pub mod netwatch;
+pub mod notify;
+pub mod own_addresses;
+#[cfg(not(feature = "minimal"))]
+pub mod peers;
+pub mod pending;
+pub mod policy;
+pub mod query_trace;
+pub mod push;
+pub mod quiet;
+pub mod random;
+pub mod reload;
+pub mod runtime;
+pub mod service_types;
+pub mod serving;
+pub mod slo;
+pub mod tcp;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod toggles;
+pub mod tsig;
+pub mod uci;
+pub mod versioned;
+pub mod zones;
 
 // Re-export commonly used types
-pub use config::{Args, Config};
+pub use config::{Args, Command, Config};
 pub use dns_handler::MdnsDnsHandler;
 pub use mdns_resolver::MdnsResolver;
@@ -0,0 +1,167 @@
+//! Per-link listeners
+//!
+//! RFC 8766 has one Discovery Proxy per link, each with a discovery domain of
+//! its own, so a client can tell which link a service is on. Every
+//! `[[links]]` entry gets a proxy of its own inside this process: UDP and TCP
+//! bound to the link's interface (SO_BINDTODEVICE, Linux only), a resolver
+//! whose mDNS daemon only uses that interface, and the handler of the main
+//! listener (policy, audit log, TSIG, ...) serving the link's domain. The main
+//! listener keeps serving `server.discovery_domain` from every interface.
+
+use crate::config::{Config, LinkConfig};
+use crate::dns_handler::MdnsDnsHandler;
+use crate::listener::bind_dns_sockets;
+use crate::mdns_resolver::MdnsResolver;
+use crate::tcp::{self, Drain, TcpLimits};
+use crate::zones::ZoneRegistry;
+use hickory_server::ServerFuture;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+type LinkResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// A link being served
+pub struct LinkServer {
+    pub link: LinkConfig,
+    pub resolver: Arc<MdnsResolver>,
+    /// First address the link's UDP and TCP listeners are bound to
+    pub addr: SocketAddr,
+    server: ServerFuture<MdnsDnsHandler>,
+}
+
+/// Refuse link settings that would make two proxies answer for one domain or interface
+pub fn check(config: &Config) -> Result<(), String> {
+    let main = crate::config::normalize_domain(&config.server.discovery_domain);
+    let mut domains = vec![main];
+    let mut interfaces: Vec<&str> = Vec::new();
+    for link in &config.links {
+        if link.interface.is_empty() {
+            return Err(format!("link {}: interface must not be empty", link.discovery_domain));
+        }
+        if interfaces.contains(&link.interface.as_str()) {
+            return Err(format!("interface {} is listed for more than one link", link.interface));
+        }
+        interfaces.push(&link.interface);
+        let domain = crate::config::normalize_domain(&link.discovery_domain);
+        if domains.contains(&domain) {
+            return Err(format!("discovery domain {} is served more than once", domain));
+        }
+        domains.push(domain);
+    }
+    Ok(())
+}
+
+/// Start serving `link` with `handler` re-pointed at a resolver of its own;
+/// TCP connections are wound down with `drain`
+pub async fn start(config: &Config, link: &LinkConfig, handler: &MdnsDnsHandler, drain: Drain) -> LinkResult<LinkServer> {
+    let config = Arc::new(config.for_link(link));
+    let resolver = Arc::new(MdnsResolver::new(config.clone())?);
+    let zones = Arc::new(ZoneRegistry::new(&[config.discovery_domain()])?);
+    let handler = handler.clone().with_resolver(resolver.clone(), zones);
+
+    let sockets = bind_dns_sockets(&config.server).await?;
+    let mut server = ServerFuture::new(handler.clone());
+    for udp in sockets.udp {
+        server.register_socket(udp);
+    }
+    let limits = TcpLimits::from_config(&config.server);
+    let handler = Arc::new(handler);
+    for listener in sockets.tcp {
+        let (handler, drain, interface) = (handler.clone(), drain.clone(), link.interface.clone());
+        tokio::spawn(async move {
+            if let Err(e) = tcp::serve(listener, handler, limits, None, drain).await {
+                error!("TCP listener for link {} failed: {}", interface, e);
+            }
+        });
+    }
+    let addrs: Vec<String> = sockets.addrs.iter().map(SocketAddr::to_string).collect();
+    info!(
+        "Serving discovery domain {} on {} at {}",
+        config.discovery_domain(),
+        link.interface,
+        addrs.join(", ")
+    );
+
+    Ok(LinkServer {
+        link: link.clone(),
+        resolver,
+        addr: sockets.addr,
+        server,
+    })
+}
+
+impl LinkServer {
+    /// Stop answering, give pending lookups until `deadline` to finish, then stop the daemon
+    pub async fn shutdown(mut self, deadline: Duration) {
+        let (stopped, lookups) = tokio::join!(self.server.shutdown_gracefully(), self.resolver.pending().wait_idle(deadline));
+        if let Err(e) = stopped {
+            error!("Listener for link {} failed during shutdown: {}", self.link.interface, e);
+        }
+        if lookups > 0 {
+            warn!("Abandoning {} lookup(s) on link {}", lookups, self.link.interface);
+        }
+        if let Err(e) = self.resolver.shutdown(Duration::from_secs(2)).await {
+            error!("Link {}: {}", self.link.interface, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(interface: &str, domain: &str) -> LinkConfig {
+        LinkConfig {
+            interface: interface.to_string(),
+            discovery_domain: domain.to_string(),
+            bind_address: None,
+            port: None,
+        }
+    }
+
+    #[test]
+    fn test_check_rejects_shared_domains_and_interfaces() {
+        let mut config = Config {
+            links: vec![link("eth1", "lan1.mdns.home.arpa"), link("eth2", "lan2.mdns.home.arpa.")],
+            ..Config::default()
+        };
+        assert!(check(&config).is_ok());
+
+        config.links.push(link("eth3", "LAN1.mdns.home.arpa."));
+        assert!(check(&config).unwrap_err().contains("more than once"));
+
+        config.links[2] = link("eth1", "lan3.mdns.home.arpa.");
+        assert!(check(&config).unwrap_err().contains("eth1"));
+
+        config.links[2] = link("eth3", "mdns.home.arpa");
+        assert!(check(&config).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_link_serves_its_own_domain() {
+        use hickory_proto::rr::Name;
+
+        let mut config = Config::default();
+        config.server.port = 0;
+        config.mdns.lazy_start = true;
+        let resolver = Arc::new(MdnsResolver::new(Arc::new(config.clone())).unwrap());
+        let handler = MdnsDnsHandler::new(resolver, config.discovery_domain().to_string());
+
+        let link = link("lo", "lo.mdns.home.arpa");
+        let server = match start(&config, &link, &handler, Drain::new()).await {
+            Ok(server) => server,
+            // SO_BINDTODEVICE needs CAP_NET_RAW before Linux 5.7
+            Err(e) if e.to_string().contains("ermission") => return,
+            Err(e) => panic!("{}", e),
+        };
+        assert!(server.addr.ip().is_loopback());
+        let link_config = server.resolver.config();
+        assert_eq!(link_config.discovery_domain(), "lo.mdns.home.arpa.");
+        assert_eq!(link_config.mdns.interfaces, vec!["lo"]);
+        assert!(handler.should_handle(&Name::from_utf8("tv.mdns.home.arpa.").unwrap()));
+        server.shutdown(Duration::from_secs(1)).await;
+    }
+}
@@ -0,0 +1,513 @@
+//! DNS socket binding
+//!
+//! Binds the UDP sockets and TCP listener on the configured port, optionally
+//! falling back to alternate ports, and turns bind failures into errors that
+//! say what went wrong and, where the OS exposes it, which process holds the port.
+//! Every bind address gets its own sockets on that port, so one process serves
+//! e.g. both 127.0.0.1 and ::1; an address whose family the host lacks (IPv6
+//! disabled) is skipped with a warning when another one binds.
+//! Several UDP sockets can share the port through SO_REUSEPORT so the kernel
+//! spreads queries over them. On Linux every socket can be pinned to one
+//! interface or VRF with SO_BINDTODEVICE, so a wildcard bind address still only
+//! serves that network.
+
+use crate::config::ServerConfig;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::{TcpListener, UdpSocket};
+use tracing::{info, warn};
+
+/// UDP sockets and TCP listeners bound on the same port
+pub struct BoundSockets {
+    /// At least one per address; more when `server.udp_sockets` asks for SO_REUSEPORT sockets
+    pub udp: Vec<UdpSocket>,
+    /// One per address
+    pub tcp: Vec<TcpListener>,
+    /// First address bound, with the port in use
+    pub addr: SocketAddr,
+    /// Every address bound
+    pub addrs: Vec<SocketAddr>,
+}
+
+/// Process holding a port, as far as the OS lets us see it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortHolder {
+    pub pid: u32,
+    pub name: String,
+}
+
+/// One failed attempt to bind a socket
+#[derive(Debug)]
+pub struct BindFailure {
+    pub addr: SocketAddr,
+    pub protocol: &'static str,
+    pub error: io::Error,
+    pub holder: Option<PortHolder>,
+}
+
+/// Every port was tried and none could be bound
+#[derive(Debug)]
+pub struct BindError {
+    pub failures: Vec<BindFailure>,
+}
+
+impl fmt::Display for BindFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}: {}", self.protocol, self.addr, self.error)?;
+        if let Some(holder) = &self.holder {
+            write!(f, " (in use by {}, pid {})", holder.name, holder.pid)?;
+        }
+        match self.error.kind() {
+            io::ErrorKind::AddrInUse => write!(
+                f,
+                "; stop the other process, change server.port or list alternates in server.fallback_ports"
+            ),
+            io::ErrorKind::PermissionDenied if self.addr.port() < 1024 => write!(
+                f,
+                "; ports below 1024 need root or CAP_NET_BIND_SERVICE"
+            ),
+            io::ErrorKind::AddrNotAvailable => write!(
+                f,
+                "; server.bind_address is not assigned to any local interface"
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for BindError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "could not bind DNS server")?;
+        for failure in &self.failures {
+            write!(f, "\n  {}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for BindError {}
+
+/// Bind UDP and TCP on every `server.bind_address` at `server.port`, then
+/// at each of `server.fallback_ports` in order
+pub async fn bind_dns_sockets(server: &ServerConfig) -> Result<BoundSockets, BindError> {
+    let mut failures = Vec::new();
+
+    let ports = std::iter::once(server.port).chain(server.fallback_ports.iter().copied());
+    for port in ports {
+        match bind_port(port, server).await {
+            Ok(sockets) => {
+                if port != server.port {
+                    warn!("Port {} unavailable, serving DNS on fallback port {}", server.port, port);
+                }
+                return Ok(sockets);
+            }
+            Err(mut failed) => failures.append(&mut failed),
+        }
+    }
+
+    Err(BindError { failures })
+}
+
+/// Bind every address on `port`
+async fn bind_port(mut port: u16, server: &ServerConfig) -> Result<BoundSockets, Vec<BindFailure>> {
+    let mut bound: Option<BoundSockets> = None;
+    let mut skipped = Vec::new();
+    for &ip in &server.bind_address {
+        match bind_pair(SocketAddr::new(ip, port), server).await {
+            Ok(pair) => {
+                // With port 0 the first address fixes the port for the others
+                port = pair.addr.port();
+                match &mut bound {
+                    Some(bound) => {
+                        bound.udp.extend(pair.udp);
+                        bound.tcp.extend(pair.tcp);
+                        bound.addrs.extend(pair.addrs);
+                    }
+                    None => bound = Some(pair),
+                }
+            }
+            Err(failure) if family_missing(&failure.error) => skipped.push(*failure),
+            Err(failure) => return Err(vec![*failure]),
+        }
+    }
+    match bound {
+        Some(bound) => {
+            for failure in skipped {
+                warn!("Not serving DNS on {}: {}", failure.addr, failure.error);
+            }
+            Ok(bound)
+        }
+        None => Err(skipped),
+    }
+}
+
+/// The host cannot have this address at all, as with ::1 when IPv6 is disabled
+fn family_missing(error: &io::Error) -> bool {
+    #[cfg(target_os = "linux")]
+    if error.raw_os_error() == Some(libc::EAFNOSUPPORT) {
+        return true;
+    }
+    error.kind() == io::ErrorKind::AddrNotAvailable
+}
+
+async fn bind_pair(addr: SocketAddr, server: &ServerConfig) -> Result<BoundSockets, Box<BindFailure>> {
+    let count = udp_socket_count(server.udp_sockets);
+    let reuse_port = count > 1;
+    let device = server.bind_device.as_deref();
+    let first = bind_udp(addr, reuse_port, server.udp_recv_buffer_bytes, device)
+        .map_err(|error| failure(addr, "UDP", error))?;
+    // With port 0 the OS picks the UDP port; TCP and further UDP sockets must follow it
+    let addr = first.local_addr().unwrap_or(addr);
+    let mut udp = vec![first];
+    while udp.len() < count {
+        udp.push(
+            bind_udp(addr, reuse_port, server.udp_recv_buffer_bytes, device)
+                .map_err(|error| failure(addr, "UDP", error))?,
+        );
+    }
+    if reuse_port {
+        info!("Opened {} UDP sockets on {} with SO_REUSEPORT", udp.len(), addr);
+    }
+    let tcp = bind_tcp(addr, device)
+        .await
+        .map_err(|error| failure(addr, "TCP", error))?;
+    Ok(BoundSockets {
+        udp,
+        tcp: vec![tcp],
+        addr,
+        addrs: vec![addr],
+    })
+}
+
+/// SO_REUSEPORT is Unix only; elsewhere a single socket is opened
+fn udp_socket_count(requested: usize) -> usize {
+    if cfg!(unix) {
+        requested.max(1)
+    } else {
+        if requested > 1 {
+            warn!("server.udp_sockets needs SO_REUSEPORT, which this platform lacks; opening one UDP socket");
+        }
+        1
+    }
+}
+
+fn bind_udp(addr: SocketAddr, reuse_port: bool, recv_buffer: Option<usize>, device: Option<&str>) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    only_v6(&socket, addr)?;
+    if let Some(device) = device {
+        bind_to_device(&socket, device)?;
+    }
+    #[cfg(unix)]
+    if reuse_port {
+        socket.set_reuse_port(true)?;
+    }
+    #[cfg(not(unix))]
+    let _ = reuse_port;
+    if let Some(size) = recv_buffer {
+        socket.set_recv_buffer_size(size)?;
+        // Linux reports double the requested size, to account for bookkeeping overhead
+        let granted = socket.recv_buffer_size()?;
+        if granted < size {
+            warn!(
+                "UDP receive buffer capped at {} bytes (asked for {}); raise the OS limit, e.g. net.core.rmem_max",
+                granted, size
+            );
+        }
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    UdpSocket::from_std(socket.into())
+}
+
+async fn bind_tcp(addr: SocketAddr, device: Option<&str>) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    only_v6(&socket, addr)?;
+    if let Some(device) = device {
+        bind_to_device(&socket, device)?;
+    }
+    // Like TcpListener::bind, so a restart does not wait out TIME_WAIT connections
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// IPV6_V6ONLY, so `::` and `0.0.0.0` can both be bound on one port instead of
+/// the IPv6 socket also claiming IPv4 where the OS defaults to dual-stack
+fn only_v6(socket: &Socket, addr: SocketAddr) -> io::Result<()> {
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    Ok(())
+}
+
+/// SO_BINDTODEVICE: only packets arriving on `device` (an interface, or a VRF
+/// to serve every interface enslaved to it) reach the socket
+#[cfg(target_os = "linux")]
+fn bind_to_device(socket: &Socket, device: &str) -> io::Result<()> {
+    socket
+        .bind_device(Some(device.as_bytes()))
+        .map_err(|e| io::Error::new(e.kind(), format!("binding to device {}: {}", device, e)))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_to_device(_socket: &Socket, _device: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "server.bind_device needs SO_BINDTODEVICE, which only Linux has",
+    ))
+}
+
+/// Bind extra TCP listeners (e.g. DNS-over-TLS) on `port` at every bind
+/// address, honoring `server.bind_device`
+pub async fn bind_tcp_listeners(server: &ServerConfig, port: u16) -> Result<Vec<TcpListener>, BindError> {
+    let mut listeners = Vec::new();
+    let mut skipped = Vec::new();
+    for &ip in &server.bind_address {
+        let addr = SocketAddr::new(ip, port);
+        match bind_tcp(addr, server.bind_device.as_deref()).await {
+            Ok(listener) => listeners.push(listener),
+            Err(error) if family_missing(&error) => skipped.push(*failure(addr, "TCP", error)),
+            Err(error) => {
+                return Err(BindError {
+                    failures: vec![*failure(addr, "TCP", error)],
+                })
+            }
+        }
+    }
+    if listeners.is_empty() {
+        return Err(BindError { failures: skipped });
+    }
+    for failure in skipped {
+        warn!("Not listening on {}: {}", failure.addr, failure.error);
+    }
+    Ok(listeners)
+}
+
+/// `addresses` on `port`, space separated, for display
+pub fn describe_addrs(addresses: &[IpAddr], port: u16) -> String {
+    let addrs: Vec<String> = addresses.iter().map(|&ip| SocketAddr::new(ip, port).to_string()).collect();
+    addrs.join(" ")
+}
+
+fn failure(addr: SocketAddr, protocol: &'static str, error: io::Error) -> Box<BindFailure> {
+    let holder = if error.kind() == io::ErrorKind::AddrInUse {
+        find_port_holder(protocol, addr.port())
+    } else {
+        None
+    };
+    Box::new(BindFailure { addr, protocol, error, holder })
+}
+
+/// Look up the process bound to `port` via /proc
+#[cfg(target_os = "linux")]
+pub fn find_port_holder(protocol: &str, port: u16) -> Option<PortHolder> {
+    let tables: &[&str] = if protocol == "TCP" {
+        &["/proc/net/tcp", "/proc/net/tcp6"]
+    } else {
+        &["/proc/net/udp", "/proc/net/udp6"]
+    };
+
+    let inodes: Vec<String> = tables
+        .iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .flat_map(|table| socket_inodes(&table, port, protocol == "TCP"))
+        .collect();
+    if inodes.is_empty() {
+        return None;
+    }
+
+    for entry in std::fs::read_dir("/proc").ok()?.flatten() {
+        let Some(pid) = entry.file_name().to_str().and_then(|s| s.parse::<u32>().ok()) else {
+            continue;
+        };
+        let Ok(fds) = std::fs::read_dir(entry.path().join("fd")) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            let Ok(target) = std::fs::read_link(fd.path()) else {
+                continue;
+            };
+            let target = target.to_string_lossy();
+            if inodes.iter().any(|inode| target == format!("socket:[{}]", inode)) {
+                let name = std::fs::read_to_string(entry.path().join("comm"))
+                    .map(|s| s.trim().to_string())
+                    .unwrap_or_else(|_| "unknown".to_string());
+                return Some(PortHolder { pid, name });
+            }
+        }
+    }
+    None
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn find_port_holder(_protocol: &str, _port: u16) -> Option<PortHolder> {
+    None
+}
+
+/// Socket inodes bound to `port` in a /proc/net/{tcp,udp}[6] table.
+/// For TCP only listening sockets (state 0A) count.
+#[cfg(target_os = "linux")]
+fn socket_inodes(table: &str, port: u16, listening_only: bool) -> Vec<String> {
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let local = fields.get(1)?;
+            let local_port = u16::from_str_radix(local.rsplit(':').next()?, 16).ok()?;
+            if local_port != port || (listening_only && fields.get(3) != Some(&"0A")) {
+                return None;
+            }
+            fields.get(9).map(|inode| inode.to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn server_config(port: u16, fallback_ports: Vec<u16>) -> ServerConfig {
+        ServerConfig {
+            bind_address: vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            port,
+            fallback_ports,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bind_uses_configured_port_when_free() {
+        let sockets = bind_dns_sockets(&server_config(0, Vec::new())).await.unwrap();
+        assert_ne!(sockets.addr.port(), 0);
+        assert_eq!(sockets.udp.len(), 1);
+        assert_eq!(sockets.tcp[0].local_addr().unwrap(), sockets.addr);
+    }
+
+    #[tokio::test]
+    async fn test_bind_every_address_on_one_port() {
+        let config = ServerConfig {
+            bind_address: vec![IpAddr::V4(Ipv4Addr::LOCALHOST), IpAddr::V6(std::net::Ipv6Addr::LOCALHOST)],
+            ..server_config(0, Vec::new())
+        };
+        let sockets = bind_dns_sockets(&config).await.unwrap();
+        assert!(sockets.addr.is_ipv4());
+        // Without IPv6 on the host, ::1 is skipped
+        assert_eq!(sockets.tcp.len(), sockets.addrs.len());
+        assert_eq!(sockets.udp.len(), sockets.addrs.len());
+        for (tcp, addr) in sockets.tcp.iter().zip(&sockets.addrs) {
+            assert_eq!(addr.port(), sockets.addr.port());
+            assert_eq!(tcp.local_addr().unwrap(), *addr);
+        }
+
+        // An address that is not the host's fails the bind when nothing else binds
+        let config = ServerConfig {
+            bind_address: vec!["192.0.2.1".parse().unwrap()],
+            ..server_config(0, Vec::new())
+        };
+        let err = bind_dns_sockets(&config).await.err().unwrap();
+        assert_eq!(err.failures[0].error.kind(), io::ErrorKind::AddrNotAvailable);
+    }
+
+    #[tokio::test]
+    async fn test_bind_both_wildcards_on_one_port() {
+        let config = ServerConfig {
+            bind_address: vec![IpAddr::V4(Ipv4Addr::UNSPECIFIED), IpAddr::V6(std::net::Ipv6Addr::UNSPECIFIED)],
+            ..server_config(0, Vec::new())
+        };
+        let sockets = bind_dns_sockets(&config).await.unwrap();
+        // :: only binds alongside 0.0.0.0 because it is IPv6-only
+        if std::net::UdpSocket::bind("[::1]:0").is_ok() {
+            assert_eq!(sockets.addrs.len(), 2);
+        }
+        for (tcp, udp) in sockets.tcp.iter().zip(&sockets.udp) {
+            assert_eq!(tcp.local_addr().unwrap().port(), sockets.addr.port());
+            assert_eq!(udp.local_addr().unwrap().port(), sockets.addr.port());
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_bind_several_udp_sockets() {
+        let config = ServerConfig {
+            udp_sockets: 3,
+            udp_recv_buffer_bytes: Some(256 * 1024),
+            ..server_config(0, Vec::new())
+        };
+        let sockets = bind_dns_sockets(&config).await.unwrap();
+        assert_eq!(sockets.udp.len(), 3);
+        for udp in &sockets.udp {
+            assert_eq!(udp.local_addr().unwrap(), sockets.addr);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_bind_to_device() {
+        let config = ServerConfig {
+            bind_device: Some("lo".to_string()),
+            ..server_config(0, Vec::new())
+        };
+        match bind_dns_sockets(&config).await {
+            Ok(sockets) => assert_eq!(sockets.tcp[0].local_addr().unwrap(), sockets.addr),
+            // SO_BINDTODEVICE needs CAP_NET_RAW before Linux 5.7
+            Err(err) => assert_eq!(err.failures[0].error.kind(), io::ErrorKind::PermissionDenied),
+        }
+
+        let config = ServerConfig {
+            bind_device: Some("no-such-if0".to_string()),
+            ..server_config(0, Vec::new())
+        };
+        let err = bind_dns_sockets(&config).await.err().unwrap();
+        assert!(err.to_string().contains("binding to device no-such-if0"));
+    }
+
+    #[tokio::test]
+    async fn test_bind_falls_back_when_port_busy() {
+        let busy = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let busy_port = busy.local_addr().unwrap().port();
+
+        let sockets = bind_dns_sockets(&server_config(busy_port, vec![0])).await.unwrap();
+        assert_ne!(sockets.addr.port(), busy_port);
+    }
+
+    #[tokio::test]
+    async fn test_bind_error_lists_every_attempt() {
+        let busy = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let busy_port = busy.local_addr().unwrap().port();
+
+        let err = match bind_dns_sockets(&server_config(busy_port, vec![busy_port])).await {
+            Ok(_) => panic!("bind on a busy port succeeded"),
+            Err(err) => err,
+        };
+        assert_eq!(err.failures.len(), 2);
+        assert_eq!(err.failures[0].error.kind(), io::ErrorKind::AddrInUse);
+
+        let message = err.to_string();
+        assert!(message.contains(&format!("127.0.0.1:{}", busy_port)));
+        assert!(message.contains("fallback_ports"));
+
+        // The socket is held by this test process
+        #[cfg(target_os = "linux")]
+        if let Some(holder) = &err.failures[0].holder {
+            assert_eq!(holder.pid, std::process::id());
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_socket_inodes_parses_proc_table() {
+        let table = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n   \
+             0: 0100007F:14D7 00000000:0000 0A 00000000:00000000 00:00000000 00000000   101        0 12345 1\n   \
+             1: 0100007F:14D7 0100007F:9C40 01 00000000:00000000 00:00000000 00000000   101        0 23456 1\n";
+        assert_eq!(socket_inodes(table, 5335, true), vec!["12345".to_string()]);
+        assert_eq!(socket_inodes(table, 5335, false).len(), 2);
+        assert!(socket_inodes(table, 53, false).is_empty());
+    }
+}
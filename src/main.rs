@@ -1,14 +1,47 @@
+#[cfg(all(unix, not(feature = "minimal")))]
+use mdns_dns_proxy::control::{self, ControlContext};
+#[cfg(not(feature = "minimal"))]
+use mdns_dns_proxy::audit::AuditLog;
+use mdns_dns_proxy::authoritative::AuthoritativeZones;
+use mdns_dns_proxy::banner;
+use mdns_dns_proxy::serving::Listeners;
+use mdns_dns_proxy::dns_handler::admin_records::RecordSuppressionConfig;
+use mdns_dns_proxy::admission::Admission;
+use mdns_dns_proxy::dns_handler::axfr::ZoneTransfers;
+use mdns_dns_proxy::dns_handler::record_acl::RecordAcls;
+use mdns_dns_proxy::netwatch::{self, NetworkState};
+use mdns_dns_proxy::own_addresses::OwnAddresses;
+use mdns_dns_proxy::mdns_resolver::{browses, daemon, known, liveness};
+#[cfg(not(feature = "minimal"))]
+use mdns_dns_proxy::mdns_resolver::{gossip, shared};
+#[cfg(not(feature = "minimal"))]
+use mdns_dns_proxy::peers::{self, PeerSet};
+use mdns_dns_proxy::policy::{self, PolicyStore};
+use mdns_dns_proxy::query_trace;
+use mdns_dns_proxy::push::PushService;
+use mdns_dns_proxy::quiet::{self, QuietSchedule};
+use mdns_dns_proxy::reload::{LogLevelSetter, Reloader};
+use mdns_dns_proxy::runtime::build_runtime;
+use mdns_dns_proxy::service_types::{self, ServiceTypes};
+use mdns_dns_proxy::slo::SloTracker;
+use mdns_dns_proxy::tsig::TsigKeyring;
+use mdns_dns_proxy::zones::ZoneRegistry;
+#[cfg(not(feature = "minimal"))]
+use mdns_dns_proxy::bench::{self, BenchConfig};
+#[cfg(not(feature = "minimal"))]
+use mdns_dns_proxy::conformance::{self, Target};
+#[cfg(not(feature = "minimal"))]
+use mdns_dns_proxy::doctor;
+use mdns_dns_proxy::config::ResolutionStep;
+#[cfg(not(feature = "minimal"))]
+use mdns_dns_proxy::Command;
 use mdns_dns_proxy::{Args, Config, MdnsDnsHandler, MdnsResolver};
 use clap::Parser;
-use hickory_server::ServerFuture;
-use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::net::{TcpListener, UdpSocket};
-use tracing::{error, info};
-use tracing_subscriber;
+use tracing::{error, info, warn};
+use tracing_subscriber::filter::LevelFilter;
 
-#[tokio::main]
-async fn main() {
+fn main() {
     // Parse command-line arguments
     let args = Args::parse();
     
@@ -18,7 +51,9 @@ async fn main() {
         return;
     }
     
-    // Load configuration
+    // Load configuration; the arguments are kept to load it again on SIGHUP
+    let command = args.command.clone();
+    let reload_args = args.clone();
     let config = match Config::load(args) {
         Ok(c) => c,
         Err(e) => {
@@ -27,18 +62,169 @@ async fn main() {
         }
     };
     
-    // Initialize tracing/logging with configured level
-    tracing_subscriber::fmt()
-        .with_max_level(config.parse_log_level())
-        .init();
+    // Initialize tracing/logging with configured level, changeable by a reload
+    use tracing_subscriber::prelude::*;
+    let set_log_level: LogLevelSetter = if config.debug.query_tracing {
+        // Queries asking for a trace log debug detail whatever the level
+        let (filter, handle) = tracing_subscriber::reload::Layer::new(query_trace::log_filter(config.parse_log_level()));
+        tracing_subscriber::registry()
+            .with(tracing_subscriber::fmt::layer().with_filter(filter))
+            .init();
+        Box::new(move |level| handle.reload(query_trace::log_filter(level)).map_err(|e| e.to_string()))
+    } else {
+        let (filter, handle) = tracing_subscriber::reload::Layer::new(LevelFilter::from_level(config.parse_log_level()));
+        tracing_subscriber::registry()
+            .with(tracing_subscriber::fmt::layer().with_filter(filter))
+            .init();
+        Box::new(move |level| handle.reload(LevelFilter::from_level(level)).map_err(|e| e.to_string()))
+    };
+
+    // Service type descriptions for logs, the inventory and doctor
+    match ServiceTypes::from_config(config.mdns.service_types_file.as_deref()) {
+        Ok(types) => {
+            if let Some(path) = &config.mdns.service_types_file {
+                info!("Loaded {} service type descriptions from {}", types.len(), path.display());
+            }
+            service_types::install(types);
+        }
+        Err(e) => {
+            error!("Failed to load service types: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // Build the runtime sized from [server] settings
+    let runtime = match build_runtime(&config.server) {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Failed to build async runtime: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    match command {
+        #[cfg(not(feature = "minimal"))]
+        Some(Command::Doctor { name, record_type, client }) => {
+            let config = Arc::new(config);
+            match runtime.block_on(doctor::run(config, &name, record_type, client)) {
+                Ok(0) => {}
+                Ok(_) => std::process::exit(1),
+                Err(e) => {
+                    eprintln!("doctor: {}", e);
+                    std::process::exit(2);
+                }
+            }
+        }
+        #[cfg(not(feature = "minimal"))]
+        Some(Command::Conformance { target, zone, timeout_ms }) => {
+            let zone = zone.unwrap_or_else(|| config.discovery_domain().to_string());
+            let zone = match hickory_proto::rr::Name::from_utf8(&zone) {
+                Ok(mut z) => {
+                    z.set_fqdn(true);
+                    z
+                }
+                Err(e) => {
+                    eprintln!("conformance: invalid zone {}: {}", zone, e);
+                    std::process::exit(2);
+                }
+            };
+            let target = Target {
+                addr: target.unwrap_or((config.server.local_address(), config.server.port).into()),
+                zone,
+                timeout: std::time::Duration::from_millis(timeout_ms),
+            };
+            if runtime.block_on(conformance::run(&target)) > 0 {
+                std::process::exit(1);
+            }
+        }
+        #[cfg(not(feature = "minimal"))]
+        Some(Command::Bench { target, zone, qps, names, duration_secs, miss_percent, timeout_ms }) => {
+            let zone = zone.unwrap_or_else(|| config.discovery_domain().to_string());
+            let zone = match hickory_proto::rr::Name::from_utf8(&zone) {
+                Ok(mut z) => {
+                    z.set_fqdn(true);
+                    z
+                }
+                Err(e) => {
+                    eprintln!("bench: invalid zone {}: {}", zone, e);
+                    std::process::exit(2);
+                }
+            };
+            let names = match names {
+                Some(path) => match std::fs::read_to_string(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|text| bench::parse_names(&text, &zone))
+                {
+                    Ok(names) => names,
+                    Err(e) => {
+                        eprintln!("bench: {}: {}", path.display(), e);
+                        std::process::exit(2);
+                    }
+                },
+                None => bench::default_names(&zone),
+            };
+            let bench_config = BenchConfig {
+                target: target.unwrap_or((config.server.local_address(), config.server.port).into()),
+                zone,
+                qps,
+                duration: std::time::Duration::from_secs(duration_secs),
+                miss_percent,
+                timeout: std::time::Duration::from_millis(timeout_ms),
+                names,
+            };
+            match runtime.block_on(bench::run(&bench_config)) {
+                Ok(mut report) => print!("{}", bench::render(&bench_config, &mut report)),
+                Err(e) => {
+                    eprintln!("bench: {}", e);
+                    std::process::exit(2);
+                }
+            }
+        }
+        None => {
+            let code = runtime.block_on(run(config, reload_args, set_log_level));
+            // Lookups abandoned at the drain deadline must not hold up the exit
+            runtime.shutdown_timeout(std::time::Duration::from_secs(1));
+            std::process::exit(code);
+        }
+    }
+}
+
+/// Settings that need a subsystem the minimal build leaves out
+#[cfg(feature = "minimal")]
+fn compiled_out(config: &Config) -> Vec<&'static str> {
+    let mut missing = Vec::new();
+    if config.admin.control_socket.is_some() {
+        missing.push("admin.control_socket");
+    }
+    if config.audit.enabled {
+        missing.push("[audit]");
+    }
+    if config.cluster.enabled {
+        missing.push("[cluster]");
+    }
+    if config.peers.discover || config.peers.forward_on_failure || config.resolution.mentions(ResolutionStep::Peers) {
+        missing.push("[peers]");
+    }
+    missing
+}
 
+/// Serve until a listener fails or a shutdown signal arrives; returns the exit status
+async fn run(config: Config, args: Args, set_log_level: LogLevelSetter) -> i32 {
         info!("Starting mDNS-DNS Discovery Proxy (RFC 8766)");
-        info!("Configuration: bind={}:{}, cache_ttl={}s, cache_enabled={}, discovery_domain={}", 
-            config.server.bind_address, 
-            config.server.port, 
+        info!("Configuration: bind={}, cache_ttl={}s, cache_enabled={}, discovery_domain={}", 
+            mdns_dns_proxy::listener::describe_addrs(&config.server.bind_address, config.server.port),
             config.cache.ttl_seconds,
             config.cache.enabled,
             config.discovery_domain());
+    banner::log(&config);
+    #[cfg(feature = "minimal")]
+    {
+        let missing = compiled_out(&config);
+        if !missing.is_empty() {
+            error!("Not available in the minimal build: {}", missing.join(", "));
+            return 1;
+        }
+    }
 
     // Wrap config in Arc for sharing
     let config = Arc::new(config);
@@ -48,67 +234,374 @@ async fn main() {
         Ok(r) => Arc::new(r),
         Err(e) => {
             error!("Failed to create mDNS resolver: {}", e);
-            return;
+            return 1;
         }
     };
     info!("mDNS resolver initialized");
 
-    // Create DNS handler
-    let handler = MdnsDnsHandler::new(resolver, config.discovery_domain().to_string());
+    // Served discovery domains; the control socket can change them at runtime
+    let zones = match ZoneRegistry::new(&[config.discovery_domain()]) {
+        Ok(z) => Arc::new(z),
+        Err(e) => {
+            error!("Invalid discovery domain {}: {}", config.discovery_domain(), e);
+            return 1;
+        }
+    };
 
-    // Configure server address from config
-    let listen_addr = SocketAddr::new(config.server.bind_address, config.server.port);
-    
-    info!("Binding DNS server to {}", listen_addr);
+    // Keep configured service instances in line with what answers on the link
+    if !config.known_services.services.is_empty() {
+        info!("Serving {} known service instance(s)", config.known_services.services.len());
+        let interval = std::time::Duration::from_secs(config.known_services.verify_interval_secs.max(1));
+        tokio::spawn(known::run(resolver.clone(), interval));
+    }
+
+    // Bound how long the daemon keeps a browse running before it is started afresh
+    if config.mdns.browse_max_age_secs > 0 {
+        let interval = std::time::Duration::from_secs((config.mdns.browse_max_age_secs / 4).max(1));
+        tokio::spawn(browses::run(resolver.clone(), interval));
+    }
+
+    // A lazily started daemon that failed to come up is retried until it does
+    if config.mdns.lazy_start {
+        info!("mDNS daemon starts with the first query that needs it");
+        let interval = std::time::Duration::from_secs(config.mdns.start_retry_secs.max(1));
+        tokio::spawn(daemon::run(resolver.clone(), interval));
+    }
+
+    // Probe cached instances so stale announcements can be spotted and filtered
+    if config.liveness.enabled {
+        info!("Probing cached service instances every {}s", config.liveness.interval_secs.max(1));
+        tokio::spawn(liveness::run(resolver.clone(), config.liveness.clone()));
+    }
+
+    // Keep the cache warm across the cluster so fail-over lands on a warm instance
+    #[cfg(not(feature = "minimal"))]
+    if config.cluster.enabled {
+        if !config.cache.enabled {
+            warn!("[cluster] is enabled but the cache is disabled; nothing will be shared");
+        }
+        match gossip::GossipCache::bind(&config.cluster).await {
+            Ok(gossip) => {
+                resolver.attach_shared_cache(Arc::new(gossip));
+                tokio::spawn(shared::run(resolver.clone()));
+            }
+            Err(e) => {
+                error!("Failed to start cluster gossip on {}: {}", config.cluster.listen, e);
+                return 1;
+            }
+        }
+    }
 
-    // Create UDP socket for DNS
-    let udp_socket = match UdpSocket::bind(&listen_addr).await {
-        Ok(s) => s,
+    // Pause background multicast during the configured quiet hours
+    match QuietSchedule::from_config(&config.quiet_hours) {
+        Ok(schedule) if !schedule.is_empty() => {
+            info!("Quiet hours: {}", config.quiet_hours.windows.join(", "));
+            tokio::spawn(quiet::run(schedule, resolver.clone()));
+        }
+        Ok(_) => {}
         Err(e) => {
-            error!("Failed to bind UDP socket: {}", e);
-            return;
+            error!("Invalid quiet_hours configuration: {}", e);
+            return 1;
         }
+    }
+
+    // Response policy, shared by the handler and the control socket
+    let policy_store = match &config.policy.rpz_file {
+        Some(path) => match PolicyStore::open(path) {
+            Ok(store) => {
+                let store = Arc::new(store);
+                info!("Response policy loaded from {} ({} triggers)", path.display(), store.current().len());
+                tokio::spawn(policy::run(store.clone(), config.policy.clone()));
+                Some(store)
+            }
+            Err(e) => {
+                error!("Failed to load response policy: {}", e);
+                return 1;
+            }
+        },
+        None => None,
     };
-    info!("UDP socket bound to {}", listen_addr);
 
-    // Create TCP listener for DNS
-    let tcp_listener = match TcpListener::bind(&listen_addr).await {
-        Ok(l) => l,
+    // Latency objectives, timed by the handler and reported by the control socket
+    let slo = SloTracker::from_config(&config.slo).map(Arc::new);
+    if let Some(slo) = &slo {
+        if let Some(url) = &config.slo.webhook_url
+            && let Err(e) = mdns_dns_proxy::slo::parse_http_url(url)
+        {
+            error!("Invalid [slo] webhook_url: {}", e);
+            return 1;
+        }
+        info!(
+            "Tracking latency objectives: {}% of cached answers within {}ms, {}% of uncached within {}ms",
+            config.slo.cached_percent, config.slo.cached_target_ms, config.slo.uncached_percent, config.slo.uncached_target_ms
+        );
+        tokio::spawn(mdns_dns_proxy::slo::run(slo.clone(), config.slo.clone()));
+    }
+
+    #[cfg(all(unix, not(feature = "minimal")))]
+    if let Some(path) = config.admin.control_socket.clone() {
+        let ctx = Arc::new(ControlContext {
+            zones: zones.clone(),
+            resolver: resolver.clone(),
+            policy: policy_store.clone(),
+            api_keys: config.admin.api_keys.clone(),
+            slo: slo.clone(),
+        });
+        let mode = config.admin.control_socket_mode;
+        tokio::spawn(async move {
+            if let Err(e) = control::serve(&path, mode, ctx).await {
+                error!("Control socket {} failed: {}", path.display(), e);
+            }
+        });
+    }
+
+    // Create DNS handler
+    #[cfg(not(feature = "minimal"))]
+    let peer_set = Arc::new(PeerSet::new(&config.peers));
+    let mut handler = MdnsDnsHandler::with_zones(resolver.clone(), zones.clone());
+    #[cfg(not(feature = "minimal"))]
+    if config.audit.enabled {
+        match AuditLog::start(&config.audit) {
+            Ok(audit) => {
+                info!("Query audit log enabled");
+                handler = handler.with_audit(audit);
+            }
+            Err(e) => {
+                error!("Failed to start audit log: {}", e);
+                return 1;
+            }
+        }
+    }
+    if let Some(store) = &policy_store {
+        handler = handler.with_policy(store.clone());
+    }
+    if !config.authoritative.zones.is_empty() {
+        match AuthoritativeZones::load(&config.authoritative.zones) {
+            Ok(authoritative) => handler = handler.with_authoritative(Arc::new(authoritative)),
+            Err(e) => {
+                error!("Failed to load authoritative zones: {}", e);
+                return 1;
+            }
+        }
+    }
+    if !config.tsig.keys.is_empty() || !config.tsig.require_from.is_empty() {
+        match TsigKeyring::from_config(&config.tsig) {
+            Ok(keyring) => handler = handler.with_tsig(Arc::new(keyring)),
+            Err(e) => {
+                error!("Invalid [tsig] configuration: {}", e);
+                return 1;
+            }
+        }
+    }
+    if config.axfr.enabled {
+        match ZoneTransfers::from_config(&config.axfr) {
+            Ok(transfers) => handler = handler.with_axfr(Arc::new(transfers)),
+            Err(e) => {
+                error!("Invalid [axfr] configuration: {}", e);
+                return 1;
+            }
+        }
+    }
+    if let Some(admission) = Admission::from_config(&config.server) {
+        info!(
+            "Answering at most {} queries at once, queueing up to {}",
+            config.server.max_concurrent_queries, config.server.query_queue_length
+        );
+        handler = handler.with_admission(Arc::new(admission), config.server.overload_response);
+    }
+    if let Some(slo) = &slo {
+        handler = handler.with_slo(slo.clone());
+    }
+    if !config.record_acls.is_empty() {
+        match RecordAcls::from_config(&config.record_acls) {
+            Ok(acls) => handler = handler.with_record_acls(Arc::new(acls)),
+            Err(e) => {
+                error!("Invalid [[record_acls]] configuration: {}", e);
+                return 1;
+            }
+        }
+    }
+    #[cfg(not(feature = "minimal"))]
+    if config.peers.forward_on_failure || config.resolution.mentions(ResolutionStep::Peers) {
+        info!("Forwarding queries to peer proxies when earlier resolution steps fail");
+        handler = handler.with_peers(peer_set.clone());
+    }
+    let own_addresses = config.server.own_address_records.then(|| {
+        let own = Arc::new(OwnAddresses::new(config.server.bind_address.clone()));
+        info!("Serving own addresses {:?} for discovery-proxy.<zone>", own.addresses());
+        own
+    });
+    if let Some(own) = &own_addresses {
+        handler = handler.with_own_addresses(own.clone());
+    }
+
+    // Interface networks decide which clients are on-link; the watcher keeps them current
+    let network_state = Arc::new(NetworkState::new());
+    handler = handler.with_suppression(RecordSuppressionConfig {
+        local_networks: Some(network_state.clone()),
+        ..Default::default()
+    });
+    if config.network.watch {
+        tokio::spawn(netwatch::run(network_state, own_addresses, resolver.clone(), config.network.clone()));
+    }
+
+    if let Err(e) = mdns_dns_proxy::links::check(&config) {
+        error!("Invalid [[links]] configuration: {}", e);
+        return 1;
+    }
+    if let Err(e) = resolver.capabilities().check(&config) {
+        error!("Unsupported configuration: {}", e);
+        return 1;
+    }
+
+    // DNS Push sessions ride on the TCP, TLS and Unix socket connections
+    let push = config
+        .push
+        .enabled
+        .then(|| Arc::new(PushService::new(handler.engine().clone(), config.push.clone())));
+
+    // Bind UDP, TCP and DNS-over-TLS, falling back to alternate ports if configured
+    info!(
+        "Binding DNS server to {}",
+        mdns_dns_proxy::listener::describe_addrs(&config.server.bind_address, config.server.port)
+    );
+    let listeners = match Listeners::start(&config.server, handler.clone(), push.clone()).await {
+        Ok(listeners) => Arc::new(listeners),
         Err(e) => {
-            error!("Failed to bind TCP listener: {}", e);
-            return;
+            error!("{}", e);
+            return 1;
         }
     };
-    info!("TCP listener bound to {}", listen_addr);
+    let bound = listeners.addrs().await;
+    let listen_addr = bound[0];
+    let listen_addrs: Vec<String> = bound.iter().map(|addr| addr.to_string()).collect();
 
-    // Create server future
-    let mut server = ServerFuture::new(handler);
+    // SIGHUP loads the configuration again and rebinds the listeners if their settings changed
+    #[cfg(unix)]
+    tokio::spawn(mdns_dns_proxy::reload::run(
+        Reloader::new(args, resolver.clone(), zones, set_log_level).with_listeners(listeners.clone()),
+    ));
+    #[cfg(not(unix))]
+    let _ = (args, zones, set_log_level);
 
-    // Register UDP socket
-    server.register_socket(udp_socket);
-    info!("Registered UDP socket");
+    // Peers are browsed for after binding so this proxy's own address can be left out
+    #[cfg(not(feature = "minimal"))]
+    if config.peers.discover {
+        let interval = std::time::Duration::from_secs(config.peers.discover_interval_secs.max(1));
+        tokio::spawn(peers::run_discovery(peer_set, resolver.clone(), bound, interval));
+    }
 
-    // Register TCP listener with configured timeout
-    server.register_listener(
-        tcp_listener, 
-        std::time::Duration::from_secs(config.server.tcp_timeout)
-    );
-    info!("Registered TCP listener");
+    // The Unix socket shares the handler with the listeners
+    let shared_handler = Arc::new(handler.clone());
+    let tcp_limits = mdns_dns_proxy::tcp::TcpLimits::from_config(&config.server);
+    // Winds down the Unix socket and link TCP connections at shutdown
+    let drain = mdns_dns_proxy::tcp::Drain::new();
+    #[cfg(unix)]
+    if let Some(path) = &config.server.unix_socket_path {
+        match mdns_dns_proxy::tcp::bind_unix(path) {
+            Ok(listener) => {
+                info!("Registered Unix socket listener on {}", path.display());
+                let handler = shared_handler.clone();
+                let path = path.clone();
+                let push = push.clone();
+                let drain = drain.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = mdns_dns_proxy::tcp::serve_unix(listener, handler, tcp_limits, push, drain).await {
+                        error!("Unix socket listener {} failed: {}", path.display(), e);
+                    }
+                });
+            }
+            Err(e) => {
+                error!("Failed to bind Unix socket {}: {}", path.display(), e);
+                return 1;
+            }
+        }
+    }
+    if !config.notify.targets.is_empty() {
+        info!("Notifying {:?} when a discovery zone changes", config.notify.targets);
+        tokio::spawn(mdns_dns_proxy::notify::run(shared_handler.engine().clone(), config.notify.clone()));
+    }
+
+    // A proxy of its own for each link, sharing the handler's policy, audit log and keys
+    let mut links = Vec::new();
+    for link in &config.links {
+        match mdns_dns_proxy::links::start(&config, link, &handler, drain.clone()).await {
+            Ok(server) => links.push(server),
+            Err(e) => {
+                error!("Failed to serve link {}: {}", link.interface, e);
+                return 1;
+            }
+        }
+    }
 
     info!("mDNS-DNS proxy server is running!");
-        info!("Serving discovery domain {} via DNS at {}", config.discovery_domain(), listen_addr);
+        info!("Serving discovery domain {} via DNS at {}", config.discovery_domain(), listen_addrs.join(", "));
         info!("Example: dig @{} -p {} hostname{}", 
-            config.server.bind_address, 
-            config.server.port,
+            listen_addr.ip(),
+            listen_addr.port(),
             config.discovery_domain());
 
-    // Run the server
-    match server.block_until_done().await {
-        Ok(_) => {
-            info!("DNS server shutdown gracefully");
-        }
-        Err(e) => {
-            error!("DNS server error: {}", e);
+    // Run until a listener fails or a shutdown signal arrives
+    let mut status = tokio::select! {
+        result = listeners.stopped() => match result {
+            Ok(()) => {
+                info!("DNS server shutdown gracefully");
+                0
+            }
+            Err(e) => {
+                error!("{}", e);
+                1
+            }
+        },
+        _ = shutdown_signal() => 0,
+    };
+
+    // Stop accepting queries and let those under way finish, up to the deadline
+    let deadline = std::time::Duration::from_secs(config.server.drain_timeout_secs);
+    info!(
+        "Shutting down: draining {} TCP connection(s) and {} pending lookup(s) for up to {:?}",
+        listeners.connections().await + drain.connections(),
+        resolver.pending().len(),
+        deadline
+    );
+    let (left, local_left, lookups) = tokio::join!(
+        listeners.shutdown(deadline),
+        drain.drain(deadline),
+        resolver.pending().wait_idle(deadline)
+    );
+    if left + local_left > 0 {
+        warn!("Closing {} TCP connection(s) that were still busy", left + local_left);
+    }
+    if lookups > 0 {
+        warn!("Abandoning {} lookup(s) still waiting on mDNS", lookups);
+    }
+    futures_util::future::join_all(links.into_iter().map(|link| link.shutdown(deadline))).await;
+    if let Err(e) = resolver.shutdown(std::time::Duration::from_secs(2)).await {
+        error!("{}", e);
+        status = 1;
+    }
+    info!("Exiting with status {}", status);
+    status
+}
+
+/// Resolves on SIGTERM or SIGINT
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = terminate.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+                return;
+            }
+            Err(e) => warn!("Cannot listen for SIGTERM: {}", e),
         }
     }
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        warn!("Cannot listen for SIGINT: {}", e);
+        std::future::pending::<()>().await;
+    }
 }
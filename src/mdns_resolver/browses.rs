@@ -0,0 +1,299 @@
+//! Age limit for the daemon's browses
+//!
+//! mdns-sd keeps browsing a service type, refreshing what it cached for it,
+//! from the first `browse` call until `stop_browse`. Every query path here
+//! browses, none stops, so a browse started once lives as long as the process
+//! and goes stale unnoticed if the daemon missed traffic for it. Browses are
+//! tracked from when they started; one older than `mdns.browse_max_age_secs`
+//! is torn down, either when the next query for its type re-establishes it or
+//! by [`run`] if no query asks for the type again.
+//!
+//! The daemon sends a type's events to the receiver of its latest `browse`
+//! call only, so two queries for one type, or a query and a DNS Push
+//! subscription, would take events from each other. Each daemon receiver is
+//! drained by a task that hands every event to all receivers returned for the
+//! type instead. Its resolved events are counted toward the health of the
+//! links their addresses are on.
+
+use crate::metrics;
+use mdns_sd::{Receiver, ServiceDaemon, ServiceEvent};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::debug;
+
+/// Events of one service type's browses, from the browse call on
+pub type BrowseReceiver = mpsc::UnboundedReceiver<ServiceEvent>;
+
+/// Receivers of each service type's events, keyed by lowercase type
+type Subscribers = Arc<Mutex<HashMap<String, Vec<mpsc::UnboundedSender<ServiceEvent>>>>>;
+
+#[cfg(any(test, feature = "fault-injection"))]
+use super::faults::Faults;
+use super::health::LinkHealth;
+use super::MdnsResolver;
+
+/// Periodically tear down browses past the age limit
+pub async fn run(resolver: Arc<MdnsResolver>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        resolver.expire_browses();
+    }
+}
+
+/// Browses started on the daemon, by service type
+#[derive(Debug)]
+pub struct Browses {
+    /// Zero keeps browses forever
+    max_age: Duration,
+    started: Mutex<HashMap<String, Instant>>,
+    subscribers: Subscribers,
+    health: Arc<LinkHealth>,
+    #[cfg(any(test, feature = "fault-injection"))]
+    faults: Option<Arc<Faults>>,
+}
+
+impl Browses {
+    pub fn new(max_age: Duration) -> Self {
+        Self {
+            max_age,
+            started: Mutex::new(HashMap::new()),
+            subscribers: Arc::new(Mutex::new(HashMap::new())),
+            health: Arc::new(LinkHealth::default()),
+            #[cfg(any(test, feature = "fault-injection"))]
+            faults: None,
+        }
+    }
+
+    /// Record resolved events in `health`
+    pub fn with_health(mut self, health: Arc<LinkHealth>) -> Self {
+        self.health = health;
+        self
+    }
+
+    /// Inject `faults` into the browses started from now on
+    #[cfg(any(test, feature = "fault-injection"))]
+    pub fn set_faults(&mut self, faults: Arc<Faults>) {
+        self.faults = Some(faults);
+    }
+
+    /// Browse `ty_domain`, restarting the daemon's browse for it first if it
+    /// has outlived the age limit. The receiver sees the events of every later
+    /// browse of the type as well, until it is dropped.
+    pub fn browse(&self, daemon: &ServiceDaemon, ty_domain: &str) -> mdns_sd::Result<BrowseReceiver> {
+        // Subscribed first so the daemon's replay of what it knows is not missed
+        let receiver = self.subscribe(ty_domain);
+        self.start(daemon, ty_domain)?;
+        Ok(receiver)
+    }
+
+    /// Start the daemon's browse for `ty_domain` unless one is running, so the
+    /// receivers of the type keep getting events after the browse was expired
+    pub fn resume(&self, daemon: &ServiceDaemon, ty_domain: &str) -> mdns_sd::Result<()> {
+        if self.started.lock().unwrap().contains_key(ty_domain) {
+            return Ok(());
+        }
+        self.start(daemon, ty_domain)
+    }
+
+    fn start(&self, daemon: &ServiceDaemon, ty_domain: &str) -> mdns_sd::Result<()> {
+        let now = Instant::now();
+        if self.take_if_stale(ty_domain, now) {
+            metrics::inc(&metrics::metrics().browse_refreshes);
+            debug!("Restarting browse for {}, older than {:?}", ty_domain, self.max_age);
+            stop(daemon, ty_domain);
+        }
+        #[cfg(any(test, feature = "fault-injection"))]
+        if let Some(faults) = &self.faults
+            && faults.browse_fails()
+        {
+            return Err(mdns_sd::Error::Msg(format!("injected fault: browse for {} failed", ty_domain)));
+        }
+        #[cfg(any(test, feature = "fault-injection"))]
+        if let Some(faults) = &self.faults
+            && faults.browse_panics()
+        {
+            panic!("injected fault: browse for {} panicked", ty_domain);
+        }
+        let events = daemon.browse(ty_domain)?;
+        self.started.lock().unwrap().entry(ty_domain.to_string()).or_insert(now);
+        let key = ty_domain.to_lowercase();
+        #[cfg(any(test, feature = "fault-injection"))]
+        if let Some(faults) = &self.faults {
+            tokio::spawn(forward_with_faults(self.subscribers.clone(), self.health.clone(), key, events, faults.clone()));
+            return Ok(());
+        }
+        tokio::spawn(forward(self.subscribers.clone(), self.health.clone(), key, events));
+        Ok(())
+    }
+
+    fn subscribe(&self, ty_domain: &str) -> BrowseReceiver {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.subscribers.lock().unwrap().entry(ty_domain.to_lowercase()).or_default().push(sender);
+        receiver
+    }
+
+    /// Stop every browse past the age limit; the next query for its type
+    /// starts a new one. Returns how many were stopped.
+    pub fn expire(&self, daemon: &ServiceDaemon) -> usize {
+        let stale = self.take_stale(Instant::now());
+        for ty_domain in &stale {
+            metrics::inc(&metrics::metrics().browses_expired);
+            debug!("Stopping browse for {}, older than {:?}", ty_domain, self.max_age);
+            stop(daemon, ty_domain);
+        }
+        stale.len()
+    }
+
+    /// Service types being browsed
+    pub fn len(&self) -> usize {
+        self.started.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn is_stale(&self, started: Instant, now: Instant) -> bool {
+        !self.max_age.is_zero() && now.duration_since(started) >= self.max_age
+    }
+
+    fn take_if_stale(&self, ty_domain: &str, now: Instant) -> bool {
+        let mut started = self.started.lock().unwrap();
+        match started.get(ty_domain) {
+            Some(&at) if self.is_stale(at, now) => {
+                started.remove(ty_domain);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn take_stale(&self, now: Instant) -> Vec<String> {
+        let mut started = self.started.lock().unwrap();
+        let stale: Vec<String> = started
+            .iter()
+            .filter(|(_, at)| self.is_stale(**at, now))
+            .map(|(ty_domain, _)| ty_domain.clone())
+            .collect();
+        for ty_domain in &stale {
+            started.remove(ty_domain);
+        }
+        stale
+    }
+}
+
+/// Hand the events of one daemon receiver to the type's receivers until the
+/// daemon drops it (the browse was stopped or replaced by a later one)
+async fn forward(subscribers: Subscribers, health: Arc<LinkHealth>, key: String, events: Receiver<ServiceEvent>) {
+    while let Ok(event) = events.recv_async().await {
+        if let ServiceEvent::ServiceResolved(info) = &event {
+            health.event(info.get_addresses());
+        }
+        deliver(&subscribers, &key, event);
+    }
+}
+
+/// [`forward`], dropping, repeating and holding back events as `faults` draw
+#[cfg(any(test, feature = "fault-injection"))]
+async fn forward_with_faults(
+    subscribers: Subscribers,
+    health: Arc<LinkHealth>,
+    key: String,
+    events: Receiver<ServiceEvent>,
+    faults: Arc<Faults>,
+) {
+    while let Ok(event) = events.recv_async().await {
+        for delay in faults.deliveries() {
+            if let ServiceEvent::ServiceResolved(info) = &event {
+                health.event(info.get_addresses());
+            }
+            if delay.is_zero() {
+                deliver(&subscribers, &key, event.clone());
+                continue;
+            }
+            let (subscribers, key, event) = (subscribers.clone(), key.clone(), event.clone());
+            tokio::spawn(async move {
+                tokio::time::sleep(delay).await;
+                deliver(&subscribers, &key, event);
+            });
+        }
+    }
+}
+
+/// Send `event` to the receivers of `key`, forgetting those dropped
+fn deliver(subscribers: &Subscribers, key: &str, event: ServiceEvent) {
+    let mut subscribers = subscribers.lock().unwrap();
+    let Some(senders) = subscribers.get_mut(key) else {
+        return;
+    };
+    senders.retain(|sender| sender.send(event.clone()).is_ok());
+    if senders.is_empty() {
+        subscribers.remove(key);
+    }
+}
+
+fn stop(daemon: &ServiceDaemon, ty_domain: &str) {
+    if let Err(e) = daemon.stop_browse(ty_domain) {
+        debug!("Failed to stop browse for {}: {}", ty_domain, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn started(browses: &Browses, ty_domain: &str, at: Instant) {
+        browses.started.lock().unwrap().insert(ty_domain.to_string(), at);
+    }
+
+    #[test]
+    fn test_browses_older_than_max_age_are_taken() {
+        let browses = Browses::new(Duration::from_secs(3600));
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        started(&browses, "_ipp._tcp.local.", at(0));
+        started(&browses, "_http._tcp.local.", at(1800));
+
+        assert!(!browses.take_if_stale("_ipp._tcp.local.", at(3599)));
+        assert!(browses.take_stale(at(3599)).is_empty());
+        assert_eq!(browses.take_stale(at(3600)), vec!["_ipp._tcp.local.".to_string()]);
+        assert_eq!(browses.len(), 1);
+        assert!(browses.take_if_stale("_http._tcp.local.", at(5400)));
+        assert!(browses.is_empty());
+    }
+
+    #[test]
+    fn test_events_reach_every_receiver_of_the_type() {
+        let browses = Browses::new(Duration::ZERO);
+        let mut first = browses.subscribe("_ipp._tcp.local.");
+        let second = browses.subscribe("_IPP._tcp.local.");
+        let mut other = browses.subscribe("_http._tcp.local.");
+
+        let started = ServiceEvent::SearchStarted("_ipp._tcp.local.".to_string());
+        deliver(&browses.subscribers, "_ipp._tcp.local.", started);
+        assert!(matches!(first.try_recv(), Ok(ServiceEvent::SearchStarted(_))));
+        assert!(other.try_recv().is_err());
+
+        // Dropped receivers are forgotten with the next event
+        drop(second);
+        let stopped = ServiceEvent::SearchStopped("_ipp._tcp.local.".to_string());
+        deliver(&browses.subscribers, "_ipp._tcp.local.", stopped);
+        assert!(matches!(first.try_recv(), Ok(ServiceEvent::SearchStopped(_))));
+        assert_eq!(browses.subscribers.lock().unwrap()["_ipp._tcp.local."].len(), 1);
+        drop(first);
+        deliver(&browses.subscribers, "_ipp._tcp.local.", ServiceEvent::SearchStarted(String::new()));
+        assert!(!browses.subscribers.lock().unwrap().contains_key("_ipp._tcp.local."));
+    }
+
+    #[test]
+    fn test_zero_max_age_keeps_browses() {
+        let browses = Browses::new(Duration::ZERO);
+        let start = Instant::now();
+        started(&browses, "_ipp._tcp.local.", start);
+        assert!(browses.take_stale(start + Duration::from_secs(86400 * 365)).is_empty());
+        assert!(!browses.take_if_stale("_ipp._tcp.local.", start + Duration::from_secs(86400)));
+    }
+}
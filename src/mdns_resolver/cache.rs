@@ -1,65 +1,535 @@
-use hickory_proto::rr::{Record, RecordType};
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use crate::metrics;
+use hickory_proto::rr::rdata::TXT;
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use hickory_proto::serialize::binary::BinEncodable;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
 use std::time::Duration;
+use tracing::warn;
+
+use super::names;
+use super::resolver::sort_canonical;
+use super::shared::{SharedCache, SharedEntry};
+
+/// Fraction of the soft limit the cache is trimmed down to once it is exceeded,
+/// so eviction does not run again on every following insert
+const EVICTION_TARGET_PERCENT: usize = 75;
 
 /// Cache entry for mDNS query results
 #[derive(Clone, Debug)]
 pub struct CacheEntry {
-    pub records: Vec<Record>,
+    pub records: Vec<CachedRecord>,
     pub timestamp: std::time::Instant,
 }
 
+impl CacheEntry {
+    /// The records as served now: each TTL is reduced by the entry's age and
+    /// limited to what is left of its `ttl` in this cache, so clients do not
+    /// hold data for longer than it stays fresh here. Zero TTLs stay zero;
+    /// others do not drop below one second.
+    pub fn decayed_records(&self, ttl: Duration) -> Vec<Record> {
+        let age = self.timestamp.elapsed().as_secs();
+        let remaining = ttl.as_secs().saturating_sub(age);
+        let remaining = u32::try_from(remaining).unwrap_or(u32::MAX);
+        let age = u32::try_from(age).unwrap_or(u32::MAX);
+        self.records
+            .iter()
+            .map(|cached| {
+                let mut record = cached.to_record();
+                if record.ttl() > 0 {
+                    record.set_ttl(record.ttl().saturating_sub(age).min(remaining).max(1));
+                }
+                record
+            })
+            .collect()
+    }
+}
+
+/// A cached record. TXT data is interned: devices of one model tend to
+/// advertise the same large TXT blob, which is then held once for all of them.
+#[derive(Clone, Debug)]
+pub enum CachedRecord {
+    Plain(Record),
+    /// The record with its TXT data taken out, and the shared data
+    Txt(Record, Arc<TXT>),
+}
+
+impl CachedRecord {
+    pub fn to_record(&self) -> Record {
+        match self {
+            CachedRecord::Plain(record) => record.clone(),
+            CachedRecord::Txt(shell, txt) => {
+                let mut record = shell.clone();
+                record.set_data(RData::TXT(TXT::clone(txt)));
+                record
+            }
+        }
+    }
+}
+
+impl From<Record> for CachedRecord {
+    fn from(record: Record) -> Self {
+        CachedRecord::Plain(record)
+    }
+}
+
+/// Wire length of TXT data, as charged against the memory limit
+fn txt_size(txt: &TXT) -> usize {
+    txt.txt_data().iter().map(|s| s.len() + 1).sum()
+}
+
+#[derive(Default)]
+struct CacheData {
+    entries: HashMap<String, CacheEntry>,
+    /// Sum of `entry_size` over all entries and `negative_size` over all
+    /// negative ones, plus each interned TXT blob once
+    bytes: usize,
+    /// Keys mDNS had no answer for, with when that stops being believed
+    negative: HashMap<String, std::time::Instant>,
+    /// Distinct TXT data held by the entries
+    txt: HashSet<Arc<TXT>>,
+    /// Bytes not held thanks to interning: each extra reference to a blob
+    txt_saved: usize,
+    /// This cache's share of the process-wide gauges as last published:
+    /// bytes, interned blobs and bytes saved
+    published: (usize, usize, usize),
+}
+
+impl CacheData {
+    /// Convert records for storage, sharing TXT data already held
+    fn intern(&mut self, records: Vec<Record>) -> Vec<CachedRecord> {
+        records
+            .into_iter()
+            .map(|mut record| {
+                let RData::TXT(txt) = record.data() else {
+                    return CachedRecord::Plain(record);
+                };
+                let shared = match self.txt.get(txt) {
+                    Some(shared) => {
+                        self.txt_saved += txt_size(shared);
+                        shared.clone()
+                    }
+                    None => {
+                        let shared = Arc::new(txt.clone());
+                        self.bytes += txt_size(&shared);
+                        self.txt.insert(shared.clone());
+                        shared
+                    }
+                };
+                record.set_data(RData::TXT(TXT::new(Vec::new())));
+                CachedRecord::Txt(record, shared)
+            })
+            .collect()
+    }
+
+    /// Give up an entry's share of its TXT blobs, dropping those no longer used
+    fn release(&mut self, key: &str, entry: CacheEntry) {
+        self.bytes -= entry_size(key, &entry.records);
+        for record in entry.records {
+            if let CachedRecord::Txt(_, txt) = record {
+                // The set and this record are the only holders left
+                if Arc::strong_count(&txt) <= 2 {
+                    self.bytes -= txt_size(&txt);
+                    self.txt.remove(&txt);
+                } else {
+                    self.txt_saved -= txt_size(&txt);
+                }
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.release(key, entry);
+        }
+    }
+
+    fn insert_negative(&mut self, key: String, expires: std::time::Instant) {
+        self.remove_negative(&key);
+        self.bytes += negative_size(&key);
+        self.negative.insert(key, expires);
+    }
+
+    fn remove_negative(&mut self, key: &str) {
+        if self.negative.remove(key).is_some() {
+            self.bytes -= negative_size(key);
+        }
+    }
+
+    /// Keep the negative entries `keep` selects by key and expiry
+    fn retain_negative(&mut self, keep: impl Fn(&str, std::time::Instant) -> bool) {
+        let bytes = &mut self.bytes;
+        self.negative.retain(|key, expires| {
+            let kept = keep(key, *expires);
+            if !kept {
+                *bytes -= negative_size(key);
+            }
+            kept
+        });
+    }
+
+    fn retain_fresh(&mut self, ttl: Duration) {
+        let expired: Vec<String> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.timestamp.elapsed() >= ttl)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &expired {
+            self.remove(key);
+        }
+    }
+
+    /// Bring this cache's share of the gauges up to date; the resolver's
+    /// caches and those of the tests all add to the same ones
+    fn publish_gauges(&mut self) {
+        self.shift_gauges((self.bytes, self.txt.len(), self.txt_saved));
+    }
+
+    fn shift_gauges(&mut self, current: (usize, usize, usize)) {
+        let (bytes, txt, txt_saved) = std::mem::replace(&mut self.published, current);
+        shift_gauge(&metrics::metrics().cache_bytes, bytes, current.0);
+        shift_gauge(&metrics::metrics().txt_interned, txt, current.1);
+        shift_gauge(&metrics::metrics().txt_interned_bytes_saved, txt_saved, current.2);
+    }
+}
+
+impl Drop for CacheData {
+    fn drop(&mut self) {
+        self.shift_gauges((0, 0, 0));
+    }
+}
+
+fn shift_gauge(gauge: &AtomicU64, from: usize, to: usize) {
+    if to >= from {
+        metrics::add(gauge, (to - from) as u64);
+    } else {
+        metrics::sub(gauge, (from - to) as u64);
+    }
+}
+
 /// Cache for mDNS query results
 pub struct Cache {
-    data: Arc<RwLock<HashMap<String, CacheEntry>>>,
-    ttl: Duration,
+    data: Arc<RwLock<CacheData>>,
+    /// Entry lifetime in milliseconds; changed by a configuration reload
+    ttl_ms: AtomicU64,
+    /// Soft memory limit in bytes; exceeding it evicts the oldest entries
+    memory_limit: Option<usize>,
+    /// Backend that new entries are shared through with other instances
+    shared: OnceLock<Arc<dyn SharedCache>>,
+    /// Store records in canonical order rather than as received
+    canonical_order: bool,
 }
 
 impl Cache {
     /// Create a new cache with the given TTL
     pub fn new(ttl: Duration) -> Self {
         Self {
-            data: Arc::new(RwLock::new(HashMap::new())),
-            ttl,
+            data: Arc::new(RwLock::new(CacheData::default())),
+            ttl_ms: AtomicU64::new(ttl.as_millis() as u64),
+            memory_limit: None,
+            shared: OnceLock::new(),
+            canonical_order: false,
         }
     }
 
-    /// Get cached records if still valid
+    /// Set a soft memory limit in bytes (None for unlimited)
+    pub fn with_memory_limit(mut self, limit: Option<usize>) -> Self {
+        self.memory_limit = limit;
+        self
+    }
+
+    /// Store records sorted with [`sort_canonical`]
+    pub fn with_canonical_order(mut self, enabled: bool) -> Self {
+        self.canonical_order = enabled;
+        self
+    }
+
+    /// Get cached records if still valid, with TTLs aged as by [`CacheEntry::decayed_records`]
     pub fn get(&self, name: &str, record_type: RecordType) -> Option<Vec<Record>> {
         let cache = self.data.read().unwrap();
         let cache_key = Self::make_key(name, record_type);
-        
-        if let Some(entry) = cache.get(&cache_key) {
-            if entry.timestamp.elapsed() < self.ttl {
-                return Some(entry.records.clone());
-            }
+
+        if let Some(entry) = cache.entries.get(&cache_key)
+            && entry.timestamp.elapsed() < self.ttl()
+        {
+            return Some(entry.decayed_records(self.ttl()));
         }
-        
+
+        // A recent empty answer is served as such
+        if let Some(expires) = cache.negative.get(&cache_key)
+            && std::time::Instant::now() < *expires
+        {
+            return Some(Vec::new());
+        }
+
         None
     }
 
     /// Cache query results
     pub fn insert(&self, name: &str, record_type: RecordType, records: Vec<Record>) {
-        let mut cache = self.data.write().unwrap();
         let cache_key = Self::make_key(name, record_type);
-        
-        cache.insert(
+        if let Some(shared) = self.shared.get() {
+            shared.publish(&SharedEntry {
+                key: cache_key.clone(),
+                records: records.clone(),
+            });
+        }
+        self.store(cache_key, records, std::time::Instant::now());
+    }
+
+    /// Remember for `ttl` that mDNS had no answer; kept locally and never shared
+    pub fn insert_negative(&self, name: &str, record_type: RecordType, ttl: Duration) {
+        if ttl.is_zero() {
+            return;
+        }
+        let now = std::time::Instant::now();
+        let mut cache = self.data.write().unwrap();
+        cache.retain_negative(|_, expires| now < expires);
+        cache.insert_negative(Self::make_key(name, record_type), now + ttl);
+        self.enforce_limit(&mut cache);
+        cache.publish_gauges();
+    }
+
+    /// Cache an entry another instance shared, without sharing it back. Dropped
+    /// unless every record has the owner name and type its key names.
+    pub fn insert_shared(&self, entry: SharedEntry) {
+        if !matches_key(&entry) {
+            warn!("Ignoring shared cache entry {}: its records do not match the key", entry.key);
+            return;
+        }
+        self.store(entry.key, entry.records, std::time::Instant::now());
+    }
+
+    /// Cache records that were cached `age` ago elsewhere; false if they have already expired
+    pub fn restore(&self, name: &str, record_type: RecordType, records: Vec<Record>, age: Duration) -> bool {
+        match std::time::Instant::now().checked_sub(age) {
+            Some(timestamp) if age < self.ttl() => {
+                self.store(Self::make_key(name, record_type), records, timestamp);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Records of every unexpired entry, with TTLs as they would be served now
+    pub fn served_records(&self) -> Vec<Record> {
+        let cache = self.data.read().unwrap();
+        cache
+            .entries
+            .values()
+            .filter(|entry| entry.timestamp.elapsed() < self.ttl())
+            .flat_map(|entry| entry.decayed_records(self.ttl()))
+            .collect()
+    }
+
+    /// Every unexpired entry as name, record type, age and records
+    pub fn aged_entries(&self) -> Vec<(String, RecordType, Duration, Vec<Record>)> {
+        let cache = self.data.read().unwrap();
+        cache
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.timestamp.elapsed() < self.ttl())
+            .filter_map(|(key, entry)| {
+                let (name, record_type) = key.rsplit_once(':')?;
+                // Keys hold the Debug form, so types without a mnemonic look like "Unknown(65)"
+                if !record_type.bytes().all(|b| b.is_ascii_uppercase() || b.is_ascii_digit()) {
+                    return None;
+                }
+                Some((
+                    name.to_string(),
+                    record_type.parse().ok()?,
+                    entry.timestamp.elapsed(),
+                    entry.records.iter().map(CachedRecord::to_record).collect(),
+                ))
+            })
+            .collect()
+    }
+
+    /// Share entries inserted from now on through `shared`; only the first backend is kept
+    pub fn attach_shared(&self, shared: Arc<dyn SharedCache>) -> bool {
+        self.shared.set(shared).is_ok()
+    }
+
+    pub fn shared(&self) -> Option<&Arc<dyn SharedCache>> {
+        self.shared.get()
+    }
+
+    /// Every unexpired entry, as it would be shared; TTLs are aged, since the
+    /// receiving instance caches the entry as new
+    pub fn fresh_entries(&self) -> Vec<SharedEntry> {
+        let cache = self.data.read().unwrap();
+        cache
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.timestamp.elapsed() < self.ttl())
+            .map(|(key, entry)| SharedEntry {
+                key: key.clone(),
+                records: entry.decayed_records(self.ttl()),
+            })
+            .collect()
+    }
+
+    fn store(&self, cache_key: String, mut records: Vec<Record>, timestamp: std::time::Instant) {
+        if self.canonical_order {
+            sort_canonical(&mut records);
+        }
+        let mut cache = self.data.write().unwrap();
+
+        cache.remove(&cache_key);
+        cache.remove_negative(&cache_key);
+        let records = cache.intern(records);
+        cache.bytes += entry_size(&cache_key, &records);
+        cache.entries.insert(
             cache_key,
             CacheEntry {
                 records,
-                timestamp: std::time::Instant::now(),
+                timestamp,
             },
         );
-        
+
         // Clean up old entries
-        cache.retain(|_, entry| entry.timestamp.elapsed() < self.ttl);
+        cache.retain_fresh(self.ttl());
+        self.enforce_limit(&mut cache);
+        cache.publish_gauges();
     }
 
-    /// Get the TTL for this cache
+    /// Evict entries once the cache is over its memory limit
+    fn enforce_limit(&self, cache: &mut CacheData) {
+        if let Some(limit) = self.memory_limit
+            && cache.bytes > limit
+        {
+            self.evict_oldest(cache, limit * EVICTION_TARGET_PERCENT / 100);
+        }
+    }
+
+    /// Drop entries, negative ones included, soonest to expire first (for
+    /// records, oldest first) until the cache is at or below `target` bytes
+    fn evict_oldest(&self, cache: &mut CacheData, target: usize) {
+        let ttl = self.ttl();
+        let mut by_expiry: Vec<(std::time::Instant, bool, String)> = cache
+            .entries
+            .iter()
+            .map(|(key, entry)| (entry.timestamp + ttl, false, key.clone()))
+            .chain(cache.negative.iter().map(|(key, expires)| (*expires, true, key.clone())))
+            .collect();
+        by_expiry.sort();
+
+        let mut evicted = 0;
+        for (_, negative, key) in by_expiry {
+            if cache.bytes <= target {
+                break;
+            }
+            if negative {
+                cache.remove_negative(&key);
+            } else {
+                cache.remove(&key);
+            }
+            evicted += 1;
+            metrics::inc(&metrics::metrics().cache_evictions);
+        }
+        if evicted > 0 {
+            warn!(
+                "Cache over memory limit, evicted {} entries ({} bytes remain)",
+                evicted, cache.bytes
+            );
+        }
+    }
+
+    /// Drop every entry whose name ends with `suffix` (a cache key such as "lab.home.arpa.")
+    pub fn remove_suffix(&self, suffix: &str) -> usize {
+        let mut cache = self.data.write().unwrap();
+        let dotted = format!(".{}", suffix);
+        let keys: Vec<String> = cache
+            .entries
+            .keys()
+            .filter(|key| {
+                let name = key.rsplit_once(':').map_or(key.as_str(), |(name, _)| name);
+                name == suffix || name.ends_with(&dotted)
+            })
+            .cloned()
+            .collect();
+        for key in &keys {
+            cache.remove(key);
+        }
+        cache.retain_negative(|key, _| {
+            let name = key.rsplit_once(':').map_or(key, |(name, _)| name);
+            name != suffix && !name.ends_with(&dotted)
+        });
+        cache.publish_gauges();
+        keys.len()
+    }
+
+    /// Drop every entry holding a record that `matches` selects, along with
+    /// all negative entries, which may no longer hold either
+    pub fn remove_matching(&self, matches: impl Fn(&Record) -> bool) -> usize {
+        let mut cache = self.data.write().unwrap();
+        let keys: Vec<String> = cache
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.records.iter().any(|record| matches(&record.to_record())))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &keys {
+            cache.remove(key);
+        }
+        cache.retain_negative(|_, _| false);
+        cache.publish_gauges();
+        keys.len()
+    }
+
+    /// Every unexpired cached record of `record_type`, from all entries
+    pub fn records_of_type(&self, record_type: RecordType) -> Vec<Record> {
+        let cache = self.data.read().unwrap();
+        cache
+            .entries
+            .values()
+            .filter(|entry| entry.timestamp.elapsed() < self.ttl())
+            .flat_map(|entry| entry.records.iter())
+            .map(CachedRecord::to_record)
+            .filter(|record| record.record_type() == record_type)
+            .collect()
+    }
+
+    /// Record types with unexpired records cached under `name`
+    pub fn types_of(&self, name: &str) -> Vec<RecordType> {
+        let cache = self.data.read().unwrap();
+        let prefix = format!("{}:", name);
+        let mut types: Vec<RecordType> = cache
+            .entries
+            .iter()
+            .filter(|(key, entry)| key.starts_with(&prefix) && entry.timestamp.elapsed() < self.ttl())
+            .flat_map(|(_, entry)| entry.records.iter())
+            .map(|record| match record {
+                CachedRecord::Plain(record) => record.record_type(),
+                CachedRecord::Txt(..) => RecordType::TXT,
+            })
+            .collect();
+        types.sort_unstable_by_key(|&t| u16::from(t));
+        types.dedup();
+        types
+    }
+
+    /// Approximate memory held by cached and negative entries, in bytes
+    pub fn memory_usage(&self) -> usize {
+        self.data.read().unwrap().bytes
+    }
+
+    /// Number of cached entries, including expired ones not yet cleaned up
     #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.data.read().unwrap().entries.len()
+    }
+
+    /// Get the TTL for this cache
     pub fn ttl(&self) -> Duration {
-        self.ttl
+        Duration::from_millis(self.ttl_ms.load(Ordering::Relaxed))
+    }
+
+    /// Change the TTL; entries already cached expire by the new one
+    pub fn set_ttl(&self, ttl: Duration) {
+        self.ttl_ms.store(ttl.as_millis() as u64, Ordering::Relaxed);
     }
 
     /// Create a cache key from name and record type
@@ -67,3 +537,39 @@ impl Cache {
         format!("{}:{:?}", name, record_type)
     }
 }
+
+/// Whether `entry` holds records, all owned by its key's name and of its key's type
+fn matches_key(entry: &SharedEntry) -> bool {
+    let Some((name, record_type)) = entry.key.rsplit_once(':') else {
+        return false;
+    };
+    let Ok(name) = Name::from_utf8(name) else {
+        return false;
+    };
+    let name = names::cache_key(&name);
+    !entry.records.is_empty()
+        && entry.records.iter().all(|record| {
+            names::cache_key(record.name()) == name && format!("{:?}", record.record_type()) == record_type
+        })
+}
+
+/// Approximate memory footprint of a negative entry: the key and its expiry
+fn negative_size(key: &str) -> usize {
+    key.len() + std::mem::size_of::<std::time::Instant>()
+}
+
+/// Approximate memory footprint of a cache entry: the key, the entry itself and
+/// each record's inline size plus its wire-format length as a stand-in for heap
+/// data. Interned TXT data is left out; the cache charges each blob once.
+fn entry_size(key: &str, records: &[CachedRecord]) -> usize {
+    let records_size: usize = records
+        .iter()
+        .map(|r| {
+            let record = match r {
+                CachedRecord::Plain(record) | CachedRecord::Txt(record, _) => record,
+            };
+            std::mem::size_of::<CachedRecord>() + record.to_bytes().map(|b| b.len()).unwrap_or(0)
+        })
+        .sum();
+    key.len() + std::mem::size_of::<CacheEntry>() + records_size
+}
@@ -0,0 +1,117 @@
+//! What the mDNS backend can do, and what the proxy does when it cannot
+//!
+//! The resolver reaches the link through a backend, today mdns-sd. It browses
+//! service types and resolves instances and host names, but has no way to send
+//! a question of any other record type, always asks for multicast responses
+//! (no QU bit), and can be limited to named interfaces. CNAME questions are
+//! asked with a [one-shot query](super::oneshot) beside it. [`Capabilities`]
+//! describes a backend so the resolver checks before asking rather than
+//! learning from a backend-specific failure. Each gap has one outcome:
+//!
+//! - a record type the backend cannot ask for: NOERROR with no answer
+//!   (NODATA), cached negatively like any empty answer and counted in
+//!   `queries_unsupported`; the daemon is not started for it
+//! - unicast responses (QU): the question is asked with QM instead
+//! - interface scoping, with `mdns.interfaces` or `[[links]]` set: startup
+//!   is refused
+//!
+//! SOA and NS are answered by the proxy itself whatever the backend, and
+//! HTTPS and SVCB are [synthesized](super::svcb) from browsed instances.
+
+use crate::config::Config;
+use hickory_proto::rr::RecordType;
+use std::fmt;
+
+/// Operations a backend supports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    pub name: &'static str,
+    /// Record types the backend can ask the link for
+    pub record_types: &'static [RecordType],
+    /// Questions of any record type, beyond `record_types`
+    pub arbitrary_types: bool,
+    /// Questions asking for unicast responses (QU, RFC 6762 Section 5.4)
+    pub unicast_responses: bool,
+    /// Queries limited to named interfaces
+    pub interface_scoping: bool,
+}
+
+/// The mdns-sd daemon
+pub const MDNS_SD: Capabilities = Capabilities {
+    name: "mdns-sd",
+    record_types: &[
+        RecordType::A,
+        RecordType::AAAA,
+        RecordType::PTR,
+        RecordType::SRV,
+        RecordType::TXT,
+        RecordType::CNAME,
+    ],
+    arbitrary_types: false,
+    unicast_responses: false,
+    interface_scoping: true,
+};
+
+impl Capabilities {
+    /// Whether the backend can ask the link for `record_type`
+    pub fn can_query(&self, record_type: RecordType) -> bool {
+        self.arbitrary_types || self.record_types.contains(&record_type)
+    }
+
+    /// Refuse settings the backend cannot honor
+    pub fn check(&self, config: &Config) -> Result<(), String> {
+        let scoped = !config.mdns.interfaces.is_empty() || !config.links.is_empty();
+        if scoped && !self.interface_scoping {
+            return Err(format!(
+                "the {} backend cannot limit queries to interfaces (mdns.interfaces, [[links]])",
+                self.name
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let types = if self.arbitrary_types {
+            "any".to_string()
+        } else {
+            let types: Vec<String> = self.record_types.iter().map(RecordType::to_string).collect();
+            types.join("/")
+        };
+        let yes_no = |supported: bool| if supported { "yes" } else { "no" };
+        write!(
+            f,
+            "{} (types {}, QU {}, interface scoping {})",
+            self.name,
+            types,
+            yes_no(self.unicast_responses),
+            yes_no(self.interface_scoping)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_degradation_matrix() {
+        assert!(MDNS_SD.can_query(RecordType::SRV));
+        assert!(!MDNS_SD.can_query(RecordType::HINFO));
+        assert_eq!(MDNS_SD.to_string(), "mdns-sd (types A/AAAA/PTR/SRV/TXT/CNAME, QU no, interface scoping yes)");
+
+        let unscoped = Capabilities {
+            name: "test",
+            arbitrary_types: true,
+            interface_scoping: false,
+            ..MDNS_SD
+        };
+        assert!(unscoped.can_query(RecordType::HINFO));
+        let mut config = Config::default();
+        assert!(unscoped.check(&config).is_ok());
+        config.mdns.interfaces = vec!["eth0".to_string()];
+        assert!(unscoped.check(&config).is_err());
+        assert!(MDNS_SD.check(&config).is_ok());
+    }
+}
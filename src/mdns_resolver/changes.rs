@@ -0,0 +1,192 @@
+//! Change detection for service instances
+//!
+//! Devices bump their TXT data (a printer's `state`, a speaker's `vers`) or move
+//! to another port without leaving the link, which add/remove tracking does not
+//! notice. Every SRV and TXT RRset looked up is compared with the one last seen
+//! for the instance; a difference is logged as an update and counted in the
+//! `instance_updates` metric. A device that rewrites its TXT record every few
+//! seconds is reported at most once per debounce window, with the changes in
+//! between counted into the next report. An instance not looked up for longer
+//! than its records' TTL is forgotten, so departed devices do not pile up.
+
+use crate::metrics;
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+use super::names;
+
+/// A change to a known instance, as reported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstanceUpdate {
+    pub instance: Name,
+    pub record_type: RecordType,
+    /// Changes seen since the last report, this one included
+    pub changes: u32,
+}
+
+impl InstanceUpdate {
+    /// Log the update, with the service type's description when one is known
+    pub fn log(&self) {
+        let instance = names::presentation(&self.instance);
+        let description = crate::service_types::registry().describe(&instance).map(|d| format!(", {}", d));
+        info!(
+            "Service instance {} updated ({:?}, {} change(s){})",
+            instance,
+            self.record_type,
+            self.changes,
+            description.unwrap_or_default()
+        );
+    }
+}
+
+#[derive(Debug)]
+struct Seen {
+    data: Vec<RData>,
+    /// When the RRset was last looked up, and for how long it stays current
+    last_seen: Instant,
+    ttl: Duration,
+    reported: Option<Instant>,
+    unreported: u32,
+}
+
+/// Last SRV and TXT data seen per instance, keyed by cache key and type
+#[derive(Debug)]
+pub struct ChangeTracker {
+    debounce: Duration,
+    seen: Mutex<HashMap<(String, RecordType), Seen>>,
+}
+
+impl ChangeTracker {
+    pub fn new(debounce: Duration) -> Self {
+        Self {
+            debounce,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Compare `records` (one RRset) with what was last seen for its owner and
+    /// return the update to report if it changed and the debounce window allows
+    #[must_use]
+    pub fn observe(&self, records: &[Record]) -> Option<InstanceUpdate> {
+        let update = self.observe_at(records, Instant::now())?;
+        metrics::inc(&metrics::metrics().instance_updates);
+        Some(update)
+    }
+
+    fn observe_at(&self, records: &[Record], now: Instant) -> Option<InstanceUpdate> {
+        let first = records.first()?;
+        let record_type = first.record_type();
+        if !matches!(record_type, RecordType::SRV | RecordType::TXT) {
+            return None;
+        }
+        let mut data: Vec<RData> = records.iter().map(|r| r.data().clone()).collect();
+        data.sort();
+        let ttl = Duration::from_secs(records.iter().map(|r| u64::from(r.ttl())).max().unwrap_or(0));
+
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, entry| now.saturating_duration_since(entry.last_seen) <= entry.ttl);
+        let key = (names::cache_key(first.name()), record_type);
+        let Some(entry) = seen.get_mut(&key) else {
+            // First sighting: an appearance, not an update
+            seen.insert(
+                key,
+                Seen {
+                    data,
+                    last_seen: now,
+                    ttl,
+                    reported: None,
+                    unreported: 0,
+                },
+            );
+            return None;
+        };
+        entry.last_seen = now;
+        entry.ttl = ttl;
+        if entry.data != data {
+            entry.data = data;
+            entry.unreported += 1;
+        }
+        if entry.unreported == 0 || entry.reported.is_some_and(|at| now.duration_since(at) < self.debounce) {
+            return None;
+        }
+        let changes = std::mem::take(&mut entry.unreported);
+        entry.reported = Some(now);
+        Some(InstanceUpdate {
+            instance: first.name().clone(),
+            record_type,
+            changes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::rdata::TXT;
+
+    fn txt(state: &str) -> Vec<Record> {
+        vec![Record::from_rdata(
+            Name::from_utf8("Office._ipp._tcp.local.").unwrap(),
+            120,
+            RData::TXT(TXT::new(vec![format!("state={}", state)])),
+        )]
+    }
+
+    #[test]
+    fn test_updates_are_debounced() {
+        let tracker = ChangeTracker::new(Duration::from_secs(10));
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(tracker.observe_at(&txt("idle"), at(0)), None);
+        assert_eq!(tracker.observe_at(&txt("idle"), at(1)), None);
+
+        let update = tracker.observe_at(&txt("printing"), at(2)).unwrap();
+        assert_eq!((update.record_type, update.changes), (RecordType::TXT, 1));
+
+        // Flapping inside the window is held back and counted
+        assert_eq!(tracker.observe_at(&txt("idle"), at(5)), None);
+        assert_eq!(tracker.observe_at(&txt("printing"), at(8)), None);
+        // and reported on the first lookup after it, even without a new change
+        assert_eq!(tracker.observe_at(&txt("printing"), at(13)).unwrap().changes, 2);
+        assert_eq!(tracker.observe_at(&txt("printing"), at(30)), None);
+    }
+
+    #[test]
+    fn test_departed_instances_are_forgotten() {
+        let tracker = ChangeTracker::new(Duration::from_secs(10));
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let srv = |instance: &str| {
+            let target = Name::from_utf8("printer.local.").unwrap();
+            vec![Record::from_rdata(
+                Name::from_utf8(instance).unwrap(),
+                120,
+                RData::SRV(hickory_proto::rr::rdata::SRV::new(0, 0, 631, target)),
+            )]
+        };
+
+        assert_eq!(tracker.observe_at(&txt("idle"), at(0)), None);
+        assert_eq!(tracker.observe_at(&srv("Printer-1a2b._ipp._tcp.local."), at(0)), None);
+        assert_eq!(tracker.seen.lock().unwrap().len(), 2);
+
+        // Looked up again within its TTL, an instance is kept
+        assert_eq!(tracker.observe_at(&txt("idle"), at(100)), None);
+        assert_eq!(tracker.seen.lock().unwrap().len(), 2);
+
+        // One renamed itself and the old name expired; the other is still looked up
+        assert_eq!(tracker.observe_at(&srv("Printer-3c4d._ipp._tcp.local."), at(200)), None);
+        let seen = tracker.seen.lock().unwrap();
+        assert_eq!(seen.len(), 2);
+        assert!(!seen.contains_key(&("printer-1a2b._ipp._tcp.local.".to_string(), RecordType::SRV)));
+        assert!(seen.contains_key(&("printer-3c4d._ipp._tcp.local.".to_string(), RecordType::SRV)));
+        drop(seen);
+
+        // A change after the data was forgotten is a new appearance, not an update
+        assert_eq!(tracker.observe_at(&txt("printing"), at(400)), None);
+        assert_eq!(tracker.seen.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,178 @@
+//! On-demand mDNS daemon
+//!
+//! The daemon binds the multicast sockets on every interface, which fails on
+//! hosts where the interfaces it needs are not up yet (VPN or bridge
+//! interfaces that appear after the proxy starts). With `mdns.lazy_start` the
+//! resolver starts without a daemon and the first query that needs multicast
+//! starts it. A start that fails fails that query; the next query tries
+//! again, and [`run`] keeps retrying in the background so the daemon is up
+//! before the next query arrives.
+
+use crate::metrics;
+use mdns_sd::{IfKind, ServiceDaemon};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use super::MdnsResolver;
+
+type DaemonResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Retry a failed daemon start every `interval` until it succeeds
+pub async fn run(resolver: Arc<MdnsResolver>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        if resolver.daemon.is_started() {
+            debug!("mDNS daemon is up; stopping start retries");
+            return;
+        }
+        if resolver.daemon.start_failed() {
+            let _ = resolver.daemon.get();
+        }
+    }
+}
+
+/// Start a daemon listening on every interface, or only on `interfaces` when any are named
+pub fn start(interfaces: &[String]) -> DaemonResult<Arc<ServiceDaemon>> {
+    let daemon = ServiceDaemon::new()?;
+    if interfaces.is_empty() {
+        daemon.enable_interface(IfKind::All)?;
+    } else {
+        daemon.disable_interface(IfKind::All)?;
+        for interface in interfaces {
+            daemon.enable_interface(IfKind::Name(interface.clone()))?;
+        }
+    }
+    // daemon.accept_unsolicited(true)?;
+    Ok(Arc::new(daemon))
+}
+
+/// The resolver's daemon, started with the resolver or by the first use
+#[derive(Default)]
+pub struct LazyDaemon {
+    daemon: Mutex<Option<Arc<ServiceDaemon>>>,
+    /// The last start attempt failed
+    failed: AtomicBool,
+    /// Shut down for good: never started again
+    stopped: AtomicBool,
+    /// Interfaces a daemon started later listens on; all when empty
+    interfaces: Vec<String>,
+}
+
+impl LazyDaemon {
+    /// Already running `daemon`
+    pub fn started(daemon: Arc<ServiceDaemon>) -> Self {
+        Self {
+            daemon: Mutex::new(Some(daemon)),
+            failed: AtomicBool::new(false),
+            stopped: AtomicBool::new(false),
+            interfaces: Vec::new(),
+        }
+    }
+
+    /// No daemon until [`get`](Self::get) is first called; it then listens on
+    /// `interfaces`, or every interface when empty
+    pub fn deferred(interfaces: Vec<String>) -> Self {
+        Self {
+            interfaces,
+            ..Self::default()
+        }
+    }
+
+    /// The daemon, starting it if it is not running yet
+    pub fn get(&self) -> DaemonResult<Arc<ServiceDaemon>> {
+        let mut daemon = self.daemon.lock().unwrap();
+        if let Some(daemon) = daemon.as_ref() {
+            return Ok(daemon.clone());
+        }
+        if self.stopped.load(Ordering::Relaxed) {
+            return Err("mDNS daemon shut down".into());
+        }
+        match start(&self.interfaces) {
+            Ok(started) => {
+                info!("mDNS daemon started");
+                self.failed.store(false, Ordering::Relaxed);
+                *daemon = Some(started.clone());
+                Ok(started)
+            }
+            Err(e) => {
+                metrics::inc(&metrics::metrics().daemon_start_failures);
+                if !self.failed.swap(true, Ordering::Relaxed) {
+                    warn!("Failed to start the mDNS daemon, retrying: {}", e);
+                }
+                Err(format!("mDNS daemon not running: {}", e).into())
+            }
+        }
+    }
+
+    /// The daemon if it is running, without starting it
+    pub fn current(&self) -> Option<Arc<ServiceDaemon>> {
+        self.daemon.lock().unwrap().clone()
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.daemon.lock().unwrap().is_some()
+    }
+
+    /// Whether the last start attempt failed, so [`run`] should try again
+    pub fn start_failed(&self) -> bool {
+        self.failed.load(Ordering::Relaxed) && !self.stopped.load(Ordering::Relaxed)
+    }
+
+    /// Stop the daemon, if it is running, and keep it from being started
+    /// again; waits up to `deadline` for it to confirm
+    pub async fn shutdown(&self, deadline: Duration) -> DaemonResult<()> {
+        self.stopped.store(true, Ordering::Relaxed);
+        let Some(daemon) = self.daemon.lock().unwrap().take() else {
+            return Ok(());
+        };
+        let status = daemon.shutdown()?;
+        match tokio::time::timeout(deadline, status.recv_async()).await {
+            Ok(Ok(_)) => {
+                info!("mDNS daemon shut down");
+                Ok(())
+            }
+            Ok(Err(e)) => Err(format!("mDNS daemon did not confirm its shutdown: {}", e).into()),
+            Err(_) => Err(format!("mDNS daemon did not shut down within {:?}", deadline).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_lazy_daemon_starts_on_first_use() {
+        let mut config = Config::default();
+        config.mdns.lazy_start = true;
+        let resolver = MdnsResolver::new(Arc::new(config)).unwrap();
+        assert!(!resolver.daemon.is_started());
+        assert_eq!(resolver.expire_browses(), 0);
+        assert!(resolver.daemon.current().is_none());
+
+        let daemon = resolver.daemon.get().unwrap();
+        assert!(resolver.daemon.is_started() && !resolver.daemon.start_failed());
+        assert!(Arc::ptr_eq(&daemon, &resolver.daemon.get().unwrap()));
+    }
+
+    #[test]
+    fn test_eager_daemon_is_started_with_the_resolver() {
+        let resolver = MdnsResolver::new(Arc::new(Config::default())).unwrap();
+        assert!(resolver.daemon.is_started());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_daemon_stays_down() {
+        let resolver = MdnsResolver::new(Arc::new(Config::default())).unwrap();
+        resolver.daemon.shutdown(Duration::from_secs(5)).await.unwrap();
+        assert!(!resolver.daemon.is_started());
+        assert!(resolver.daemon.get().is_err());
+        assert!(!resolver.daemon.start_failed());
+        // Nothing left to stop
+        resolver.daemon.shutdown(Duration::from_secs(1)).await.unwrap();
+    }
+}
@@ -0,0 +1,186 @@
+//! Fault injection between the mDNS daemon and the resolver
+//!
+//! Real links lose multicast, answer late, repeat themselves, and the daemon
+//! sometimes refuses a browse. To see how the resolver and handler cope
+//! (timeouts, retries, SERVFAIL), tests build a resolver
+//! [`with_faults`](super::MdnsResolver::with_faults) and the browses it starts
+//! suffer them: a browse fails outright, or each of its events is dropped,
+//! repeated, or delivered late, and so possibly out of order. A browse can
+//! also panic, standing in for a bug on the query path. Draws come from a
+//! seeded generator, so a failing run can be repeated with its seed. Hostname
+//! lookups go to the daemon directly and are not affected.
+//!
+//! Compiled for this crate's tests and with the `fault-injection` feature.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Faults to inject, each with the probability it happens (0.0 to 1.0)
+#[derive(Debug)]
+pub struct Faults {
+    browse_errors: f64,
+    browse_panics: f64,
+    drops: f64,
+    duplicates: f64,
+    delays: f64,
+    /// Range delayed events are held back for
+    delay: (Duration, Duration),
+    rng: Mutex<u64>,
+    injected: Injected,
+}
+
+#[derive(Debug, Default)]
+struct Injected {
+    browse_errors: AtomicU64,
+    browse_panics: AtomicU64,
+    drops: AtomicU64,
+    duplicates: AtomicU64,
+    delays: AtomicU64,
+}
+
+/// Count of each fault injected so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FaultCounts {
+    pub browse_errors: u64,
+    pub browse_panics: u64,
+    pub drops: u64,
+    pub duplicates: u64,
+    pub delays: u64,
+}
+
+impl Faults {
+    /// No faults yet; draws start from `seed`
+    pub fn new(seed: u64) -> Self {
+        Self {
+            browse_errors: 0.0,
+            browse_panics: 0.0,
+            drops: 0.0,
+            duplicates: 0.0,
+            delays: 0.0,
+            delay: (Duration::ZERO, Duration::ZERO),
+            // xorshift never leaves zero
+            rng: Mutex::new(seed.max(1)),
+            injected: Injected::default(),
+        }
+    }
+
+    /// Fail this share of browse calls as the daemon would
+    pub fn with_browse_errors(mut self, probability: f64) -> Self {
+        self.browse_errors = probability;
+        self
+    }
+
+    /// Panic in this share of browse calls
+    pub fn with_browse_panics(mut self, probability: f64) -> Self {
+        self.browse_panics = probability;
+        self
+    }
+
+    /// Lose this share of events
+    pub fn with_drops(mut self, probability: f64) -> Self {
+        self.drops = probability;
+        self
+    }
+
+    /// Deliver this share of events twice
+    pub fn with_duplicates(mut self, probability: f64) -> Self {
+        self.duplicates = probability;
+        self
+    }
+
+    /// Hold back this share of event deliveries for between `min` and `max`
+    pub fn with_delays(mut self, probability: f64, min: Duration, max: Duration) -> Self {
+        self.delays = probability;
+        self.delay = (min, max.max(min));
+        self
+    }
+
+    /// Faults injected so far
+    pub fn injected(&self) -> FaultCounts {
+        FaultCounts {
+            browse_errors: self.injected.browse_errors.load(Ordering::Relaxed),
+            browse_panics: self.injected.browse_panics.load(Ordering::Relaxed),
+            drops: self.injected.drops.load(Ordering::Relaxed),
+            duplicates: self.injected.duplicates.load(Ordering::Relaxed),
+            delays: self.injected.delays.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Next draw, uniform in [0, 1)
+    fn draw(&self) -> f64 {
+        let mut state = self.rng.lock().unwrap();
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        (*state >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn happens(&self, probability: f64, count: &AtomicU64) -> bool {
+        let happens = probability > 0.0 && self.draw() < probability;
+        if happens {
+            count.fetch_add(1, Ordering::Relaxed);
+        }
+        happens
+    }
+
+    /// Whether the next browse call fails
+    pub(super) fn browse_fails(&self) -> bool {
+        self.happens(self.browse_errors, &self.injected.browse_errors)
+    }
+
+    /// Whether the next browse call panics
+    pub(super) fn browse_panics(&self) -> bool {
+        self.happens(self.browse_panics, &self.injected.browse_panics)
+    }
+
+    /// How long to hold back each delivery of the next event: none when it is
+    /// dropped, two when it is duplicated
+    pub(super) fn deliveries(&self) -> Vec<Duration> {
+        if self.happens(self.drops, &self.injected.drops) {
+            return Vec::new();
+        }
+        let copies = if self.happens(self.duplicates, &self.injected.duplicates) { 2 } else { 1 };
+        (0..copies)
+            .map(|_| {
+                if !self.happens(self.delays, &self.injected.delays) {
+                    return Duration::ZERO;
+                }
+                let (min, max) = self.delay;
+                min + (max - min).mul_f64(self.draw())
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_faults_follow_probabilities_and_seed() {
+        let none = Faults::new(7);
+        assert!((0..1000).all(|_| !none.browse_fails() && none.deliveries() == vec![Duration::ZERO]));
+        assert_eq!(none.injected(), FaultCounts::default());
+
+        let all = Faults::new(7)
+            .with_duplicates(1.0)
+            .with_delays(1.0, Duration::from_millis(10), Duration::from_millis(20));
+        for _ in 0..100 {
+            let deliveries = all.deliveries();
+            assert_eq!(deliveries.len(), 2);
+            assert!(deliveries.iter().all(|d| (Duration::from_millis(10)..=Duration::from_millis(20)).contains(d)));
+        }
+        assert_eq!(all.injected().duplicates, 100);
+        assert_eq!(all.injected().delays, 200);
+
+        let half = |seed| {
+            let faults = Faults::new(seed).with_drops(0.5);
+            (0..1000).map(|_| faults.deliveries().is_empty()).collect::<Vec<_>>()
+        };
+        let dropped = half(42).iter().filter(|&&dropped| dropped).count();
+        assert!((400..600).contains(&dropped), "{} of 1000 dropped", dropped);
+        assert_eq!(half(42), half(42));
+        assert_ne!(half(42), half(43));
+    }
+}
@@ -0,0 +1,67 @@
+//! Rate limit for fresh (cache-bypassing) queries
+//!
+//! A fresh query skips the cache and always goes out as multicast, so any
+//! client able to ask for one could otherwise flood the link. At most
+//! `[mdns] fresh_queries_per_minute` are let through; the rest are answered
+//! normally, from the cache where possible.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Fixed one-minute window counter shared by all clients
+#[derive(Debug)]
+pub struct FreshLimiter {
+    per_minute: u32,
+    /// Start of the current window and queries let through in it
+    window: Mutex<Option<(Instant, u32)>>,
+}
+
+impl FreshLimiter {
+    pub fn new(per_minute: u32) -> Self {
+        Self {
+            per_minute,
+            window: Mutex::new(None),
+        }
+    }
+
+    /// Whether one more fresh query may go out now
+    pub fn allow(&self) -> bool {
+        self.allow_at(Instant::now())
+    }
+
+    fn allow_at(&self, now: Instant) -> bool {
+        if self.per_minute == 0 {
+            return false;
+        }
+        let mut window = self.window.lock().unwrap();
+        match window.as_mut() {
+            Some((start, count)) if now.duration_since(*start) < WINDOW => {
+                if *count >= self.per_minute {
+                    return false;
+                }
+                *count += 1;
+            }
+            _ => *window = Some((now, 1)),
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limit_per_window() {
+        let limiter = FreshLimiter::new(2);
+        let now = Instant::now();
+        assert!(limiter.allow_at(now));
+        assert!(limiter.allow_at(now + Duration::from_secs(1)));
+        assert!(!limiter.allow_at(now + Duration::from_secs(59)));
+        assert!(limiter.allow_at(now + Duration::from_secs(60)));
+
+        assert!(!FreshLimiter::new(0).allow_at(now));
+    }
+}
@@ -0,0 +1,321 @@
+//! Cache gossip between the instances of a cluster
+//!
+//! The built-in [`SharedCache`] backend: entries are sent as UDP datagrams to
+//! every configured member, in DNS wire format. Each datagram carries an
+//! HMAC-SHA256 under `cluster.secret`; only datagrams from configured members
+//! with a valid MAC are accepted. Delivery is best effort; a lost entry is
+//! simply queried again on a miss.
+
+use crate::config::{ClusterConfig, TsigAlgorithm};
+use crate::tsig;
+use async_trait::async_trait;
+use hickory_proto::rr::Record;
+use hickory_proto::serialize::binary::{BinDecodable, BinEncodable};
+use std::io;
+use std::net::SocketAddr;
+use tokio::net::UdpSocket;
+use tracing::{debug, info, warn};
+
+use super::shared::{SharedCache, SharedEntry, SharedMessage};
+
+/// Leading bytes of every gossip datagram
+const MAGIC: &[u8; 4] = b"MDPC";
+
+const KIND_ENTRY: u8 = 1;
+const KIND_SYNC: u8 = 2;
+
+/// Largest UDP payload over IPv4
+const MAX_DATAGRAM: usize = 65_507;
+
+/// Length of the HMAC-SHA256 trailing every datagram
+const MAC_LEN: usize = 32;
+
+/// Gossip over UDP to a fixed set of cluster members
+pub struct GossipCache {
+    socket: UdpSocket,
+    /// Non-blocking handle to the same socket, so publishing never waits on the runtime
+    sender: std::net::UdpSocket,
+    members: Vec<SocketAddr>,
+    secret: Vec<u8>,
+}
+
+impl GossipCache {
+    /// Fails on a missing or undecodable `cluster.secret`, or if `listen` cannot be bound
+    pub async fn bind(config: &ClusterConfig) -> io::Result<Self> {
+        let secret = data_encoding::BASE64
+            .decode(config.secret.trim().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("cluster.secret is not base64: {}", e)))?;
+        if secret.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "cluster.secret is required"));
+        }
+        let sender = std::net::UdpSocket::bind(config.listen)?;
+        sender.set_nonblocking(true)?;
+        let socket = UdpSocket::from_std(sender.try_clone()?)?;
+        info!(
+            "Sharing cache on {} with {} cluster member(s)",
+            socket.local_addr()?,
+            config.members.len()
+        );
+        Ok(Self {
+            socket,
+            sender,
+            members: config.members.clone(),
+            secret,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    fn is_member(&self, addr: SocketAddr) -> bool {
+        self.members.iter().any(|member| member.ip() == addr.ip())
+    }
+
+    /// The configured address of the member at `addr`, whose source port may differ
+    fn member_for(&self, addr: SocketAddr) -> SocketAddr {
+        self.members
+            .iter()
+            .find(|member| **member == addr)
+            .or_else(|| self.members.iter().find(|member| member.ip() == addr.ip()))
+            .copied()
+            .unwrap_or(addr)
+    }
+
+    /// `body` followed by its MAC
+    fn seal(&self, mut body: Vec<u8>) -> Vec<u8> {
+        let mac = TsigAlgorithm::HmacSha256.mac(&self.secret, &[&body]);
+        body.extend_from_slice(&mac);
+        body
+    }
+
+    /// The body of `datagram` if its MAC verifies
+    fn open<'a>(&self, datagram: &'a [u8]) -> Option<&'a [u8]> {
+        let split = datagram.len().checked_sub(MAC_LEN)?;
+        let (body, mac) = datagram.split_at(split);
+        tsig::mac_matches(&TsigAlgorithm::HmacSha256.mac(&self.secret, &[body]), mac).then_some(body)
+    }
+}
+
+#[async_trait]
+impl SharedCache for GossipCache {
+    fn publish(&self, entry: &SharedEntry) {
+        let Some(datagram) = encode_entry(entry).map(|body| self.seal(body)) else {
+            return;
+        };
+        for member in &self.members {
+            if let Err(e) = self.sender.send_to(&datagram, *member) {
+                debug!("Shared cache entry {} not sent to {}: {}", entry.key, member, e);
+            }
+        }
+    }
+
+    fn request_sync(&self) {
+        let datagram = self.seal([&MAGIC[..], &[KIND_SYNC]].concat());
+        for member in &self.members {
+            if let Err(e) = self.sender.send_to(&datagram, *member) {
+                debug!("Cache sync request not sent to {}: {}", member, e);
+            }
+        }
+    }
+
+    async fn send_entries(&self, to: SocketAddr, entries: &[SharedEntry]) {
+        for entry in entries {
+            if let Some(datagram) = encode_entry(entry).map(|body| self.seal(body))
+                && let Err(e) = self.socket.send_to(&datagram, to).await
+            {
+                warn!("Cache sync to {} failed: {}", to, e);
+                return;
+            }
+        }
+    }
+
+    async fn recv(&self) -> io::Result<SharedMessage> {
+        let mut buf = vec![0u8; MAX_DATAGRAM];
+        loop {
+            let (len, from) = self.socket.recv_from(&mut buf).await?;
+            if !self.is_member(from) {
+                debug!("Ignoring cache gossip from non-member {}", from);
+                continue;
+            }
+            let Some(body) = self.open(&buf[..len]) else {
+                debug!("Ignoring cache gossip from {} with a bad MAC", from);
+                continue;
+            };
+            match decode(body) {
+                Some(Decoded::Entry(entry)) => return Ok(SharedMessage::Entry(entry)),
+                Some(Decoded::Sync) => {
+                    return Ok(SharedMessage::Sync {
+                        from: self.member_for(from),
+                    })
+                }
+                None => debug!("Ignoring malformed cache gossip from {}", from),
+            }
+        }
+    }
+}
+
+enum Decoded {
+    Entry(SharedEntry),
+    Sync,
+}
+
+/// Magic, kind, key length and key, record count, then each record length-prefixed
+fn encode_entry(entry: &SharedEntry) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(64);
+    out.extend_from_slice(MAGIC);
+    out.push(KIND_ENTRY);
+    out.extend_from_slice(&u16::try_from(entry.key.len()).ok()?.to_be_bytes());
+    out.extend_from_slice(entry.key.as_bytes());
+    out.extend_from_slice(&u16::try_from(entry.records.len()).ok()?.to_be_bytes());
+    for record in &entry.records {
+        let bytes = match record.to_bytes() {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                debug!("Shared cache entry {} not encodable: {}", entry.key, e);
+                return None;
+            }
+        };
+        out.extend_from_slice(&u16::try_from(bytes.len()).ok()?.to_be_bytes());
+        out.extend_from_slice(&bytes);
+    }
+    if out.len() + MAC_LEN > MAX_DATAGRAM {
+        debug!("Shared cache entry {} too large to send ({} bytes)", entry.key, out.len());
+        return None;
+    }
+    Some(out)
+}
+
+fn decode(datagram: &[u8]) -> Option<Decoded> {
+    let rest = datagram.strip_prefix(MAGIC)?;
+    let (&kind, mut rest) = rest.split_first()?;
+    match kind {
+        KIND_SYNC => Some(Decoded::Sync),
+        KIND_ENTRY => {
+            let key = String::from_utf8(take(&mut rest)?.to_vec()).ok()?;
+            let count = read_u16(&mut rest)?;
+            let mut records = Vec::with_capacity(count as usize);
+            for _ in 0..count {
+                records.push(Record::from_bytes(take(&mut rest)?).ok()?);
+            }
+            Some(Decoded::Entry(SharedEntry { key, records }))
+        }
+        _ => None,
+    }
+}
+
+fn read_u16(rest: &mut &[u8]) -> Option<u16> {
+    let (len, tail) = rest.split_first_chunk::<2>()?;
+    *rest = tail;
+    Some(u16::from_be_bytes(*len))
+}
+
+/// A length-prefixed field
+fn take<'a>(rest: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = read_u16(rest)? as usize;
+    if rest.len() < len {
+        return None;
+    }
+    let (field, tail) = rest.split_at(len);
+    *rest = tail;
+    Some(field)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::rdata::{A, TXT};
+    use hickory_proto::rr::{Name, RData};
+    use std::time::Duration;
+
+    fn entry() -> SharedEntry {
+        let name = Name::from_utf8("printer.local.").unwrap();
+        SharedEntry {
+            key: "printer.local.:A".to_string(),
+            records: vec![
+                Record::from_rdata(name.clone(), 120, RData::A(A::new(192, 168, 1, 20))),
+                Record::from_rdata(name, 4500, RData::TXT(TXT::new(vec!["rp=ipp/print".to_string()]))),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_entry_round_trip() {
+        let datagram = encode_entry(&entry()).unwrap();
+        match decode(&datagram) {
+            Some(Decoded::Entry(decoded)) => assert_eq!(decoded, entry()),
+            _ => panic!("entry not decoded"),
+        }
+        // Truncated datagrams are rejected rather than read short
+        assert!(decode(&datagram[..datagram.len() - 1]).is_none());
+        assert!(decode(b"XXXX\x01").is_none());
+    }
+
+    const SECRET: &str = "c2hhcmVkIGNsdXN0ZXIgc2VjcmV0";
+
+    async fn gossip(members: Vec<SocketAddr>, secret: &str) -> io::Result<GossipCache> {
+        GossipCache::bind(&ClusterConfig {
+            enabled: true,
+            listen: "127.0.0.1:0".parse().unwrap(),
+            members,
+            secret: secret.to_string(),
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_secret_is_required() {
+        for secret in ["", "not base64!"] {
+            let err = gossip(Vec::new(), secret).await.err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_gossip_between_members() {
+        let receiver = gossip(Vec::new(), SECRET).await.unwrap();
+        let sender = gossip(vec![receiver.local_addr().unwrap()], SECRET).await.unwrap();
+        // The receiver accepts only its members; the sender is on the same loopback IP
+        let receiver = GossipCache {
+            members: vec![sender.local_addr().unwrap()],
+            ..receiver
+        };
+
+        sender.publish(&entry());
+        let message = tokio::time::timeout(Duration::from_secs(2), receiver.recv()).await.unwrap().unwrap();
+        assert_eq!(message, SharedMessage::Entry(entry()));
+
+        sender.request_sync();
+        let message = tokio::time::timeout(Duration::from_secs(2), receiver.recv()).await.unwrap().unwrap();
+        assert_eq!(
+            message,
+            SharedMessage::Sync {
+                from: sender.local_addr().unwrap()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_datagrams_without_the_secret_are_dropped() {
+        let receiver = gossip(Vec::new(), SECRET).await.unwrap();
+        let forger = gossip(vec![receiver.local_addr().unwrap()], "b3RoZXIgc2VjcmV0").await.unwrap();
+        let sender = gossip(vec![receiver.local_addr().unwrap()], SECRET).await.unwrap();
+        // Both are members by address; only the MAC tells them apart
+        let receiver = GossipCache {
+            members: vec![sender.local_addr().unwrap(), forger.local_addr().unwrap()],
+            ..receiver
+        };
+
+        let mut forged = entry();
+        forged.key = "forged.local.:A".to_string();
+        forger.publish(&forged);
+        // Unsigned and truncated datagrams fail too
+        let unsigned = encode_entry(&forged).unwrap();
+        forger.sender.send_to(&unsigned, receiver.local_addr().unwrap()).unwrap();
+        forger.sender.send_to(b"MDPC", receiver.local_addr().unwrap()).unwrap();
+        sender.publish(&entry());
+
+        let message = tokio::time::timeout(Duration::from_secs(2), receiver.recv()).await.unwrap().unwrap();
+        assert_eq!(message, SharedMessage::Entry(entry()));
+    }
+}
@@ -0,0 +1,224 @@
+//! Per-link mDNS health
+//!
+//! One daemon sends and receives multicast on every interface, so a segment
+//! where multicast broke (a switch snooping IGMP wrongly, an interface the
+//! daemon could not join) shows only as answers missing for the devices on
+//! it. Browse events, resolved answers and failed liveness probes are
+//! attributed to the link, an interface with its networks, whose networks hold
+//! their addresses. Per link and for the daemon as a whole, the `health`
+//! control command reports when a browse event last arrived, when a query last
+//! resolved and how many errors were seen: failed liveness probes on a link,
+//! failed mDNS lookups for the daemon. Every link holds fe80::/64, so IPv6
+//! link-local addresses count only for the interface mdns-sd scoped them to.
+
+use crate::netwatch::{self, LocalNetwork};
+use mdns_sd::ScopedIp;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, RwLock};
+use std::time::Instant;
+use tracing::debug;
+
+/// Name the daemon's own status is reported under
+pub const DAEMON: &str = "daemon";
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Stats {
+    last_event: Option<Instant>,
+    last_resolution: Option<Instant>,
+    errors: u64,
+}
+
+/// Health of one link, or of the daemon, as the `health` command lists it
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkStatus {
+    /// Interface name, or [`DAEMON`]
+    pub name: String,
+    /// The interface's networks; empty for the daemon
+    pub networks: Vec<LocalNetwork>,
+    /// Last browse event with an address on the link
+    pub last_event: Option<Instant>,
+    /// Last mDNS answer with an address on the link
+    pub last_resolution: Option<Instant>,
+    pub errors: u64,
+}
+
+/// Event, resolution and error times per link and for the daemon
+#[derive(Debug, Default)]
+pub struct LinkHealth {
+    links: RwLock<Vec<(String, LocalNetwork)>>,
+    /// Per interface name; kept while an interface is down so its history
+    /// is there when it comes back
+    stats: Mutex<HashMap<String, Stats>>,
+    daemon: Mutex<Stats>,
+}
+
+impl LinkHealth {
+    /// Links read from the interfaces now (none if they cannot be read)
+    pub fn new() -> Self {
+        let health = Self::default();
+        health.refresh_links();
+        health
+    }
+
+    pub fn with_links(links: Vec<(String, LocalNetwork)>) -> Self {
+        let health = Self::default();
+        *health.links.write().unwrap() = links;
+        health
+    }
+
+    /// Re-read the interfaces after a network change
+    pub fn refresh_links(&self) {
+        match netwatch::interface_networks() {
+            Ok(links) => *self.links.write().unwrap() = links,
+            Err(e) => debug!("Could not read interfaces for link health: {}", e),
+        }
+    }
+
+    /// A browse event resolved an instance at `addresses`
+    pub fn event<'a>(&self, addresses: impl IntoIterator<Item = &'a ScopedIp>) {
+        let now = Instant::now();
+        self.daemon.lock().unwrap().last_event = Some(now);
+        self.update(addresses.into_iter().map(scoped), |stats| stats.last_event = Some(now));
+    }
+
+    /// A query was answered from mDNS, by instances at `instances` and
+    /// address records for `addresses`
+    pub fn resolved<'a>(&self, instances: impl IntoIterator<Item = &'a ScopedIp>, addresses: &[IpAddr]) {
+        let now = Instant::now();
+        self.daemon.lock().unwrap().last_resolution = Some(now);
+        let addresses = instances.into_iter().map(scoped).chain(addresses.iter().map(|addr| (*addr, None)));
+        self.update(addresses, |stats| stats.last_resolution = Some(now));
+    }
+
+    /// An mDNS lookup failed
+    pub fn lookup_failed(&self) {
+        self.daemon.lock().unwrap().errors += 1;
+    }
+
+    /// A liveness probe of an instance at `addresses` failed
+    pub fn probe_failed(&self, addresses: &[IpAddr]) {
+        self.update(addresses.iter().map(|addr| (*addr, None)), |stats| stats.errors += 1);
+    }
+
+    /// Links holding one of `addresses`, sorted, each once
+    pub fn links_of<'a>(&self, addresses: impl IntoIterator<Item = &'a ScopedIp>) -> Vec<String> {
+        let mut names: Vec<String> = addresses
+            .into_iter()
+            .map(scoped)
+            .filter_map(|(addr, scope)| self.link_of(&addr, scope))
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Link whose networks hold `client`, if any
+    pub fn link_of_client(&self, client: &IpAddr) -> Option<String> {
+        self.link_of(client, None)
+    }
+
+    /// The daemon first, then each link in interface order
+    pub fn statuses(&self) -> Vec<LinkStatus> {
+        let daemon = *self.daemon.lock().unwrap();
+        let mut statuses = vec![status(DAEMON.to_string(), Vec::new(), daemon)];
+
+        let mut links: Vec<(String, Vec<LocalNetwork>)> = Vec::new();
+        for (name, network) in self.links.read().unwrap().iter() {
+            match links.iter_mut().find(|(link, _)| link == name) {
+                Some((_, networks)) => networks.push(*network),
+                None => links.push((name.clone(), vec![*network])),
+            }
+        }
+        links.sort_by(|a, b| a.0.cmp(&b.0));
+        let stats = self.stats.lock().unwrap();
+        for (name, networks) in links {
+            let link = stats.get(&name).copied().unwrap_or_default();
+            statuses.push(status(name, networks, link));
+        }
+        statuses
+    }
+
+    /// Apply `change` once to every link holding one of `addresses`
+    fn update(&self, addresses: impl Iterator<Item = (IpAddr, Option<String>)>, change: impl Fn(&mut Stats)) {
+        let mut names: Vec<String> = addresses.filter_map(|(addr, scope)| self.link_of(&addr, scope)).collect();
+        names.sort();
+        names.dedup();
+        if names.is_empty() {
+            return;
+        }
+        let mut stats = self.stats.lock().unwrap();
+        for name in names {
+            change(stats.entry(name).or_default());
+        }
+    }
+
+    fn link_of(&self, addr: &IpAddr, scope: Option<String>) -> Option<String> {
+        if let IpAddr::V6(v6) = addr
+            && v6.is_unicast_link_local()
+        {
+            return scope.filter(|scope| !scope.is_empty());
+        }
+        let links = self.links.read().unwrap();
+        links.iter().find(|(_, network)| network.contains(addr)).map(|(name, _)| name.clone())
+    }
+}
+
+fn scoped(ip: &ScopedIp) -> (IpAddr, Option<String>) {
+    let scope = match ip {
+        ScopedIp::V6(v6) => Some(v6.scope_id().name.clone()),
+        _ => None,
+    };
+    (ip.to_ip_addr(), scope)
+}
+
+fn status(name: String, networks: Vec<LocalNetwork>, stats: Stats) -> LinkStatus {
+    LinkStatus {
+        name,
+        networks,
+        last_event: stats.last_event,
+        last_resolution: stats.last_resolution,
+        errors: stats.errors,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network(addr: &str, prefix_len: u8) -> LocalNetwork {
+        LocalNetwork {
+            addr: addr.parse().unwrap(),
+            prefix_len,
+        }
+    }
+
+    #[test]
+    fn test_activity_is_attributed_to_links() {
+        let health = LinkHealth::with_links(vec![
+            ("eth0".to_string(), network("192.168.1.5", 24)),
+            ("eth1".to_string(), network("10.0.3.1", 24)),
+            ("eth0".to_string(), network("fe80::1", 64)),
+            ("eth1".to_string(), network("fe80::2", 64)),
+        ]);
+        let lan: IpAddr = "192.168.1.40".parse().unwrap();
+        health.event([&ScopedIp::from(lan)]);
+        health.resolved([], &["10.0.3.7".parse().unwrap(), "172.16.0.1".parse().unwrap()]);
+        health.probe_failed(&["10.0.3.7".parse().unwrap(), "10.0.3.8".parse().unwrap()]);
+        // Unscoped link-local addresses belong to no link in particular
+        health.probe_failed(&["fe80::9".parse().unwrap()]);
+        health.lookup_failed();
+
+        let statuses = health.statuses();
+        let names: Vec<&str> = statuses.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, [DAEMON, "eth0", "eth1"]);
+        let (daemon, eth0, eth1) = (&statuses[0], &statuses[1], &statuses[2]);
+        assert!(daemon.last_event.is_some() && daemon.last_resolution.is_some());
+        assert_eq!(daemon.errors, 1);
+        assert_eq!(eth0.networks.len(), 2);
+        assert!(eth0.last_event.is_some() && eth0.last_resolution.is_none());
+        assert_eq!(eth0.errors, 0);
+        assert!(eth1.last_event.is_none() && eth1.last_resolution.is_some());
+        assert_eq!(eth1.errors, 1);
+    }
+}
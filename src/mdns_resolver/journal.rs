@@ -0,0 +1,161 @@
+//! Write-ahead journal for the known-services store
+//!
+//! The background check of `[known_services]` learns instances the
+//! configuration does not list, and fresher details for those it does; the
+//! cache snapshot does not hold them, so without `known_services.journal` a
+//! restart forgets them until the next check. Each check's result for a
+//! service type is appended as one JSON line and synced before the store
+//! applies it, so a crash loses at most the update being written. At startup
+//! the journal is replayed in order, a final line torn by a crash is skipped,
+//! and the journal is compacted to one line per service type holding the
+//! store as replayed: written beside it, synced and renamed over it.
+
+use crate::config::KnownService;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::warn;
+
+/// The instances one check of a service type found
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Update {
+    /// Service type as browsed (e.g. "_ipp._tcp.local.")
+    #[serde(rename = "type")]
+    pub service_type: String,
+    pub instances: Vec<Instance>,
+}
+
+/// One instance as it answered
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Instance {
+    #[serde(flatten)]
+    pub service: KnownService,
+    #[serde(default)]
+    pub addresses: Vec<IpAddr>,
+}
+
+/// Append-only journal file
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl Journal {
+    /// Open the journal at `path`, creating it if needed, with the updates it holds in order
+    pub fn open(path: &Path) -> io::Result<(Self, Vec<Update>)> {
+        let updates = match File::open(path) {
+            Ok(file) => read_updates(path, file)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let journal = Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+        };
+        Ok((journal, updates))
+    }
+
+    /// Append `update` and wait until it is on disk
+    pub fn append(&self, update: &Update) -> io::Result<()> {
+        let mut line = serde_json::to_vec(update)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap();
+        file.write_all(&line)?;
+        file.sync_data()
+    }
+
+    /// Replace the journal with `updates`, the whole store in as few lines as it takes
+    pub fn compact(&self, updates: &[Update]) -> io::Result<()> {
+        let file_name = self.path.file_name().ok_or_else(|| io::Error::other("journal path has no file name"))?;
+        let temporary = self.path.with_file_name(format!(".{}.tmp", file_name.to_string_lossy()));
+        let mut contents = Vec::new();
+        for update in updates {
+            serde_json::to_writer(&mut contents, update)?;
+            contents.push(b'\n');
+        }
+
+        // Held throughout so no append lands in the file being replaced
+        let mut file = self.file.lock().unwrap();
+        let mut compacted = File::create(&temporary)?;
+        compacted.write_all(&contents)?;
+        compacted.sync_all()?;
+        std::fs::rename(&temporary, &self.path)?;
+        #[cfg(unix)]
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            File::open(dir)?.sync_all()?;
+        }
+        *file = OpenOptions::new().append(true).open(&self.path)?;
+        Ok(())
+    }
+}
+
+/// Updates in `file` up to the first line that does not parse: the tail a crash left
+fn read_updates(path: &Path, file: File) -> io::Result<Vec<Update>> {
+    let mut updates = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(update) => updates.push(update),
+            Err(e) => {
+                warn!("{} line {}: {}; ignoring it and anything after", path.display(), number + 1, e);
+                break;
+            }
+        }
+    }
+    Ok(updates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(name: &str, port: u16) -> Update {
+        Update {
+            service_type: "_ipp._tcp.local.".to_string(),
+            instances: vec![Instance {
+                service: KnownService {
+                    service_type: "_ipp._tcp.local.".to_string(),
+                    name: name.to_string(),
+                    host: "printer.local.".to_string(),
+                    port,
+                    txt: vec!["rp=ipp/print".to_string()],
+                },
+                addresses: vec!["192.168.1.20".parse().unwrap()],
+            }],
+        }
+    }
+
+    #[test]
+    fn test_journal_replays_compacts_and_skips_torn_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("known.journal");
+
+        let (journal, updates) = Journal::open(&path).unwrap();
+        assert!(updates.is_empty());
+        journal.append(&update("Office Printer", 631)).unwrap();
+        journal.append(&update("Lab Printer", 8631)).unwrap();
+        drop(journal);
+
+        // A crash in the middle of an append
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"type\":\"_ipp._tcp.local.\",\"instan").unwrap();
+        drop(file);
+
+        let (journal, updates) = Journal::open(&path).unwrap();
+        assert_eq!(updates, vec![update("Office Printer", 631), update("Lab Printer", 8631)]);
+
+        journal.compact(&updates[1..]).unwrap();
+        journal.append(&update("Hall Printer", 631)).unwrap();
+        let (_, updates) = Journal::open(&path).unwrap();
+        assert_eq!(updates, vec![update("Lab Printer", 8631), update("Hall Printer", 631)]);
+        assert!(!dir.path().join(".known.journal.tmp").exists());
+    }
+}
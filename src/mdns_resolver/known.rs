@@ -0,0 +1,360 @@
+//! Statically configured service instances
+//!
+//! Answers PTR/SRV/TXT queries for configured instances without waiting on mDNS,
+//! bridging the gap for devices that sleep through browses. A background check
+//! browses each configured type and folds what actually answered into the store:
+//! live data replaces the configured details, newly seen instances are served
+//! alongside them, and configured instances stay even when they do not answer.
+//! With `known_services.journal`, what the checks learn survives a restart
+//! (see [`journal`](super::journal)).
+
+use crate::config::{KnownService, ServiceRecordKind};
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use mdns_sd::{ResolvedService, ServiceInfo, TxtProperty};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use super::journal::{Instance, Journal, Update};
+use super::names;
+use super::query::instance_records;
+use super::MdnsResolver;
+
+type KnownResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Periodically check configured instances against mDNS
+pub async fn run(resolver: Arc<MdnsResolver>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        // Both modes exist to keep multicast down; the configured records keep being served
+        if resolver.is_read_only() || resolver.is_quiet() {
+            continue;
+        }
+        if let Err(e) = resolver.verify_known_services().await {
+            warn!("Known service check failed: {}", e);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct KnownEntry {
+    info: ResolvedService,
+    /// From the configuration file, as opposed to discovered by a check
+    configured: bool,
+}
+
+/// Instances answered without a multicast round trip
+#[derive(Debug, Default)]
+pub struct KnownStore {
+    entries: RwLock<Vec<KnownEntry>>,
+    /// Where updates are written ahead of being applied, when configured
+    journal: Option<Journal>,
+}
+
+impl KnownStore {
+    pub fn from_config(services: &[KnownService]) -> KnownResult<Self> {
+        let entries = services
+            .iter()
+            .map(|service| {
+                Ok(KnownEntry {
+                    info: resolved_service(service, &[])?,
+                    configured: true,
+                })
+            })
+            .collect::<KnownResult<Vec<_>>>()?;
+        Ok(Self {
+            entries: RwLock::new(entries),
+            journal: None,
+        })
+    }
+
+    /// Replay the journal at `path` onto the configured instances, compact it
+    /// and write later updates to it
+    pub fn with_journal(mut self, path: &Path) -> KnownResult<Self> {
+        let (journal, updates) = Journal::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        for update in &updates {
+            let live = update
+                .instances
+                .iter()
+                .map(|instance| resolved_service(&instance.service, &instance.addresses))
+                .collect::<KnownResult<Vec<_>>>()?;
+            self.apply(&update.service_type, live);
+        }
+        journal
+            .compact(&self.snapshot())
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        info!("Replayed {} known service update(s) from {}", updates.len(), path.display());
+        self.journal = Some(journal);
+        Ok(self)
+    }
+
+    /// Service types (e.g. "_ipp._tcp.local.") with at least one configured instance
+    pub fn service_types(&self) -> Vec<String> {
+        let mut types: Vec<String> = Vec::new();
+        for entry in self.entries.read().unwrap().iter().filter(|e| e.configured) {
+            if !types.iter().any(|t| t.eq_ignore_ascii_case(&entry.info.ty_domain)) {
+                types.push(entry.info.ty_domain.clone());
+            }
+        }
+        types
+    }
+
+    /// Records for the `.local.` name `name`, or None if no known instance covers the question
+    pub fn answer(&self, name: &Name, record_type: RecordType) -> KnownResult<Option<Vec<Record>>> {
+        let entries = self.entries.read().unwrap();
+        match record_type {
+            RecordType::PTR => {
+                let service_type = names::mdns_string(name);
+                let mut records = Vec::new();
+                for entry in entries.iter().filter(|e| e.info.ty_domain.eq_ignore_ascii_case(&service_type)) {
+                    let target = names::instance_name(&entry.info.fullname, &entry.info.ty_domain)?;
+                    records.push(Record::from_rdata(
+                        name.clone(),
+                        120,
+                        RData::PTR(hickory_proto::rr::rdata::PTR(target)),
+                    ));
+                }
+                Ok((!records.is_empty()).then_some(records))
+            }
+            RecordType::SRV | RecordType::TXT => {
+                let kind = if record_type == RecordType::SRV { ServiceRecordKind::Srv } else { ServiceRecordKind::Txt };
+                let Some(entry) = entries
+                    .iter()
+                    .find(|e| names::instance_matches(name, &e.info.fullname, &e.info.ty_domain))
+                else {
+                    return Ok(None);
+                };
+                Ok(Some(instance_records(&entry.info, &[kind])?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Fold in a browse of `service_type`; returns configured instances that did not answer
+    pub fn update(&self, service_type: &str, live: Vec<ResolvedService>) -> Vec<String> {
+        if let Some(journal) = &self.journal {
+            let update = Update {
+                service_type: service_type.to_string(),
+                instances: live.iter().map(journal_instance).collect(),
+            };
+            if let Err(e) = journal.append(&update) {
+                warn!("Could not write the known services journal: {}", e);
+            }
+        }
+        self.apply(service_type, live)
+    }
+
+    /// The store as one update per service type, which replayed onto the
+    /// configuration gives the store back
+    fn snapshot(&self) -> Vec<Update> {
+        let mut updates: Vec<Update> = Vec::new();
+        for entry in self.entries.read().unwrap().iter() {
+            let instance = journal_instance(&entry.info);
+            match updates.iter_mut().find(|u| u.service_type.eq_ignore_ascii_case(&entry.info.ty_domain)) {
+                Some(update) => update.instances.push(instance),
+                None => updates.push(Update {
+                    service_type: entry.info.ty_domain.clone(),
+                    instances: vec![instance],
+                }),
+            }
+        }
+        updates
+    }
+
+    fn apply(&self, service_type: &str, live: Vec<ResolvedService>) -> Vec<String> {
+        let mut entries = self.entries.write().unwrap();
+        let mut seen = vec![false; entries.len()];
+
+        for info in live {
+            match entries.iter().position(|e| e.info.fullname.eq_ignore_ascii_case(&info.fullname)) {
+                Some(idx) => {
+                    entries[idx].info = info;
+                    seen[idx] = true;
+                }
+                None => {
+                    debug!("Known service type {} has new instance {}", service_type, info.fullname);
+                    entries.push(KnownEntry { info, configured: false });
+                    seen.push(true);
+                }
+            }
+        }
+
+        let mut missing = Vec::new();
+        let mut kept = Vec::with_capacity(entries.len());
+        for (entry, seen) in entries.drain(..).zip(seen) {
+            if !seen && entry.info.ty_domain.eq_ignore_ascii_case(service_type) {
+                // Discovered instances go away with the device; configured ones stay
+                if !entry.configured {
+                    continue;
+                }
+                missing.push(entry.info.fullname.clone());
+            }
+            kept.push(entry);
+        }
+        *entries = kept;
+        missing
+    }
+}
+
+/// The configured (or journaled) instance as mDNS would report it
+fn resolved_service(service: &KnownService, addresses: &[IpAddr]) -> KnownResult<ResolvedService> {
+    let ty_domain = local_name(&service.service_type);
+    let host = local_name(&service.host);
+    let properties: Vec<TxtProperty> = service
+        .txt
+        .iter()
+        .map(|entry| match entry.split_once('=') {
+            Some((key, value)) => TxtProperty::from((key, value)),
+            None => TxtProperty::from(entry.as_str()),
+        })
+        .collect();
+    let info = ServiceInfo::new(&ty_domain, &service.name, &host, addresses, service.port, properties)
+        .map_err(|e| format!("known service {}: {}", service.name, e))?;
+    Ok(info.as_resolved_service())
+}
+
+/// A resolved instance in the journal's form
+fn journal_instance(info: &ResolvedService) -> Instance {
+    let name = info
+        .fullname
+        .strip_suffix(info.ty_domain.as_str())
+        .map_or(info.fullname.as_str(), |name| name.trim_end_matches('.'));
+    let txt = info
+        .get_properties()
+        .iter()
+        .map(|property| match property.val() {
+            Some(_) => format!("{}={}", property.key(), property.val_str()),
+            None => property.key().to_string(),
+        })
+        .collect();
+    Instance {
+        service: KnownService {
+            service_type: info.ty_domain.clone(),
+            name: name.to_string(),
+            host: info.get_hostname().to_string(),
+            port: info.get_port(),
+            txt,
+        },
+        addresses: info.get_addresses().iter().map(|addr| addr.to_ip_addr()).collect(),
+    }
+}
+
+/// "printer" or "printer.local" as "printer.local."
+fn local_name(name: &str) -> String {
+    let trimmed = name.trim().trim_end_matches('.');
+    if trimmed.to_ascii_lowercase().ends_with(".local") {
+        format!("{}.", trimmed)
+    } else {
+        format!("{}.local.", trimmed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn printer() -> KnownService {
+        KnownService {
+            service_type: "_ipp._tcp".to_string(),
+            name: "Office Printer".to_string(),
+            host: "printer".to_string(),
+            port: 631,
+            txt: vec!["rp=ipp/print".to_string()],
+        }
+    }
+
+    fn live(name: &str, port: u16) -> ResolvedService {
+        ServiceInfo::new("_ipp._tcp.local.", name, "printer.local.", "192.168.1.20", port, &[("rp", "ipp/print")][..])
+            .unwrap()
+            .as_resolved_service()
+    }
+
+    #[test]
+    fn test_answers_ptr_srv_txt_from_config() {
+        let store = KnownStore::from_config(&[printer()]).unwrap();
+
+        let ptr = store.answer(&Name::from_utf8("_ipp._tcp.local.").unwrap(), RecordType::PTR).unwrap().unwrap();
+        assert_eq!(ptr.len(), 1);
+        let instance = match ptr[0].data() {
+            RData::PTR(ptr) => ptr.0.clone(),
+            other => panic!("unexpected {:?}", other),
+        };
+
+        let srv = store.answer(&instance, RecordType::SRV).unwrap().unwrap();
+        match srv[0].data() {
+            RData::SRV(srv) => {
+                assert_eq!(srv.port(), 631);
+                assert_eq!(srv.target().to_utf8(), "printer.local.");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(store.answer(&instance, RecordType::TXT).unwrap().unwrap().len(), 1);
+
+        assert!(store.answer(&Name::from_utf8("_http._tcp.local.").unwrap(), RecordType::PTR).unwrap().is_none());
+        assert!(store.answer(&instance, RecordType::A).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_update_refreshes_adds_and_reports_missing() {
+        let store = KnownStore::from_config(&[printer()]).unwrap();
+        let ty = "_ipp._tcp.local.";
+
+        // Live data replaces the configured details and new instances are added
+        let missing = store.update(ty, vec![live("Office Printer", 8631), live("Lab Printer", 631)]);
+        assert!(missing.is_empty());
+        let ptr = store.answer(&Name::from_utf8(ty).unwrap(), RecordType::PTR).unwrap().unwrap();
+        assert_eq!(ptr.len(), 2);
+        let srv = store
+            .answer(&names::name_from_labels_str("Office Printer._ipp._tcp.local.").unwrap(), RecordType::SRV)
+            .unwrap()
+            .unwrap();
+        assert!(matches!(srv[0].data(), RData::SRV(srv) if srv.port() == 8631));
+
+        // Nothing answers: the configured instance stays, the discovered one goes
+        let missing = store.update(ty, Vec::new());
+        assert_eq!(missing, vec!["Office Printer._ipp._tcp.local.".to_string()]);
+        let ptr = store.answer(&Name::from_utf8(ty).unwrap(), RecordType::PTR).unwrap().unwrap();
+        assert_eq!(ptr.len(), 1);
+        assert_eq!(store.service_types(), vec![ty.to_string()]);
+    }
+
+    #[test]
+    fn test_journal_keeps_discovered_instances() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("known.journal");
+        let ty = "_ipp._tcp.local.";
+        let ptr_count = |store: &KnownStore| {
+            store.answer(&Name::from_utf8(ty).unwrap(), RecordType::PTR).unwrap().map_or(0, |ptr| ptr.len())
+        };
+
+        let store = KnownStore::from_config(&[printer()]).unwrap().with_journal(&path).unwrap();
+        store.update(ty, vec![live("Office Printer", 8631), live("Lab Printer", 631)]);
+        assert_eq!(ptr_count(&store), 2);
+        drop(store);
+
+        // After a restart the discovered instance and the live port are back
+        let store = KnownStore::from_config(&[printer()]).unwrap().with_journal(&path).unwrap();
+        assert_eq!(ptr_count(&store), 2);
+        let entries = store.entries.read().unwrap();
+        let office = entries.iter().find(|e| e.info.fullname.starts_with("Office")).unwrap();
+        assert!(office.configured);
+        assert_eq!(office.info.get_port(), 8631);
+        let lab = entries.iter().find(|e| e.info.fullname.starts_with("Lab")).unwrap();
+        assert!(!lab.configured);
+        assert_eq!(lab.info.get_addresses().len(), 1);
+        assert_eq!(lab.info.get_property_val_str("rp"), Some("ipp/print"));
+        drop(entries);
+        // Compacted to one line for the one service type
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn test_local_name() {
+        assert_eq!(local_name("printer"), "printer.local.");
+        assert_eq!(local_name("printer.local"), "printer.local.");
+        assert_eq!(local_name("_ipp._tcp.local."), "_ipp._tcp.local.");
+    }
+}
@@ -0,0 +1,164 @@
+//! Liveness probing of cached service instances
+//!
+//! Announcements outlive the services they describe when a device leaves the link
+//! without sending goodbyes. At a low rate, each cached SRV record's target is
+//! probed with a TCP connect to the service port, using cached addresses only, so
+//! probing sends no multicast. Instances that fail are marked dead and, if
+//! configured, left out of PTR and SRV answers until a later probe succeeds.
+//! ICMP is not used since it needs raw sockets.
+
+use crate::config::LivenessConfig;
+use hickory_proto::rr::{Name, RData, Record};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+
+use super::names;
+use super::MdnsResolver;
+
+/// Probe cached instances every `interval_secs`
+pub async fn run(resolver: Arc<MdnsResolver>, config: LivenessConfig) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(config.interval_secs.max(1)));
+    let timeout = Duration::from_millis(config.timeout_ms);
+    loop {
+        ticker.tick().await;
+        resolver.probe_liveness(timeout).await;
+    }
+}
+
+/// Result of the last probe of an instance
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Liveness {
+    pub alive: bool,
+    pub checked: Instant,
+}
+
+/// A cached service instance as listed by the control socket inventory
+#[derive(Debug, Clone, PartialEq)]
+pub struct InventoryEntry {
+    pub instance: Name,
+    pub target: Name,
+    pub port: u16,
+    /// Description of the instance's service type, when the registry has one
+    pub description: Option<String>,
+    /// None until the instance has been probed
+    pub liveness: Option<Liveness>,
+    /// Links a browse saw the instance on; empty if none was attributed
+    pub links: Vec<String>,
+}
+
+/// Last probe result per instance, keyed by the instance name's cache key
+#[derive(Debug, Default)]
+pub struct LivenessTable {
+    entries: RwLock<HashMap<String, Liveness>>,
+}
+
+impl LivenessTable {
+    pub fn get(&self, instance: &Name) -> Option<Liveness> {
+        self.entries.read().unwrap().get(&names::cache_key(instance)).copied()
+    }
+
+    /// Store a probe result; returns the previous state if it differs
+    pub fn record(&self, instance: &Name, alive: bool) -> Option<bool> {
+        let liveness = Liveness {
+            alive,
+            checked: Instant::now(),
+        };
+        let previous = self.entries.write().unwrap().insert(names::cache_key(instance), liveness);
+        previous.map(|p| p.alive).filter(|&was| was != alive)
+    }
+
+    /// Forget instances that are no longer cached
+    pub fn retain(&self, instances: &[Name]) {
+        let keys: Vec<String> = instances.iter().map(names::cache_key).collect();
+        self.entries.write().unwrap().retain(|key, _| keys.contains(key));
+    }
+
+    pub fn is_dead(&self, instance: &Name) -> bool {
+        self.get(instance).is_some_and(|l| !l.alive)
+    }
+
+    /// Drop SRV records owned by, and PTR records pointing at, dead instances
+    pub fn filter_dead(&self, records: Vec<Record>) -> Vec<Record> {
+        records
+            .into_iter()
+            .filter(|record| match record.data() {
+                RData::SRV(_) => !self.is_dead(record.name()),
+                RData::PTR(ptr) => !self.is_dead(&ptr.0),
+                _ => true,
+            })
+            .collect()
+    }
+}
+
+/// Whether any of `addresses` accepts a TCP connection on `port` within `timeout`
+pub async fn probe(addresses: &[IpAddr], port: u16, timeout: Duration) -> bool {
+    for address in addresses {
+        let connect = TcpStream::connect(SocketAddr::new(*address, port));
+        if let Ok(Ok(_)) = tokio::time::timeout(timeout, connect).await {
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::rdata::{PTR, SRV};
+
+    fn name(name: &str) -> Name {
+        names::name_from_labels_str(name).unwrap()
+    }
+
+    #[test]
+    fn test_filter_dead_drops_srv_and_ptr() {
+        let table = LivenessTable::default();
+        let dead = name("Old Printer._ipp._tcp.mdns.home.arpa.");
+        let alive = name("New Printer._ipp._tcp.mdns.home.arpa.");
+        let service = name("_ipp._tcp.mdns.home.arpa.");
+        let target = name("printer.mdns.home.arpa.");
+        table.record(&dead, false);
+        assert_eq!(table.record(&alive, true), None);
+
+        let records = vec![
+            Record::from_rdata(service.clone(), 10, RData::PTR(PTR(dead.clone()))),
+            Record::from_rdata(service, 10, RData::PTR(PTR(alive.clone()))),
+            Record::from_rdata(dead, 10, RData::SRV(SRV::new(0, 0, 631, target.clone()))),
+            Record::from_rdata(alive.clone(), 10, RData::SRV(SRV::new(0, 0, 631, target))),
+        ];
+        let kept = table.filter_dead(records);
+        assert_eq!(kept.len(), 2);
+        assert!(kept.iter().all(|r| match r.data() {
+            RData::PTR(ptr) => ptr.0 == alive,
+            _ => *r.name() == alive,
+        }));
+    }
+
+    #[test]
+    fn test_record_reports_transitions_and_retain_forgets() {
+        let table = LivenessTable::default();
+        let instance = name("Printer._ipp._tcp.mdns.home.arpa.");
+        assert_eq!(table.record(&instance, true), None);
+        assert_eq!(table.record(&instance, true), None);
+        assert_eq!(table.record(&instance, false), Some(true));
+        assert!(table.is_dead(&instance));
+
+        table.retain(&[]);
+        assert!(table.get(&instance).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_probe_tcp_connect() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let localhost = [IpAddr::from([127, 0, 0, 1])];
+        assert!(probe(&localhost, port, Duration::from_secs(1)).await);
+
+        drop(listener);
+        assert!(!probe(&localhost, port, Duration::from_secs(1)).await);
+        assert!(!probe(&[], port, Duration::from_secs(1)).await);
+    }
+}
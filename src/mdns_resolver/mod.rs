@@ -1,8 +1,36 @@
+pub mod browses;
 mod cache;
+pub mod capabilities;
+mod changes;
+pub mod daemon;
+#[cfg(any(test, feature = "fault-injection"))]
+pub mod faults;
+mod fresh;
+#[cfg(not(feature = "minimal"))]
+pub mod gossip;
+pub mod health;
+mod journal;
+pub mod known;
+pub mod liveness;
+mod names;
+pub mod oneshot;
+mod provenance;
 mod query;
 mod resolver;
+pub mod reverse;
+pub mod service;
+pub mod shared;
+pub mod snapshot;
+pub mod svcb;
+mod txt_size;
+mod wake;
+mod watch;
 
-pub use resolver::MdnsResolver;
+pub use resolver::{mark_fresh, rewrite_records_to_discovery_domain, sort_canonical, MdnsResolver};
+pub use service::{ServiceInstance, TxtValue};
+pub use watch::Watch;
+pub(crate) use names::{classify, from_presentation, presentation, rdata_presentation, NameKind};
+pub(crate) use resolver::MAX_UNICAST_TTL;
 
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,481 @@
+//! Name handling shared by every query path.
+//!
+//! DNS-SD instance labels are arbitrary UTF-8 and may contain spaces, dots and
+//! other bytes that need escaping in presentation format. All conversions
+//! between DNS `Name`s, mdns-sd fullname strings and cache keys go through here
+//! so the SRV, TXT and PTR paths agree on how an instance is spelled.
+
+use hickory_proto::rr::{Name, RData};
+
+type NameResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Presentation form of a name per RFC 1035 Section 5.1: printable ASCII is
+/// kept, '.' and '\' inside a label are backslash-escaped and every other byte
+/// (spaces, UTF-8) becomes a decimal `\DDD` escape.
+pub fn presentation(name: &Name) -> String {
+    let mut out = String::new();
+    for label in name.iter() {
+        out.push_str(&escape_label(label));
+        out.push('.');
+    }
+    if out.is_empty() {
+        out.push('.');
+    }
+    out
+}
+
+/// Record data in presentation form, with any names in it escaped as by
+/// [`presentation`]. hickory's own formatting escapes in octal, which dig and
+/// other RFC 1035 parsers read as decimal, so names it prints do not round-trip.
+pub fn rdata_presentation(rdata: &RData) -> String {
+    match rdata {
+        RData::PTR(ptr) => presentation(&ptr.0),
+        RData::CNAME(cname) => presentation(&cname.0),
+        RData::NS(ns) => presentation(&ns.0),
+        RData::SRV(srv) => format!("{} {} {} {}", srv.priority(), srv.weight(), srv.port(), presentation(srv.target())),
+        other => other.to_string(),
+    }
+}
+
+/// Parse the output of [`presentation`] back into a fully qualified name
+pub fn from_presentation(text: &str) -> NameResult<Name> {
+    let mut labels: Vec<Vec<u8>> = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '.' => {
+                labels.push(unescape_label(&text[start..i]));
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if start < text.len() {
+        labels.push(unescape_label(&text[start..]));
+    }
+    // "." is the root; an empty label anywhere else is an error from_labels reports
+    if labels.len() == 1 && labels[0].is_empty() {
+        return Ok(Name::root());
+    }
+    let mut name = Name::from_labels(labels)?;
+    name.set_fqdn(true);
+    Ok(name)
+}
+
+/// Escape a single raw label for presentation format
+pub fn escape_label(label: &[u8]) -> String {
+    let mut out = String::with_capacity(label.len());
+    for &byte in label {
+        match byte {
+            b'.' | b'\\' | b'"' | b'(' | b')' | b';' | b'@' | b'$' => {
+                out.push('\\');
+                out.push(byte as char);
+            }
+            0x21..=0x7e => out.push(byte as char),
+            _ => out.push_str(&format!("\\{:03}", byte)),
+        }
+    }
+    out
+}
+
+/// Decode presentation escapes (`\DDD` decimal and `\X`) in a label back to raw bytes.
+/// Text without escapes is returned unchanged.
+pub fn unescape_label(label: &str) -> Vec<u8> {
+    let bytes = label.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 1 < bytes.len() {
+            let digits = &bytes[i + 1..bytes.len().min(i + 4)];
+            if digits.len() == 3 && digits.iter().all(u8::is_ascii_digit) {
+                let value = digits.iter().fold(0u32, |acc, d| acc * 10 + (d - b'0') as u32);
+                if value <= 255 {
+                    out.push(value as u8);
+                    i += 4;
+                    continue;
+                }
+            }
+            out.push(bytes[i + 1]);
+            i += 2;
+            continue;
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    out
+}
+
+/// Canonical form of a raw instance label used for comparisons: any Unicode
+/// whitespace folded to a plain space and case folded. Labels are already raw
+/// bytes here; presentation escapes are decoded when text is parsed into a
+/// `Name` ([`from_presentation`], `Name::from_utf8`), never again afterwards,
+/// so a label holding a literal `\032` stays distinct from one holding a space.
+pub fn normalize_instance(label: &[u8]) -> String {
+    String::from_utf8_lossy(label)
+        .chars()
+        .map(|c| if c.is_whitespace() { ' ' } else { c })
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Key under which records for `name` are cached: presentation form of the
+/// normalized labels, so spellings differing only in case or whitespace share an entry.
+pub fn cache_key(name: &Name) -> String {
+    let mut out = String::new();
+    for label in name.iter() {
+        let normalized = normalize_instance(label);
+        out.push_str(&escape_label(normalized.as_bytes()));
+        out.push('.');
+    }
+    if out.is_empty() {
+        out.push('.');
+    }
+    out
+}
+
+/// Index of the `_service` label in a DNS-SD name, i.e. the label preceding `_tcp`/`_udp`
+fn service_label_index(labels: &[&[u8]]) -> Option<usize> {
+    let proto = labels
+        .iter()
+        .rposition(|l| l.eq_ignore_ascii_case(b"_tcp") || l.eq_ignore_ascii_case(b"_udp"))?;
+    if proto == 0 || !labels[proto - 1].starts_with(b"_") {
+        return None;
+    }
+    Some(proto - 1)
+}
+
+/// What a name is by DNS-SD grammar (RFC 6763 Section 7)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameKind {
+    /// No underscore labels, e.g. a host name
+    Host,
+    /// `_service._proto`, a `<subtype>._sub._service._proto` subtype, or the
+    /// `_services._dns-sd._udp` meta-query
+    ServiceType,
+    /// `<instance>._service._proto`
+    ServiceInstance,
+    /// Underscore labels that are not DNS-SD, e.g. `_dmarc` or `_acme-challenge.host`
+    Other,
+}
+
+/// Classify the labels of `name` below `zone`
+pub fn classify(name: &Name, zone: &Name) -> NameKind {
+    let relative = name.num_labels().saturating_sub(zone.num_labels()) as usize;
+    let labels: Vec<&[u8]> = name.iter().take(relative).collect();
+
+    match labels.split_last() {
+        Some((proto, rest)) if proto.eq_ignore_ascii_case(b"_tcp") || proto.eq_ignore_ascii_case(b"_udp") => {
+            match rest.split_last() {
+                Some((service, prefix)) if is_service_name(service) => match prefix {
+                    [] => NameKind::ServiceType,
+                    [_, sub] if sub.eq_ignore_ascii_case(b"_sub") => NameKind::ServiceType,
+                    [meta] if meta.eq_ignore_ascii_case(b"_services") && service.eq_ignore_ascii_case(b"_dns-sd") => {
+                        NameKind::ServiceType
+                    }
+                    _ => NameKind::ServiceInstance,
+                },
+                _ => NameKind::Other,
+            }
+        }
+        _ if labels.iter().any(|l| l.starts_with(b"_")) => NameKind::Other,
+        _ => NameKind::Host,
+    }
+}
+
+/// Whether `label` is `_` followed by a service name per RFC 6335 Section 5.1:
+/// 1-15 letters, digits and hyphens, at least one letter, hyphens neither at
+/// either end nor adjacent
+fn is_service_name(label: &[u8]) -> bool {
+    let Some(service) = label.strip_prefix(b"_") else {
+        return false;
+    };
+    (1..=15).contains(&service.len())
+        && service.iter().all(|b| b.is_ascii_alphanumeric() || *b == b'-')
+        && service.iter().any(u8::is_ascii_alphabetic)
+        && !service.starts_with(b"-")
+        && !service.ends_with(b"-")
+        && !service.windows(2).any(|w| w == b"--")
+}
+
+/// Split a service instance name into its (raw) instance label and the mdns-sd
+/// service type string, e.g. `My Printer` and `_ipp._tcp.local.`.
+/// An instance that arrived as several labels (unescaped dots) is re-joined.
+pub fn split_instance(name: &Name) -> Option<(String, String)> {
+    let labels: Vec<&[u8]> = name.iter().collect();
+    let service_idx = service_label_index(&labels)?;
+    if service_idx == 0 {
+        return None;
+    }
+
+    let instance = labels[..service_idx]
+        .iter()
+        .map(|l| String::from_utf8_lossy(l).into_owned())
+        .collect::<Vec<_>>()
+        .join(".");
+    let service_type = labels[service_idx..]
+        .iter()
+        .map(|l| String::from_utf8_lossy(l).into_owned())
+        .collect::<Vec<_>>()
+        .join(".");
+
+    Some((instance, format!("{}.", service_type)))
+}
+
+/// Whether a queried instance name refers to the mdns-sd instance `fullname`
+/// of type `ty_domain`, regardless of whitespace flavour or case.
+pub fn instance_matches(query: &Name, fullname: &str, ty_domain: &str) -> bool {
+    let Some((query_instance, query_type)) = split_instance(query) else {
+        return false;
+    };
+    if !query_type.eq_ignore_ascii_case(ty_domain) {
+        return false;
+    }
+
+    let Some(instance) = fullname
+        .strip_suffix(ty_domain)
+        .map(|i| i.trim_end_matches('.'))
+    else {
+        return false;
+    };
+
+    normalize_instance(query_instance.as_bytes()) == normalize_instance(instance.as_bytes())
+}
+
+/// Name as the mdns-sd daemon spells it: raw labels joined with dots, fully qualified
+pub fn mdns_string(name: &Name) -> String {
+    let mut out = String::new();
+    for label in name.iter() {
+        out.push_str(&String::from_utf8_lossy(label));
+        out.push('.');
+    }
+    out
+}
+
+/// Split an mdns-sd subtype name such as "_printer._sub._http._tcp.local." into
+/// its subtype label and parent service type ("_printer", "_http._tcp.local.")
+pub fn split_subtype(ty_domain: &str) -> Option<(&str, &str)> {
+    let idx = ty_domain.to_ascii_lowercase().find("._sub.")?;
+    let (subtype, parent) = (&ty_domain[..idx], &ty_domain[idx + "._sub.".len()..]);
+    (!subtype.is_empty() && !parent.is_empty()).then_some((subtype, parent))
+}
+
+/// Build a DNS name for an mdns-sd service instance. The instance portion of
+/// `fullname` becomes a single raw label (spaces, dots, backslashes and UTF-8
+/// preserved on the wire, as mdns-sd spells them unescaped); the service type
+/// labels follow unchanged.
+pub fn instance_name(fullname: &str, ty_domain: &str) -> NameResult<Name> {
+    let instance = fullname
+        .strip_suffix(ty_domain)
+        .map(|i| i.trim_end_matches('.'))
+        .filter(|i| !i.is_empty());
+
+    match instance {
+        Some(instance) => {
+            let ty = name_from_labels_str(ty_domain)?;
+            Ok(ty.prepend_label(instance.as_bytes())?)
+        }
+        None => name_from_labels_str(fullname),
+    }
+}
+
+/// Build a DNS Name from raw labels, permitting spaces by constructing Labels from bytes.
+pub fn name_from_labels_str(fullname: &str) -> NameResult<Name> {
+    let labels: Vec<&[u8]> = fullname
+        .split('.')
+        .filter(|s| !s.is_empty())
+        .map(str::as_bytes)
+        .collect();
+    Ok(Name::from_labels(labels)?)
+}
+
+/// Replace the `zone` suffix of `name` with `new_zone`, keeping the leading labels as raw bytes.
+/// Returns None when `name` is not within `zone`.
+pub fn replace_zone(name: &Name, zone: &Name, new_zone: &Name) -> NameResult<Option<Name>> {
+    if !zone.zone_of(name) {
+        return Ok(None);
+    }
+    let prefix = (name.num_labels() - zone.num_labels()) as usize;
+    let labels: Vec<&[u8]> = name
+        .iter()
+        .take(prefix)
+        .chain(new_zone.iter())
+        .collect();
+    Ok(Some(Name::from_labels(labels)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raw(labels: &[&str]) -> Name {
+        Name::from_labels(labels.iter().map(|l| l.as_bytes())).unwrap()
+    }
+
+    #[test]
+    fn test_presentation_escapes_space_and_utf8_decimal() {
+        let name = raw(&["My Printer", "_ipp", "_tcp", "local"]);
+        assert_eq!(presentation(&name), "My\\032Printer._ipp._tcp.local.");
+
+        let name = raw(&["Café", "_http", "_tcp", "local"]);
+        assert_eq!(presentation(&name), "Caf\\195\\169._http._tcp.local.");
+
+        let name = raw(&["a.b", "_http", "_tcp", "local"]);
+        assert_eq!(presentation(&name), "a\\.b._http._tcp.local.");
+    }
+
+    #[test]
+    fn test_from_presentation_roundtrip() {
+        for labels in [&["My Printer", "_ipp", "_tcp", "local"][..], &["a.b", "_http", "_tcp", "local"], &["Café"]] {
+            let name = raw(labels);
+            assert_eq!(from_presentation(&presentation(&name)).unwrap().to_utf8(), name.to_utf8());
+        }
+        assert!(from_presentation(".").unwrap().is_root());
+        assert!(from_presentation("printer.local").unwrap().is_fqdn());
+        assert!(from_presentation("a..b.").is_err());
+    }
+
+    #[test]
+    fn test_unescape_label_roundtrip() {
+        assert_eq!(unescape_label("My\\032Printer"), b"My Printer");
+        assert_eq!(unescape_label("a\\.b"), b"a.b");
+        assert_eq!(unescape_label("plain"), b"plain");
+        assert_eq!(unescape_label(&escape_label("Café (2)".as_bytes())), "Café (2)".as_bytes());
+    }
+
+    #[test]
+    fn test_normalize_instance_forms_agree() {
+        let expected = normalize_instance(b"my printer");
+        assert_eq!(normalize_instance(&unescape_label("My\\032Printer")), expected);
+        assert_eq!(normalize_instance(b"My Printer"), expected);
+        assert_eq!(normalize_instance("My\u{a0}Printer".as_bytes()), expected);
+        // Raw labels are not unescaped a second time
+        assert_ne!(normalize_instance(b"My\\032Printer"), expected);
+    }
+
+    #[test]
+    fn test_split_instance() {
+        let name = raw(&["My Printer", "_ipp", "_tcp", "local"]);
+        let (instance, ty) = split_instance(&name).unwrap();
+        assert_eq!(instance, "My Printer");
+        assert_eq!(ty, "_ipp._tcp.local.");
+
+        // Instance containing an unescaped dot arrives as two labels
+        let name = raw(&["v1", "2", "_http", "_tcp", "local"]);
+        assert_eq!(split_instance(&name).unwrap().0, "v1.2");
+
+        assert!(split_instance(&raw(&["_ipp", "_tcp", "local"])).is_none());
+        assert!(split_instance(&raw(&["host", "local"])).is_none());
+    }
+
+    #[test]
+    fn test_split_subtype() {
+        assert_eq!(
+            split_subtype("_universal._SUB._ipp._tcp.local."),
+            Some(("_universal", "_ipp._tcp.local."))
+        );
+        assert_eq!(split_subtype("_ipp._tcp.local."), None);
+        assert_eq!(split_subtype("._sub._ipp._tcp.local."), None);
+    }
+
+    #[test]
+    fn test_classify_dns_sd_grammar() {
+        let zone = Name::from_utf8("mdns.home.arpa.").unwrap();
+        let kind = |name: &str| classify(&name_from_labels_str(name).unwrap(), &zone);
+
+        assert_eq!(kind("printer.mdns.home.arpa."), NameKind::Host);
+        assert_eq!(kind("_ipp._tcp.mdns.home.arpa."), NameKind::ServiceType);
+        assert_eq!(kind("_printer._sub._http._tcp.mdns.home.arpa."), NameKind::ServiceType);
+        assert_eq!(kind("_services._dns-sd._udp.mdns.home.arpa."), NameKind::ServiceType);
+        assert_eq!(kind("Office Printer._ipp._tcp.mdns.home.arpa."), NameKind::ServiceInstance);
+        assert_eq!(kind("My.Printer._ipp._tcp.mdns.home.arpa."), NameKind::ServiceInstance);
+
+        assert_eq!(kind("_dmarc.mdns.home.arpa."), NameKind::Other);
+        assert_eq!(kind("_acme-challenge.printer.mdns.home.arpa."), NameKind::Other);
+        assert_eq!(kind("_tcp.mdns.home.arpa."), NameKind::Other);
+        assert_eq!(kind("_sip._tls.mdns.home.arpa."), NameKind::Other);
+        assert_eq!(kind("_this-is-far-too-long._tcp.mdns.home.arpa."), NameKind::Other);
+        assert_eq!(kind("_123._tcp.mdns.home.arpa."), NameKind::Other);
+        assert_eq!(kind("_bad--name._tcp.mdns.home.arpa."), NameKind::Other);
+        assert_eq!(kind("x._tcp.mdns.home.arpa."), NameKind::Other);
+    }
+
+    #[test]
+    fn test_instance_matches_escaped_and_raw_queries() {
+        let fullname = "My Printer._ipp._tcp.local.";
+        let ty = "_ipp._tcp.local.";
+
+        assert!(instance_matches(&raw(&["My Printer", "_ipp", "_tcp", "local"]), fullname, ty));
+        assert!(instance_matches(&from_presentation("my\\032printer._ipp._tcp.local.").unwrap(), fullname, ty));
+        assert!(!instance_matches(&raw(&["my\\032printer", "_ipp", "_tcp", "local"]), fullname, ty));
+        assert!(instance_matches(&raw(&["MY PRINTER", "_IPP", "_TCP", "local"]), fullname, ty));
+        assert!(!instance_matches(&raw(&["Other", "_ipp", "_tcp", "local"]), fullname, ty));
+        assert!(!instance_matches(&raw(&["My Printer", "_http", "_tcp", "local"]), fullname, ty));
+    }
+
+    #[test]
+    fn test_instance_name_keeps_dots_in_single_label() {
+        let name = instance_name("Office v1.2._http._tcp.local.", "_http._tcp.local.").unwrap();
+        assert_eq!(name.num_labels(), 4);
+        assert_eq!(name.iter().next().unwrap(), b"Office v1.2");
+    }
+
+    #[test]
+    fn test_instance_name_is_raw_on_the_wire_and_escaped_in_presentation() {
+        use hickory_proto::op::Message;
+        use hickory_proto::rr::rdata::PTR;
+        use hickory_proto::rr::Record;
+
+        let ty = "_http._tcp.local.";
+        // The presentation forms are as dig prints them
+        let cases = [
+            ("Office v1.2", "Office\\032v1\\.2._http._tcp.local."),
+            ("Café", "Caf\\195\\169._http._tcp.local."),
+            ("A\\032B", "A\\\\032B._http._tcp.local."),
+        ];
+        for (instance, expected) in cases {
+            let target = instance_name(&format!("{}.{}", instance, ty), ty).unwrap();
+            assert_eq!(target.iter().next().unwrap(), instance.as_bytes());
+            assert_eq!(presentation(&target), expected);
+            assert_eq!(from_presentation(expected).unwrap(), target);
+
+            // Through the wire and hickory's parser, the label bytes come back unchanged
+            let mut message = Message::new();
+            let owner = name_from_labels_str(ty).unwrap();
+            message.add_answer(Record::from_rdata(owner, 120, RData::PTR(PTR(target.clone()))));
+            let decoded = Message::from_vec(&message.to_vec().unwrap()).unwrap();
+            let RData::PTR(ptr) = decoded.answers()[0].data() else { panic!("not a PTR") };
+            assert_eq!(ptr.0.iter().next().unwrap(), instance.as_bytes());
+            assert_eq!(rdata_presentation(decoded.answers()[0].data()), expected);
+        }
+    }
+
+    #[test]
+    fn test_cache_key_is_case_and_escape_insensitive() {
+        let a = raw(&["My Printer", "_ipp", "_tcp", "local"]);
+        let b = raw(&["my printer", "_IPP", "_tcp", "LOCAL"]);
+        let c = from_presentation("My\\032Printer._ipp._tcp.local.").unwrap();
+        assert_eq!(cache_key(&a), cache_key(&b));
+        assert_eq!(cache_key(&a), cache_key(&c));
+
+        // A label that really holds a backslash is a different name
+        let literal = raw(&["My\\032Printer", "_ipp", "_tcp", "local"]);
+        assert_ne!(cache_key(&a), cache_key(&literal));
+        assert_eq!(cache_key(&literal), "my\\\\032printer._ipp._tcp.local.");
+    }
+
+    #[test]
+    fn test_replace_zone() {
+        let zone = Name::from_utf8("mdns.home.arpa.").unwrap();
+        let local = Name::from_utf8("local.").unwrap();
+        let name = raw(&["My Printer", "_ipp", "_tcp", "mdns", "home", "arpa"]);
+
+        let mapped = replace_zone(&name, &zone, &local).unwrap().unwrap();
+        assert_eq!(mdns_string(&mapped), "My Printer._ipp._tcp.local.");
+
+        let outside = Name::from_utf8("example.com.").unwrap();
+        assert!(replace_zone(&outside, &zone, &local).unwrap().is_none());
+    }
+}
@@ -0,0 +1,160 @@
+//! One-shot mDNS queries
+//!
+//! mdns-sd only asks for what browsing and host resolution need and drops
+//! records of other types, so questions it cannot ask (reverse PTR, CNAME) go
+//! to the mDNS group from an ephemeral port instead. Responders answer such
+//! legacy unicast queries directly to the sender (RFC 6762 Section 6.7).
+//!
+//! The IPv6 group is link-scoped, so an IPv6 query is sent once per interface
+//! with an IPv6 link-local address, each time scoped to that interface.
+
+use crate::random::random_u64;
+use hickory_proto::op::{Message, MessageType, Query};
+use hickory_proto::rr::{Name, Record, RecordType};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tracing::debug;
+
+const MDNS_V4: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(224, 0, 0, 251)), 5353);
+const MDNS_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+
+/// Indexes of the interfaces that are up with an IPv6 link-local address,
+/// limited to `interfaces` (by name) unless it is empty
+pub fn ipv6_scopes(interfaces: &[String]) -> std::io::Result<Vec<u32>> {
+    let mut scopes: Vec<u32> = if_addrs::get_if_addrs()?
+        .into_iter()
+        .filter(|interface| interface.is_oper_up() && !interface.is_loopback())
+        .filter(|interface| interfaces.is_empty() || interfaces.contains(&interface.name))
+        .filter(|interface| matches!(interface.ip(), IpAddr::V6(v6) if v6.is_unicast_link_local()))
+        .filter_map(|interface| interface.index)
+        .collect();
+    scopes.sort_unstable();
+    scopes.dedup();
+    Ok(scopes)
+}
+
+/// The IPv4 group, or with `ipv6` the IPv6 group on each of `scopes`
+pub fn destinations(ipv6: bool, scopes: &[u32]) -> Vec<SocketAddr> {
+    if ipv6 {
+        scopes.iter().map(|&scope| SocketAddrV6::new(MDNS_V6, 5353, 0, scope).into()).collect()
+    } else {
+        vec![MDNS_V4]
+    }
+}
+
+/// Ask `destinations` (all of one address family) for the `record_type`
+/// records of `name`, collecting unicast answers until `timeout`; returns
+/// those of the first response that has any
+pub async fn query(
+    name: &Name,
+    record_type: RecordType,
+    destinations: &[SocketAddr],
+    timeout: Duration,
+) -> std::io::Result<Vec<Record>> {
+    let bind: SocketAddr = match destinations.first() {
+        Some(SocketAddr::V6(_)) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        _ => (Ipv4Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    let mut message = Message::new();
+    // Legacy unicast queries carry an ID, which responders echo
+    message.set_id(random_u64() as u16).add_query(Query::query(name.clone(), record_type));
+    let bytes = message.to_vec()?;
+    let mut sent = 0;
+    let mut last_error = None;
+    for &destination in destinations {
+        match socket.send_to(&bytes, destination).await {
+            Ok(_) => sent += 1,
+            Err(e) => {
+                debug!("One-shot mDNS query to {} failed: {}", destination, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    if sent == 0 {
+        return Err(last_error.unwrap_or_else(|| std::io::Error::other("no interface to send the query on")));
+    }
+
+    let mut buf = [0u8; 9000];
+    let deadline = tokio::time::Instant::now() + timeout;
+    while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+        let (len, from) = received?;
+        let Ok(response) = Message::from_vec(&buf[..len]) else {
+            debug!("Ignoring undecodable one-shot mDNS answer from {}", from);
+            continue;
+        };
+        if response.message_type() != MessageType::Response {
+            continue;
+        }
+        let answers: Vec<Record> = response
+            .answers()
+            .iter()
+            .filter(|record| record.record_type() == record_type && record.name() == name)
+            .cloned()
+            .collect();
+        // A name has one owner on the link, so its answer is the answer
+        if !answers.is_empty() {
+            return Ok(answers);
+        }
+    }
+    Ok(Vec::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::RData;
+    use hickory_proto::rr::rdata::CNAME;
+
+    #[tokio::test]
+    async fn test_query_collects_unicast_answer() {
+        // A stand-in responder on loopback answers with one matching and one unrelated record
+        let responder = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let destination = responder.local_addr().unwrap();
+        let alias = Name::from_utf8("www.local.").unwrap();
+        let answer = {
+            let alias = alias.clone();
+            tokio::spawn(async move {
+                let mut buf = [0u8; 512];
+                let (len, from) = responder.recv_from(&mut buf).await.unwrap();
+                let query = Message::from_vec(&buf[..len]).unwrap();
+                let mut response = Message::new();
+                response.set_id(query.id()).set_message_type(MessageType::Response);
+                let target = Name::from_utf8("nas.local.").unwrap();
+                response.add_answer(Record::from_rdata(alias, 10, RData::CNAME(CNAME(target))));
+                response.add_answer(Record::from_rdata(Name::from_utf8("other.local.").unwrap(), 10, RData::CNAME(CNAME(Name::root()))));
+                responder.send_to(&response.to_vec().unwrap(), from).await.unwrap();
+            })
+        };
+        let records = query(&alias, RecordType::CNAME, &[destination], Duration::from_secs(2)).await.unwrap();
+        answer.await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].data(), &RData::CNAME(CNAME(Name::from_utf8("nas.local.").unwrap())));
+
+        // The IPv6 group is addressed once per interface, scoped to it
+        assert_eq!(destinations(false, &[2, 3]), vec![MDNS_V4]);
+        assert_eq!(
+            destinations(true, &[2, 3]),
+            vec!["[ff02::fb%2]:5353".parse::<SocketAddr>().unwrap(), "[ff02::fb%3]:5353".parse().unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_ipv6_scopes_follow_configured_interfaces() {
+        let all = ipv6_scopes(&[]).unwrap();
+        assert!(all.is_sorted());
+        assert_eq!(ipv6_scopes(&["no-such-interface0".to_string()]).unwrap(), Vec::<u32>::new());
+
+        // Each configured interface yields at most its own scope, and only one
+        // found without a filter
+        for interface in if_addrs::get_if_addrs().unwrap() {
+            let scopes = ipv6_scopes(std::slice::from_ref(&interface.name)).unwrap();
+            assert!(scopes.len() <= 1, "{}: {:?}", interface.name, scopes);
+            assert!(scopes.iter().all(|scope| all.contains(scope) && Some(*scope) == interface.index));
+            if interface.is_loopback() {
+                assert!(scopes.is_empty());
+            }
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! Links each service instance was seen on
+//!
+//! With several links under one discovery zone, a browse lists the instances
+//! of every link, and a device announcing itself on two of them is still one
+//! instance (see `query::merge_instance`). This table keeps, per instance, the
+//! links whose networks hold its addresses, attributed as
+//! [`LinkHealth`](super::health::LinkHealth) does, and whether all of those
+//! addresses are link-local. An instance reachable only at link-local addresses
+//! is of no use to a client on another link, so its PTR, SRV and TXT records
+//! are left out of that client's answers (RFC 8766 Section 5.5.2). An instance
+//! not browsed for longer than the cache TTL is forgotten.
+
+use hickory_proto::rr::{Name, RData, Record};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::names;
+
+#[derive(Debug)]
+struct SeenOn {
+    /// Interface names, sorted
+    links: Vec<String>,
+    link_local_only: bool,
+    last_seen: Instant,
+}
+
+/// Links per instance, keyed by the instance name's cache key
+#[derive(Debug, Default)]
+pub struct InstanceLinks {
+    entries: Mutex<HashMap<String, SeenOn>>,
+}
+
+impl InstanceLinks {
+    /// Note that `instance` was seen on `links`; an instance seen again within
+    /// `max_age` keeps the links it was seen on before
+    pub fn record(&self, instance: &Name, links: Vec<String>, link_local_only: bool, max_age: Duration) {
+        self.record_at(instance, links, link_local_only, max_age, Instant::now());
+    }
+
+    fn record_at(&self, instance: &Name, links: Vec<String>, link_local_only: bool, max_age: Duration, now: Instant) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| now.saturating_duration_since(entry.last_seen) <= max_age);
+        let entry = entries.entry(names::cache_key(instance)).or_insert_with(|| SeenOn {
+            links: Vec::new(),
+            link_local_only: true,
+            last_seen: now,
+        });
+        entry.links.extend(links);
+        entry.links.sort();
+        entry.links.dedup();
+        entry.link_local_only &= link_local_only;
+        entry.last_seen = now;
+    }
+
+    /// Links `instance` was seen on; empty if it was not browsed or is on no known link
+    pub fn links(&self, instance: &Name) -> Vec<String> {
+        self.entries
+            .lock()
+            .unwrap()
+            .get(&names::cache_key(instance))
+            .map(|entry| entry.links.clone())
+            .unwrap_or_default()
+    }
+
+    /// Whether a client on `client_link` (None: on none of the links) can reach `instance`
+    pub fn usable_from(&self, instance: &Name, client_link: Option<&str>) -> bool {
+        match self.entries.lock().unwrap().get(&names::cache_key(instance)) {
+            Some(entry) if entry.link_local_only => {
+                client_link.is_some_and(|link| entry.links.iter().any(|l| l == link))
+            }
+            _ => true,
+        }
+    }
+
+    /// Drop the PTR records pointing at, and the records owned by, instances a
+    /// client on `client_link` cannot reach
+    pub fn filter(&self, records: Vec<Record>, client_link: Option<&str>) -> Vec<Record> {
+        records
+            .into_iter()
+            .filter(|record| {
+                let instance = match record.data() {
+                    RData::PTR(ptr) => &ptr.0,
+                    _ => record.name(),
+                };
+                self.usable_from(instance, client_link)
+            })
+            .collect()
+    }
+}
+
+/// Whether every address in `addresses` is link-local; false when there are none
+pub fn link_local_only(addresses: impl IntoIterator<Item = IpAddr>) -> bool {
+    let mut any = false;
+    for addr in addresses {
+        let link_local = match addr {
+            IpAddr::V4(v4) => v4.is_link_local(),
+            IpAddr::V6(v6) => v6.is_unicast_link_local(),
+        };
+        if !link_local {
+            return false;
+        }
+        any = true;
+    }
+    any
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::rdata::PTR;
+
+    fn name(s: &str) -> Name {
+        Name::from_utf8(s).unwrap()
+    }
+
+    fn links(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn test_links_merge_and_expire() {
+        let table = InstanceLinks::default();
+        let start = Instant::now();
+        let max_age = Duration::from_secs(60);
+        let printer = name("Printer._ipp._tcp.mdns.home.arpa.");
+
+        table.record_at(&printer, links(&["eth1"]), false, max_age, start);
+        table.record_at(&name("printer._IPP._tcp.mdns.home.arpa."), links(&["eth0"]), false, max_age, start);
+        assert_eq!(table.links(&printer), ["eth0", "eth1"]);
+
+        // Another instance browsed long after: the printer is forgotten
+        let scanner = name("Scanner._uscan._tcp.mdns.home.arpa.");
+        table.record_at(&scanner, links(&["eth0"]), false, max_age, start + Duration::from_secs(120));
+        assert!(table.links(&printer).is_empty());
+        assert_eq!(table.links(&scanner), ["eth0"]);
+    }
+
+    #[test]
+    fn test_link_local_instances_hidden_from_other_links() {
+        let table = InstanceLinks::default();
+        let max_age = Duration::from_secs(60);
+        let sensor = name("Sensor._hap._tcp.mdns.home.arpa.");
+        let printer = name("Printer._ipp._tcp.mdns.home.arpa.");
+        table.record(&sensor, links(&["eth1"]), true, max_age);
+        table.record(&printer, links(&["eth1"]), false, max_age);
+
+        let browse = name("_hap._tcp.mdns.home.arpa.");
+        let records = vec![
+            Record::from_rdata(browse.clone(), 10, RData::PTR(PTR(sensor.clone()))),
+            Record::from_rdata(name("_ipp._tcp.mdns.home.arpa."), 10, RData::PTR(PTR(printer.clone()))),
+            Record::from_rdata(browse, 10, RData::PTR(PTR(name("Unknown._hap._tcp.mdns.home.arpa.")))),
+        ];
+        assert_eq!(table.filter(records.clone(), Some("eth1")).len(), 3);
+        let elsewhere = table.filter(records.clone(), Some("eth0"));
+        assert_eq!(elsewhere.len(), 2);
+        assert!(elsewhere.iter().all(|r| r.data() != records[0].data()));
+        assert_eq!(table.filter(records, None).len(), 2);
+
+        // Seen with a routable address too, it is reachable from anywhere
+        table.record(&sensor, links(&["eth2"]), false, max_age);
+        assert!(table.usable_from(&sensor, Some("eth0")));
+        assert_eq!(table.links(&sensor), ["eth1", "eth2"]);
+    }
+
+    #[test]
+    fn test_link_local_only() {
+        let addr = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert!(link_local_only([addr("169.254.7.1"), addr("fe80::1")]));
+        assert!(!link_local_only([addr("fe80::1"), addr("192.168.1.20")]));
+        assert!(!link_local_only([]));
+    }
+}
@@ -1,17 +1,26 @@
-use crate::config::Config;
-use hickory_proto::rr::{domain::Label, Name, RData, Record};
-use mdns_sd::{HostnameResolutionEvent, ServiceDaemon, ServiceEvent};
+use crate::config::{Config, ServiceRecordKind};
+use hickory_proto::rr::{Name, RData, Record};
+use mdns_sd::{HostnameResolutionEvent, ResolvedService, ServiceDaemon, ServiceEvent};
 use tokio::time::timeout;
 use tracing::{debug, error, info};
 
+use super::browses::Browses;
+use super::names::{self, name_from_labels_str, NameKind};
+
+/// Answer records for a service query along with the resolved instances they came from
+#[derive(Default)]
+pub struct ServiceAnswer {
+    pub records: Vec<Record>,
+    pub instances: Vec<ResolvedService>,
+}
+
 /// Query for A records (IPv4)
 pub async fn query_a_aaaa(
     daemon: &ServiceDaemon,
     name: &Name,
     config: &Config,
 ) -> Result<Vec<Record>, Box<dyn std::error::Error + Send + Sync>> {
-    let hostname = name.to_utf8().to_lowercase();
-    let hostname_unescaped = unescape_instance_label(&hostname);
+    let hostname = names::mdns_string(name).to_lowercase();
 
     // Check if this is a .local query
     if hostname.strip_suffix(".").unwrap_or(&hostname).split(".").last().unwrap_or("") != "local" {
@@ -19,24 +28,35 @@ pub async fn query_a_aaaa(
     }
 
     // Try to resolve as a service instance or hostname
-    resolve_hostname(daemon, &hostname_unescaped, config).await
+    resolve_hostname(daemon, &hostname, config).await
 }
 
 /// Query for PTR records (service enumeration)
 pub async fn query_ptr(
     daemon: &ServiceDaemon,
+    browses: &Browses,
     name: &Name,
     config: &Config,
-) -> Result<Vec<Record>, Box<dyn std::error::Error + Send + Sync>> {
-    let service_type = name.to_utf8();
+) -> Result<ServiceAnswer, Box<dyn std::error::Error + Send + Sync>> {
+    if !has_dns_sd_shape(name, NameKind::ServiceType) {
+        return Ok(ServiceAnswer::default());
+    }
+    let service_type = names::mdns_string(name);
+
+    // Subtype (RFC 6763 Section 7.1): not every responder answers a subtype
+    // query with subtype records, so browse the parent type as well and keep
+    // the instances the daemon knows to carry the subtype
+    let parent_type = names::split_subtype(&service_type).map(|(_, parent)| parent.to_string());
 
     debug!("Browsing for service type: {}", service_type);
 
-    let receiver = daemon.browse(&service_type)?;
+    let mut receiver = browses.browse(daemon, &service_type)?;
+    let mut parent_receiver = parent_type.as_deref().map(|parent| browses.browse(daemon, parent)).transpose()?;
     let mut records = Vec::new();
+    let mut instances: Vec<ResolvedService> = Vec::new();
 
     // Wait for service discovery events with timeout
-    let timeout_duration = config.service_query_timeout();
+    let timeout_duration = config.service_query_timeout_for(&service_type);
     let poll_interval = config.service_poll_interval();
     let start = std::time::Instant::now();
 
@@ -45,15 +65,48 @@ pub async fn query_ptr(
             break;
         }
 
-        match timeout(poll_interval, receiver.recv_async()).await {
-            Ok(Ok(event)) => {
+        let next_event = async {
+            match &mut parent_receiver {
+                Some(parent_receiver) => tokio::select! {
+                    event = receiver.recv() => event,
+                    event = parent_receiver.recv() => event,
+                },
+                None => receiver.recv().await,
+            }
+        };
+
+        match timeout(poll_interval, next_event).await {
+            Ok(Some(event)) => {
                 match event {
-                    ServiceEvent::ServiceResolved(info) => {
-                        info!("Discovered service: {}", info.get_fullname());
+                    ServiceEvent::ServiceResolved(mut info) => {
+                        if let Some(parent_type) = &parent_type {
+                            let is_member = info.ty_domain.eq_ignore_ascii_case(&service_type)
+                                || info
+                                    .get_subtype()
+                                    .as_ref()
+                                    .is_some_and(|subtype| subtype.eq_ignore_ascii_case(&service_type));
+                            if !is_member {
+                                continue;
+                            }
+                            // Instances are named under the parent type
+                            info.sub_ty_domain = Some(service_type.clone());
+                            info.ty_domain = parent_type.clone();
+                        }
+
+                        // The same instance resolved again, e.g. on another link, adds addresses only
+                        if !merge_instance(&mut instances, &info) {
+                            debug!("Merged addresses of {} seen again", info.get_fullname());
+                            continue;
+                        }
+
+                        match crate::service_types::registry().describe(&info.ty_domain) {
+                            Some(description) => info!("Discovered service: {} ({})", info.get_fullname(), description),
+                            None => info!("Discovered service: {}", info.get_fullname()),
+                        }
 
                         // Create PTR record
-                        let ptr_name = Name::from_utf8(&service_type)?;
-                        let target_name = name_from_labels_str(info.get_fullname())?;
+                        let ptr_name = name.clone();
+                        let target_name = names::instance_name(info.get_fullname(), &info.ty_domain)?;
 
                         let record = Record::from_rdata(
                             ptr_name,
@@ -75,8 +128,8 @@ pub async fn query_ptr(
                     _ => {}
                 }
             }
-            Ok(Err(e)) => {
-                error!("Error receiving mDNS event: {}", e);
+            Ok(None) => {
+                error!("mDNS browse for {} ended", service_type);
                 break;
             }
             Err(_) => {
@@ -86,36 +139,49 @@ pub async fn query_ptr(
         }
     }
 
-    Ok(records)
+    Ok(ServiceAnswer { records, instances })
+}
+
+/// Add `info` to `instances`, or, if an instance of that name is already
+/// listed, add its addresses to that one. True if the instance is new.
+pub fn merge_instance(instances: &mut Vec<ResolvedService>, info: &ResolvedService) -> bool {
+    match instances.iter_mut().find(|i| i.fullname.eq_ignore_ascii_case(&info.fullname)) {
+        Some(known) => {
+            known.addresses.extend(info.addresses.iter().cloned());
+            false
+        }
+        None => {
+            instances.push(info.clone());
+            true
+        }
+    }
 }
 
 /// Query for SRV records (service location)
 pub async fn query_srv(
     daemon: &ServiceDaemon,
+    browses: &Browses,
     name: &Name,
     config: &Config,
-) -> Result<Vec<Record>, Box<dyn std::error::Error + Send + Sync>> {
-    let service_name = name.to_utf8();
-
-    debug!("Resolving SRV for: {}", service_name);
-
-    // Extract service type from full name
-    // Format: instance._service._tcp.local.
-    // We need to get _service._tcp.local. from instance._service._tcp.local.
-    let parts: Vec<&str> = service_name.split('.').collect();
-    if parts.len() < 4 {
-        return Ok(Vec::new());
+) -> Result<ServiceAnswer, Box<dyn std::error::Error + Send + Sync>> {
+    debug!("Resolving SRV for: {}", names::presentation(name));
+    if !has_dns_sd_shape(name, NameKind::ServiceInstance) {
+        return Ok(ServiceAnswer::default());
     }
 
-    // Skip instance name (first part) and reconstruct service type
-    let service_type = parts[1..].join(".");
+    // Format: instance._service._tcp.local.
+    // The instance label may contain spaces, dots or escapes; the service type follows it
+    let Some((_, service_type)) = names::split_instance(name) else {
+        return Ok(ServiceAnswer { records: Vec::new(), instances: Vec::new() });
+    };
 
     debug!("Browsing for service type: {}", service_type);
 
-    let receiver = daemon.browse(&service_type)?;
+    let mut receiver = browses.browse(daemon, &service_type)?;
     let mut records = Vec::new();
+    let mut instances = Vec::new();
 
-    let timeout_duration = config.service_query_timeout();
+    let timeout_duration = config.service_query_timeout_for(&service_type);
     let poll_interval = config.service_poll_interval();
     let start = std::time::Instant::now();
 
@@ -125,72 +191,47 @@ pub async fn query_srv(
             break;
         }
 
-        match timeout(poll_interval, receiver.recv_async()).await {
-            Ok(Ok(ServiceEvent::ServiceResolved(info))) => {
-                // Compare case-insensitively and normalize escaped/unescaped instance labels
-                let unescaped_query = unescape_instance_label(&service_name).to_lowercase();
-                let escaped_query = escape_instance_label(&unescaped_query);
-
-                let info_fullname = info.get_fullname();
-                let info_fullname_lc = info_fullname.to_lowercase();
-                let escaped_info_fullname = escape_instance_label(&info_fullname_lc);
-
-                debug!("Comparing queried service name '{}' (escaped: '{}') with resolved service name '{}' (escaped: '{}')",
-                    unescaped_query, escaped_query, info_fullname_lc, escaped_info_fullname);
-
-                if info_fullname_lc == unescaped_query || escaped_info_fullname == escaped_query {
-                    let srv_name = name.clone();
-                    let target = Name::from_utf8(info.get_hostname())?;
-
-                    let record = Record::from_rdata(
-                        srv_name,
-                        120,
-                        RData::SRV(hickory_proto::rr::rdata::SRV::new(
-                            0,               // priority
-                            0,               // weight
-                            info.get_port(), // port
-                            target,          // target hostname
-                        )),
-                    );
-
-                    records.push(record);
-                    break;
-                }
+        match timeout(poll_interval, receiver.recv()).await {
+            // Compare independent of escaping and case
+            Ok(Some(ServiceEvent::ServiceResolved(info)))
+                if names::instance_matches(name, info.get_fullname(), &info.ty_domain) =>
+            {
+                records.push(srv_record(name.clone(), &info)?);
+                instances.push(*info);
+                break;
             }
-            Ok(Ok(ServiceEvent::SearchStopped(_))) => break,
-            Ok(Err(_)) => break,
+            Ok(Some(ServiceEvent::SearchStopped(_))) => break,
+            Ok(None) => break,
             Err(_) => continue, // Timeout, try again
             _ => {}
         }
     }
 
-    Ok(records)
+    Ok(ServiceAnswer { records, instances })
 }
 
 /// Query for TXT records
 pub async fn query_txt(
     daemon: &ServiceDaemon,
+    browses: &Browses,
     name: &Name,
     config: &Config,
-) -> Result<Vec<Record>, Box<dyn std::error::Error + Send + Sync>> {
-    let service_name = name.to_utf8();
-
-    debug!("Resolving TXT for: {}", service_name);
-
-    // Extract service type from full name
-    // Format: instance._service._tcp.local.
-    let parts: Vec<&str> = service_name.split('.').collect();
-    if parts.len() < 4 {
-        return Ok(Vec::new());
+) -> Result<ServiceAnswer, Box<dyn std::error::Error + Send + Sync>> {
+    debug!("Resolving TXT for: {}", names::presentation(name));
+    if !has_dns_sd_shape(name, NameKind::ServiceInstance) {
+        return Ok(ServiceAnswer::default());
     }
 
-    // Skip instance name (first part) and reconstruct service type
-    let service_type = parts[1..].join(".");
+    // Format: instance._service._tcp.local.
+    let Some((_, service_type)) = names::split_instance(name) else {
+        return Ok(ServiceAnswer { records: Vec::new(), instances: Vec::new() });
+    };
 
-    let receiver = daemon.browse(&service_type)?;
+    let mut receiver = browses.browse(daemon, &service_type)?;
     let mut records = Vec::new();
+    let mut instances = Vec::new();
 
-    let timeout_duration = config.service_query_timeout();
+    let timeout_duration = config.service_query_timeout_for(&service_type);
     let poll_interval = config.service_poll_interval();
     let start = std::time::Instant::now();
 
@@ -200,41 +241,103 @@ pub async fn query_txt(
             break;
         }
 
-        match timeout(poll_interval, receiver.recv_async()).await {
-            Ok(Ok(ServiceEvent::ServiceResolved(info))) => {
-                // Compare case-insensitively and normalize escaped/unescaped instance labels
-                let unescaped_query = unescape_instance_label(&service_name).to_lowercase();
-                let escaped_query = escape_instance_label(&unescaped_query);
+        match timeout(poll_interval, receiver.recv()).await {
+            // Compare independent of escaping and case
+            Ok(Some(ServiceEvent::ServiceResolved(info)))
+                if names::instance_matches(name, info.get_fullname(), &info.ty_domain) =>
+            {
+                if let Some(record) = txt_record(name.clone(), &info) {
+                    records.push(record);
+                }
+                instances.push(*info);
+                break;
+            }
+            Ok(Some(ServiceEvent::SearchStopped(_))) => break,
+            Ok(None) => break,
+            Err(_) => continue, // Timeout, try again
+            _ => {}
+        }
+    }
 
-                let info_fullname = info.get_fullname();
-                let info_fullname_lc = info_fullname.to_lowercase();
-                let escaped_info_fullname = escape_instance_label(&info_fullname_lc);
+    Ok(ServiceAnswer { records, instances })
+}
 
-                if info_fullname_lc == unescaped_query || escaped_info_fullname == escaped_query {
-                    let txt_name = name.clone();
+/// Whether `.local.` name `name` is of DNS-SD kind `expected`. Anything else is
+/// answered empty without browsing, since mdns-sd would reject the service type
+/// or wait out the timeout for a service that cannot exist.
+fn has_dns_sd_shape(name: &Name, expected: NameKind) -> bool {
+    let local = Name::from_ascii("local.").expect("valid name");
+    if local.zone_of(name) && names::classify(name, &local) == expected {
+        return true;
+    }
+    debug!("{} is not a DNS-SD {:?} name, not browsing", names::presentation(name), expected);
+    false
+}
 
-                    let txt_records: Vec<String> = info
-                        .get_properties()
-                        .iter()
-                        .map(|prop| format!("{}={}", prop.key(), prop.val_str()))
-                        .collect();
+/// Build the SRV record for a resolved instance
+fn srv_record(name: Name, info: &ResolvedService) -> Result<Record, Box<dyn std::error::Error + Send + Sync>> {
+    // Raw labels, as for the host's A/AAAA owner names, not IDNA-encoded
+    let target = name_from_labels_str(info.get_hostname())?;
+
+    Ok(Record::from_rdata(
+        name,
+        120,
+        RData::SRV(hickory_proto::rr::rdata::SRV::new(
+            0,               // priority
+            0,               // weight
+            info.get_port(), // port
+            target,          // target hostname
+        )),
+    ))
+}
 
-                    if !txt_records.is_empty() {
-                        let record = Record::from_rdata(
-                            txt_name,
-                            120,
-                            RData::TXT(hickory_proto::rr::rdata::TXT::new(txt_records)),
-                        );
+/// Build the TXT record for a resolved instance, or None if it has no properties
+fn txt_record(name: Name, info: &ResolvedService) -> Option<Record> {
+    let txt_records: Vec<String> = info
+        .get_properties()
+        .iter()
+        .map(|prop| format!("{}={}", prop.key(), prop.val_str()))
+        .collect();
 
-                        records.push(record);
-                    }
-                    break;
+    if txt_records.is_empty() {
+        return None;
+    }
+
+    Some(Record::from_rdata(
+        name,
+        120,
+        RData::TXT(hickory_proto::rr::rdata::TXT::new(txt_records)),
+    ))
+}
+
+/// Derive the records of the requested kinds from a single resolved instance.
+/// SRV/TXT are owned by the instance fullname, A/AAAA by its target host.
+pub fn instance_records(
+    info: &ResolvedService,
+    kinds: &[ServiceRecordKind],
+) -> Result<Vec<Record>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut records = Vec::new();
+    let instance_name = names::instance_name(info.get_fullname(), &info.ty_domain)?;
+
+    for kind in kinds {
+        match kind {
+            ServiceRecordKind::Srv => records.push(srv_record(instance_name.clone(), info)?),
+            ServiceRecordKind::Txt => records.extend(txt_record(instance_name.clone(), info)),
+            ServiceRecordKind::A | ServiceRecordKind::Aaaa => {
+                let host = name_from_labels_str(info.get_hostname())?;
+                for addr in info.get_addresses() {
+                    let rdata = match (kind, addr) {
+                        (ServiceRecordKind::A, mdns_sd::ScopedIp::V4(v4)) => {
+                            RData::A(hickory_proto::rr::rdata::A::from(*v4.addr()))
+                        }
+                        (ServiceRecordKind::Aaaa, mdns_sd::ScopedIp::V6(v6)) => {
+                            RData::AAAA(hickory_proto::rr::rdata::AAAA::from(*v6.addr()))
+                        }
+                        _ => continue,
+                    };
+                    records.push(Record::from_rdata(host.clone(), 120, rdata));
                 }
             }
-            Ok(Ok(ServiceEvent::SearchStopped(_))) => break,
-            Ok(Err(_)) => break,
-            Err(_) => continue, // Timeout, try again
-            _ => {}
         }
     }
 
@@ -347,38 +450,8 @@ async fn resolve_hostname(
     Ok(records)
 }
 
-/// Escape the instance label (first label) of an mDNS fullname so Hickory accepts it as DNS.
-/// Spaces are turned into "\032" per DNS escaping rules; other labels are left untouched.
-fn escape_instance_label(fullname: &str) -> String {
-    let mut parts: Vec<String> = fullname.split('.').map(str::to_string).collect();
-    if let Some(first) = parts.first_mut() {
-        *first = first.replace(' ', "\\032");
-    }
-    parts.join(".")
-}
-
-/// Inverse of escape_instance_label for comparison: "\032" back to space in the first label.
-fn unescape_instance_label(fullname: &str) -> String {
-    let mut parts: Vec<String> = fullname.split('.').map(str::to_string).collect();
-    if let Some(first) = parts.first_mut() {
-        *first = first.replace("\\032", " ");
-    }
-    parts.join(".")
-}
-
-/// Build a DNS Name from raw labels, permitting spaces by constructing Labels from bytes.
-fn name_from_labels_str(fullname: &str) -> Result<Name, Box<dyn std::error::Error + Send + Sync>> {
-    let labels: Vec<Label> = fullname
-        .split('.')
-        .filter(|s| !s.is_empty())
-        .map(|s| Label::from_raw_bytes(s.as_bytes()))
-        .collect::<Result<Vec<_>, _>>()?;
-    Ok(Name::from_labels(labels)?)
-}
-
 /// Query for SOA (Start of Authority) records per RFC 8766 Section 6.1
 pub async fn query_soa(
-    _daemon: &ServiceDaemon,
     name: &Name,
 ) -> Result<Vec<Record>, Box<dyn std::error::Error + Send + Sync>> {
     use hickory_proto::rr::rdata::SOA;
@@ -412,7 +485,6 @@ pub async fn query_soa(
 
 /// Query for NS (Name Server) records per RFC 8766 Section 6.2
 pub async fn query_ns(
-    _daemon: &ServiceDaemon,
     name: &Name,
 ) -> Result<Vec<Record>, Box<dyn std::error::Error + Send + Sync>> {
     use hickory_proto::rr::rdata::NS;
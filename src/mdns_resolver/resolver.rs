@@ -1,34 +1,121 @@
+use hickory_proto::rr::rdata::{PTR, SVCB};
 use hickory_proto::rr::{Name, Record, RecordType, RData};
-use mdns_sd::{IfKind, ServiceDaemon};
-use std::sync::Arc;
-use tracing::{debug, warn};
-use crate::config::Config;
+use mdns_sd::{IfKind, ResolvedService, ServiceDaemon};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::{debug, info, warn};
+use crate::config::{Config, ServiceRecordKind};
+use crate::metrics;
+use crate::netwatch::LocalNetwork;
+use crate::pending::PendingQueries;
+use crate::toggles::FeatureToggles;
+use std::time::Instant;
 
 /// Maximum TTL for unicast DNS responses per RFC 8766 Section 5.5.1
 /// TTLs are capped at 10 seconds to ensure timely updates for remote clients
-const MAX_UNICAST_TTL: u32 = 10;
+pub(crate) const MAX_UNICAST_TTL: u32 = 10;
 
+use super::browses::Browses;
+use super::capabilities::{self, Capabilities};
 use super::cache::Cache;
+use super::fresh::FreshLimiter;
+use super::health::{LinkHealth, LinkStatus};
+use super::service::ServiceInstance;
+use super::shared::SharedCache;
+use super::snapshot::{CachedRrset, LastKnownHost, Snapshot, SnapshotRecord};
+use super::changes::ChangeTracker;
+use super::daemon::{self, LazyDaemon};
+use super::known::KnownStore;
+use super::liveness::{self, InventoryEntry, LivenessTable};
+use super::wake::WakeManager;
+use super::watch::Watch;
+use super::names;
+use super::txt_size;
 use super::query;
+use super::oneshot;
+use super::provenance::{self, InstanceLinks};
+use super::reverse;
+use super::svcb;
+
+/// Configured known services, with their journal when one is set
+fn known_store(config: &Config) -> Result<KnownStore, Box<dyn std::error::Error + Send + Sync>> {
+    let known = KnownStore::from_config(&config.known_services.services)?;
+    match &config.known_services.journal {
+        Some(path) => known.with_journal(path),
+        None => Ok(known),
+    }
+}
 
 /// mDNS resolver that bridges DNS queries to mDNS
 pub struct MdnsResolver {
-    daemon: Arc<ServiceDaemon>,
+    /// Started with the resolver, or by the first query with `mdns.lazy_start`
+    pub(crate) daemon: LazyDaemon,
     pub(crate) cache: Cache,
-    config: Arc<Config>,
+    /// Configuration in effect, replaced by [`MdnsResolver::reload`]
+    config: RwLock<Arc<Config>>,
+    /// Answer from cache only, never sending multicast queries
+    read_only: AtomicBool,
+    /// Quiet hours in effect: no prefetching, shortened timeouts
+    quiet: AtomicBool,
+    /// Behaviors switched on or off at runtime
+    toggles: FeatureToggles,
+    /// `config` with timeouts capped for quiet hours
+    quiet_config: RwLock<Arc<Config>>,
+    /// Configured service instances, answered without waiting on mDNS
+    known: KnownStore,
+    /// Devices sent a Wake-on-LAN packet when their address is asked for
+    wake: WakeManager,
+    /// Last probe result per cached service instance
+    liveness: LivenessTable,
+    /// Links each browsed service instance was seen on
+    instance_links: InstanceLinks,
+    /// Rate limit for queries that bypass the cache
+    fresh: FreshLimiter,
+    /// Queries being answered right now, for the control socket
+    pending: PendingQueries,
+    /// Last SRV and TXT data per instance, to report changes
+    changes: ChangeTracker,
+    /// Browses running on the daemon, restarted past their age limit
+    browses: Browses,
+    /// Activity and errors per link, for the control socket
+    health: Arc<LinkHealth>,
+    /// Names and address types being looked up in the background by
+    /// [`prefetch_other_family`](Self::prefetch_other_family)
+    family_prefetches: Mutex<HashSet<(String, RecordType)>>,
 }
 
 impl MdnsResolver {
     /// Create a new mDNS resolver
     pub fn new(config: Arc<Config>) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
-        let daemon = Arc::new(ServiceDaemon::new()?);
-        daemon.enable_interface(IfKind::All)?;
-        // daemon.accept_unsolicited(true)?;
-        
+        let daemon = if config.mdns.lazy_start {
+            LazyDaemon::deferred(config.mdns.interfaces.clone())
+        } else {
+            LazyDaemon::started(daemon::start(&config.mdns.interfaces)?)
+        };
+        let health = Arc::new(LinkHealth::new());
+
         Ok(Self {
             daemon,
-            cache: Cache::new(config.cache_ttl()),
-            config,
+            cache: Cache::new(config.cache_ttl()).with_memory_limit(config.cache_memory_limit())
+                .with_canonical_order(config.debug.deterministic_output),
+            read_only: AtomicBool::new(config.mdns.read_only),
+            quiet: AtomicBool::new(false),
+            toggles: FeatureToggles::from_config(&config),
+            quiet_config: RwLock::new(Arc::new(config.with_quiet_timeouts())),
+            known: known_store(&config)?,
+            wake: WakeManager::from_config(&config.wake)?,
+            liveness: LivenessTable::default(),
+            instance_links: InstanceLinks::default(),
+            fresh: FreshLimiter::new(config.mdns.fresh_queries_per_minute),
+            pending: PendingQueries::default(),
+            changes: ChangeTracker::new(std::time::Duration::from_secs(config.mdns.change_debounce_secs)),
+            browses: Browses::new(std::time::Duration::from_secs(config.mdns.browse_max_age_secs))
+                .with_health(health.clone()),
+            health,
+            family_prefetches: Mutex::default(),
+            config: RwLock::new(config),
         })
     }
 
@@ -40,59 +127,309 @@ impl MdnsResolver {
     ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
         daemon.enable_interface(IfKind::All)?;
         // daemon.accept_unsolicited(true)?;
-        
+        let health = Arc::new(LinkHealth::new());
+
         Ok(Self {
-            daemon,
-            cache: Cache::new(config.cache_ttl()),
-            config,
+            daemon: LazyDaemon::started(daemon),
+            cache: Cache::new(config.cache_ttl()).with_memory_limit(config.cache_memory_limit())
+                .with_canonical_order(config.debug.deterministic_output),
+            read_only: AtomicBool::new(config.mdns.read_only),
+            quiet: AtomicBool::new(false),
+            toggles: FeatureToggles::from_config(&config),
+            quiet_config: RwLock::new(Arc::new(config.with_quiet_timeouts())),
+            known: known_store(&config)?,
+            wake: WakeManager::from_config(&config.wake)?,
+            liveness: LivenessTable::default(),
+            instance_links: InstanceLinks::default(),
+            fresh: FreshLimiter::new(config.mdns.fresh_queries_per_minute),
+            pending: PendingQueries::default(),
+            changes: ChangeTracker::new(std::time::Duration::from_secs(config.mdns.change_debounce_secs)),
+            browses: Browses::new(std::time::Duration::from_secs(config.mdns.browse_max_age_secs))
+                .with_health(health.clone()),
+            health,
+            family_prefetches: Mutex::default(),
+            config: RwLock::new(config),
         })
     }
 
-    /// Query mDNS for a given name and record type
+    /// Inject `faults` between the daemon and this resolver's browses
+    #[cfg(any(test, feature = "fault-injection"))]
+    pub fn with_faults(mut self, faults: Arc<super::faults::Faults>) -> Self {
+        self.browses.set_faults(faults);
+        self
+    }
+
+    /// Approximate memory held by the record cache, in bytes
+    pub fn cache_memory_usage(&self) -> usize {
+        self.cache.memory_usage()
+    }
+
+    /// Share cache entries with other instances through `shared`; false if a backend is already attached
+    pub fn attach_shared_cache(&self, shared: Arc<dyn SharedCache>) -> bool {
+        self.cache.attach_shared(shared)
+    }
+
+    /// Configuration in effect
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Put `config` into effect for the cache TTL, mDNS timeouts, resolution
+    /// chain and everything else read per query; settings applied when the
+    /// resolver was created (daemon, known services, rate limits) stay as they were
+    pub fn reload(&self, config: Arc<Config>) {
+        self.cache.set_ttl(config.cache_ttl());
+        *self.quiet_config.write().unwrap() = Arc::new(config.with_quiet_timeouts());
+        *self.config.write().unwrap() = config;
+    }
+
+    /// Whether queries are answered from cache only
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::Relaxed)
+    }
+
+    /// Switch read-only mode on or off at runtime
+    pub fn set_read_only(&self, read_only: bool) {
+        self.read_only.store(read_only, Ordering::Relaxed);
+    }
+
+    /// What the mDNS backend can ask the link for
+    pub fn capabilities(&self) -> &'static Capabilities {
+        &capabilities::MDNS_SD
+    }
+
+    /// Behaviors that can be switched at runtime
+    pub fn toggles(&self) -> &FeatureToggles {
+        &self.toggles
+    }
+
+    /// Whether quiet hours are in effect
+    pub fn is_quiet(&self) -> bool {
+        self.quiet.load(Ordering::Relaxed)
+    }
+
+    /// Enter or leave quiet hours
+    pub fn set_quiet(&self, quiet: bool) {
+        self.quiet.store(quiet, Ordering::Relaxed);
+    }
+
+    /// Stop the daemon's browses that have outlived `mdns.browse_max_age_secs`
+    pub fn expire_browses(&self) -> usize {
+        self.daemon.current().map_or(0, |daemon| self.browses.expire(&daemon))
+    }
+
+    /// Configuration governing mDNS queries right now
+    fn query_config(&self) -> Arc<Config> {
+        let config = if self.is_quiet() { &self.quiet_config } else { &self.config };
+        config.read().unwrap().clone()
+    }
+
+    /// Drop cached state for a discovery zone that is no longer served
+    pub fn purge_zone(&self, apex: &Name) -> usize {
+        self.cache.remove_suffix(&names::cache_key(apex))
+    }
+
+    /// Drop cached answers holding an address inside one of `networks`
+    pub fn flush_networks(&self, networks: &[LocalNetwork]) -> usize {
+        self.cache.remove_matching(|record| {
+            let addr = match record.data() {
+                RData::A(a) => IpAddr::V4(a.0),
+                RData::AAAA(aaaa) => IpAddr::V6(aaaa.0),
+                _ => return false,
+            };
+            networks.iter().any(|network| network.contains(&addr))
+        })
+    }
+
+    /// Query mDNS for a name in the configured discovery domain
     pub async fn query(
         &self,
         name: &Name,
         record_type: RecordType,
     ) -> Result<Vec<Record>, Box<dyn std::error::Error + Send + Sync>> {
-        let query_name = name.to_utf8();
+        let zone = Name::from_utf8(self.config().discovery_domain())?;
+        self.query_in_zone(name, &zone, record_type).await
+    }
 
-        let mdns_name = map_query_to_local(name, self.config.discovery_domain())?;
-        let mdns_query = mdns_name.to_utf8();
-        
-        debug!("Querying mDNS for {} (mapped to {} for mDNS, type: {:?})", query_name, mdns_query, record_type);
+    /// Query mDNS for a name in discovery zone `zone`; answers are rewritten back into that zone
+    pub async fn query_in_zone(
+        &self,
+        name: &Name,
+        zone: &Name,
+        record_type: RecordType,
+    ) -> Result<Vec<Record>, Box<dyn std::error::Error + Send + Sync>> {
+        self.query_in_zone_with(name, zone, record_type, false).await
+    }
 
-        // Check cache first
-        if let Some(cached) = self.cache.get(&query_name, record_type) {
-            debug!("Returning cached results for {} (type: {:?})", query_name, record_type);
+    /// Like [`query_in_zone`](Self::query_in_zone), but bypass the cache and answer
+    /// with TTL 0, as long as the fresh query rate limit allows
+    pub async fn query_fresh_in_zone(
+        &self,
+        name: &Name,
+        zone: &Name,
+        record_type: RecordType,
+    ) -> Result<Vec<Record>, Box<dyn std::error::Error + Send + Sync>> {
+        self.query_in_zone_with(name, zone, record_type, true).await
+    }
+
+    async fn query_in_zone_with(
+        &self,
+        name: &Name,
+        zone: &Name,
+        record_type: RecordType,
+        fresh_requested: bool,
+    ) -> Result<Vec<Record>, Box<dyn std::error::Error + Send + Sync>> {
+        let fresh = self.take_fresh(name, fresh_requested);
+        let mut records = self.lookup_in_zone(name, zone, record_type, !fresh).await?;
+        if fresh {
+            mark_fresh(&mut records);
+        }
+        Ok(records)
+    }
+
+    /// Whether a query for `name` is answered fresh: asked for, or always for its
+    /// service type, and allowed by the rate limit. Counts against the limit.
+    pub fn take_fresh(&self, name: &Name, fresh_requested: bool) -> bool {
+        // Types whose strategy asks for it are always fresh; nothing is fresh from cache only
+        let wants_fresh = fresh_requested
+            || self
+                .config()
+                .strategy_for(&names::mdns_string(name))
+                .is_some_and(|strategy| strategy.fresh);
+        let fresh = wants_fresh && !self.is_read_only() && self.fresh.allow();
+        if wants_fresh && !fresh {
+            debug!("Fresh query for {} not allowed now, answering normally", names::presentation(name));
+            metrics::inc(&metrics::metrics().fresh_rate_limited);
+        }
+        fresh
+    }
+
+    /// Cache, then known services, then mDNS
+    async fn lookup_in_zone(
+        &self,
+        name: &Name,
+        zone: &Name,
+        record_type: RecordType,
+        use_cache: bool,
+    ) -> Result<Vec<Record>, Box<dyn std::error::Error + Send + Sync>> {
+        if use_cache && let Some(cached) = self.cached_in_zone(name, record_type) {
             return Ok(cached);
         }
+        if let Some(records) = self.known_in_zone(name, zone, record_type)? {
+            return Ok(records);
+        }
+        self.mdns_in_zone(name, zone, record_type).await
+    }
+
+    /// Cached answer for a name in a discovery zone, empty if mDNS recently had none
+    pub fn cached_in_zone(&self, name: &Name, record_type: RecordType) -> Option<Vec<Record>> {
+        // Escaped and raw spellings of the same name share one cache entry
+        let query_name = names::cache_key(name);
+        let started = Instant::now();
+        let cached = self.cache.get(&query_name, record_type);
+        metrics::observe(&metrics::metrics().cache_lookup_time, started.elapsed());
+        if cached.is_some() {
+            debug!("Returning cached results for {} (type: {:?})", query_name, record_type);
+        }
+        cached
+    }
 
-        // Perform mDNS query based on record type
-        let mdns_records = match record_type {
-            RecordType::A | RecordType::AAAA => query::query_a_aaaa(&self.daemon, &mdns_name, &self.config).await?,
-            RecordType::PTR => query::query_ptr(&self.daemon, &mdns_name, &self.config).await?,
-            RecordType::SRV => query::query_srv(&self.daemon, &mdns_name, &self.config).await?,
-            RecordType::TXT => query::query_txt(&self.daemon, &mdns_name, &self.config).await?,
-            RecordType::SOA => query::query_soa(&self.daemon, &mdns_name).await?,
-            RecordType::NS => query::query_ns(&self.daemon, &mdns_name).await?,
-            _ => {
-                warn!("Unsupported record type: {:?}", record_type);
-                Vec::new()
+    /// Answer from the configured known service instances, if they hold the name
+    pub fn known_in_zone(
+        &self,
+        name: &Name,
+        zone: &Name,
+        record_type: RecordType,
+    ) -> Result<Option<Vec<Record>>, Box<dyn std::error::Error + Send + Sync>> {
+        let mdns_name = map_query_to_local(name, zone)?;
+        // Configured instances are answered at once; the background check keeps them current
+        match self.known.answer(&mdns_name, record_type)? {
+            Some(records) => {
+                debug!("Answering {} {:?} from known services", names::mdns_string(&mdns_name), record_type);
+                self.finalize_records(records, zone).map(Some)
             }
-        };
+            None => Ok(None),
+        }
+    }
+
+    /// Record types the proxy holds records of for a name in a discovery zone:
+    /// unexpired cache entries and configured known services
+    pub fn types_in_zone(&self, name: &Name, zone: &Name) -> Vec<RecordType> {
+        let mut types = self.cache.types_of(&names::cache_key(name));
+        for record_type in [RecordType::PTR, RecordType::SRV, RecordType::TXT] {
+            if self.known_in_zone(name, zone, record_type).is_ok_and(|records| records.is_some_and(|r| !r.is_empty())) {
+                types.push(record_type);
+            }
+        }
+        types.sort_unstable_by_key(|&t| u16::from(t));
+        types.dedup();
+        types
+    }
 
-        // Rewrite returned records from .local to the configured discovery domain
-        let mut records = rewrite_records_to_discovery_domain(mdns_records, self.config.discovery_domain())?;
+    /// Query mDNS itself (nothing in read-only mode) and cache the answer
+    pub async fn mdns_in_zone(
+        &self,
+        name: &Name,
+        zone: &Name,
+        record_type: RecordType,
+    ) -> Result<Vec<Record>, Box<dyn std::error::Error + Send + Sync>> {
+        let query_name = names::cache_key(name);
+        let mdns_name = map_query_to_local(name, zone)?;
+        let mdns_query = names::mdns_string(&mdns_name);
 
-        // Cap TTLs at 10 seconds per RFC 8766 Section 5.5.1
-        // This ensures remote clients receive timely updates
-        for record in &mut records {
-            if record.ttl() > MAX_UNICAST_TTL {
-                record.set_ttl(MAX_UNICAST_TTL);
+        debug!("Querying mDNS for {} (mapped to {} for mDNS, type: {:?})", names::presentation(name), mdns_query, record_type);
+
+        if self.is_read_only() {
+            debug!("Read-only mode, not querying mDNS for {}", mdns_query);
+            return Ok(Vec::new());
+        }
+
+        let is_address = record_type == RecordType::A || record_type == RecordType::AAAA;
+        let wake_device = is_address && self.wake.is_device(&mdns_name);
+        if wake_device {
+            // Wakes the device while the query below is outstanding
+            self.wake.wake(&mdns_name);
+        }
+
+        let started = Instant::now();
+        let lookup = self.lookup(&mdns_name, record_type).await;
+        metrics::observe(&metrics::metrics().mdns_wait_time, started.elapsed());
+        if lookup.is_err() {
+            self.health.lookup_failed();
+        }
+        let (mdns_records, instances) = lookup?;
+        if !mdns_records.is_empty() {
+            let addresses: Vec<IpAddr> = mdns_records.iter().filter_map(|r| r.data().ip_addr()).collect();
+            self.health.resolved(instances.iter().flat_map(|i| i.get_addresses()), &addresses);
+        }
+
+        if wake_device {
+            if !mdns_records.is_empty() {
+                self.wake.remember(&mdns_name, &mdns_records);
+            } else if let Some(records) = self.wake.last_known(&mdns_name, record_type) {
+                // Still asleep: serve the last known addresses without caching them
+                debug!("Serving last known {:?} records for sleeping device {}", record_type, mdns_query);
+                return self.finalize_records(records, zone);
             }
         }
 
-        if record_type == RecordType::A || record_type == RecordType::AAAA {
+        if record_type == RecordType::PTR {
+            self.record_links(zone, &instances);
+        }
+
+        // Cache records derived from resolved instances per the service type's strategy
+        // (paused during quiet hours to keep multicast to a minimum)
+        if !instances.is_empty() && !self.is_quiet() {
+            self.prefetch(&mdns_query, zone, &instances)?;
+        }
+
+        // Rewrite to the discovery domain and cap TTLs per RFC 8766 Section 5.5.1
+        let records = self.finalize_records(mdns_records, zone)?;
+
+        // Empty answers are cached for the zone's SOA MINIMUM, as the SOA advertises
+        let negative_ttl = std::time::Duration::from_secs(self.config().soa_minimum(zone).into());
+
+        if is_address {
             // Need to segment the returned record set into A and AAAA records
             let (a_records, aaaa_records): (Vec<Record>, Vec<Record>) = records
                 .into_iter()
@@ -105,61 +442,624 @@ impl MdnsResolver {
                 self.cache.insert(&query_name, RecordType::AAAA, aaaa_records.clone());
             }
             
-            Ok(match record_type {
+            let records = match record_type {
                 RecordType::A => a_records,
                 RecordType::AAAA => aaaa_records,
                 _ => unreachable!(),
-            })
+            };
+            if records.is_empty() {
+                self.cache.insert_negative(&query_name, record_type, negative_ttl);
+            }
+            Ok(records)
         } else {
-            if !records.is_empty() {
+            if records.is_empty() {
+                self.cache.insert_negative(&query_name, record_type, negative_ttl);
+            } else {
+                if let Some(update) = self.changes.observe(&records) {
+                    update.log();
+                }
                 self.cache.insert(&query_name, record_type, records.clone());
             }
 
             Ok(records)
         }
     }
+
+    /// PTR records for reverse name `name` (`in-addr.arpa` or `ip6.arpa`) naming
+    /// hosts in discovery zone `zone`: from cached addresses, else from the link.
+    /// Empty when no host has the address or it cannot be on the link.
+    pub async fn reverse_in_zone(&self, name: &Name, zone: &Name) -> Result<Vec<Record>, Box<dyn std::error::Error + Send + Sync>> {
+        let Some(addr) = reverse::address_of(name).filter(reverse::is_local_address) else {
+            return Ok(Vec::new());
+        };
+        let query_name = names::cache_key(name);
+        if let Some(cached) = self.cached_in_zone(name, RecordType::PTR) {
+            return Ok(cached);
+        }
+
+        let addresses: Vec<Record> = [RecordType::A, RecordType::AAAA]
+            .into_iter()
+            .flat_map(|record_type| self.cache.records_of_type(record_type))
+            .collect();
+        let mut hosts = reverse::hosts_with_address(&addresses, addr);
+        if hosts.is_empty() && !self.is_read_only() {
+            debug!("Querying mDNS for the name of {}", addr);
+            let started = Instant::now();
+            let scopes = match addr {
+                std::net::IpAddr::V4(_) => Vec::new(),
+                std::net::IpAddr::V6(_) => oneshot::ipv6_scopes(&self.config().mdns.interfaces)?,
+            };
+            let found = reverse::query(name, addr, &scopes, self.query_config().hostname_resolution_timeout()).await;
+            metrics::observe(&metrics::metrics().mdns_wait_time, started.elapsed());
+            for host in found? {
+                hosts.push(rewrite_name_to_discovery(&host, zone)?);
+            }
+        }
+
+        let records: Vec<Record> = hosts
+            .into_iter()
+            .map(|host| Record::from_rdata(name.clone(), MAX_UNICAST_TTL, RData::PTR(PTR(host))))
+            .collect();
+        if records.is_empty() {
+            let negative_ttl = std::time::Duration::from_secs(self.config().soa_minimum(zone).into());
+            self.cache.insert_negative(&query_name, RecordType::PTR, negative_ttl);
+        } else {
+            self.cache.insert(&query_name, RecordType::PTR, records.clone());
+        }
+        Ok(records)
+    }
+
+    /// After `name` was answered with `record_type` (A or AAAA) records, look up
+    /// the other address type in the background unless it is cached, so a
+    /// dual-stack client's second query is a cache hit. Returns whether a
+    /// lookup was started: not with `mdns.address_family_prefetch` off, in
+    /// read-only mode or quiet hours, nor while one for the name is running.
+    pub fn prefetch_other_family(self: &Arc<Self>, name: &Name, zone: &Name, record_type: RecordType) -> bool {
+        let other = match record_type {
+            RecordType::A => RecordType::AAAA,
+            RecordType::AAAA => RecordType::A,
+            _ => return false,
+        };
+        if !self.config().mdns.address_family_prefetch || self.is_read_only() || self.is_quiet() {
+            return false;
+        }
+        let key = (names::cache_key(name), other);
+        if self.cache.get(&key.0, other).is_some() || !self.family_prefetches.lock().unwrap().insert(key.clone()) {
+            return false;
+        }
+
+        debug!("Prefetching {:?} records for {}", other, names::presentation(name));
+        let (resolver, name, zone) = (self.clone(), name.clone(), zone.clone());
+        tokio::spawn(async move {
+            if let Err(e) = resolver.lookup_in_zone(&name, &zone, other, true).await {
+                debug!("Prefetching {:?} records for {} failed: {}", other, names::presentation(&name), e);
+            }
+            resolver.family_prefetches.lock().unwrap().remove(&key);
+        });
+        true
+    }
+
+    /// Resolved instances of a `.local.` service type, bypassing the cache
+    pub async fn browse(&self, service_type: &Name) -> Result<Vec<ResolvedService>, Box<dyn std::error::Error + Send + Sync>> {
+        let (_, instances) = self.lookup(service_type, RecordType::PTR).await?;
+        Ok(instances)
+    }
+
+    /// Follow the browse behind a DNS Push subscription to `name`/`record_type`
+    /// in `zone`, starting from the answer `current`: PTR records of a service
+    /// type or subtype, SRV or TXT records of an instance. None for anything
+    /// else, for the meta-query (which a browse does not answer completely),
+    /// and in read-only mode.
+    pub fn watch(
+        &self,
+        name: &Name,
+        zone: &Name,
+        record_type: RecordType,
+        current: &[Record],
+    ) -> Result<Option<Watch>, Box<dyn std::error::Error + Send + Sync>> {
+        if self.is_read_only() {
+            return Ok(None);
+        }
+        let mdns_name = map_query_to_local(name, zone)?;
+        let local = Name::from_ascii("local.")?;
+        let service_type = match (record_type, names::classify(&mdns_name, &local)) {
+            (RecordType::PTR, names::NameKind::ServiceType) => {
+                let service_type = names::mdns_string(&mdns_name);
+                if service_type.to_ascii_lowercase().starts_with("_services._dns-sd.") {
+                    return Ok(None);
+                }
+                service_type
+            }
+            (RecordType::SRV | RecordType::TXT, names::NameKind::ServiceInstance) => {
+                match names::split_instance(&mdns_name) {
+                    Some((_, service_type)) => service_type,
+                    None => return Ok(None),
+                }
+            }
+            _ => return Ok(None),
+        };
+        let daemon = self.daemon.get()?;
+        let events = self.browses.browse(&daemon, &service_type)?;
+        debug!("Watching {} for {:?} changes", service_type, record_type);
+        Ok(Some(Watch::new(name, mdns_name, zone, record_type, service_type, events, current)))
+    }
+
+    /// Start the browse for `service_type` again if the age limit stopped it
+    pub(super) fn resume_browse(&self, service_type: &str) {
+        let Some(daemon) = self.daemon.current() else {
+            return;
+        };
+        if let Err(e) = self.browses.resume(&daemon, service_type) {
+            warn!("Failed to resume browse for {}: {}", service_type, e);
+        }
+    }
+
+    /// Resolve one service instance by its `.local.` fullname (e.g.
+    /// "Office Printer._ipp._tcp.local."), bypassing the cache. None if it did not answer.
+    pub async fn resolve_service(&self, instance: &str) -> Result<Option<ServiceInstance>, Box<dyn std::error::Error + Send + Sync>> {
+        let name = names::name_from_labels_str(instance)?;
+        if names::split_instance(&name).is_none() {
+            return Err(format!("{} is not a service instance name", instance).into());
+        }
+        let (_, instances) = self.lookup(&name, RecordType::SRV).await?;
+        Ok(instances.first().map(ServiceInstance::from))
+    }
+
+    /// Browse every configured service type and refresh the known-services store
+    pub async fn verify_known_services(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        for service_type in self.known.service_types() {
+            let instances = self.browse(&Name::from_utf8(&service_type)?).await?;
+            debug!("Known service check for {}: {} instance(s) answered", service_type, instances.len());
+            for fullname in self.known.update(&service_type, instances) {
+                info!("Known service {} did not answer; still serving configured records", fullname);
+            }
+        }
+        Ok(())
+    }
+
+    /// Send the mDNS query for a `.local.` name, returning answers and resolved instances
+    async fn lookup(
+        &self,
+        mdns_name: &Name,
+        record_type: RecordType,
+    ) -> Result<(Vec<Record>, Vec<ResolvedService>), Box<dyn std::error::Error + Send + Sync>> {
+        let config = self.query_config();
+        match record_type {
+            RecordType::SOA => return Ok((query::query_soa(mdns_name).await?, Vec::new())),
+            RecordType::NS => return Ok((query::query_ns(mdns_name).await?, Vec::new())),
+            RecordType::CNAME => {
+                let destinations = oneshot::destinations(false, &[]);
+                let timeout = config.hostname_resolution_timeout();
+                return Ok((oneshot::query(mdns_name, record_type, &destinations, timeout).await?, Vec::new()));
+            }
+            RecordType::HTTPS | RecordType::SVCB if config.mdns.https_records => {
+                if !svcb::applies(mdns_name, record_type) {
+                    return Ok((Vec::new(), Vec::new()));
+                }
+                // Synthesized from every `_https._tcp` instance on the link
+                let daemon = self.daemon.get()?;
+                let service = Name::from_ascii(svcb::HTTPS_SERVICE)?;
+                let answer = query::query_ptr(&daemon, &self.browses, &service, &config).await?;
+                let records = svcb::synthesize(mdns_name, record_type, &answer.instances)?;
+                return Ok((records, answer.instances));
+            }
+            _ if !self.capabilities().can_query(record_type) => {
+                debug!("The {} backend cannot ask for {:?} records; answering empty", self.capabilities().name, record_type);
+                metrics::inc(&metrics::metrics().queries_unsupported);
+                return Ok((Vec::new(), Vec::new()));
+            }
+            _ => {}
+        }
+        let daemon = self.daemon.get()?;
+        Ok(match record_type {
+            RecordType::A | RecordType::AAAA => (query::query_a_aaaa(&daemon, mdns_name, &config).await?, Vec::new()),
+            RecordType::PTR => {
+                let answer = query::query_ptr(&daemon, &self.browses, mdns_name, &config).await?;
+                // Per-type instance counts; a subtype lists only some of its parent's
+                // instances and the meta-query none, so neither is counted
+                let service_type = names::mdns_string(mdns_name);
+                let local = Name::from_ascii("local.")?;
+                if names::classify(mdns_name, &local) == names::NameKind::ServiceType
+                    && names::split_subtype(&service_type).is_none()
+                    && !service_type.to_ascii_lowercase().starts_with("_services._dns-sd.")
+                {
+                    metrics::observe_instances(&service_type, answer.instances.iter().map(|i| i.fullname.as_str()));
+                }
+                (answer.records, answer.instances)
+            }
+            RecordType::SRV => {
+                let answer = query::query_srv(&daemon, &self.browses, mdns_name, &config).await?;
+                (answer.records, answer.instances)
+            }
+            RecordType::TXT => {
+                let answer = query::query_txt(&daemon, &self.browses, mdns_name, &config).await?;
+                (answer.records, answer.instances)
+            }
+            _ => unreachable!("{:?} is not a type the backend can ask for", record_type),
+        })
+
+    }
+
+    /// Raw mDNS answer for a name in discovery zone `zone`, bypassing the cache:
+    /// records keep their `.local.` names and multicast TTLs. Used by `doctor`.
+    pub async fn query_direct(
+        &self,
+        name: &Name,
+        zone: &Name,
+        record_type: RecordType,
+    ) -> Result<Vec<Record>, Box<dyn std::error::Error + Send + Sync>> {
+        let mdns_name = map_query_to_local(name, zone)?;
+        let (mut records, _) = self.lookup(&mdns_name, record_type).await?;
+        if record_type == RecordType::A || record_type == RecordType::AAAA {
+            records.retain(|record| record.record_type() == record_type);
+        }
+        Ok(records)
+    }
+
+    /// Rewrite records from .local to the discovery domain, cap their TTLs and
+    /// bring TXT data within size limits
+    pub(super) fn finalize_records(&self, records: Vec<Record>, zone: &Name) -> Result<Vec<Record>, Box<dyn std::error::Error + Send + Sync>> {
+        let started = Instant::now();
+        let records = rewrite_records_to_discovery_domain(records, zone);
+        metrics::observe(&metrics::metrics().rewrite_time, started.elapsed());
+        let mut records: Vec<Record> = records?
+            .into_iter()
+            .filter_map(|record| {
+                if record.record_type() != RecordType::TXT {
+                    return Some(record);
+                }
+                let (policy, max_bytes) = self.config().txt_size_for(&record.name().to_utf8());
+                txt_size::enforce(record, policy, max_bytes)
+            })
+            .collect();
+
+        // Cap TTLs at 10 seconds per RFC 8766 Section 5.5.1
+        // This ensures remote clients receive timely updates
+        for record in &mut records {
+            if record.ttl() > MAX_UNICAST_TTL {
+                record.set_ttl(MAX_UNICAST_TTL);
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Cache the records a service type's strategy asks to prefetch from resolved instances,
+    /// or everything they yield when co-resolution is enabled
+    pub(super) fn prefetch(&self, service_name: &str, zone: &Name, instances: &[ResolvedService]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let config = self.config();
+        let kinds: &[ServiceRecordKind] = match config.strategy_for(service_name) {
+            Some(strategy) if !strategy.prefetch.is_empty() => &strategy.prefetch,
+            // One resolution event carries host, port and properties: keep all of it
+            _ if config.mdns.co_resolve => &ServiceRecordKind::ALL,
+            _ => return Ok(()),
+        };
+
+        let mut derived = Vec::new();
+        for info in instances {
+            derived.extend(query::instance_records(info, kinds)?);
+        }
+
+        // Group by owner name and type so each cache entry holds a complete RRset
+        let mut groups: HashMap<(String, RecordType), Vec<Record>> = HashMap::new();
+        for record in self.finalize_records(derived, zone)? {
+            groups
+                .entry((names::cache_key(record.name()), record.record_type()))
+                .or_default()
+                .push(record);
+        }
+
+        for ((name, record_type), records) in groups {
+            debug!("Prefetched {} {:?} record(s) for {}", records.len(), record_type, name);
+            if let Some(update) = self.changes.observe(&records) {
+                update.log();
+            }
+            self.cache.insert(&name, record_type, records);
+        }
+
+        Ok(())
+    }
+
+    /// Cached records in discovery zone `zone`, TTLs as they would be served, for
+    /// a zone transfer. SOA and NS records are left out: the proxy answers those itself.
+    pub fn zone_records(&self, zone: &Name) -> Vec<Record> {
+        let mut records: Vec<Record> = self
+            .cache
+            .served_records()
+            .into_iter()
+            .filter(|record| zone.zone_of(record.name()))
+            .filter(|record| !matches!(record.record_type(), RecordType::SOA | RecordType::NS))
+            .collect();
+        for record in &mut records {
+            record.set_ttl(record.ttl().min(MAX_UNICAST_TTL));
+        }
+        records
+    }
+
+    /// Learned state: unexpired cache entries and last known Wake-on-LAN addresses
+    pub fn export_snapshot(&self) -> Snapshot {
+        let mut snapshot = Snapshot::new();
+        let to_json = |records: &[Record]| -> Vec<SnapshotRecord> {
+            records
+                .iter()
+                .filter_map(|record| match SnapshotRecord::from_record(record) {
+                    Ok(exported) => Some(exported),
+                    Err(e) => {
+                        warn!("Not exporting {} {:?}: {}", record.name(), record.record_type(), e);
+                        None
+                    }
+                })
+                .collect()
+        };
+        for (name, record_type, age, records) in self.cache.aged_entries() {
+            snapshot.cache.push(CachedRrset {
+                name,
+                record_type: super::snapshot::type_to_str(record_type),
+                cached_at: super::snapshot::cached_at(age),
+                records: to_json(&records),
+            });
+        }
+        for (host, records) in self.wake.remembered() {
+            snapshot.last_known.push(LastKnownHost {
+                host: names::presentation(&host),
+                records: to_json(&records),
+            });
+        }
+        // Sorted so exports of the same state compare equal
+        snapshot.cache.sort_by(|a, b| (&a.name, &a.record_type).cmp(&(&b.name, &b.record_type)));
+        snapshot.last_known.sort_by(|a, b| a.host.cmp(&b.host));
+        snapshot
+    }
+
+    /// Restore exported state; nothing is changed unless the whole snapshot parses.
+    /// Returns the number of cache entries restored, leaving out those already expired
+    pub fn import_snapshot(&self, snapshot: &Snapshot) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+        let parse = |records: &[SnapshotRecord]| -> Result<Vec<Record>, Box<dyn std::error::Error + Send + Sync>> {
+            records.iter().map(SnapshotRecord::to_record).collect()
+        };
+        let mut cache = Vec::with_capacity(snapshot.cache.len());
+        for entry in &snapshot.cache {
+            let record_type = super::snapshot::type_from_str(&entry.record_type)?;
+            let records = parse(&entry.records).map_err(|e| format!("{} {}: {}", entry.name, entry.record_type, e))?;
+            cache.push((entry, record_type, records));
+        }
+        let mut last_known = Vec::with_capacity(snapshot.last_known.len());
+        for host in &snapshot.last_known {
+            let name = names::from_presentation(&host.host)?;
+            last_known.push((name, parse(&host.records).map_err(|e| format!("{}: {}", host.host, e))?));
+        }
+
+        let mut restored = 0;
+        for (entry, record_type, records) in cache {
+            if self.cache.restore(&entry.name, record_type, records, entry.age()) {
+                restored += 1;
+            }
+        }
+        for (host, records) in last_known {
+            self.wake.remember(&host, &records);
+        }
+        Ok(restored)
+    }
+
+    /// Queries being answered right now
+    pub fn pending(&self) -> &PendingQueries {
+        &self.pending
+    }
+
+    /// Shut the mDNS daemon down for good, waiting up to `deadline` for it to stop
+    pub async fn shutdown(&self, deadline: std::time::Duration) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.daemon.shutdown(deadline).await
+    }
+
+    /// Cached service instances with the result of their last liveness probe
+    pub fn inventory(&self) -> Vec<InventoryEntry> {
+        let service_types = crate::service_types::registry();
+        let mut entries: Vec<InventoryEntry> = Vec::new();
+        for record in self.cache.records_of_type(RecordType::SRV) {
+            let RData::SRV(srv) = record.data() else {
+                continue;
+            };
+            let key = names::cache_key(record.name());
+            if entries.iter().any(|e| names::cache_key(&e.instance) == key) {
+                continue;
+            }
+            entries.push(InventoryEntry {
+                instance: record.name().clone(),
+                target: srv.target().clone(),
+                port: srv.port(),
+                description: service_types.describe(&names::presentation(record.name())).map(str::to_string),
+                liveness: self.liveness.get(record.name()),
+                links: self.instance_links.links(record.name()),
+            });
+        }
+        entries.sort_by_key(|e| names::cache_key(&e.instance));
+        entries
+    }
+
+    /// Health of the daemon and of each link it serves
+    pub fn health(&self) -> Vec<LinkStatus> {
+        self.health.statuses()
+    }
+
+    /// Re-read the links health is reported for, after an interface change
+    pub fn refresh_links(&self) {
+        self.health.refresh_links();
+    }
+
+    /// TCP-probe every cached instance whose target addresses are cached too
+    pub async fn probe_liveness(&self, timeout: std::time::Duration) {
+        let inventory = self.inventory();
+        for entry in &inventory {
+            let target = names::cache_key(&entry.target);
+            let addresses: Vec<std::net::IpAddr> = [RecordType::A, RecordType::AAAA]
+                .into_iter()
+                .filter_map(|t| self.cache.get(&target, t))
+                .flatten()
+                .filter_map(|r| r.data().ip_addr())
+                .collect();
+            if addresses.is_empty() {
+                debug!("No cached addresses for {}, not probing {}", entry.target, entry.instance);
+                continue;
+            }
+            let alive = liveness::probe(&addresses, entry.port, timeout).await;
+            if let Some(was_alive) = self.liveness.record(&entry.instance, alive) {
+                info!(
+                    "Service instance {} is now {} (was {}){}",
+                    names::presentation(&entry.instance),
+                    if alive { "alive" } else { "dead" },
+                    if was_alive { "alive" } else { "dead" },
+                    entry.description.as_deref().map(|d| format!(" [{}]", d)).unwrap_or_default()
+                );
+            } else if !alive {
+                debug!("Service instance {} failed its liveness probe", names::presentation(&entry.instance));
+            }
+            if !alive {
+                self.health.probe_failed(&addresses);
+            }
+        }
+        let instances: Vec<Name> = inventory.into_iter().map(|e| e.instance).collect();
+        self.liveness.retain(&instances);
+    }
+
+    /// Note the links a browse saw each of `instances` on
+    pub(super) fn record_links(&self, zone: &Name, instances: &[ResolvedService]) {
+        let local = Name::from_ascii("local.").expect("valid name");
+        let max_age = self.config().cache_ttl();
+        for info in instances {
+            let instance = names::instance_name(&info.fullname, &info.ty_domain)
+                .and_then(|name| names::replace_zone(&name, &local, zone));
+            let Ok(Some(instance)) = instance else {
+                continue;
+            };
+            let links = self.health.links_of(info.get_addresses());
+            let link_local_only = provenance::link_local_only(info.get_addresses().iter().map(|ip| ip.to_ip_addr()));
+            self.instance_links.record(&instance, links, link_local_only, max_age);
+        }
+    }
+
+    /// Drop the records of instances reachable only at link-local addresses on
+    /// links other than `client`'s; clients on this host reach every link
+    pub fn filter_other_links(&self, records: Vec<Record>, client: IpAddr) -> Vec<Record> {
+        if client.is_loopback() {
+            return records;
+        }
+        let client_link = self.health.link_of_client(&client);
+        self.instance_links.filter(records, client_link.as_deref())
+    }
+
+    /// Drop records of instances that failed their last probe, if so configured
+    pub fn filter_dead(&self, records: Vec<Record>) -> Vec<Record> {
+        if !self.config().liveness.filter_dead {
+            return records;
+        }
+        self.liveness.filter_dead(records)
+    }
+
+    /// Records for the additional section of a PTR/SRV answer, taken from the cache
+    /// according to the service type's strategy, or for a PTR answer with
+    /// `mdns.answer_bundles` each instance's SRV, TXT and addresses (RFC 6763
+    /// Section 12.1), grouped by instance. Never triggers mDNS traffic.
+    pub fn additional_records(&self, answers: &[Record]) -> Vec<Record> {
+        let config = self.config();
+        let mut additional: Vec<Record> = Vec::new();
+
+        for answer in answers {
+            let instance = match answer.data() {
+                RData::PTR(ptr) => ptr.0.clone(),
+                RData::SRV(_) => answer.name().clone(),
+                _ => continue,
+            };
+            let kinds: &[ServiceRecordKind] = match config.strategy_for(&answer.name().to_utf8()) {
+                Some(strategy) if !strategy.additional.is_empty() => &strategy.additional,
+                _ if answer.record_type() == RecordType::PTR && config.mdns.answer_bundles => &ServiceRecordKind::ALL,
+                _ => continue,
+            };
+
+            let instance_key = names::cache_key(&instance);
+            for kind in kinds {
+                let record_type = kind.record_type();
+                let found = match kind {
+                    ServiceRecordKind::Srv | ServiceRecordKind::Txt => {
+                        if answer.record_type() == record_type {
+                            continue;
+                        }
+                        self.cache.get(&instance_key, record_type)
+                    }
+                    ServiceRecordKind::A | ServiceRecordKind::Aaaa => {
+                        let targets: Vec<Name> = match answer.data() {
+                            RData::SRV(srv) => vec![srv.target().clone()],
+                            _ => self
+                                .cache
+                                .get(&instance_key, RecordType::SRV)
+                                .unwrap_or_default()
+                                .iter()
+                                .filter_map(|r| match r.data() {
+                                    RData::SRV(srv) => Some(srv.target().clone()),
+                                    _ => None,
+                                })
+                                .collect(),
+                        };
+                        let mut addrs = Vec::new();
+                        for target in targets {
+                            addrs.extend(self.cache.get(&names::cache_key(&target), record_type).unwrap_or_default());
+                        }
+                        Some(addrs)
+                    }
+                };
+
+                for record in found.unwrap_or_default() {
+                    if !additional.contains(&record) && !answers.contains(&record) {
+                        additional.push(record);
+                    }
+                }
+            }
+        }
+
+        additional
+    }
+}
+
+/// Answer records of a fresh query with TTL 0, so downstream resolvers do not
+/// cache what was asked for fresh
+pub fn mark_fresh(records: &mut [Record]) {
+    metrics::inc(&metrics::metrics().fresh_queries);
+    for record in records {
+        record.set_ttl(0);
+    }
+}
+
+/// Sort by owner name, record type and rdata, so the order no longer depends
+/// on the order mDNS answers arrived in
+pub fn sort_canonical(records: &mut [Record]) {
+    records.sort_by(|a, b| {
+        a.name()
+            .cmp(b.name())
+            .then_with(|| a.record_type().cmp(&b.record_type()))
+            .then_with(|| a.data().cmp(b.data()))
+    });
 }
 
-fn map_query_to_local(name: &Name, discovery_domain: &str) -> Result<Name, Box<dyn std::error::Error + Send + Sync>> {
-    let mut mapped = name.to_utf8().to_lowercase();
-    let disc = discovery_domain.to_lowercase();
-    let disc_no_dot = disc.trim_end_matches('.');
-
-    let mapped_to_local = if mapped.ends_with(&disc) {
-        let prefix = mapped[..mapped.len() - disc.len()]
-            .trim_end_matches('.')
-            .to_string();
-        Some(prefix)
-    } else if mapped.ends_with(disc_no_dot) {
-        let prefix = mapped[..mapped.len() - disc_no_dot.len()]
-            .trim_end_matches('.')
-            .to_string();
-        Some(prefix)
-    } else {
-        None
-    };
-
-    if let Some(prefix) = mapped_to_local {
-        mapped = format!("{}.local.", prefix);
-    }
-
-    Ok(Name::from_utf8(&mapped)?)
+fn map_query_to_local(name: &Name, zone: &Name) -> Result<Name, Box<dyn std::error::Error + Send + Sync>> {
+    let local = Name::from_ascii("local.")?;
+    let lower = name.to_lowercase();
+
+    // Work on raw labels so instance names with spaces or escapes survive the mapping
+    Ok(names::replace_zone(&lower, zone, &local)?.unwrap_or(lower))
+}
+
+fn rewrite_name_to_discovery(name: &Name, zone: &Name) -> Result<Name, Box<dyn std::error::Error + Send + Sync>> {
+    let local = Name::from_ascii("local.")?;
+    Ok(names::replace_zone(name, &local, zone)?.unwrap_or_else(|| name.clone()))
 }
 
-fn rewrite_name_to_discovery(name: &Name, discovery_domain: &str) -> Result<Name, Box<dyn std::error::Error + Send + Sync>> {
-    let mut n = name.to_utf8();
-    let disc = discovery_domain.to_lowercase();
-    let local = "local.";
-    let lower = n.to_lowercase();
-    if lower.ends_with(local) {
-        n.truncate(n.len() - local.len());
-        n.push_str(&disc);
-        return Ok(Name::from_utf8(&n)?);
-    }
-    Ok(name.clone())
+/// `svcb` with its target name rewritten into `zone`
+fn rewrite_svcb_target(svcb: &SVCB, zone: &Name) -> Result<SVCB, Box<dyn std::error::Error + Send + Sync>> {
+    let target = rewrite_name_to_discovery(svcb.target_name(), zone)?;
+    Ok(SVCB::new(svcb.svc_priority(), target, svcb.svc_params().to_vec()))
 }
 
-fn rewrite_records_to_discovery_domain(records: Vec<Record>, discovery_domain: &str) -> Result<Vec<Record>, Box<dyn std::error::Error + Send + Sync>> {
+/// Rewrite `.local.` names in `records` (owners and targets) into `discovery_domain`, keeping TTLs
+pub fn rewrite_records_to_discovery_domain(records: Vec<Record>, discovery_domain: &Name) -> Result<Vec<Record>, Box<dyn std::error::Error + Send + Sync>> {
     let mut out = Vec::with_capacity(records.len());
     for record in records.into_iter() {
         let mut new_record = record.clone();
@@ -184,6 +1084,12 @@ fn rewrite_records_to_discovery_domain(records: Vec<Record>, discovery_domain: &
                 let target = rewrite_name_to_discovery(&ns.0, discovery_domain)?;
                 Some(RData::NS(hickory_proto::rr::rdata::NS(target)))
             }
+            RData::CNAME(cname) => {
+                let target = rewrite_name_to_discovery(&cname.0, discovery_domain)?;
+                Some(RData::CNAME(hickory_proto::rr::rdata::CNAME(target)))
+            }
+            RData::SVCB(svcb) => Some(RData::SVCB(rewrite_svcb_target(svcb, discovery_domain)?)),
+            RData::HTTPS(https) => Some(RData::HTTPS(hickory_proto::rr::rdata::HTTPS(rewrite_svcb_target(&https.0, discovery_domain)?))),
             RData::SOA(soa) => {
                 let mname = rewrite_name_to_discovery(soa.mname(), discovery_domain)?;
                 let rname = rewrite_name_to_discovery(soa.rname(), discovery_domain)?;
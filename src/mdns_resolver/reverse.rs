@@ -0,0 +1,137 @@
+//! Reverse lookups (`in-addr.arpa` and `ip6.arpa` PTR)
+//!
+//! `dig -x` asks for the PTR record of an address's reverse name. The proxy
+//! first looks for the address among the A and AAAA records it has cached, then
+//! asks the link with a [one-shot query](super::oneshot), which mdns-sd cannot
+//! send. Only private, link-local and unique local addresses are looked up;
+//! nothing else can be on the link.
+//!
+//! `ip6.arpa` names hold the address as 32 nibbles, least significant first.
+//! Their query goes out on the interfaces with an IPv6 link-local address
+//! (those of `mdns.interfaces` when set). A link-local address carries no scope
+//! in a reverse name, so when hosts on several links use the same one, all of
+//! them are answered.
+
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+use super::oneshot;
+
+/// Address whose reverse name `name` is; None for other names and partial reverse names
+pub fn address_of(name: &Name) -> Option<IpAddr> {
+    let text = name.to_lowercase().to_utf8();
+    let text = text.trim_end_matches('.');
+    if let Some(octets) = text.strip_suffix(".in-addr.arpa") {
+        let octets: Vec<u8> = octets.split('.').map(octet).collect::<Option<_>>()?;
+        let [d, c, b, a] = octets[..] else {
+            return None;
+        };
+        return Some(IpAddr::V4(Ipv4Addr::new(a, b, c, d)));
+    }
+    let nibbles = text.strip_suffix(".ip6.arpa")?;
+    let nibbles: Vec<u8> = nibbles
+        .split('.')
+        .map(|nibble| match nibble.len() {
+            1 => u8::from_str_radix(nibble, 16).ok(),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+    if nibbles.len() != 32 {
+        return None;
+    }
+    let bits = nibbles.iter().rev().fold(0u128, |bits, &nibble| (bits << 4) | u128::from(nibble));
+    Some(IpAddr::V6(Ipv6Addr::from(bits)))
+}
+
+/// Value of an `in-addr.arpa` label written as the decimal octet: no sign and
+/// no leading zeros, so each address has one reverse name
+fn octet(label: &str) -> Option<u8> {
+    if !label.bytes().all(|b| b.is_ascii_digit()) || (label.len() > 1 && label.starts_with('0')) {
+        return None;
+    }
+    label.parse().ok()
+}
+
+/// Whether `addr` can belong to a device on the link
+pub fn is_local_address(addr: &IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(v4) => v4.is_private() || v4.is_link_local(),
+        IpAddr::V6(v6) => v6.is_unicast_link_local() || v6.is_unique_local(),
+    }
+}
+
+/// Owner names of the A or AAAA records among `records` holding `addr`
+pub fn hosts_with_address(records: &[Record], addr: IpAddr) -> Vec<Name> {
+    let mut hosts: Vec<Name> = Vec::new();
+    for record in records {
+        if record.data().ip_addr() == Some(addr) && !hosts.contains(record.name()) {
+            hosts.push(record.name().clone());
+        }
+    }
+    hosts
+}
+
+/// Ask the link for the PTR records of reverse name `name` (of `addr`) with a
+/// one-shot query; IPv6 queries go out on each interface in `scopes` (see
+/// [`oneshot::ipv6_scopes`]). Returns the host names found.
+pub async fn query(name: &Name, addr: IpAddr, scopes: &[u32], timeout: Duration) -> std::io::Result<Vec<Name>> {
+    let destinations = oneshot::destinations(addr.is_ipv6(), scopes);
+    let mut hosts: Vec<Name> = Vec::new();
+    for record in oneshot::query(name, RecordType::PTR, &destinations, timeout).await? {
+        if let RData::PTR(ptr) = record.data()
+            && !hosts.contains(&ptr.0)
+        {
+            hosts.push(ptr.0.clone());
+        }
+    }
+    Ok(hosts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::rdata::{A, AAAA};
+
+    fn name(text: &str) -> Name {
+        Name::from_utf8(text).unwrap()
+    }
+
+    #[test]
+    fn test_reverse_names_and_cached_hosts() {
+        assert_eq!(address_of(&name("50.1.168.192.in-addr.arpa.")), Some("192.168.1.50".parse().unwrap()));
+        assert_eq!(address_of(&name("50.1.168.192.IN-ADDR.ARPA")), Some("192.168.1.50".parse().unwrap()));
+        assert_eq!(address_of(&name("1.168.192.in-addr.arpa.")), None);
+        assert_eq!(address_of(&name("300.1.168.192.in-addr.arpa.")), None);
+        assert_eq!(address_of(&name("0.1.168.192.in-addr.arpa.")), Some("192.168.1.0".parse().unwrap()));
+        assert_eq!(address_of(&name("050.1.168.192.in-addr.arpa.")), None);
+        // Decoded from the wire, a label may hold any byte
+        let signed = Name::from_labels([&b"+50"[..], b"1", b"168", b"192", b"in-addr", b"arpa"]).unwrap();
+        assert_eq!(address_of(&signed), None);
+        let v6 = "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.e.f.ip6.arpa.";
+        assert_eq!(address_of(&name(v6)), Some("fe80::1".parse().unwrap()));
+        assert_eq!(address_of(&name("printer.mdns.home.arpa.")), None);
+
+        assert!(is_local_address(&"192.168.1.50".parse().unwrap()));
+        assert!(is_local_address(&"fe80::1".parse().unwrap()));
+        assert!(!is_local_address(&"8.8.8.8".parse().unwrap()));
+
+        let records = [
+            Record::from_rdata(name("printer.local."), 120, RData::A(A::new(192, 168, 1, 50))),
+            Record::from_rdata(name("printer.local."), 120, RData::AAAA(AAAA::new(0xfe80, 0, 0, 0, 0, 0, 0, 1))),
+            Record::from_rdata(name("nas.local."), 120, RData::A(A::new(192, 168, 1, 51))),
+        ];
+        assert_eq!(hosts_with_address(&records, "192.168.1.50".parse().unwrap()), vec![name("printer.local.")]);
+        assert_eq!(hosts_with_address(&records, "fe80::1".parse().unwrap()), vec![name("printer.local.")]);
+        assert!(hosts_with_address(&records, "192.168.1.52".parse().unwrap()).is_empty());
+
+        // Nibbles are hex digits, least significant first, in either case
+        let ula = "B.A.9.8.7.6.5.4.3.2.1.0.f.e.d.c.0.0.0.0.0.0.0.0.0.0.d.c.b.a.d.f.ip6.arpa.";
+        assert_eq!(address_of(&name(ula)), Some("fdab:cd00::cdef:123:4567:89ab".parse().unwrap()));
+        assert!(is_local_address(&address_of(&name(ula)).unwrap()));
+        assert_eq!(address_of(&name(&v6[2..])), None);
+        assert_eq!(address_of(&name(&v6.replacen('1', "10", 1))), None);
+        assert_eq!(address_of(&name(&v6.replacen('1', "g", 1))), None);
+        assert!(!is_local_address(&"2001:db8::1".parse().unwrap()));
+    }
+}
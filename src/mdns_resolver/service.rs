@@ -0,0 +1,140 @@
+//! Typed view of resolved DNS-SD service instances
+//!
+//! For library consumers that want discovery data rather than DNS records:
+//! [`ServiceInstance`] carries the instance name, target host, port, addresses
+//! and TXT properties of one resolved instance, as returned by
+//! [`MdnsResolver::resolve_service`](super::MdnsResolver::resolve_service).
+
+use mdns_sd::ResolvedService;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Value of a TXT property (RFC 6763 Section 6.4)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxtValue {
+    /// Key present without '=': a boolean attribute that is true
+    Flag,
+    /// Value that is valid UTF-8 (possibly empty)
+    Text(String),
+    /// Value that is not UTF-8
+    Binary(Vec<u8>),
+}
+
+/// One resolved DNS-SD service instance
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceInstance {
+    /// Instance label, e.g. "Office Printer"
+    pub name: String,
+    /// Service type with its domain, e.g. "_ipp._tcp.local."
+    pub service_type: String,
+    /// Target host of the SRV record, e.g. "printer.local."
+    pub host: String,
+    pub port: u16,
+    /// Addresses of the target host, IPv4 first
+    pub addresses: Vec<IpAddr>,
+    /// TXT properties keyed by lowercased key, since keys are case-insensitive
+    pub properties: BTreeMap<String, TxtValue>,
+}
+
+impl ServiceInstance {
+    /// Text value of property `key`, if present and UTF-8
+    pub fn text(&self, key: &str) -> Option<&str> {
+        match self.properties.get(&key.to_ascii_lowercase())? {
+            TxtValue::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Whether property `key` is present, with or without a value
+    pub fn has(&self, key: &str) -> bool {
+        self.properties.contains_key(&key.to_ascii_lowercase())
+    }
+
+    /// Value of property `key` parsed as `T` (e.g. a number), if present and valid
+    pub fn parse<T: FromStr>(&self, key: &str) -> Option<T> {
+        self.text(key)?.parse().ok()
+    }
+}
+
+impl From<&ResolvedService> for ServiceInstance {
+    fn from(info: &ResolvedService) -> Self {
+        let fullname = info.get_fullname();
+        let name = fullname
+            .strip_suffix(info.ty_domain.as_str())
+            .map_or(fullname, |name| name.trim_end_matches('.'))
+            .to_string();
+
+        let mut addresses: Vec<IpAddr> = info.get_addresses().iter().map(|addr| addr.to_ip_addr()).collect();
+        addresses.sort_by_key(|addr| (addr.is_ipv6(), *addr));
+        addresses.dedup();
+
+        // The first occurrence of a key wins (RFC 6763 Section 6.4)
+        let mut properties = BTreeMap::new();
+        for property in info.get_properties().iter() {
+            let value = match property.val() {
+                None => TxtValue::Flag,
+                Some(bytes) => match std::str::from_utf8(bytes) {
+                    Ok(text) => TxtValue::Text(text.to_string()),
+                    Err(_) => TxtValue::Binary(bytes.to_vec()),
+                },
+            };
+            properties.entry(property.key().to_ascii_lowercase()).or_insert(value);
+        }
+
+        Self {
+            name,
+            service_type: info.ty_domain.clone(),
+            host: info.get_hostname().to_string(),
+            port: info.get_port(),
+            addresses,
+            properties,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mdns_sd::{ServiceInfo, TxtProperty};
+
+    #[test]
+    fn test_instance_from_resolved_service() {
+        let properties: Vec<TxtProperty> = vec![
+            ("rp", "ipp/print").into(),
+            ("PDL", "application/pdf").into(),
+            ("qtotal", "2").into(),
+            ("Color", "").into(),
+            "duplex".into(),
+        ];
+        let info = ServiceInfo::new(
+            "_ipp._tcp.local.",
+            "Office Printer",
+            "printer.local.",
+            "fe80::20,192.168.1.20",
+            631,
+            properties,
+        )
+        .unwrap()
+        .as_resolved_service();
+
+        let instance = ServiceInstance::from(&info);
+        assert_eq!(instance.name, "Office Printer");
+        assert_eq!(instance.service_type, "_ipp._tcp.local.");
+        assert_eq!(instance.host, "printer.local.");
+        assert_eq!(instance.port, 631);
+        assert_eq!(
+            instance.addresses,
+            vec!["192.168.1.20".parse::<IpAddr>().unwrap(), "fe80::20".parse().unwrap()]
+        );
+
+        assert_eq!(instance.text("RP"), Some("ipp/print"));
+        assert_eq!(instance.text("pdl"), Some("application/pdf"));
+        assert_eq!(instance.parse::<u32>("qtotal"), Some(2));
+        assert_eq!(instance.properties["color"], TxtValue::Text(String::new()));
+        assert_eq!(instance.properties["duplex"], TxtValue::Flag);
+        assert!(instance.has("Duplex"));
+        assert_eq!(instance.text("duplex"), None);
+        assert!(!instance.has("missing"));
+    }
+}
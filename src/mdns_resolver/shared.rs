@@ -0,0 +1,74 @@
+//! Cache shared between instances of a cluster
+//!
+//! Instances behind one anycast address or VIP each hold their own cache, so a
+//! fail-over would otherwise land clients on a cold instance that has to query
+//! mDNS for everything again. Each instance publishes the entries it caches
+//! through a [`SharedCache`] backend and stores the entries the others publish.
+//! A starting instance asks the others for their warm entries.
+//!
+//! [`GossipCache`](super::gossip::GossipCache) is the built-in backend (not in
+//! the `minimal` build).
+
+use async_trait::async_trait;
+use hickory_proto::rr::Record;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+use super::MdnsResolver;
+
+/// A cache entry as shared between instances
+#[derive(Debug, Clone, PartialEq)]
+pub struct SharedEntry {
+    /// Cache key, owner name and record type
+    pub key: String,
+    pub records: Vec<Record>,
+}
+
+/// Something another instance sent
+#[derive(Debug, Clone, PartialEq)]
+pub enum SharedMessage {
+    /// An entry to cache
+    Entry(SharedEntry),
+    /// A request for this instance's fresh entries
+    Sync { from: SocketAddr },
+}
+
+/// Backend carrying cache entries between instances
+#[async_trait]
+pub trait SharedCache: Send + Sync {
+    /// Send a newly cached entry to the other instances; must not block
+    fn publish(&self, entry: &SharedEntry);
+
+    /// Ask the other instances for their fresh entries
+    fn request_sync(&self);
+
+    /// Answer a sync request from `to`
+    async fn send_entries(&self, to: SocketAddr, entries: &[SharedEntry]);
+
+    /// Wait for the next message from another instance
+    async fn recv(&self) -> io::Result<SharedMessage>;
+}
+
+/// Store what other instances share, and answer their sync requests
+pub async fn run(resolver: Arc<MdnsResolver>) {
+    let Some(shared) = resolver.cache.shared().cloned() else {
+        return;
+    };
+    shared.request_sync();
+    loop {
+        match shared.recv().await {
+            Ok(SharedMessage::Entry(entry)) => {
+                debug!("Received shared cache entry {}", entry.key);
+                resolver.cache.insert_shared(entry);
+            }
+            Ok(SharedMessage::Sync { from }) => {
+                let entries = resolver.cache.fresh_entries();
+                debug!("Sending {} cache entries to {}", entries.len(), from);
+                shared.send_entries(from, &entries).await;
+            }
+            Err(e) => warn!("Shared cache receive failed: {}", e),
+        }
+    }
+}
@@ -0,0 +1,293 @@
+//! Export and import of learned state
+//!
+//! The record cache and the last known addresses of Wake-on-LAN devices are
+//! written as one JSON document, so an upgrade or a move to another host can
+//! start warm. The format is versioned and independent of the in-memory layout:
+//! names (with `\DDD` escapes) and record types in presentation format, and RDATA in the
+//! RFC 3597 generic form (`\# <length> <hex>`), which round-trips every type.
+//!
+//! Entries carry the Unix time they were cached at, so time spent between export
+//! and import counts against their TTL. Version 1 stored an age relative to the
+//! export instead; such snapshots are migrated assuming they are imported right
+//! after being exported.
+//!
+//! ```json
+//! {"format":"mdns-dns-proxy-snapshot","version":"2.0",
+//!  "cache":[{"name":"printer.local.","type":"A","cached_at":1760700000,
+//!            "records":[{"name":"printer.local.","type":"A","class":"IN","ttl":120,"rdata":"\\# 4 c0a80114"}]}],
+//!  "last_known":[{"host":"nas.local.","records":[...]}]}
+//! ```
+
+use hickory_proto::rr::{DNSClass, RData, Record, RecordType};
+use hickory_proto::serialize::binary::{BinDecoder, BinEncodable, Restrict};
+use crate::versioned::{Format, FormatVersion};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::names;
+
+/// Snapshot document format; older major versions are migrated on import
+pub const SNAPSHOT_FORMAT: Format = Format {
+    name: "mdns-dns-proxy-snapshot",
+    current: FormatVersion::new(2, 0),
+    migrations: &[v1_to_v2],
+};
+
+type SnapshotResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Learned state of a resolver
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub format: String,
+    pub version: FormatVersion,
+    #[serde(default)]
+    pub cache: Vec<CachedRrset>,
+    #[serde(default)]
+    pub last_known: Vec<LastKnownHost>,
+}
+
+/// A cache entry and when it was cached
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedRrset {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub record_type: String,
+    /// Unix time in seconds
+    pub cached_at: u64,
+    pub records: Vec<SnapshotRecord>,
+}
+
+/// Last known address records of a Wake-on-LAN device
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LastKnownHost {
+    pub host: String,
+    pub records: Vec<SnapshotRecord>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotRecord {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub record_type: String,
+    pub class: String,
+    pub ttl: u32,
+    pub rdata: String,
+}
+
+impl Snapshot {
+    pub fn new() -> Self {
+        Self {
+            format: SNAPSHOT_FORMAT.name.to_string(),
+            version: SNAPSHOT_FORMAT.current,
+            cache: Vec::new(),
+            last_known: Vec::new(),
+        }
+    }
+
+    /// Single-line JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("snapshot serializes")
+    }
+
+    /// Read a snapshot written by this or an earlier version
+    pub fn from_json(json: &str) -> SnapshotResult<Self> {
+        SNAPSHOT_FORMAT.read(json)
+    }
+}
+
+impl CachedRrset {
+    /// Time elapsed since the entry was cached; zero for times in the future
+    pub fn age(&self) -> Duration {
+        let cached_at = UNIX_EPOCH + Duration::from_secs(self.cached_at);
+        SystemTime::now().duration_since(cached_at).unwrap_or_default()
+    }
+}
+
+/// Unix time `age` ago
+pub fn cached_at(age: Duration) -> u64 {
+    SystemTime::now()
+        .checked_sub(age)
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Version 2 replaced the export-relative `age_secs` with an absolute `cached_at`
+fn v1_to_v2(document: &mut serde_json::Map<String, Value>) -> SnapshotResult<()> {
+    let Some(Value::Array(entries)) = document.get_mut("cache") else {
+        return Ok(());
+    };
+    for entry in entries {
+        let entry = entry.as_object_mut().ok_or("cache entry is not an object")?;
+        let age = entry
+            .remove("age_secs")
+            .and_then(|age| age.as_u64())
+            .ok_or("cache entry without age_secs")?;
+        entry.insert("cached_at".to_string(), Value::from(cached_at(Duration::from_secs(age))));
+    }
+    Ok(())
+}
+
+impl Default for Snapshot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SnapshotRecord {
+    pub fn from_record(record: &Record) -> SnapshotResult<Self> {
+        let rdata = record.data().to_bytes()?;
+        let hex: String = rdata.iter().map(|b| format!("{:02x}", b)).collect();
+        Ok(Self {
+            name: names::presentation(record.name()),
+            record_type: type_to_str(record.record_type()),
+            class: class_to_str(record.dns_class()),
+            ttl: record.ttl(),
+            rdata: format!("\\# {} {}", rdata.len(), hex),
+        })
+    }
+
+    pub fn to_record(&self) -> SnapshotResult<Record> {
+        let name = names::from_presentation(&self.name)?;
+        let record_type = type_from_str(&self.record_type)?;
+        let bytes = parse_generic_rdata(&self.rdata)?;
+        let length = u16::try_from(bytes.len()).map_err(|_| "rdata too long")?;
+        let rdata = RData::read(&mut BinDecoder::new(&bytes), record_type, Restrict::new(length))?;
+        let mut record = Record::from_rdata(name, self.ttl, rdata);
+        record.set_dns_class(class_from_str(&self.class)?);
+        Ok(record)
+    }
+}
+
+/// Mnemonic if there is one, else the RFC 3597 `TYPE<n>` form
+pub fn type_to_str(record_type: RecordType) -> String {
+    match record_type {
+        RecordType::Unknown(code) => format!("TYPE{}", code),
+        known => known.to_string(),
+    }
+}
+
+pub fn type_from_str(value: &str) -> SnapshotResult<RecordType> {
+    let value = value.to_ascii_uppercase();
+    match value.strip_prefix("TYPE").map(u16::from_str) {
+        Some(Ok(code)) => Ok(RecordType::from(code)),
+        _ => Ok(RecordType::from_str(&value)?),
+    }
+}
+
+fn class_to_str(class: DNSClass) -> String {
+    match class {
+        DNSClass::IN | DNSClass::CH | DNSClass::HS | DNSClass::NONE | DNSClass::ANY => class.to_string(),
+        other => format!("CLASS{}", u16::from(other)),
+    }
+}
+
+fn class_from_str(value: &str) -> SnapshotResult<DNSClass> {
+    let value = value.to_ascii_uppercase();
+    match value.strip_prefix("CLASS").map(u16::from_str) {
+        Some(Ok(code)) => Ok(DNSClass::from(code)),
+        _ => Ok(DNSClass::from_str(&value)?),
+    }
+}
+
+/// `\# <length> <hex>`, the hex optionally split by whitespace
+fn parse_generic_rdata(value: &str) -> SnapshotResult<Vec<u8>> {
+    let mut tokens = value.split_whitespace();
+    if tokens.next() != Some("\\#") {
+        return Err(format!("rdata {:?} is not in \\# <length> <hex> form", value).into());
+    }
+    let length: usize = tokens.next().ok_or("rdata length missing")?.parse()?;
+    let hex: String = tokens.collect();
+    if hex.len() != length * 2 {
+        return Err(format!("rdata length {} does not match {} hex digits", length, hex.len()).into());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| Ok(u8::from_str_radix(&hex[i..i + 2], 16)?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::Name;
+    use hickory_proto::rr::rdata::{A, PTR, SRV, TXT};
+
+    fn records() -> Vec<Record> {
+        let instance = names::name_from_labels_str("Office Printer._ipp._tcp.local.").unwrap();
+        let host = Name::from_utf8("printer.local.").unwrap();
+        vec![
+            Record::from_rdata(host.clone(), 120, RData::A(A::new(192, 168, 1, 20))),
+            Record::from_rdata(
+                Name::from_utf8("_ipp._tcp.local.").unwrap(),
+                4500,
+                RData::PTR(PTR(instance.clone())),
+            ),
+            Record::from_rdata(instance.clone(), 120, RData::SRV(SRV::new(0, 0, 631, host))),
+            Record::from_rdata(
+                instance,
+                4500,
+                RData::TXT(TXT::new(vec!["rp=ipp/print".to_string(), "note=Room 2".to_string()])),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        for record in records() {
+            let exported = SnapshotRecord::from_record(&record).unwrap();
+            let imported = exported.to_record().unwrap();
+            assert_eq!(imported, record);
+            assert_eq!(imported.name().to_utf8(), record.name().to_utf8());
+        }
+        let a = SnapshotRecord::from_record(&records()[0]).unwrap();
+        assert_eq!(a.rdata, "\\# 4 c0a80114");
+        assert_eq!((a.record_type.as_str(), a.class.as_str()), ("A", "IN"));
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let mut snapshot = Snapshot::new();
+        snapshot.cache.push(CachedRrset {
+            name: "printer.local.".to_string(),
+            record_type: "A".to_string(),
+            cached_at: cached_at(Duration::from_secs(12)),
+            records: vec![SnapshotRecord::from_record(&records()[0]).unwrap()],
+        });
+        let json = snapshot.to_json();
+        assert!(!json.contains('\n'));
+        assert_eq!(Snapshot::from_json(&json).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn test_newer_major_version_rejected() {
+        let err = Snapshot::from_json(r#"{"format":"mdns-dns-proxy-snapshot","version":"3.0","cache":[]}"#).unwrap_err();
+        assert!(err.to_string().contains("version 3.0 is newer"));
+        assert!(Snapshot::from_json("not json").is_err());
+    }
+
+    #[test]
+    fn test_version_1_migrated() {
+        let record = SnapshotRecord::from_record(&records()[0]).unwrap();
+        let v1 = format!(
+            r#"{{"version":1,"cache":[{{"name":"printer.local.","type":"A","age_secs":30,"records":[{}]}}],"last_known":[]}}"#,
+            serde_json::to_string(&record).unwrap()
+        );
+        let snapshot = Snapshot::from_json(&v1).unwrap();
+        assert_eq!(snapshot.version, SNAPSHOT_FORMAT.current);
+        let age = snapshot.cache[0].age().as_secs();
+        assert!((30..=31).contains(&age), "age {}", age);
+        assert_eq!(snapshot.cache[0].records, vec![record]);
+    }
+
+    #[test]
+    fn test_unknown_types_and_bad_rdata() {
+        assert_eq!(type_to_str(RecordType::Unknown(65_280)), "TYPE65280");
+        assert_eq!(type_from_str("type65280").unwrap(), RecordType::Unknown(65_280));
+        assert_eq!(type_from_str("srv").unwrap(), RecordType::SRV);
+        assert!(parse_generic_rdata("\\# 2 c0").is_err());
+        assert!(parse_generic_rdata("192.168.1.20").is_err());
+        assert_eq!(parse_generic_rdata("\\# 2 c0 a8").unwrap(), vec![0xc0, 0xa8]);
+    }
+}
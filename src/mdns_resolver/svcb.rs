@@ -0,0 +1,139 @@
+//! HTTPS and SVCB records synthesized from DNS-SD (RFC 9460)
+//!
+//! mDNS has no service binding records, but an `_https._tcp` instance carries
+//! everything one holds: the target host and port in its SRV record, the host's
+//! addresses, and in some responders an `alpn` TXT key. Browsers ask for the
+//! HTTPS record of the host they connect to, so an HTTPS question for a host
+//! that serves `_https._tcp` instances is answered with one ServiceMode record
+//! per instance, the host itself as the target. An SVCB question for an
+//! instance name is answered with a record pointing at its SRV target.
+
+use super::names::{self, name_from_labels_str};
+use hickory_proto::rr::rdata::svcb::{Alpn, IpHint, SvcParamKey, SvcParamValue, SVCB};
+use hickory_proto::rr::rdata::{A, AAAA, HTTPS};
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use mdns_sd::{ResolvedService, ScopedIp};
+
+/// The service type whose instances are turned into HTTPS and SVCB records
+pub const HTTPS_SERVICE: &str = "_https._tcp.local.";
+
+/// Whether a `record_type` question for `name` could be answered: HTTPS for
+/// any name, SVCB only for `_https._tcp` instance names
+pub fn applies(name: &Name, record_type: RecordType) -> bool {
+    match record_type {
+        RecordType::HTTPS => true,
+        RecordType::SVCB => names::split_instance(name).is_some_and(|(_, ty)| ty.eq_ignore_ascii_case(HTTPS_SERVICE)),
+        _ => false,
+    }
+}
+
+/// HTTPS (for a host name) or SVCB (for an instance name) records for `name`
+/// from the resolved `_https._tcp` `instances`; empty if none matches
+pub fn synthesize(
+    name: &Name,
+    record_type: RecordType,
+    instances: &[ResolvedService],
+) -> Result<Vec<Record>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut records = Vec::new();
+    for info in instances {
+        let host = name_from_labels_str(info.get_hostname())?;
+        let rdata = match record_type {
+            RecordType::HTTPS if host == *name => {
+                // The default port of the scheme is left implicit
+                let port = (info.get_port() != 443).then_some(info.get_port());
+                RData::HTTPS(HTTPS(service_binding(Name::root(), port, info)))
+            }
+            RecordType::SVCB if names::instance_matches(name, info.get_fullname(), &info.ty_domain) => {
+                RData::SVCB(service_binding(host, Some(info.get_port()), info))
+            }
+            _ => continue,
+        };
+        let record = Record::from_rdata(name.clone(), 120, rdata);
+        if !records.contains(&record) {
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+/// ServiceMode binding to `target`, with parameters in ascending key order as
+/// the wire format requires
+fn service_binding(target: Name, port: Option<u16>, info: &ResolvedService) -> SVCB {
+    let mut params = Vec::new();
+    if let Some(alpn) = info.get_property_val_str("alpn") {
+        let protocols: Vec<String> = alpn.split(',').map(str::trim).filter(|p| !p.is_empty()).map(String::from).collect();
+        if !protocols.is_empty() {
+            params.push((SvcParamKey::Alpn, SvcParamValue::Alpn(Alpn(protocols))));
+        }
+    }
+    if let Some(port) = port {
+        params.push((SvcParamKey::Port, SvcParamValue::Port(port)));
+    }
+    let (mut v4, mut v6) = (Vec::new(), Vec::new());
+    for addr in info.get_addresses() {
+        match addr {
+            ScopedIp::V4(addr) => v4.push(A::from(*addr.addr())),
+            ScopedIp::V6(addr) => v6.push(AAAA::from(*addr.addr())),
+            _ => {}
+        }
+    }
+    // Hints in a stable order, however the addresses arrived
+    v4.sort_by_key(|a| a.0);
+    v6.sort_by_key(|a| a.0);
+    if !v4.is_empty() {
+        params.push((SvcParamKey::Ipv4Hint, SvcParamValue::Ipv4Hint(IpHint(v4))));
+    }
+    if !v6.is_empty() {
+        params.push((SvcParamKey::Ipv6Hint, SvcParamValue::Ipv6Hint(IpHint(v6))));
+    }
+    SVCB::new(1, target, params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn https_instance(instance: &str, port: u16, alpn: Option<&str>) -> ResolvedService {
+        let properties: HashMap<String, String> =
+            alpn.map(|alpn| ("alpn".to_string(), alpn.to_string())).into_iter().collect();
+        mdns_sd::ServiceInfo::new(HTTPS_SERVICE, instance, "router.local.", "192.168.1.1", port, properties)
+            .unwrap()
+            .as_resolved_service()
+    }
+
+    #[test]
+    fn test_synthesize_https_and_svcb() {
+        let instances = vec![
+            https_instance("Router Admin", 443, Some("h2, http/1.1")),
+            https_instance("Router API", 8443, None),
+        ];
+        let host = Name::from_utf8("Router.local.").unwrap();
+
+        let records = synthesize(&host, RecordType::HTTPS, &instances).unwrap();
+        assert_eq!(records.len(), 2);
+        let RData::HTTPS(HTTPS(admin)) = records[0].data() else {
+            panic!("Expected HTTPS record");
+        };
+        assert_eq!(admin.svc_priority(), 1);
+        assert!(admin.target_name().is_root());
+        let alpn = SvcParamValue::Alpn(Alpn(vec!["h2".to_string(), "http/1.1".to_string()]));
+        let hint = SvcParamValue::Ipv4Hint(IpHint(vec![A::new(192, 168, 1, 1)]));
+        assert_eq!(admin.svc_params(), &[(SvcParamKey::Alpn, alpn), (SvcParamKey::Ipv4Hint, hint.clone())]);
+        let RData::HTTPS(HTTPS(api)) = records[1].data() else {
+            panic!("Expected HTTPS record");
+        };
+        assert_eq!(api.svc_params(), &[(SvcParamKey::Port, SvcParamValue::Port(8443)), (SvcParamKey::Ipv4Hint, hint)]);
+
+        let instance = names::name_from_labels_str("Router API._https._tcp.local.").unwrap();
+        assert!(applies(&instance, RecordType::SVCB));
+        let records = synthesize(&instance, RecordType::SVCB, &instances).unwrap();
+        assert_eq!(records.len(), 1);
+        assert!(matches!(records[0].data(), RData::SVCB(svcb) if svcb.target_name() == &host));
+
+        // Other hosts, other types and instances of other services have nothing
+        assert!(synthesize(&Name::from_utf8("nas.local.").unwrap(), RecordType::HTTPS, &instances).unwrap().is_empty());
+        assert!(synthesize(&host, RecordType::SVCB, &instances).unwrap().is_empty());
+        assert!(!applies(&names::name_from_labels_str("Printer._ipp._tcp.local.").unwrap(), RecordType::SVCB));
+    }
+}
@@ -1,5 +1,5 @@
 use super::*;
-use cache::{Cache, CacheEntry};
+use cache::{Cache, CacheEntry, CachedRecord};
 use crate::config::Config;
 use hickory_proto::rr::{Name, RData, Record, RecordType};
 use std::net::Ipv4Addr;
@@ -24,7 +24,7 @@ fn create_test_record(name: &str, ttl: u32) -> Record {
 
 #[test]
 fn test_cache_entry_creation() {
-    let records = vec![create_test_record("test.local", 120)];
+    let records = vec![CachedRecord::from(create_test_record("test.local", 120))];
     let entry = CacheEntry {
         records: records.clone(),
         timestamp: std::time::Instant::now(),
@@ -84,6 +84,45 @@ async fn test_cache_expiration() {
     assert!(cache.get("test.local", RecordType::A).is_none());
 }
 
+#[test]
+fn test_cache_ttls_decay_with_age() {
+    let cache = Cache::new(Duration::from_secs(100));
+    let records = vec![
+        create_test_record("test.local", 120),
+        create_test_record("test.local", 70),
+        create_test_record("test.local", 30),
+        create_test_record("test.local", 0),
+    ];
+    assert!(cache.restore("test.local", RecordType::A, records, Duration::from_secs(60)));
+
+    let ttls: Vec<u32> = cache.get("test.local", RecordType::A).unwrap().iter().map(|r| r.ttl()).collect();
+    // Limited by the 40 s left in the cache, aged by 60 s, kept alive, kept a goodbye
+    assert_eq!(ttls, vec![40, 10, 1, 0]);
+
+    // A fresh entry is served with its own TTLs up to the cache lifetime
+    cache.insert("fresh.local", RecordType::A, vec![create_test_record("fresh.local", 120)]);
+    assert_eq!(cache.get("fresh.local", RecordType::A).unwrap()[0].ttl(), 100);
+}
+
+#[tokio::test]
+async fn test_negative_entry_expires_and_yields_to_records() {
+    let cache = Cache::new(Duration::from_secs(120));
+
+    cache.insert_negative("gone.local", RecordType::A, Duration::from_millis(100));
+    assert_eq!(cache.get("gone.local", RecordType::A), Some(Vec::new()));
+    assert!(cache.get("gone.local", RecordType::AAAA).is_none());
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert!(cache.get("gone.local", RecordType::A).is_none());
+
+    // A zero SOA MINIMUM disables negative caching
+    cache.insert_negative("gone.local", RecordType::A, Duration::ZERO);
+    assert!(cache.get("gone.local", RecordType::A).is_none());
+
+    cache.insert_negative("back.local", RecordType::A, Duration::from_secs(10));
+    cache.insert("back.local", RecordType::A, vec![create_test_record("back.local", 120)]);
+    assert_eq!(cache.get("back.local", RecordType::A).unwrap().len(), 1);
+}
+
 #[tokio::test]
 async fn test_cache_multiple_entries() {
     let cache = Cache::new(Duration::from_secs(120));
@@ -136,6 +175,75 @@ async fn test_cache_cleanup_on_insert() {
     assert!(cache.get("host3.local", RecordType::A).is_some());
 }
 
+/// Backend that records what is published
+#[derive(Default)]
+struct RecordingShared {
+    published: std::sync::Mutex<Vec<shared::SharedEntry>>,
+}
+
+#[async_trait::async_trait]
+impl shared::SharedCache for RecordingShared {
+    fn publish(&self, entry: &shared::SharedEntry) {
+        self.published.lock().unwrap().push(entry.clone());
+    }
+
+    fn request_sync(&self) {}
+
+    async fn send_entries(&self, _to: std::net::SocketAddr, _entries: &[shared::SharedEntry]) {}
+
+    async fn recv(&self) -> std::io::Result<shared::SharedMessage> {
+        std::future::pending().await
+    }
+}
+
+#[tokio::test]
+async fn test_cache_shares_local_inserts_only() {
+    let cache = Cache::new(Duration::from_secs(120));
+    let backend = Arc::new(RecordingShared::default());
+    assert!(cache.attach_shared(backend.clone()));
+
+    cache.insert("host1.local", RecordType::A, vec![create_test_record("host1.local", 120)]);
+    let published = backend.published.lock().unwrap().clone();
+    assert_eq!(published.len(), 1);
+
+    // An entry received from another instance is cached but not sent back out
+    let received = shared::SharedEntry {
+        key: published[0].key.replace("host1", "host2"),
+        records: vec![create_test_record("host2.local", 120)],
+    };
+    cache.insert_shared(received);
+    assert!(cache.get("host2.local", RecordType::A).is_some());
+    assert_eq!(backend.published.lock().unwrap().len(), 1);
+    assert_eq!(cache.fresh_entries().len(), 2);
+}
+
+#[test]
+fn test_shared_entries_must_match_their_key() {
+    let cache = Cache::new(Duration::from_secs(120));
+    let record = create_test_record("host1.local", 120);
+    let rejected = [
+        // Records for another name, or of another type
+        ("bank.example.:A", vec![record.clone()]),
+        ("host1.local:AAAA", vec![record.clone()]),
+        ("host1.local", vec![record.clone()]),
+        ("host1.local:A", Vec::new()),
+    ];
+    for (key, records) in rejected {
+        cache.insert_shared(shared::SharedEntry {
+            key: key.to_string(),
+            records,
+        });
+    }
+    assert!(cache.fresh_entries().is_empty());
+
+    // Case and the trailing dot do not matter
+    cache.insert_shared(shared::SharedEntry {
+        key: "HOST1.local.:A".to_string(),
+        records: vec![record],
+    });
+    assert_eq!(cache.fresh_entries().len(), 1);
+}
+
 #[test]
 fn test_query_name_parsing() {
     // Test that Name parsing works correctly
@@ -152,7 +260,7 @@ async fn test_unsupported_record_type_returns_empty() {
     let name = Name::from_utf8("test.local").unwrap();
     
     // Test unsupported record types
-    let result = resolver.query(&name, RecordType::CNAME).await;
+    let result = resolver.query(&name, RecordType::HINFO).await;
     assert!(result.is_ok());
     assert!(result.unwrap().is_empty());
     
@@ -175,7 +283,7 @@ async fn test_non_local_domain_returns_empty() {
 
 #[test]
 fn test_cache_entry_clone() {
-    let records = vec![create_test_record("test.local", 120)];
+    let records = vec![CachedRecord::from(create_test_record("test.local", 120))];
     let entry = CacheEntry {
         records: records.clone(),
         timestamp: std::time::Instant::now(),
@@ -187,7 +295,7 @@ fn test_cache_entry_clone() {
 
 #[test]
 fn test_cache_entry_debug() {
-    let records = vec![create_test_record("test.local", 120)];
+    let records = vec![CachedRecord::from(create_test_record("test.local", 120))];
     let entry = CacheEntry {
         records,
         timestamp: std::time::Instant::now(),
@@ -393,3 +501,638 @@ async fn test_cache_record_type_aware() {
     assert_eq!(cached_aaaa.unwrap().len(), 1);
     assert!(cached_ptr.is_none()); // PTR was never cached
 }
+
+fn create_test_service(props: &[(&str, &str)]) -> mdns_sd::ResolvedService {
+    let properties: std::collections::HashMap<String, String> = props
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    mdns_sd::ServiceInfo::new(
+        "_ipp._tcp.local.",
+        "Printer",
+        "printer.local.",
+        "192.168.1.20",
+        631,
+        properties,
+    )
+    .unwrap()
+    .as_resolved_service()
+}
+
+#[test]
+fn test_instance_records_derives_requested_kinds() {
+    use crate::config::ServiceRecordKind;
+
+    let info = create_test_service(&[("rp", "ipp/print")]);
+    let records = query::instance_records(
+        &info,
+        &[ServiceRecordKind::Srv, ServiceRecordKind::Txt, ServiceRecordKind::A, ServiceRecordKind::Aaaa],
+    )
+    .unwrap();
+
+    // No IPv6 address was advertised, so no AAAA record
+    assert_eq!(records.len(), 3);
+    assert!(records.iter().any(|r| r.record_type() == RecordType::SRV
+        && r.name().to_utf8() == "Printer._ipp._tcp.local."));
+    assert!(records.iter().any(|r| r.record_type() == RecordType::TXT));
+    assert!(records.iter().any(|r| r.record_type() == RecordType::A
+        && r.name().to_utf8() == "printer.local."));
+}
+
+#[test]
+fn test_repeated_instance_merges_addresses() {
+    let mut instances = Vec::new();
+    assert!(query::merge_instance(&mut instances, &create_test_service(&[])));
+
+    // The same printer resolved on a second link, under another address
+    let other_link = mdns_sd::ServiceInfo::new(
+        "_ipp._tcp.local.",
+        "printer",
+        "printer.local.",
+        "10.0.5.20",
+        631,
+        None,
+    )
+    .unwrap()
+    .as_resolved_service();
+    assert!(!query::merge_instance(&mut instances, &other_link));
+
+    assert_eq!(instances.len(), 1);
+    let addresses = crate::mdns_resolver::ServiceInstance::from(&instances[0]).addresses;
+    assert_eq!(addresses, vec!["10.0.5.20".parse::<std::net::IpAddr>().unwrap(), "192.168.1.20".parse().unwrap()]);
+}
+
+#[test]
+fn test_link_local_instance_hidden_from_other_links() {
+    use hickory_proto::rr::rdata::PTR;
+
+    let resolver = MdnsResolver::new(Arc::new(Config::default())).unwrap();
+    let zone = Name::from_utf8("mdns.home.arpa.").unwrap();
+    let sensor = mdns_sd::ServiceInfo::new("_hap._tcp.local.", "Sensor", "sensor.local.", "169.254.7.1", 8080, None)
+        .unwrap()
+        .as_resolved_service();
+    resolver.record_links(&zone, &[sensor, create_test_service(&[])]);
+
+    let ptr = |service: &str, instance: &str| {
+        Record::from_rdata(
+            Name::from_utf8(service).unwrap(),
+            10,
+            RData::PTR(PTR(Name::from_utf8(instance).unwrap())),
+        )
+    };
+    let records = vec![
+        ptr("_hap._tcp.mdns.home.arpa.", "Sensor._hap._tcp.mdns.home.arpa."),
+        ptr("_ipp._tcp.mdns.home.arpa.", "Printer._ipp._tcp.mdns.home.arpa."),
+    ];
+    // A client on none of this host's links cannot reach a link-local-only instance
+    let remote = resolver.filter_other_links(records.clone(), "203.0.113.7".parse().unwrap());
+    assert_eq!(remote, records[1..]);
+    // This host itself reaches every link
+    assert_eq!(resolver.filter_other_links(records.clone(), "127.0.0.1".parse().unwrap()), records);
+}
+
+#[test]
+fn test_instance_records_skips_empty_txt() {
+    use crate::config::ServiceRecordKind;
+
+    let info = create_test_service(&[]);
+    let records = query::instance_records(&info, &[ServiceRecordKind::Txt]).unwrap();
+    assert!(records.is_empty());
+}
+
+#[test]
+fn test_co_resolution_caches_all_instance_records() {
+    let resolver = MdnsResolver::new(create_test_config(60)).unwrap();
+    let zone = Name::from_utf8("mdns.home.arpa.").unwrap();
+    let info = create_test_service(&[("rp", "ipp/print")]);
+
+    resolver.prefetch("_ipp._tcp.local.", &zone, &[info]).unwrap();
+
+    let instance = names::cache_key(&Name::from_utf8("Printer._ipp._tcp.mdns.home.arpa.").unwrap());
+    let host = names::cache_key(&Name::from_utf8("printer.mdns.home.arpa.").unwrap());
+    assert!(resolver.cache.get(&instance, RecordType::SRV).is_some());
+    assert!(resolver.cache.get(&instance, RecordType::TXT).is_some());
+    let a = resolver.cache.get(&host, RecordType::A).unwrap();
+    assert!(a.iter().all(|r| r.ttl() <= 10));
+}
+
+#[tokio::test]
+async fn test_known_services_answered_without_mdns() {
+    let mut config = Config::default();
+    config.known_services.services.push(crate::config::KnownService {
+        service_type: "_ipp._tcp".to_string(),
+        name: "Office Printer".to_string(),
+        host: "printer".to_string(),
+        port: 631,
+        txt: Vec::new(),
+    });
+    let resolver = MdnsResolver::new(Arc::new(config)).unwrap();
+    let zone = Name::from_utf8("mdns.home.arpa.").unwrap();
+
+    let started = std::time::Instant::now();
+    let ptr = resolver
+        .query_in_zone(&Name::from_utf8("_ipp._tcp.mdns.home.arpa.").unwrap(), &zone, RecordType::PTR)
+        .await
+        .unwrap();
+    let srv = resolver
+        .query_in_zone(&names::name_from_labels_str("Office Printer._ipp._tcp.mdns.home.arpa.").unwrap(), &zone, RecordType::SRV)
+        .await
+        .unwrap();
+    // Well under the service query timeout: no browse was waited on
+    assert!(started.elapsed() < Duration::from_millis(500));
+
+    assert_eq!(ptr.len(), 1);
+    let instance = names::name_from_labels_str("Office Printer._ipp._tcp.mdns.home.arpa.").unwrap();
+    assert!(matches!(ptr[0].data(), RData::PTR(p) if p.0 == instance));
+    assert!(matches!(srv[0].data(), RData::SRV(s) if s.target().to_utf8() == "printer.mdns.home.arpa." && s.port() == 631));
+    assert!(srv[0].ttl() <= 10);
+}
+
+#[test]
+fn test_co_resolution_can_be_disabled() {
+    let mut config = Config::default();
+    config.mdns.co_resolve = false;
+    let resolver = MdnsResolver::new(Arc::new(config)).unwrap();
+    let zone = Name::from_utf8("mdns.home.arpa.").unwrap();
+
+    resolver.prefetch("_ipp._tcp.local.", &zone, &[create_test_service(&[])]).unwrap();
+
+    let instance = names::cache_key(&Name::from_utf8("Printer._ipp._tcp.mdns.home.arpa.").unwrap());
+    assert!(resolver.cache.get(&instance, RecordType::SRV).is_none());
+}
+
+#[tokio::test]
+async fn test_prefetch_other_address_family() {
+    let mut config = Config::default();
+    config.mdns.hostname_resolution_timeout_ms = 200;
+    let resolver = Arc::new(MdnsResolver::new(Arc::new(config.clone())).unwrap());
+    let zone = Name::from_utf8("mdns.home.arpa.").unwrap();
+    let host = Name::from_utf8("nowhere-4522.mdns.home.arpa.").unwrap();
+    let key = names::cache_key(&host);
+
+    assert!(resolver.prefetch_other_family(&host, &zone, RecordType::A));
+    // One lookup per name at a time, and none for other types
+    assert!(!resolver.prefetch_other_family(&host, &zone, RecordType::A));
+    assert!(!resolver.prefetch_other_family(&host, &zone, RecordType::TXT));
+
+    // Nobody answers, so the lookup leaves an empty AAAA answer in the cache
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert_eq!(resolver.cache.get(&key, RecordType::AAAA), Some(Vec::new()));
+    assert!(resolver.cache.get(&key, RecordType::A).is_none());
+    assert!(!resolver.prefetch_other_family(&host, &zone, RecordType::A));
+
+    config.mdns.address_family_prefetch = false;
+    let disabled = Arc::new(MdnsResolver::new(Arc::new(config)).unwrap());
+    assert!(!disabled.prefetch_other_family(&host, &zone, RecordType::AAAA));
+}
+
+#[test]
+fn test_additional_records_follow_strategy() {
+    use crate::config::{ServiceRecordKind, ServiceStrategy};
+
+    let mut config = Config::default();
+    config.strategies.insert(
+        "_ipp._tcp".to_string(),
+        ServiceStrategy {
+            additional: vec![ServiceRecordKind::Srv, ServiceRecordKind::A],
+            ..Default::default()
+        },
+    );
+    let resolver = MdnsResolver::new(Arc::new(config)).unwrap();
+
+    let instance = Name::from_utf8("Printer._ipp._tcp.mdns.home.arpa.").unwrap();
+    let host = Name::from_utf8("printer.mdns.home.arpa.").unwrap();
+    let srv = Record::from_rdata(
+        instance.clone(),
+        10,
+        RData::SRV(hickory_proto::rr::rdata::SRV::new(0, 0, 631, host.clone())),
+    );
+    resolver.cache.insert(&names::cache_key(&instance), RecordType::SRV, vec![srv]);
+    resolver.cache.insert(&names::cache_key(&host), RecordType::A, vec![create_test_record("printer.mdns.home.arpa.", 10)]);
+
+    let ptr = Record::from_rdata(
+        Name::from_utf8("_ipp._tcp.mdns.home.arpa.").unwrap(),
+        10,
+        RData::PTR(hickory_proto::rr::rdata::PTR(instance)),
+    );
+
+    let additional = resolver.additional_records(&[ptr]);
+    assert_eq!(additional.len(), 2);
+    assert!(additional.iter().any(|r| r.record_type() == RecordType::SRV));
+    assert!(additional.iter().any(|r| r.record_type() == RecordType::A));
+}
+
+#[test]
+fn test_additional_records_bundle_without_strategy() {
+    let resolver = MdnsResolver::new(create_test_config(120)).unwrap();
+    let instance = Name::from_utf8("Web._http._tcp.mdns.home.arpa.").unwrap();
+    let host = Name::from_utf8("printer.mdns.home.arpa.").unwrap();
+    let srv = Record::from_rdata(
+        instance.clone(),
+        10,
+        RData::SRV(hickory_proto::rr::rdata::SRV::new(0, 0, 80, host.clone())),
+    );
+    let txt = Record::from_rdata(
+        instance.clone(),
+        10,
+        RData::TXT(hickory_proto::rr::rdata::TXT::new(vec!["path=/".to_string()])),
+    );
+    resolver.cache.insert(&names::cache_key(&instance), RecordType::SRV, vec![srv.clone()]);
+    resolver.cache.insert(&names::cache_key(&instance), RecordType::TXT, vec![txt]);
+    resolver.cache.insert(&names::cache_key(&host), RecordType::A, vec![create_test_record("printer.mdns.home.arpa.", 10)]);
+    let ptr = Record::from_rdata(
+        Name::from_utf8("_http._tcp.mdns.home.arpa.").unwrap(),
+        10,
+        RData::PTR(hickory_proto::rr::rdata::PTR(instance)),
+    );
+
+    // A browse carries the instance's whole bundle; an SRV answer only what a strategy asks for
+    let types: Vec<RecordType> = resolver.additional_records(std::slice::from_ref(&ptr)).iter().map(Record::record_type).collect();
+    assert_eq!(types, vec![RecordType::SRV, RecordType::TXT, RecordType::A]);
+    assert!(resolver.additional_records(&[srv]).is_empty());
+
+    let mut config = (*create_test_config(120)).clone();
+    config.mdns.answer_bundles = false;
+    resolver.reload(Arc::new(config));
+    assert!(resolver.additional_records(&[ptr]).is_empty());
+}
+
+#[tokio::test]
+async fn test_query_cache_shared_between_escaped_and_raw_names() {
+    let resolver = MdnsResolver::new(create_test_config(120)).unwrap();
+
+    let raw = Name::from_labels(vec![
+        "My Printer".as_bytes(), b"_ipp", b"_tcp", b"mdns", b"home", b"arpa",
+    ])
+    .unwrap();
+    // As parsed from `dig 'my\032printer._IPP._tcp.mdns.home.arpa' TXT`
+    let escaped = names::from_presentation("my\\032printer._IPP._tcp.mdns.home.arpa.").unwrap();
+
+    let txt = Record::from_rdata(
+        raw.clone(),
+        10,
+        RData::TXT(hickory_proto::rr::rdata::TXT::new(vec!["rp=ipp/print".to_string()])),
+    );
+    resolver.cache.insert(&names::cache_key(&raw), RecordType::TXT, vec![txt]);
+
+    // A client sending the escaped spelling hits the same cache entry
+    let cached = resolver.query(&escaped, RecordType::TXT).await.unwrap();
+    assert_eq!(cached.len(), 1);
+
+    // A label holding a literal backslash names a different instance
+    let literal = Name::from_labels(vec![
+        "my\\032printer".as_bytes(), b"_ipp", b"_tcp", b"mdns", b"home", b"arpa",
+    ])
+    .unwrap();
+    assert!(resolver.cache.get(&names::cache_key(&literal), RecordType::TXT).is_none());
+}
+
+#[test]
+fn test_instance_records_use_single_instance_label() {
+    use crate::config::ServiceRecordKind;
+
+    let info = mdns_sd::ServiceInfo::new(
+        "_http._tcp.local.",
+        "Office v1.2",
+        "office.local.",
+        "192.168.1.30",
+        80,
+        None,
+    )
+    .unwrap()
+    .as_resolved_service();
+
+    let records = query::instance_records(&info, &[ServiceRecordKind::Srv]).unwrap();
+    assert_eq!(records[0].name().num_labels(), 4);
+    assert_eq!(records[0].name().iter().next().unwrap(), b"Office v1.2");
+}
+
+#[test]
+fn test_cache_tracks_memory_usage() {
+    let cache = Cache::new(Duration::from_secs(120));
+    assert_eq!(cache.memory_usage(), 0);
+
+    cache.insert("host1.local", RecordType::A, vec![create_test_record("host1.local", 120)]);
+    let one = cache.memory_usage();
+    assert!(one > 0);
+
+    cache.insert("host2.local", RecordType::A, vec![create_test_record("host2.local", 120)]);
+    assert!(cache.memory_usage() > one);
+
+    // Replacing an entry does not double count it
+    let two = cache.memory_usage();
+    cache.insert("host2.local", RecordType::A, vec![create_test_record("host2.local", 120)]);
+    assert_eq!(cache.memory_usage(), two);
+}
+
+#[test]
+fn test_cache_interns_identical_txt_data() {
+    let txt = |name: &str| {
+        let blob = vec!["model=ExampleCam-4000-with-a-very-long-model-string".to_string(); 8];
+        Record::from_rdata(
+            Name::from_utf8(name).unwrap(),
+            120,
+            RData::TXT(hickory_proto::rr::rdata::TXT::new(blob)),
+        )
+    };
+    let cache = Cache::new(Duration::from_secs(120));
+    cache.insert("cam1.local", RecordType::TXT, vec![txt("cam1.local")]);
+    let one = cache.memory_usage();
+    cache.insert("cam2.local", RecordType::TXT, vec![txt("cam2.local")]);
+
+    // The second copy of the blob costs only its record, not the data again
+    assert!(cache.memory_usage() - one < one / 2);
+    assert_eq!(cache.get("cam2.local", RecordType::TXT).unwrap(), vec![txt("cam2.local")]);
+
+    cache.remove_suffix("cam1.local");
+    assert_eq!(cache.get("cam2.local", RecordType::TXT).unwrap(), vec![txt("cam2.local")]);
+    cache.remove_suffix("cam2.local");
+    assert_eq!(cache.memory_usage(), 0);
+}
+
+#[test]
+fn test_cache_evicts_oldest_over_memory_limit() {
+    let probe = Cache::new(Duration::from_secs(120));
+    probe.insert("host0.local", RecordType::A, vec![create_test_record("host0.local", 120)]);
+    let entry_size = probe.memory_usage();
+
+    // Room for about four entries
+    let cache = Cache::new(Duration::from_secs(120)).with_memory_limit(Some(entry_size * 4 + entry_size / 2));
+    for i in 0..5 {
+        let name = format!("host{}.local", i);
+        cache.insert(&name, RecordType::A, vec![create_test_record(&name, 120)]);
+        std::thread::sleep(Duration::from_millis(2));
+    }
+
+    assert!(cache.memory_usage() <= entry_size * 4 + entry_size / 2);
+    assert!(cache.len() < 5);
+    assert!(cache.get("host0.local", RecordType::A).is_none());
+    assert!(cache.get("host4.local", RecordType::A).is_some());
+}
+
+#[test]
+fn test_negative_entries_count_against_memory_limit() {
+    let probe = Cache::new(Duration::from_secs(120));
+    probe.insert_negative("gone0.local", RecordType::A, Duration::from_secs(60));
+    let negative_size = probe.memory_usage();
+    assert!(negative_size > 0);
+    probe.insert_negative("gone0.local", RecordType::A, Duration::from_secs(60));
+    assert_eq!(probe.memory_usage(), negative_size);
+    probe.insert("gone0.local", RecordType::A, vec![create_test_record("gone0.local", 120)]);
+    probe.remove_suffix("gone0.local");
+    assert_eq!(probe.memory_usage(), 0);
+
+    // Room for about four negative entries; the ones expiring soonest go first
+    let cache = Cache::new(Duration::from_secs(120)).with_memory_limit(Some(negative_size * 4 + negative_size / 2));
+    for i in 0..5 {
+        let name = format!("gone{}.local", i);
+        cache.insert_negative(&name, RecordType::A, Duration::from_secs(60 + i));
+    }
+    assert!(cache.memory_usage() <= negative_size * 4 + negative_size / 2);
+    assert!(cache.get("gone0.local", RecordType::A).is_none());
+    assert_eq!(cache.get("gone4.local", RecordType::A), Some(Vec::new()));
+}
+
+#[test]
+fn test_purge_zone_drops_only_that_zone() {
+    let resolver = MdnsResolver::new(create_test_config(120)).unwrap();
+    let lab = Name::from_utf8("host.lab.home.arpa.").unwrap();
+    let other = Name::from_utf8("host.mdns.home.arpa.").unwrap();
+    resolver.cache.insert(&names::cache_key(&lab), RecordType::A, vec![create_test_record("host.lab.home.arpa.", 10)]);
+    resolver.cache.insert(&names::cache_key(&other), RecordType::A, vec![create_test_record("host.mdns.home.arpa.", 10)]);
+
+    let purged = resolver.purge_zone(&Name::from_utf8("lab.home.arpa.").unwrap());
+    assert_eq!(purged, 1);
+    assert!(resolver.cache.get(&names::cache_key(&lab), RecordType::A).is_none());
+    assert!(resolver.cache.get(&names::cache_key(&other), RecordType::A).is_some());
+}
+
+#[test]
+fn test_cache_records_of_type() {
+    let cache = Cache::new(Duration::from_secs(60));
+    cache.insert("host1.local", RecordType::A, vec![create_test_record("host1.local", 120)]);
+    cache.insert("host2.local", RecordType::A, vec![create_test_record("host2.local", 120)]);
+    assert_eq!(cache.records_of_type(RecordType::A).len(), 2);
+    assert!(cache.records_of_type(RecordType::SRV).is_empty());
+}
+
+#[tokio::test]
+async fn test_probe_liveness_marks_instances() {
+    use hickory_proto::rr::rdata::SRV;
+
+    let resolver = MdnsResolver::new(create_test_config(120)).unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let instance = Name::from_utf8("printer._ipp._tcp.mdns.home.arpa.").unwrap();
+    let target = Name::from_utf8("printer.mdns.home.arpa.").unwrap();
+    let srv = Record::from_rdata(instance.clone(), 10, RData::SRV(SRV::new(0, 0, port, target.clone())));
+    let address = Record::from_rdata(target, 10, RData::A(Ipv4Addr::LOCALHOST.into()));
+    resolver.cache.insert("printer._ipp._tcp.mdns.home.arpa.", RecordType::SRV, vec![srv.clone()]);
+    resolver.cache.insert("printer.mdns.home.arpa.", RecordType::A, vec![address]);
+
+    resolver.probe_liveness(Duration::from_secs(1)).await;
+    let inventory = resolver.inventory();
+    assert_eq!(inventory.len(), 1);
+    assert!(inventory[0].liveness.unwrap().alive);
+
+    drop(listener);
+    resolver.probe_liveness(Duration::from_secs(1)).await;
+    assert!(!resolver.inventory()[0].liveness.unwrap().alive);
+
+    // Dead instances are only filtered when configured to
+    assert_eq!(resolver.filter_dead(vec![srv]).len(), 1);
+}
+
+#[tokio::test]
+async fn test_resolve_service_rejects_non_instance_names() {
+    let resolver = MdnsResolver::new(create_test_config(120)).unwrap();
+    assert!(resolver.resolve_service("printer.local.").await.is_err());
+    assert!(resolver.resolve_service("_ipp._tcp.local.").await.is_err());
+}
+
+#[tokio::test]
+async fn test_malformed_service_names_are_not_browsed() {
+    let mut config = Config::default();
+    config.mdns.service_query_timeout_ms = 5000;
+    let daemon = mdns_sd::ServiceDaemon::new().unwrap();
+    let browses = browses::Browses::new(Duration::ZERO);
+    let name = |s: &str| names::name_from_labels_str(s).unwrap();
+
+    let started = std::time::Instant::now();
+    for ptr in ["printer.local.", "_dmarc.local.", "_bad--name._tcp.local.", "Printer._ipp._tcp.local."] {
+        let answer = query::query_ptr(&daemon, &browses, &name(ptr), &config).await.unwrap();
+        assert!(answer.records.is_empty() && answer.instances.is_empty(), "{}", ptr);
+    }
+    for instance in ["_ipp._tcp.local.", "Printer._this-is-far-too-long._tcp.local.", "printer.local."] {
+        assert!(query::query_srv(&daemon, &browses, &name(instance), &config).await.unwrap().records.is_empty());
+        assert!(query::query_txt(&daemon, &browses, &name(instance), &config).await.unwrap().records.is_empty());
+    }
+    // Each would otherwise have waited out the 5 s service timeout
+    assert!(started.elapsed() < Duration::from_secs(2));
+    let _ = daemon.shutdown();
+}
+
+#[tokio::test]
+async fn test_fresh_query_over_limit_answers_from_cache() {
+    let mut config = Config::default();
+    config.mdns.fresh_queries_per_minute = 0;
+    let resolver = MdnsResolver::new(Arc::new(config)).unwrap();
+    let zone = Name::from_utf8("mdns.home.arpa.").unwrap();
+    let host = Name::from_utf8("printer.mdns.home.arpa.").unwrap();
+    resolver.cache.insert(&names::cache_key(&host), RecordType::A, vec![create_test_record("printer.mdns.home.arpa.", 10)]);
+
+    let records = resolver.query_fresh_in_zone(&host, &zone, RecordType::A).await.unwrap();
+    assert_eq!(records.len(), 1);
+    assert!(records[0].ttl() > 0);
+}
+
+#[test]
+fn test_cache_canonical_order() {
+    let a = |last: u8| {
+        Record::from_rdata(
+            Name::from_utf8("host.local").unwrap(),
+            120,
+            RData::A(hickory_proto::rr::rdata::A::from(Ipv4Addr::new(192, 168, 1, last))),
+        )
+    };
+    let received = vec![a(30), a(2), a(10)];
+
+    let cache = Cache::new(Duration::from_secs(120));
+    cache.insert("host.local", RecordType::A, received.clone());
+    assert_eq!(cache.get("host.local", RecordType::A).unwrap(), received);
+
+    let cache = Cache::new(Duration::from_secs(120)).with_canonical_order(true);
+    cache.insert("host.local", RecordType::A, received);
+    assert_eq!(cache.get("host.local", RecordType::A).unwrap(), vec![a(2), a(10), a(30)]);
+}
+
+#[tokio::test]
+async fn test_watch_follows_browse_events() {
+    use mdns_sd::ServiceEvent;
+
+    let resolver = MdnsResolver::new(create_test_config(120)).unwrap();
+    let zone = Name::from_utf8("mdns.home.arpa.").unwrap();
+    let watch = |name: &str, record_type| {
+        let (events, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let name = Name::from_ascii(name).unwrap();
+        let mdns_name = names::replace_zone(&name, &zone, &Name::from_ascii("local.").unwrap()).unwrap().unwrap();
+        let watch = watch::Watch::new(&name, mdns_name, &zone, record_type, "_ipp._tcp.local.".to_string(), receiver, &[]);
+        (events, watch)
+    };
+    let resolved = || ServiceEvent::ServiceResolved(Box::new(create_test_service(&[])));
+    let removed = || ServiceEvent::ServiceRemoved("_ipp._tcp.local.".to_string(), "Printer._ipp._tcp.local.".to_string());
+
+    let (events, mut ptr) = watch("_ipp._tcp.mdns.home.arpa.", RecordType::PTR);
+    events.send(resolved()).unwrap();
+    let records = ptr.changed(&resolver).await.unwrap();
+    assert_eq!(records.len(), 1);
+    let RData::PTR(target) = records[0].data() else { panic!("not a PTR") };
+    assert_eq!(target.0.to_utf8(), "Printer._ipp._tcp.mdns.home.arpa.");
+    assert_eq!(records[0].ttl(), MAX_UNICAST_TTL);
+
+    // Resolving the same instance again changes nothing; its removal does
+    events.send(resolved()).unwrap();
+    events.send(removed()).unwrap();
+    assert!(ptr.changed(&resolver).await.unwrap().is_empty());
+
+    let (events, mut srv) = watch("Printer._ipp._tcp.mdns.home.arpa.", RecordType::SRV);
+    events.send(resolved()).unwrap();
+    let records = srv.changed(&resolver).await.unwrap();
+    let RData::SRV(data) = records[0].data() else { panic!("not an SRV") };
+    assert_eq!((data.target().to_utf8().as_str(), data.port()), ("printer.mdns.home.arpa.", 631));
+    drop(events);
+    assert!(srv.changed(&resolver).await.is_none());
+}
+
+#[tokio::test]
+async fn test_watch_follows_subtype_browse() {
+    use mdns_sd::ServiceEvent;
+
+    let resolver = MdnsResolver::new(create_test_config(120)).unwrap();
+    let zone = Name::from_utf8("mdns.home.arpa.").unwrap();
+    let subtype = "_universal._sub._ipp._tcp.local.";
+    let name = Name::from_ascii("_universal._sub._ipp._tcp.mdns.home.arpa.").unwrap();
+    let mdns_name = names::replace_zone(&name, &zone, &Name::from_ascii("local.").unwrap()).unwrap().unwrap();
+    let (events, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut sub = watch::Watch::new(&name, mdns_name, &zone, RecordType::PTR, subtype.to_string(), receiver, &[]);
+
+    // Only instances carrying the subtype are listed, named under the parent type
+    events.send(ServiceEvent::ServiceResolved(Box::new(create_test_service(&[])))).unwrap();
+    let mut info = create_test_service(&[]);
+    info.sub_ty_domain = Some(subtype.to_string());
+    events.send(ServiceEvent::ServiceResolved(Box::new(info))).unwrap();
+    let records = sub.changed(&resolver).await.unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].name().to_utf8(), "_universal._sub._ipp._tcp.mdns.home.arpa.");
+    let RData::PTR(target) = records[0].data() else { panic!("not a PTR") };
+    assert_eq!(target.0.to_utf8(), "Printer._ipp._tcp.mdns.home.arpa.");
+    assert_eq!(records[0].ttl(), MAX_UNICAST_TTL);
+
+    // The subtype browse reports the removal under the subtype
+    events.send(ServiceEvent::ServiceRemoved(subtype.to_string(), "Printer._ipp._tcp.local.".to_string())).unwrap();
+    assert!(sub.changed(&resolver).await.unwrap().is_empty());
+    drop(events);
+    assert!(sub.changed(&resolver).await.is_none());
+}
+
+#[tokio::test]
+async fn test_unsupported_type_answers_empty_without_daemon() {
+    let mut config = Config::default();
+    config.mdns.lazy_start = true;
+    let resolver = MdnsResolver::new(Arc::new(config)).unwrap();
+    let name = Name::from_utf8("printer.mdns.home.arpa.").unwrap();
+    let before = crate::metrics::metrics().queries_unsupported.load(std::sync::atomic::Ordering::Relaxed);
+
+    assert!(!resolver.capabilities().can_query(RecordType::HINFO));
+    assert!(resolver.query(&name, RecordType::HINFO).await.unwrap().is_empty());
+    assert!(!resolver.daemon.is_started());
+    if crate::metrics::ENABLED {
+        assert!(crate::metrics::metrics().queries_unsupported.load(std::sync::atomic::Ordering::Relaxed) > before);
+    }
+
+    // SOA is the proxy's own, not the backend's
+    let apex = Name::from_utf8("mdns.home.arpa.").unwrap();
+    assert_eq!(resolver.query(&apex, RecordType::SOA).await.unwrap().len(), 1);
+    assert!(!resolver.daemon.is_started());
+}
+
+#[test]
+fn test_cname_target_rewritten_to_discovery_domain() {
+    use hickory_proto::rr::rdata::CNAME;
+
+    let zone = Name::from_utf8("mdns.home.arpa.").unwrap();
+    let cname = |owner: &str, target: &str| {
+        Record::from_rdata(Name::from_utf8(owner).unwrap(), 10, RData::CNAME(CNAME(Name::from_utf8(target).unwrap())))
+    };
+    let records = rewrite_records_to_discovery_domain(
+        vec![cname("www.local.", "nas.local."), cname("docs.local.", "docs.example.com.")],
+        &zone,
+    )
+    .unwrap();
+    assert_eq!(records[0], cname("www.mdns.home.arpa.", "nas.mdns.home.arpa."));
+    // Targets outside .local are passed through
+    assert_eq!(records[1], cname("docs.mdns.home.arpa.", "docs.example.com."));
+}
+
+#[test]
+fn test_svcb_target_rewritten_to_discovery_domain() {
+    use hickory_proto::rr::rdata::svcb::{SvcParamKey, SvcParamValue, SVCB};
+    use hickory_proto::rr::rdata::HTTPS;
+
+    let zone = Name::from_utf8("mdns.home.arpa.").unwrap();
+    let params = vec![(SvcParamKey::Port, SvcParamValue::Port(8443))];
+    let svcb = |owner: &str, target: &str| {
+        let binding = SVCB::new(1, Name::from_utf8(target).unwrap(), params.clone());
+        Record::from_rdata(names::name_from_labels_str(owner).unwrap(), 10, RData::SVCB(binding))
+    };
+    let https = Record::from_rdata(
+        Name::from_utf8("router.local.").unwrap(),
+        10,
+        RData::HTTPS(HTTPS(SVCB::new(1, Name::root(), params.clone()))),
+    );
+    let records = rewrite_records_to_discovery_domain(vec![svcb("API._https._tcp.local.", "router.local."), https], &zone).unwrap();
+    assert_eq!(records[0], svcb("API._https._tcp.mdns.home.arpa.", "router.mdns.home.arpa."));
+    assert_eq!(records[1].name(), &Name::from_utf8("router.mdns.home.arpa.").unwrap());
+    assert!(matches!(records[1].data(), RData::HTTPS(HTTPS(binding)) if binding.target_name().is_root() && binding.svc_params() == &params[..]));
+}
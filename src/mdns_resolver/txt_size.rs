@@ -0,0 +1,117 @@
+//! Size limits for TXT data
+//!
+//! A character-string holds at most 255 bytes and a record's data at most
+//! 65535, but mdns-sd hands over whatever a device advertised, and a string
+//! that is too long makes the whole response fail to encode. Each TXT record is
+//! checked as it is rewritten into the discovery zone and brought within limits
+//! by its service type's `oversized_txt` policy; every change is counted and
+//! logged with the instance the data came from.
+
+use crate::config::OversizedTxt;
+use crate::metrics;
+use hickory_proto::rr::rdata::TXT;
+use hickory_proto::rr::{RData, Record};
+use tracing::warn;
+
+/// Longest character-string (RFC 1035 Section 3.3)
+pub const MAX_STRING_BYTES: usize = 255;
+
+/// `record` within limits: at most `max_bytes` of TXT data and no string over
+/// [`MAX_STRING_BYTES`]. None when the policy drops it. Records other than TXT
+/// pass unchanged.
+pub(super) fn enforce(record: Record, policy: OversizedTxt, max_bytes: usize) -> Option<Record> {
+    let RData::TXT(txt) = record.data() else {
+        return Some(record);
+    };
+    let max_bytes = max_bytes.min(u16::MAX as usize);
+    let strings = txt.txt_data();
+    let long = strings.iter().filter(|s| s.len() > MAX_STRING_BYTES).count();
+    let total: usize = strings.iter().map(|s| 1 + s.len()).sum();
+    if long == 0 && total <= max_bytes {
+        return Some(record);
+    }
+
+    let instance = record.name().to_utf8();
+    if policy == OversizedTxt::Drop {
+        metrics::inc(&metrics::metrics().txt_dropped);
+        warn!(
+            "Dropping the TXT record of {}: {} string(s) over {} bytes, {} bytes in all (limit {})",
+            instance, long, MAX_STRING_BYTES, total, max_bytes
+        );
+        return None;
+    }
+
+    let mut kept: Vec<&[u8]> = Vec::new();
+    let mut size = 0;
+    for string in strings {
+        let string: &[u8] = if string.len() <= MAX_STRING_BYTES {
+            string
+        } else if policy == OversizedTxt::Truncate {
+            &string[..MAX_STRING_BYTES]
+        } else {
+            continue;
+        };
+        if size + 1 + string.len() > max_bytes {
+            break;
+        }
+        size += 1 + string.len();
+        kept.push(string);
+    }
+    metrics::inc(&metrics::metrics().txt_truncated);
+    warn!(
+        "Shortened the TXT record of {} ({:?}): {} string(s) over {} bytes, kept {} of {} string(s) in {} of {} bytes",
+        instance,
+        policy,
+        long,
+        MAX_STRING_BYTES,
+        kept.len(),
+        strings.len(),
+        size,
+        total
+    );
+    // An empty TXT record is a single empty string (RFC 6763 Section 6.1)
+    if kept.is_empty() {
+        kept.push(b"");
+    }
+    let data = RData::TXT(TXT::from_bytes(kept));
+    let mut record = record;
+    record.set_data(data);
+    Some(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::Name;
+    use hickory_proto::serialize::binary::BinEncodable;
+
+    fn txt(strings: &[&str]) -> Record {
+        let name = Name::from_utf8("Printer._ipp._tcp.mdns.home.arpa.").unwrap();
+        Record::from_rdata(name, 10, RData::TXT(TXT::new(strings.iter().map(|s| s.to_string()).collect())))
+    }
+
+    fn strings(record: &Record) -> Vec<usize> {
+        let RData::TXT(txt) = record.data() else { panic!("not a TXT record") };
+        txt.txt_data().iter().map(|s| s.len()).collect()
+    }
+
+    #[test]
+    fn test_oversized_txt_policies() {
+        let long = format!("note={}", "x".repeat(300));
+        let record = txt(&["rp=ipp/print", &long, "ty=Office"]);
+        assert!(record.to_bytes().is_err());
+
+        let truncated = enforce(record.clone(), OversizedTxt::Truncate, 65535).unwrap();
+        assert_eq!(strings(&truncated), [12, MAX_STRING_BYTES, 9]);
+        assert!(truncated.to_bytes().is_ok());
+        let omitted = enforce(record.clone(), OversizedTxt::Omit, 65535).unwrap();
+        assert_eq!(strings(&omitted), [12, 9]);
+        assert!(enforce(record, OversizedTxt::Drop, 65535).is_none());
+
+        // Strings past the total cap are left out
+        let record = txt(&["rp=ipp/print", "ty=Office"]);
+        assert_eq!(strings(&enforce(record.clone(), OversizedTxt::Truncate, 13).unwrap()), [12]);
+        assert_eq!(strings(&enforce(record.clone(), OversizedTxt::Omit, 5).unwrap()), [0]);
+        assert_eq!(enforce(record.clone(), OversizedTxt::Drop, 1000), Some(record));
+    }
+}
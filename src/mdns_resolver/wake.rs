@@ -0,0 +1,260 @@
+//! Wake-on-LAN for sleeping devices
+//!
+//! Hosts listed under `[wake]` get a magic packet when their address is asked for
+//! and not cached. The query still goes out over mDNS; if the device has not woken
+//! in time the last addresses it answered with are served instead of nothing.
+//! Magic packets to the same host are rate limited.
+
+use crate::config::WakeConfig;
+use crate::metrics;
+use hickory_proto::rr::{Name, Record, RecordType};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+type WakeResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Devices that can be woken, keyed by lowercase `.local.` host name
+#[derive(Debug, Default)]
+pub struct WakeManager {
+    devices: HashMap<Name, [u8; 6]>,
+    broadcast_address: Option<SocketAddr>,
+    min_interval: Duration,
+    last_sent: Mutex<HashMap<Name, Instant>>,
+    /// Raw `.local.` address records from the last successful lookup
+    last_known: Mutex<HashMap<Name, Vec<Record>>>,
+}
+
+impl WakeManager {
+    pub fn from_config(config: &WakeConfig) -> WakeResult<Self> {
+        if !config.enabled {
+            return Ok(Self::default());
+        }
+        let mut devices = HashMap::new();
+        for device in &config.devices {
+            let mac = parse_mac(&device.mac)
+                .ok_or_else(|| format!("wake device {}: invalid MAC address {}", device.host, device.mac))?;
+            devices.insert(host_name(&device.host)?, mac);
+        }
+        Ok(Self {
+            devices,
+            broadcast_address: Some(config.broadcast_address),
+            min_interval: Duration::from_secs(config.min_interval_secs),
+            ..Self::default()
+        })
+    }
+
+    pub fn is_device(&self, host: &Name) -> bool {
+        self.devices.contains_key(&host.to_lowercase())
+    }
+
+    /// Send a magic packet to `host` unless one went out within the minimum interval
+    pub fn wake(&self, host: &Name) -> bool {
+        let host = host.to_lowercase();
+        let (Some(mac), Some(target)) = (self.devices.get(&host), self.broadcast_address) else {
+            return false;
+        };
+        if !self.should_send(&host, Instant::now()) {
+            debug!("Wake-on-LAN for {} rate limited", host);
+            return false;
+        }
+        match send_magic_packet(mac, target) {
+            Ok(()) => {
+                info!("Sent Wake-on-LAN magic packet for {} to {}", host, target);
+                metrics::inc(&metrics::metrics().wake_packets);
+                true
+            }
+            Err(e) => {
+                warn!("Failed to send Wake-on-LAN magic packet for {}: {}", host, e);
+                false
+            }
+        }
+    }
+
+    /// Remember the address records `host` answered with
+    pub fn remember(&self, host: &Name, records: &[Record]) {
+        if records.is_empty() {
+            return;
+        }
+        self.last_known.lock().unwrap().insert(host.to_lowercase(), records.to_vec());
+    }
+
+    /// Every host with remembered records, for export
+    pub fn remembered(&self) -> Vec<(Name, Vec<Record>)> {
+        let last_known = self.last_known.lock().unwrap();
+        last_known.iter().map(|(host, records)| (host.clone(), records.clone())).collect()
+    }
+
+    /// Last known records of `record_type` for `host`, if any
+    pub fn last_known(&self, host: &Name, record_type: RecordType) -> Option<Vec<Record>> {
+        let last_known = self.last_known.lock().unwrap();
+        let records: Vec<Record> = last_known
+            .get(&host.to_lowercase())?
+            .iter()
+            .filter(|r| r.record_type() == record_type)
+            .cloned()
+            .collect();
+        (!records.is_empty()).then_some(records)
+    }
+
+    fn should_send(&self, host: &Name, now: Instant) -> bool {
+        let mut last_sent = self.last_sent.lock().unwrap();
+        if let Some(sent) = last_sent.get(host)
+            && now.duration_since(*sent) < self.min_interval
+        {
+            return false;
+        }
+        last_sent.insert(host.clone(), now);
+        true
+    }
+}
+
+/// Six 0xFF bytes followed by the MAC address sixteen times
+fn magic_packet(mac: &[u8; 6]) -> [u8; 102] {
+    let mut packet = [0xFF; 102];
+    for chunk in packet[6..].chunks_exact_mut(6) {
+        chunk.copy_from_slice(mac);
+    }
+    packet
+}
+
+fn send_magic_packet(mac: &[u8; 6], target: SocketAddr) -> std::io::Result<()> {
+    let bind: SocketAddr = match target {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind)?;
+    socket.set_broadcast(true)?;
+    socket.send_to(&magic_packet(mac), target)?;
+    Ok(())
+}
+
+/// "aa:bb:cc:dd:ee:ff" or "aa-bb-cc-dd-ee-ff"
+fn parse_mac(mac: &str) -> Option<[u8; 6]> {
+    let parts: Vec<&str> = mac.trim().split([':', '-']).collect();
+    if parts.len() != 6 {
+        return None;
+    }
+    let mut bytes = [0u8; 6];
+    for (byte, part) in bytes.iter_mut().zip(parts) {
+        if part.len() != 2 {
+            return None;
+        }
+        *byte = u8::from_str_radix(part, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+/// "nas" or "nas.local" as the name `nas.local.`
+fn host_name(host: &str) -> WakeResult<Name> {
+    let trimmed = host.trim().trim_end_matches('.');
+    let fqdn = if trimmed.to_ascii_lowercase().ends_with(".local") {
+        format!("{}.", trimmed)
+    } else {
+        format!("{}.local.", trimmed)
+    };
+    Ok(Name::from_utf8(fqdn)?.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::WakeDevice;
+    use hickory_proto::rr::RData;
+    use hickory_proto::rr::rdata::{A, AAAA};
+
+    fn devices() -> Vec<WakeDevice> {
+        vec![WakeDevice {
+            host: "NAS".to_string(),
+            mac: "00:11:32:aa:bb:cc".to_string(),
+        }]
+    }
+
+    fn manager(min_interval_secs: u64) -> WakeManager {
+        WakeManager::from_config(&WakeConfig {
+            enabled: true,
+            min_interval_secs,
+            devices: devices(),
+            ..WakeConfig::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_magic_packet_layout() {
+        let mac = [0x00, 0x11, 0x32, 0xaa, 0xbb, 0xcc];
+        let packet = magic_packet(&mac);
+        assert_eq!(&packet[..6], &[0xFF; 6]);
+        assert!(packet[6..].chunks(6).all(|chunk| chunk == mac));
+    }
+
+    #[test]
+    fn test_parse_mac() {
+        assert_eq!(parse_mac("00:11:32:AA:bb:cc"), Some([0x00, 0x11, 0x32, 0xaa, 0xbb, 0xcc]));
+        assert_eq!(parse_mac("00-11-32-aa-bb-cc"), Some([0x00, 0x11, 0x32, 0xaa, 0xbb, 0xcc]));
+        assert_eq!(parse_mac("00:11:32:aa:bb"), None);
+        assert_eq!(parse_mac("00:11:32:aa:bb:zz"), None);
+        assert_eq!(parse_mac("0:11:32:aa:bb:cc"), None);
+    }
+
+    #[test]
+    fn test_devices_matched_case_insensitively() {
+        let wake = manager(60);
+        assert!(wake.is_device(&Name::from_utf8("nas.local.").unwrap()));
+        assert!(wake.is_device(&Name::from_utf8("Nas.Local.").unwrap()));
+        assert!(!wake.is_device(&Name::from_utf8("printer.local.").unwrap()));
+
+        let disabled = WakeManager::from_config(&WakeConfig {
+            devices: devices(),
+            ..WakeConfig::default()
+        })
+        .unwrap();
+        assert!(!disabled.is_device(&Name::from_utf8("nas.local.").unwrap()));
+    }
+
+    #[test]
+    fn test_invalid_mac_rejected() {
+        let config = WakeConfig {
+            enabled: true,
+            devices: vec![WakeDevice {
+                host: "nas".to_string(),
+                mac: "not-a-mac".to_string(),
+            }],
+            ..WakeConfig::default()
+        };
+        assert!(WakeManager::from_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let wake = manager(60);
+        let host = Name::from_utf8("nas.local.").unwrap();
+        let now = Instant::now();
+        assert!(wake.should_send(&host, now));
+        assert!(!wake.should_send(&host, now + Duration::from_secs(59)));
+        assert!(wake.should_send(&host, now + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_last_known_filtered_by_type() {
+        let wake = manager(60);
+        let host = Name::from_utf8("nas.local.").unwrap();
+        assert!(wake.last_known(&host, RecordType::A).is_none());
+
+        wake.remember(
+            &host,
+            &[
+                Record::from_rdata(host.clone(), 120, RData::A(A::new(192, 168, 1, 30))),
+                Record::from_rdata(host.clone(), 120, RData::AAAA(AAAA::new(0xfe80, 0, 0, 0, 0, 0, 0, 1))),
+            ],
+        );
+        assert_eq!(wake.last_known(&host, RecordType::A).unwrap().len(), 1);
+        assert_eq!(wake.last_known(&host, RecordType::AAAA).unwrap().len(), 1);
+
+        // An empty lookup does not erase what was known
+        wake.remember(&host, &[]);
+        assert!(wake.last_known(&host, RecordType::A).is_some());
+    }
+}
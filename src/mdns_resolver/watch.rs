@@ -0,0 +1,171 @@
+//! Long-lived browses behind DNS Push subscriptions
+//!
+//! A subscription to the PTR records of a service type or subtype, or to an
+//! instance's SRV or TXT records, follows the browse of the service type (or
+//! subtype, passed through to mdns-sd) for as long as it lasts: every
+//! instance the browse resolves or sees removed updates the subscribed RRset,
+//! rewritten into the subscription's zone and TTL-capped as answers are. A
+//! browse stopped by the age limit is started again, since no query may come
+//! along to do it.
+
+use crate::config::ServiceRecordKind;
+use hickory_proto::rr::rdata::PTR;
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use mdns_sd::{ResolvedService, ServiceEvent};
+use std::collections::BTreeMap;
+use tracing::debug;
+
+use super::browses::BrowseReceiver;
+use super::names;
+use super::query;
+use super::MdnsResolver;
+
+/// Key of the single RRset of an SRV or TXT subscription
+const INSTANCE: &str = "";
+
+/// A subscribed RRset kept current from browse events
+pub struct Watch {
+    /// Subscribed name in its discovery zone
+    name: Name,
+    /// The same name under `local.`
+    mdns_name: Name,
+    zone: Name,
+    record_type: RecordType,
+    service_type: String,
+    events: BrowseReceiver,
+    /// Records per instance: keyed by the cache key of the PTR target, or
+    /// [`INSTANCE`] for an SRV or TXT subscription
+    records: BTreeMap<String, Vec<Record>>,
+}
+
+impl Watch {
+    pub(super) fn new(
+        name: &Name,
+        mdns_name: Name,
+        zone: &Name,
+        record_type: RecordType,
+        service_type: String,
+        events: BrowseReceiver,
+        current: &[Record],
+    ) -> Self {
+        let mut records: BTreeMap<String, Vec<Record>> = BTreeMap::new();
+        for record in current.iter().filter(|r| r.record_type() == record_type) {
+            let key = match record.data() {
+                RData::PTR(ptr) => names::cache_key(&ptr.0),
+                _ => INSTANCE.to_string(),
+            };
+            records.entry(key).or_default().push(record.clone());
+        }
+        Self {
+            name: name.clone(),
+            mdns_name,
+            zone: zone.clone(),
+            record_type,
+            service_type,
+            events,
+            records,
+        }
+    }
+
+    /// The subscribed RRset as last seen
+    pub fn records(&self) -> Vec<Record> {
+        self.records.values().flatten().cloned().collect()
+    }
+
+    /// Wait until the RRset changes and return it; None once the resolver stops browsing for good
+    pub async fn changed(&mut self, resolver: &MdnsResolver) -> Option<Vec<Record>> {
+        loop {
+            let changed = match self.events.recv().await? {
+                ServiceEvent::ServiceResolved(info) => self.resolved(resolver, &info),
+                ServiceEvent::ServiceRemoved(ty_domain, fullname) => self.removed(resolver, &ty_domain, &fullname),
+                ServiceEvent::SearchStopped(_) => {
+                    resolver.resume_browse(&self.service_type);
+                    false
+                }
+                _ => false,
+            };
+            if changed {
+                return Some(self.records());
+            }
+        }
+    }
+
+    fn resolved(&mut self, resolver: &MdnsResolver, info: &ResolvedService) -> bool {
+        let update = match self.record_type {
+            RecordType::PTR
+                if info.ty_domain.eq_ignore_ascii_case(&self.service_type)
+                    || info.get_subtype().as_ref().is_some_and(|sub| sub.eq_ignore_ascii_case(&self.service_type)) =>
+            {
+                self.ptr_record(resolver, info.get_fullname(), self.instance_type(&info.ty_domain))
+            }
+            RecordType::SRV | RecordType::TXT
+                if names::instance_matches(&self.mdns_name, info.get_fullname(), &info.ty_domain) =>
+            {
+                let kind = if self.record_type == RecordType::SRV {
+                    ServiceRecordKind::Srv
+                } else {
+                    ServiceRecordKind::Txt
+                };
+                query::instance_records(info, &[kind])
+                    .and_then(|records| resolver.finalize_records(records, &self.zone))
+                    .map(|records| (INSTANCE.to_string(), records))
+                    .ok()
+            }
+            _ => None,
+        };
+        let Some((key, records)) = update else {
+            return false;
+        };
+        if records.is_empty() {
+            return self.records.remove(&key).is_some();
+        }
+        let data = |records: &[Record]| {
+            let mut data: Vec<RData> = records.iter().map(|r| r.data().clone()).collect();
+            data.sort();
+            data
+        };
+        if self.records.get(&key).is_some_and(|known| data(known) == data(&records)) {
+            return false;
+        }
+        debug!("Watched {} {:?} changed by {}", names::presentation(&self.name), self.record_type, info.get_fullname());
+        self.records.insert(key, records);
+        true
+    }
+
+    fn removed(&mut self, resolver: &MdnsResolver, ty_domain: &str, fullname: &str) -> bool {
+        let key = match self.record_type {
+            RecordType::PTR if ty_domain.eq_ignore_ascii_case(&self.service_type) => {
+                match self.ptr_record(resolver, fullname, self.instance_type(ty_domain)) {
+                    Some((key, _)) => key,
+                    None => return false,
+                }
+            }
+            RecordType::SRV | RecordType::TXT if names::instance_matches(&self.mdns_name, fullname, ty_domain) => {
+                INSTANCE.to_string()
+            }
+            _ => return false,
+        };
+        self.records.remove(&key).is_some()
+    }
+
+    /// Service type instances are named under: the parent type of a subtype
+    /// (whatever type an event reports), else `ty_domain`
+    fn instance_type<'a>(&'a self, ty_domain: &'a str) -> &'a str {
+        names::split_subtype(&self.service_type).map_or(ty_domain, |(_, parent)| parent)
+    }
+
+    /// PTR record from the subscribed name to instance `fullname`, in the zone
+    fn ptr_record(&self, resolver: &MdnsResolver, fullname: &str, ty_domain: &str) -> Option<(String, Vec<Record>)> {
+        let target = names::instance_name(fullname, ty_domain).ok()?;
+        let record = Record::from_rdata(self.mdns_name.clone(), 120, RData::PTR(PTR(target)));
+        let mut records = resolver.finalize_records(vec![record], &self.zone).ok()?;
+        for record in &mut records {
+            record.set_name(self.name.clone());
+        }
+        let key = match records.first()?.data() {
+            RData::PTR(ptr) => names::cache_key(&ptr.0),
+            _ => return None,
+        };
+        Some((key, records))
+    }
+}
@@ -0,0 +1,429 @@
+//! Process-wide counters, gauges and latency histograms
+//!
+//! Counters are plain atomics in a single static so any module can bump them
+//! without threading a handle through constructors. Without the `metrics`
+//! feature (or with `minimal`) updates compile to nothing and every value reads 0.
+//!
+//! Latency is recorded per phase of answering a query (cache lookup, mDNS wait,
+//! rewrite, response serialization) so a regression in one stage shows up on
+//! its own rather than only in the total.
+//!
+//! Service types are not known up front, so instances per type and their churn
+//! are kept in a map beside the counters rather than as fields of [`Metrics`].
+
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds of the histogram buckets in microseconds; a final bucket takes the rest
+pub const BUCKET_BOUNDS_US: [u64; 16] = [
+    50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000, 1_000_000,
+    2_500_000, 5_000_000,
+];
+
+const BUCKETS: usize = BUCKET_BOUNDS_US.len() + 1;
+
+/// Distribution of durations over [`BUCKET_BOUNDS_US`]
+#[derive(Debug)]
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKETS],
+    count: AtomicU64,
+    sum_us: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; BUCKETS],
+            count: AtomicU64::new(0),
+            sum_us: AtomicU64::new(0),
+        }
+    }
+
+    fn snapshot(&self) -> HistogramSnapshot {
+        HistogramSnapshot {
+            buckets: std::array::from_fn(|i| self.buckets[i].load(Ordering::Relaxed)),
+            count: self.count.load(Ordering::Relaxed),
+            sum_us: self.sum_us.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Plain copy of a [`Histogram`]; `buckets[i]` counts observations of at most
+/// `BUCKET_BOUNDS_US[i]` (and above the previous bound), the last one everything larger
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct HistogramSnapshot {
+    pub buckets: [u64; BUCKETS],
+    pub count: u64,
+    pub sum_us: u64,
+}
+
+/// Counters describing what the proxy has done since startup
+#[derive(Debug)]
+pub struct Metrics {
+    /// DNS requests received
+    pub requests: AtomicU64,
+    /// Requests whose handling panicked and were answered with SERVFAIL
+    pub handler_panics: AtomicU64,
+    /// Requests that waited for a free slot under `server.max_concurrent_queries`
+    pub queries_queued: AtomicU64,
+    /// Requests turned away with `server.overload_response` because the queue was full or too slow
+    pub queries_overloaded: AtomicU64,
+    /// mDNS lookups of record types the backend cannot ask for, answered empty
+    pub queries_unsupported: AtomicU64,
+    /// Times a latency objective of `[slo]` started being missed
+    pub slo_breaches: AtomicU64,
+    /// Cache entries evicted because the memory limit was exceeded
+    pub cache_evictions: AtomicU64,
+    /// Approximate bytes held by all caches (gauge)
+    pub cache_bytes: AtomicU64,
+    /// Audit lines dropped because the writer fell behind
+    pub audit_dropped: AtomicU64,
+    /// 1 while quiet hours are in effect (gauge)
+    pub quiet_active: AtomicU64,
+    /// Times quiet hours started or ended
+    pub quiet_transitions: AtomicU64,
+    /// Wake-on-LAN magic packets sent
+    pub wake_packets: AtomicU64,
+    /// Queries answered by forwarding to a peer proxy
+    pub peer_forwards: AtomicU64,
+    /// UDP answers sent by the batched listener without waiting (batch-udp feature)
+    pub udp_fast_path_responses: AtomicU64,
+    /// Interface address changes seen by the network watcher
+    pub network_changes: AtomicU64,
+    /// Queries answered from a fresh mDNS lookup, bypassing the cache
+    pub fresh_queries: AtomicU64,
+    /// Fresh queries answered normally because the per-minute limit was reached
+    pub fresh_rate_limited: AtomicU64,
+    /// Changed SRV or TXT data of known service instances reported as updates
+    pub instance_updates: AtomicU64,
+    /// TXT records shortened to fit DNS limits or the configured cap
+    pub txt_truncated: AtomicU64,
+    /// TXT records left out for exceeding DNS limits or the configured cap
+    pub txt_dropped: AtomicU64,
+    /// TCP connections closed because a message's length prefix exceeded the limit
+    pub tcp_oversized_messages: AtomicU64,
+    /// TCP messages answered with FORMERR for carrying too many questions
+    pub tcp_too_many_questions: AtomicU64,
+    /// TCP connections closed because a message did not arrive within the read deadline
+    pub tcp_read_timeouts: AtomicU64,
+    /// UDP answers trimmed and marked TC to fit the client's payload size
+    pub truncated_responses: AtomicU64,
+    /// Browses restarted by a query because they outlived the age limit
+    pub browse_refreshes: AtomicU64,
+    /// Browses past the age limit stopped with no query asking for them
+    pub browses_expired: AtomicU64,
+    /// Failed attempts to start the mDNS daemon on demand
+    pub daemon_start_failures: AtomicU64,
+    /// DNS Push subscriptions accepted
+    pub push_subscriptions: AtomicU64,
+    /// DNS Push PUSH messages sent
+    pub push_messages: AtomicU64,
+    /// Queries whose TSIG signature was verified
+    pub tsig_verified: AtomicU64,
+    /// Signed queries answered NOTAUTH: unknown key, bad MAC or time outside the fudge
+    pub tsig_rejected: AtomicU64,
+    /// Unsigned queries refused from clients that must sign
+    pub tsig_unsigned_refused: AtomicU64,
+    /// Zone transfers (AXFR/IXFR) sent
+    pub axfr_transfers: AtomicU64,
+    /// Zone transfers refused: disabled, over UDP or to a client not allowed
+    pub axfr_refused: AtomicU64,
+    /// DNS NOTIFY messages a downstream server acknowledged
+    pub notify_sent: AtomicU64,
+    /// DNS NOTIFY messages given up on after every resend
+    pub notify_failed: AtomicU64,
+    /// Distinct TXT blobs held by all caches (gauge)
+    pub txt_interned: AtomicU64,
+    /// Bytes of TXT data the caches avoid holding twice by sharing blobs (gauge)
+    pub txt_interned_bytes_saved: AtomicU64,
+    /// Time spent looking up the record cache
+    pub cache_lookup_time: Histogram,
+    /// Time spent waiting for mDNS answers
+    pub mdns_wait_time: Histogram,
+    /// Time spent rewriting records into the discovery domain
+    pub rewrite_time: Histogram,
+    /// Time spent serializing and sending responses
+    pub serialize_time: Histogram,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            requests: AtomicU64::new(0),
+            handler_panics: AtomicU64::new(0),
+            queries_queued: AtomicU64::new(0),
+            queries_overloaded: AtomicU64::new(0),
+            queries_unsupported: AtomicU64::new(0),
+            slo_breaches: AtomicU64::new(0),
+            cache_evictions: AtomicU64::new(0),
+            cache_bytes: AtomicU64::new(0),
+            audit_dropped: AtomicU64::new(0),
+            quiet_active: AtomicU64::new(0),
+            quiet_transitions: AtomicU64::new(0),
+            wake_packets: AtomicU64::new(0),
+            peer_forwards: AtomicU64::new(0),
+            udp_fast_path_responses: AtomicU64::new(0),
+            network_changes: AtomicU64::new(0),
+            fresh_queries: AtomicU64::new(0),
+            fresh_rate_limited: AtomicU64::new(0),
+            instance_updates: AtomicU64::new(0),
+            txt_truncated: AtomicU64::new(0),
+            txt_dropped: AtomicU64::new(0),
+            tcp_oversized_messages: AtomicU64::new(0),
+            tcp_too_many_questions: AtomicU64::new(0),
+            tcp_read_timeouts: AtomicU64::new(0),
+            truncated_responses: AtomicU64::new(0),
+            browse_refreshes: AtomicU64::new(0),
+            browses_expired: AtomicU64::new(0),
+            daemon_start_failures: AtomicU64::new(0),
+            push_subscriptions: AtomicU64::new(0),
+            push_messages: AtomicU64::new(0),
+            tsig_verified: AtomicU64::new(0),
+            tsig_rejected: AtomicU64::new(0),
+            tsig_unsigned_refused: AtomicU64::new(0),
+            axfr_transfers: AtomicU64::new(0),
+            axfr_refused: AtomicU64::new(0),
+            notify_sent: AtomicU64::new(0),
+            notify_failed: AtomicU64::new(0),
+            txt_interned: AtomicU64::new(0),
+            txt_interned_bytes_saved: AtomicU64::new(0),
+            cache_lookup_time: Histogram::new(),
+            mdns_wait_time: Histogram::new(),
+            rewrite_time: Histogram::new(),
+            serialize_time: Histogram::new(),
+        }
+    }
+
+    /// Point-in-time copy of all counters
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            requests: self.requests.load(Ordering::Relaxed),
+            handler_panics: self.handler_panics.load(Ordering::Relaxed),
+            queries_queued: self.queries_queued.load(Ordering::Relaxed),
+            queries_overloaded: self.queries_overloaded.load(Ordering::Relaxed),
+            queries_unsupported: self.queries_unsupported.load(Ordering::Relaxed),
+            slo_breaches: self.slo_breaches.load(Ordering::Relaxed),
+            cache_evictions: self.cache_evictions.load(Ordering::Relaxed),
+            cache_bytes: self.cache_bytes.load(Ordering::Relaxed),
+            audit_dropped: self.audit_dropped.load(Ordering::Relaxed),
+            quiet_active: self.quiet_active.load(Ordering::Relaxed),
+            quiet_transitions: self.quiet_transitions.load(Ordering::Relaxed),
+            wake_packets: self.wake_packets.load(Ordering::Relaxed),
+            peer_forwards: self.peer_forwards.load(Ordering::Relaxed),
+            udp_fast_path_responses: self.udp_fast_path_responses.load(Ordering::Relaxed),
+            network_changes: self.network_changes.load(Ordering::Relaxed),
+            fresh_queries: self.fresh_queries.load(Ordering::Relaxed),
+            fresh_rate_limited: self.fresh_rate_limited.load(Ordering::Relaxed),
+            instance_updates: self.instance_updates.load(Ordering::Relaxed),
+            txt_truncated: self.txt_truncated.load(Ordering::Relaxed),
+            txt_dropped: self.txt_dropped.load(Ordering::Relaxed),
+            tcp_oversized_messages: self.tcp_oversized_messages.load(Ordering::Relaxed),
+            tcp_too_many_questions: self.tcp_too_many_questions.load(Ordering::Relaxed),
+            tcp_read_timeouts: self.tcp_read_timeouts.load(Ordering::Relaxed),
+            truncated_responses: self.truncated_responses.load(Ordering::Relaxed),
+            browse_refreshes: self.browse_refreshes.load(Ordering::Relaxed),
+            browses_expired: self.browses_expired.load(Ordering::Relaxed),
+            daemon_start_failures: self.daemon_start_failures.load(Ordering::Relaxed),
+            push_subscriptions: self.push_subscriptions.load(Ordering::Relaxed),
+            push_messages: self.push_messages.load(Ordering::Relaxed),
+            tsig_verified: self.tsig_verified.load(Ordering::Relaxed),
+            tsig_rejected: self.tsig_rejected.load(Ordering::Relaxed),
+            tsig_unsigned_refused: self.tsig_unsigned_refused.load(Ordering::Relaxed),
+            axfr_transfers: self.axfr_transfers.load(Ordering::Relaxed),
+            axfr_refused: self.axfr_refused.load(Ordering::Relaxed),
+            notify_sent: self.notify_sent.load(Ordering::Relaxed),
+            notify_failed: self.notify_failed.load(Ordering::Relaxed),
+            txt_interned: self.txt_interned.load(Ordering::Relaxed),
+            txt_interned_bytes_saved: self.txt_interned_bytes_saved.load(Ordering::Relaxed),
+            cache_lookup_time: self.cache_lookup_time.snapshot(),
+            mdns_wait_time: self.mdns_wait_time.snapshot(),
+            rewrite_time: self.rewrite_time.snapshot(),
+            serialize_time: self.serialize_time.snapshot(),
+        }
+    }
+}
+
+/// Plain copy of the counters in [`Metrics`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct MetricsSnapshot {
+    pub requests: u64,
+    pub handler_panics: u64,
+    pub queries_queued: u64,
+    pub queries_overloaded: u64,
+    pub queries_unsupported: u64,
+    pub slo_breaches: u64,
+    pub cache_evictions: u64,
+    pub cache_bytes: u64,
+    pub audit_dropped: u64,
+    pub quiet_active: u64,
+    pub quiet_transitions: u64,
+    pub wake_packets: u64,
+    pub peer_forwards: u64,
+    pub udp_fast_path_responses: u64,
+    pub network_changes: u64,
+    pub fresh_queries: u64,
+    pub fresh_rate_limited: u64,
+    pub instance_updates: u64,
+    pub txt_truncated: u64,
+    pub txt_dropped: u64,
+    pub tcp_oversized_messages: u64,
+    pub tcp_too_many_questions: u64,
+    pub tcp_read_timeouts: u64,
+    pub truncated_responses: u64,
+    pub browse_refreshes: u64,
+    pub browses_expired: u64,
+    pub daemon_start_failures: u64,
+    pub push_subscriptions: u64,
+    pub push_messages: u64,
+    pub tsig_verified: u64,
+    pub tsig_rejected: u64,
+    pub tsig_unsigned_refused: u64,
+    pub axfr_transfers: u64,
+    pub axfr_refused: u64,
+    pub notify_sent: u64,
+    pub notify_failed: u64,
+    pub txt_interned: u64,
+    pub txt_interned_bytes_saved: u64,
+    pub cache_lookup_time: HistogramSnapshot,
+    pub mdns_wait_time: HistogramSnapshot,
+    pub rewrite_time: HistogramSnapshot,
+    pub serialize_time: HistogramSnapshot,
+}
+
+static METRICS: Metrics = Metrics::new();
+
+/// Instances last seen per service type and how often they came and went
+#[derive(Debug, Default)]
+struct ServiceTypeEntry {
+    instances: BTreeSet<String>,
+    added: u64,
+    removed: u64,
+}
+
+static SERVICE_TYPES: Mutex<BTreeMap<String, ServiceTypeEntry>> = Mutex::new(BTreeMap::new());
+
+/// Instances of one service type (gauge), with the instances that appeared
+/// and disappeared since startup (counters). The first browse of a type counts
+/// every instance it found as added.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ServiceTypeSnapshot {
+    pub instances: u64,
+    pub added: u64,
+    pub removed: u64,
+}
+
+/// Global metrics instance
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+/// Whether this build records metrics
+pub const ENABLED: bool = cfg!(all(feature = "metrics", not(feature = "minimal")));
+
+/// Increment a counter by one
+pub fn inc(counter: &AtomicU64) {
+    if ENABLED {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Set a gauge to `value`
+pub fn set(gauge: &AtomicU64, value: u64) {
+    if ENABLED {
+        gauge.store(value, Ordering::Relaxed);
+    }
+}
+
+/// Add `delta` to a gauge that several owners contribute to
+pub fn add(gauge: &AtomicU64, delta: u64) {
+    if ENABLED {
+        gauge.fetch_add(delta, Ordering::Relaxed);
+    }
+}
+
+/// Take `delta` off a gauge that several owners contribute to
+pub fn sub(gauge: &AtomicU64, delta: u64) {
+    if ENABLED {
+        gauge.fetch_sub(delta, Ordering::Relaxed);
+    }
+}
+
+/// Record the complete set of instances a browse of `service_type` found;
+/// instance names are compared case-insensitively
+pub fn observe_instances<'a>(service_type: &str, instances: impl IntoIterator<Item = &'a str>) {
+    if ENABLED {
+        let current: BTreeSet<String> = instances.into_iter().map(str::to_lowercase).collect();
+        let mut types = SERVICE_TYPES.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = types.entry(service_type.to_lowercase()).or_default();
+        entry.added += current.difference(&entry.instances).count() as u64;
+        entry.removed += entry.instances.difference(&current).count() as u64;
+        entry.instances = current;
+    }
+}
+
+/// Per service type instance counts and churn, by lowercase service type
+pub fn service_types() -> BTreeMap<String, ServiceTypeSnapshot> {
+    let types = SERVICE_TYPES.lock().unwrap_or_else(|e| e.into_inner());
+    types
+        .iter()
+        .map(|(service_type, entry)| {
+            let snapshot = ServiceTypeSnapshot {
+                instances: entry.instances.len() as u64,
+                added: entry.added,
+                removed: entry.removed,
+            };
+            (service_type.clone(), snapshot)
+        })
+        .collect()
+}
+
+/// Record one observation of `elapsed` in `histogram`
+pub fn observe(histogram: &Histogram, elapsed: Duration) {
+    if ENABLED {
+        let us = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+        let bucket = BUCKET_BOUNDS_US.partition_point(|&bound| bound < us);
+        histogram.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        histogram.count.fetch_add(1, Ordering::Relaxed);
+        histogram.sum_us.fetch_add(us, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_observe_buckets() {
+        let histogram = Histogram::new();
+        observe(&histogram, Duration::from_micros(50));
+        observe(&histogram, Duration::from_micros(51));
+        observe(&histogram, Duration::from_secs(60));
+        let snapshot = histogram.snapshot();
+        if !ENABLED {
+            assert_eq!(snapshot, HistogramSnapshot::default());
+            return;
+        }
+        assert_eq!(snapshot.buckets[0], 1);
+        assert_eq!(snapshot.buckets[1], 1);
+        assert_eq!(snapshot.buckets[BUCKETS - 1], 1);
+        assert_eq!(snapshot.count, 3);
+        assert_eq!(snapshot.sum_us, 60_000_101);
+    }
+
+    #[test]
+    fn test_observe_instances_churn() {
+        let ty = "_churn-test._tcp.local.";
+        observe_instances(ty, ["Kitchen._churn-test._tcp.local.", "Den._churn-test._tcp.local."]);
+        observe_instances(ty, ["kitchen._churn-test._tcp.local.", "Office._churn-test._tcp.local."]);
+        observe_instances(ty, []);
+        let snapshot = service_types().get(ty).copied().unwrap_or_default();
+        if !ENABLED {
+            assert_eq!(snapshot, ServiceTypeSnapshot::default());
+            return;
+        }
+        assert_eq!(snapshot, ServiceTypeSnapshot { instances: 0, added: 3, removed: 3 });
+    }
+}
@@ -0,0 +1,342 @@
+//! Interface address watcher
+//!
+//! Laptops move between networks and routers renew DHCP leases, so the
+//! addresses read at startup go stale. The watcher waits for netlink address
+//! and link notifications on Linux, or polls the interfaces elsewhere, and on
+//! each change refreshes the proxy's own address records, the networks treated
+//! as on-link for record suppression and, if asked to, drops cached answers
+//! pointing into networks that went away.
+
+use crate::config::NetworkConfig;
+use crate::mdns_resolver::MdnsResolver;
+use crate::metrics;
+use crate::own_addresses::OwnAddresses;
+use std::io;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Notifications arrive in bursts (address, route and link messages for one
+/// change); wait this long before re-reading the interfaces
+const SETTLE_DELAY: Duration = Duration::from_millis(500);
+
+/// Network of one interface address, e.g. 192.168.1.5/24
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalNetwork {
+    pub addr: IpAddr,
+    pub prefix_len: u8,
+}
+
+impl LocalNetwork {
+    /// Whether `addr` lies inside this network
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match (self.addr, addr) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                prefix_matches(u32::from(net).into(), u32::from(*addr).into(), 32, self.prefix_len)
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => prefix_matches(u128::from(net), u128::from(*addr), 128, self.prefix_len),
+            _ => false,
+        }
+    }
+}
+
+impl std::str::FromStr for LocalNetwork {
+    type Err = String;
+
+    /// A network such as "10.20.0.0/16", or a single address
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match s.trim().split_once('/') {
+            Some((addr, len)) => (addr, Some(len)),
+            None => (s.trim(), None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| format!("{} is not a network", s))?;
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(len) => len
+                .parse::<u8>()
+                .ok()
+                .filter(|len| *len <= bits)
+                .ok_or_else(|| format!("bad prefix length in {}", s))?,
+            None => bits,
+        };
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+fn prefix_matches(net: u128, addr: u128, bits: u8, prefix_len: u8) -> bool {
+    let prefix_len = prefix_len.min(bits);
+    if prefix_len == 0 {
+        return true;
+    }
+    let shift = bits - prefix_len;
+    net >> shift == addr >> shift
+}
+
+/// Networks of the interfaces that are up, loopback excluded
+pub fn local_networks() -> io::Result<Vec<LocalNetwork>> {
+    let mut networks: Vec<LocalNetwork> = interface_networks()?.into_iter().map(|(_, network)| network).collect();
+    networks.sort_by_key(|network| (network.addr.is_ipv6(), network.addr, network.prefix_len));
+    networks.dedup();
+    Ok(networks)
+}
+
+/// Networks of the interfaces that are up, loopback excluded, with the name
+/// of the interface each is on
+pub fn interface_networks() -> io::Result<Vec<(String, LocalNetwork)>> {
+    Ok(if_addrs::get_if_addrs()?
+        .into_iter()
+        .filter(|interface| interface.is_oper_up() && !interface.is_loopback())
+        .map(|interface| {
+            let prefix_len = match &interface.addr {
+                if_addrs::IfAddr::V4(v4) => v4.prefixlen,
+                if_addrs::IfAddr::V6(v6) => v6.prefixlen,
+            };
+            let network = LocalNetwork {
+                addr: interface.ip(),
+                prefix_len,
+            };
+            (interface.name, network)
+        })
+        .collect())
+}
+
+/// Networks of this host's interfaces, as last read
+#[derive(Debug, Default)]
+pub struct NetworkState {
+    networks: RwLock<Vec<LocalNetwork>>,
+}
+
+impl NetworkState {
+    /// State read from the interfaces now (empty if they cannot be read)
+    pub fn new() -> Self {
+        let networks = local_networks().unwrap_or_else(|e| {
+            warn!("Could not read interface addresses: {}", e);
+            Vec::new()
+        });
+        Self::with_networks(networks)
+    }
+
+    pub fn with_networks(networks: Vec<LocalNetwork>) -> Self {
+        Self {
+            networks: RwLock::new(networks),
+        }
+    }
+
+    pub fn networks(&self) -> Vec<LocalNetwork> {
+        self.networks.read().unwrap().clone()
+    }
+
+    /// Whether `addr` is inside one of the interface networks
+    pub fn is_on_link(&self, addr: &IpAddr) -> bool {
+        self.networks.read().unwrap().iter().any(|network| network.contains(addr))
+    }
+
+    /// Replace the networks with `networks`, returning the ones that went
+    /// away, or None if nothing changed
+    pub fn update(&self, networks: Vec<LocalNetwork>) -> Option<Vec<LocalNetwork>> {
+        let mut current = self.networks.write().unwrap();
+        if *current == networks {
+            return None;
+        }
+        let removed = current.iter().filter(|old| !networks.contains(old)).copied().collect();
+        *current = networks;
+        Some(removed)
+    }
+}
+
+/// Source of "addresses may have changed" signals
+enum ChangeEvents {
+    #[cfg(target_os = "linux")]
+    Netlink(netlink::Socket),
+    Poll(tokio::time::Interval),
+}
+
+impl ChangeEvents {
+    fn open(poll_interval: Duration) -> Self {
+        #[cfg(target_os = "linux")]
+        match netlink::Socket::open() {
+            Ok(socket) => return ChangeEvents::Netlink(socket),
+            Err(e) => warn!("Netlink unavailable ({}), polling interfaces every {:?}", e, poll_interval),
+        }
+        Self::poll(poll_interval)
+    }
+
+    fn poll(poll_interval: Duration) -> Self {
+        let mut interval = tokio::time::interval(poll_interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        ChangeEvents::Poll(interval)
+    }
+
+    /// Wait until the interfaces are worth re-reading
+    async fn next(&mut self, poll_interval: Duration) {
+        match self {
+            #[cfg(target_os = "linux")]
+            ChangeEvents::Netlink(socket) => match socket.wait().await {
+                Ok(()) => tokio::time::sleep(SETTLE_DELAY).await,
+                Err(e) => {
+                    warn!("Netlink receive failed ({}), polling interfaces every {:?}", e, poll_interval);
+                    *self = Self::poll(poll_interval);
+                }
+            },
+            ChangeEvents::Poll(interval) => {
+                interval.tick().await;
+            }
+        }
+    }
+}
+
+/// Follow interface changes for as long as the proxy runs
+pub async fn run(
+    state: Arc<NetworkState>,
+    own_addresses: Option<Arc<OwnAddresses>>,
+    resolver: Arc<MdnsResolver>,
+    config: NetworkConfig,
+) {
+    let poll_interval = Duration::from_secs(config.poll_interval_secs.max(1));
+    let mut events = ChangeEvents::open(poll_interval);
+    loop {
+        events.next(poll_interval).await;
+
+        let networks = match local_networks() {
+            Ok(networks) => networks,
+            Err(e) => {
+                warn!("Could not read interface addresses: {}", e);
+                continue;
+            }
+        };
+        let Some(removed) = state.update(networks) else {
+            continue;
+        };
+        info!("Interface networks changed: {:?}", state.networks());
+        metrics::inc(&metrics::metrics().network_changes);
+        resolver.refresh_links();
+
+        if let Some(own) = &own_addresses
+            && own.refresh()
+        {
+            info!("Own addresses changed: {:?}", own.addresses());
+        }
+        if config.flush_cache && !removed.is_empty() {
+            let flushed = resolver.flush_networks(&removed);
+            debug!("Flushed {} cache entries pointing into {:?}", flushed, removed);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod netlink {
+    //! Subscription to rtnetlink address and link notifications
+
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use tokio::io::unix::AsyncFd;
+
+    pub struct Socket(AsyncFd<OwnedFd>);
+
+    impl Socket {
+        pub fn open() -> io::Result<Self> {
+            // SAFETY: plain socket(2) call; the descriptor is owned right after
+            let fd = unsafe {
+                libc::socket(
+                    libc::AF_NETLINK,
+                    libc::SOCK_RAW | libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK,
+                    libc::NETLINK_ROUTE,
+                )
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // SAFETY: fd is a fresh descriptor nothing else owns
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+            // SAFETY: sockaddr_nl is plain data; all-zero is a valid value
+            let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+            addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            addr.nl_groups = (libc::RTMGRP_LINK | libc::RTMGRP_IPV4_IFADDR | libc::RTMGRP_IPV6_IFADDR) as u32;
+            // SAFETY: addr is a valid sockaddr_nl of the given length
+            let rc = unsafe {
+                libc::bind(
+                    fd.as_raw_fd(),
+                    &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                    std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+                )
+            };
+            if rc < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Self(AsyncFd::new(fd)?))
+        }
+
+        /// Wait for at least one notification, then drain the queue. The
+        /// messages themselves are not parsed: the interfaces are re-read.
+        pub async fn wait(&self) -> io::Result<()> {
+            let mut buf = [0u8; 8192];
+            loop {
+                let mut guard = self.0.readable().await?;
+                let mut received = false;
+                loop {
+                    // SAFETY: buf is valid for writes of buf.len() bytes
+                    let n = unsafe {
+                        libc::recv(self.0.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0)
+                    };
+                    if n >= 0 {
+                        received = true;
+                        continue;
+                    }
+                    let err = io::Error::last_os_error();
+                    match err.raw_os_error() {
+                        // Notifications were lost to a full buffer: something changed
+                        Some(libc::ENOBUFS) => received = true,
+                        Some(libc::EINTR) => {}
+                        _ if err.kind() == io::ErrorKind::WouldBlock => {
+                            guard.clear_ready();
+                            break;
+                        }
+                        _ => return Err(err),
+                    }
+                }
+                if received {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network(cidr: &str) -> LocalNetwork {
+        cidr.parse().unwrap()
+    }
+
+    #[test]
+    fn test_network_contains() {
+        let lan = network("192.168.1.5/24");
+        assert!(lan.contains(&"192.168.1.200".parse().unwrap()));
+        assert!(!lan.contains(&"192.168.2.1".parse().unwrap()));
+        assert!(!lan.contains(&"fe80::1".parse().unwrap()));
+
+        let v6 = network("2001:db8:1::5/64");
+        assert!(v6.contains(&"2001:db8:1::99".parse().unwrap()));
+        assert!(!v6.contains(&"2001:db8:2::99".parse().unwrap()));
+        assert!(network("10.0.0.1/0").contains(&"8.8.8.8".parse().unwrap()));
+        assert_eq!(network("fd00::7").prefix_len, 128);
+        assert!("10.0.0.0/33".parse::<LocalNetwork>().is_err());
+        assert!("eth0".parse::<LocalNetwork>().is_err());
+    }
+
+    #[test]
+    fn test_update_reports_removed_networks() {
+        let state = NetworkState::with_networks(vec![network("192.168.1.5/24"), network("10.0.0.5/8")]);
+        assert!(state.is_on_link(&"192.168.1.9".parse().unwrap()));
+
+        assert_eq!(state.update(state.networks()), None);
+        let removed = state.update(vec![network("10.0.0.5/8"), network("172.16.0.5/16")]).unwrap();
+        assert_eq!(removed, vec![network("192.168.1.5/24")]);
+        assert!(!state.is_on_link(&"192.168.1.9".parse().unwrap()));
+        assert!(state.is_on_link(&"172.16.3.3".parse().unwrap()));
+    }
+}
@@ -0,0 +1,196 @@
+//! DNS NOTIFY (RFC 1996) to downstream servers
+//!
+//! A server that caches the discovery zones, or mirrors them by AXFR, would
+//! otherwise only see a new instance when its copy expires. Every
+//! `notify.check_interval_secs` each discovery zone is built as a zone transfer
+//! would send it, and compared with what it held when last checked; TTLs are
+//! not compared. When a zone changed, every target is sent a NOTIFY for it
+//! over UDP, resent until the target acknowledges or `notify.retries` resends
+//! went unanswered. The zones as found at startup are the baseline, not a change.
+//!
+//! The SOA serial of the discovery zones is always 0, so a secondary that only
+//! transfers when the serial grows will not: NOTIFY suits servers that re-query
+//! or re-transfer whenever they are notified.
+
+use crate::config::NotifyConfig;
+use crate::dns_handler::QueryEngine;
+use crate::metrics;
+use crate::random::random_u64;
+use hickory_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tracing::{debug, info, warn};
+
+type NotifyResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Records of a zone as compared: owner, type and data, without TTLs
+type Contents = Vec<(Name, RecordType, RData)>;
+
+/// What each zone held when last checked
+#[derive(Debug, Default)]
+struct ZoneChanges {
+    seen: HashMap<Name, Contents>,
+}
+
+impl ZoneChanges {
+    /// Remember `records` as the contents of `zone`; whether they differ from
+    /// the last ones seen (false the first time)
+    fn changed(&mut self, zone: &Name, records: &[Record]) -> bool {
+        let mut contents: Contents = records
+            .iter()
+            .map(|record| (record.name().to_lowercase(), record.record_type(), record.data().clone()))
+            .collect();
+        contents.sort();
+        contents.dedup();
+        let changed = self.seen.get(zone).is_some_and(|previous| *previous != contents);
+        self.seen.insert(zone.clone(), contents);
+        changed
+    }
+
+    /// Forget zones no longer served
+    fn retain(&mut self, zones: &[Name]) {
+        self.seen.retain(|zone, _| zones.contains(zone));
+    }
+}
+
+/// Check the discovery zones for changes and notify `config.targets` for as long as the proxy runs
+pub async fn run(engine: QueryEngine, config: NotifyConfig) {
+    let timeout = Duration::from_millis(config.timeout_ms.max(1));
+    let mut ticker = tokio::time::interval(Duration::from_secs(config.check_interval_secs.max(1)));
+    let mut changes = ZoneChanges::default();
+    loop {
+        ticker.tick().await;
+        let zones = engine.zones().list();
+        changes.retain(&zones);
+        for zone in &zones {
+            let records = engine.zone_transfer(zone);
+            if !changes.changed(zone, &records) {
+                continue;
+            }
+            info!("Discovery zone {} changed, notifying {} server(s)", zone, config.targets.len());
+            let soa = records.first().filter(|record| record.record_type() == RecordType::SOA);
+            let sends = config
+                .targets
+                .iter()
+                .map(|&target| notify_with_retries(target, zone, soa, timeout, config.retries));
+            futures_util::future::join_all(sends).await;
+        }
+    }
+}
+
+async fn notify_with_retries(target: SocketAddr, zone: &Name, soa: Option<&Record>, timeout: Duration, retries: u32) {
+    for attempt in 0..=retries {
+        match notify(target, zone, soa, timeout).await {
+            Ok(()) => {
+                metrics::inc(&metrics::metrics().notify_sent);
+                debug!("{} acknowledged the NOTIFY for {}", target, zone);
+                return;
+            }
+            Err(e) if attempt < retries => debug!("NOTIFY for {} to {} failed ({}), resending", zone, target, e),
+            Err(e) => {
+                metrics::inc(&metrics::metrics().notify_failed);
+                warn!("NOTIFY for {} to {} failed: {}", zone, target, e);
+            }
+        }
+    }
+}
+
+/// Send one NOTIFY for `zone` to `target` and wait for its acknowledgement
+async fn notify(target: SocketAddr, zone: &Name, soa: Option<&Record>, timeout: Duration) -> NotifyResult<()> {
+    let mut message = Message::new();
+    message
+        .set_id(random_u64() as u16)
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Notify)
+        .set_authoritative(true);
+    message.add_query(Query::query(zone.clone(), RecordType::SOA));
+    // The current SOA is a hint the target may use (RFC 1996 Section 3.7)
+    if let Some(soa) = soa {
+        message.add_answer(soa.clone());
+    }
+
+    let bind: SocketAddr = if target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }.parse()?;
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(target).await?;
+    socket.send(&message.to_vec()?).await?;
+
+    let mut buf = vec![0u8; 65_535];
+    let response = loop {
+        let len = tokio::time::timeout(timeout, socket.recv(&mut buf))
+            .await
+            .map_err(|_| format!("no acknowledgement within {:?}", timeout))??;
+        let response = Message::from_vec(&buf[..len])?;
+        // Ignore stray datagrams for other messages
+        if response.id() == message.id()
+            && response.message_type() == MessageType::Response
+            && response.op_code() == OpCode::Notify
+        {
+            break response;
+        }
+    };
+    match response.response_code() {
+        ResponseCode::NoError => Ok(()),
+        code => Err(format!("answered {:?}", code).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::rdata::A;
+
+    fn a(host: &str, last: u8, ttl: u32) -> Record {
+        let name = Name::from_utf8(format!("{}.mdns.home.arpa.", host)).unwrap();
+        Record::from_rdata(name, ttl, RData::A(A::new(192, 168, 1, last)))
+    }
+
+    #[test]
+    fn test_zone_changes_ignore_ttls_and_order() {
+        let zone = Name::from_utf8("mdns.home.arpa.").unwrap();
+        let mut changes = ZoneChanges::default();
+        assert!(!changes.changed(&zone, &[a("printer", 20, 120)]));
+        assert!(!changes.changed(&zone, &[a("PRINTER", 20, 30)]));
+        assert!(changes.changed(&zone, &[a("printer", 20, 30), a("nas", 5, 30)]));
+        assert!(!changes.changed(&zone, &[a("nas", 5, 10), a("printer", 20, 10)]));
+        assert!(changes.changed(&zone, &[a("nas", 5, 10)]));
+
+        changes.retain(&[]);
+        assert!(!changes.changed(&zone, &[a("printer", 20, 120)]));
+    }
+
+    #[tokio::test]
+    async fn test_notify_is_resent_until_acknowledged() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let target = socket.local_addr().unwrap();
+        let secondary = tokio::spawn(async move {
+            let mut buf = vec![0u8; 4096];
+            // The first NOTIFY goes unanswered
+            socket.recv_from(&mut buf).await.unwrap();
+            let (len, from) = socket.recv_from(&mut buf).await.unwrap();
+            let notify = Message::from_vec(&buf[..len]).unwrap();
+            let mut ack = Message::new();
+            ack.set_id(notify.id())
+                .set_message_type(MessageType::Response)
+                .set_op_code(OpCode::Notify)
+                .set_authoritative(true);
+            ack.add_query(notify.queries()[0].clone());
+            socket.send_to(&ack.to_vec().unwrap(), from).await.unwrap();
+            notify
+        });
+
+        let zone = Name::from_utf8("mdns.home.arpa.").unwrap();
+        let before = metrics::metrics().snapshot().notify_sent;
+        notify_with_retries(target, &zone, None, Duration::from_millis(200), 2).await;
+        let notify = secondary.await.unwrap();
+        assert_eq!(notify.op_code(), OpCode::Notify);
+        assert!(notify.authoritative());
+        assert_eq!(notify.queries()[0].name(), &zone);
+        assert_eq!(notify.queries()[0].query_type(), RecordType::SOA);
+        if metrics::ENABLED {
+            assert!(metrics::metrics().snapshot().notify_sent > before);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+//! Address records for the proxy's own host name
+//!
+//! Zone apex NS answers name this proxy `discovery-proxy.<zone>`. For a
+//! delegation to it to work without manual glue, A and AAAA queries for that
+//! name are answered from the addresses the proxy is reachable on: each bind
+//! address that is a specific one, and the host's interface addresses for a
+//! wildcard one. The network watcher ([`crate::netwatch`]) re-reads them when
+//! interface addresses change.
+
+use crate::dns_handler::admin_records::{is_ipv4_link_local, is_ipv6_link_local};
+use hickory_proto::rr::rdata::{A, AAAA};
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use std::net::IpAddr;
+use std::sync::RwLock;
+use tracing::warn;
+
+/// TTL of the address records, capped like every other answer (RFC 8766 Section 5.5.1)
+const ADDRESS_TTL: u32 = 10;
+
+/// Current addresses of this proxy
+#[derive(Debug)]
+pub struct OwnAddresses {
+    bind_addresses: Vec<IpAddr>,
+    addresses: RwLock<Vec<IpAddr>>,
+}
+
+impl OwnAddresses {
+    /// Addresses for a server bound to `bind_addresses`, read from the interfaces now
+    pub fn new(bind_addresses: Vec<IpAddr>) -> Self {
+        let own = Self::with_addresses(bind_addresses, Vec::new());
+        own.refresh();
+        own
+    }
+
+    /// Fixed addresses, e.g. for tests
+    pub fn with_addresses(bind_addresses: Vec<IpAddr>, addresses: Vec<IpAddr>) -> Self {
+        Self {
+            bind_addresses,
+            addresses: RwLock::new(addresses),
+        }
+    }
+
+    /// Re-read the interface addresses. Returns true if they changed.
+    pub fn refresh(&self) -> bool {
+        let interfaces = match if_addrs::get_if_addrs() {
+            Ok(interfaces) => interfaces,
+            Err(e) => {
+                warn!("Could not read interface addresses: {}", e);
+                return false;
+            }
+        };
+        let addresses = select_addresses(
+            &self.bind_addresses,
+            interfaces.iter().filter(|i| i.is_oper_up()).map(|i| i.ip()),
+        );
+
+        let mut current = self.addresses.write().unwrap();
+        if *current == addresses {
+            return false;
+        }
+        *current = addresses;
+        true
+    }
+
+    pub fn addresses(&self) -> Vec<IpAddr> {
+        self.addresses.read().unwrap().clone()
+    }
+
+    /// A or AAAA records for `name`; empty for other types
+    pub fn records(&self, name: &Name, record_type: RecordType) -> Vec<Record> {
+        self.addresses
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(|addr| match (addr, record_type) {
+                (IpAddr::V4(v4), RecordType::A) => Some(RData::A(A(*v4))),
+                (IpAddr::V6(v6), RecordType::AAAA) => Some(RData::AAAA(AAAA(*v6))),
+                _ => None,
+            })
+            .map(|rdata| Record::from_rdata(name.clone(), ADDRESS_TTL, rdata))
+            .collect()
+    }
+}
+
+/// Addresses a client can reach a server bound to `bind_addresses` on: each
+/// bind address that is a specific one, and for a wildcard one the interface
+/// addresses of the families it listens on that are not loopback or
+/// link-local. IPv4 first.
+pub fn select_addresses(bind_addresses: &[IpAddr], interface_addresses: impl IntoIterator<Item = IpAddr>) -> Vec<IpAddr> {
+    let (wildcards, specific): (Vec<IpAddr>, Vec<IpAddr>) = bind_addresses.iter().partition(|addr| addr.is_unspecified());
+    // 0.0.0.0 only listens on IPv4; :: normally takes both families
+    let ipv4 = !wildcards.is_empty();
+    let ipv6 = wildcards.iter().any(IpAddr::is_ipv6);
+
+    let mut addresses: Vec<IpAddr> = interface_addresses
+        .into_iter()
+        .filter(|addr| {
+            let family_served = if addr.is_ipv4() { ipv4 } else { ipv6 };
+            let usable = match addr {
+                IpAddr::V4(v4) => !v4.is_loopback() && !v4.is_unspecified() && !is_ipv4_link_local(v4),
+                IpAddr::V6(v6) => !v6.is_loopback() && !v6.is_unspecified() && !is_ipv6_link_local(v6),
+            };
+            family_served && usable
+        })
+        .chain(specific)
+        .collect();
+    addresses.sort_by_key(|addr| (addr.is_ipv6(), *addr));
+    addresses.dedup();
+    addresses
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ips(addrs: &[&str]) -> Vec<IpAddr> {
+        addrs.iter().map(|a| a.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_select_addresses() {
+        let interfaces = ips(&["fe80::1", "127.0.0.1", "2001:db8::5", "192.168.1.5", "169.254.3.3", "::1", "10.0.0.5"]);
+
+        assert_eq!(
+            select_addresses(&ips(&["::"]), interfaces.clone()),
+            ips(&["10.0.0.5", "192.168.1.5", "2001:db8::5"])
+        );
+        assert_eq!(
+            select_addresses(&ips(&["0.0.0.0"]), interfaces.clone()),
+            ips(&["10.0.0.5", "192.168.1.5"])
+        );
+        assert_eq!(select_addresses(&ips(&["192.168.1.5"]), interfaces.clone()), ips(&["192.168.1.5"]));
+        assert_eq!(
+            select_addresses(&ips(&["::1", "127.0.0.1"]), interfaces.clone()),
+            ips(&["127.0.0.1", "::1"])
+        );
+        assert_eq!(
+            select_addresses(&ips(&["0.0.0.0", "2001:db8::9"]), interfaces),
+            ips(&["10.0.0.5", "192.168.1.5", "2001:db8::9"])
+        );
+    }
+
+    #[test]
+    fn test_records_by_family() {
+        let own = OwnAddresses::with_addresses(ips(&["::"]), ips(&["192.168.1.5", "2001:db8::5"]));
+        let name = Name::from_utf8("discovery-proxy.mdns.home.arpa.").unwrap();
+
+        let a = own.records(&name, RecordType::A);
+        assert_eq!(a.len(), 1);
+        assert_eq!(a[0].data(), &RData::A(A::new(192, 168, 1, 5)));
+        assert_eq!(own.records(&name, RecordType::AAAA).len(), 1);
+        assert!(own.records(&name, RecordType::TXT).is_empty());
+    }
+}
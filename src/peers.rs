@@ -0,0 +1,234 @@
+//! Peer proxies: discovery and query fail-over
+//!
+//! Other proxies on the link are configured by address or found by browsing
+//! `_dns._udp.local.`. When the local mDNS query fails, the question can be
+//! forwarded over unicast DNS to a peer serving the same discovery domain.
+//! Forwarded queries carry a private-use EDNS option; a proxy receiving one never
+//! forwards it again, so two proxies with broken backends cannot bounce a query
+//! between them. Peers that just failed are tried after the others.
+
+use crate::config::PeersConfig;
+use crate::mdns_resolver::MdnsResolver;
+use crate::metrics;
+use crate::random::random_u64;
+use hickory_proto::op::{Edns, Message, Query, ResponseCode};
+use hickory_proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+use hickory_proto::rr::{Name, Record, RecordType};
+use hickory_server::server::Request;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tracing::{debug, info, warn};
+
+/// EDNS option (private use range, RFC 6891 Section 9) marking a forwarded query
+pub const FORWARDED_OPTION: u16 = 65_001;
+
+/// How long a peer that failed is tried last
+const FAILURE_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Service type browsed for peers
+const PEER_SERVICE_TYPE: &str = "_dns._udp.local.";
+
+type PeerResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Known peer proxies and their recent failures
+pub struct PeerSet {
+    configured: Vec<SocketAddr>,
+    discovered: RwLock<Vec<SocketAddr>>,
+    failed: Mutex<HashMap<SocketAddr, Instant>>,
+    timeout: Duration,
+}
+
+impl PeerSet {
+    pub fn new(config: &PeersConfig) -> Self {
+        Self {
+            configured: config.addresses.clone(),
+            discovered: RwLock::new(Vec::new()),
+            failed: Mutex::new(HashMap::new()),
+            timeout: Duration::from_millis(config.forward_timeout_ms),
+        }
+    }
+
+    /// Peers in the order they are tried: configured before discovered, recently failed last
+    pub fn peers(&self) -> Vec<SocketAddr> {
+        let mut peers = self.configured.clone();
+        for addr in self.discovered.read().unwrap().iter() {
+            if !peers.contains(addr) {
+                peers.push(*addr);
+            }
+        }
+        let failed = self.failed.lock().unwrap();
+        // Stable sort keeps the configured/discovered order within each group
+        peers.sort_by_key(|addr| failed.get(addr).is_some_and(|at| at.elapsed() < FAILURE_BACKOFF));
+        peers
+    }
+
+    pub fn set_discovered(&self, peers: Vec<SocketAddr>) {
+        *self.discovered.write().unwrap() = peers;
+    }
+
+    /// Ask each peer in turn; the answers of the first that responds without error
+    pub async fn forward(&self, name: &Name, record_type: RecordType) -> Option<Vec<Record>> {
+        for peer in self.peers() {
+            match exchange(peer, name, record_type, self.timeout).await {
+                Ok(answers) => {
+                    debug!("Peer {} answered {} {:?} with {} record(s)", peer, name, record_type, answers.len());
+                    self.failed.lock().unwrap().remove(&peer);
+                    metrics::inc(&metrics::metrics().peer_forwards);
+                    return Some(answers);
+                }
+                Err(e) => {
+                    warn!("Peer {} failed for {} {:?}: {}", peer, name, record_type, e);
+                    self.failed.lock().unwrap().insert(peer, Instant::now());
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Whether `request` was forwarded by a peer and so must not be forwarded again
+pub fn is_forwarded(request: &Request) -> bool {
+    request
+        .edns()
+        .is_some_and(|edns| edns.option(EdnsCode::Unknown(FORWARDED_OPTION)).is_some())
+}
+
+/// Periodically browse for peers, leaving out this proxy's own `listen` addresses
+pub async fn run_discovery(peers: Arc<PeerSet>, resolver: Arc<MdnsResolver>, listen: Vec<SocketAddr>, interval: Duration) {
+    let service_type = Name::from_ascii(PEER_SERVICE_TYPE).expect("valid service type");
+    let mut ticker = tokio::time::interval(interval);
+    let mut last_count = 0;
+    loop {
+        ticker.tick().await;
+        if resolver.is_read_only() || resolver.is_quiet() {
+            continue;
+        }
+        let instances = match resolver.browse(&service_type).await {
+            Ok(instances) => instances,
+            Err(e) => {
+                warn!("Peer proxy discovery failed: {}", e);
+                continue;
+            }
+        };
+        let mut found: Vec<SocketAddr> = Vec::new();
+        for instance in &instances {
+            for ip in &instance.addresses {
+                let addr = SocketAddr::new(ip.to_ip_addr(), instance.port);
+                if !listen.contains(&addr) && !found.contains(&addr) {
+                    found.push(addr);
+                }
+            }
+        }
+        if found.len() != last_count {
+            info!("Discovered {} peer proxy address(es)", found.len());
+            last_count = found.len();
+        }
+        peers.set_discovered(found);
+    }
+}
+
+async fn exchange(peer: SocketAddr, name: &Name, record_type: RecordType, timeout: Duration) -> PeerResult<Vec<Record>> {
+    let mut query = Message::new();
+    query.set_id(random_u64() as u16);
+    query.add_query(Query::query(name.clone(), record_type));
+    let mut edns = Edns::new();
+    edns.options_mut().insert(EdnsOption::Unknown(FORWARDED_OPTION, Vec::new()));
+    query.set_edns(edns);
+
+    let bind: SocketAddr = if peer.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }.parse()?;
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(peer).await?;
+    socket.send(&query.to_vec()?).await?;
+
+    let mut buf = vec![0u8; 65_535];
+    let response = loop {
+        let len = tokio::time::timeout(timeout, socket.recv(&mut buf))
+            .await
+            .map_err(|_| format!("no response within {:?}", timeout))??;
+        let response = Message::from_vec(&buf[..len])?;
+        // Ignore stray datagrams for other queries
+        if response.id() == query.id() {
+            break response;
+        }
+    };
+    match response.response_code() {
+        ResponseCode::NoError => Ok(response.answers().to_vec()),
+        code => Err(format!("answered {:?}", code).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::RData;
+    use hickory_proto::rr::rdata::A;
+
+    /// Answer one query with an A record, or SERVFAIL if `fail`; returns whether it was marked
+    async fn fake_peer(fail: bool) -> (SocketAddr, tokio::task::JoinHandle<bool>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let handle = tokio::spawn(async move {
+            let mut buf = vec![0u8; 4096];
+            let (len, from) = socket.recv_from(&mut buf).await.unwrap();
+            let query = Message::from_vec(&buf[..len]).unwrap();
+            let marked = query
+                .extensions()
+                .as_ref()
+                .is_some_and(|edns| edns.option(EdnsCode::Unknown(FORWARDED_OPTION)).is_some());
+            let mut response = Message::new();
+            response.set_id(query.id());
+            if fail {
+                response.set_response_code(ResponseCode::ServFail);
+            } else {
+                let name = query.queries()[0].name().clone();
+                response.add_answer(Record::from_rdata(name, 10, RData::A(A::new(192, 168, 1, 20))));
+            }
+            socket.send_to(&response.to_vec().unwrap(), from).await.unwrap();
+            marked
+        });
+        (addr, handle)
+    }
+
+    fn peer_set(addresses: Vec<SocketAddr>) -> PeerSet {
+        PeerSet::new(&PeersConfig {
+            addresses,
+            forward_timeout_ms: 500,
+            ..PeersConfig::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_forward_falls_over_to_next_peer() {
+        let (failing, failing_task) = fake_peer(true).await;
+        let (healthy, healthy_task) = fake_peer(false).await;
+        let peers = peer_set(vec![failing, healthy]);
+
+        let name = Name::from_utf8("printer.mdns.home.arpa.").unwrap();
+        let answers = peers.forward(&name, RecordType::A).await.unwrap();
+        assert_eq!(answers.len(), 1);
+        assert!(failing_task.await.unwrap());
+        assert!(healthy_task.await.unwrap());
+
+        // The peer that failed is now tried last
+        assert_eq!(peers.peers(), vec![healthy, failing]);
+    }
+
+    #[tokio::test]
+    async fn test_forward_gives_up_without_peers() {
+        let peers = peer_set(Vec::new());
+        let name = Name::from_utf8("printer.mdns.home.arpa.").unwrap();
+        assert!(peers.forward(&name, RecordType::A).await.is_none());
+    }
+
+    #[test]
+    fn test_discovered_peers_follow_configured() {
+        let configured: SocketAddr = "192.168.1.3:53".parse().unwrap();
+        let discovered: SocketAddr = "192.168.1.4:53".parse().unwrap();
+        let peers = peer_set(vec![configured]);
+        peers.set_discovered(vec![discovered, configured]);
+        assert_eq!(peers.peers(), vec![configured, discovered]);
+    }
+}
@@ -0,0 +1,176 @@
+//! Queries being answered right now
+//!
+//! Every query registers itself for as long as the engine works on it, along
+//! with the phase it is in (the resolution step running, e.g. `mdns`), so a
+//! slow-query investigation can see what is stuck through the control socket's
+//! `pending` command. Entries are removed by a guard, so cancelled and
+//! panicking queries do not linger.
+
+use hickory_proto::rr::{Name, RecordType};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// In-flight queries by registration number
+#[derive(Debug, Default)]
+pub struct PendingQueries {
+    next_id: AtomicU64,
+    queries: Mutex<HashMap<u64, Entry>>,
+    /// Woken when the last query in flight finishes
+    idle: Notify,
+}
+
+#[derive(Debug)]
+struct Entry {
+    name: Name,
+    record_type: RecordType,
+    client: SocketAddr,
+    started: Instant,
+    phase: &'static str,
+}
+
+/// One in-flight query as listed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingQuery {
+    pub name: Name,
+    pub record_type: RecordType,
+    pub client: SocketAddr,
+    pub elapsed: Duration,
+    pub phase: &'static str,
+}
+
+/// Keeps a query listed until dropped
+pub struct PendingGuard<'a> {
+    registry: &'a PendingQueries,
+    id: u64,
+}
+
+impl PendingQueries {
+    /// List a query until the returned guard is dropped
+    pub fn start(&self, name: &Name, record_type: RecordType, client: SocketAddr) -> PendingGuard<'_> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.queries.lock().unwrap().insert(
+            id,
+            Entry {
+                name: name.clone(),
+                record_type,
+                client,
+                started: Instant::now(),
+                phase: "started",
+            },
+        );
+        PendingGuard { registry: self, id }
+    }
+
+    /// Queries in flight, longest-running first
+    pub fn list(&self) -> Vec<PendingQuery> {
+        let mut queries: Vec<PendingQuery> = self
+            .queries
+            .lock()
+            .unwrap()
+            .values()
+            .map(|entry| PendingQuery {
+                name: entry.name.clone(),
+                record_type: entry.record_type,
+                client: entry.client,
+                elapsed: entry.started.elapsed(),
+                phase: entry.phase,
+            })
+            .collect();
+        queries.sort_by_key(|query| std::cmp::Reverse(query.elapsed));
+        queries
+    }
+
+    /// Number of queries in flight
+    pub fn len(&self) -> usize {
+        self.queries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Wait until no query is in flight or `deadline` passes; returns the
+    /// number still in flight
+    pub async fn wait_idle(&self, deadline: Duration) -> usize {
+        let idle = async {
+            loop {
+                // Created before the check so a query finishing in between still wakes it
+                let finished = self.idle.notified();
+                if self.is_empty() {
+                    return;
+                }
+                finished.await;
+            }
+        };
+        let _ = tokio::time::timeout(deadline, idle).await;
+        self.len()
+    }
+}
+
+impl PendingGuard<'_> {
+    /// Record what the query is waiting on now
+    pub fn set_phase(&self, phase: &'static str) {
+        if let Some(entry) = self.registry.queries.lock().unwrap().get_mut(&self.id) {
+            entry.phase = phase;
+        }
+    }
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        let mut queries = self.registry.queries.lock().unwrap();
+        queries.remove(&self.id);
+        if queries.is_empty() {
+            self.registry.idle.notify_waiters();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listed_until_guard_dropped() {
+        let pending = PendingQueries::default();
+        let client = "192.168.1.40:5353".parse().unwrap();
+        let printer = Name::from_utf8("printer.mdns.home.arpa.").unwrap();
+
+        let first = pending.start(&printer, RecordType::A, client);
+        std::thread::sleep(Duration::from_millis(2));
+        let second = pending.start(&printer, RecordType::AAAA, client);
+        first.set_phase("mdns");
+
+        let listed = pending.list();
+        assert_eq!(listed.len(), 2);
+        assert_eq!((listed[0].record_type, listed[0].phase), (RecordType::A, "mdns"));
+        assert_eq!((listed[1].record_type, listed[1].phase), (RecordType::AAAA, "started"));
+
+        drop(first);
+        assert_eq!(pending.list().len(), 1);
+        drop(second);
+        assert!(pending.list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_wait_idle() {
+        let pending = std::sync::Arc::new(PendingQueries::default());
+        let client = "192.168.1.40:5353".parse().unwrap();
+        let printer = Name::from_utf8("printer.mdns.home.arpa.").unwrap();
+        assert_eq!(pending.wait_idle(Duration::from_secs(1)).await, 0);
+
+        let guard = pending.start(&printer, RecordType::A, client);
+        assert_eq!(pending.wait_idle(Duration::from_millis(20)).await, 1);
+        let waiter = tokio::spawn({
+            let pending = pending.clone();
+            async move { pending.wait_idle(Duration::from_secs(5)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        drop(guard);
+        assert_eq!(tokio::time::timeout(Duration::from_secs(1), waiter).await.unwrap().unwrap(), 0);
+    }
+}
@@ -0,0 +1,638 @@
+//! Response policy from an RPZ file
+//!
+//! Loads a Response Policy Zone (a zone file in the format policy tooling emits
+//! for BIND and Unbound) and applies its QNAME triggers to queries in the served
+//! discovery domains. Trigger owner names are the queried names relative to the
+//! policy zone's origin, e.g. `printer.mdns.home.arpa.rpz.example.` matches
+//! queries for `printer.mdns.home.arpa.`; a leading `*` label matches names
+//! below. Supported actions:
+//!
+//! - `CNAME .` — answer NXDOMAIN
+//! - `CNAME *.` — answer NODATA
+//! - `CNAME rpz-passthru.` — answer normally, overriding a broader trigger
+//! - `CNAME rpz-drop.` — send no response
+//! - any other records — answer with them instead ("local data")
+//!
+//! IP, NSDNAME and NSIP triggers are not supported and are skipped. The file is
+//! polled for changes and reloaded; a file that fails to parse leaves the
+//! previous policy in place.
+//!
+//! The triggers can also be exchanged as JSON [`PolicyRule`]s over the control
+//! socket, so management tools need not write zone files. An imported rule set
+//! is validated as a whole, written over the policy file and swapped in at once.
+
+use crate::config::PolicyConfig;
+use crate::mdns_resolver::{from_presentation, presentation, rdata_presentation};
+use hickory_proto::rr::rdata::CNAME;
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use hickory_proto::serialize::txt::RDataParser;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use tracing::{debug, info, warn};
+
+type PolicyResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// What to do with a query matching a trigger
+#[derive(Debug, Clone, PartialEq)]
+pub enum PolicyAction {
+    NxDomain,
+    NoData,
+    Passthru,
+    Drop,
+    /// Local data, owned by the trigger name
+    Rewrite(Vec<Record>),
+}
+
+impl PolicyAction {
+    /// Records answering `record_type` for `name`; empty means NODATA.
+    /// A local-data CNAME answers every type.
+    pub fn rewrite_answers(records: &[Record], name: &Name, record_type: RecordType) -> Vec<Record> {
+        let cname: Vec<&Record> = records.iter().filter(|r| r.record_type() == RecordType::CNAME).collect();
+        let selected: Vec<&Record> = if cname.is_empty() {
+            records.iter().filter(|r| r.record_type() == record_type).collect()
+        } else {
+            cname
+        };
+        selected
+            .into_iter()
+            .map(|r| {
+                let mut record = r.clone();
+                record.set_name(name.clone());
+                record
+            })
+            .collect()
+    }
+}
+
+/// Origin of policy files written from imported rules
+const RULES_ORIGIN: &str = "rpz.invalid.";
+
+/// One trigger and its action, as exported and imported over the control socket
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PolicyRule {
+    /// Queried name in presentation form; a leading `*` label matches names below it
+    pub trigger: String,
+    pub action: RuleAction,
+    /// Local data answered for `local` rules
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub records: Vec<RuleRecord>,
+}
+
+/// [`PolicyAction`] as named in JSON rules
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    NxDomain,
+    NoData,
+    Passthru,
+    Drop,
+    Local,
+}
+
+/// A local data record of a rule; `data` is in zone file form, e.g. "192.168.1.50"
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleRecord {
+    #[serde(rename = "type")]
+    pub record_type: String,
+    #[serde(default = "default_rule_ttl")]
+    pub ttl: u32,
+    pub data: String,
+}
+
+fn default_rule_ttl() -> u32 {
+    60
+}
+
+impl PolicyRule {
+    fn new(trigger: String, action: &PolicyAction) -> Self {
+        let (action, records) = match action {
+            PolicyAction::NxDomain => (RuleAction::NxDomain, Vec::new()),
+            PolicyAction::NoData => (RuleAction::NoData, Vec::new()),
+            PolicyAction::Passthru => (RuleAction::Passthru, Vec::new()),
+            PolicyAction::Drop => (RuleAction::Drop, Vec::new()),
+            PolicyAction::Rewrite(records) => {
+                let records = records
+                    .iter()
+                    .map(|record| RuleRecord {
+                        record_type: record.record_type().to_string(),
+                        ttl: record.ttl(),
+                        data: rdata_presentation(record.data()),
+                    })
+                    .collect();
+                (RuleAction::Local, records)
+            }
+        };
+        Self { trigger, action, records }
+    }
+
+    /// The rule as RPZ zone file lines relative to [`RULES_ORIGIN`]
+    fn zone_lines(&self) -> PolicyResult<String> {
+        let trigger = from_presentation(&self.trigger)?;
+        if trigger.is_root() {
+            return Err("the root is not a trigger".into());
+        }
+        let owner = presentation(&trigger);
+        let owner = owner.trim_end_matches('.');
+        let target = match self.action {
+            RuleAction::NxDomain => ".",
+            RuleAction::NoData => "*.",
+            RuleAction::Passthru => "rpz-passthru.",
+            RuleAction::Drop => "rpz-drop.",
+            RuleAction::Local => {
+                if self.records.is_empty() {
+                    return Err("local rule without records".into());
+                }
+                let mut lines = String::new();
+                for record in &self.records {
+                    if record.data.contains(['\n', '\r']) {
+                        return Err("record data spans lines".into());
+                    }
+                    lines.push_str(&format!("{} {} {} {}\n", owner, record.ttl, record.record_type, record.data));
+                }
+                return Ok(lines);
+            }
+        };
+        if !self.records.is_empty() {
+            return Err(format!("records given for a {:?} rule", self.action).into());
+        }
+        Ok(format!("{} CNAME {}\n", owner, target))
+    }
+}
+
+/// Triggers parsed from one RPZ file
+#[derive(Debug, Default)]
+pub struct Policy {
+    /// Keyed by lowercase trigger name
+    exact: HashMap<Name, PolicyAction>,
+    /// `*.example.` triggers, keyed by lowercase `example.`
+    wildcard: HashMap<Name, PolicyAction>,
+}
+
+impl Policy {
+    /// Parse RPZ zone file text; `origin` applies when the file has no `$ORIGIN`
+    pub fn parse(contents: &str, origin: Option<Name>) -> PolicyResult<Self> {
+        let (origin, records) = parse_zone(contents, origin.unwrap_or_else(Name::root))?;
+
+        let mut triggers: Vec<(Name, Vec<Record>)> = Vec::new();
+        for record in records {
+            if matches!(record.record_type(), RecordType::SOA | RecordType::NS) {
+                continue;
+            }
+            let owner = record.name();
+            // Owners outside the origin, such as an absolute name shorter than it, are not triggers
+            if !origin.zone_of(owner) {
+                continue;
+            }
+            // num_labels() leaves out a leading `*`, so count the labels themselves
+            let prefix = owner.iter().count() - origin.iter().count();
+            if prefix == 0 {
+                continue;
+            }
+            let trigger = Name::from_labels(owner.iter().take(prefix))?.to_lowercase();
+            // The rpz-* labels introduce IP and name server triggers
+            if trigger.iter().any(|label| label.to_ascii_lowercase().starts_with(b"rpz-")) {
+                debug!("Skipping unsupported RPZ trigger {}", owner);
+                continue;
+            }
+            match triggers.iter_mut().find(|(t, _)| *t == trigger) {
+                Some((_, records)) => records.push(record),
+                None => triggers.push((trigger, vec![record])),
+            }
+        }
+
+        let mut policy = Policy::default();
+        for (trigger, records) in triggers {
+            let action = action_for(records);
+            if trigger.is_wildcard() {
+                policy.wildcard.insert(trigger.base_name(), action);
+            } else {
+                policy.exact.insert(trigger, action);
+            }
+        }
+        Ok(policy)
+    }
+
+    pub fn load(path: &Path) -> PolicyResult<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        Self::parse(&contents, None).map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+
+    /// Policy from imported rules, with the RPZ zone file text it was parsed from.
+    /// Every rule must parse on its own and name a trigger no other rule names.
+    pub fn from_rules(rules: &[PolicyRule]) -> PolicyResult<(Self, String)> {
+        let mut zone = format!("; Written by a policy import over the control socket\n$ORIGIN {}\n", RULES_ORIGIN);
+        for (i, rule) in rules.iter().enumerate() {
+            let at = |e: Box<dyn std::error::Error + Send + Sync>| format!("rule {} ({}): {}", i + 1, rule.trigger, e);
+            let lines = rule.zone_lines().map_err(at)?;
+            let alone = Self::parse(&format!("$ORIGIN {}\n{}", RULES_ORIGIN, lines), None).map_err(at)?;
+            if alone.is_empty() {
+                return Err(at("unsupported trigger".into()).into());
+            }
+            zone.push_str(&lines);
+        }
+        let policy = Self::parse(&zone, None)?;
+        if policy.len() != rules.len() {
+            return Err("rules repeat a trigger".into());
+        }
+        Ok((policy, zone))
+    }
+
+    /// Every trigger as a rule, sorted by trigger
+    pub fn to_rules(&self) -> Vec<PolicyRule> {
+        let exact = self.exact.iter().map(|(trigger, action)| PolicyRule::new(presentation(trigger), action));
+        let wildcard = self
+            .wildcard
+            .iter()
+            .map(|(base, action)| PolicyRule::new(format!("*.{}", presentation(base)), action));
+        let mut rules: Vec<PolicyRule> = exact.chain(wildcard).collect();
+        rules.sort_by(|a, b| a.trigger.cmp(&b.trigger));
+        rules
+    }
+
+    /// Number of triggers
+    pub fn len(&self) -> usize {
+        self.exact.len() + self.wildcard.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Action for `name`: an exact trigger, else the wildcard of the closest enclosing name
+    pub fn lookup(&self, name: &Name) -> Option<&PolicyAction> {
+        let name = name.to_lowercase();
+        if let Some(action) = self.exact.get(&name) {
+            return Some(action);
+        }
+        let mut base = name.base_name();
+        loop {
+            if let Some(action) = self.wildcard.get(&base) {
+                return Some(action);
+            }
+            if base.is_root() {
+                return None;
+            }
+            base = base.base_name();
+        }
+    }
+}
+
+/// Records of a zone file, one per line (or per parenthesized group). Owner names
+/// may carry `\DDD` escapes, so instance names with spaces can be triggers.
+fn parse_zone(contents: &str, mut origin: Name) -> PolicyResult<(Name, Vec<Record>)> {
+    let mut ttl = 3600;
+    let mut owner: Option<Name> = None;
+    let mut records = Vec::new();
+    let mut pending = String::new();
+
+    for (number, line) in contents.lines().enumerate() {
+        let line = strip_comment(line);
+        pending.push_str(line);
+        pending.push(' ');
+        if pending.matches('(').count() > pending.matches(')').count() {
+            continue;
+        }
+        let entry = std::mem::take(&mut pending).replace(['(', ')'], " ");
+        let continues_owner = entry.starts_with([' ', '\t']);
+        let mut tokens = entry.split_whitespace();
+        let Some(first) = tokens.next() else {
+            continue;
+        };
+        let at = |e: Box<dyn std::error::Error + Send + Sync>| format!("line {}: {}", number + 1, e);
+
+        match first {
+            "$ORIGIN" => {
+                let name = tokens.next().ok_or("line without name after $ORIGIN")?;
+                origin = parse_name(name, &origin).map_err(at)?;
+                continue;
+            }
+            "$TTL" => {
+                let value = tokens.next().ok_or("line without value after $TTL")?;
+                ttl = value.parse().map_err(|e| at(Box::new(e)))?;
+                continue;
+            }
+            _ => {}
+        }
+
+        let mut tokens: Vec<&str> = if continues_owner {
+            std::iter::once(first).chain(tokens).collect()
+        } else {
+            owner = Some(parse_name(first, &origin).map_err(at)?);
+            tokens.collect()
+        };
+        let owner = owner.clone().ok_or_else(|| format!("line {}: record without owner", number + 1))?;
+
+        let mut record_ttl = ttl;
+        let record_type = loop {
+            let token = (!tokens.is_empty()).then(|| tokens.remove(0));
+            let token = token.ok_or_else(|| format!("line {}: record without type", number + 1))?;
+            if let Ok(value) = token.parse::<u32>() {
+                record_ttl = value;
+            } else if !token.eq_ignore_ascii_case("IN") {
+                break token.to_ascii_uppercase().parse::<RecordType>().map_err(|e| at(Box::new(e)))?;
+            }
+        };
+        let rdata = match record_type {
+            RecordType::CNAME => {
+                let target = tokens.first().ok_or_else(|| format!("line {}: CNAME without target", number + 1))?;
+                RData::CNAME(CNAME(parse_name(target, &origin).map_err(at)?))
+            }
+            _ => RData::try_from_str(record_type, &tokens.join(" ")).map_err(|e| at(Box::new(e)))?,
+        };
+        records.push(Record::from_rdata(owner, record_ttl, rdata));
+    }
+    Ok((origin, records))
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Zone file name: `@`, absolute, or relative to `origin`, with `\DDD` and `\X` escapes
+fn parse_name(text: &str, origin: &Name) -> PolicyResult<Name> {
+    if text == "@" {
+        return Ok(origin.clone());
+    }
+    if text == "." {
+        return Ok(Name::root());
+    }
+    let mut labels: Vec<Vec<u8>> = vec![Vec::new()];
+    let mut chars = text.chars();
+    let mut absolute = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let next = chars.next().ok_or("name ends in a backslash")?;
+                if next.is_ascii_digit() {
+                    let digits: String = std::iter::once(next).chain(chars.by_ref().take(2)).collect();
+                    let byte: u8 = digits.parse().map_err(|_| format!("bad escape \\{} in {}", digits, text))?;
+                    labels.last_mut().unwrap().push(byte);
+                } else {
+                    let mut buf = [0; 4];
+                    labels.last_mut().unwrap().extend_from_slice(next.encode_utf8(&mut buf).as_bytes());
+                }
+            }
+            '.' if chars.as_str().is_empty() => absolute = true,
+            '.' => labels.push(Vec::new()),
+            c => {
+                let mut buf = [0; 4];
+                labels.last_mut().unwrap().extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+    }
+    let name = Name::from_labels(labels)?;
+    Ok(if absolute { name } else { name.append_domain(origin)? })
+}
+
+/// A CNAME to one of the special targets is an action; anything else is local data
+fn action_for(records: Vec<Record>) -> PolicyAction {
+    if let [record] = records.as_slice()
+        && let RData::CNAME(target) = record.data()
+    {
+        let target = target.0.to_lowercase();
+        if target.is_root() {
+            return PolicyAction::NxDomain;
+        }
+        if target.is_wildcard() && target.base_name().is_root() {
+            return PolicyAction::NoData;
+        }
+        match target.to_ascii().as_str() {
+            "rpz-passthru." => return PolicyAction::Passthru,
+            "rpz-drop." => return PolicyAction::Drop,
+            _ => {}
+        }
+    }
+    PolicyAction::Rewrite(records)
+}
+
+/// The current policy, replaced when the file changes
+pub struct PolicyStore {
+    path: PathBuf,
+    current: RwLock<Arc<Policy>>,
+    modified: Mutex<Option<SystemTime>>,
+}
+
+impl PolicyStore {
+    /// Load `path`; fails if the file cannot be read or parsed
+    pub fn open(path: &Path) -> PolicyResult<Self> {
+        let modified = std::fs::metadata(path)?.modified().ok();
+        let policy = Policy::load(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            current: RwLock::new(Arc::new(policy)),
+            modified: Mutex::new(modified),
+        })
+    }
+
+    pub fn current(&self) -> Arc<Policy> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Reload if the file's modification time changed; returns whether it was reloaded
+    pub fn reload_if_changed(&self) -> PolicyResult<bool> {
+        let modified = std::fs::metadata(&self.path)?.modified().ok();
+        let mut last = self.modified.lock().unwrap();
+        if modified == *last {
+            return Ok(false);
+        }
+        // Remember the attempt so a broken file is reported once, not on every poll
+        *last = modified;
+        let policy = Policy::load(&self.path)?;
+        *self.current.write().unwrap() = Arc::new(policy);
+        Ok(true)
+    }
+
+    /// Replace the policy with `rules`: nothing changes unless all of them are
+    /// valid; then the file is replaced (through a rename, so a reader never
+    /// sees it half written) and the new policy takes effect at once.
+    /// Returns the number of triggers.
+    pub fn import(&self, rules: &[PolicyRule]) -> PolicyResult<usize> {
+        let (policy, zone) = Policy::from_rules(rules)?;
+        let file_name = self.path.file_name().ok_or("policy file has no name")?;
+        let temporary = self.path.with_file_name(format!(".{}.tmp", file_name.to_string_lossy()));
+
+        // Held throughout so a poll in between does not load the file a second time
+        let mut last = self.modified.lock().unwrap();
+        std::fs::write(&temporary, zone)
+            .and_then(|()| std::fs::rename(&temporary, &self.path))
+            .map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        *last = std::fs::metadata(&self.path)?.modified().ok();
+        let triggers = policy.len();
+        *self.current.write().unwrap() = Arc::new(policy);
+        Ok(triggers)
+    }
+}
+
+/// Poll the policy file and reload it on change
+pub async fn run(store: Arc<PolicyStore>, config: PolicyConfig) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(config.reload_interval_secs.max(1)));
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        match store.reload_if_changed() {
+            Ok(true) => info!("Reloaded response policy ({} triggers)", store.current().len()),
+            Ok(false) => {}
+            Err(e) => warn!("Failed to reload response policy, keeping the previous one: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RPZ: &str = r#"
+$ORIGIN rpz.example.
+$TTL 60
+@                                       SOA ns.example. admin.example. 1 3600 600 86400 60
+@                                       NS  ns.example.
+blocked.mdns.home.arpa                  CNAME .
+*.guest.mdns.home.arpa                  CNAME *.
+ok.guest.mdns.home.arpa                 CNAME rpz-passthru.
+Secret\032Printer._ipp._tcp.mdns.home.arpa CNAME rpz-drop.
+nas.mdns.home.arpa                      A   192.168.1.50
+alias.mdns.home.arpa                    CNAME nas.mdns.home.arpa.
+32.1.168.192.rpz-ip                     CNAME .
+"#;
+
+    fn name(name: &str) -> Name {
+        Name::from_utf8(name).unwrap()
+    }
+
+    #[test]
+    fn test_parse_actions() {
+        let policy = Policy::parse(RPZ, None).unwrap();
+        assert_eq!(policy.len(), 6);
+        assert_eq!(policy.lookup(&name("Blocked.mdns.home.arpa.")), Some(&PolicyAction::NxDomain));
+        assert_eq!(policy.lookup(&name("tv.guest.mdns.home.arpa.")), Some(&PolicyAction::NoData));
+        assert_eq!(policy.lookup(&name("a.b.guest.mdns.home.arpa.")), Some(&PolicyAction::NoData));
+        assert_eq!(policy.lookup(&name("ok.guest.mdns.home.arpa.")), Some(&PolicyAction::Passthru));
+        let instance = crate::mdns_resolver::from_presentation("secret printer._ipp._tcp.mdns.home.arpa.").unwrap();
+        assert_eq!(policy.lookup(&instance), Some(&PolicyAction::Drop));
+        assert!(matches!(policy.lookup(&name("nas.mdns.home.arpa.")), Some(PolicyAction::Rewrite(_))));
+        assert_eq!(policy.lookup(&name("guest.mdns.home.arpa.")), None);
+        assert_eq!(policy.lookup(&name("printer.mdns.home.arpa.")), None);
+    }
+
+    #[test]
+    fn test_owners_outside_origin_are_skipped() {
+        let rpz = "$ORIGIN rpz.example.\nlocal. CNAME .\nother.example. CNAME .\nblocked CNAME .\n";
+        let policy = Policy::parse(rpz, None).unwrap();
+        assert_eq!(policy.len(), 1);
+        assert_eq!(policy.lookup(&name("blocked.")), Some(&PolicyAction::NxDomain));
+        assert_eq!(policy.lookup(&name("local.")), None);
+    }
+
+    #[test]
+    fn test_rewrite_answers() {
+        let policy = Policy::parse(RPZ, None).unwrap();
+        let Some(PolicyAction::Rewrite(records)) = policy.lookup(&name("nas.mdns.home.arpa.")) else {
+            panic!("expected local data");
+        };
+        let qname = name("NAS.mdns.home.arpa.");
+        let answers = PolicyAction::rewrite_answers(records, &qname, RecordType::A);
+        assert_eq!(answers.len(), 1);
+        assert_eq!(answers[0].name(), &qname);
+        assert!(PolicyAction::rewrite_answers(records, &qname, RecordType::AAAA).is_empty());
+
+        let Some(PolicyAction::Rewrite(records)) = policy.lookup(&name("alias.mdns.home.arpa.")) else {
+            panic!("expected local data");
+        };
+        let answers = PolicyAction::rewrite_answers(records, &name("alias.mdns.home.arpa."), RecordType::AAAA);
+        assert_eq!(answers[0].record_type(), RecordType::CNAME);
+    }
+
+    #[test]
+    fn test_rules_round_trip() {
+        let policy = Policy::parse(RPZ, None).unwrap();
+        let rules = policy.to_rules();
+        assert_eq!(rules.len(), 6);
+        let drop = rules.iter().find(|r| r.action == RuleAction::Drop).unwrap();
+        assert_eq!(drop.trigger, "secret\\032printer._ipp._tcp.mdns.home.arpa.");
+        let nodata = rules.iter().find(|r| r.action == RuleAction::NoData).unwrap();
+        assert_eq!(nodata.trigger, "*.guest.mdns.home.arpa.");
+        let alias = rules.iter().find(|r| r.trigger == "alias.mdns.home.arpa.").unwrap();
+        assert_eq!(alias.records[0].data, "nas.mdns.home.arpa.");
+
+        let json = serde_json::to_string(&rules).unwrap();
+        let parsed: Vec<PolicyRule> = serde_json::from_str(&json).unwrap();
+        let (imported, _) = Policy::from_rules(&parsed).unwrap();
+        assert_eq!(imported.to_rules(), rules);
+        assert_eq!(imported.lookup(&name("x.guest.mdns.home.arpa.")), Some(&PolicyAction::NoData));
+    }
+
+    #[test]
+    fn test_invalid_rules_are_rejected() {
+        let rule = |json: &str| -> Vec<PolicyRule> { serde_json::from_str(json).unwrap() };
+        let err = |rules: Vec<PolicyRule>| Policy::from_rules(&rules).err().unwrap().to_string();
+
+        let bad = rule(r#"[{"trigger":"a.mdns.home.arpa.","action":"nxdomain"},
+                          {"trigger":"b.mdns.home.arpa.","action":"local","records":[{"type":"A","data":"not-an-ip"}]}]"#);
+        assert!(err(bad).starts_with("rule 2 (b.mdns.home.arpa.)"));
+        assert!(err(rule(r#"[{"trigger":"a.mdns.home.arpa.","action":"local"}]"#)).contains("without records"));
+        let extra = r#"[{"trigger":"a.mdns.home.arpa.","action":"drop","records":[{"type":"A","data":"192.0.2.1"}]}]"#;
+        assert!(err(rule(extra)).contains("records given"));
+        let twice = r#"[{"trigger":"a.mdns.home.arpa.","action":"drop"},{"trigger":"A.mdns.home.arpa","action":"nodata"}]"#;
+        assert!(err(rule(twice)).contains("repeat"));
+        assert!(serde_json::from_str::<Vec<PolicyRule>>(r#"[{"trigger":"a.","action":"block"}]"#).is_err());
+    }
+
+    #[test]
+    fn test_store_import_replaces_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("policy.rpz");
+        std::fs::write(&path, "$ORIGIN rpz.example.\nblocked.mdns.home.arpa 60 CNAME .\n").unwrap();
+        let store = PolicyStore::open(&path).unwrap();
+
+        let rules: Vec<PolicyRule> = serde_json::from_str(
+            r#"[{"trigger":"nas.mdns.home.arpa.","action":"local","records":[{"type":"A","ttl":30,"data":"192.168.1.50"}]}]"#,
+        )
+        .unwrap();
+        assert_eq!(store.import(&rules).unwrap(), 1);
+        assert!(store.current().lookup(&name("blocked.mdns.home.arpa.")).is_none());
+        assert!(!store.reload_if_changed().unwrap());
+        assert_eq!(Policy::load(&path).unwrap().to_rules(), rules);
+
+        // An invalid set leaves file and policy alone
+        let broken: Vec<PolicyRule> = serde_json::from_str(r#"[{"trigger":".","action":"drop"}]"#).unwrap();
+        assert!(store.import(&broken).is_err());
+        assert_eq!(store.current().to_rules(), rules);
+        assert_eq!(Policy::load(&path).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_store_reloads_on_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("policy.rpz");
+        std::fs::write(&path, "$ORIGIN rpz.example.\nblocked.mdns.home.arpa 60 CNAME .\n").unwrap();
+        let store = PolicyStore::open(&path).unwrap();
+        assert_eq!(store.current().len(), 1);
+        assert!(!store.reload_if_changed().unwrap());
+
+        std::fs::write(&path, "$ORIGIN rpz.example.\na.mdns.home.arpa 60 CNAME .\nb.mdns.home.arpa 60 CNAME .\n")
+            .unwrap();
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(5)).unwrap();
+        assert!(store.reload_if_changed().unwrap());
+        assert_eq!(store.current().len(), 2);
+
+        // A broken file keeps the previous policy
+        std::fs::write(&path, "$ORIGIN rpz.example.\nbroken IN BOGUS data\n").unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+        assert!(store.reload_if_changed().is_err());
+        assert_eq!(store.current().len(), 2);
+    }
+}
@@ -0,0 +1,260 @@
+//! DNS Stateful Operations messages (RFC 8490) and the DNS Push TLVs (RFC 8765)
+//!
+//! A DSO message is a DNS header with opcode 6 and all four section counts
+//! zero, followed by TLVs: a 16-bit type, a 16-bit length and that many bytes
+//! of data. The first TLV of a request is its primary TLV and says what is
+//! asked; any after it are modifiers such as padding. A request carries a
+//! nonzero message ID and is answered; a unidirectional message has ID 0.
+
+use hickory_proto::ProtoError;
+use hickory_proto::rr::{DNSClass, Name, Record, RecordType};
+use hickory_proto::serialize::binary::{BinDecodable, BinDecoder, BinEncodable, BinEncoder};
+
+/// DSO opcode (RFC 8490 Section 10.2)
+pub const OPCODE: u8 = 6;
+
+/// Keepalive TLV: inactivity timeout and keepalive interval in milliseconds
+pub const KEEPALIVE: u16 = 0x0001;
+/// Retry Delay TLV
+pub const RETRY_DELAY: u16 = 0x0002;
+/// Encryption Padding TLV, a modifier ignored on receipt
+pub const ENCRYPTION_PADDING: u16 = 0x0003;
+/// SUBSCRIBE TLV (RFC 8765 Section 6.2)
+pub const SUBSCRIBE: u16 = 0x0040;
+/// PUSH TLV (RFC 8765 Section 6.3)
+pub const PUSH: u16 = 0x0041;
+/// UNSUBSCRIBE TLV (RFC 8765 Section 6.4)
+pub const UNSUBSCRIBE: u16 = 0x0042;
+/// RECONFIRM TLV (RFC 8765 Section 6.5)
+pub const RECONFIRM: u16 = 0x0043;
+
+/// RCODE answering a request whose primary TLV is not implemented
+pub const DSOTYPENI: u8 = 11;
+
+/// TTL marking a record in a PUSH TLV as removed (RFC 8765 Section 6.3.1)
+pub const DELETE_TTL: u32 = 0xFFFF_FFFF;
+
+const HEADER_LEN: usize = 12;
+
+/// hickory remembers names for compression only within the first 16 KiB of a
+/// message, so records written past that point come out uncompressed
+const UNCOMPRESSED_OFFSET: usize = 0x4000;
+
+/// One TLV of a DSO message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tlv<'a> {
+    pub tlv_type: u16,
+    pub data: &'a [u8],
+}
+
+/// Decoded DSO message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message<'a> {
+    pub id: u16,
+    pub response: bool,
+    pub rcode: u8,
+    pub tlvs: Vec<Tlv<'a>>,
+}
+
+impl Message<'_> {
+    /// First TLV, which says what a request asks for
+    pub fn primary(&self) -> Option<&Tlv<'_>> {
+        self.tlvs.first()
+    }
+
+    /// A unidirectional message, sent without expecting a response
+    pub fn is_unidirectional(&self) -> bool {
+        self.id == 0
+    }
+}
+
+/// Opcode of the DNS message in `bytes`, which must hold at least a header
+pub fn opcode(bytes: &[u8]) -> u8 {
+    (bytes[2] >> 3) & 0x0F
+}
+
+/// Decode a DSO message
+pub fn parse(bytes: &[u8]) -> Result<Message<'_>, String> {
+    if bytes.len() < HEADER_LEN {
+        return Err("message shorter than a header".to_string());
+    }
+    if opcode(bytes) != OPCODE {
+        return Err(format!("opcode {} is not DSO", opcode(bytes)));
+    }
+    if bytes[4..HEADER_LEN].iter().any(|b| *b != 0) {
+        return Err("DSO message with nonzero section counts".to_string());
+    }
+
+    let mut tlvs = Vec::new();
+    let mut rest = &bytes[HEADER_LEN..];
+    while !rest.is_empty() {
+        if rest.len() < 4 {
+            return Err("truncated TLV header".to_string());
+        }
+        let tlv_type = u16::from_be_bytes([rest[0], rest[1]]);
+        let len = u16::from_be_bytes([rest[2], rest[3]]) as usize;
+        let data = rest.get(4..4 + len).ok_or_else(|| format!("TLV {} overruns the message", tlv_type))?;
+        tlvs.push(Tlv { tlv_type, data });
+        rest = &rest[4 + len..];
+    }
+
+    Ok(Message {
+        id: u16::from_be_bytes([bytes[0], bytes[1]]),
+        response: bytes[2] & 0x80 != 0,
+        rcode: bytes[3] & 0x0F,
+        tlvs,
+    })
+}
+
+/// Encode a DSO message; `rcode` must fit the header's four bits
+pub fn encode(id: u16, response: bool, rcode: u8, tlvs: &[(u16, Vec<u8>)]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_LEN + tlvs.iter().map(|(_, data)| 4 + data.len()).sum::<usize>());
+    bytes.extend_from_slice(&id.to_be_bytes());
+    bytes.push(if response { 0x80 } else { 0 } | (OPCODE << 3));
+    bytes.push(rcode & 0x0F);
+    bytes.extend_from_slice(&[0; 8]);
+    for (tlv_type, data) in tlvs {
+        bytes.extend_from_slice(&tlv_type.to_be_bytes());
+        bytes.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(data);
+    }
+    bytes
+}
+
+/// Keepalive TLV data
+pub fn keepalive(inactivity_timeout_ms: u32, keepalive_interval_ms: u32) -> Vec<u8> {
+    let mut data = inactivity_timeout_ms.to_be_bytes().to_vec();
+    data.extend_from_slice(&keepalive_interval_ms.to_be_bytes());
+    data
+}
+
+/// Question of a SUBSCRIBE TLV
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subscribe {
+    pub name: Name,
+    pub record_type: RecordType,
+    pub class: DNSClass,
+}
+
+/// Decode SUBSCRIBE TLV data: an uncompressed name, a type and a class
+pub fn parse_subscribe(data: &[u8]) -> Result<Subscribe, String> {
+    let mut decoder = BinDecoder::new(data);
+    let name = Name::read(&mut decoder).map_err(|e| format!("bad SUBSCRIBE name: {}", e))?;
+    let record_type = decoder.read_u16().map_err(|e| e.to_string())?.unverified();
+    let class = decoder.read_u16().map_err(|e| e.to_string())?.unverified();
+    if !decoder.is_empty() {
+        return Err("trailing bytes after the SUBSCRIBE question".to_string());
+    }
+    Ok(Subscribe {
+        name,
+        record_type: RecordType::from(record_type),
+        class: DNSClass::from(class),
+    })
+}
+
+/// Decode UNSUBSCRIBE TLV data: the message ID of the SUBSCRIBE it ends
+pub fn parse_unsubscribe(data: &[u8]) -> Option<u16> {
+    <[u8; 2]>::try_from(data).ok().map(u16::from_be_bytes)
+}
+
+/// A change carried by a PUSH TLV
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Add(Record),
+    Remove(Record),
+}
+
+/// PUSH TLV data: the changed records, uncompressed as RFC 8765 Section 6.3.1
+/// requires, removed ones with [`DELETE_TTL`]. hickory's canonical mode would
+/// drop compression as well, but lowercases instance names.
+pub fn push_data(changes: &[Change]) -> Result<Vec<u8>, ProtoError> {
+    let mut buffer = vec![0; UNCOMPRESSED_OFFSET];
+    let mut encoder = BinEncoder::new(&mut buffer);
+    encoder.set_offset(UNCOMPRESSED_OFFSET);
+    for change in changes {
+        match change {
+            Change::Add(record) => record.emit(&mut encoder)?,
+            Change::Remove(record) => {
+                let mut record = record.clone();
+                record.set_ttl(DELETE_TTL);
+                record.emit(&mut encoder)?;
+            }
+        }
+    }
+    buffer.drain(..UNCOMPRESSED_OFFSET);
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::{Header, OpCode};
+    use hickory_proto::rr::RData;
+    use hickory_proto::rr::rdata::PTR;
+
+    fn subscribe_data(name: &str, record_type: RecordType) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut encoder = BinEncoder::new(&mut data);
+        Name::from_utf8(name).unwrap().emit(&mut encoder).unwrap();
+        encoder.emit_u16(record_type.into()).unwrap();
+        encoder.emit_u16(DNSClass::IN.into()).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_messages_roundtrip() {
+        let bytes = encode(7, false, 0, &[(SUBSCRIBE, subscribe_data("_ipp._tcp.mdns.home.arpa.", RecordType::PTR))]);
+        let header = Header::from_bytes(&bytes[..HEADER_LEN]).unwrap();
+        assert_eq!(header.op_code(), OpCode::from_u8(OPCODE));
+        assert_eq!(opcode(&bytes), OPCODE);
+
+        let message = parse(&bytes).unwrap();
+        assert_eq!((message.id, message.response, message.rcode), (7, false, 0));
+        let primary = message.primary().unwrap();
+        assert_eq!(primary.tlv_type, SUBSCRIBE);
+        let subscribe = parse_subscribe(primary.data).unwrap();
+        assert_eq!(subscribe.name, Name::from_utf8("_ipp._tcp.mdns.home.arpa.").unwrap());
+        assert_eq!((subscribe.record_type, subscribe.class), (RecordType::PTR, DNSClass::IN));
+
+        let bytes = encode(7, true, DSOTYPENI, &[]);
+        let response = parse(&bytes).unwrap();
+        assert!(response.response && response.tlvs.is_empty());
+        assert_eq!(response.rcode, DSOTYPENI);
+        assert_eq!(parse_unsubscribe(&7u16.to_be_bytes()), Some(7));
+        assert_eq!(parse_unsubscribe(&[0, 7, 0]), None);
+    }
+
+    #[test]
+    fn test_malformed_messages_are_rejected() {
+        let mut bytes = encode(1, false, 0, &[(KEEPALIVE, keepalive(15_000, 3_600_000))]);
+        assert_eq!(parse(&bytes).unwrap().primary().unwrap().data.len(), 8);
+        bytes.pop();
+        assert!(parse(&bytes).unwrap_err().contains("overruns"));
+
+        let mut counts = encode(1, false, 0, &[]);
+        counts[5] = 1;
+        assert!(parse(&counts).is_err());
+
+        let mut data = subscribe_data("_ipp._tcp.mdns.home.arpa.", RecordType::PTR);
+        data.push(0);
+        assert!(parse_subscribe(&data).is_err());
+    }
+
+    #[test]
+    fn test_push_data_is_uncompressed_and_keeps_case() {
+        let owner = Name::from_ascii("_ipp._tcp.mdns.home.arpa.").unwrap();
+        let target = Name::from_ascii("Office._ipp._tcp.mdns.home.arpa.").unwrap();
+        let record = Record::from_rdata(owner.clone(), 10, RData::PTR(PTR(target.clone())));
+        let data = push_data(&[Change::Add(record.clone()), Change::Remove(record)]).unwrap();
+
+        // No compression pointers, so each record decodes on its own
+        let mut decoder = BinDecoder::new(&data);
+        let added = Record::read(&mut decoder).unwrap();
+        let removed = Record::read(&mut decoder).unwrap();
+        assert!(decoder.is_empty());
+        assert_eq!((added.ttl(), removed.ttl()), (10, DELETE_TTL));
+        let RData::PTR(ptr) = removed.data() else { panic!("not a PTR") };
+        assert_eq!(ptr.0.to_utf8(), "Office._ipp._tcp.mdns.home.arpa.");
+        assert_eq!(data.len(), 2 * (owner.len() + 1 + 10 + target.len() + 1));
+    }
+}
@@ -0,0 +1,520 @@
+//! DNS Push Notifications (RFC 8765)
+//!
+//! A client on the TCP or DNS-over-TLS listener or the Unix socket opens a DNS
+//! Stateful Operations session (RFC 8490) by sending a DSO request, and SUBSCRIBEs to a
+//! name and type in a discovery zone. The subscription is answered through the
+//! [`QueryEngine`] like a query, so zones, response policy and suppression
+//! apply, and the answer is sent in a first PUSH message. Afterwards:
+//!
+//! - PTR subscriptions to a service type or subtype and SRV or TXT
+//!   subscriptions to an instance follow the resolver's browse of the service
+//!   type ([`Watch`]), so instances that appear, change or leave are pushed as
+//!   soon as mDNS reports them;
+//! - everything else (addresses, administrative and policy answers)
+//!   is re-resolved every `push.refresh_secs` and the difference pushed.
+//!
+//! DNS-over-TLS connections are framed by [`tcp::serve_tls`](crate::tcp::serve_tls)
+//! like TCP ones, so their sessions work the same way.
+//!
+//! [`Watch`]: crate::mdns_resolver::Watch
+
+pub mod dso;
+
+use crate::config::PushConfig;
+use crate::dns_handler::{ClientMeta, QueryEngine};
+use crate::mdns_resolver::{presentation, Watch};
+use crate::metrics;
+use crate::tcp::write_message;
+use crate::toggles::Feature;
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::{DNSClass, Name, Record, RecordType};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWrite;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use dso::Change;
+
+/// Most records sent in one PUSH message, keeping it well below the 64 KiB TCP limit
+const MAX_PUSH_RECORDS: usize = 32;
+
+/// Subscriptions and keepalive settings shared by every DSO session
+pub struct PushService {
+    engine: QueryEngine,
+    config: PushConfig,
+}
+
+impl PushService {
+    pub fn new(engine: QueryEngine, config: PushConfig) -> Self {
+        Self { engine, config }
+    }
+
+    /// Whether push is switched on (see [`crate::toggles`]); when it is off,
+    /// DSO messages are not accepted and subscriptions end
+    pub fn is_enabled(&self) -> bool {
+        self.engine.resolver().toggles().is_enabled(Feature::Push)
+    }
+
+    fn inactivity_timeout(&self) -> Duration {
+        Duration::from_secs(self.config.inactivity_timeout_secs)
+    }
+
+    fn keepalive_interval(&self) -> Duration {
+        Duration::from_secs(self.config.keepalive_interval_secs)
+    }
+
+    /// Keepalive TLV data announcing this server's timeouts
+    fn keepalive(&self) -> Vec<u8> {
+        let ms = |d: Duration| u32::try_from(d.as_millis()).unwrap_or(u32::MAX);
+        dso::keepalive(ms(self.inactivity_timeout()), ms(self.keepalive_interval()))
+    }
+}
+
+/// A running subscription
+struct Subscription {
+    name: Name,
+    record_type: RecordType,
+    task: JoinHandle<()>,
+}
+
+/// The DSO session of one connection; dropping it ends its subscriptions
+pub struct Session<W> {
+    service: Arc<PushService>,
+    writer: Arc<Mutex<W>>,
+    client: ClientMeta,
+    write_timeout: Duration,
+    /// A DSO request has been answered successfully (RFC 8490 Section 5.1)
+    established: bool,
+    /// By the message ID of their SUBSCRIBE
+    subscriptions: HashMap<u16, Subscription>,
+}
+
+impl<W> Drop for Session<W> {
+    fn drop(&mut self) {
+        for subscription in self.subscriptions.values() {
+            subscription.task.abort();
+        }
+    }
+}
+
+impl<W: AsyncWrite + Unpin + Send + 'static> Session<W> {
+    pub fn new(service: Arc<PushService>, writer: Arc<Mutex<W>>, client: ClientMeta, write_timeout: Duration) -> Self {
+        Self {
+            service,
+            writer,
+            client,
+            write_timeout,
+            established: false,
+            subscriptions: HashMap::new(),
+        }
+    }
+
+    /// How long the connection may go without a message: the inactivity
+    /// timeout, or with subscriptions twice the keepalive interval
+    pub fn idle_timeout(&self) -> Duration {
+        if self.subscriptions.is_empty() {
+            self.service.inactivity_timeout()
+        } else {
+            self.service.keepalive_interval() * 2
+        }
+    }
+
+    /// Answer one DSO message. An error is a protocol violation or a failed
+    /// write, after which the connection must be closed.
+    pub async fn handle(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let message = match dso::parse(bytes) {
+            Ok(message) => message,
+            Err(e) => {
+                let id = u16::from_be_bytes([bytes[0], bytes[1]]);
+                if id != 0 {
+                    let _ = self.respond(id, ResponseCode::FormErr.low(), Vec::new()).await;
+                }
+                return Err(e);
+            }
+        };
+        if message.response {
+            return Err("unexpected DSO response".to_string());
+        }
+        let Some(primary) = message.primary() else {
+            return Err("DSO message without a primary TLV".to_string());
+        };
+
+        match (primary.tlv_type, message.is_unidirectional()) {
+            (dso::KEEPALIVE, false) => {
+                self.respond(message.id, ResponseCode::NoError.low(), vec![(dso::KEEPALIVE, self.service.keepalive())])
+                    .await
+            }
+            (dso::SUBSCRIBE, false) => self.subscribe(message.id, primary.data).await,
+            (dso::UNSUBSCRIBE, true) if self.established => {
+                let id = dso::parse_unsubscribe(primary.data).ok_or("malformed UNSUBSCRIBE")?;
+                let subscription = self.subscriptions.remove(&id).ok_or_else(|| format!("UNSUBSCRIBE of unknown ID {}", id))?;
+                subscription.task.abort();
+                debug!(
+                    "{} unsubscribed from {} {:?}",
+                    self.client.addr,
+                    presentation(&subscription.name),
+                    subscription.record_type
+                );
+                Ok(())
+            }
+            // Nothing is cached on the client's behalf that a reconfirmation could refresh
+            (dso::RECONFIRM, true) if self.established => Ok(()),
+            // TLVs only a server sends, or that cannot be primary
+            (dso::RETRY_DELAY | dso::ENCRYPTION_PADDING | dso::PUSH, _) => {
+                Err(format!("DSO TLV {} sent as primary TLV", primary.tlv_type))
+            }
+            (tlv_type, true) => Err(format!("unexpected unidirectional DSO TLV {}", tlv_type)),
+            (_, false) => self.respond(message.id, dso::DSOTYPENI, Vec::new()).await,
+        }
+    }
+
+    async fn subscribe(&mut self, id: u16, data: &[u8]) -> Result<(), String> {
+        if self.subscriptions.contains_key(&id) {
+            return Err(format!("SUBSCRIBE reuses the ID {} of an active subscription", id));
+        }
+        let question = match dso::parse_subscribe(data) {
+            Ok(question) => question,
+            Err(e) => {
+                debug!("Malformed SUBSCRIBE from {}: {}", self.client.addr, e);
+                return self.respond(id, ResponseCode::FormErr.low(), Vec::new()).await;
+            }
+        };
+        let (name, record_type) = (question.name, question.record_type);
+        let rcode = if !matches!(question.class, DNSClass::IN | DNSClass::ANY) || record_type == RecordType::ANY {
+            Some(ResponseCode::NotImp)
+        } else if self.subscriptions.values().any(|s| s.record_type == record_type && s.name == name) {
+            Some(ResponseCode::FormErr)
+        } else if self.subscriptions.len() >= self.service.config.max_subscriptions {
+            Some(ResponseCode::Refused)
+        } else if self.service.engine.zone_for(&name).is_none() {
+            Some(ResponseCode::NotAuth)
+        } else {
+            None
+        };
+        if let Some(rcode) = rcode {
+            debug!("Refusing SUBSCRIBE for {} {:?} from {}: {}", presentation(&name), record_type, self.client.addr, rcode);
+            return self.respond(id, rcode.low(), Vec::new()).await;
+        }
+
+        let service = self.service.clone();
+        let engine = &service.engine;
+        let answer = engine.resolve(&name, record_type, &self.client).await;
+        if answer.drop || matches!(answer.response_code, ResponseCode::NXDomain | ResponseCode::Refused) {
+            debug!("Refusing SUBSCRIBE for {} {:?}: {}", presentation(&name), record_type, answer.response_code);
+            return self.respond(id, ResponseCode::Refused.low(), Vec::new()).await;
+        }
+        self.respond(id, ResponseCode::NoError.low(), Vec::new()).await?;
+
+        // Only answers from the resolution chain may be followed through mDNS;
+        // policy and administrative answers are re-resolved so they keep applying
+        let followed = !matches!(answer.source, "policy" | "admin" | "not-dns-sd");
+        let watch = match engine.zone_for(&name) {
+            Some(zone) if followed => engine
+                .resolver()
+                .watch(&name, &zone, record_type, &answer.answers)
+                .unwrap_or_else(|e| {
+                    warn!("Not following {} through mDNS: {}", presentation(&name), e);
+                    None
+                }),
+            _ => None,
+        };
+        info!(
+            "{} subscribed to {} {:?} ({})",
+            self.client.addr,
+            presentation(&name),
+            record_type,
+            if watch.is_some() { "browse" } else { "refresh" }
+        );
+        metrics::inc(&metrics::metrics().push_subscriptions);
+
+        let follower = Follower {
+            service: self.service.clone(),
+            writer: self.writer.clone(),
+            client: self.client.clone(),
+            write_timeout: self.write_timeout,
+            name: name.clone(),
+            record_type,
+        };
+        let task = tokio::spawn(follower.run(answer.answers, watch));
+        self.subscriptions.insert(id, Subscription { name, record_type, task });
+        Ok(())
+    }
+
+    /// Send a DSO response; a NOERROR response establishes the session
+    async fn respond(&mut self, id: u16, rcode: u8, tlvs: Vec<(u16, Vec<u8>)>) -> Result<(), String> {
+        let bytes = dso::encode(id, true, rcode, &tlvs);
+        write_message(&self.writer, &bytes, self.write_timeout)
+            .await
+            .map_err(|e| format!("failed to write DSO response: {}", e))?;
+        if rcode == ResponseCode::NoError.low() && !self.established {
+            debug!("DSO session established with {}", self.client.addr);
+            self.established = true;
+        }
+        Ok(())
+    }
+}
+
+/// Task pushing the changes of one subscription
+struct Follower<W> {
+    service: Arc<PushService>,
+    writer: Arc<Mutex<W>>,
+    client: ClientMeta,
+    write_timeout: Duration,
+    name: Name,
+    record_type: RecordType,
+}
+
+impl<W: AsyncWrite + Unpin + Send + 'static> Follower<W> {
+    /// Push `initial`, then every change, until a write fails
+    async fn run(self, initial: Vec<Record>, mut watch: Option<Watch>) {
+        let refresh = Duration::from_secs(self.service.config.refresh_secs.max(1));
+        let mut pushed = Vec::new();
+        let mut next = initial;
+        loop {
+            if !self.service.is_enabled() {
+                debug!("Ending subscription of {} to {}: push is switched off", self.client.addr, presentation(&self.name));
+                return;
+            }
+            let changes = diff(&pushed, &next);
+            if !changes.is_empty() {
+                if let Err(e) = self.push(&changes).await {
+                    debug!("Ending subscription of {} to {}: {}", self.client.addr, presentation(&self.name), e);
+                    return;
+                }
+                pushed = next;
+            }
+            next = match &mut watch {
+                Some(watch) => match watch.changed(self.service.engine.resolver()).await {
+                    Some(records) => records,
+                    None => return,
+                },
+                None => {
+                    tokio::time::sleep(refresh).await;
+                    let answer = self.service.engine.resolve(&self.name, self.record_type, &self.client).await;
+                    // A failed lookup says nothing about what changed
+                    if answer.response_code == ResponseCode::ServFail {
+                        pushed.clone()
+                    } else {
+                        answer.answers
+                    }
+                }
+            };
+        }
+    }
+
+    async fn push(&self, changes: &[Change]) -> Result<(), String> {
+        for chunk in changes.chunks(MAX_PUSH_RECORDS) {
+            let data = dso::push_data(chunk).map_err(|e| format!("failed to encode PUSH: {}", e))?;
+            let bytes = dso::encode(0, false, 0, &[(dso::PUSH, data)]);
+            write_message(&self.writer, &bytes, self.write_timeout)
+                .await
+                .map_err(|e| format!("failed to write PUSH: {}", e))?;
+            metrics::inc(&metrics::metrics().push_messages);
+        }
+        Ok(())
+    }
+}
+
+/// Changes turning `old` into `new`, removals first. TTLs are not compared;
+/// names, in owners and rdata, compare case-insensitively.
+fn diff(old: &[Record], new: &[Record]) -> Vec<Change> {
+    let same = |a: &Record, b: &Record| a.record_type() == b.record_type() && a.name() == b.name() && a.data() == b.data();
+    let removed = old.iter().filter(|o| !new.iter().any(|n| same(o, n))).cloned().map(Change::Remove);
+    let added = new.iter().filter(|n| !old.iter().any(|o| same(o, n))).cloned().map(Change::Add);
+    removed.chain(added).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::mdns_resolver::MdnsResolver;
+    use crate::zones::ZoneRegistry;
+    use hickory_proto::rr::RData;
+    use hickory_proto::rr::rdata::PTR;
+    use hickory_proto::serialize::binary::{BinDecodable, BinDecoder, BinEncodable, BinEncoder};
+    use hickory_proto::xfer::Protocol;
+    use tokio::io::{duplex, AsyncRead, AsyncReadExt, DuplexStream, WriteHalf};
+
+    fn ptr(instance: &str) -> Record {
+        let service = Name::from_utf8("_ipp._tcp.mdns.home.arpa.").unwrap();
+        let target = Name::from_utf8(format!("{}._ipp._tcp.mdns.home.arpa.", instance)).unwrap();
+        Record::from_rdata(service, 10, RData::PTR(PTR(target)))
+    }
+
+    fn subscribe(id: u16, name: &str, record_type: RecordType) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut encoder = BinEncoder::new(&mut data);
+        Name::from_utf8(name).unwrap().emit(&mut encoder).unwrap();
+        encoder.emit_u16(record_type.into()).unwrap();
+        encoder.emit_u16(DNSClass::IN.into()).unwrap();
+        dso::encode(id, false, 0, &[(dso::SUBSCRIBE, data)])
+    }
+
+    async fn read_dso<R: AsyncRead + Unpin>(reader: &mut R) -> Vec<u8> {
+        let read = async {
+            let len = reader.read_u16().await.unwrap();
+            let mut bytes = vec![0; len as usize];
+            reader.read_exact(&mut bytes).await.unwrap();
+            bytes
+        };
+        tokio::time::timeout(Duration::from_secs(5), read).await.expect("no DSO message")
+    }
+
+    /// Records of the PUSH message in `bytes`
+    fn pushed(bytes: &[u8]) -> Vec<Record> {
+        let message = dso::parse(bytes).unwrap();
+        assert!(message.is_unidirectional() && !message.response);
+        let primary = message.primary().unwrap();
+        assert_eq!(primary.tlv_type, dso::PUSH);
+        let mut decoder = BinDecoder::new(primary.data);
+        let mut records = Vec::new();
+        while !decoder.is_empty() {
+            records.push(Record::read(&mut decoder).unwrap());
+        }
+        records
+    }
+
+    fn session() -> (Arc<MdnsResolver>, Session<WriteHalf<DuplexStream>>, DuplexStream) {
+        let config = Config {
+            push: PushConfig {
+                enabled: true,
+                max_subscriptions: 1,
+                refresh_secs: 1,
+                ..PushConfig::default()
+            },
+            ..Config::default()
+        };
+        let push = config.push.clone();
+        let resolver = Arc::new(MdnsResolver::new(Arc::new(config)).unwrap());
+        resolver.set_read_only(true);
+        let zones = Arc::new(ZoneRegistry::new(&["mdns.home.arpa."]).unwrap());
+        let service = Arc::new(PushService::new(QueryEngine::new(resolver.clone(), zones), push));
+        let (client, server) = duplex(65536);
+        let (_, writer) = tokio::io::split(server);
+        let client_meta = ClientMeta::new("192.168.1.40:40000".parse().unwrap(), Protocol::Tcp);
+        let session = Session::new(service, Arc::new(Mutex::new(writer)), client_meta, Duration::from_secs(1));
+        (resolver, session, client)
+    }
+
+    #[tokio::test]
+    async fn test_session_requests() {
+        let (_, mut session, mut client) = session();
+
+        session.handle(&dso::encode(1, false, 0, &[(dso::KEEPALIVE, dso::keepalive(0, 0))])).await.unwrap();
+        let response = read_dso(&mut client).await;
+        let response = dso::parse(&response).unwrap();
+        assert_eq!((response.id, response.response, response.rcode), (1, true, 0));
+        assert_eq!(response.primary().unwrap().data, dso::keepalive(15_000, 3_600_000));
+
+        // Unknown request TLVs are answered, unknown unidirectional ones end the session
+        session.handle(&dso::encode(2, false, 0, &[(0x7777, Vec::new())])).await.unwrap();
+        assert_eq!(dso::parse(&read_dso(&mut client).await).unwrap().rcode, dso::DSOTYPENI);
+        session.handle(&subscribe(3, "example.com.", RecordType::A)).await.unwrap();
+        assert_eq!(dso::parse(&read_dso(&mut client).await).unwrap().rcode, ResponseCode::NotAuth.low());
+        assert!(session.handle(&dso::encode(0, false, 0, &[(0x7777, Vec::new())])).await.is_err());
+        assert!(session.handle(&dso::encode(0, false, 0, &[(dso::KEEPALIVE, dso::keepalive(0, 0))])).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_subscription_pushes_changes() {
+        let (resolver, mut session, mut client) = session();
+        resolver.cache.insert("_ipp._tcp.mdns.home.arpa.", RecordType::PTR, vec![ptr("Office")]);
+
+        session.handle(&subscribe(4, "_ipp._tcp.mdns.home.arpa.", RecordType::PTR)).await.unwrap();
+        let response = dso::parse(&read_dso(&mut client).await).unwrap().rcode;
+        assert_eq!(response, ResponseCode::NoError.low());
+        assert_eq!(pushed(&read_dso(&mut client).await), vec![ptr("Office")]);
+        assert_eq!(session.idle_timeout(), Duration::from_secs(7200));
+
+        // Over the subscription limit
+        session.handle(&subscribe(5, "printer.mdns.home.arpa.", RecordType::A)).await.unwrap();
+        assert_eq!(dso::parse(&read_dso(&mut client).await).unwrap().rcode, ResponseCode::Refused.low());
+
+        // The next refresh sees the new instance and the one that left
+        resolver.cache.insert("_ipp._tcp.mdns.home.arpa.", RecordType::PTR, vec![ptr("Den")]);
+        let changes = pushed(&read_dso(&mut client).await);
+        assert_eq!(changes.len(), 2);
+        assert_eq!((changes[0].ttl(), changes[0].data()), (dso::DELETE_TTL, ptr("Office").data()));
+        assert_eq!(changes[1].data(), ptr("Den").data());
+        assert!(changes[1].ttl() <= 10);
+
+        session.handle(&dso::encode(0, false, 0, &[(dso::UNSUBSCRIBE, 4u16.to_be_bytes().to_vec())])).await.unwrap();
+        assert_eq!(session.idle_timeout(), Duration::from_secs(15));
+        assert!(session.handle(&dso::encode(0, false, 0, &[(dso::UNSUBSCRIBE, 4u16.to_be_bytes().to_vec())])).await.is_err());
+    }
+
+    #[cfg(feature = "tls")]
+    #[tokio::test]
+    async fn test_session_over_tls() {
+        use crate::config::{ServerConfig, TlsConfig};
+        use crate::dns_handler::MdnsDnsHandler;
+        use crate::tcp::{self, Drain, TcpLimits};
+        use rustls::pki_types::pem::PemObject;
+        use rustls::pki_types::{CertificateDer, ServerName};
+        use std::path::PathBuf;
+        use tokio::io::AsyncWriteExt;
+
+        let data = |file: &str| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data").join(file);
+        let config = Config {
+            push: PushConfig {
+                enabled: true,
+                ..PushConfig::default()
+            },
+            ..Config::default()
+        };
+        let push = config.push.clone();
+        let resolver = Arc::new(MdnsResolver::new(Arc::new(config)).unwrap());
+        resolver.set_read_only(true);
+        resolver.cache.insert("_ipp._tcp.mdns.home.arpa.", RecordType::PTR, vec![ptr("Office")]);
+        let zones = Arc::new(ZoneRegistry::new(&["mdns.home.arpa."]).unwrap());
+        let handler = MdnsDnsHandler::with_zones(resolver, zones);
+        let service = Arc::new(PushService::new(handler.engine().clone(), push));
+
+        let tls = crate::tls::server_config(&TlsConfig {
+            cert_file: data("mtls-server-cert.pem"),
+            key_file: data("mtls-server-key.pem"),
+            ..Default::default()
+        })
+        .unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let drain = Drain::new();
+        let limits = TcpLimits::from_config(&ServerConfig::default());
+        let acceptor = Arc::new(crate::tls::Acceptor::new(tls));
+        tokio::spawn(tcp::serve_tls(listener, acceptor, Arc::new(handler), limits, Some(service), drain.clone()));
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_file(data("mtls-ca.pem")).unwrap()).unwrap();
+        let client = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let name = ServerName::try_from("discovery-proxy.mdns.home.arpa").unwrap();
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let mut stream = tokio_rustls::TlsConnector::from(Arc::new(client)).connect(name, stream).await.unwrap();
+
+        let request = subscribe(4, "_ipp._tcp.mdns.home.arpa.", RecordType::PTR);
+        stream.write_all(&(request.len() as u16).to_be_bytes()).await.unwrap();
+        stream.write_all(&request).await.unwrap();
+        let response = dso::parse(&read_dso(&mut stream).await).unwrap().rcode;
+        assert_eq!(response, ResponseCode::NoError.low());
+        assert_eq!(pushed(&read_dso(&mut stream).await), vec![ptr("Office")]);
+        drain.drain(Duration::from_secs(1)).await;
+    }
+
+    #[test]
+    fn test_diff_ignores_ttl_and_case() {
+        let mut office = ptr("OFFICE");
+        office.set_ttl(120);
+        assert!(diff(&[ptr("Office")], &[office]).is_empty());
+        assert_eq!(
+            diff(&[ptr("Office")], &[ptr("Den")]),
+            vec![Change::Remove(ptr("Office")), Change::Add(ptr("Den"))]
+        );
+    }
+}
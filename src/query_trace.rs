@@ -0,0 +1,119 @@
+//! Per-query tracing
+//!
+//! A query carrying EDNS option [`TRACE_OPTION`] is answered inside a
+//! `query_trace` span, and everything logged within that span is written down to
+//! debug level whatever the configured log level. This lets a single lookup be
+//! followed on a production proxy, e.g. with `dig +ednsopt=65002 ...`, without
+//! raising the level for all traffic. Only honored when `[debug] query_tracing`
+//! is set, since any client can add the option.
+
+use hickory_proto::rr::rdata::opt::EdnsCode;
+use hickory_server::server::Request;
+use tracing::{Level, Metadata, Span, Subscriber};
+use tracing_subscriber::filter::DynFilterFn;
+use tracing_subscriber::layer::{Context, Filter};
+use tracing_subscriber::registry::LookupSpan;
+
+/// EDNS option (private use range, RFC 6891 Section 9) asking for a traced answer
+pub const TRACE_OPTION: u16 = 65_002;
+
+const SPAN_NAME: &str = "query_trace";
+
+/// Whether `request` asks to be traced
+pub fn is_traced(request: &Request) -> bool {
+    request
+        .edns()
+        .is_some_and(|edns| edns.option(EdnsCode::Unknown(TRACE_OPTION)).is_some())
+}
+
+/// Span to answer `request` in: a `query_trace` span if it asks for tracing, else a disabled one
+pub fn span(request: &Request, enabled: bool) -> Span {
+    if enabled && is_traced(request) {
+        tracing::info_span!("query_trace", id = request.id(), client = %request.src())
+    } else {
+        Span::none()
+    }
+}
+
+/// Log filter passing events up to `max_level`, and debug events inside a `query_trace` span
+pub fn log_filter<S>(max_level: Level) -> impl Filter<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    DynFilterFn::new(move |metadata: &Metadata<'_>, cx: &Context<'_, S>| {
+        if metadata.level() <= &max_level {
+            return true;
+        }
+        // The span itself must be recorded for events to be found inside it
+        if metadata.is_span() {
+            return metadata.name() == SPAN_NAME;
+        }
+        metadata.level() <= &Level::DEBUG
+            && cx
+                .lookup_current()
+                .is_some_and(|span| span.scope().any(|s| s.name() == SPAN_NAME))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::{Edns, Message, Query};
+    use hickory_proto::rr::rdata::opt::EdnsOption;
+    use hickory_proto::rr::{Name, RecordType};
+    use hickory_proto::serialize::binary::{BinDecodable, BinEncodable};
+    use hickory_proto::xfer::Protocol;
+    use hickory_server::authority::MessageRequest;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tracing_subscriber::Layer;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    fn request(traced: bool) -> Request {
+        let mut message = Message::new();
+        message.add_query(Query::query(Name::from_utf8("printer.mdns.home.arpa.").unwrap(), RecordType::A));
+        let mut edns = Edns::new();
+        if traced {
+            edns.options_mut().insert(EdnsOption::Unknown(TRACE_OPTION, Vec::new()));
+        }
+        message.set_edns(edns);
+        let bytes = message.to_bytes().unwrap();
+        Request::new(
+            MessageRequest::from_bytes(&bytes).unwrap(),
+            "192.168.1.40:5353".parse().unwrap(),
+            Protocol::Udp,
+        )
+    }
+
+    #[test]
+    fn test_option_detected() {
+        assert!(is_traced(&request(true)));
+        assert!(!is_traced(&request(false)));
+    }
+
+    struct CountEvents(Arc<AtomicUsize>);
+
+    impl<S: Subscriber> Layer<S> for CountEvents {
+        fn on_event(&self, _event: &tracing::Event<'_>, _cx: Context<'_, S>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_debug_logged_only_inside_trace_span() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let subscriber =
+            tracing_subscriber::registry().with(CountEvents(count.clone()).with_filter(log_filter(Level::WARN)));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug!("dropped");
+            tracing::warn!("kept");
+            span(&request(true), true).in_scope(|| {
+                tracing::debug!("kept while tracing");
+                tracing::trace!("dropped while tracing");
+            });
+            span(&request(true), false).in_scope(|| tracing::debug!("dropped, tracing disabled"));
+            span(&request(false), true).in_scope(|| tracing::debug!("dropped, not asked for"));
+        });
+        assert_eq!(count.load(Ordering::Relaxed), 2);
+    }
+}
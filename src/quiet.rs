@@ -0,0 +1,182 @@
+//! Scheduled quiet hours
+//!
+//! A background task flips the resolver into quiet mode while the wall clock is
+//! inside one of the configured windows. Quiet mode pauses prefetching and caps
+//! mDNS query timeouts so the proxy generates as little multicast as possible.
+
+use crate::config::QuietHoursConfig;
+use crate::mdns_resolver::MdnsResolver;
+use crate::metrics;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+type QuietResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// How often the schedule is re-evaluated
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// Parsed quiet-hours windows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuietSchedule {
+    /// (start, end) in minutes after local midnight; end < start wraps past midnight
+    windows: Vec<(u32, u32)>,
+    /// Local time minus UTC, in minutes
+    utc_offset_minutes: i32,
+}
+
+impl QuietSchedule {
+    pub fn from_config(config: &QuietHoursConfig) -> QuietResult<Self> {
+        let windows = config
+            .windows
+            .iter()
+            .map(|w| parse_window(w))
+            .collect::<QuietResult<Vec<_>>>()?;
+        Ok(Self {
+            windows,
+            utc_offset_minutes: parse_offset(&config.utc_offset)?,
+        })
+    }
+
+    /// Whether any window is configured
+    pub fn is_empty(&self) -> bool {
+        self.windows.is_empty()
+    }
+
+    /// Whether `unix_secs` falls inside a quiet window
+    pub fn is_quiet_at(&self, unix_secs: u64) -> bool {
+        let utc_minute = ((unix_secs / 60) % MINUTES_PER_DAY as u64) as i32;
+        let minute = (utc_minute + self.utc_offset_minutes).rem_euclid(MINUTES_PER_DAY as i32) as u32;
+        self.windows.iter().any(|&(start, end)| {
+            if start <= end {
+                minute >= start && minute < end
+            } else {
+                minute >= start || minute < end
+            }
+        })
+    }
+}
+
+/// Keep the resolver's quiet flag in line with the schedule
+pub async fn run(schedule: QuietSchedule, resolver: Arc<MdnsResolver>) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let quiet = schedule.is_quiet_at(now);
+        if quiet != resolver.is_quiet() {
+            info!("Quiet hours {}", if quiet { "started" } else { "ended" });
+            resolver.set_quiet(quiet);
+            metrics::inc(&metrics::metrics().quiet_transitions);
+        }
+        metrics::set(&metrics::metrics().quiet_active, quiet as u64);
+    }
+}
+
+/// Parse "HH:MM-HH:MM"
+fn parse_window(window: &str) -> QuietResult<(u32, u32)> {
+    let (start, end) = window
+        .split_once('-')
+        .ok_or_else(|| format!("quiet hours window '{}' must look like HH:MM-HH:MM", window))?;
+    let (start, end) = (parse_time(start.trim())?, parse_time(end.trim())?);
+    if start == end {
+        return Err(format!("quiet hours window '{}' is empty", window).into());
+    }
+    Ok((start, end))
+}
+
+/// Parse "HH:MM" into minutes after midnight
+fn parse_time(time: &str) -> QuietResult<u32> {
+    let invalid = || format!("invalid time '{}', expected HH:MM", time);
+    let (h, m) = time.split_once(':').ok_or_else(invalid)?;
+    let (h, m): (u32, u32) = (h.parse().map_err(|_| invalid())?, m.parse().map_err(|_| invalid())?);
+    if h > 23 || m > 59 {
+        return Err(invalid().into());
+    }
+    Ok(h * 60 + m)
+}
+
+/// Parse "+HH:MM" / "-HH:MM" into minutes
+fn parse_offset(offset: &str) -> QuietResult<i32> {
+    let invalid = || format!("invalid UTC offset '{}', expected +HH:MM or -HH:MM", offset);
+    let (sign, rest) = match offset.as_bytes().first() {
+        Some(b'+') => (1, &offset[1..]),
+        Some(b'-') => (-1, &offset[1..]),
+        _ => return Err(invalid().into()),
+    };
+    let (h, m) = rest.split_once(':').ok_or_else(invalid)?;
+    let (h, m): (i32, i32) = (h.parse().map_err(|_| invalid())?, m.parse().map_err(|_| invalid())?);
+    if h > 14 || m > 59 {
+        return Err(invalid().into());
+    }
+    Ok(sign * (h * 60 + m))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(windows: &[&str], offset: &str) -> QuietSchedule {
+        QuietSchedule::from_config(&QuietHoursConfig {
+            windows: windows.iter().map(|w| w.to_string()).collect(),
+            utc_offset: offset.to_string(),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    /// Seconds since the epoch for `hh:mm` UTC on 1970-01-02
+    fn at(hh: u64, mm: u64) -> u64 {
+        86_400 + hh * 3600 + mm * 60
+    }
+
+    #[test]
+    fn test_window_within_day() {
+        let s = schedule(&["12:00-13:30"], "+00:00");
+        assert!(!s.is_quiet_at(at(11, 59)));
+        assert!(s.is_quiet_at(at(12, 0)));
+        assert!(s.is_quiet_at(at(13, 29)));
+        assert!(!s.is_quiet_at(at(13, 30)));
+    }
+
+    #[test]
+    fn test_window_wrapping_midnight() {
+        let s = schedule(&["23:00-06:00"], "+00:00");
+        assert!(s.is_quiet_at(at(23, 30)));
+        assert!(s.is_quiet_at(at(2, 0)));
+        assert!(!s.is_quiet_at(at(6, 0)));
+        assert!(!s.is_quiet_at(at(12, 0)));
+    }
+
+    #[test]
+    fn test_utc_offset_applies() {
+        // 23:00 local at UTC+02:00 is 21:00 UTC
+        let s = schedule(&["23:00-06:00"], "+02:00");
+        assert!(s.is_quiet_at(at(21, 0)));
+        assert!(!s.is_quiet_at(at(4, 30)));
+
+        let s = schedule(&["23:00-06:00"], "-05:00");
+        assert!(s.is_quiet_at(at(4, 0)));
+        assert!(!s.is_quiet_at(at(23, 30)));
+    }
+
+    #[test]
+    fn test_invalid_config_rejected() {
+        for (windows, offset) in [
+            (vec!["25:00-06:00"], "+00:00"),
+            (vec!["23:00"], "+00:00"),
+            (vec!["10:00-10:00"], "+00:00"),
+            (vec!["23:00-06:00"], "02:00"),
+            (vec!["23:00-06:00"], "+2"),
+        ] {
+            let config = QuietHoursConfig {
+                windows: windows.iter().map(|w| w.to_string()).collect(),
+                utc_offset: offset.to_string(),
+                ..Default::default()
+            };
+            assert!(QuietSchedule::from_config(&config).is_err(), "{:?} {}", windows, offset);
+        }
+    }
+}
@@ -0,0 +1,16 @@
+//! Unpredictable numbers for query IDs, throwaway names, TTL jitter and the
+//! audit log's per-run salt
+//!
+//! The standard library's randomly keyed hasher over the current time is
+//! enough for these without pulling in a random number crate. It is not a
+//! cryptographic generator and must not make keys.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub(crate) fn random_u64() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos());
+    hasher.finish()
+}
@@ -0,0 +1,262 @@
+//! Configuration reload on SIGHUP
+//!
+//! On SIGHUP the configuration file is read again, with the command-line and
+//! environment overrides applied as at startup, and put into effect. Everything
+//! the resolver and handler read per query
+//! follows the new file: cache TTL, mDNS and resolution-step timeouts, the
+//! resolution chain, strategies, TTL jitter and floor, answers for off-link and
+//! non-DNS-SD names. The log level is changed, and a changed discovery domain
+//! is served in place of the old one, whose cache entries are dropped. The
+//! main listeners are rebound when their addresses, ports, DNS-over-TLS or
+//! TCP settings changed, with connections to the old ones finished in the
+//! background, and otherwise read the DNS-over-TLS certificate again (see
+//! [`serving`](crate::serving)). Settings read once at startup (the daemon,
+//! push, peers, `[[links]]`, the Unix socket, ...) keep their old values; a reload that changes them says so and
+//! they take effect at the next restart. A file that fails to load leaves the
+//! running configuration as it was.
+
+use crate::config::{Args, Config};
+use crate::mdns_resolver::MdnsResolver;
+use crate::serving::Listeners;
+use crate::service_types::{self, ServiceTypes};
+use crate::zones::ZoneRegistry;
+use std::sync::Arc;
+use tracing::{info, warn, Level};
+
+type ReloadResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Changes the level of the installed log subscriber
+pub type LogLevelSetter = Box<dyn Fn(Level) -> Result<(), String> + Send + Sync>;
+
+/// Settings put into effect by a reload, as `section` or `section.key`
+const RELOADABLE: &[&str] = &[
+    "logging.level",
+    "cache.ttl_seconds",
+    "server.discovery_domain",
+    "server.bind_address",
+    "server.port",
+    "server.fallback_ports",
+    "server.bind_device",
+    "server.udp_sockets",
+    "server.udp_recv_buffer_bytes",
+    "server.tls",
+    "server.tcp_timeout",
+    "server.tcp_max_message_bytes",
+    "server.tcp_max_questions",
+    "server.tcp_read_timeout_ms",
+    "server.drain_timeout_secs",
+    "server.ttl_jitter_secs",
+    "server.ttl_floor_secs",
+    "server.nsec_records",
+    "server.peer_proxies",
+    "server.off_link_local_queries",
+    "server.edns_udp_payload",
+    "mdns.service_query_timeout_ms",
+    "mdns.service_poll_interval_ms",
+    "mdns.hostname_resolution_timeout_ms",
+    "mdns.co_resolve",
+    "mdns.answer_bundles",
+    "mdns.address_family_prefetch",
+    "mdns.https_records",
+    "mdns.reverse_lookups",
+    "mdns.service_types_file",
+    "mdns.non_dns_sd_names",
+    "mdns.oversized_txt",
+    "mdns.max_txt_bytes",
+    "strategies",
+    "zones",
+    "service_groups",
+    "resolution",
+    "quiet_hours.query_timeout_ms",
+    "liveness.filter_dead",
+    "debug.lint",
+    "debug.provenance_record",
+];
+
+/// Everything a reload updates
+pub struct Reloader {
+    args: Args,
+    resolver: Arc<MdnsResolver>,
+    zones: Arc<ZoneRegistry>,
+    set_log_level: LogLevelSetter,
+    listeners: Option<Arc<Listeners>>,
+}
+
+impl Reloader {
+    /// `args` are those the proxy was started with: the file they name is
+    /// read again and their overrides applied on top
+    pub fn new(args: Args, resolver: Arc<MdnsResolver>, zones: Arc<ZoneRegistry>, set_log_level: LogLevelSetter) -> Self {
+        Self {
+            args,
+            resolver,
+            zones,
+            set_log_level,
+            listeners: None,
+        }
+    }
+
+    /// Rebind `listeners` when a reload changes their settings
+    pub fn with_listeners(mut self, listeners: Arc<Listeners>) -> Self {
+        self.listeners = Some(listeners);
+        self
+    }
+
+    /// Load the configuration again and put it into effect; returns the
+    /// changed settings that need a restart
+    pub fn reload(&self) -> ReloadResult<Vec<String>> {
+        let config = Config::load(self.args.clone())?;
+        self.apply(config)
+    }
+
+    /// Put `config` into effect; returns the changed settings that need a restart
+    pub fn apply(&self, config: Config) -> ReloadResult<Vec<String>> {
+        let old = self.resolver.config();
+        let restart = restart_needed(&old, &config)?;
+        // Read before anything changes, so an unreadable file leaves the old descriptions in effect
+        let service_types = ServiceTypes::from_config(config.mdns.service_types_file.as_deref())?;
+
+        if old.discovery_domain() != config.discovery_domain() {
+            // Validated before anything changes, so a bad domain leaves the old one served
+            self.zones.add(config.discovery_domain())?;
+            if let Some(apex) = self.zones.remove(old.discovery_domain())? {
+                let purged = self.resolver.purge_zone(&apex);
+                info!("Stopped serving {} ({} cache entries dropped)", apex, purged);
+            }
+            info!("Serving discovery domain {}", config.discovery_domain());
+        }
+        if old.logging.level != config.logging.level {
+            (self.set_log_level)(config.parse_log_level())?;
+            info!("Log level is now {}", config.parse_log_level());
+        }
+        service_types::install(service_types);
+        self.resolver.reload(Arc::new(config));
+        Ok(restart)
+    }
+}
+
+/// Settings that differ between `old` and `new` but are only read at startup
+pub fn restart_needed(old: &Config, new: &Config) -> ReloadResult<Vec<String>> {
+    let old = toml::Table::try_from(old)?;
+    let new = toml::Table::try_from(new)?;
+    let mut changed = Vec::new();
+    let sections: std::collections::BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    for section in sections {
+        let (before, after) = (old.get(section), new.get(section));
+        if before == after || RELOADABLE.contains(&section.as_str()) {
+            continue;
+        }
+        match (before.and_then(|v| v.as_table()), after.and_then(|v| v.as_table())) {
+            (Some(before), Some(after)) => {
+                let keys: std::collections::BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+                for key in keys {
+                    let setting = format!("{}.{}", section, key);
+                    if before.get(key) != after.get(key) && !RELOADABLE.contains(&setting.as_str()) {
+                        changed.push(setting);
+                    }
+                }
+            }
+            _ => changed.push(section.clone()),
+        }
+    }
+    Ok(changed)
+}
+
+/// Reload on every SIGHUP for as long as the proxy runs
+#[cfg(unix)]
+pub async fn run(reloader: Reloader) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            warn!("Cannot listen for SIGHUP, configuration reload is unavailable: {}", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        let Some(path) = &reloader.args.config else {
+            warn!("SIGHUP received, but no configuration file was given; nothing to reload");
+            continue;
+        };
+        match reloader.reload() {
+            Ok(restart) if restart.is_empty() => info!("Reloaded configuration from {}", path.display()),
+            Ok(restart) => warn!(
+                "Reloaded configuration from {}; restart to apply {}",
+                path.display(),
+                restart.join(", ")
+            ),
+            Err(e) => {
+                warn!("Failed to reload configuration, keeping the previous one: {}", e);
+                continue;
+            }
+        }
+        if let Some(listeners) = &reloader.listeners {
+            listeners.reload(&reloader.resolver.config().server).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use hickory_proto::rr::Name;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_restart_needed_lists_startup_settings() {
+        let old = Config::default();
+        let mut new = Config::default();
+        new.cache.ttl_seconds = 30;
+        new.mdns.service_query_timeout_ms = 500;
+        new.server.discovery_domain = "lan.home.arpa.".to_string();
+        assert!(restart_needed(&old, &new).unwrap().is_empty());
+
+        new.server.port = 53;
+        new.server.unix_socket_path = Some("/run/mdns-dns-proxy.sock".into());
+        new.push.enabled = true;
+        assert_eq!(restart_needed(&old, &new).unwrap(), vec!["push.enabled", "server.unix_socket_path"]);
+    }
+
+    #[tokio::test]
+    async fn test_reload_updates_running_configuration() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "[mdns]\nlazy_start = true\n").unwrap();
+        let args = Args::parse_from(["mdns-dns-proxy", "--config", path.to_str().unwrap()]);
+
+        let config = Config::load(args.clone()).unwrap();
+        let zones = Arc::new(ZoneRegistry::new(&[config.discovery_domain()]).unwrap());
+        let resolver = Arc::new(MdnsResolver::new(Arc::new(config)).unwrap());
+        let levels = Arc::new(Mutex::new(Vec::new()));
+        let seen = levels.clone();
+        let reloader = Reloader::new(
+            args,
+            resolver.clone(),
+            zones.clone(),
+            Box::new(move |level| {
+                seen.lock().unwrap().push(level);
+                Ok(())
+            }),
+        );
+
+        std::fs::write(
+            &path,
+            "[server]\ndiscovery_domain = \"lan.home.arpa\"\nport = 53\nudp_sockets = 2\n\
+             [push]\nenabled = true\n\
+             [cache]\nttl_seconds = \"30s\"\n\
+             [logging]\nlevel = \"debug\"\n\
+             [mdns]\nlazy_start = true\nservice_query_timeout_ms = 500\n",
+        )
+        .unwrap();
+        assert_eq!(reloader.reload().unwrap(), vec!["push.enabled"]);
+        assert_eq!(resolver.config().cache.ttl_seconds, 30);
+        assert_eq!(resolver.config().mdns.service_query_timeout_ms, 500);
+        assert_eq!(zones.list(), vec![Name::from_utf8("lan.home.arpa.").unwrap()]);
+        assert_eq!(*levels.lock().unwrap(), vec![Level::DEBUG]);
+
+        // A broken file changes nothing
+        std::fs::write(&path, "[cache]\nttl_seconds = \"30\"\n").unwrap();
+        assert!(reloader.reload().is_err());
+        assert_eq!(resolver.config().cache.ttl_seconds, 30);
+    }
+}
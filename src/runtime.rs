@@ -0,0 +1,55 @@
+//! Tokio runtime construction
+//!
+//! The runtime is built explicitly rather than via `#[tokio::main]` so its
+//! size can follow `[server]` settings; tokio's defaults (one worker per core,
+//! up to 512 blocking threads) are far more than a small router needs. Builds
+//! without the `multi-thread` feature run everything on the main thread.
+
+use crate::config::ServerConfig;
+use tokio::runtime::{Builder, Runtime};
+
+/// Build the runtime the server runs on: multi-threaded, or on the calling
+/// thread without the `multi-thread` feature (`worker_threads` is then ignored)
+pub fn build_runtime(server: &ServerConfig) -> std::io::Result<Runtime> {
+    #[cfg(feature = "multi-thread")]
+    let mut builder = Builder::new_multi_thread();
+    #[cfg(not(feature = "multi-thread"))]
+    let mut builder = Builder::new_current_thread();
+    builder.enable_all().thread_name("mdns-dns-proxy");
+
+    if let Some(workers) = server.worker_threads {
+        builder.worker_threads(workers.get());
+    }
+    if let Some(blocking) = server.max_blocking_threads {
+        builder.max_blocking_threads(blocking.get());
+    }
+
+    builder.build()
+}
+
+#[cfg(all(test, feature = "multi-thread"))]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use std::num::NonZeroUsize;
+
+    #[test]
+    fn test_build_runtime_with_explicit_sizes() {
+        let server = ServerConfig {
+            worker_threads: NonZeroUsize::new(2),
+            max_blocking_threads: NonZeroUsize::new(4),
+            ..Default::default()
+        };
+        let runtime = build_runtime(&server).unwrap();
+        assert_eq!(runtime.metrics().num_workers(), 2);
+        assert_eq!(runtime.block_on(async { 1 + 1 }), 2);
+    }
+
+    #[test]
+    fn test_zero_thread_counts_are_rejected() {
+        for key in ["worker_threads", "max_blocking_threads"] {
+            let err = Config::parse(&format!("[server]\n{} = 0", key)).unwrap_err().to_string();
+            assert!(err.contains(key) && err.contains("nonzero"), "{}", err);
+        }
+    }
+}
@@ -0,0 +1,220 @@
+//! Service type descriptions
+//!
+//! Logs, the control socket inventory and `doctor` name a service type by its
+//! description as well as its label, e.g. "_ipp._tcp — Internet Printing
+//! Protocol". Common DNS-SD types are built in; `mdns.service_types_file`
+//! names a copy of the IANA Service Name and Transport Protocol Port Number
+//! Registry in CSV form (service-names-port-numbers.csv) whose descriptions
+//! are added to them. The file is read at startup and again on every reload,
+//! so a fresh download takes effect on SIGHUP.
+//!
+//! Like [`crate::metrics`], the registry in effect is process-wide, so any
+//! module can describe a name without a handle being passed to it.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, LazyLock, RwLock};
+
+/// Types seen on most home and office networks, with the descriptions of the DNS-SD registry
+const BUILTIN: &[(&str, &str)] = &[
+    ("_adisk._tcp", "Time Machine disk"),
+    ("_afpovertcp._tcp", "Apple File Sharing"),
+    ("_airplay._tcp", "AirPlay"),
+    ("_airport._tcp", "AirPort Base Station"),
+    ("_companion-link._tcp", "Apple companion link"),
+    ("_device-info._tcp", "Device information"),
+    ("_dns-sd._udp", "DNS Service Discovery"),
+    ("_dns-update._udp", "DNS Dynamic Update"),
+    ("_ftp._tcp", "File Transfer"),
+    ("_googlecast._tcp", "Google Cast"),
+    ("_hap._tcp", "HomeKit Accessory Protocol"),
+    ("_hap._udp", "HomeKit Accessory Protocol over UDP"),
+    ("_homekit._tcp", "HomeKit"),
+    ("_http._tcp", "World Wide Web HTML-over-HTTP"),
+    ("_https._tcp", "HTTP over SSL/TLS"),
+    ("_ipp._tcp", "Internet Printing Protocol"),
+    ("_ipps._tcp", "Internet Printing Protocol over TLS"),
+    ("_matter._tcp", "Matter operational node"),
+    ("_matterc._udp", "Matter commissionable node"),
+    ("_meshcop._udp", "Thread mesh commissioning"),
+    ("_mqtt._tcp", "MQTT message broker"),
+    ("_nfs._tcp", "Network File System"),
+    ("_pdl-datastream._tcp", "Printer PDL Data Stream"),
+    ("_printer._tcp", "Line Printer Daemon (LPD/LPR)"),
+    ("_raop._tcp", "Remote Audio Output Protocol (AirTunes)"),
+    ("_rfb._tcp", "Remote Frame Buffer (VNC)"),
+    ("_scanner._tcp", "Bonjour Scanning"),
+    ("_sftp-ssh._tcp", "Secure File Transfer Protocol over SSH"),
+    ("_sleep-proxy._udp", "Sleep Proxy Server"),
+    ("_smb._tcp", "Server Message Block (Windows file sharing)"),
+    ("_spotify-connect._tcp", "Spotify Connect"),
+    ("_ssh._tcp", "SSH Remote Login Protocol"),
+    ("_uscan._tcp", "Universal Scan"),
+    ("_uscans._tcp", "Universal Scan over TLS"),
+    ("_workstation._tcp", "Workgroup Manager"),
+];
+
+/// Description of each service type, keyed by its lowercase `_service._proto` label pair
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceTypes {
+    descriptions: HashMap<String, String>,
+}
+
+impl Default for ServiceTypes {
+    fn default() -> Self {
+        let descriptions = BUILTIN.iter().map(|(ty, description)| (ty.to_string(), description.to_string())).collect();
+        Self { descriptions }
+    }
+}
+
+impl ServiceTypes {
+    /// Built-in types plus those of the IANA registry CSV at `path`, which win where both describe a type
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut types = Self::default();
+        let added = parse_iana_csv(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        types.descriptions.extend(added);
+        Ok(types)
+    }
+
+    /// Types from `mdns.service_types_file`, or the built-in ones when it is unset
+    pub fn from_config(path: Option<&Path>) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        match path {
+            Some(path) => Self::load(path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Number of types described
+    pub fn len(&self) -> usize {
+        self.descriptions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.descriptions.is_empty()
+    }
+
+    /// Description of the service type in `name`: a type (`_ipp._tcp.local.`),
+    /// a subtype or an instance name in presentation format
+    pub fn describe(&self, name: &str) -> Option<&str> {
+        self.descriptions.get(&service_type(name)?).map(String::as_str)
+    }
+
+    /// `_ipp._tcp — Internet Printing Protocol` for the service type in `name`
+    pub fn label(&self, name: &str) -> Option<String> {
+        let service_type = service_type(name)?;
+        let description = self.descriptions.get(&service_type)?;
+        Some(format!("{} — {}", service_type, description))
+    }
+}
+
+/// The lowercase `_service._proto` label pair of `name`
+fn service_type(name: &str) -> Option<String> {
+    let labels: Vec<&str> = name.split('.').collect();
+    let proto = labels
+        .iter()
+        .rposition(|l| l.eq_ignore_ascii_case("_tcp") || l.eq_ignore_ascii_case("_udp"))?;
+    let service = labels[..proto].last().filter(|l| l.starts_with('_'))?;
+    Some(format!("{}.{}", service, labels[proto]).to_ascii_lowercase())
+}
+
+/// Descriptions from the IANA registry CSV (columns Service Name, Port Number,
+/// Transport Protocol, Description, ...); rows without a name, transport or
+/// description are skipped, and the first row for a type wins
+fn parse_iana_csv(text: &str) -> Result<HashMap<String, String>, String> {
+    let mut rows = csv_rows(text)?.into_iter();
+    let header = rows.next().ok_or("empty file")?;
+    if header.first().map(|h| h.trim()) != Some("Service Name") || header.len() < 4 {
+        return Err("not the IANA service name registry (expected a \"Service Name\" header)".to_string());
+    }
+    let mut descriptions = HashMap::new();
+    for row in rows {
+        let [name, _port, proto, description, ..] = &row[..] else {
+            continue;
+        };
+        let (name, proto, description) = (name.trim(), proto.trim(), description.trim());
+        if name.is_empty() || !matches!(proto, "tcp" | "udp") || description.is_empty() {
+            continue;
+        }
+        descriptions
+            .entry(format!("_{}._{}", name, proto).to_ascii_lowercase())
+            .or_insert_with(|| description.split_whitespace().collect::<Vec<_>>().join(" "));
+    }
+    Ok(descriptions)
+}
+
+/// Split CSV into rows of fields; quoted fields may hold commas, newlines and doubled quotes
+fn csv_rows(text: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', true) => quoted = false,
+            ('"', false) if field.is_empty() => quoted = true,
+            (',', false) => row.push(std::mem::take(&mut field)),
+            ('\r', false) => {}
+            ('\n', false) => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("unterminated quoted field".to_string());
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+static REGISTRY: LazyLock<RwLock<Arc<ServiceTypes>>> = LazyLock::new(RwLock::default);
+
+/// The service types in effect
+pub fn registry() -> Arc<ServiceTypes> {
+    REGISTRY.read().unwrap().clone()
+}
+
+/// Put `types` into effect for every later description
+pub fn install(types: ServiceTypes) {
+    *REGISTRY.write().unwrap() = Arc::new(types);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_from_builtin_and_iana_csv() {
+        let types = ServiceTypes::default();
+        assert_eq!(types.describe("_ipp._tcp.local."), Some("Internet Printing Protocol"));
+        assert_eq!(types.describe("Office Printer._IPP._tcp.mdns.home.arpa."), Some("Internet Printing Protocol"));
+        assert_eq!(types.describe("_universal._sub._ipp._tcp.local."), Some("Internet Printing Protocol"));
+        assert_eq!(types.label("_ssh._tcp.local.").as_deref(), Some("_ssh._tcp — SSH Remote Login Protocol"));
+        assert_eq!(types.describe("printer.mdns.home.arpa."), None);
+        assert_eq!(types.describe("_nope._tcp.local."), None);
+
+        let csv = "Service Name,Port Number,Transport Protocol,Description,Assignee,Contact,Registration Date,\
+                   Modification Date,Reference,Service Code,Unauthorized Use Reported,Assignment Notes\r\n\
+                   ipp,631,tcp,IPP (Internet Printing Protocol),,,,,[RFC8011],,,\r\n\
+                   ipp,631,udp,IPP (Internet Printing Protocol),,,,,[RFC8011],,,\r\n\
+                   ,1000,tcp,Unassigned,,,,,,,,\r\n\
+                   elcsd,704,tcp,\"errlog copy/server \"\"daemon\"\",\n with a note\",,,,,,,,\r\n\
+                   x-plane,,,,,,,,,,,\r\n";
+        let parsed = parse_iana_csv(csv).unwrap();
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(parsed["_ipp._tcp"], "IPP (Internet Printing Protocol)");
+        assert_eq!(parsed["_elcsd._tcp"], "errlog copy/server \"daemon\", with a note");
+        assert!(parse_iana_csv("name,description\nipp,Printing\n").is_err());
+        assert!(parse_iana_csv("Service Name,Port Number,Transport Protocol,Description\n\"ipp").is_err());
+    }
+}
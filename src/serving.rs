@@ -0,0 +1,553 @@
+//! The main DNS listeners, rebound on reload
+//!
+//! UDP, TCP and DNS-over-TLS on `server.bind_address` are served as one
+//! generation: the sockets bound with one set of listener settings, the tasks
+//! serving them, and a [`Drain`] of their own. When a reload changes those
+//! settings, the generation stops accepting, a new one is bound, and the old
+//! connections are finished in the background for up to
+//! `server.drain_timeout_secs` while new ones are served by the new
+//! listeners. If the new settings cannot be bound, the old ones are bound
+//! again. A reload that leaves the settings as they were reads the
+//! DNS-over-TLS certificate and key again, so a renewed certificate is used
+//! from the next handshake on.
+
+use crate::config::{ServerConfig, TlsConfig};
+use crate::dns_handler::MdnsDnsHandler;
+use crate::listener::bind_dns_sockets;
+use crate::push::PushService;
+use crate::tcp::{self, Drain, TcpLimits};
+use hickory_server::ServerFuture;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+type ServeResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Why the listeners stopped: `Ok` if the UDP server shut down cleanly
+type Stopped = Result<(), String>;
+
+/// TLS settings a generation handshakes with
+#[cfg(feature = "tls")]
+type TlsSettings = Arc<rustls::ServerConfig>;
+#[cfg(not(feature = "tls"))]
+type TlsSettings = std::convert::Infallible;
+
+/// Settings the listeners are bound with; a change to any of them rebinds
+#[derive(Debug, PartialEq)]
+struct Binding {
+    bind_address: Vec<IpAddr>,
+    port: u16,
+    fallback_ports: Vec<u16>,
+    bind_device: Option<String>,
+    udp_sockets: usize,
+    udp_recv_buffer_bytes: Option<usize>,
+    tls: Option<TlsConfig>,
+    limits: TcpLimits,
+}
+
+impl Binding {
+    fn of(server: &ServerConfig) -> Self {
+        Self {
+            bind_address: server.bind_address.clone(),
+            port: server.port,
+            fallback_ports: server.fallback_ports.clone(),
+            bind_device: server.bind_device.clone(),
+            udp_sockets: server.udp_sockets,
+            udp_recv_buffer_bytes: server.udp_recv_buffer_bytes,
+            tls: server.tls.clone(),
+            limits: TcpLimits::from_config(server),
+        }
+    }
+}
+
+/// Read the certificate and key of `server.tls`, if configured
+#[cfg(feature = "tls")]
+fn tls_settings(server: &ServerConfig) -> ServeResult<Option<TlsSettings>> {
+    server.tls.as_ref().map(crate::tls::server_config).transpose()
+}
+
+#[cfg(not(feature = "tls"))]
+fn tls_settings(server: &ServerConfig) -> ServeResult<Option<TlsSettings>> {
+    match server.tls {
+        Some(_) => Err("[server.tls] is configured, but this build lacks the tls feature".into()),
+        None => Ok(None),
+    }
+}
+
+/// Listeners bound with one set of settings
+struct Generation {
+    server: ServerConfig,
+    addrs: Vec<SocketAddr>,
+    drain: Drain,
+    /// Asks the task running the `ServerFuture` to shut it down
+    stop_udp: Option<oneshot::Sender<()>>,
+    /// Batched UDP listeners, which stop when aborted
+    batch_udp: Vec<JoinHandle<()>>,
+    /// Every task serving a socket or accepting connections
+    tasks: Vec<JoinHandle<()>>,
+    #[cfg(feature = "tls")]
+    acceptor: Option<Arc<crate::tls::Acceptor>>,
+    /// Addresses the DNS-over-TLS listeners are bound to
+    #[cfg(feature = "tls")]
+    tls_addrs: Vec<SocketAddr>,
+}
+
+impl Generation {
+    async fn start(
+        server: &ServerConfig,
+        tls: Option<TlsSettings>,
+        handler: &Arc<MdnsDnsHandler>,
+        push: &Option<Arc<PushService>>,
+        stopped: &mpsc::UnboundedSender<Stopped>,
+    ) -> ServeResult<Self> {
+        let sockets = bind_dns_sockets(server).await?;
+        // Bound before anything is served, so a failure leaves nothing running
+        #[cfg(feature = "tls")]
+        let tls_listeners = match &server.tls {
+            Some(config) => crate::listener::bind_tcp_listeners(server, config.port).await?,
+            None => Vec::new(),
+        };
+        #[cfg(feature = "tls")]
+        let tls_addrs = tls_listeners.iter().map(|listener| listener.local_addr()).collect::<Result<Vec<_>, _>>()?;
+        let addrs: Vec<String> = sockets.addrs.iter().map(SocketAddr::to_string).collect();
+        info!("UDP socket(s) and TCP listener bound to {}", addrs.join(", "));
+
+        let drain = Drain::new();
+        let limits = TcpLimits::from_config(server);
+        let mut tasks = Vec::new();
+
+        // Each UDP socket is served by its own task
+        let udp_count = sockets.udp.len();
+        #[cfg(all(feature = "batch-udp", target_os = "linux"))]
+        let (udp_server, batch_udp) = {
+            let batch_udp = sockets.udp.into_iter().map(|udp| {
+                let (handler, stopped) = (handler.clone(), stopped.clone());
+                tokio::spawn(async move {
+                    if let Err(e) = crate::batch_udp::serve(udp, handler).await {
+                        let _ = stopped.send(Err(format!("Batched UDP listener failed: {}", e)));
+                    }
+                })
+            });
+            (None::<ServerFuture<MdnsDnsHandler>>, batch_udp.collect())
+        };
+        #[cfg(not(all(feature = "batch-udp", target_os = "linux")))]
+        let (udp_server, batch_udp) = {
+            let mut udp_server = ServerFuture::new((**handler).clone());
+            for udp in sockets.udp {
+                udp_server.register_socket(udp);
+            }
+            (Some(udp_server), Vec::new())
+        };
+        info!("Registered {} UDP socket(s)", udp_count);
+        let stop_udp = udp_server.map(|mut udp_server| {
+            let (stop, stop_requested) = oneshot::channel();
+            let stopped = stopped.clone();
+            tasks.push(tokio::spawn(async move {
+                tokio::select! {
+                    result = udp_server.block_until_done() => {
+                        let _ = stopped.send(result.map_err(|e| format!("DNS server error: {}", e)));
+                    }
+                    _ = stop_requested => {
+                        if let Err(e) = udp_server.shutdown_gracefully().await {
+                            error!("DNS server error during shutdown: {}", e);
+                        }
+                    }
+                }
+            }));
+            stop
+        });
+
+        // TCP is framed by our own listener so message size, question count and read deadlines are enforced
+        let tcp_count = sockets.tcp.len();
+        for listener in sockets.tcp {
+            let (handler, push, drain, stopped) = (handler.clone(), push.clone(), drain.clone(), stopped.clone());
+            tasks.push(tokio::spawn(async move {
+                if let Err(e) = tcp::serve(listener, handler, limits, push, drain).await {
+                    let _ = stopped.send(Err(format!("TCP listener failed: {}", e)));
+                }
+            }));
+        }
+        info!("Registered {} TCP listener(s)", tcp_count);
+
+        // DNS-over-TLS on its own port, with the same framing and limits
+        #[cfg(feature = "tls")]
+        let acceptor = match (&server.tls, tls) {
+            (Some(config), Some(tls)) => {
+                let acceptor = Arc::new(crate::tls::Acceptor::new(tls));
+                for listener in tls_listeners {
+                    let (acceptor, handler, push) = (acceptor.clone(), handler.clone(), push.clone());
+                    let (drain, stopped) = (drain.clone(), stopped.clone());
+                    tasks.push(tokio::spawn(async move {
+                        if let Err(e) = tcp::serve_tls(listener, acceptor, handler, limits, push, drain).await {
+                            let _ = stopped.send(Err(format!("DNS-over-TLS listener failed: {}", e)));
+                        }
+                    }));
+                }
+                if config.client_ca_file.is_some() {
+                    info!("Registered DNS-over-TLS listener(s) on port {}, requiring client certificates", config.port);
+                } else {
+                    info!("Registered DNS-over-TLS listener(s) on port {}", config.port);
+                }
+                Some(acceptor)
+            }
+            _ => None,
+        };
+        #[cfg(not(feature = "tls"))]
+        if let Some(tls) = tls {
+            match tls {}
+        }
+
+        Ok(Self {
+            server: server.clone(),
+            addrs: sockets.addrs,
+            drain,
+            stop_udp,
+            batch_udp,
+            tasks,
+            #[cfg(feature = "tls")]
+            acceptor,
+            #[cfg(feature = "tls")]
+            tls_addrs,
+        })
+    }
+
+    /// Every address bound, DNS-over-TLS included
+    fn describe(&self) -> String {
+        #[cfg(feature = "tls")]
+        let addrs = self.addrs.iter().chain(&self.tls_addrs);
+        #[cfg(not(feature = "tls"))]
+        let addrs = self.addrs.iter();
+        addrs.map(SocketAddr::to_string).collect::<Vec<_>>().join(", ")
+    }
+
+    /// TLS settings in use, to bind these listeners again with
+    fn tls(&self) -> Option<TlsSettings> {
+        #[cfg(feature = "tls")]
+        return self.acceptor.as_ref().map(|acceptor| acceptor.config());
+        #[cfg(not(feature = "tls"))]
+        None
+    }
+
+    /// Stop accepting and close the sockets; open connections are left to the drain
+    async fn stop(&mut self) {
+        self.drain.stop_accepting();
+        if let Some(stop) = self.stop_udp.take() {
+            let _ = stop.send(());
+        }
+        for task in &self.batch_udp {
+            task.abort();
+        }
+        for task in self.tasks.drain(..).chain(self.batch_udp.drain(..)) {
+            let _ = task.await;
+        }
+    }
+}
+
+/// The main listeners: the generation serving now, and those still draining
+pub struct Listeners {
+    handler: Arc<MdnsDnsHandler>,
+    push: Option<Arc<PushService>>,
+    current: Mutex<Generation>,
+    /// Connections of replaced generations being finished
+    retiring: std::sync::Mutex<Vec<JoinHandle<()>>>,
+    stopped: mpsc::UnboundedSender<Stopped>,
+    stopped_rx: Mutex<mpsc::UnboundedReceiver<Stopped>>,
+}
+
+impl Listeners {
+    /// Bind and serve `server`'s listeners with `handler`, and DNS Push on
+    /// TCP and TLS connections if `push` is given
+    pub async fn start(server: &ServerConfig, handler: MdnsDnsHandler, push: Option<Arc<PushService>>) -> ServeResult<Self> {
+        let tls = tls_settings(server)?;
+        let handler = Arc::new(handler);
+        let (stopped, stopped_rx) = mpsc::unbounded_channel();
+        let current = Generation::start(server, tls, &handler, &push, &stopped).await?;
+        Ok(Self {
+            handler,
+            push,
+            current: Mutex::new(current),
+            retiring: std::sync::Mutex::new(Vec::new()),
+            stopped,
+            stopped_rx: Mutex::new(stopped_rx),
+        })
+    }
+
+    /// Addresses bound now, the first one first
+    pub async fn addrs(&self) -> Vec<SocketAddr> {
+        self.current.lock().await.addrs.clone()
+    }
+
+    /// Connections open on the current listeners
+    pub async fn connections(&self) -> usize {
+        self.current.lock().await.drain.connections()
+    }
+
+    /// Resolves once a listener stops serving: `Ok` if the UDP server shut
+    /// down cleanly, the failure otherwise. Listeners stopped by a reload or
+    /// [`shutdown`](Self::shutdown) are not reported.
+    pub async fn stopped(&self) -> Result<(), String> {
+        match self.stopped_rx.lock().await.recv().await {
+            Some(stopped) => stopped,
+            // The sender lives as long as self
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Put `server`'s listener settings into effect: rebind if they changed,
+    /// otherwise read the DNS-over-TLS certificate again
+    pub async fn reload(&self, server: &ServerConfig) {
+        let mut current = self.current.lock().await;
+        if Binding::of(server) == Binding::of(&current.server) {
+            #[cfg(feature = "tls")]
+            if let (Some(config), Some(acceptor)) = (&server.tls, &current.acceptor) {
+                match crate::tls::server_config(config) {
+                    Ok(tls) => {
+                        acceptor.replace(tls);
+                        info!("Reloaded the DNS-over-TLS certificate from {}", config.cert_file.display());
+                    }
+                    Err(e) => warn!("Keeping the previous DNS-over-TLS certificate: {}", e),
+                }
+            }
+            return;
+        }
+
+        // Read before anything stops, so a bad certificate leaves the old listeners serving
+        let tls = match tls_settings(server) {
+            Ok(tls) => tls,
+            Err(e) => {
+                warn!("Keeping the listeners on {}: {}", current.describe(), e);
+                return;
+            }
+        };
+        let previous_tls = current.tls();
+        current.stop().await;
+        let old_drain = current.drain.clone();
+        match Generation::start(server, tls, &self.handler, &self.push, &self.stopped).await {
+            Ok(next) => {
+                info!("Listeners rebound to {}", next.describe());
+                *current = next;
+            }
+            Err(e) => {
+                warn!("Failed to bind the new listeners, binding the previous ones again: {}", e);
+                let previous = current.server.clone();
+                match Generation::start(&previous, previous_tls, &self.handler, &self.push, &self.stopped).await {
+                    Ok(again) => *current = again,
+                    Err(e) => {
+                        let _ = self.stopped.send(Err(format!("Failed to bind the previous listeners again: {}", e)));
+                    }
+                }
+            }
+        }
+
+        let deadline = Duration::from_secs(server.drain_timeout_secs);
+        let open = old_drain.connections();
+        if open > 0 {
+            info!("Finishing {} connection(s) on the previous listeners for up to {:?}", open, deadline);
+        }
+        let mut retiring = self.retiring.lock().unwrap();
+        retiring.retain(|task| !task.is_finished());
+        retiring.push(tokio::spawn(async move {
+            let left = old_drain.drain(deadline).await;
+            if left > 0 {
+                warn!("Closing {} connection(s) on the previous listeners that were still busy", left);
+            }
+        }));
+    }
+
+    /// Stop accepting and close connections as they become idle; returns the
+    /// number of current connections still open when `deadline` passed.
+    /// Connections of replaced listeners are waited for up to their own deadline.
+    pub async fn shutdown(&self, deadline: Duration) -> usize {
+        let mut current = self.current.lock().await;
+        current.stop().await;
+        let left = current.drain.drain(deadline).await;
+        let retiring = std::mem::take(&mut *self.retiring.lock().unwrap());
+        futures_util::future::join_all(retiring).await;
+        left
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mdns_resolver::MdnsResolver;
+    use crate::zones::ZoneRegistry;
+    use hickory_proto::op::{Message, Query};
+    use hickory_proto::rr::{Name, RecordType};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    fn handler() -> MdnsDnsHandler {
+        let mut config = crate::config::Config::default();
+        config.mdns.lazy_start = true;
+        let config = Arc::new(config);
+        let resolver = Arc::new(MdnsResolver::new(config.clone()).unwrap());
+        let zones = Arc::new(ZoneRegistry::new(&[config.discovery_domain()]).unwrap());
+        MdnsDnsHandler::with_zones(resolver, zones)
+    }
+
+    fn server(port: u16) -> ServerConfig {
+        ServerConfig {
+            bind_address: vec!["127.0.0.1".parse().unwrap()],
+            port,
+            ..Default::default()
+        }
+    }
+
+    /// Ask for the SOA of the discovery domain over `stream`
+    async fn ask<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin>(stream: &mut S, id: u16) -> Message {
+        let mut message = Message::new();
+        message.set_id(id);
+        message.add_query(Query::query(Name::from_utf8("local.").unwrap(), RecordType::SOA));
+        let bytes = message.to_vec().unwrap();
+        stream.write_all(&(bytes.len() as u16).to_be_bytes()).await.unwrap();
+        stream.write_all(&bytes).await.unwrap();
+        let len = stream.read_u16().await.unwrap();
+        let mut reply = vec![0; len as usize];
+        stream.read_exact(&mut reply).await.unwrap();
+        Message::from_vec(&reply).unwrap()
+    }
+
+    #[test]
+    fn test_binding_ignores_settings_read_per_query() {
+        let old = server(5353);
+        let mut new = server(5353);
+        new.ttl_jitter_secs = 30;
+        new.discovery_domain = "lan.home.arpa.".to_string();
+        assert_eq!(Binding::of(&old), Binding::of(&new));
+        new.tcp_max_questions = 2;
+        assert_ne!(Binding::of(&old), Binding::of(&new));
+        assert_ne!(Binding::of(&old), Binding::of(&server(5354)));
+    }
+
+    #[tokio::test]
+    async fn test_reload_rebinds_and_drains_old_connections() {
+        let listeners = Listeners::start(&server(0), handler(), None).await.unwrap();
+        let old = listeners.addrs().await[0];
+        let mut open = TcpStream::connect(old).await.unwrap();
+        assert_eq!(ask(&mut open, 1).await.id(), 1);
+
+        // Port 0 again binds another free port
+        let mut changed = server(0);
+        changed.tcp_max_questions = 2;
+        listeners.reload(&changed).await;
+        let new = listeners.addrs().await[0];
+        assert_ne!(old, new);
+        assert!(TcpStream::connect(old).await.is_err());
+        let mut fresh = TcpStream::connect(new).await.unwrap();
+        assert_eq!(ask(&mut fresh, 2).await.id(), 2);
+
+        // The old connection was idle, so the drain closed it
+        let mut rest = Vec::new();
+        let closed = tokio::time::timeout(Duration::from_secs(2), open.read_to_end(&mut rest)).await;
+        assert_eq!(closed.unwrap().unwrap(), 0);
+
+        assert_eq!(listeners.shutdown(Duration::from_secs(2)).await, 0);
+        assert!(TcpStream::connect(new).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_failed_rebind_keeps_serving_previous_settings() {
+        // A concrete port, so binding the previous settings again lands on it
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let listeners = Listeners::start(&server(port), handler(), None).await.unwrap();
+
+        let unbindable = ServerConfig {
+            bind_address: vec!["192.0.2.1".parse().unwrap()],
+            ..server(port)
+        };
+        listeners.reload(&unbindable).await;
+        let addrs = listeners.addrs().await;
+        assert_eq!(addrs[0].port(), port);
+        let mut stream = TcpStream::connect(addrs[0]).await.unwrap();
+        assert_eq!(ask(&mut stream, 3).await.id(), 3);
+        listeners.shutdown(Duration::from_secs(2)).await;
+    }
+
+    #[cfg(feature = "tls")]
+    #[tokio::test]
+    async fn test_tls_certificate_reload_and_rebind() {
+        use rustls::pki_types::pem::PemObject;
+        use rustls::pki_types::{CertificateDer, ServerName};
+        use std::path::PathBuf;
+
+        let data = |file: &str| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data").join(file);
+        let dir = tempfile::tempdir().unwrap();
+        let (cert, key) = (dir.path().join("cert.pem"), dir.path().join("key.pem"));
+        std::fs::copy(data("mtls-server-cert.pem"), &cert).unwrap();
+        std::fs::copy(data("mtls-server-key.pem"), &key).unwrap();
+        let config = ServerConfig {
+            tls: Some(TlsConfig {
+                port: 0,
+                cert_file: cert.clone(),
+                key_file: key.clone(),
+                ..Default::default()
+            }),
+            ..server(0)
+        };
+        let listeners = Listeners::start(&config, handler(), None).await.unwrap();
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_file(data("mtls-ca.pem")).unwrap()).unwrap();
+        let client = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(client));
+        let connect = |addr: SocketAddr| {
+            let connector = connector.clone();
+            async move {
+                let stream = TcpStream::connect(addr).await?;
+                let name = ServerName::try_from("discovery-proxy.mdns.home.arpa").unwrap();
+                connector.connect(name, stream).await
+            }
+        };
+
+        let old_addr = listeners.current.lock().await.tls_addrs[0];
+        let mut open = connect(old_addr).await.unwrap();
+        assert_eq!(ask(&mut open, 1).await.id(), 1);
+
+        // A certificate from another CA replaced on disk is used from the next handshake on
+        std::fs::copy(data("dot-cert.pem"), &cert).unwrap();
+        std::fs::copy(data("dot-key.pem"), &key).unwrap();
+        listeners.reload(&config).await;
+        assert!(connect(old_addr).await.is_err());
+        assert_eq!(ask(&mut open, 2).await.id(), 2);
+
+        // Rebinding finishes the message under way on the old connection, then closes it
+        std::fs::copy(data("mtls-server-cert.pem"), &cert).unwrap();
+        std::fs::copy(data("mtls-server-key.pem"), &key).unwrap();
+        let mut message = Message::new();
+        message.set_id(3);
+        message.add_query(Query::query(Name::from_utf8("local.").unwrap(), RecordType::SOA));
+        let bytes = message.to_vec().unwrap();
+        open.write_all(&(bytes.len() as u16).to_be_bytes()).await.unwrap();
+        open.flush().await.unwrap();
+        // Let the server start reading the message before the drain begins
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let changed = ServerConfig {
+            tcp_max_questions: 2,
+            ..config.clone()
+        };
+        listeners.reload(&changed).await;
+        open.write_all(&bytes).await.unwrap();
+        let len = open.read_u16().await.unwrap();
+        let mut reply = vec![0; len as usize];
+        open.read_exact(&mut reply).await.unwrap();
+        assert_eq!(Message::from_vec(&reply).unwrap().id(), 3);
+        let mut rest = Vec::new();
+        let closed = tokio::time::timeout(Duration::from_secs(2), open.read_to_end(&mut rest)).await;
+        assert!(closed.is_ok());
+
+        let new_addr = listeners.current.lock().await.tls_addrs[0];
+        assert_ne!(old_addr, new_addr);
+        let mut fresh = connect(new_addr).await.unwrap();
+        assert_eq!(ask(&mut fresh, 4).await.id(), 4);
+        drop(fresh);
+        listeners.shutdown(Duration::from_secs(2)).await;
+    }
+}
@@ -0,0 +1,362 @@
+//! Latency objectives
+//!
+//! `[slo]` sets two objectives, e.g. 99% of cached answers within 5ms and 95%
+//! of uncached ones within 1.5s. Each answer is timed from the moment the
+//! query arrived (admission queue included) until its response was sent, and
+//! counted against its class: cached (the cache or `[known_services]`
+//! answered) or uncached (the query went to mDNS or a peer, or failed with
+//! SERVFAIL). Other answers, from policy or the proxy's own records, are not
+//! counted.
+//!
+//! Compliance is the share of a class's answers within target over the last
+//! `window_secs`, kept as one bucket per second. A class with fewer than
+//! `min_samples` answers in the window is not judged. Every
+//! `check_interval_secs` each class is compared with its objective; a breach
+//! starting is logged as a warning and counted in `slo_breaches`, its end is
+//! logged too, and both are POSTed as JSON to `webhook_url` when one is set.
+
+use crate::config::SloConfig;
+use crate::metrics;
+use hickory_proto::op::ResponseCode;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::{debug, info, warn};
+
+type SloResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Kind of answer an objective applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Class {
+    Cached,
+    Uncached,
+}
+
+impl Class {
+    /// Class of an answer from `source` (see `Answer::source`) with `response_code`
+    pub fn of(source: &str, response_code: ResponseCode) -> Option<Self> {
+        match source {
+            "cache" | "known" => Some(Class::Cached),
+            "mdns" | "peers" | "reverse" | "none" => Some(Class::Uncached),
+            "" if response_code == ResponseCode::ServFail => Some(Class::Uncached),
+            _ => None,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Answers of one second
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    second: u64,
+    total: u64,
+    within: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Objective {
+    percent: f64,
+    target: Duration,
+}
+
+/// How a class stands against its objective
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ClassStatus {
+    pub class: Class,
+    pub objective_percent: f64,
+    pub target_ms: u64,
+    /// Answers in the window
+    pub samples: u64,
+    /// Percentage of them within target; None without any
+    pub compliance: Option<f64>,
+    /// Judged, and below the objective
+    pub breached: bool,
+}
+
+/// Answer latencies per class over a rolling window
+#[derive(Debug)]
+pub struct SloTracker {
+    started: Instant,
+    window_secs: u64,
+    min_samples: u64,
+    objectives: [Objective; 2],
+    buckets: [Mutex<VecDeque<Bucket>>; 2],
+    /// Breach state as of the last check
+    breached: [AtomicBool; 2],
+}
+
+impl SloTracker {
+    /// Objectives of `[slo]`; None unless it is enabled
+    pub fn from_config(config: &SloConfig) -> Option<Self> {
+        config.enabled.then(|| Self {
+            started: Instant::now(),
+            window_secs: config.window_secs.max(1),
+            min_samples: config.min_samples,
+            objectives: [
+                Objective {
+                    percent: config.cached_percent,
+                    target: Duration::from_millis(config.cached_target_ms),
+                },
+                Objective {
+                    percent: config.uncached_percent,
+                    target: Duration::from_millis(config.uncached_target_ms),
+                },
+            ],
+            buckets: Default::default(),
+            breached: Default::default(),
+        })
+    }
+
+    /// Count an answer of `class` that took `elapsed`
+    pub fn record(&self, class: Class, elapsed: Duration) {
+        self.record_at(class, elapsed, Instant::now());
+    }
+
+    fn record_at(&self, class: Class, elapsed: Duration, now: Instant) {
+        let second = now.duration_since(self.started).as_secs();
+        let within = u64::from(elapsed <= self.objectives[class.index()].target);
+        let mut buckets = self.buckets[class.index()].lock().unwrap();
+        match buckets.back_mut() {
+            Some(bucket) if bucket.second == second => {
+                bucket.total += 1;
+                bucket.within += within;
+            }
+            _ => buckets.push_back(Bucket { second, total: 1, within }),
+        }
+        while buckets.front().is_some_and(|b| b.second + self.window_secs <= second) {
+            buckets.pop_front();
+        }
+    }
+
+    /// Each class against its objective now
+    pub fn status(&self) -> Vec<ClassStatus> {
+        self.status_at(Instant::now())
+    }
+
+    fn status_at(&self, now: Instant) -> Vec<ClassStatus> {
+        let second = now.duration_since(self.started).as_secs();
+        [Class::Cached, Class::Uncached]
+            .into_iter()
+            .map(|class| {
+                let objective = self.objectives[class.index()];
+                let (samples, within) = self.buckets[class.index()]
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .filter(|b| b.second + self.window_secs > second)
+                    .fold((0, 0), |(total, within), b| (total + b.total, within + b.within));
+                let compliance = (samples > 0).then(|| within as f64 * 100.0 / samples as f64);
+                ClassStatus {
+                    class,
+                    objective_percent: objective.percent,
+                    target_ms: objective.target.as_millis() as u64,
+                    samples,
+                    compliance,
+                    breached: samples >= self.min_samples.max(1) && compliance.is_some_and(|c| c < objective.percent),
+                }
+            })
+            .collect()
+    }
+
+    /// Compare each class with its objective; returns those whose breach started or ended since the last check
+    pub fn check(&self) -> Vec<ClassStatus> {
+        self.check_at(Instant::now())
+    }
+
+    fn check_at(&self, now: Instant) -> Vec<ClassStatus> {
+        self.status_at(now)
+            .into_iter()
+            .filter(|status| self.breached[status.class.index()].swap(status.breached, Ordering::Relaxed) != status.breached)
+            .collect()
+    }
+}
+
+/// Check the objectives every `config.check_interval_secs` for as long as the proxy runs
+pub async fn run(tracker: Arc<SloTracker>, config: SloConfig) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(config.check_interval_secs.max(1)));
+    loop {
+        ticker.tick().await;
+        for status in tracker.check() {
+            let compliance = status.compliance.unwrap_or(100.0);
+            if status.breached {
+                metrics::inc(&metrics::metrics().slo_breaches);
+                warn!(
+                    "Latency objective for {:?} answers breached: {:.2}% of {} within {}ms, objective {}%",
+                    status.class, compliance, status.samples, status.target_ms, status.objective_percent
+                );
+            } else {
+                info!("Latency objective for {:?} answers met again ({:.2}%)", status.class, compliance);
+            }
+            if let Some(url) = &config.webhook_url {
+                let body = webhook_body(&status);
+                match post(url, &body, Duration::from_secs(5)).await {
+                    Ok(()) => debug!("Posted the {:?} objective change to {}", status.class, url),
+                    Err(e) => warn!("SLO webhook {} failed: {}", url, e),
+                }
+            }
+        }
+    }
+}
+
+/// JSON array of the status of each class
+pub fn status_json(statuses: &[ClassStatus]) -> String {
+    serde_json::to_string(statuses).expect("slo status serializes")
+}
+
+fn webhook_body(status: &ClassStatus) -> String {
+    let mut value = serde_json::to_value(status).expect("slo status serializes");
+    value["event"] = serde_json::json!(if status.breached { "breach" } else { "recovered" });
+    value.to_string()
+}
+
+/// Host, port and path of an `http://` URL
+pub fn parse_http_url(url: &str) -> SloResult<(String, u16, String)> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| format!("{}: only http:// URLs are supported", url))?;
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => (&rest[..slash], &rest[slash..]),
+        None => (rest, "/"),
+    };
+    // An IPv6 address is bracketed, so its colons are not taken for the port's
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(bracketed) => bracketed.split_once(']').ok_or_else(|| format!("{}: unclosed [", url))?,
+        None => authority.split_once(':').unwrap_or((authority, "")),
+    };
+    let port = match port.strip_prefix(':').unwrap_or(port) {
+        "" => 80,
+        port => port.parse().map_err(|_| format!("{}: invalid port", url))?,
+    };
+    if host.is_empty() {
+        return Err(format!("{}: missing host", url).into());
+    }
+    Ok((host.to_string(), port, path.to_string()))
+}
+
+/// Value of the Host header (RFC 9110 Section 7.2): IPv6 addresses bracketed,
+/// and the port unless it is 80
+fn host_header(host: &str, port: u16) -> String {
+    let host = if host.contains(':') { format!("[{}]", host) } else { host.to_string() };
+    match port {
+        80 => host,
+        port => format!("{}:{}", host, port),
+    }
+}
+
+/// POST `body` as JSON to `url`, failing on anything but a 2xx status
+async fn post(url: &str, body: &str, timeout: Duration) -> SloResult<()> {
+    let (host, port, path) = parse_http_url(url)?;
+    let exchange = async {
+        let mut stream = TcpStream::connect((host.as_str(), port)).await?;
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            path,
+            host_header(&host, port),
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        Ok::<_, std::io::Error>(response)
+    };
+    let response = tokio::time::timeout(timeout, exchange).await.map_err(|_| "timed out")??;
+    let status_line = String::from_utf8_lossy(&response).lines().next().unwrap_or_default().to_string();
+    match status_line.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(format!("unexpected response {:?}", status_line).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker() -> SloTracker {
+        let config = SloConfig {
+            enabled: true,
+            min_samples: 4,
+            window_secs: 60,
+            ..SloConfig::default()
+        };
+        SloTracker::from_config(&config).unwrap()
+    }
+
+    #[test]
+    fn test_breach_starts_and_ends_with_the_window() {
+        let tracker = tracker();
+        let start = tracker.started;
+        let fast = Duration::from_millis(1);
+        let slow = Duration::from_secs(3);
+        assert_eq!(Class::of("cache", ResponseCode::NoError), Some(Class::Cached));
+        assert_eq!(Class::of("", ResponseCode::ServFail), Some(Class::Uncached));
+        assert_eq!(Class::of("policy", ResponseCode::NXDomain), None);
+
+        // Too few answers to judge
+        for _ in 0..3 {
+            tracker.record_at(Class::Uncached, slow, start);
+        }
+        assert!(tracker.check_at(start).is_empty());
+
+        tracker.record_at(Class::Uncached, fast, start + Duration::from_secs(1));
+        tracker.record_at(Class::Cached, fast, start);
+        let changed = tracker.check_at(start + Duration::from_secs(1));
+        assert_eq!(changed.len(), 1);
+        assert_eq!(changed[0].class, Class::Uncached);
+        assert_eq!(changed[0].compliance, Some(25.0));
+        assert!(changed[0].breached);
+        // Reported once
+        assert!(tracker.check_at(start + Duration::from_secs(2)).is_empty());
+
+        // The slow answers age out of the window
+        let later = start + Duration::from_secs(61);
+        for _ in 0..4 {
+            tracker.record_at(Class::Uncached, fast, later);
+        }
+        let changed = tracker.check_at(later);
+        assert_eq!(changed.len(), 1);
+        assert!(!changed[0].breached);
+        assert_eq!(tracker.status_at(later)[1].samples, 4);
+        assert_eq!(tracker.status_at(later)[0].compliance, None);
+
+        assert!(SloTracker::from_config(&SloConfig::default()).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_webhook_post() {
+        assert_eq!(
+            parse_http_url("http://alerts.home.arpa:9000/hooks/a").unwrap(),
+            ("alerts.home.arpa".to_string(), 9000, "/hooks/a".to_string())
+        );
+        assert_eq!(parse_http_url("http://[::1]:81").unwrap(), ("::1".to_string(), 81, "/".to_string()));
+        assert_eq!(parse_http_url("http://alerts").unwrap().1, 80);
+        assert!(parse_http_url("https://alerts/").is_err());
+        assert_eq!(host_header("::1", 81), "[::1]:81");
+        assert_eq!(host_header("::1", 80), "[::1]");
+        assert_eq!(host_header("alerts", 80), "alerts");
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let url = format!("http://{}/hook", addr);
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0u8; 4096];
+            let len = stream.read(&mut request).await.unwrap();
+            stream.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").await.unwrap();
+            String::from_utf8_lossy(&request[..len]).to_string()
+        });
+        let status = tracker().status().remove(0);
+        post(&url, &webhook_body(&status), Duration::from_secs(2)).await.unwrap();
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /hook HTTP/1.1\r\n"));
+        assert!(request.contains(&format!("\r\nHost: 127.0.0.1:{}\r\n", addr.port())), "{}", request);
+        assert!(request.contains("\"class\":\"cached\""));
+        assert!(request.contains("\"event\":\"recovered\""));
+    }
+}
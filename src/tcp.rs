@@ -0,0 +1,636 @@
+//! Hardened DNS-over-TCP listener
+//!
+//! `ServerFuture` accepts any length prefix up to 64 KiB and only times out a
+//! connection that goes quiet for `tcp_timeout`, so a client trickling a byte at
+//! a time can hold a connection open indefinitely. This listener frames
+//! messages itself and enforces [`TcpLimits`]: the length prefix is checked
+//! before the body is read, the header's question count before the message is
+//! decoded, and once a message has started arriving all of it must arrive
+//! within the read deadline. Requests on one connection are handled in turn,
+//! as `ServerFuture` does.
+//!
+//! [`serve_unix`] serves the same framing and limits on a Unix stream socket
+//! (`server.unix_socket_path`) for local stub resolvers and tools.
+//!
+//! With `push.enabled`, DSO messages (opcode 6) are handed to the connection's
+//! DNS Push [`Session`] instead of the request handler, and once a session
+//! holds subscriptions the connection is kept open for its keepalive interval
+//! rather than the idle timeout.
+//!
+//! [`serve_tls`] does the same behind a TLS handshake for DNS-over-TLS, with
+//! a certificate that can be replaced while connections are open.
+//!
+//! At shutdown, or when a reload rebinds the listeners, a [`Drain`] stops the
+//! listeners accepting and closes each open connection once it is idle: a
+//! message being read or answered is finished, and the connection is closed
+//! instead of waiting for the next one.
+
+use crate::config::ServerConfig;
+use crate::dns_handler::ClientMeta;
+use crate::metrics;
+use crate::push::{dso, PushService, Session};
+use hickory_proto::op::{Header, MessageType, ResponseCode};
+use hickory_proto::rr::Record;
+use hickory_proto::serialize::binary::{BinDecodable, BinEncodable, BinEncoder};
+use hickory_proto::xfer::Protocol;
+use hickory_server::authority::{MessageRequest, MessageResponse};
+use hickory_server::server::{Request, RequestHandler, ResponseHandler, ResponseInfo};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::{watch, Mutex};
+use tokio::time::timeout;
+use tracing::{debug, warn};
+
+/// Size of the fixed DNS header
+const HEADER_LEN: usize = 12;
+
+/// Limits applied to every TCP connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpLimits {
+    /// Largest message accepted; longer length prefixes close the connection
+    pub max_message_bytes: u16,
+    /// Most questions a message may carry; more is answered with FORMERR
+    pub max_questions: u16,
+    /// How long a connection may wait between messages
+    pub idle_timeout: Duration,
+    /// How long the rest of a message may take once its first byte arrived,
+    /// and how long writing a response may take
+    pub read_timeout: Duration,
+}
+
+impl TcpLimits {
+    pub fn from_config(server: &ServerConfig) -> Self {
+        Self {
+            max_message_bytes: server.tcp_max_message_bytes,
+            max_questions: server.tcp_max_questions,
+            idle_timeout: Duration::from_secs(server.tcp_timeout),
+            read_timeout: Duration::from_millis(server.tcp_read_timeout_ms),
+        }
+    }
+}
+
+/// Winds down the listeners and connections it was given to
+#[derive(Debug, Clone)]
+pub struct Drain {
+    draining: Arc<watch::Sender<bool>>,
+    /// One receiver per open connection
+    open: Arc<watch::Sender<()>>,
+}
+
+/// Held by a connection for as long as it is open
+#[derive(Debug)]
+struct DrainToken {
+    draining: watch::Receiver<bool>,
+    _open: watch::Receiver<()>,
+}
+
+impl Drain {
+    pub fn new() -> Self {
+        Self {
+            draining: Arc::new(watch::channel(false).0),
+            open: Arc::new(watch::channel(()).0),
+        }
+    }
+
+    fn token(&self) -> DrainToken {
+        DrainToken {
+            draining: self.draining.subscribe(),
+            _open: self.open.subscribe(),
+        }
+    }
+
+    /// Connections open right now
+    pub fn connections(&self) -> usize {
+        self.open.receiver_count()
+    }
+
+    /// Whether the listeners were told to stop accepting
+    pub fn is_draining(&self) -> bool {
+        *self.draining.borrow()
+    }
+
+    /// Stop accepting and close connections as they become idle, without waiting for them
+    pub fn stop_accepting(&self) {
+        self.draining.send_replace(true);
+    }
+
+    /// Stop accepting and close connections as they become idle; returns the
+    /// number still open when `deadline` passed (0 if all closed in time)
+    pub async fn drain(&self, deadline: Duration) -> usize {
+        self.stop_accepting();
+        let _ = timeout(deadline, self.open.closed()).await;
+        self.connections()
+    }
+}
+
+impl Default for Drain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Resolves once `draining` is set; never if its [`Drain`] is gone
+async fn drain_started(draining: &mut watch::Receiver<bool>) {
+    if draining.wait_for(|draining| *draining).await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Why a connection stopped being read
+#[derive(Debug, PartialEq, Eq)]
+enum Stop {
+    /// The client closed the connection or it failed
+    Closed,
+    /// Nothing arrived within the idle timeout
+    Idle,
+    /// The listener is draining and the connection was idle
+    Draining,
+    /// A message started but did not finish within the read deadline
+    Slow,
+    /// The length prefix exceeded the limit
+    Oversized(u16),
+}
+
+/// Serve DNS over connections accepted from `listener` until `drain` starts,
+/// and DNS Push if `push` is given
+pub async fn serve<H: RequestHandler>(
+    listener: TcpListener,
+    handler: Arc<H>,
+    limits: TcpLimits,
+    push: Option<Arc<PushService>>,
+    drain: Drain,
+) -> io::Result<()> {
+    let mut draining = drain.draining.subscribe();
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = drain_started(&mut draining) => return Ok(()),
+        };
+        let (stream, src) = match accepted {
+            Ok(accepted) => accepted,
+            // Running out of descriptors or a reset before accept should not stop the listener
+            Err(e) => {
+                warn!("Failed to accept TCP connection: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let handler = handler.clone();
+        let push = push.clone();
+        let token = drain.token();
+        tokio::spawn(async move {
+            let (reader, writer) = stream.into_split();
+            connection(reader, writer, ClientMeta::new(src, Protocol::Tcp), handler, limits, push, token).await;
+        });
+    }
+}
+
+/// Serve DNS-over-TLS on connections accepted from `listener` until `drain`
+/// starts, handshaking with the certificate `acceptor` holds at the time. The
+/// handshake must finish within the idle timeout.
+#[cfg(feature = "tls")]
+pub async fn serve_tls<H: RequestHandler>(
+    listener: TcpListener,
+    acceptor: Arc<crate::tls::Acceptor>,
+    handler: Arc<H>,
+    limits: TcpLimits,
+    push: Option<Arc<PushService>>,
+    drain: Drain,
+) -> io::Result<()> {
+    let mut draining = drain.draining.subscribe();
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = drain_started(&mut draining) => return Ok(()),
+        };
+        let (stream, src) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Failed to accept DNS-over-TLS connection: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let tls = acceptor.current();
+        let handler = handler.clone();
+        let push = push.clone();
+        let token = drain.token();
+        tokio::spawn(async move {
+            let stream = match timeout(limits.idle_timeout, tls.accept(stream)).await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(e)) => {
+                    debug!("TLS handshake with {} failed: {}", src, e);
+                    return;
+                }
+                Err(_) => {
+                    debug!("TLS handshake with {} not finished in time", src);
+                    return;
+                }
+            };
+            let (reader, writer) = tokio::io::split(stream);
+            connection(reader, writer, ClientMeta::new(src, Protocol::Tls), handler, limits, push, token).await;
+        });
+    }
+}
+
+/// Source address given to requests arriving on the Unix socket, which have none
+#[cfg(unix)]
+const UNIX_CLIENT: SocketAddr = SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST), 0);
+
+/// Bind a Unix stream socket at `path`, replacing a stale socket file, open to every local user
+#[cfg(unix)]
+pub fn bind_unix(path: &std::path::Path) -> io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::PermissionsExt;
+
+    // A socket file left behind by a previous run would make bind fail
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o666))?;
+    Ok(listener)
+}
+
+/// Serve DNS over connections accepted from the Unix socket `listener` until `drain` starts.
+/// Requests are answered as if they came from the loopback address over TCP.
+#[cfg(unix)]
+pub async fn serve_unix<H: RequestHandler>(
+    listener: tokio::net::UnixListener,
+    handler: Arc<H>,
+    limits: TcpLimits,
+    push: Option<Arc<PushService>>,
+    drain: Drain,
+) -> io::Result<()> {
+    let mut draining = drain.draining.subscribe();
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = drain_started(&mut draining) => return Ok(()),
+        };
+        let stream = match accepted {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!("Failed to accept Unix socket connection: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let handler = handler.clone();
+        let push = push.clone();
+        let token = drain.token();
+        tokio::spawn(async move {
+            let (reader, writer) = stream.into_split();
+            connection(reader, writer, ClientMeta::new(UNIX_CLIENT, Protocol::Tcp), handler, limits, push, token).await;
+        });
+    }
+}
+
+/// Read and answer messages on one connection until it stops
+async fn connection<R, W, H>(
+    mut reader: R,
+    writer: W,
+    client: ClientMeta,
+    handler: Arc<H>,
+    limits: TcpLimits,
+    push: Option<Arc<PushService>>,
+    mut token: DrainToken,
+) where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin + Send + 'static,
+    H: RequestHandler,
+{
+    let src = client.addr;
+    let writer = Arc::new(Mutex::new(writer));
+    let mut session: Option<Session<W>> = None;
+    loop {
+        let read_limits = match &session {
+            Some(session) => TcpLimits {
+                idle_timeout: session.idle_timeout(),
+                ..limits
+            },
+            None => limits,
+        };
+        let bytes = match read_message(&mut reader, &read_limits, &mut token.draining).await {
+            Ok(bytes) => bytes,
+            Err(stop) => {
+                match stop {
+                    Stop::Closed => {}
+                    Stop::Idle => debug!("Closing idle TCP connection from {}", src),
+                    Stop::Draining => debug!("Closing TCP connection from {}: draining", src),
+                    Stop::Slow => {
+                        metrics::inc(&metrics::metrics().tcp_read_timeouts);
+                        debug!("Closing TCP connection from {}: message not received in time", src);
+                    }
+                    Stop::Oversized(len) => {
+                        metrics::inc(&metrics::metrics().tcp_oversized_messages);
+                        debug!("Closing TCP connection from {}: {} byte message exceeds the limit", src, len);
+                    }
+                }
+                return;
+            }
+        };
+
+        let questions = u16::from_be_bytes([bytes[4], bytes[5]]);
+        if questions > limits.max_questions {
+            metrics::inc(&metrics::metrics().tcp_too_many_questions);
+            debug!("Rejecting TCP message from {} with {} questions", src, questions);
+            let reply = form_error(&bytes);
+            if write_message(&writer, &reply, limits.read_timeout).await.is_err() {
+                return;
+            }
+            continue;
+        }
+
+        let push_enabled = push.as_ref().is_some_and(|push| push.is_enabled());
+        if session.is_some() && !push_enabled {
+            debug!("Ending DSO session with {}: push is switched off", src);
+            session = None;
+        }
+        if let Some(push) = &push
+            && push_enabled
+            && dso::opcode(&bytes) == dso::OPCODE
+        {
+            let session = session.get_or_insert_with(|| {
+                Session::new(push.clone(), writer.clone(), client.clone(), limits.read_timeout)
+            });
+            if let Err(e) = session.handle(&bytes).await {
+                debug!("Closing DSO session with {}: {}", src, e);
+                return;
+            }
+            continue;
+        }
+
+        let message = match MessageRequest::from_bytes(&bytes) {
+            Ok(message) => message,
+            Err(e) => {
+                debug!("Closing TCP connection from {}: undecodable message: {}", src, e);
+                let reply = form_error(&bytes);
+                let _ = write_message(&writer, &reply, limits.read_timeout).await;
+                return;
+            }
+        };
+        let request = Request::new(message, src, client.protocol);
+        let responder = TcpResponse {
+            writer: writer.clone(),
+            write_timeout: limits.read_timeout,
+        };
+        handler.handle_request(&request, responder).await;
+    }
+}
+
+/// Read one length-prefixed message, applying the idle timeout to its first
+/// byte and the read deadline to the rest. While `draining` is set, an idle
+/// connection stops before its first byte.
+async fn read_message<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    limits: &TcpLimits,
+    draining: &mut watch::Receiver<bool>,
+) -> Result<Vec<u8>, Stop> {
+    // A message that has already arrived is still read
+    let first = tokio::select! {
+        biased;
+        first = timeout(limits.idle_timeout, reader.read_u8()) => match first {
+            Ok(Ok(byte)) => byte,
+            Ok(Err(_)) => return Err(Stop::Closed),
+            Err(_) => return Err(Stop::Idle),
+        },
+        _ = drain_started(draining) => return Err(Stop::Draining),
+    };
+    let rest = async {
+        let len = u16::from_be_bytes([first, reader.read_u8().await.map_err(|_| Stop::Closed)?]);
+        if len > limits.max_message_bytes {
+            return Err(Stop::Oversized(len));
+        }
+        // Too short to hold a header: no message worth answering
+        if (len as usize) < HEADER_LEN {
+            return Err(Stop::Closed);
+        }
+        let mut bytes = vec![0; len as usize];
+        reader.read_exact(&mut bytes).await.map_err(|_| Stop::Closed)?;
+        Ok(bytes)
+    };
+    timeout(limits.read_timeout, rest).await.unwrap_or(Err(Stop::Slow))
+}
+
+/// FORMERR reply echoing the ID and opcode of the message in `bytes`
+fn form_error(bytes: &[u8]) -> Vec<u8> {
+    let mut header = Header::new();
+    if let Ok(request) = Header::from_bytes(&bytes[..HEADER_LEN]) {
+        header.set_id(request.id());
+        header.set_op_code(request.op_code());
+    }
+    header.set_message_type(MessageType::Response);
+    header.set_response_code(ResponseCode::FormErr);
+    header.to_bytes().unwrap_or_default()
+}
+
+/// Write `bytes` with its length prefix within `deadline`
+pub(crate) async fn write_message<W: AsyncWrite + Unpin>(writer: &Mutex<W>, bytes: &[u8], deadline: Duration) -> io::Result<()> {
+    let len = u16::try_from(bytes.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "response too long"))?;
+    let mut framed = Vec::with_capacity(bytes.len() + 2);
+    framed.extend_from_slice(&len.to_be_bytes());
+    framed.extend_from_slice(bytes);
+    let mut writer = writer.lock().await;
+    timeout(deadline, writer.write_all(&framed))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "response not written in time"))?
+}
+
+/// Response handle writing the encoded answer to the connection
+struct TcpResponse<W> {
+    writer: Arc<Mutex<W>>,
+    write_timeout: Duration,
+}
+
+impl<W> Clone for TcpResponse<W> {
+    fn clone(&self) -> Self {
+        Self {
+            writer: self.writer.clone(),
+            write_timeout: self.write_timeout,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<W: AsyncWrite + Unpin + Send + 'static> ResponseHandler for TcpResponse<W> {
+    async fn send_response<'a>(
+        &mut self,
+        response: MessageResponse<
+            '_,
+            'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+        >,
+    ) -> io::Result<ResponseInfo> {
+        let mut buffer = Vec::with_capacity(512);
+        let mut encoder = BinEncoder::new(&mut buffer);
+        encoder.set_max_size(u16::MAX);
+        let info = response.destructive_emit(&mut encoder)?;
+        write_message(&self.writer, &buffer, self.write_timeout).await?;
+        Ok(info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::{Message, Query};
+    use hickory_proto::rr::{Name, RecordType};
+    use hickory_server::authority::MessageResponseBuilder;
+    use tokio::io::{duplex, split};
+
+    /// Answers every query with an empty NOERROR response
+    struct Empty;
+
+    #[async_trait::async_trait]
+    impl RequestHandler for Empty {
+        async fn handle_request<R: ResponseHandler>(&self, request: &Request, mut response: R) -> ResponseInfo {
+            let builder = MessageResponseBuilder::from_message_request(request);
+            let message = builder.build_no_records(Header::response_from_request(request.header()));
+            response.send_response(message).await.unwrap()
+        }
+    }
+
+    fn limits() -> TcpLimits {
+        TcpLimits {
+            max_message_bytes: 512,
+            max_questions: 1,
+            idle_timeout: Duration::from_secs(5),
+            read_timeout: Duration::from_millis(200),
+        }
+    }
+
+    fn query(id: u16, questions: usize) -> Vec<u8> {
+        let mut message = Message::new();
+        message.set_id(id);
+        for i in 0..questions {
+            let name = Name::from_utf8(format!("host{}.mdns.home.arpa.", i)).unwrap();
+            message.add_query(Query::query(name, RecordType::A));
+        }
+        let bytes = message.to_bytes().unwrap();
+        let mut framed = (bytes.len() as u16).to_be_bytes().to_vec();
+        framed.extend(bytes);
+        framed
+    }
+
+    async fn read_reply<R: AsyncRead + Unpin>(reader: &mut R) -> Message {
+        let len = reader.read_u16().await.unwrap();
+        let mut bytes = vec![0; len as usize];
+        reader.read_exact(&mut bytes).await.unwrap();
+        Message::from_vec(&bytes).unwrap()
+    }
+
+    /// Client end of a connection served with `limits`
+    fn connect(limits: TcpLimits) -> (tokio::task::JoinHandle<()>, tokio::io::DuplexStream) {
+        connect_draining(limits, &Drain::new())
+    }
+
+    fn connect_draining(limits: TcpLimits, drain: &Drain) -> (tokio::task::JoinHandle<()>, tokio::io::DuplexStream) {
+        let (client, server) = duplex(4096);
+        let (reader, writer) = split(server);
+        let src: SocketAddr = "192.168.1.40:40000".parse().unwrap();
+        let task = tokio::spawn(connection(reader, writer, ClientMeta::new(src, Protocol::Tcp), Arc::new(Empty), limits, None, drain.token()));
+        (task, client)
+    }
+
+    #[tokio::test]
+    async fn test_too_many_questions_is_answered_with_formerr() {
+        let (task, mut client) = connect(limits());
+        client.write_all(&query(7, 2)).await.unwrap();
+        let reply = read_reply(&mut client).await;
+        assert_eq!((reply.id(), reply.response_code()), (7, ResponseCode::FormErr));
+
+        // The connection stays usable
+        client.write_all(&query(8, 1)).await.unwrap();
+        let reply = read_reply(&mut client).await;
+        assert_eq!((reply.id(), reply.response_code()), (8, ResponseCode::NoError));
+        drop(client);
+        task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_oversized_message_closes_the_connection() {
+        let (task, mut client) = connect(limits());
+        client.write_all(&600u16.to_be_bytes()).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(1), task).await.unwrap().unwrap();
+        let mut rest = Vec::new();
+        assert_eq!(client.read_to_end(&mut rest).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_drain_closes_idle_connections() {
+        let drain = Drain::new();
+        let (task, mut client) = connect_draining(limits(), &drain);
+        client.write_all(&query(10, 1)).await.unwrap();
+        assert_eq!(read_reply(&mut client).await.response_code(), ResponseCode::NoError);
+        assert_eq!(drain.connections(), 1);
+
+        // A message already under way is finished before the connection closes
+        let (late, mut late_client) = connect_draining(limits(), &drain);
+        let framed = query(11, 1);
+        late_client.write_all(&framed[..4]).await.unwrap();
+        let draining = tokio::spawn({
+            let drain = drain.clone();
+            async move { drain.drain(Duration::from_secs(2)).await }
+        });
+        tokio::time::timeout(Duration::from_secs(1), task).await.unwrap().unwrap();
+        late_client.write_all(&framed[4..]).await.unwrap();
+        assert_eq!(read_reply(&mut late_client).await.id(), 11);
+        late.await.unwrap();
+        assert_eq!(draining.await.unwrap(), 0);
+        let mut rest = Vec::new();
+        assert_eq!(client.read_to_end(&mut rest).await.unwrap(), 0);
+
+        // Nothing is left to wait for
+        assert_eq!(drain.drain(Duration::from_secs(1)).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_trickled_message_times_out() {
+        let (task, mut client) = connect(limits());
+        let framed = query(9, 1);
+        // The prefix and half the body, then nothing
+        client.write_all(&framed[..framed.len() / 2]).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(1), task).await.unwrap().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unix_socket_serves_dns() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dns.sock");
+        std::fs::write(&path, b"stale").unwrap();
+        let listener = bind_unix(&path).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o666);
+        let drain = Drain::new();
+        let server = tokio::spawn(serve_unix(listener, Arc::new(Empty), limits(), None, drain.clone()));
+
+        let mut client = tokio::net::UnixStream::connect(&path).await.unwrap();
+        client.write_all(&query(11, 1)).await.unwrap();
+        let reply = read_reply(&mut client).await;
+        assert_eq!((reply.id(), reply.response_code()), (11, ResponseCode::NoError));
+        client.write_all(&query(12, 2)).await.unwrap();
+        assert_eq!(read_reply(&mut client).await.response_code(), ResponseCode::FormErr);
+
+        // Draining stops the listener and closes the idle connection
+        assert_eq!(drain.drain(Duration::from_secs(2)).await, 0);
+        server.await.unwrap().unwrap();
+        assert!(tokio::net::UnixStream::connect(&path).await.is_err());
+    }
+
+    #[test]
+    fn test_from_config() {
+        let server = ServerConfig::default();
+        let limits = TcpLimits::from_config(&server);
+        assert_eq!(limits.idle_timeout, Duration::from_secs(server.tcp_timeout));
+        assert_eq!(limits.max_questions, 1);
+    }
+}
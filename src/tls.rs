@@ -0,0 +1,207 @@
+//! DNS-over-TLS (RFC 7858)
+//!
+//! Clients on untrusted segments can query the proxy over TLS on a port of
+//! its own (853 by default) next to plain UDP and TCP. The certificate chain
+//! and private key are read from PEM files at startup and again on reload;
+//! [`tcp::serve_tls`](crate::tcp::serve_tls) does the handshake and serves the
+//! same handler, framing and limits as the TCP listener. With
+//! `client_ca_file` set, clients must present a certificate issued by one of
+//! its CAs, so only managed devices can use the listener.
+
+use crate::config::TlsConfig;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ResolvesServerCert, ServerConfig, WebPkiClientVerifier};
+use rustls::sign::{CertifiedKey, SingleCertAndKey};
+use rustls::RootCertStore;
+use std::sync::{Arc, RwLock};
+
+type TlsResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Certificate resolver serving the configured chain and key to every client
+pub fn load_certificate(config: &TlsConfig) -> TlsResult<Arc<dyn ResolvesServerCert>> {
+    let chain = CertificateDer::pem_file_iter(&config.cert_file)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("server.tls.cert_file {}: {}", config.cert_file.display(), e))?;
+    if chain.is_empty() {
+        return Err(format!("server.tls.cert_file {}: no certificates", config.cert_file.display()).into());
+    }
+    let key = PrivateKeyDer::from_pem_file(&config.key_file)
+        .map_err(|e| format!("server.tls.key_file {}: {}", config.key_file.display(), e))?;
+    let certified = CertifiedKey::from_der(chain, key, &rustls::crypto::ring::default_provider())
+        .map_err(|e| format!("server.tls: {}", e))?;
+    Ok(Arc::new(SingleCertAndKey::from(certified)))
+}
+
+/// TLS settings of the DNS-over-TLS listener: the configured certificate, ALPN
+/// "dot", and client certificates verified against `client_ca_file` when set
+pub fn server_config(config: &TlsConfig) -> TlsResult<Arc<ServerConfig>> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("server.tls: {}", e))?;
+    let builder = match &config.client_ca_file {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in CertificateDer::pem_file_iter(path)
+                .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+                .map_err(|e| format!("server.tls.client_ca_file {}: {}", path.display(), e))?
+            {
+                roots
+                    .add(cert)
+                    .map_err(|e| format!("server.tls.client_ca_file {}: {}", path.display(), e))?;
+            }
+            if roots.is_empty() {
+                return Err(format!("server.tls.client_ca_file {}: no certificates", path.display()).into());
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .map_err(|e| format!("server.tls.client_ca_file: {}", e))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut server_config = builder.with_cert_resolver(load_certificate(config)?);
+    server_config.alpn_protocols = vec![b"dot".to_vec()];
+    Ok(Arc::new(server_config))
+}
+
+/// Handshakes DNS-over-TLS connections with settings that a reload can
+/// replace; connections already open keep the settings they started with
+#[derive(Debug)]
+pub struct Acceptor {
+    config: RwLock<Arc<ServerConfig>>,
+}
+
+impl Acceptor {
+    pub fn new(config: Arc<ServerConfig>) -> Self {
+        Self {
+            config: RwLock::new(config),
+        }
+    }
+
+    /// Settings in use for new connections
+    pub fn config(&self) -> Arc<ServerConfig> {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Acceptor for the next connection
+    pub fn current(&self) -> tokio_rustls::TlsAcceptor {
+        tokio_rustls::TlsAcceptor::from(self.config())
+    }
+
+    /// Use `config` for connections accepted from now on
+    pub fn replace(&self, config: Arc<ServerConfig>) {
+        *self.config.write().unwrap() = config;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn data(file: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data").join(file)
+    }
+
+    #[test]
+    fn test_load_certificate() {
+        let config = TlsConfig {
+            cert_file: data("dot-cert.pem"),
+            key_file: data("dot-key.pem"),
+            ..Default::default()
+        };
+        assert!(load_certificate(&config).is_ok());
+
+        // The key file holds no certificate
+        let swapped = TlsConfig {
+            cert_file: data("dot-key.pem"),
+            ..config.clone()
+        };
+        let err = load_certificate(&swapped).err().unwrap().to_string();
+        assert!(err.contains("cert_file"), "{}", err);
+
+        let missing = TlsConfig {
+            key_file: data("missing.pem"),
+            ..config
+        };
+        let err = load_certificate(&missing).err().unwrap().to_string();
+        assert!(err.contains("missing.pem"), "{}", err);
+    }
+
+    /// Complete a TLS handshake in memory between `server` and a client
+    /// trusting the test CA, presenting the test client certificate if `with_cert`
+    fn handshake(server: Arc<ServerConfig>, with_cert: bool) -> Result<(), rustls::Error> {
+        use rustls::pki_types::ServerName;
+        use rustls::{ClientConfig, ClientConnection, ServerConnection};
+
+        let mut roots = RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_file(data("mtls-ca.pem")).unwrap()).unwrap();
+        let builder = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        let mut client_config = if with_cert {
+            let chain = vec![CertificateDer::from_pem_file(data("mtls-client-cert.pem")).unwrap()];
+            let key = PrivateKeyDer::from_pem_file(data("mtls-client-key.pem")).unwrap();
+            builder.with_client_auth_cert(chain, key).unwrap()
+        } else {
+            builder.with_no_client_auth()
+        };
+        client_config.alpn_protocols = vec![b"dot".to_vec()];
+        let name = ServerName::try_from("discovery-proxy.mdns.home.arpa").unwrap();
+        let mut client = ClientConnection::new(Arc::new(client_config), name).unwrap();
+        let mut server = ServerConnection::new(server).unwrap();
+
+        let mut wire = Vec::new();
+        while client.is_handshaking() || server.is_handshaking() || client.wants_write() || server.wants_write() {
+            wire.clear();
+            client.write_tls(&mut wire).unwrap();
+            server.read_tls(&mut wire.as_slice()).unwrap();
+            server.process_new_packets()?;
+            wire.clear();
+            server.write_tls(&mut wire).unwrap();
+            client.read_tls(&mut wire.as_slice()).unwrap();
+            client.process_new_packets()?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_server_config_client_auth() {
+        let config = TlsConfig {
+            cert_file: data("mtls-server-cert.pem"),
+            key_file: data("mtls-server-key.pem"),
+            ..Default::default()
+        };
+        let open = server_config(&config).unwrap();
+        assert_eq!(open.alpn_protocols, vec![b"dot".to_vec()]);
+        assert!(handshake(open, false).is_ok());
+
+        let mutual = server_config(&TlsConfig {
+            client_ca_file: Some(data("mtls-ca.pem")),
+            ..config.clone()
+        })
+        .unwrap();
+        assert!(handshake(mutual.clone(), true).is_ok());
+        assert!(handshake(mutual, false).is_err());
+
+        // A client certificate from another CA is refused
+        let other_ca = server_config(&TlsConfig {
+            client_ca_file: Some(data("dot-cert.pem")),
+            ..config.clone()
+        })
+        .unwrap();
+        assert!(handshake(other_ca, true).is_err());
+
+        let err = server_config(&TlsConfig {
+            client_ca_file: Some(data("dot-key.pem")),
+            ..config
+        })
+        .err()
+        .unwrap()
+        .to_string();
+        assert!(err.contains("client_ca_file"), "{}", err);
+    }
+}
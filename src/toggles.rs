@@ -0,0 +1,135 @@
+//! Behaviors that can be switched at runtime
+//!
+//! During an incident it helps to turn a behavior off without editing the
+//! configuration and restarting: record suppression when it hides records a
+//! client needs, forwarding when peers answer badly, DNS Push when sessions
+//! load the proxy. The control command `toggle <feature> on|off` flips one;
+//! the change takes effect with the next query and is not written back to the
+//! configuration. Forwarding and push can only be switched when the
+//! configuration set them up, since their peers and sessions are created at
+//! startup.
+
+use crate::config::{Config, ResolutionStep};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// A behavior that can be switched at runtime
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// Suppression of records the client cannot use (RFC 8766 Section 5.5.2)
+    Suppression,
+    /// Forwarding to peer proxies
+    Forwarding,
+    /// DNS Push sessions
+    Push,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 3] = [Feature::Suppression, Feature::Forwarding, Feature::Push];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::Suppression => "suppression",
+            Feature::Forwarding => "forwarding",
+            Feature::Push => "push",
+        }
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Feature {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Feature::ALL
+            .into_iter()
+            .find(|feature| feature.name() == s)
+            .ok_or_else(|| format!("unknown feature {} (one of suppression, forwarding, push)", s))
+    }
+}
+
+#[derive(Debug)]
+struct Toggle {
+    /// Whether the configuration set the feature up
+    available: bool,
+    enabled: AtomicBool,
+}
+
+impl Toggle {
+    fn new(available: bool) -> Self {
+        Self {
+            available,
+            enabled: AtomicBool::new(available),
+        }
+    }
+}
+
+/// Current state of each [`Feature`]
+#[derive(Debug)]
+pub struct FeatureToggles {
+    toggles: [Toggle; 3],
+}
+
+impl FeatureToggles {
+    /// Every feature the configuration sets up, switched on
+    pub fn from_config(config: &Config) -> Self {
+        let forwarding = config.peers.forward_on_failure || config.resolution.mentions(ResolutionStep::Peers);
+        Self {
+            toggles: [Toggle::new(true), Toggle::new(forwarding), Toggle::new(config.push.enabled)],
+        }
+    }
+
+    fn toggle(&self, feature: Feature) -> &Toggle {
+        &self.toggles[feature as usize]
+    }
+
+    /// Whether `feature` is set up and switched on
+    pub fn is_enabled(&self, feature: Feature) -> bool {
+        self.toggle(feature).enabled.load(Ordering::Relaxed)
+    }
+
+    /// Whether the configuration set `feature` up
+    pub fn is_available(&self, feature: Feature) -> bool {
+        self.toggle(feature).available
+    }
+
+    /// Switch `feature` on or off, returning whether it was on; fails for a
+    /// feature the configuration did not set up
+    pub fn set(&self, feature: Feature, enabled: bool) -> Result<bool, String> {
+        let toggle = self.toggle(feature);
+        if !toggle.available {
+            return Err(format!("{} is not configured", feature));
+        }
+        Ok(toggle.enabled.swap(enabled, Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_toggles_follow_configuration() {
+        let mut config = Config::default();
+        config.push.enabled = true;
+        let toggles = FeatureToggles::from_config(&config);
+        assert!(toggles.is_enabled(Feature::Suppression));
+        assert!(toggles.is_enabled(Feature::Push));
+        assert!(!toggles.is_available(Feature::Forwarding));
+
+        assert_eq!(toggles.set(Feature::Push, false), Ok(true));
+        assert!(!toggles.is_enabled(Feature::Push));
+        assert_eq!(toggles.set(Feature::Push, false), Ok(false));
+        assert!(toggles.set(Feature::Forwarding, true).is_err());
+        assert!(!toggles.is_enabled(Feature::Forwarding));
+
+        assert_eq!("suppression".parse::<Feature>(), Ok(Feature::Suppression));
+        assert!("serve-stale".parse::<Feature>().is_err());
+    }
+}
@@ -0,0 +1,723 @@
+//! TSIG transaction signatures (RFC 8945)
+//!
+//! Resolvers that reach the proxy across an untrusted segment sign their
+//! queries with a secret shared through `[tsig] keys`. A signed query is
+//! verified before it is answered, and its answer is signed with the same
+//! key; one that fails is answered NOTAUTH with the TSIG error (BADKEY,
+//! BADSIG, BADTIME or BADTRUNC) the RFC asks for. Clients on the interfaces
+//! and networks of `[tsig] require_from` must sign: their unsigned queries
+//! are refused.
+//!
+//! hickory decodes a query before the handler sees it, so the signed bytes
+//! are encoded again from the decoded message: the question as received, the
+//! other sections, then the OPT record last before the TSIG record, where
+//! resolvers put it. Only the HMAC-SHA2 algorithms are implemented; HMAC-MD5
+//! and HMAC-SHA1 are legacy (RFC 8945 Section 6).
+
+use crate::config::{TsigAlgorithm, TsigConfig};
+use crate::netwatch::{self, LocalNetwork};
+use hickory_proto::rr::rdata::NULL;
+use hickory_proto::rr::{DNSClass, Name, RData, Record, RecordType};
+use hickory_proto::serialize::binary::{BinDecodable, BinDecoder, BinEncodable, BinEncoder};
+use hickory_server::authority::{MessageRequest, MessageResponse, MessageResponseBuilder};
+use hickory_server::server::{ResponseHandler, ResponseInfo};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use std::io;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+type TsigResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// TSIG error: the MAC did not verify
+pub const BADSIG: u16 = 16;
+/// TSIG error: unknown key or algorithm
+pub const BADKEY: u16 = 17;
+/// TSIG error: signed outside the allowed time window
+pub const BADTIME: u16 = 18;
+/// TSIG error: MAC truncated below the allowed length
+pub const BADTRUNC: u16 = 22;
+
+/// How long interface names in `require_from` keep the networks they were last mapped to
+const INTERFACE_REFRESH: Duration = Duration::from_secs(30);
+
+impl TsigAlgorithm {
+    /// Algorithm name as carried in TSIG records
+    pub fn name(self) -> &'static str {
+        match self {
+            TsigAlgorithm::HmacSha256 => "hmac-sha256.",
+            TsigAlgorithm::HmacSha384 => "hmac-sha384.",
+            TsigAlgorithm::HmacSha512 => "hmac-sha512.",
+        }
+    }
+
+    fn mac_len(self) -> usize {
+        match self {
+            TsigAlgorithm::HmacSha256 => 32,
+            TsigAlgorithm::HmacSha384 => 48,
+            TsigAlgorithm::HmacSha512 => 64,
+        }
+    }
+
+    pub(crate) fn mac(self, secret: &[u8], parts: &[&[u8]]) -> Vec<u8> {
+        match self {
+            TsigAlgorithm::HmacSha256 => hmac::<Sha256>(64, secret, parts),
+            TsigAlgorithm::HmacSha384 => hmac::<Sha384>(128, secret, parts),
+            TsigAlgorithm::HmacSha512 => hmac::<Sha512>(128, secret, parts),
+        }
+    }
+}
+
+/// HMAC (RFC 2104) over the concatenation of `parts`
+fn hmac<D: Digest>(block_size: usize, secret: &[u8], parts: &[&[u8]]) -> Vec<u8> {
+    let mut key = if secret.len() > block_size {
+        D::digest(secret).to_vec()
+    } else {
+        secret.to_vec()
+    };
+    key.resize(block_size, 0);
+
+    let mut inner = D::new();
+    inner.update(key.iter().map(|b| b ^ 0x36).collect::<Vec<u8>>());
+    for part in parts {
+        inner.update(part);
+    }
+    let mut outer = D::new();
+    outer.update(key.iter().map(|b| b ^ 0x5c).collect::<Vec<u8>>());
+    outer.update(inner.finalize());
+    outer.finalize().to_vec()
+}
+
+/// A configured key, its secret decoded
+#[derive(Debug)]
+struct Key {
+    name: Name,
+    algorithm: TsigAlgorithm,
+    secret: Vec<u8>,
+}
+
+/// Client networks that must sign, by address or by interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Required {
+    Network(LocalNetwork),
+    Interface(String),
+}
+
+/// Configured keys and the clients that must use them
+#[derive(Debug)]
+pub struct TsigKeyring {
+    keys: Vec<Arc<Key>>,
+    required: Vec<Required>,
+    /// Networks of the interfaces in `required`, and when they were read
+    interface_networks: Mutex<Option<(Instant, Vec<LocalNetwork>)>>,
+    fudge: u16,
+}
+
+impl TsigKeyring {
+    /// Keyring for `[tsig]`; fails on an undecodable secret or network
+    pub fn from_config(config: &TsigConfig) -> TsigResult<Self> {
+        let mut keys = Vec::new();
+        for key in &config.keys {
+            let mut name = Name::from_utf8(&key.name).map_err(|e| format!("TSIG key name {}: {}", key.name, e))?;
+            name.set_fqdn(true);
+            let secret = data_encoding::BASE64
+                .decode(key.secret.trim().as_bytes())
+                .map_err(|e| format!("TSIG key {}: secret is not base64: {}", key.name, e))?;
+            if secret.is_empty() {
+                return Err(format!("TSIG key {} has an empty secret", key.name).into());
+            }
+            keys.push(Arc::new(Key {
+                name: name.to_lowercase(),
+                algorithm: key.algorithm,
+                secret,
+            }));
+        }
+        let required = config
+            .require_from
+            .iter()
+            .map(|entry| parse_required(entry))
+            .collect::<TsigResult<Vec<Required>>>()?;
+        if keys.is_empty() && !required.is_empty() {
+            warn!("[tsig] require_from is set without keys: those clients cannot be answered");
+        }
+        Ok(Self {
+            keys,
+            required,
+            interface_networks: Mutex::new(None),
+            fudge: config.fudge_secs,
+        })
+    }
+
+    /// Whether unsigned queries from `client` are refused
+    pub fn requires_signature(&self, client: IpAddr) -> bool {
+        let mut interfaces = false;
+        for required in &self.required {
+            match required {
+                Required::Network(network) if network.contains(&client) => return true,
+                Required::Network(_) => {}
+                Required::Interface(_) => interfaces = true,
+            }
+        }
+        interfaces && self.interface_networks().iter().any(|network| network.contains(&client))
+    }
+
+    /// Networks of the `require_from` interfaces, re-read now and then as addresses change
+    fn interface_networks(&self) -> Vec<LocalNetwork> {
+        let mut cached = self.interface_networks.lock().unwrap();
+        if let Some((read, networks)) = cached.as_ref()
+            && read.elapsed() < INTERFACE_REFRESH
+        {
+            return networks.clone();
+        }
+        let networks: Vec<LocalNetwork> = match netwatch::interface_networks() {
+            Ok(interfaces) => interfaces
+                .into_iter()
+                .filter(|(name, _)| self.required.contains(&Required::Interface(name.clone())))
+                .map(|(_, network)| network)
+                .collect(),
+            Err(e) => {
+                debug!("Could not read interfaces for [tsig] require_from: {}", e);
+                Vec::new()
+            }
+        };
+        *cached = Some((Instant::now(), networks.clone()));
+        networks
+    }
+
+    /// Check the signature of `request`, if it has one
+    pub fn verify(&self, request: &MessageRequest) -> Verification {
+        self.verify_at(request, unix_time())
+    }
+
+    fn verify_at(&self, request: &MessageRequest, now: u64) -> Verification {
+        let Some((record, others)) = split_tsig(request) else {
+            return Verification::Unsigned;
+        };
+        let Some(tsig) = Tsig::parse(record) else {
+            debug!("Malformed TSIG record from a client");
+            return Verification::FormErr;
+        };
+        let rejected = |error, key| Verification::Rejected(self.signer(&tsig, key, error, now));
+
+        let Some(key) = self
+            .keys
+            .iter()
+            .find(|key| key.name == tsig.key_name && algorithm_matches(key.algorithm, &tsig.algorithm))
+        else {
+            debug!("TSIG key {} ({}) is not configured", tsig.key_name, tsig.algorithm);
+            return rejected(BADKEY, None);
+        };
+        if tsig.mac.len() > key.algorithm.mac_len() {
+            return Verification::FormErr;
+        }
+        let Some(bytes) = signed_request(request, others, tsig.original_id) else {
+            return Verification::FormErr;
+        };
+        let expected = key.algorithm.mac(&key.secret, &[&bytes, &tsig.variables()]);
+        if tsig.mac.is_empty() || !mac_matches(&expected[..tsig.mac.len()], &tsig.mac) {
+            debug!("TSIG signature with key {} did not verify", tsig.key_name);
+            return rejected(BADSIG, None);
+        }
+        // The window is the fudge the client signed with (RFC 8945 Section 5.2.3), up to the configured one
+        if now.abs_diff(tsig.time_signed) > u64::from(tsig.fudge.min(self.fudge)) {
+            debug!("TSIG time {} is {}s off", tsig.time_signed, now.abs_diff(tsig.time_signed));
+            return rejected(BADTIME, Some(key.clone()));
+        }
+        if tsig.mac.len() < key.algorithm.mac_len().div_ceil(2).max(10) {
+            return rejected(BADTRUNC, Some(key.clone()));
+        }
+        Verification::Verified(self.signer(&tsig, Some(key.clone()), 0, now))
+    }
+
+    fn signer(&self, request: &Tsig, key: Option<Arc<Key>>, error: u16, now: u64) -> Signer {
+        Signer {
+            key,
+            request: request.clone(),
+            error,
+            fudge: self.fudge,
+            now,
+            chained: false,
+        }
+    }
+}
+
+fn parse_required(entry: &str) -> TsigResult<Required> {
+    let entry = entry.trim();
+    if entry.contains('/') || entry.parse::<IpAddr>().is_ok() {
+        let network = entry.parse().map_err(|e| format!("[tsig] require_from: {}", e))?;
+        return Ok(Required::Network(network));
+    }
+    if entry.is_empty() {
+        return Err("[tsig] require_from: empty entry".into());
+    }
+    Ok(Required::Interface(entry.to_string()))
+}
+
+fn algorithm_matches(algorithm: TsigAlgorithm, name: &Name) -> bool {
+    name.to_ascii().trim_end_matches('.').eq_ignore_ascii_case(algorithm.name().trim_end_matches('.'))
+}
+
+/// Compare without returning early, so timing does not reveal how much of a MAC matched
+pub(crate) fn mac_matches(expected: &[u8], given: &[u8]) -> bool {
+    expected.len() == given.len() && expected.iter().zip(given).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// The TSIG record of `request` and the additional records before it. hickory
+/// leaves TSIG last in `additionals()`, unless its DNSSEC support is compiled
+/// in, which moves it to `sig0()`.
+fn split_tsig(request: &MessageRequest) -> Option<(&Record, &[Record])> {
+    if let Some(record) = request.sig0().last().filter(|r| r.record_type() == RecordType::TSIG) {
+        return Some((record, request.additionals()));
+    }
+    let (record, others) = request.additionals().split_last()?;
+    (record.record_type() == RecordType::TSIG).then_some((record, others))
+}
+
+/// The request as it was signed: original ID, the TSIG record left out
+fn signed_request(request: &MessageRequest, others: &[Record], original_id: u16) -> Option<Vec<u8>> {
+    let mut additionals = others.to_vec();
+    if let Some(edns) = request.edns() {
+        additionals.push(Record::from(edns));
+    }
+    let mut header = *request.header();
+    header.set_id(original_id);
+    let message = MessageResponseBuilder::from_message_request(request).build(
+        header,
+        request.answers().iter(),
+        request.name_servers().iter(),
+        std::iter::empty(),
+        additionals.iter(),
+    );
+    let mut bytes = Vec::new();
+    message.destructive_emit(&mut BinEncoder::new(&mut bytes)).ok()?;
+    Some(bytes)
+}
+
+/// Result of checking a query's signature
+#[derive(Debug)]
+pub enum Verification {
+    /// The query carries no TSIG record
+    Unsigned,
+    /// Verified: answer through [`SigningResponder`] with this signer
+    Verified(Signer),
+    /// Failed: answer NOTAUTH through [`SigningResponder`], which adds the TSIG error
+    Rejected(Signer),
+    /// The TSIG record is malformed
+    FormErr,
+}
+
+/// Fields of a TSIG record (RFC 8945 Section 4.2)
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Tsig {
+    key_name: Name,
+    algorithm: Name,
+    /// Seconds since the epoch, 48 bits on the wire
+    time_signed: u64,
+    fudge: u16,
+    mac: Vec<u8>,
+    original_id: u16,
+    error: u16,
+    other: Vec<u8>,
+}
+
+impl Tsig {
+    fn parse(record: &Record) -> Option<Self> {
+        if record.dns_class() != DNSClass::ANY {
+            return None;
+        }
+        // Unknown to hickory unless its DNSSEC support is compiled in
+        let rdata = match record.data() {
+            RData::Unknown { rdata, .. } => rdata.anything().to_vec(),
+            data => data.to_bytes().ok()?,
+        };
+        let mut decoder = BinDecoder::new(&rdata);
+        let algorithm = Name::read(&mut decoder).ok()?;
+        let time = decoder.read_slice(6).ok()?.unverified();
+        let time_signed = time.iter().fold(0u64, |acc, b| (acc << 8) | u64::from(*b));
+        let fudge = decoder.read_u16().ok()?.unverified();
+        let mac_len = decoder.read_u16().ok()?.unverified();
+        let mac = decoder.read_vec(mac_len as usize).ok()?.unverified();
+        let original_id = decoder.read_u16().ok()?.unverified();
+        let error = decoder.read_u16().ok()?.unverified();
+        let other_len = decoder.read_u16().ok()?.unverified();
+        let other = decoder.read_vec(other_len as usize).ok()?.unverified();
+        if !decoder.is_empty() {
+            return None;
+        }
+        Some(Self {
+            key_name: record.name().clone(),
+            algorithm,
+            time_signed,
+            fudge,
+            mac,
+            original_id,
+            error,
+            other,
+        })
+    }
+
+    fn record(&self) -> Record {
+        let mut rdata = canonical_name(&self.algorithm);
+        rdata.extend_from_slice(&self.time_signed.to_be_bytes()[2..]);
+        rdata.extend_from_slice(&self.fudge.to_be_bytes());
+        rdata.extend_from_slice(&(self.mac.len() as u16).to_be_bytes());
+        rdata.extend_from_slice(&self.mac);
+        rdata.extend_from_slice(&self.original_id.to_be_bytes());
+        rdata.extend_from_slice(&self.error.to_be_bytes());
+        rdata.extend_from_slice(&(self.other.len() as u16).to_be_bytes());
+        rdata.extend_from_slice(&self.other);
+        let data = RData::Unknown {
+            code: RecordType::TSIG,
+            rdata: NULL::with(rdata),
+        };
+        let mut record = Record::from_rdata(self.key_name.clone(), 0, data);
+        record.set_dns_class(DNSClass::ANY);
+        record
+    }
+
+    /// The TSIG variables appended to the message for the MAC (RFC 8945 Section 4.3.3)
+    fn variables(&self) -> Vec<u8> {
+        let mut variables = canonical_name(&self.key_name);
+        variables.extend_from_slice(&u16::from(DNSClass::ANY).to_be_bytes());
+        variables.extend_from_slice(&0u32.to_be_bytes());
+        variables.extend_from_slice(&canonical_name(&self.algorithm));
+        variables.extend_from_slice(&self.time_signed.to_be_bytes()[2..]);
+        variables.extend_from_slice(&self.fudge.to_be_bytes());
+        variables.extend_from_slice(&self.error.to_be_bytes());
+        variables.extend_from_slice(&(self.other.len() as u16).to_be_bytes());
+        variables.extend_from_slice(&self.other);
+        variables
+    }
+}
+
+/// Lowercase, uncompressed wire form of `name`
+fn canonical_name(name: &Name) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for label in name.to_lowercase().iter() {
+        bytes.push(label.len() as u8);
+        bytes.extend_from_slice(label);
+    }
+    bytes.push(0);
+    bytes
+}
+
+/// Signs the response to one verified, or rejected, request
+#[derive(Debug, Clone)]
+pub struct Signer {
+    /// None for BADKEY and BADSIG, whose responses carry an empty MAC
+    key: Option<Arc<Key>>,
+    request: Tsig,
+    error: u16,
+    fudge: u16,
+    now: u64,
+    /// A message was signed already: the next signature continues from its MAC
+    chained: bool,
+}
+
+impl Signer {
+    /// TSIG error the response reports; 0 after a successful verification
+    pub fn error(&self) -> u16 {
+        self.error
+    }
+
+    /// Bytes the TSIG record adds to the response, to keep free under a UDP size limit
+    pub fn record_len(&self) -> usize {
+        let mac_len = self.key.as_ref().map_or(0, |key| key.algorithm.mac_len());
+        let other_len = if self.error == BADTIME { 6 } else { 0 };
+        canonical_name(&self.request.key_name).len() + 10 + canonical_name(&self.request.algorithm).len() + 16 + mac_len + other_len
+    }
+
+    /// The TSIG record for `response`, the message encoded without it. Messages
+    /// after the first of a multi-message answer (a zone transfer) are signed over
+    /// the previous MAC and the timers only (RFC 8945 Section 5.3.1).
+    fn sign(&mut self, response: &[u8]) -> Record {
+        let badtime = self.error == BADTIME;
+        let mut tsig = Tsig {
+            key_name: self.request.key_name.clone(),
+            algorithm: self.request.algorithm.clone(),
+            // A BADTIME answer keeps the client's time and tells it the proxy's
+            time_signed: if badtime { self.request.time_signed } else { self.now },
+            fudge: self.fudge,
+            mac: Vec::new(),
+            original_id: self.request.original_id,
+            error: self.error,
+            other: if badtime { self.now.to_be_bytes()[2..].to_vec() } else { Vec::new() },
+        };
+        if let Some(key) = &self.key {
+            let mut request_mac = (self.request.mac.len() as u16).to_be_bytes().to_vec();
+            request_mac.extend_from_slice(&self.request.mac);
+            let variables = if self.chained {
+                let mut timers = tsig.time_signed.to_be_bytes()[2..].to_vec();
+                timers.extend_from_slice(&tsig.fudge.to_be_bytes());
+                timers
+            } else {
+                tsig.variables()
+            };
+            tsig.mac = key.algorithm.mac(&key.secret, &[&request_mac, response, &variables]);
+            self.request.mac = tsig.mac.clone();
+            self.chained = true;
+        }
+        tsig.record()
+    }
+}
+
+/// Response handler adding a TSIG record to what it sends
+#[derive(Clone)]
+pub struct SigningResponder<R> {
+    inner: R,
+    signer: Signer,
+}
+
+impl<R> SigningResponder<R> {
+    pub fn new(inner: R, signer: Signer) -> Self {
+        Self { inner, signer }
+    }
+}
+
+#[async_trait::async_trait]
+impl<R: ResponseHandler> ResponseHandler for SigningResponder<R> {
+    async fn send_response<'a>(
+        &mut self,
+        response: MessageResponse<
+            '_,
+            'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+        >,
+    ) -> io::Result<ResponseInfo> {
+        // The MAC covers the encoded message, so encode it, sign it, and send
+        // the same records again with the OPT record and signature appended
+        let mut bytes = Vec::new();
+        response
+            .destructive_emit(&mut BinEncoder::new(&mut bytes))
+            .map_err(io::Error::other)?;
+        let unsigned = MessageRequest::from_bytes(&bytes).map_err(io::Error::other)?;
+        let mut additionals = unsigned.additionals().to_vec();
+        if let Some(edns) = unsigned.edns() {
+            additionals.push(Record::from(edns));
+        }
+        additionals.push(self.signer.sign(&bytes));
+        let signed = MessageResponseBuilder::from_message_request(&unsigned).build(
+            *unsigned.header(),
+            unsigned.answers().iter(),
+            unsigned.name_servers().iter(),
+            std::iter::empty(),
+            additionals.iter(),
+        );
+        self.inner.send_response(signed).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TsigKey;
+    use hickory_proto::op::{Edns, Message, MessageType, OpCode, Query};
+
+    const SECRET: &str = "c2VjcmV0LWtleS1mb3ItdGVzdHM=";
+
+    fn keyring(require_from: &[&str]) -> TsigKeyring {
+        TsigKeyring::from_config(&TsigConfig {
+            keys: vec![TsigKey {
+                name: "resolver1.example.".to_string(),
+                algorithm: TsigAlgorithm::HmacSha256,
+                secret: SECRET.to_string(),
+            }],
+            require_from: require_from.iter().map(|s| s.to_string()).collect(),
+            fudge_secs: 300,
+        })
+        .unwrap()
+    }
+
+    /// A query signed the way a resolver signs it: MAC over the message and the TSIG variables
+    fn signed_query(key_name: &str, secret: &[u8], time_signed: u64) -> Vec<u8> {
+        signed_query_with_fudge(key_name, secret, time_signed, 300)
+    }
+
+    fn signed_query_with_fudge(key_name: &str, secret: &[u8], time_signed: u64, fudge: u16) -> Vec<u8> {
+        let mut message = Message::new();
+        message.set_id(4242).set_message_type(MessageType::Query).set_op_code(OpCode::Query);
+        message.add_query(Query::query(Name::from_ascii("Printer.mdns.home.arpa.").unwrap(), RecordType::A));
+        message.set_edns(Edns::new());
+        let unsigned = message.to_bytes().unwrap();
+
+        let mut tsig = Tsig {
+            key_name: Name::from_ascii(key_name).unwrap(),
+            algorithm: Name::from_ascii("hmac-sha256.").unwrap(),
+            time_signed,
+            fudge,
+            mac: Vec::new(),
+            original_id: 4242,
+            error: 0,
+            other: Vec::new(),
+        };
+        tsig.mac = TsigAlgorithm::HmacSha256.mac(secret, &[&unsigned, &tsig.variables()]);
+        let mut bytes = unsigned;
+        bytes.extend_from_slice(&tsig.record().to_bytes().unwrap());
+        // One more additional record: the TSIG
+        bytes[11] += 1;
+        bytes
+    }
+
+    /// `dig +adflag -y hmac-sha256:resolver1.example.:<SECRET> printer.mdns.home.arpa. A`
+    /// laid out as dig 9.18 sends it: RD and AD set, an OPT record with a client
+    /// COOKIE, TSIG signed at 1700000000 with fudge 300. The MAC was computed
+    /// over these bytes with Python's hmac module, not with this module's code.
+    const DIG_QUERY: &[&str] = &[
+        "5b1c01200001000000000002077072696e746572046d646e7304686f6d650461",
+        "727061000001000100002904d000000000000c000a00088f3a1c0d5e6b7a2909",
+        "7265736f6c76657231076578616d706c650000fa00ff00000000003d0b686d61",
+        "632d7368613235360000006553f100012c002032350f11ddbe2658944a2c6792",
+        "5d196ab9caff2924035ac3c13259eaf51f6b805b1c00000000",
+    ];
+
+    fn secret() -> Vec<u8> {
+        data_encoding::BASE64.decode(SECRET.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_hmac_sha256_vector() {
+        // RFC 4231 test case 2
+        let mac = hmac::<Sha256>(64, b"Jefe", &[b"what do ya ", b"want for nothing?"]);
+        assert_eq!(
+            data_encoding::HEXLOWER.encode(&mac),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_verify_signed_queries() {
+        let keyring = keyring(&[]);
+        let now = 1_700_000_000;
+        let request = |bytes: Vec<u8>| MessageRequest::from_bytes(&bytes).unwrap();
+
+        let Verification::Verified(signer) = keyring.verify_at(&request(signed_query("resolver1.example.", &secret(), now)), now + 10)
+        else {
+            panic!("signature did not verify");
+        };
+        assert_eq!(signer.error(), 0);
+        // Key names compare case-insensitively
+        assert!(matches!(
+            keyring.verify_at(&request(signed_query("RESOLVER1.example.", &secret(), now)), now),
+            Verification::Verified(_)
+        ));
+
+        let outcome = |bytes| match keyring.verify_at(&request(bytes), now) {
+            Verification::Rejected(signer) => signer.error(),
+            other => panic!("not rejected: {:?}", other),
+        };
+        assert_eq!(outcome(signed_query("other.example.", &secret(), now)), BADKEY);
+        assert_eq!(outcome(signed_query("resolver1.example.", b"wrong", now)), BADSIG);
+        assert_eq!(outcome(signed_query("resolver1.example.", &secret(), now - 301)), BADTIME);
+
+        let mut message = Message::new();
+        message.add_query(Query::query(Name::from_ascii("printer.mdns.home.arpa.").unwrap(), RecordType::A));
+        assert!(matches!(keyring.verify_at(&request(message.to_bytes().unwrap()), now), Verification::Unsigned));
+    }
+
+    #[test]
+    fn test_time_window_is_the_request_fudge() {
+        let keyring = keyring(&[]);
+        let now = 1_700_000_000;
+        let verify = |time_signed, fudge| {
+            let bytes = signed_query_with_fudge("resolver1.example.", &secret(), time_signed, fudge);
+            match keyring.verify_at(&MessageRequest::from_bytes(&bytes).unwrap(), now) {
+                Verification::Verified(_) => 0,
+                Verification::Rejected(signer) => signer.error(),
+                other => panic!("unexpected outcome: {:?}", other),
+            }
+        };
+        // A client allowing less than the configured 300s gets its own window
+        assert_eq!(verify(now - 60, 60), 0);
+        assert_eq!(verify(now - 100, 60), BADTIME);
+        // A wider one is capped at the configured fudge
+        assert_eq!(verify(now + 250, 600), 0);
+        assert_eq!(verify(now + 400, 600), BADTIME);
+    }
+
+    #[test]
+    fn test_verify_dig_query() {
+        let wire = data_encoding::HEXLOWER.decode(DIG_QUERY.concat().as_bytes()).unwrap();
+        let request = MessageRequest::from_bytes(&wire).unwrap();
+        assert!(request.header().authentic_data() && request.header().recursion_desired());
+        assert_eq!(request.edns().map(|edns| edns.max_payload()), Some(1232));
+
+        // Where hickory puts the TSIG record depends on its dnssec feature
+        let (record, others) = split_tsig(&request).unwrap();
+        assert_eq!(record.record_type(), RecordType::TSIG);
+        assert!(others.is_empty());
+        assert_eq!(request.sig0().len() + request.additionals().len(), 1);
+
+        // Re-encoded, the query is what dig signed
+        let signed = signed_request(&request, others, 0x5b1c).unwrap();
+        let tsig_len = record.to_bytes().unwrap().len();
+        let mut unsigned = wire[..wire.len() - tsig_len].to_vec();
+        unsigned[11] -= 1;
+        assert_eq!(signed, unsigned);
+
+        let now = 1_700_000_000;
+        assert!(matches!(keyring(&[]).verify_at(&request, now + 5), Verification::Verified(_)));
+
+        let mut tampered = wire.clone();
+        // Clear the AD bit, which the MAC covers
+        tampered[3] &= !0x20;
+        let request = MessageRequest::from_bytes(&tampered).unwrap();
+        assert!(matches!(keyring(&[]).verify_at(&request, now), Verification::Rejected(ref signer) if signer.error() == BADSIG));
+    }
+
+    #[test]
+    fn test_response_signature_covers_request_mac() {
+        let keyring = keyring(&[]);
+        let now = 1_700_000_000;
+        let query = MessageRequest::from_bytes(&signed_query("resolver1.example.", &secret(), now)).unwrap();
+        let Verification::Verified(mut signer) = keyring.verify_at(&query, now) else {
+            panic!("signature did not verify");
+        };
+        let request_mac = signer.request.mac.clone();
+        let response = b"response bytes";
+        let record = signer.sign(response);
+        assert_eq!((record.record_type(), record.dns_class(), record.ttl()), (RecordType::TSIG, DNSClass::ANY, 0));
+        let tsig = Tsig::parse(&record).unwrap();
+        assert_eq!(record.to_bytes().unwrap().len(), signer.record_len());
+
+        let prior = |mac: &[u8]| [(mac.len() as u16).to_be_bytes().as_slice(), mac].concat();
+        let expected = TsigAlgorithm::HmacSha256.mac(&secret(), &[&prior(&request_mac), response, &tsig.variables()]);
+        assert_eq!(tsig.mac, expected);
+        assert_eq!((tsig.original_id, tsig.time_signed), (4242, now));
+
+        // The next message of a transfer is signed over the previous MAC and the timers
+        let next = Tsig::parse(&signer.sign(b"more")).unwrap();
+        let timers = [&now.to_be_bytes()[2..], &300u16.to_be_bytes()].concat();
+        let expected = TsigAlgorithm::HmacSha256.mac(&secret(), &[&prior(&tsig.mac), b"more", &timers]);
+        assert_eq!(next.mac, expected);
+    }
+
+    #[test]
+    fn test_required_clients() {
+        let keyring = keyring(&["10.20.0.0/16", "192.168.9.9", "no-such-interface0"]);
+        assert!(keyring.requires_signature("10.20.3.4".parse().unwrap()));
+        assert!(keyring.requires_signature("192.168.9.9".parse().unwrap()));
+        assert!(!keyring.requires_signature("192.168.9.10".parse().unwrap()));
+        assert!(!keyring.requires_signature("127.0.0.1".parse().unwrap()));
+
+        let config = |require_from: &str, secret: &str| TsigConfig {
+            keys: vec![TsigKey {
+                name: "k.".to_string(),
+                algorithm: TsigAlgorithm::HmacSha512,
+                secret: secret.to_string(),
+            }],
+            require_from: vec![require_from.to_string()],
+            fudge_secs: 300,
+        };
+        assert!(TsigKeyring::from_config(&config("10.0.0.0/33", SECRET)).is_err());
+        assert!(TsigKeyring::from_config(&config("eth0/8", SECRET)).is_err());
+        assert!(TsigKeyring::from_config(&config("eth0", "not base64!")).is_err());
+    }
+}
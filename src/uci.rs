@@ -0,0 +1,373 @@
+//! OpenWrt UCI and flat `key=value` configuration formats
+//!
+//! Both formats are mapped onto the same table layout as the TOML file and
+//! then deserialized into [`Config`]. UCI values are untyped strings, so the
+//! serialized default configuration is used as a schema to decide whether a
+//! value is a boolean, integer, list or string.
+//!
+//! UCI (`/etc/config/mdns-dns-proxy`):
+//!
+//! ```text
+//! config server 'server'
+//!     option bind_address '0.0.0.0'
+//!     option port '53'
+//!     list fallback_ports '5335'
+//!
+//! config cache 'cache'
+//!     option enabled '1'
+//!
+//! config strategy '_ipp._tcp'
+//!     list prefetch 'SRV'
+//! ```
+//!
+//! Flat: one `section.key=value` per line, lists comma-separated, e.g.
+//! `server.port=53` or `strategies._ipp._tcp.prefetch=SRV,TXT`.
+
+use crate::config::{Config, ServiceStrategy};
+use toml::{Table, Value};
+
+type ParseResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Section type used for per-service-type strategies in UCI files
+const STRATEGY_SECTION: &str = "strategy";
+
+/// Whether `contents` looks like a UCI file (first statement is `config` or `package`)
+pub fn looks_like_uci(contents: &str) -> bool {
+    contents
+        .lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty() && !l.starts_with('#'))
+        .is_some_and(|l| l.starts_with("config ") || l.starts_with("package "))
+}
+
+/// Parse a UCI configuration file
+pub fn parse_uci(contents: &str) -> ParseResult<Config> {
+    let schema = Schema::new()?;
+    let mut root = Table::new();
+    // (table path, strategy name) of the section currently being filled
+    let mut section: Option<(String, Option<String>)> = None;
+
+    for (lineno, line) in contents.lines().enumerate() {
+        let words = split_words(line).map_err(|e| format!("line {}: {}", lineno + 1, e))?;
+        let Some(keyword) = words.first() else {
+            continue;
+        };
+
+        match keyword.as_str() {
+            "package" => {}
+            "config" => {
+                let Some(ty) = words.get(1) else {
+                    return Err(format!("line {}: config without section type", lineno + 1).into());
+                };
+                section = if ty == STRATEGY_SECTION {
+                    let Some(name) = words.get(2) else {
+                        return Err(format!("line {}: strategy section needs a service type name", lineno + 1).into());
+                    };
+                    Some(("strategies".to_string(), Some(name.clone())))
+                } else {
+                    Some((ty.clone(), None))
+                };
+            }
+            "option" | "list" => {
+                let (Some(key), Some(value)) = (words.get(1), words.get(2)) else {
+                    return Err(format!("line {}: {} needs a name and a value", lineno + 1, keyword).into());
+                };
+                let Some((table, name)) = &section else {
+                    return Err(format!("line {}: {} outside of a config section", lineno + 1, keyword).into());
+                };
+                let values = if keyword == "list" {
+                    vec![value.clone()]
+                } else {
+                    value.split_whitespace().map(str::to_string).collect()
+                };
+                schema
+                    .set(&mut root, table, name.as_deref(), key, values, keyword == "list")
+                    .map_err(|e| format!("line {}: {}", lineno + 1, e))?;
+            }
+            other => return Err(format!("line {}: unknown keyword '{}'", lineno + 1, other).into()),
+        }
+    }
+
+    Ok(Value::Table(root).try_into()?)
+}
+
+/// Parse a flat `section.key=value` file
+pub fn parse_flat(contents: &str) -> ParseResult<Config> {
+    let schema = Schema::new()?;
+    let mut root = Table::new();
+
+    for (lineno, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((path, value)) = line.split_once('=') else {
+            return Err(format!("line {}: expected section.key=value", lineno + 1).into());
+        };
+        let path = path.trim();
+        let value = unquote(value.trim());
+
+        let (table, name, key) = if let Some(rest) = path.strip_prefix("strategies.") {
+            let Some((name, key)) = rest.rsplit_once('.') else {
+                return Err(format!("line {}: expected strategies.<type>.<key>", lineno + 1).into());
+            };
+            ("strategies", Some(name), key)
+        } else {
+            let Some((table, key)) = path.split_once('.') else {
+                return Err(format!("line {}: key '{}' has no section", lineno + 1, path).into());
+            };
+            (table, None, key)
+        };
+
+        let values = value
+            .split(',')
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .collect();
+        schema
+            .set(&mut root, table, name, key, values, false)
+            .map_err(|e| format!("line {}: {}", lineno + 1, e))?;
+    }
+
+    Ok(Value::Table(root).try_into()?)
+}
+
+/// Serialized defaults used to type untyped string values
+struct Schema {
+    config: Table,
+    strategy: Table,
+}
+
+impl Schema {
+    fn new() -> ParseResult<Self> {
+        Ok(Self {
+            config: Table::try_from(Config::default())?,
+            strategy: Table::try_from(ServiceStrategy::default())?,
+        })
+    }
+
+    /// Store `values` under `[table] key` (or `[strategies."name"] key`), typed per the schema
+    fn set(
+        &self,
+        root: &mut Table,
+        table: &str,
+        name: Option<&str>,
+        key: &str,
+        values: Vec<String>,
+        append: bool,
+    ) -> ParseResult<()> {
+        let expected = match name {
+            Some(_) => self.strategy.get(key),
+            None => self
+                .config
+                .get(table)
+                .and_then(Value::as_table)
+                .and_then(|t| t.get(key)),
+        };
+
+        let target = root
+            .entry(table)
+            .or_insert_with(|| Value::Table(Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| format!("'{}' is not a section", table))?;
+        let target = match name {
+            Some(name) => target
+                .entry(name)
+                .or_insert_with(|| Value::Table(Table::new()))
+                .as_table_mut()
+                .ok_or_else(|| format!("'{}' is not a section", name))?,
+            None => target,
+        };
+
+        let value = match expected {
+            Some(Value::Array(_)) => Value::Array(values.iter().map(|v| guess(v)).collect()),
+            Some(Value::Boolean(_)) => Value::Boolean(parse_bool(&single(key, &values)?)?),
+            Some(Value::Integer(_)) => {
+                let raw = single(key, &values)?;
+                match raw.parse() {
+                    Ok(n) => Value::Integer(n),
+                    // Durations with units ("2s") are converted when the config is deserialized
+                    Err(_) if crate::duration::parse(&raw).is_ok() => Value::String(raw),
+                    Err(_) => return Err(format!("{} must be an integer, got '{}'", key, raw).into()),
+                }
+            }
+            Some(Value::String(_)) => Value::String(values.join(" ")),
+            // Unset optional settings and unknown keys: infer from the text
+            _ if append || values.len() > 1 => Value::Array(values.iter().map(|v| guess(v)).collect()),
+            _ => guess(&single(key, &values)?),
+        };
+
+        match (target.get_mut(key), value) {
+            (Some(Value::Array(existing)), Value::Array(more)) if append => existing.extend(more),
+            (_, value) => {
+                target.insert(key.to_string(), value);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn single(key: &str, values: &[String]) -> ParseResult<String> {
+    match values {
+        [value] => Ok(value.clone()),
+        _ => Err(format!("{} takes a single value", key).into()),
+    }
+}
+
+/// UCI boolean spellings
+fn parse_bool(value: &str) -> ParseResult<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" | "enabled" => Ok(true),
+        "0" | "false" | "no" | "off" | "disabled" => Ok(false),
+        _ => Err(format!("'{}' is not a boolean", value).into()),
+    }
+}
+
+/// Best-effort typing for values the schema has no default for
+fn guess(value: &str) -> Value {
+    if let Ok(i) = value.parse::<i64>() {
+        Value::Integer(i)
+    } else if let Ok(b) = value.parse::<bool>() {
+        Value::Boolean(b)
+    } else {
+        Value::String(value.to_string())
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['\'', '"'] {
+        if let Some(inner) = value.strip_prefix(quote).and_then(|v| v.strip_suffix(quote)) {
+            return inner;
+        }
+    }
+    value
+}
+
+/// Split a UCI line into words, honouring single/double quotes and `#` comments
+fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '#' {
+            break;
+        } else if c == '\'' || c == '"' {
+            chars.next();
+            let mut word = String::new();
+            loop {
+                match chars.next() {
+                    Some(ch) if ch == c => break,
+                    Some(ch) => word.push(ch),
+                    None => return Err("unterminated quote".to_string()),
+                }
+            }
+            words.push(word);
+        } else {
+            let mut word = String::new();
+            while let Some(&ch) = chars.peek() {
+                if ch.is_whitespace() {
+                    break;
+                }
+                word.push(ch);
+                chars.next();
+            }
+            words.push(word);
+        }
+    }
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ServiceRecordKind;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    #[test]
+    fn test_parse_uci() {
+        let contents = r#"
+package mdns-dns-proxy
+
+# Serve the whole LAN
+config server 'server'
+	option bind_address '0.0.0.0'
+	option port '53'
+	list fallback_ports '5335'
+	list fallback_ports "5336"
+	option discovery_domain 'lan.home.arpa.'
+
+config cache 'cache'
+	option enabled '0'
+	option ttl_seconds '1m'
+
+config strategy '_ipp._tcp'
+	option timeout_ms '4000'
+	list prefetch 'SRV'
+	list prefetch 'TXT'
+"#;
+        assert!(looks_like_uci(contents));
+        let config = parse_uci(contents).unwrap();
+
+        assert_eq!(config.server.bind_address, vec![IpAddr::V4(Ipv4Addr::UNSPECIFIED)]);
+        assert_eq!(config.server.port, 53);
+        assert_eq!(config.server.fallback_ports, vec![5335, 5336]);
+        assert_eq!(config.server.discovery_domain, "lan.home.arpa.");
+        assert!(!config.cache.enabled);
+        assert_eq!(config.cache.ttl_seconds, 60);
+
+        let strategy = &config.strategies["_ipp._tcp"];
+        assert_eq!(strategy.timeout_ms, Some(4000));
+        assert_eq!(strategy.prefetch, vec![ServiceRecordKind::Srv, ServiceRecordKind::Txt]);
+    }
+
+    #[test]
+    fn test_parse_uci_option_list_and_optional_values() {
+        let contents = "config server\n\toption fallback_ports '5335 5336'\n\toption worker_threads '2'\n";
+        let config = parse_uci(contents).unwrap();
+        assert_eq!(config.server.fallback_ports, vec![5335, 5336]);
+        assert_eq!(config.server.worker_threads, std::num::NonZeroUsize::new(2));
+    }
+
+    #[test]
+    fn test_parse_uci_errors() {
+        assert!(parse_uci("option port '53'").is_err());
+        assert!(parse_uci("config server\n\toption port 'fifty'").is_err());
+        assert!(parse_uci("config cache\n\toption enabled 'maybe'").is_err());
+        assert!(parse_uci("config server\n\toption bind_address '0.0.0.0").is_err());
+        assert!(parse_uci("config strategy\n\tlist prefetch 'SRV'").is_err());
+    }
+
+    #[test]
+    fn test_parse_flat() {
+        let contents = "\
+# router settings
+server.bind_address=192.168.1.1
+server.port=53
+server.fallback_ports=5335,5336
+cache.enabled=no
+logging.level=\"debug\"
+strategies._ipp._tcp.prefetch=SRV,TXT
+";
+        assert!(!looks_like_uci(contents));
+        let config = parse_flat(contents).unwrap();
+
+        assert_eq!(config.server.bind_address, vec![IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1))]);
+        assert_eq!(config.server.port, 53);
+        assert_eq!(config.server.fallback_ports, vec![5335, 5336]);
+        assert!(!config.cache.enabled);
+        assert_eq!(config.logging.level, "debug");
+        assert_eq!(
+            config.strategies["_ipp._tcp"].prefetch,
+            vec![ServiceRecordKind::Srv, ServiceRecordKind::Txt]
+        );
+    }
+
+    #[test]
+    fn test_parse_flat_errors() {
+        assert!(parse_flat("port=53").is_err());
+        assert!(parse_flat("server.port").is_err());
+        assert!(parse_flat("server.port=53,54").is_err());
+    }
+}
@@ -0,0 +1,188 @@
+//! Versioned on-disk formats
+//!
+//! Every JSON document the proxy writes for later reading starts with a header
+//! naming the format and its `MAJOR.MINOR` version:
+//!
+//! ```json
+//! {"format":"mdns-dns-proxy-snapshot","version":"2.0", ...}
+//! ```
+//!
+//! A minor bump only adds fields, so a document from a newer minor version is
+//! read with a warning that the additions are ignored. A major bump changes the
+//! meaning or layout of existing fields: older documents are migrated one major
+//! version at a time before being deserialized, and documents from a newer major
+//! version are refused rather than misread. Documents from before headers
+//! existed carry an integer `version` and no `format`; `N` is read as `N.0`.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use std::fmt;
+use tracing::warn;
+
+type VersionedResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Rewrites a document of major version `N` into major version `N + 1` in place
+pub type Migration = fn(&mut serde_json::Map<String, Value>) -> VersionedResult<()>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FormatVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl FormatVersion {
+    pub const fn new(major: u32, minor: u32) -> Self {
+        Self { major, minor }
+    }
+
+    /// `"2.1"`, or a bare integer as written before versions had a minor part
+    fn from_value(value: &Value) -> VersionedResult<Self> {
+        match value {
+            Value::Number(n) => {
+                let major = n.as_u64().and_then(|n| u32::try_from(n).ok()).ok_or("version is not a whole number")?;
+                Ok(Self::new(major, 0))
+            }
+            Value::String(s) => s.parse(),
+            _ => Err("version is neither a number nor a string".into()),
+        }
+    }
+}
+
+impl fmt::Display for FormatVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+impl std::str::FromStr for FormatVersion {
+    type Err = Box<dyn std::error::Error + Send + Sync>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (major, minor) = s.split_once('.').ok_or_else(|| format!("version {:?} is not MAJOR.MINOR", s))?;
+        Ok(Self::new(major.parse()?, minor.parse()?))
+    }
+}
+
+impl Serialize for FormatVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for FormatVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        Self::from_value(&value).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Description of one document format
+pub struct Format {
+    /// Value of the `format` header field
+    pub name: &'static str,
+    /// Version this build writes
+    pub current: FormatVersion,
+    /// `migrations[i]` turns major version `i + 1` into `i + 2`
+    pub migrations: &'static [Migration],
+}
+
+impl Format {
+    /// Check the header, migrate older documents and deserialize
+    pub fn read<T: DeserializeOwned>(&self, json: &str) -> VersionedResult<T> {
+        let mut document: serde_json::Map<String, Value> = serde_json::from_str(json)?;
+        match document.get("format") {
+            Some(Value::String(name)) if name == self.name => {}
+            Some(other) => return Err(format!("not a {} document (format {})", self.name, other).into()),
+            // Only documents written before headers existed lack the field
+            None => {}
+        }
+        let mut version = FormatVersion::from_value(document.get("version").ok_or("version header missing")?)?;
+        if version.major > self.current.major {
+            return Err(format!(
+                "{} version {} is newer than this build supports ({}); upgrade to read it",
+                self.name, version, self.current
+            )
+            .into());
+        }
+        if version.major == 0 {
+            return Err(format!("{} version {} is not valid", self.name, version).into());
+        }
+        while version.major < self.current.major {
+            let migrate = self
+                .migrations
+                .get(version.major as usize - 1)
+                .ok_or_else(|| format!("no migration from {} version {}", self.name, version))?;
+            migrate(&mut document).map_err(|e| format!("migrating {} version {}: {}", self.name, version, e))?;
+            version = FormatVersion::new(version.major + 1, 0);
+        }
+        if version.minor > self.current.minor {
+            warn!(
+                "{} version {}.{} is newer than this build ({}); fields it added are ignored",
+                self.name, version.major, version.minor, self.current
+            );
+        }
+        document.insert("format".to_string(), Value::String(self.name.to_string()));
+        document.insert("version".to_string(), Value::String(self.current.to_string()));
+        Ok(serde_json::from_value(Value::Object(document))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Doc {
+        format: String,
+        version: FormatVersion,
+        hosts: Vec<String>,
+    }
+
+    /// Version 1 had a single `host`; version 2 has a list
+    fn v1_to_v2(document: &mut serde_json::Map<String, Value>) -> VersionedResult<()> {
+        let host = document.remove("host").ok_or("host missing")?;
+        document.insert("hosts".to_string(), Value::Array(vec![host]));
+        Ok(())
+    }
+
+    const FORMAT: Format = Format {
+        name: "test-doc",
+        current: FormatVersion::new(2, 1),
+        migrations: &[v1_to_v2],
+    };
+
+    #[test]
+    fn test_current_and_newer_minor() {
+        let doc: Doc = FORMAT.read(r#"{"format":"test-doc","version":"2.1","hosts":["a"]}"#).unwrap();
+        assert_eq!(doc.hosts, vec!["a"]);
+        let doc: Doc = FORMAT.read(r#"{"format":"test-doc","version":"2.7","hosts":["a"],"added":1}"#).unwrap();
+        assert_eq!(doc.version, FormatVersion::new(2, 1));
+    }
+
+    #[test]
+    fn test_legacy_document_migrated() {
+        let doc: Doc = FORMAT.read(r#"{"version":1,"host":"a"}"#).unwrap();
+        assert_eq!(doc.format, "test-doc");
+        assert_eq!(doc.hosts, vec!["a"]);
+        let err = FORMAT.read::<Doc>(r#"{"version":"1.0"}"#).unwrap_err();
+        assert!(err.to_string().contains("migrating test-doc version 1.0: host missing"));
+    }
+
+    #[test]
+    fn test_newer_major_and_wrong_format_refused() {
+        let err = FORMAT.read::<Doc>(r#"{"format":"test-doc","version":"3.0","hosts":[]}"#).unwrap_err();
+        assert!(err.to_string().contains("newer than this build supports (2.1)"));
+        assert!(FORMAT.read::<Doc>(r#"{"format":"other","version":"2.0","hosts":[]}"#).is_err());
+        assert!(FORMAT.read::<Doc>(r#"{"format":"test-doc","hosts":[]}"#).is_err());
+        assert!(FORMAT.read::<Doc>(r#"{"version":0,"hosts":[]}"#).is_err());
+    }
+
+    #[test]
+    fn test_version_round_trip() {
+        let version: FormatVersion = "2.10".parse().unwrap();
+        assert_eq!(version, FormatVersion::new(2, 10));
+        assert_eq!(serde_json::to_string(&version).unwrap(), "\"2.10\"");
+        assert!("2".parse::<FormatVersion>().is_err());
+    }
+}
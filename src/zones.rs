@@ -0,0 +1,121 @@
+//! Discovery domains served by the proxy
+//!
+//! Every zone is backed by the same link (`.local.`); the registry only decides
+//! which query names are ours and which apex a name belongs to. Zones can be
+//! added and removed at runtime through the control socket.
+
+use hickory_proto::rr::Name;
+use std::sync::RwLock;
+
+type ZoneResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Set of discovery domains currently served
+#[derive(Debug)]
+pub struct ZoneRegistry {
+    zones: RwLock<Vec<Name>>,
+}
+
+impl ZoneRegistry {
+    /// Create a registry serving the given discovery domains
+    pub fn new<S: AsRef<str>>(domains: &[S]) -> ZoneResult<Self> {
+        let mut zones = Vec::new();
+        for domain in domains {
+            let apex = parse_domain(domain.as_ref())?;
+            if !zones.contains(&apex) {
+                zones.push(apex);
+            }
+        }
+        Ok(Self {
+            zones: RwLock::new(zones),
+        })
+    }
+
+    /// Apexes of all served zones
+    pub fn list(&self) -> Vec<Name> {
+        self.zones.read().unwrap().clone()
+    }
+
+    /// Start serving `domain`. Returns false if it was already served.
+    pub fn add(&self, domain: &str) -> ZoneResult<bool> {
+        let apex = parse_domain(domain)?;
+        let mut zones = self.zones.write().unwrap();
+        if zones.contains(&apex) {
+            return Ok(false);
+        }
+        zones.push(apex);
+        Ok(true)
+    }
+
+    /// Stop serving `domain`. Returns the removed apex, or None if it was not served.
+    pub fn remove(&self, domain: &str) -> ZoneResult<Option<Name>> {
+        let apex = parse_domain(domain)?;
+        let mut zones = self.zones.write().unwrap();
+        let Some(idx) = zones.iter().position(|z| *z == apex) else {
+            return Ok(None);
+        };
+        Ok(Some(zones.remove(idx)))
+    }
+
+    /// Apex of the most specific served zone containing `name`
+    pub fn zone_for(&self, name: &Name) -> Option<Name> {
+        self.zones
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|apex| apex.zone_of(name))
+            .max_by_key(|apex| apex.num_labels())
+            .cloned()
+    }
+}
+
+/// Parse a discovery domain into a fully qualified, lowercased apex
+fn parse_domain(domain: &str) -> ZoneResult<Name> {
+    let trimmed = domain.trim().trim_matches('.');
+    if trimmed.is_empty() {
+        return Err("discovery domain must not be empty".into());
+    }
+    let mut apex = Name::from_utf8(trimmed)?.to_lowercase();
+    apex.set_fqdn(true);
+    if apex == Name::from_ascii("local.")? {
+        return Err("local. is the mDNS link domain and cannot be served".into());
+    }
+    Ok(apex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_remove_zone() {
+        let zones = ZoneRegistry::new(&["mdns.home.arpa."]).unwrap();
+        assert!(zones.add("VLAN20.home.arpa").unwrap());
+        assert!(!zones.add("vlan20.home.arpa.").unwrap());
+        assert_eq!(zones.list().len(), 2);
+
+        let removed = zones.remove("vlan20.home.arpa.").unwrap();
+        assert_eq!(removed, Some(Name::from_utf8("vlan20.home.arpa.").unwrap()));
+        assert_eq!(zones.remove("vlan20.home.arpa.").unwrap(), None);
+        assert_eq!(zones.list().len(), 1);
+    }
+
+    #[test]
+    fn test_zone_for_prefers_most_specific() {
+        let zones = ZoneRegistry::new(&["home.arpa.", "lab.home.arpa."]).unwrap();
+        let name = Name::from_utf8("printer.lab.home.arpa.").unwrap();
+        assert_eq!(zones.zone_for(&name), Some(Name::from_utf8("lab.home.arpa.").unwrap()));
+
+        let name = Name::from_utf8("printer.home.arpa.").unwrap();
+        assert_eq!(zones.zone_for(&name), Some(Name::from_utf8("home.arpa.").unwrap()));
+
+        assert_eq!(zones.zone_for(&Name::from_utf8("example.com.").unwrap()), None);
+    }
+
+    #[test]
+    fn test_rejects_invalid_domains() {
+        let zones = ZoneRegistry::new::<&str>(&[]).unwrap();
+        assert!(zones.add("").is_err());
+        assert!(zones.add(".").is_err());
+        assert!(zones.add("local.").is_err());
+    }
+}
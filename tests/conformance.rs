@@ -0,0 +1,46 @@
+//! The conformance suite against an in-process proxy; not in the `minimal` build
+#![cfg(not(feature = "minimal"))]
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use hickory_proto::rr::Name;
+use hickory_server::ServerFuture;
+use mdns_dns_proxy::conformance::{run_checks, Target};
+use mdns_dns_proxy::{Config, MdnsDnsHandler, MdnsResolver};
+use serial_test::serial;
+use tokio::net::{TcpListener, UdpSocket};
+
+/// Run the conformance suite over the wire against an in-process proxy
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn test_conformance_suite_passes_against_own_server() {
+    let mut config = Config::default();
+    config.mdns.hostname_resolution_timeout_ms = 300;
+    config.mdns.service_query_timeout_ms = 300;
+    config.mdns.service_poll_interval_ms = 100;
+    let config = Arc::new(config);
+
+    let resolver = Arc::new(MdnsResolver::new(config.clone()).expect("failed to create resolver"));
+    let handler = MdnsDnsHandler::new(resolver, config.discovery_domain().to_string());
+
+    let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = udp.local_addr().unwrap();
+    let tcp = TcpListener::bind(addr).await.unwrap();
+    let mut server = ServerFuture::new(handler);
+    server.register_socket(udp);
+    server.register_listener(tcp, Duration::from_secs(5));
+
+    let target = Target {
+        addr,
+        zone: Name::from_utf8(config.discovery_domain()).unwrap(),
+        timeout: Duration::from_secs(5),
+    };
+    let outcomes = run_checks(&target).await;
+
+    let failures: Vec<_> = outcomes.iter().filter(|o| o.result.is_err()).collect();
+    assert!(failures.is_empty(), "failed checks: {:?}", failures);
+    assert_eq!(outcomes.len(), 10);
+
+    server.shutdown_gracefully().await.ok();
+}
@@ -0,0 +1,107 @@
+//! Request handling under injected mDNS faults; run with `--features fault-injection`
+#![cfg(feature = "fault-injection")]
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{Name, RecordType};
+use hickory_proto::xfer::Protocol;
+use hickory_server::server::RequestHandler;
+use mdns_dns_proxy::dns_handler::testing::{self, CapturingResponseHandler};
+use mdns_dns_proxy::dns_handler::MdnsDnsHandler;
+use mdns_dns_proxy::mdns_resolver::faults::Faults;
+use mdns_dns_proxy::{Config, MdnsResolver};
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use serial_test::serial;
+
+const QUERY_TIMEOUT_MS: u64 = 1500;
+
+/// Advertise an instance of a service type unique to this run; returns the type
+fn advertise(daemon: &ServiceDaemon) -> String {
+    let suffix = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() % 1_000_000;
+    let service_type = format!("_faults{}._tcp.local.", suffix);
+    let mut info = ServiceInfo::new(
+        &service_type,
+        "flaky",
+        &format!("flaky-{}.local.", suffix),
+        "127.0.0.1",
+        6300,
+        HashMap::<String, String>::new(),
+    )
+    .unwrap();
+    info.set_requires_probe(false);
+    daemon.register(info).unwrap();
+    service_type
+}
+
+fn handler(daemon: Arc<ServiceDaemon>, faults: Arc<Faults>) -> MdnsDnsHandler {
+    let mut config = Config::default();
+    config.mdns.service_query_timeout_ms = QUERY_TIMEOUT_MS;
+    let resolver = MdnsResolver::with_daemon(daemon, Arc::new(config)).unwrap().with_faults(faults);
+    MdnsDnsHandler::new(Arc::new(resolver), "mdns.home.arpa.".to_string())
+}
+
+/// PTR query for `service_type` in the discovery domain, and how long it took
+async fn browse(handler: &MdnsDnsHandler, service_type: &str) -> (Message, Duration) {
+    let name = Name::from_utf8(service_type.replace("local.", "mdns.home.arpa.")).unwrap();
+    let mut query = Message::new();
+    query.set_id(4519).set_recursion_desired(true);
+    query.add_query(Query::query(name, RecordType::PTR));
+    let request = testing::request(&query, "127.0.0.1:53000".parse().unwrap(), Protocol::Udp);
+
+    let capture = CapturingResponseHandler::default();
+    let start = Instant::now();
+    handler.handle_request(&request, capture.clone()).await;
+    (capture.take_response().expect("a response is sent"), start.elapsed())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn failed_browses_answer_servfail_promptly() {
+    let daemon = Arc::new(ServiceDaemon::new().unwrap());
+    let service_type = advertise(&daemon);
+    let faults = Arc::new(Faults::new(1).with_browse_errors(1.0));
+    let handler = handler(daemon, faults.clone());
+
+    let (response, elapsed) = browse(&handler, &service_type).await;
+    assert_eq!(response.response_code(), ResponseCode::ServFail);
+    assert!(elapsed < Duration::from_millis(QUERY_TIMEOUT_MS), "took {:?}", elapsed);
+    assert!(faults.injected().browse_errors >= 1);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn lost_events_end_in_an_empty_answer_at_the_timeout() {
+    let daemon = Arc::new(ServiceDaemon::new().unwrap());
+    let service_type = advertise(&daemon);
+    let faults = Arc::new(Faults::new(2).with_drops(1.0));
+    let handler = handler(daemon, faults.clone());
+
+    let (response, elapsed) = browse(&handler, &service_type).await;
+    assert_ne!(response.response_code(), ResponseCode::ServFail);
+    assert!(response.answers().is_empty());
+    assert!(elapsed >= Duration::from_millis(QUERY_TIMEOUT_MS), "took {:?}", elapsed);
+    assert!(elapsed < Duration::from_millis(QUERY_TIMEOUT_MS * 3), "took {:?}", elapsed);
+    assert!(faults.injected().drops >= 1);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn repeated_and_late_events_give_one_answer_per_instance() {
+    let daemon = Arc::new(ServiceDaemon::new().unwrap());
+    let service_type = advertise(&daemon);
+    let faults = Arc::new(Faults::new(3).with_duplicates(1.0).with_delays(
+        0.5,
+        Duration::from_millis(50),
+        Duration::from_millis(300),
+    ));
+    let handler = handler(daemon, faults.clone());
+
+    let (response, _) = browse(&handler, &service_type).await;
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    let pointers: Vec<_> = response.answers().iter().filter(|r| r.record_type() == RecordType::PTR).collect();
+    assert_eq!(pointers.len(), 1, "{:?}", response.answers());
+    assert!(faults.injected().duplicates >= 1);
+}
@@ -27,6 +27,11 @@ struct TestMdnsService {
 
 impl TestMdnsService {
     fn advertise(daemon: Arc<ServiceDaemon>, ip_addrs: &[&str], port: u16) -> Self {
+        Self::advertise_as(daemon, SERVICE_TYPE, ip_addrs, port)
+    }
+
+    /// Advertise under `service_type`, which may be a subtype ("_x._sub._http._tcp.local.")
+    fn advertise_as(daemon: Arc<ServiceDaemon>, service_type: &str, ip_addrs: &[&str], port: u16) -> Self {
         let suffix = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .expect("time went backwards")
@@ -35,7 +40,7 @@ impl TestMdnsService {
         let host_name = format!("integration-mdns-{suffix}.local.");
 
         let mut service_info = ServiceInfo::new(
-            SERVICE_TYPE,
+            service_type,
             &instance_name,
             &host_name,
             ip_addrs,
@@ -121,17 +126,47 @@ async fn resolves_srv_record_for_service_instance() {
                 false
             }
         }),
-        "expected SRV record pointing at {}:{} but found {:?}",
-        format!(
-            "{}.{}",
-            service.host_name.trim_end_matches(".local."),
-            discovery_domain
-        ),
+        "expected SRV record pointing at {}.{}:{} but found {:?}",
+        service.host_name.trim_end_matches(".local."),
+        discovery_domain,
         service.port,
         records
     );
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn browses_service_subtype() {
+    let daemon = Arc::new(ServiceDaemon::new().expect("failed to create daemon"));
+    let plain = TestMdnsService::advertise(daemon.clone(), &["127.0.0.1"], 6400);
+    let printer = TestMdnsService::advertise_as(daemon.clone(), "_printer._sub._http._tcp.local.", &["127.0.0.1"], 6401);
+    printer.allow_propagation().await;
+
+    let config = create_test_config(5);
+    let discovery_domain = config.discovery_domain().to_string();
+    let resolver = MdnsResolver::with_daemon(daemon, config)
+        .expect("failed to create resolver");
+    let ptr_name = Name::from_utf8(format!("_printer._sub._http._tcp.{}", discovery_domain))
+        .expect("invalid subtype name");
+
+    let records = query_with_retry(&resolver, &ptr_name, RecordType::PTR).await;
+
+    // Instances are named under the parent type, and only subtype members are listed
+    let target = |service: &TestMdnsService| {
+        format!("{}.{}", service.full_name.trim_end_matches(".local."), discovery_domain)
+    };
+    let targets: Vec<String> = records
+        .iter()
+        .filter(|record| record.name() == &ptr_name)
+        .filter_map(|record| match record.data() {
+            RData::PTR(ptr) => Some(ptr.0.to_utf8()),
+            _ => None,
+        })
+        .collect();
+    assert!(targets.contains(&target(&printer)), "expected {} in {:?}", target(&printer), targets);
+    assert!(!targets.contains(&target(&plain)), "unexpected {} in {:?}", target(&plain), targets);
+}
+
 async fn query_with_retry(
     resolver: &MdnsResolver,
     name: &Name,
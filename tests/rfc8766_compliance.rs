@@ -197,7 +197,7 @@ async fn test_soa_record_structure() {
     let resolver = MdnsResolver::with_daemon(daemon, config)
         .expect("failed to create resolver");
     
-    let query_name = Name::from_utf8(&format!("test.{}", discovery_domain)).expect("invalid hostname");
+    let query_name = Name::from_utf8(format!("test.{}", discovery_domain)).expect("invalid hostname");
     let records = resolver.query(&query_name, RecordType::SOA).await
         .expect("query failed");
     
@@ -222,7 +222,7 @@ async fn test_ns_record_structure() {
     let resolver = MdnsResolver::with_daemon(daemon, config)
         .expect("failed to create resolver");
     
-    let query_name = Name::from_utf8(&format!("test.{}", discovery_domain)).expect("invalid hostname");
+    let query_name = Name::from_utf8(format!("test.{}", discovery_domain)).expect("invalid hostname");
     let records = resolver.query(&query_name, RecordType::NS).await
         .expect("query failed");
     
@@ -475,6 +475,7 @@ async fn test_record_suppression_config() {
     let config = RecordSuppressionConfig {
         enabled: false,
         client_ip: Some(IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8))),
+        ..Default::default()
     };
     assert!(
         !should_suppress_address_record(&link_local_record, &config),
@@ -485,6 +486,7 @@ async fn test_record_suppression_config() {
     let config = RecordSuppressionConfig {
         enabled: true,
         client_ip: Some(IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8))),
+        ..Default::default()
     };
     assert!(
         should_suppress_address_record(&link_local_record, &config),